@@ -0,0 +1,80 @@
+/* Lights every led with the bass, fading out between the kicks */
+
+#include <stdlib.h>
+#include <turbo_effect.h>
+
+typedef struct {
+	float level;
+} Pulse;
+
+static TurboAudioApi audio_api;
+
+static void *plugin_create(void)
+{
+	return calloc(1, sizeof(Pulse));
+}
+
+static void plugin_destroy(void *plugin)
+{
+	free(plugin);
+}
+
+static const char *name(const void *plugin)
+{
+	(void)plugin;
+	return "Pulse";
+}
+
+static TurboPixelRequirements pixel_requirements(const void *plugin)
+{
+	(void)plugin;
+	return (TurboPixelRequirements){ 0, 0 };
+}
+
+static void tick(const void *plugin, TurboColor *leds, unsigned long len)
+{
+	Pulse *pulse = (Pulse *)plugin;
+	float bass = audio_api.get_average_amplitude(audio_api.instance, 20.0f, 150.0f);
+	float level = pulse->level * 0.9f;
+	if (bass > level) {
+		level = bass > 1.0f ? 1.0f : bass;
+	}
+	pulse->level = level;
+
+	for (unsigned long i = 0; i < len; i++) {
+		leds[i].r = (uint8_t)(level * 255.0f);
+		leds[i].g = 0;
+		leds[i].b = (uint8_t)(level * 64.0f);
+	}
+}
+
+static void seed(const void *plugin, uint64_t seed)
+{
+	/* The pulse follows the bass, nothing is random */
+	(void)plugin;
+	(void)seed;
+}
+
+static void load(TurboAudioApi api)
+{
+	audio_api = api;
+}
+
+static void unload(void)
+{
+	audio_api.free(audio_api.instance);
+}
+
+static const TurboEffectPluginVTable VTABLE = {
+	plugin_create, plugin_destroy, name, pixel_requirements, tick, seed, load, unload,
+};
+
+uint32_t _plugin_abi_version(void)
+{
+	return TURBO_EFFECT_PLUGIN_ABI_VERSION;
+}
+
+const void *_plugin_vtable(void)
+{
+	return &VTABLE;
+}
@@ -0,0 +1,52 @@
+require("libs.framework")
+
+-- Bias lighting from the edges of the screen, captured with the `screen_capture` config, for a
+-- strip glued clockwise around a monitor or a TV. The music can pump its brightness with the
+-- bass. Black while the screen isn't captured.
+SettingsSchema = {
+	type = "object",
+	properties = {
+		-- Where the first led sits around the screen, from 0 at the top left corner going
+		-- clockwise, 0.5 being the bottom right corner on a square screen
+		start = { type = "number", minimum = 0, maximum = 1 },
+		-- For a strip going counterclockwise
+		reverse = { type = "boolean" },
+		-- Part of the brightness given by the bass, 0 to follow the screen alone
+		audio_mix = { type = "number", minimum = 0, maximum = 1 },
+		-- How fast the leds follow the screen, higher to react sooner and flicker more
+		response = { type = "number", minimum = 0.1, maximum = 60 },
+	},
+}
+
+-- Seconds for the loudest bass remembered to fade to nothing
+local PEAK_DECAY = 10
+local MIN_PEAK = 1
+
+local peak = MIN_PEAK
+local energy = 0
+
+function Tick()
+	local bass = Fft_Result:get_average_amplitude(20, 250)
+	peak = math.max(bass, peak - peak * Turbo.delta / PEAK_DECAY, MIN_PEAK)
+	energy = Turbo.approach(energy, bass / peak, 8)
+
+	local audio_mix = settings.audio_mix or 0
+	local brightness = 1 - audio_mix + audio_mix * energy
+	local follow = math.min(1, Turbo.delta * (settings.response or 8))
+	local start = settings.start or 0
+	local direction = 1
+	if settings.reverse then
+		direction = -1
+	end
+
+	for index = 1, #Colors do
+		local r, g, b = Screen:get_around(start + direction * (index - 0.5) / #Colors)
+		local led = Colors[index]
+		Turbo.set(
+			index,
+			Turbo.lerp(led.r, r * brightness, follow),
+			Turbo.lerp(led.g, g * brightness, follow),
+			Turbo.lerp(led.b, b * brightness, follow)
+		)
+	end
+end
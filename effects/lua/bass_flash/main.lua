@@ -0,0 +1,34 @@
+require("libs.framework")
+local Peak = require("peak")
+
+-- The whole strip flashing on the bass and fading out in between. An example of an effect
+-- package: its default settings and the features it reads are in `effect.json`, and `peak.lua`
+-- is a module of the package
+local channel = { type = "integer", minimum = 0, maximum = 255 }
+
+SettingsSchema = {
+	type = "object",
+	properties = {
+		color = {
+			type = "object",
+			properties = { r = channel, g = channel, b = channel },
+			required = { "r", "g", "b" },
+		},
+		-- How fast the flash fades, per second
+		fade = { type = "number", minimum = 0.1, maximum = 50 },
+	},
+}
+
+local WHITE = { r = 255, g = 255, b = 255 }
+
+local bass = Peak.new(10, 1)
+local brightness = 0
+
+function Tick()
+	local energy = bass:scale(Fft_Result:get_average_amplitude(20, 250))
+	brightness = math.max(energy, Turbo.approach(brightness, 0, settings.fade or 4))
+	local color = settings.color or WHITE
+	for index = 1, #Colors do
+		Turbo.set(index, color.r * brightness, color.g * brightness, color.b * brightness)
+	end
+end
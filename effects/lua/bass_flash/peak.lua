@@ -0,0 +1,15 @@
+-- Scales a signal to its loudest recent moments, so that an effect reacts the same at any volume
+local Peak = {}
+Peak.__index = Peak
+
+-- `decay` is the seconds for the loudest value remembered to fade to nothing
+function Peak.new(decay, minimum)
+	return setmetatable({ decay = decay, minimum = minimum, peak = minimum }, Peak)
+end
+
+function Peak:scale(value)
+	self.peak = math.max(value, self.peak - self.peak * Turbo.delta / self.decay, self.minimum)
+	return value / self.peak
+end
+
+return Peak
@@ -0,0 +1,15 @@
+require("libs.framework")
+local Classic = require("libs.classic")
+
+-- The whole strip slowly brightening and dimming, with the next color on every breath
+SettingsSchema = Classic.schema()
+
+function Tick()
+	local progress = Turbo.time * Classic.speed() * 0.25
+	local breath = math.floor(progress)
+	local brightness = Turbo.ease.in_out_sine(1 - math.abs(2 * (progress - breath) - 1))
+	local r, g, b = Classic.nth(breath)
+	for index = 1, #Colors do
+		Turbo.set(index, r * brightness, g * brightness, b * brightness)
+	end
+end
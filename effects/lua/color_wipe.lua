@@ -0,0 +1,23 @@
+require("libs.framework")
+local Classic = require("libs.classic")
+
+-- Fills the strip with a color led by led, then wipes it with the next color
+SettingsSchema = Classic.schema()
+
+function Tick()
+	local progress = Turbo.time * Classic.speed() * 0.5
+	local wipe = math.floor(progress)
+	local lit = math.floor((progress - wipe) * #Colors)
+	local r, g, b = Classic.nth(wipe)
+	local previous_r, previous_g, previous_b = 0, 0, 0
+	if wipe > 0 then
+		previous_r, previous_g, previous_b = Classic.nth(wipe - 1)
+	end
+	for index = 1, #Colors do
+		if index <= lit then
+			Turbo.set(index, r, g, b)
+		else
+			Turbo.set(index, previous_r, previous_g, previous_b)
+		end
+	end
+end
@@ -0,0 +1,106 @@
+require("libs.framework")
+local Classic = require("libs.classic")
+
+-- Comets with fading tails launched on every bass onset, or when the derived feature named by
+-- `trigger` rises above 0.5. They start from the `origin`, both ways from the center, and cross
+-- the strip in a second at a `speed` of 1.
+SettingsSchema = Classic.schema({
+	-- Length of the tails, as a part of the strip
+	tail = { type = "number", minimum = 0, maximum = 1 },
+	origin = { type = "string", enum = { "start", "end", "center", "random" } },
+	-- The oldest comets disappear to make room for the new ones
+	max_comets = { type = "integer", minimum = 1, maximum = 100 },
+	-- How far above its recent average the bass rises on an onset
+	sensitivity = { type = "number", minimum = 1, maximum = 10 },
+	trigger = { type = "string" },
+})
+
+-- Seconds the recent average of the bass follows it over
+local AVERAGE_TIME = 0.5
+-- Shortest time between two onsets, in seconds
+local REFRACTORY_TIME = 0.15
+-- Quieter bass isn't an onset, so that noise in silence launches nothing
+local MIN_BASS = 1
+
+local comets = {}
+local launched = 0
+local average = 0
+local last_onset = -math.huge
+local triggered = false
+
+local function detect_onset()
+	if settings.trigger then
+		local value = Features[settings.trigger] or 0
+		local rising = value > 0.5 and not triggered
+		triggered = value > 0.5
+		return rising
+	end
+	local bass = Fft_Result:get_average_amplitude(20, 250)
+	local onset = bass > MIN_BASS
+		and bass > average * (settings.sensitivity or 1.5)
+		and Turbo.time - last_onset > REFRACTORY_TIME
+	average = Turbo.lerp(average, bass, math.min(1, Turbo.delta / AVERAGE_TIME))
+	if onset then
+		last_onset = Turbo.time
+	end
+	return onset
+end
+
+local function launch(position, direction, r, g, b)
+	if #comets >= (settings.max_comets or 8) then
+		table.remove(comets, 1)
+	end
+	table.insert(comets, { position = position, direction = direction, r = r, g = g, b = b })
+end
+
+function Tick()
+	local length = #Colors
+	if detect_onset() then
+		local r, g, b = Classic.nth(launched)
+		launched = launched + 1
+		local origin = settings.origin or "start"
+		if origin == "start" then
+			launch(0, 1, r, g, b)
+		elseif origin == "end" then
+			launch(length - 1, -1, r, g, b)
+		elseif origin == "center" then
+			launch((length - 1) / 2, 1, r, g, b)
+			launch((length - 1) / 2, -1, r, g, b)
+		else
+			local direction = 1
+			if math.random() < 0.5 then
+				direction = -1
+			end
+			launch(math.random() * (length - 1), direction, r, g, b)
+		end
+	end
+
+	for index = 1, length do
+		Turbo.set(index, 0, 0, 0)
+	end
+	local tail = math.max(1, (settings.tail or 0.15) * length)
+	local step = Classic.speed() * length * Turbo.delta
+	for index = #comets, 1, -1 do
+		local comet = comets[index]
+		comet.position = comet.position + comet.direction * step
+		-- Gone once the end of its tail went past the far end of the strip
+		local tail_end = comet.position - comet.direction * tail
+		if (comet.direction > 0 and tail_end > length) or (comet.direction < 0 and tail_end < -1) then
+			table.remove(comets, index)
+		else
+			for k = 0, math.floor(tail) do
+				local led = math.floor(comet.position - comet.direction * k + 0.5) + 1
+				local color = Colors[led]
+				if color then
+					local brightness = (1 - k / tail) ^ 2
+					Turbo.set(
+						led,
+						math.max(color.r, comet.r * brightness),
+						math.max(color.g, comet.g * brightness),
+						math.max(color.b, comet.b * brightness)
+					)
+				end
+			end
+		end
+	end
+end
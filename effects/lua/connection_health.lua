@@ -0,0 +1,93 @@
+require("libs.framework")
+
+-- Shows the health of a connection on the strip, to find the flaky device of an installation
+-- without a laptop: green while connected, yellow while reconnecting and red once dead, with a
+-- bar growing with the bytes sent and a red tail at the end growing with the frames dropped.
+-- Follows the connection doing worst without a `connection`.
+SettingsSchema = {
+	type = "object",
+	properties = {
+		-- Id of the connection to show
+		connection = { type = "integer", minimum = 0 },
+		-- Bytes per second lighting the whole bar
+		max_bytes_per_second = { type = "number", minimum = 1 },
+		-- Brightness of the leds past the bar, 0 to only show the bar
+		background = { type = "number", minimum = 0, maximum = 1 },
+	},
+}
+
+local HEALTH_COLORS = {
+	Connected = { 0, 255, 0 },
+	Reconnecting = { 255, 160, 0 },
+	Dead = { 255, 0, 0 },
+}
+-- Seconds for the tail to shrink once the frames aren't dropped anymore
+local TAIL_DECAY = 1
+
+local tail = 0
+
+-- Higher for the connections doing worse
+local function badness(connection)
+	local score = connection.dropped_per_second
+	if connection.health == "Reconnecting" then
+		score = score + 1000
+	elseif connection.health == "Dead" then
+		score = score + 2000
+	end
+	return score
+end
+
+local function followed_connection()
+	if settings.connection ~= nil then
+		return Turbo.connections()[settings.connection]
+	end
+	local worst = nil
+	for _, connection in pairs(Turbo.connections()) do
+		if worst == nil or badness(connection) > badness(worst) then
+			worst = connection
+		end
+	end
+	return worst
+end
+
+function Tick()
+	local connection = followed_connection()
+	if connection == nil then
+		for index = 1, #Colors do
+			Turbo.set(index, 0, 0, 0)
+		end
+		return
+	end
+
+	local color = HEALTH_COLORS[connection.health]
+	local bar = Turbo.map_clamped(
+		connection.bytes_per_second,
+		0,
+		settings.max_bytes_per_second or 100000,
+		0,
+		#Colors
+	)
+	local background = settings.background or 0.1
+	for index = 1, #Colors do
+		local brightness = background
+		if index <= bar then
+			brightness = 1
+		end
+		Turbo.set(index, color[1] * brightness, color[2] * brightness, color[3] * brightness)
+	end
+
+	-- Part of the frames of the last second that were dropped
+	local sent = connection.fps + connection.dropped_per_second
+	local dropped = 0
+	if sent > 0 then
+		dropped = connection.dropped_per_second / sent
+	end
+	local target = 0
+	if dropped > 0 then
+		target = math.max(1, dropped * #Colors / 2)
+	end
+	tail = math.max(target, Turbo.approach(tail, 0, #Colors / 2 / TAIL_DECAY))
+	for index = #Colors - math.floor(tail + 0.5) + 1, #Colors do
+		Turbo.set(index, 255, 0, 0)
+	end
+end
@@ -0,0 +1,66 @@
+require("libs.framework")
+
+-- A rainbow, or the gradient of a palette, scrolling along the strip. The music speeds it up
+-- and saturates it, following the energy of the bass scaled to the loudest recent moments, so
+-- that it reacts the same at any volume. `speed` and `saturation` can also be bound to other
+-- audio features in the settings file instead.
+local channel = { type = "integer", minimum = 0, maximum = 255 }
+local color = {
+	type = "object",
+	properties = { r = channel, g = channel, b = channel },
+	required = { "r", "g", "b" },
+}
+
+SettingsSchema = {
+	type = "object",
+	properties = {
+		-- Gradients scrolled per second in silence
+		speed = { type = "number", minimum = -10, maximum = 10 },
+		-- Gradients per second added at full energy
+		speed_energy = { type = "number", minimum = -10, maximum = 10 },
+		saturation = { type = "number", minimum = 0, maximum = 1 },
+		-- Part of the saturation lost in silence, 0 not to react
+		saturation_energy = { type = "number", minimum = 0, maximum = 1 },
+		-- Times the gradient repeats along the strip
+		scale = { type = "number", minimum = 0.1, maximum = 20 },
+		-- Colors of the gradient, which loops back to the first. A rainbow if missing
+		palette = { type = "array", items = color, minItems = 1 },
+	},
+}
+
+-- Seconds for the loudest bass remembered to fade to nothing
+local PEAK_DECAY = 10
+local MIN_PEAK = 1
+
+local offset = 0
+local peak = MIN_PEAK
+local energy = 0
+
+local function gradient(t)
+	local palette = settings.palette
+	if palette == nil then
+		return Turbo.hsv(t, 1, 1)
+	end
+	local position = Turbo.fract(t) * #palette
+	local index = math.floor(position)
+	local from, to = palette[index + 1], palette[(index + 1) % #palette + 1]
+	local f = position - index
+	return Turbo.lerp(from.r, to.r, f), Turbo.lerp(from.g, to.g, f), Turbo.lerp(from.b, to.b, f)
+end
+
+function Tick()
+	local bass = Fft_Result:get_average_amplitude(20, 250)
+	peak = math.max(bass, peak - peak * Turbo.delta / PEAK_DECAY, MIN_PEAK)
+	energy = Turbo.approach(energy, bass / peak, 4)
+
+	local speed = (settings.speed or 0.2) + (settings.speed_energy or 0.8) * energy
+	offset = Turbo.fract(offset + speed * Turbo.delta)
+	local saturation = (settings.saturation or 1)
+		* (1 - (settings.saturation_energy or 0.5) * (1 - energy))
+	local scale = settings.scale or 1
+
+	for index = 1, #Colors do
+		local h, s, v = Turbo.rgb_to_hsv(gradient((index - 1) / #Colors * scale - offset))
+		Turbo.set(index, Turbo.hsv(h, s * saturation, v))
+	end
+end
@@ -0,0 +1,73 @@
+-- Settings shared by the classic effects, which don't react to the audio:
+-- - `speed` multiplies the pace of the animation, 1 by default
+-- - `color`, like { "r": 255, "g": 120, "b": 0 }, is the color they draw with
+-- - `palette`, a list of colors, is gone through instead of `color`
+-- - `seed` seeds `math.random`, for the effects drawing at random to do the same on every run
+-- They go through the rainbow when neither is set.
+local Classic = {}
+
+local channel = { type = "integer", minimum = 0, maximum = 255 }
+local color = {
+	type = "object",
+	properties = { r = channel, g = channel, b = channel },
+	required = { "r", "g", "b" },
+}
+
+-- Schema of the shared settings and of the `properties` of the effect
+function Classic.schema(properties)
+	local schema = {
+		type = "object",
+		properties = {
+			speed = { type = "number", minimum = 0, maximum = 10 },
+			color = color,
+			palette = { type = "array", items = color, minItems = 1 },
+			seed = { type = "integer", minimum = 0 },
+		},
+	}
+	for name, property in pairs(properties or {}) do
+		schema.properties[name] = property
+	end
+	return schema
+end
+
+function Classic.speed()
+	return settings.speed or 1
+end
+
+-- Color at t in [0, 1), wrapping around: along the palette, the color, or the rainbow
+function Classic.color(t)
+	t = Turbo.fract(t)
+	if settings.palette then
+		local palette = settings.palette
+		local position = t * #palette
+		local from = palette[math.floor(position) + 1]
+		local to = palette[math.floor(position + 1) % #palette + 1]
+		local f = position - math.floor(position)
+		return Turbo.lerp(from.r, to.r, f), Turbo.lerp(from.g, to.g, f), Turbo.lerp(from.b, to.b, f)
+	end
+	if settings.color then
+		return settings.color.r, settings.color.g, settings.color.b
+	end
+	return Turbo.hsv(t, 1, 1)
+end
+
+-- nth color of a sequence: the colors of the palette one after the other, the color, or hues
+-- far apart on the rainbow
+function Classic.nth(n)
+	if settings.palette then
+		local color = settings.palette[n % #settings.palette + 1]
+		return color.r, color.g, color.b
+	end
+	return Classic.color(n * 0.618)
+end
+
+-- Dims every led by `rate` per second, leaving the tail of what moved
+function Classic.fade(rate)
+	local factor = math.max(0, 1 - rate * Turbo.delta)
+	for index = 1, #Colors do
+		local led = Colors[index]
+		Turbo.set(index, led.r * factor, led.g * factor, led.b * factor)
+	end
+end
+
+return Classic
@@ -1,5 +1,12 @@
+-- Colors of the leds, { r = 0, g = 0, b = 0 } tables in [0, 255]. They hold the colors of the
+-- previous frame when `Tick` is called, so an effect can fade or move them instead of drawing
+-- everything again. What isn't written is kept as is.
 Colors = {}
 
+-- Pixel counts the effect needs, 0 meaning no requirement. Effects that look wrong on small
+-- segments override it, they are then rendered at the native size and resampled.
+PixelRequirements = { min = 0, native = 0 }
+
 function Set_colors()
 	local data = {}
 	for i, value in pairs(Colors) do
@@ -11,6 +18,15 @@ function Set_colors()
 	Colors_bin = table.concat(data)
 end
 
+-- Fills Colors with the current colors of the leds, packed as r, g, b bytes
+function Load_colors(data)
+	for index = 1, #Colors do
+		local color = Colors[index]
+		local offset = (index - 1) * 3
+		color.r, color.g, color.b = string.byte(data, offset + 1, offset + 3)
+	end
+end
+
 function Resize_Colors(len)
 	if len ~= #Colors then
 		Colors = {}
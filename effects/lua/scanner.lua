@@ -0,0 +1,20 @@
+require("libs.framework")
+local Classic = require("libs.classic")
+
+-- A dot sweeping back and forth with a fading tail, the larson scanner of KITT and the cylons
+SettingsSchema = Classic.schema({
+	width = { type = "integer", minimum = 1, maximum = 50 },
+})
+
+function Tick()
+	Classic.fade(4 * Classic.speed())
+	local phase = Turbo.phase(Classic.speed() * 0.5)
+	-- Triangle wave, from one end to the other and back
+	local position = (1 - math.abs(2 * phase - 1)) * (#Colors - 1)
+	local width = settings.width or 3
+	local r, g, b = Classic.color(phase)
+	local first = math.floor(position - (width - 1) / 2 + 0.5)
+	for index = first, first + width - 1 do
+		Turbo.set(index + 1, r, g, b)
+	end
+end
@@ -0,0 +1,11 @@
+require("libs.framework")
+local Classic = require("libs.classic")
+
+-- The whole strip in the color, or the palette spread along the strip. It doesn't move
+SettingsSchema = Classic.schema()
+
+function Tick()
+	for index = 1, #Colors do
+		Turbo.set(index, Classic.color((index - 1) / #Colors))
+	end
+end
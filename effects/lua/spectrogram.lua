@@ -3,6 +3,8 @@ require("libs.colors")
 
 SettingsSchema = {}
 
+PixelRequirements = { min = 20, native = 60 }
+
 local view = 800
 local tick = 0
 
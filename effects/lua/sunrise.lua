@@ -0,0 +1,42 @@
+require("libs.framework")
+
+-- A sunrise following the clock: from night to a deep red at `start`, then through orange to a
+-- warm white `minutes` later, where it stays until the effect is switched off. Meant for a
+-- schedule switching to it before `start`, like a wake up light.
+SettingsSchema = {
+	type = "object",
+	properties = {
+		-- Local time the sunrise starts at, like "07:00"
+		start = { type = "string", pattern = "^[0-9]{1,2}:[0-9]{2}$" },
+		minutes = { type = "number", minimum = 1, maximum = 240 },
+	},
+}
+
+local SKY = {
+	{ r = 0, g = 0, b = 0 },
+	{ r = 120, g = 10, b = 0 },
+	{ r = 255, g = 80, b = 0 },
+	{ r = 255, g = 170, b = 60 },
+	{ r = 255, g = 230, b = 180 },
+}
+local DAY = 24 * 60 * 60
+
+local function start_seconds()
+	local hours, minutes = string.match(settings.start or "07:00", "^(%d+):(%d+)$")
+	return (tonumber(hours) * 60 + tonumber(minutes)) * 60
+end
+
+function Tick()
+	-- Seconds since the start, counting the hours before it as the night before
+	local elapsed = (Turbo.clock - start_seconds()) % DAY
+	if elapsed > DAY / 2 then
+		elapsed = 0
+	end
+	local progress = math.min(1, elapsed / ((settings.minutes or 30) * 60))
+	for index = 1, #Colors do
+		-- The bottom of the strip rises ahead of its top, like the light of the horizon
+		local height = (index - 1) / math.max(1, #Colors - 1)
+		local t = Turbo.clamp(progress * 1.3 - height * 0.3, 0, 1)
+		Turbo.set(index, Turbo.palette(SKY, t))
+	end
+end
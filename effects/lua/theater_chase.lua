@@ -0,0 +1,19 @@
+require("libs.framework")
+local Classic = require("libs.classic")
+
+-- One led out of `spacing` lit, marching along the strip like the lights of a theater marquee
+SettingsSchema = Classic.schema({
+	spacing = { type = "integer", minimum = 2, maximum = 20 },
+})
+
+function Tick()
+	local spacing = settings.spacing or 3
+	local step = math.floor(Turbo.time * Classic.speed() * 10)
+	for index = 1, #Colors do
+		if (index - 1 + step) % spacing == 0 then
+			Turbo.set(index, Classic.color((index - 1) / #Colors))
+		else
+			Turbo.set(index, 0, 0, 0)
+		end
+	end
+end
@@ -0,0 +1,21 @@
+require("libs.framework")
+local Classic = require("libs.classic")
+
+-- Leds lighting up at random and fading out, like stars
+SettingsSchema = Classic.schema({
+	-- Twinkles started per led and per second
+	density = { type = "number", minimum = 0, maximum = 10 },
+})
+
+function Tick()
+	Classic.fade(2 * Classic.speed())
+	local twinkles = (settings.density or 0.1) * #Colors * Turbo.delta
+	-- The fraction of a twinkle left is started with the matching probability
+	local count = math.floor(twinkles)
+	if math.random() < twinkles - count then
+		count = count + 1
+	end
+	for _ = 1, count do
+		Turbo.set(math.random(#Colors), Classic.color(math.random()))
+	end
+end
@@ -0,0 +1,72 @@
+require("libs.framework")
+
+-- The recent audio history flowing along the strip: every frame the strip moves by a led and
+-- the current energy is written at its head. In the `bands` mode the bass, mids and treble
+-- are the red, green and blue of the head, in the `full` mode the energy of the whole spectrum
+-- picks a color along the `palette`. Each band is scaled to its loudest recent moments.
+local channel = { type = "integer", minimum = 0, maximum = 255 }
+local color = {
+	type = "object",
+	properties = { r = channel, g = channel, b = channel },
+	required = { "r", "g", "b" },
+}
+
+SettingsSchema = {
+	type = "object",
+	properties = {
+		mode = { type = "string", enum = { "bands", "full" } },
+		-- The head is the first led with `start`, the last one with `end`
+		head = { type = "string", enum = { "start", "end" } },
+		-- Multiplies the energy after its scaling, above 1 to saturate more often
+		gain = { type = "number", minimum = 0, maximum = 10 },
+		-- Colors from silence to the loudest energy in the `full` mode
+		palette = { type = "array", items = color, minItems = 1 },
+	},
+}
+
+-- From black to white through the colors of a fire
+local HEAT = {
+	{ r = 0, g = 0, b = 0 },
+	{ r = 160, g = 0, b = 0 },
+	{ r = 255, g = 120, b = 0 },
+	{ r = 255, g = 255, b = 80 },
+	{ r = 255, g = 255, b = 255 },
+}
+-- Seconds for the loudest energy remembered to fade to nothing
+local PEAK_DECAY = 10
+local MIN_PEAK = 1
+
+local peaks = {}
+
+-- Energy between two frequencies in [0, 1], scaled to its recent peak
+local function energy(band, lower, upper)
+	local amplitude = Fft_Result:get_average_amplitude(lower, upper)
+	local peak = peaks[band] or MIN_PEAK
+	peak = math.max(amplitude, peak - peak * Turbo.delta / PEAK_DECAY, MIN_PEAK)
+	peaks[band] = peak
+	return Turbo.clamp(amplitude / peak * (settings.gain or 1), 0, 1)
+end
+
+function Tick()
+	local count = #Colors
+	local head, from, to, step = 1, count - 1, 1, -1
+	if settings.head == "end" then
+		head, from, to, step = count, 2, count, 1
+	end
+	-- Moves every led a step away from the head, the last one falling off
+	for index = from, to, step do
+		local previous = Colors[index]
+		Turbo.set(index - step, previous.r, previous.g, previous.b)
+	end
+
+	if settings.mode == "full" then
+		Turbo.set(head, Turbo.palette(settings.palette or HEAT, energy("full", 20, 16000)))
+	else
+		Turbo.set(
+			head,
+			255 * energy("bass", 20, 250),
+			255 * energy("mids", 250, 4000),
+			255 * energy("treble", 4000, 16000)
+		)
+	end
+end
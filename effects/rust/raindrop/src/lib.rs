@@ -1,11 +1,66 @@
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::sync::Mutex;
-use turbo_plugin::{make_native_effect_plugin, effect_plugin::NativeEffectPlugin, Color};
+use turbo_plugin::{
+    audio_api, effect_plugin::NativeEffectPlugin, make_native_effect_plugin, Color,
+};
+
+/// Colors of the drops, picked at random
+const DROP_COLORS: [Color; 4] = [
+    Color {
+        r: 255,
+        g: 0,
+        b: 255,
+    },
+    Color {
+        r: 255,
+        g: 255,
+        b: 0,
+    },
+    Color {
+        r: 0,
+        g: 255,
+        b: 255,
+    },
+    Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    },
+];
+// Part of its brightness a ripple keeps every tick, so that the faster ones spread farther
+const RIPPLE_FADE: f32 = 0.75;
+// Dimmer ripples are dropped
+const MIN_BRIGHTNESS: f32 = 0.02;
+// Part of the way the average of the spectral flux moves toward it every tick
+const FLUX_SMOOTHING: f32 = 0.05;
+// Quieter flux isn't an onset, so that noise in silence drops nothing
+const MIN_FLUX: f32 = 1.0;
+// Ripples of the strongest onsets are this many times faster than those of the weakest ones
+const MAX_STRENGTH: f32 = 4.0;
+// Shortest time between two onsets, so that a hit doesn't drop on several ticks in a row
+const MIN_TICKS_BETWEEN_ONSETS: u32 = 6;
 
 #[derive(Clone, Copy, Debug)]
 pub struct RaindropSettings {
-    pub rain_speed: i32,
+    /// Leds a ripple moves per tick for the weakest onset and the random drops
+    pub rain_speed: f32,
+    /// Chance of a random drop on every tick, once the music stopped giving onsets
     pub drop_rate: f64,
+    /// How far above its recent average the spectral flux rises on an onset
+    pub onset_threshold: f32,
+    /// Ticks without an onset before the drops fall at random again
+    pub fallback_ticks: u32,
+}
+
+impl Default for RaindropSettings {
+    fn default() -> Self {
+        Self {
+            rain_speed: 1.0,
+            drop_rate: 0.5,
+            onset_threshold: 2.0,
+            fallback_ticks: 120,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -14,18 +69,80 @@ pub enum RipleDirection {
     Right,
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Copy, Debug)]
+struct Ripple {
+    position: f32,
+    /// Leds moved per tick
+    speed: f32,
+    direction: RipleDirection,
+    color: Color,
+    brightness: f32,
+}
+
+#[derive(Debug)]
 pub struct RaindropState {
-    riples: Vec<(usize, Color, RipleDirection)>,
+    riples: Vec<Ripple>,
+    flux_average: f32,
+    ticks_since_onset: u32,
+    /// Picks where the drops fall and their colors, seeded by the settings for the same rain on
+    /// every run
+    rng: StdRng,
 }
 
+impl Default for RaindropState {
+    fn default() -> Self {
+        Self {
+            riples: Vec::new(),
+            flux_average: 0.0,
+            // Falls back to the random drops until the first onset
+            ticks_since_onset: u32::MAX,
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl RaindropState {
+    /// Strength of the onset of this tick, from 1 at the threshold to [`MAX_STRENGTH`]
+    fn detect_onset(&mut self, settings: &RaindropSettings) -> Option<f32> {
+        let flux = audio_api::get_spectral_features().flux;
+        let average = self.flux_average;
+        self.flux_average += (flux - average) * FLUX_SMOOTHING;
+        self.ticks_since_onset = self.ticks_since_onset.saturating_add(1);
+
+        let threshold = average.max(MIN_FLUX / settings.onset_threshold) * settings.onset_threshold;
+        if flux < threshold || self.ticks_since_onset < MIN_TICKS_BETWEEN_ONSETS {
+            return None;
+        }
+        self.ticks_since_onset = 0;
+        Some((flux / threshold).min(MAX_STRENGTH))
+    }
+
+    fn add_drop(&mut self, position: usize, speed: f32) {
+        let color = DROP_COLORS[self.rng.gen_range(0..DROP_COLORS.len())];
+        for direction in [RipleDirection::Left, RipleDirection::Right] {
+            self.riples.push(Ripple {
+                position: position as f32,
+                speed,
+                direction,
+                color,
+                brightness: 1.0,
+            });
+        }
+    }
+}
+
+/// Drops falling on the strip, their ripples spreading both ways and adding up where they
+/// cross. They fall on the onsets of the music, their ripples moving faster for stronger
+/// onsets, and at random when the music gives none.
 struct Raindrop {
+    settings: RaindropSettings,
     state: Mutex<RaindropState>,
 }
 
 impl Raindrop {
     pub fn new() -> Self {
         Self {
+            settings: Default::default(),
             state: Default::default(),
         }
     }
@@ -40,77 +157,47 @@ impl NativeEffectPlugin for Raindrop {
     }
 
     fn tick(&self, leds: &mut [Color]) {
+        let settings = &self.settings;
         let mut state = self.state.lock().unwrap();
         leds.fill(Color { r: 0, g: 0, b: 0 });
-        let color_size = leds.len();
-        let mut next_riples: Vec<(usize, Color, RipleDirection)> = vec![];
-        const SHIFT: usize = 1;
-        for (current_position, color, direction) in &state.riples {
-            let next_position = match direction {
-                RipleDirection::Left => {
-                    if current_position < &SHIFT {
-                        continue;
-                    }
-                    current_position - SHIFT
-                }
-                RipleDirection::Right => {
-                    if current_position + SHIFT >= color_size {
-                        continue;
-                    }
-                    current_position + SHIFT
-                }
-            };
-            const NUMERATOR: u8 = 3;
-            const DENOMINATOR: u8 = 4;
-            let next_color = Color {
-                r: color.r / DENOMINATOR * NUMERATOR,
-                g: color.g / DENOMINATOR * NUMERATOR,
-                b: color.b / DENOMINATOR * NUMERATOR,
-            };
-            if let Some(led) = leds.get_mut(next_position) {
-                led.r += next_color.r;
-                led.g += next_color.g;
-                led.b += next_color.b;
-                next_riples.push((next_position, next_color, *direction));
-            }
+        if leds.is_empty() {
+            return;
         }
 
-        if !rand::thread_rng().gen_bool(0.5) {
-            state.riples = next_riples;
-            return;
+        let end = leds.len() as f32;
+        state.riples.retain_mut(|ripple| {
+            match ripple.direction {
+                RipleDirection::Left => ripple.position -= ripple.speed,
+                RipleDirection::Right => ripple.position += ripple.speed,
+            }
+            ripple.brightness *= RIPPLE_FADE;
+            ripple.position >= 0.0 && ripple.position < end && ripple.brightness > MIN_BRIGHTNESS
+        });
+
+        let strength = state.detect_onset(settings);
+        let position = state.rng.gen_range(0..leds.len());
+        match strength {
+            Some(strength) => state.add_drop(position, settings.rain_speed * strength),
+            None if state.ticks_since_onset > settings.fallback_ticks
+                && state.rng.gen_bool(settings.drop_rate) =>
+            {
+                state.add_drop(position, settings.rain_speed)
+            }
+            None => {}
         }
 
-        for _ in 0..1 {
-            let new_position = rand::thread_rng().gen_range(0..color_size);
-            let next_color = match rand::thread_rng().gen_range(0..4) {
-                0 => Color {
-                    r: 255,
-                    g: 0,
-                    b: 255,
-                },
-                1 => Color {
-                    r: 255,
-                    g: 255,
-                    b: 0,
-                },
-                2 => Color {
-                    r: 0,
-                    g: 255,
-                    b: 255,
-                },
-                3 => Color {
-                    r: 255,
-                    g: 255,
-                    b: 255,
-                },
-                _ => unreachable!(),
-            };
-            *leds.get_mut(new_position).expect("Rng lib failed.") = next_color;
-            next_riples.push((new_position, next_color, RipleDirection::Left));
-            next_riples.push((new_position, next_color, RipleDirection::Right));
+        // The ripples add up, a new drop being as bright as it gets
+        for ripple in &state.riples {
+            let led = &mut leds[ripple.position as usize];
+            let scale = |channel: u8| (channel as f32 * ripple.brightness) as u8;
+            led.r = led.r.saturating_add(scale(ripple.color.r));
+            led.g = led.g.saturating_add(scale(ripple.color.g));
+            led.b = led.b.saturating_add(scale(ripple.color.b));
         }
+    }
 
-        state.riples = next_riples;
+    fn seed(&self, seed: u64) {
+        self.state.lock().unwrap().rng = StdRng::seed_from_u64(seed);
     }
 
     fn load() {}
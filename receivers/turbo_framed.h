@@ -0,0 +1,170 @@
+/*
+ * Reference receiver of the `Framed` encoding of turbo_audio, for the firmwares of the
+ * controllers reading the frames from a tcp stream, like an ESP32 or an ESP8266.
+ *
+ * Every frame is laid out as
+ *
+ *   'T' 'A'    magic
+ *   length     u16, big endian, bytes of data (3 per led, r g b)
+ *   sequence   u16, big endian, one more than the previous frame, wrapping around
+ *   data       length bytes
+ *   crc        u16, big endian, CRC-16/CCITT-FALSE of the data
+ *
+ * Feed the bytes as they arrive to turbo_framed_feed, which calls back with the data of every
+ * valid frame. It looks for the magic again after a torn frame, so it recovers from any garbage
+ * in the stream, and counts the frames it had to drop and the ones that never arrived.
+ *
+ *   static uint8_t buffer[TURBO_FRAMED_MAX_DATA(300)];
+ *   static TurboFramed receiver;
+ *
+ *   static void show(const uint8_t *rgb, uint16_t length, void *context)
+ *   {
+ *       // Copy the leds out of rgb, it's only valid during the call
+ *   }
+ *
+ *   turbo_framed_init(&receiver, buffer, sizeof(buffer), show, NULL);
+ *   while (client.available())
+ *       turbo_framed_feed(&receiver, client.read());
+ *
+ * Plain C99, without allocation, so it builds for Arduino and ESP-IDF alike.
+ */
+
+#ifndef TURBO_FRAMED_H
+#define TURBO_FRAMED_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/* Buffer size holding the data of a strip of `leds` leds */
+#define TURBO_FRAMED_MAX_DATA(leds) ((leds) * 3)
+
+typedef void (*TurboFramedCallback)(const uint8_t *rgb, uint16_t length, void *context);
+
+typedef enum {
+	TURBO_FRAMED_MAGIC_T,
+	TURBO_FRAMED_MAGIC_A,
+	TURBO_FRAMED_LENGTH,
+	TURBO_FRAMED_SEQUENCE,
+	TURBO_FRAMED_DATA,
+	TURBO_FRAMED_CRC,
+} TurboFramedState;
+
+typedef struct {
+	uint8_t *buffer;
+	size_t capacity;
+	TurboFramedCallback callback;
+	void *context;
+
+	TurboFramedState state;
+	/* Bytes of the current field read so far */
+	uint16_t field;
+	uint8_t field_bytes;
+	uint16_t length;
+	uint16_t sequence;
+	uint16_t received;
+	uint16_t crc;
+
+	/* Sequence of the last valid frame, and whether there was one */
+	uint16_t last_sequence;
+	uint8_t synced;
+
+	/* Frames torn, too long for the buffer or failing their crc */
+	uint32_t invalid_frames;
+	/* Frames skipped by the sender or lost, from the gaps in the sequence */
+	uint32_t missed_frames;
+} TurboFramed;
+
+static inline uint16_t turbo_framed_crc16(uint16_t crc, uint8_t byte)
+{
+	crc ^= (uint16_t)byte << 8;
+	for (int bit = 0; bit < 8; bit++)
+		crc = (crc & 0x8000) ? (uint16_t)((crc << 1) ^ 0x1021) : (uint16_t)(crc << 1);
+	return crc;
+}
+
+static inline void turbo_framed_init(TurboFramed *receiver, uint8_t *buffer, size_t capacity,
+				     TurboFramedCallback callback, void *context)
+{
+	*receiver = (TurboFramed){
+		.buffer = buffer,
+		.capacity = capacity,
+		.callback = callback,
+		.context = context,
+		.state = TURBO_FRAMED_MAGIC_T,
+	};
+}
+
+/* Reads the u16 fields a byte at a time. Returns 1 once the field is complete */
+static inline int turbo_framed_field(TurboFramed *receiver, uint8_t byte)
+{
+	receiver->field = (uint16_t)(receiver->field << 8) | byte;
+	if (++receiver->field_bytes < 2)
+		return 0;
+	receiver->field_bytes = 0;
+	return 1;
+}
+
+static inline void turbo_framed_accept(TurboFramed *receiver)
+{
+	if (receiver->synced) {
+		uint16_t gap = (uint16_t)(receiver->sequence - receiver->last_sequence - 1);
+		/* A sequence going back is the sender restarting, not 65535 missed frames */
+		if (gap < 0x8000)
+			receiver->missed_frames += gap;
+	}
+	receiver->last_sequence = receiver->sequence;
+	receiver->synced = 1;
+	receiver->callback(receiver->buffer, receiver->length, receiver->context);
+}
+
+static inline void turbo_framed_feed(TurboFramed *receiver, uint8_t byte)
+{
+	switch (receiver->state) {
+	case TURBO_FRAMED_MAGIC_T:
+		if (byte == 'T')
+			receiver->state = TURBO_FRAMED_MAGIC_A;
+		break;
+	case TURBO_FRAMED_MAGIC_A:
+		if (byte == 'A')
+			receiver->state = TURBO_FRAMED_LENGTH;
+		else if (byte != 'T')
+			receiver->state = TURBO_FRAMED_MAGIC_T;
+		break;
+	case TURBO_FRAMED_LENGTH:
+		if (!turbo_framed_field(receiver, byte))
+			break;
+		receiver->length = receiver->field;
+		if (receiver->length > receiver->capacity) {
+			receiver->invalid_frames++;
+			receiver->state = TURBO_FRAMED_MAGIC_T;
+			break;
+		}
+		receiver->state = TURBO_FRAMED_SEQUENCE;
+		break;
+	case TURBO_FRAMED_SEQUENCE:
+		if (!turbo_framed_field(receiver, byte))
+			break;
+		receiver->sequence = receiver->field;
+		receiver->received = 0;
+		receiver->crc = 0xffff;
+		receiver->state = receiver->length ? TURBO_FRAMED_DATA : TURBO_FRAMED_CRC;
+		break;
+	case TURBO_FRAMED_DATA:
+		receiver->buffer[receiver->received++] = byte;
+		receiver->crc = turbo_framed_crc16(receiver->crc, byte);
+		if (receiver->received == receiver->length)
+			receiver->state = TURBO_FRAMED_CRC;
+		break;
+	case TURBO_FRAMED_CRC:
+		if (!turbo_framed_field(receiver, byte))
+			break;
+		if (receiver->field == receiver->crc)
+			turbo_framed_accept(receiver);
+		else
+			receiver->invalid_frames++;
+		receiver->state = TURBO_FRAMED_MAGIC_T;
+		break;
+	}
+}
+
+#endif
@@ -0,0 +1,64 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::path::{Path, PathBuf};
+use turbo_audio::{
+    audio::signal_generator::SyntheticAudio, headless::EffectRenderer,
+    plugins::effects::registry::RHAI_EFFECTS_FOLDER,
+};
+
+const LUA_EFFECTS_FOLDER: &str = "../effects/lua/";
+const PIXELS: [usize; 3] = [60, 300, 1200];
+// Fits the schemas of every effect of the repo
+const SETTINGS: &str = r#"{"enable_beep_boops": false, "intensity": 5, "speed": 1.0}"#;
+// Does nothing, so that rendering it measures what a lua effect costs on its own
+const EMPTY_LUA_EFFECT: &str = "SettingsSchema = {}\n\nfunction Tick()\nend\n";
+
+fn effect_files(folder: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(folder)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    files.retain(|path| path.extension().is_some_and(|ext| ext == extension));
+    files.sort();
+    files
+}
+
+fn bench_effect(c: &mut Criterion, name: &str, effect: &Path) {
+    let mut group = c.benchmark_group(format!("effect/{name}"));
+    for pixels in PIXELS {
+        let mut renderer = EffectRenderer::new(
+            effect,
+            SETTINGS,
+            Path::new(LUA_EFFECTS_FOLDER),
+            pixels,
+            SyntheticAudio::Kicks(120.0),
+            48000,
+        )
+        .unwrap_or_else(|e| panic!("Couldn't load {}: {e}", effect.display()));
+        // Effects reacting to the audio get something to react to
+        renderer.feed_audio();
+        group.throughput(Throughput::Elements(pixels as u64));
+        group.bench_function(BenchmarkId::from_parameter(pixels), |b| {
+            b.iter(|| renderer.render().len())
+        });
+    }
+    group.finish();
+}
+
+// Every lua and rhai effect of the repo, rendering a frame at several strip lengths
+fn effects(c: &mut Criterion) {
+    let files = effect_files(Path::new(LUA_EFFECTS_FOLDER), "lua")
+        .into_iter()
+        .chain(effect_files(Path::new(RHAI_EFFECTS_FOLDER), "rhai"));
+    for effect in files {
+        let name = effect.file_name().unwrap().to_string_lossy().into_owned();
+        bench_effect(c, &name, &effect);
+    }
+}
+
+fn lua_tick_overhead(c: &mut Criterion) {
+    let effect = std::env::temp_dir().join("turbo_audio_bench_empty.lua");
+    std::fs::write(&effect, EMPTY_LUA_EFFECT).expect("Couldn't write the empty lua effect");
+    bench_effect(c, "empty.lua", &effect);
+}
+
+criterion_group!(benches, effects, lua_tick_overhead);
+criterion_main!(benches);
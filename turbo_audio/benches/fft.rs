@@ -0,0 +1,37 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::f32::consts::TAU;
+use turbo_audio::{
+    audio::audio_processing::{AudioSignalProcessor, FftConfig},
+    ticks_per_second,
+};
+
+const SAMPLE_RATE: u32 = 48000;
+
+// A tick of a 440Hz tone, analyzed in windows of several sizes with the default overlap
+fn compute_fft(c: &mut Criterion) {
+    let samples_per_tick = (SAMPLE_RATE / ticks_per_second()) as usize;
+    let tick: Vec<f32> = (0..samples_per_tick)
+        .map(|index| 0.5 * (TAU * 440.0 * index as f32 / SAMPLE_RATE as f32).sin())
+        .collect();
+
+    let mut group = c.benchmark_group("compute_fft");
+    for size in [512, 1024, 2048, 4096] {
+        let config = FftConfig {
+            size,
+            hop: size / 4,
+            ..Default::default()
+        };
+        let (mut audio_tx, audio_rx) = ringbuf::HeapRb::<f32>::new(samples_per_tick).split();
+        let mut audio_processor = AudioSignalProcessor::new(audio_rx, SAMPLE_RATE, config);
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                audio_tx.push_slice(&tick);
+                audio_processor.compute_fft();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, compute_fft);
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use turbo_audio::{
+    connections::encoder::{Crc, CustomFraming, FrameEncoding, LengthField},
+    post_processing::{self, ColorCalibration, PostProcessingChain, PostProcessingStage},
+    simd,
+};
+
+const PIXELS: [usize; 3] = [60, 300, 1200];
+
+// Turning the rgb bytes of a ledstrip into the packets of every protocol
+fn encode(c: &mut Criterion) {
+    let encodings = [
+        ("raw_rgb", FrameEncoding::RawRgb),
+        ("adalight", FrameEncoding::Adalight),
+        ("wled", FrameEncoding::Wled { timeout_s: 2 }),
+        (
+            "sacn",
+            FrameEncoding::Sacn {
+                universe: 1,
+                priority: 100,
+            },
+        ),
+        ("ddp", FrameEncoding::Ddp { destination_id: 1 }),
+        (
+            "custom",
+            FrameEncoding::Custom(CustomFraming {
+                header: b"TA".to_vec(),
+                stats: true,
+                length: Some(LengthField::U16Be),
+                sequence: true,
+                crc: Some(Crc::Crc32),
+            }),
+        ),
+        ("framed", FrameEncoding::Framed),
+    ];
+
+    for (name, encoding) in encodings {
+        let mut group = c.benchmark_group(format!("encode/{name}"));
+        for pixels in PIXELS {
+            let rgb: Vec<u8> = (0..pixels * 3).map(|index| index as u8).collect();
+            let mut encoder = encoding.encoder();
+            group.throughput(Throughput::Elements(pixels as u64));
+            group.bench_function(BenchmarkId::from_parameter(pixels), |b| {
+                b.iter(|| encoder.encode(&rgb))
+            });
+        }
+        group.finish();
+    }
+}
+
+// Turning the colors of a ledstrip into its rgb bytes, with the vectorized kernels and with the
+// plain loops they replace
+fn output_stage(c: &mut Criterion) {
+    let stages = [
+        PostProcessingStage::Brightness,
+        PostProcessingStage::Gamma { gamma: 2.2 },
+        PostProcessingStage::WhiteBalance {
+            r: 1.0,
+            g: 0.9,
+            b: 0.8,
+        },
+        PostProcessingStage::Limiter { max_power: 0.5 },
+    ];
+    let context = post_processing::ProcessingContext { brightness: 0.8 };
+
+    for (name, enabled) in [("simd", true), ("scalar", false)] {
+        simd::set_enabled(enabled);
+        let mut group = c.benchmark_group(format!("output/{name}"));
+        for pixels in PIXELS {
+            let colors: Vec<[f32; 3]> = (0..pixels)
+                .map(|index| [0, 1, 2].map(|channel| ((index * 3 + channel) % 256) as f32))
+                .collect();
+            let mut chain = PostProcessingChain::new(&stages, &ColorCalibration::default());
+            let mut bytes = Vec::new();
+            group.throughput(Throughput::Elements(pixels as u64));
+            group.bench_function(BenchmarkId::from_parameter(pixels), |b| {
+                b.iter(|| {
+                    post_processing::quantize(chain.process(&colors, &context), &mut bytes);
+                    bytes.len()
+                })
+            });
+        }
+        group.finish();
+    }
+    simd::set_enabled(true);
+}
+
+criterion_group!(benches, encode, output_stage);
+criterion_main!(benches);
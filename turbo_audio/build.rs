@@ -0,0 +1,17 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+// Generates the server of the gRPC api, with the protoc bundled in the build dependencies so that
+// building doesn't need it installed
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("No protoc for this platform");
+    std::env::set_var("PROTOC", protoc);
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/turbo_audio.proto"], &["proto"])
+        .expect("Couldn't compile proto/turbo_audio.proto");
+}
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use turbo_audio::resources::color::ConfigColor;
+
+// The colors of the settings, written as strings or as json objects
+fuzz_target!(|text: &str| {
+    let _ = text.parse::<ConfigColor>();
+    if let Ok(value) = serde_json::from_str::<serde_json::Value>(text) {
+        let _ = ConfigColor::try_from(&value);
+    }
+});
@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use turbo_audio::config_parser::{parse_config, ConfigFormat};
+
+// A settings file in any format is parsed or rejected with an error, never a panic, and so are
+// its ledstrips once built and rendered like a frame of the run loop
+fuzz_target!(|text: &str| {
+    for format in [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml] {
+        let Ok(parsed) = parse_config(text, format) else {
+            continue;
+        };
+        for ledstrip in &parsed.config.ledstrips {
+            let Ok(mut ledstrip) = ledstrip.build() else {
+                continue;
+            };
+            for effect in &mut ledstrip.effects {
+                let (start, end) = effect.interval;
+                let segment = &mut ledstrip.colors[start..=end];
+                let mut pixels = effect.layout.gather(segment);
+                if let Some(blur) = &mut effect.blur {
+                    blur.apply(&mut pixels);
+                }
+                effect.layout.scatter(&pixels, segment);
+            }
+            if let Some(transform) = &mut ledstrip.transform {
+                transform.apply(&ledstrip.colors);
+            }
+        }
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use turbo_audio::parameter_mapping::Expression;
+
+// The parameter bindings and derived features of the config
+fuzz_target!(|source: &str| {
+    let _ = Expression::parse(source);
+});
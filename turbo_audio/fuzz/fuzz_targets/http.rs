@@ -0,0 +1,27 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use turbo_audio::control::http::answer;
+
+// A request line like `PUT /brightness` and a body, routed and parsed up to the command sent to
+// the engine, which is stopped
+fuzz_target!(|data: &[u8]| {
+    let (request, body) = match data.iter().position(|byte| *byte == b'\n') {
+        Some(end) => (&data[..end], &data[end + 1..]),
+        None => (data, &[][..]),
+    };
+    let Some((method, url)) = std::str::from_utf8(request)
+        .ok()
+        .and_then(|request| request.split_once(' '))
+    else {
+        return;
+    };
+    let Ok(method) = method.parse() else {
+        return;
+    };
+    let (sender, _) = std::sync::mpsc::channel();
+    let capture_dir = std::env::temp_dir();
+    for authorized in [false, true] {
+        let _ = answer(&method, url, body, authorized, Some(&capture_dir), &sender);
+    }
+});
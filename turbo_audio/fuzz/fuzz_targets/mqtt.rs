@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use turbo_audio::control::mqtt::{apply_command, command_ledstrip, LightState};
+
+// The topic and json payload of a message on the command topics of the Home Assistant lights
+fuzz_target!(|message: (&str, &[u8])| {
+    let (topic, payload) = message;
+    let ledstrip_id = command_ledstrip("turbo_audio", topic).unwrap_or(0);
+    let mut state = LightState::default();
+    let _ = apply_command(&mut state, ledstrip_id, payload);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use turbo_audio::control::osc::{decode_packet, to_command};
+
+// Any udp packet sent to the OSC port is decoded and mapped to commands or rejected
+fuzz_target!(|data: &[u8]| {
+    if let Ok(messages) = decode_packet(data) {
+        for message in messages {
+            let _ = to_command(message);
+        }
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use turbo_audio::control::socket::answer;
+
+// Any line written to the control socket is answered, with an error when the engine is stopped
+fuzz_target!(|line: &str| {
+    let (sender, _) = std::sync::mpsc::channel();
+    let _ = answer(line, &sender);
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Mutex, OnceLock},
+};
+use turbo_audio::sync::{local_time, SyncConfig, SyncPeer};
+
+// Kept between the runs, so that the messages are followed from a leader already seen
+static PEER: OnceLock<Mutex<SyncPeer>> = OnceLock::new();
+
+// A udp packet on the sync port, from one of a few leaders, followed and used like the clock of
+// the effects does
+fuzz_target!(|message: (u8, &[u8])| {
+    let (sender, data) = message;
+    let peer = PEER.get_or_init(|| {
+        // A leader binds a free port, so that the runs don't compete for the sync port
+        let config: SyncConfig = serde_json::from_str(r#"{"role": "leader"}"#).unwrap();
+        Mutex::new(SyncPeer::new(&config).unwrap())
+    });
+    let mut peer = peer.lock().unwrap();
+    let address = SocketAddr::from((Ipv4Addr::new(10, 0, 0, sender % 4), 9940));
+    let _ = peer.on_message(address, data);
+    if let Some(beat) = peer.leader_beat() {
+        let _ = beat.phase(local_time() + peer.offset());
+    }
+});
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+/// Joins the Ableton Link session of the local network, whose tempo and beat phase, shared by DJ
+/// software and other Link-enabled applications, drive the beat of the effects instead of the
+/// beats detected in the audio. Needs turbo_audio to be built with the `link` feature
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct AbletonLinkConfig {
+    /// Only follows the session while its peers play, for the applications that share their
+    /// start and stop
+    pub start_stop_sync: bool,
+    /// Milliseconds between two reads of the session
+    pub poll_ms: u64,
+}
+
+impl Default for AbletonLinkConfig {
+    fn default() -> Self {
+        Self {
+            start_stop_sync: false,
+            poll_ms: 10,
+        }
+    }
+}
+
+#[cfg(feature = "link")]
+pub use session::AbletonLink;
+
+#[cfg(feature = "link")]
+mod session {
+    use super::AbletonLinkConfig;
+    use crate::sync::{local_time, SharedTransport, TransportPosition};
+    use rusty_link::{AblLink, SessionState};
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+
+    // Beats in a bar, so that the bars of the session line up with the ones of the effects
+    const QUANTUM: f64 = 4.0;
+    // Tempo of the session when no other peer is there
+    const INITIAL_BPM: f64 = 120.0;
+
+    /// Takes part in the session on its own thread, storing its tempo and position for the
+    /// controller while it has other peers. They are cleared once it stops
+    pub struct AbletonLink {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl AbletonLink {
+        pub fn new(config: &AbletonLinkConfig, position: SharedTransport) -> Self {
+            let stop: Arc<AtomicBool> = Arc::default();
+            let period = Duration::from_millis(config.poll_ms.max(1));
+            let start_stop_sync = config.start_stop_sync;
+            let thread = {
+                let stop = stop.clone();
+                thread::Builder::new()
+                    .name("ableton-link".to_owned())
+                    .spawn(move || {
+                        let link = AblLink::new(INITIAL_BPM);
+                        link.enable_start_stop_sync(start_stop_sync);
+                        link.enable(true);
+                        tracing::info!("Joined the Ableton Link session");
+                        let mut state = SessionState::new();
+                        let mut peers = 0;
+                        while !stop.load(Ordering::Relaxed) {
+                            let peer_count = link.num_peers();
+                            if peer_count != peers {
+                                tracing::info!("{peer_count} peers in the Ableton Link session");
+                                peers = peer_count;
+                            }
+                            link.capture_app_session_state(&mut state);
+                            // Alone, the session only has the tempo it started with
+                            let playing = !start_stop_sync || state.is_playing();
+                            let session = (peers > 0 && playing).then(|| TransportPosition {
+                                beats: state.beat_at_time(link.clock_micros(), QUANTUM),
+                                bpm: state.tempo(),
+                                read_at: local_time(),
+                            });
+                            *position.lock().unwrap() = session;
+                            thread::sleep(period);
+                        }
+                        link.enable(false);
+                        *position.lock().unwrap() = None;
+                    })
+                    .expect("Couldn't start the Ableton Link thread")
+            };
+            Self {
+                stop,
+                thread: Some(thread),
+            }
+        }
+    }
+
+    impl Drop for AbletonLink {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                if thread.join().is_err() {
+                    tracing::error!("The Ableton Link thread panicked");
+                }
+            }
+        }
+    }
+}
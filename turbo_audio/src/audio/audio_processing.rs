@@ -1,18 +1,77 @@
-use dasp::Sample;
-use dasp_signal::Signal;
+use super::{
+    noise_gate::{NoiseGate, NoiseGateConfig},
+    smoothing::SmoothingProfile,
+    wav_capture::WavCapture,
+    weighting::Weighting,
+};
+use arc_swap::ArcSwap;
 use dasp_window::Window;
-use rustfft::num_complex::Complex;
-use std::sync::{Arc, RwLock};
+use realfft::{num_complex::Complex, RealToComplex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::Arc,
+};
+pub use turbo_plugin::audio_api::{Chroma, SpectralFeatures};
 
-#[derive(Default)]
+// Share of the energy below the spectral rolloff
+const ROLLOFF_ENERGY: f32 = 0.85;
+// Keeps the log of silent bins finite in the spectral flatness
+const FLATNESS_EPSILON: f32 = 1e-10;
+// Frequencies the chroma is computed from, from A1 to about D#8. The bins below are too coarse
+// to tell the notes apart
+const CHROMA_RANGE: (f32, f32) = (55.0, 5000.0);
+// Mean of the square of the hann window, the share of the energy of the samples it keeps
+const HANN_MEAN_SQUARE: f32 = 0.375;
+
+/// Windows the audio is analyzed in. They overlap so that the features follow the audio more
+/// closely than the window size alone allows
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct FftConfig {
+    /// Samples in a window. Bigger windows tell the low frequencies apart better but react slower
+    pub size: usize,
+    /// Samples between the starts of two windows, the features update `sample_rate / hop` times
+    /// per second. At most `size`, for windows that don't overlap
+    pub hop: usize,
+    /// Frames kept for the effects to read back, `history * hop / sample_rate` seconds of them
+    pub history: usize,
+}
+
+impl Default for FftConfig {
+    fn default() -> Self {
+        Self {
+            size: 1024,
+            hop: 256,
+            history: 64,
+        }
+    }
+}
+
+/// Latest fft result, replaced as a whole every frame so that the readers never wait for the audio
+/// processing, nor the other way around. A loaded frame stays the same as long as it's held
+pub type SharedFftResult = Arc<ArcSwap<FftResult>>;
+
+/// Amplitudes of the frequencies of a frame, from 0Hz to the nyquist frequency
+#[derive(Debug, Default, Clone)]
 pub struct FftResult {
     raw_bins: Vec<f32>,
     fft_resolution: f32,
+    spectral_features: SpectralFeatures,
+    chroma: Chroma,
+    rms: f32,
+    // Samples of the window, oldest first, before the hann window
+    samples: Vec<f32>,
+    // Bins of the frames before this one, the most recent first. Shared between the frames, so
+    // keeping them costs a copy of the bins per frame
+    history: VecDeque<Arc<[f32]>>,
 }
 
 impl Drop for FftResult {
     fn drop(&mut self) {
-        log::debug!("Dropping FftResult");
+        tracing::debug!("Dropping FftResult");
     }
 }
 
@@ -21,21 +80,251 @@ impl FftResult {
         Self {
             raw_bins,
             fft_resolution,
+            spectral_features: SpectralFeatures::default(),
+            chroma: Chroma::default(),
+            rms: 0.0,
+            samples: Vec::new(),
+            history: VecDeque::new(),
         }
     }
 
+    pub fn raw_bins(&self) -> &[f32] {
+        &self.raw_bins
+    }
+
+    pub fn fft_resolution(&self) -> f32 {
+        self.fft_resolution
+    }
+
+    /// Spectral features of the frame, computed once when its bins are set
+    pub fn spectral_features(&self) -> SpectralFeatures {
+        self.spectral_features
+    }
+
+    /// Chroma of the frame, computed once when its bins are set
+    pub fn chroma(&self) -> Chroma {
+        self.chroma
+    }
+
+    /// Root mean square of the samples of the frame, in [0, 1] for samples in [-1, 1]
+    pub fn rms(&self) -> f32 {
+        self.rms
+    }
+
+    /// Last `count` samples of the window the frame was computed from, oldest first, in [-1, 1].
+    /// The whole window for a bigger count. Empty for the frames of a recording, which only
+    /// keeps the bins
+    pub fn samples(&self, count: usize) -> &[f32] {
+        &self.samples[self.samples.len().saturating_sub(count)..]
+    }
+
+    /// Root mean square of the last `count` samples, following the loudness closer than
+    /// [`Self::rms`] for counts shorter than the window
+    pub fn waveform_rms(&self, count: usize) -> f32 {
+        let samples = self.samples(count);
+        if samples.is_empty() {
+            return 0.0;
+        }
+        (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
     pub fn get_max_frequency(&self) -> f32 {
         self.get_bin_frequency_at_index(self.raw_bins.len() - 1)
     }
 
+    pub fn get_frequency_amplitude(&self, frequency: f32) -> Option<f32> {
+        self.spectrum(0)?.get_frequency_amplitude(frequency)
+    }
+
+    pub fn get_average_amplitude(&self, lower_frequency: f32, upper_frequency: f32) -> Option<f32> {
+        self.spectrum(0)?
+            .get_average_amplitude(lower_frequency, upper_frequency)
+    }
+
+    /// Average amplitudes of `count` bands spread evenly over `range` on a log frequency scale,
+    /// like the ear hears them. 0 for the bands past the max frequency
+    pub fn log_bands(&self, count: usize, range: (f32, f32)) -> Vec<f32> {
+        let ratio = (range.1 / range.0).powf(1.0 / count as f32);
+        (0..count)
+            .map(|band| {
+                let lower_frequency = range.0 * ratio.powi(band as i32);
+                self.get_average_amplitude(lower_frequency, lower_frequency * ratio)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Average amplitude between two frequencies `frames_back` frames ago, the current frame
+    /// being 0. None past the frames kept, [`Self::history_len`]
+    pub fn get_past_average_amplitude(
+        &self,
+        frames_back: usize,
+        lower_frequency: f32,
+        upper_frequency: f32,
+    ) -> Option<f32> {
+        self.spectrum(frames_back)?
+            .get_average_amplitude(lower_frequency, upper_frequency)
+    }
+
+    /// Frames that can be read back, the current one included
+    pub fn history_len(&self) -> usize {
+        self.history.len() + 1
+    }
+
+    // Bins of the frame `frames_back` frames ago
+    fn spectrum(&self, frames_back: usize) -> Option<Spectrum<'_>> {
+        let bins = match frames_back {
+            0 => &self.raw_bins,
+            _ => self.history.get(frames_back - 1)?.as_ref(),
+        };
+        Some(Spectrum {
+            bins,
+            fft_resolution: self.fft_resolution,
+        })
+    }
+
+    // Keeps the bins of `previous` in front of its history, up to `size` frames
+    fn remember(&mut self, previous: &FftResult, size: usize) {
+        self.history.clone_from(&previous.history);
+        self.history.truncate(size);
+        if size > 0 {
+            if self.history.len() == size {
+                self.history.pop_back();
+            }
+            self.history
+                .push_front(Arc::from(previous.raw_bins.as_slice()));
+        }
+    }
+
+    fn get_bin_frequency_at_index(&self, index: usize) -> f32 {
+        index as f32 * self.fft_resolution
+    }
+
+    /// Moves every bin towards `target` using an exponential moving average
+    fn smooth_towards(&mut self, target: &FftResult, alpha: f32) {
+        self.fft_resolution = target.fft_resolution;
+        // The waveform isn't smoothed, it would cancel itself out
+        self.samples.clone_from(&target.samples);
+        self.raw_bins.resize(target.raw_bins.len(), 0.0);
+        let mut flux = 0.0;
+        self.raw_bins
+            .iter_mut()
+            .zip(target.raw_bins.iter())
+            .for_each(|(smoothed, raw)| {
+                let step = alpha * (raw - *smoothed);
+                flux += step.max(0.0);
+                *smoothed += step;
+            });
+        self.update_spectral_features(flux / self.raw_bins.len().max(1) as f32);
+        self.update_chroma();
+        self.update_rms();
+    }
+
+    // Computes the features of new bins, `previous` being the frame before them
+    fn update_features(&mut self, previous: &FftResult) {
+        self.update_spectral_features(spectral_flux(&previous.raw_bins, &self.raw_bins));
+        self.update_chroma();
+        self.update_rms();
+    }
+
+    fn update_rms(&mut self) {
+        self.rms = bins_rms(&self.raw_bins);
+    }
+
+    /// Computes the spectral features of the current bins, given the flux since the previous
+    /// frame, which needs bins that aren't kept
+    fn update_spectral_features(&mut self, flux: f32) {
+        let spectrum = &self.raw_bins;
+        let total: f32 = spectrum.iter().sum();
+        if spectrum.len() < 2 || total <= 0.0 || !total.is_finite() {
+            self.spectral_features = SpectralFeatures {
+                flux,
+                ..Default::default()
+            };
+            return;
+        }
+
+        let centroid = spectrum
+            .iter()
+            .enumerate()
+            .map(|(index, amplitude)| self.get_bin_frequency_at_index(index) * amplitude)
+            .sum::<f32>()
+            / total;
+
+        let mut energy = 0.0;
+        let rolloff_index = spectrum
+            .iter()
+            .position(|amplitude| {
+                energy += amplitude;
+                energy >= ROLLOFF_ENERGY * total
+            })
+            .unwrap_or(spectrum.len() - 1);
+
+        let mean_log = spectrum
+            .iter()
+            .map(|amplitude| (amplitude + FLATNESS_EPSILON).ln())
+            .sum::<f32>()
+            / spectrum.len() as f32;
+        let flatness = mean_log.exp() / (total / spectrum.len() as f32);
+
+        self.spectral_features = SpectralFeatures {
+            flux,
+            centroid,
+            rolloff: self.get_bin_frequency_at_index(rolloff_index),
+            flatness: flatness.clamp(0.0, 1.0),
+        };
+    }
+
+    /// Sums the bins into the pitch class of their frequency, equal temperament tuned to A440
+    fn update_chroma(&mut self) {
+        let mut pitch_classes = [0.0f32; 12];
+        for (index, amplitude) in self.raw_bins.iter().enumerate() {
+            let frequency = self.get_bin_frequency_at_index(index);
+            if frequency < CHROMA_RANGE.0 || frequency > CHROMA_RANGE.1 || !amplitude.is_finite() {
+                continue;
+            }
+            // Midi note number, 69 being A4
+            let note = 69.0 + 12.0 * (frequency / 440.0).log2();
+            pitch_classes[(note.round() as usize) % 12] += amplitude;
+        }
+
+        let (dominant, strongest) = pitch_classes.iter().copied().enumerate().fold(
+            (-1, 0.0),
+            |(dominant, strongest), (pitch_class, energy)| {
+                if energy > strongest {
+                    (pitch_class as i32, energy)
+                } else {
+                    (dominant, strongest)
+                }
+            },
+        );
+        if strongest > 0.0 {
+            pitch_classes
+                .iter_mut()
+                .for_each(|energy| *energy /= strongest);
+        }
+        self.chroma = Chroma {
+            pitch_classes,
+            dominant,
+        };
+    }
+}
+
+// Bins of a frame, the current one or one of the history
+struct Spectrum<'a> {
+    bins: &'a [f32],
+    fft_resolution: f32,
+}
+
+impl Spectrum<'_> {
     pub fn get_frequency_amplitude(&self, frequency: f32) -> Option<f32> {
         let lower_index = (frequency / self.fft_resolution) as usize;
         let upper_index = lower_index + 1;
         let precise_index = frequency / self.fft_resolution;
         Some(
-            self.raw_bins.get(lower_index)?
+            self.bins.get(lower_index)?
                 + (precise_index - lower_index as f32)
-                    * (self.raw_bins.get(upper_index)? - self.raw_bins.get(lower_index)?),
+                    * (self.bins.get(upper_index)? - self.bins.get(lower_index)?),
         )
     }
 
@@ -66,16 +355,16 @@ impl FftResult {
         }
 
         let lower_partial_area = (self.get_frequency_amplitude(lower_frequency)?
-            + self.raw_bins.get(low_known_index)?)
+            + self.bins.get(low_known_index)?)
             / 2.0f32
             * (self.get_bin_frequency_at_index(low_known_index) - lower_frequency);
 
         let upper_partial_area = (self.get_frequency_amplitude(upper_frequency)?
-            + self.raw_bins.get(upper_known_index)?)
+            + self.bins.get(upper_known_index)?)
             / 2.0f32
             * (upper_frequency - self.get_bin_frequency_at_index(upper_known_index));
 
-        let area_no_lerp = self.raw_bins[low_known_index..=upper_known_index]
+        let area_no_lerp = self.bins[low_known_index..=upper_known_index]
             .windows(2)
             .map(|slice| (slice[0] + slice[1]) / 2.0f32 * self.fft_resolution)
             .sum::<f32>();
@@ -88,79 +377,321 @@ impl FftResult {
     }
 }
 
+// Average increase of the bins of `current` over the ones of `previous`, ignoring the decreases
+fn spectral_flux(previous: &[f32], current: &[f32]) -> f32 {
+    if current.is_empty() {
+        return 0.0;
+    }
+    previous
+        .iter()
+        .zip(current)
+        .map(|(previous, current)| (current - previous).max(0.0))
+        .sum::<f32>()
+        / current.len() as f32
+}
+
+/// Gets the rms of the samples from their bins with Parseval's theorem, so that the recorded and
+/// smoothed bins have an rms too
+pub fn bins_rms(bins: &[f32]) -> f32 {
+    let bin_count = bins.len();
+    if bin_count < 2 {
+        return 0.0;
+    }
+    let size = (2 * (bin_count - 1)) as f32;
+    // The bins are the squared magnitudes divided by sqrt(size). The upper half of the spectrum
+    // mirrors them, except for the first and last bins
+    let power = 2.0 * bins.iter().sum::<f32>() - bins[0] - bins[bin_count - 1];
+    let windowed_mean_square = power * size.sqrt() / (size * size);
+    (windowed_mean_square / HANN_MEAN_SQUARE).max(0.0).sqrt()
+}
+
 pub struct AudioSignalProcessor {
     audio_sample_buffer: dasp_ring_buffer::Fixed<Vec<f32>>,
     audio_sample_rx: ringbuf::HeapConsumer<f32>,
     tmp_vec: Vec<f32>,
-    fft_plan: Arc<dyn rustfft::Fft<f32>>,
-    fft_compute_buffer: Vec<Complex<f32>>,
-    fft_window_buffer: Vec<Complex<f32>>,
+    fft_plan: Arc<dyn RealToComplex<f32>>,
+    // Hann window, applied to the samples before the fft
+    fft_window: Vec<f32>,
+    fft_input: Vec<f32>,
+    fft_output: Vec<Complex<f32>>,
+    fft_scratch: Vec<Complex<f32>>,
     fft_buffer_size: usize,
-    pub fft_result: Arc<RwLock<FftResult>>,
+    hop: usize,
+    // Frames kept in the history of the fft results
+    history: usize,
+    samples_since_fft: usize,
+    // Seconds of audio between two ffts
+    frame_duration: f32,
+    pub fft_result: SharedFftResult,
+    smoothed_fft_results: HashMap<SmoothingProfile, SharedFftResult>,
+    // Frames published before the current ones, written over by the next ones
+    spare_fft_results: HashMap<SmoothingProfile, Arc<FftResult>>,
+    noise_gate: NoiseGate,
+    // Factor of every bin, once the noise is gated
+    bin_gains: Option<Vec<f32>>,
+    // Samples read by the last compute_fft
+    last_sample_count: usize,
+    sample_rate: u32,
+    capture: Option<WavCapture>,
 }
 
 impl AudioSignalProcessor {
-    pub fn new(
-        audio_rx: ringbuf::HeapConsumer<f32>,
-        sample_rate: u32,
-        fft_buffer_size: usize,
-    ) -> Self {
-        let mut planner = rustfft::FftPlanner::new();
+    pub fn new(audio_rx: ringbuf::HeapConsumer<f32>, sample_rate: u32, config: FftConfig) -> Self {
+        let fft_buffer_size = config.size.max(2);
+        let hop = config.hop.clamp(1, fft_buffer_size);
+        if hop != config.hop || fft_buffer_size != config.size {
+            tracing::warn!(
+                "Invalid fft size {} or hop {}, using {fft_buffer_size} and {hop}",
+                config.size,
+                config.hop
+            );
+        }
+        let fft_plan = realfft::RealFftPlanner::new().plan_fft_forward(fft_buffer_size);
+        let fft_resolution = sample_rate as f32 / fft_buffer_size as f32;
+        // The fft of real samples is symmetric, only the bins up to the nyquist frequency are kept
+        let bin_count = fft_buffer_size / 2 + 1;
+        let fft_result = Arc::new(ArcSwap::from_pointee(FftResult::new(
+            vec![0.0f32; bin_count],
+            fft_resolution,
+        )));
+        let smoothed_fft_results = SmoothingProfile::ALL
+            .into_iter()
+            .map(|profile| match profile {
+                SmoothingProfile::Raw => (profile, fft_result.clone()),
+                _ => (
+                    profile,
+                    Arc::new(ArcSwap::from_pointee(FftResult::new(
+                        vec![0.0f32; bin_count],
+                        fft_resolution,
+                    ))),
+                ),
+            })
+            .collect();
+        let fft_window = (0..fft_buffer_size)
+            .map(|index| {
+                dasp_window::Hanning::window(index as f32 / (fft_buffer_size as f32 - 1.0))
+            })
+            .collect();
         Self {
             audio_sample_buffer: dasp_ring_buffer::Fixed::from(vec![0_f32; fft_buffer_size]),
             audio_sample_rx: audio_rx,
             tmp_vec: vec![0f32; fft_buffer_size],
-            fft_compute_buffer: vec![Complex::<f32>::default(); fft_buffer_size],
-            fft_plan: planner.plan_fft_forward(fft_buffer_size),
-            fft_window_buffer: vec![],
+            fft_window,
+            fft_input: fft_plan.make_input_vec(),
+            fft_output: fft_plan.make_output_vec(),
+            fft_scratch: fft_plan.make_scratch_vec(),
+            fft_plan,
             fft_buffer_size,
-            fft_result: Arc::new(RwLock::new(FftResult::new(
-                vec![0.0f32; fft_buffer_size],
-                sample_rate as f32 / fft_buffer_size as f32,
-            ))),
+            hop,
+            history: config.history,
+            samples_since_fft: 0,
+            frame_duration: hop as f32 / sample_rate as f32,
+            fft_result,
+            smoothed_fft_results,
+            spare_fft_results: HashMap::new(),
+            noise_gate: NoiseGate::default(),
+            bin_gains: None,
+            last_sample_count: 0,
+            sample_rate,
+            capture: None,
+        }
+    }
+
+    /// Subtracts the noise floor saved in the config from the computed frames and gates them.
+    /// The bins given to [`Self::set_fft`] are left as is
+    pub fn set_noise_gate(&mut self, config: NoiseGateConfig) {
+        self.noise_gate = NoiseGate::new(config, self.fft_buffer_size / 2 + 1);
+    }
+
+    /// Measures the noise floor over the next `seconds` of audio, then saves and subtracts it
+    pub fn set_weighting(&mut self, weighting: &Weighting) {
+        let fft_resolution = self.fft_result.load().fft_resolution();
+        self.bin_gains = weighting.bin_gains(self.fft_buffer_size / 2 + 1, fft_resolution);
+    }
+
+    pub fn calibrate_noise_floor(&mut self, seconds: f32) {
+        let frames = (seconds / self.frame_duration).ceil().max(1.0) as usize;
+        self.noise_gate.calibrate(frames);
+    }
+
+    /// Writes the next `seconds` of samples to a wav file, to check which stream the audio input
+    /// captures. The capture only advances while samples arrive
+    pub fn capture_audio(&mut self, path: &Path, seconds: f32) -> Result<(), String> {
+        if let Some(capture) = &self.capture {
+            return Err(format!(
+                "Already capturing the audio to {}",
+                capture.path().display()
+            ));
         }
+        let capture = WavCapture::create(path, self.sample_rate, seconds)
+            .map_err(|e| format!("Couldn't create {}: {e}", path.display()))?;
+        tracing::info!("Capturing {seconds}s of audio to {}", path.display());
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    /// Reads the samples from another stream, like when the audio device was restarted
+    pub fn set_audio_rx(&mut self, audio_rx: ringbuf::HeapConsumer<f32>) {
+        self.audio_sample_rx = audio_rx;
+        self.samples_since_fft = 0;
+    }
+
+    /// Returns the fft results for every smoothing profile
+    pub fn smoothed_fft_results(&self) -> HashMap<SmoothingProfile, SharedFftResult> {
+        self.smoothed_fft_results.clone()
+    }
+
+    /// Samples read by the last [`Self::compute_fft`]. Zero while the audio stream is stalled
+    pub fn last_sample_count(&self) -> usize {
+        self.last_sample_count
     }
 
+    /// Computes an fft every `hop` samples received since the previous call, so that the features
+    /// move with the audio rather than with the ticks. Publishes silence when no sample arrived.
+    /// The samples, the window and the fft buffers are allocated once, in [`Self::new`]
     pub fn compute_fft(&mut self) {
         let sample_count = self.audio_sample_rx.pop_slice(self.tmp_vec.as_mut_slice());
+        self.last_sample_count = sample_count;
+        self.write_capture(sample_count);
         if sample_count == 0 {
             self.audio_sample_buffer.iter_mut().for_each(|x| *x = 0.0);
-        } else {
-            self.tmp_vec.iter().take(sample_count).for_each(|sample| {
-                self.audio_sample_buffer.push(*sample);
-            });
+            self.samples_since_fft = 0;
+            self.process_window();
+            return;
+        }
+
+        for index in 0..sample_count {
+            self.audio_sample_buffer.push(self.tmp_vec[index]);
+            self.samples_since_fft += 1;
+            if self.samples_since_fft == self.hop {
+                self.samples_since_fft = 0;
+                self.process_window();
+            }
         }
+    }
+
+    fn write_capture(&mut self, sample_count: usize) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        let result = match capture.write(&self.tmp_vec[..sample_count]) {
+            Ok(false) => return,
+            Ok(true) => Ok(()),
+            Err(e) => Err(e),
+        };
+        let capture = self.capture.take().unwrap();
+        let (path, seconds) = (capture.path().to_owned(), capture.seconds());
+        match result.and_then(|()| capture.finish()) {
+            Ok(()) => tracing::info!("Captured {seconds}s of audio to {}", path.display()),
+            Err(e) => tracing::error!("Stopping the audio capture to {}: {e}", path.display()),
+        }
+    }
 
-        self.fft_window_buffer.clear();
-        self.fft_window_buffer.extend(
-            dasp_signal::from_iter(
-                self.audio_sample_buffer
-                    .iter()
-                    .map(|e| e.to_sample::<f32>()),
+    fn process_window(&mut self) {
+        self.fft_input
+            .iter_mut()
+            .zip(self.audio_sample_buffer.iter().zip(&self.fft_window))
+            .for_each(|(input, (sample, hann_factor))| *input = sample * hann_factor);
+
+        // The buffers are sized by the plan, it can't fail
+        self.fft_plan
+            .process_with_scratch(
+                &mut self.fft_input,
+                &mut self.fft_output,
+                &mut self.fft_scratch,
             )
-            .scale_amp(1.0)
-            .take(self.fft_buffer_size)
-            .enumerate()
-            .map(|(index, value)| {
-                let hann_factor = dasp_window::Hanning::window(
-                    index as f32 / (self.fft_buffer_size as f32 - 1.0),
+            .unwrap();
+
+        let (fft_output, fft_buffer_size) = (&self.fft_output, self.fft_buffer_size);
+        let noise_gate = &mut self.noise_gate;
+        let bin_gains = self.bin_gains.as_deref();
+        let samples = &self.audio_sample_buffer;
+        let spare = self.spare_fft_results.remove(&SmoothingProfile::Raw);
+        let spare = publish(
+            &self.fft_result,
+            spare,
+            self.history,
+            |fft_result, previous| {
+                fft_result.fft_resolution = previous.fft_resolution;
+                fft_result.samples.clear();
+                fft_result.samples.extend(samples.iter());
+                fft_result.raw_bins.clear();
+                fft_result.raw_bins.extend(
+                    fft_output
+                        .iter()
+                        .map(|bin| bin.norm_sqr() / (fft_buffer_size as f32).sqrt()),
                 );
-                Complex::<f32> {
-                    re: value * hann_factor,
-                    im: 0.0,
+                noise_gate.apply(&mut fft_result.raw_bins);
+                if let Some(bin_gains) = bin_gains {
+                    fft_result
+                        .raw_bins
+                        .iter_mut()
+                        .zip(bin_gains)
+                        .for_each(|(bin, gain)| *bin *= gain);
                 }
-            }),
+                fft_result.update_features(previous);
+            },
         );
+        self.spare_fft_results.insert(SmoothingProfile::Raw, spare);
 
-        self.fft_plan
-            .process_with_scratch(&mut self.fft_window_buffer, &mut self.fft_compute_buffer);
-
-        let mut fft_result = self.fft_result.write().unwrap();
-        fft_result.raw_bins.clear();
-        fft_result.raw_bins.extend(
-            self.fft_window_buffer
-                .iter()
-                .map(|bin| bin.norm_sqr() / (self.fft_buffer_size as f32).sqrt()),
+        self.update_smoothed_fft_results(self.frame_duration);
+    }
+
+    /// Publishes fft bins that were computed elsewhere, like in a recording, instead of computing
+    /// them from the audio stream
+    pub fn set_fft(&mut self, raw_bins: &[f32], fft_resolution: f32) {
+        let spare = self.spare_fft_results.remove(&SmoothingProfile::Raw);
+        let spare = publish(
+            &self.fft_result,
+            spare,
+            self.history,
+            |fft_result, previous| {
+                fft_result.fft_resolution = fft_resolution;
+                fft_result.samples.clear();
+                fft_result.raw_bins.clear();
+                fft_result.raw_bins.extend_from_slice(raw_bins);
+                fft_result.update_features(previous);
+            },
         );
+        self.spare_fft_results.insert(SmoothingProfile::Raw, spare);
+
+        self.update_smoothed_fft_results(1.0 / crate::ticks_per_second() as f32);
     }
+
+    fn update_smoothed_fft_results(&mut self, frame_duration: f32) {
+        let fft_result = self.fft_result.load();
+        for (profile, smoothed_fft_result) in &self.smoothed_fft_results {
+            if *profile == SmoothingProfile::Raw {
+                continue;
+            }
+            let spare = self.spare_fft_results.remove(profile);
+            let spare = publish(
+                smoothed_fft_result,
+                spare,
+                self.history,
+                |smoothed, previous| {
+                    smoothed.raw_bins.clone_from(&previous.raw_bins);
+                    smoothed.smooth_towards(&fft_result, profile.alpha_over(frame_duration));
+                },
+            );
+            self.spare_fft_results.insert(*profile, spare);
+        }
+    }
+}
+
+// Publishes the frame written by `write`, given the current one, and returns the current one to
+// be given back as `spare` for the next frame. The spare is written over in place unless a reader
+// still holds it, in which case it's copied
+fn publish(
+    shared: &ArcSwap<FftResult>,
+    spare: Option<Arc<FftResult>>,
+    history: usize,
+    write: impl FnOnce(&mut FftResult, &FftResult),
+) -> Arc<FftResult> {
+    let mut next = spare.unwrap_or_default();
+    let current = shared.load();
+    let frame = Arc::make_mut(&mut next);
+    write(frame, &current);
+    frame.remember(&current, history);
+    shared.swap(next)
 }
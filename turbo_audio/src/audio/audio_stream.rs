@@ -1,17 +1,170 @@
-use anyhow::Context;
+use anyhow::{anyhow, bail, Context};
 use cpal::traits::{DeviceTrait, HostTrait};
 use cpal::{
     Device, FromSample, InputCallbackInfo, SampleFormat, StreamConfig, SupportedStreamConfig,
+    SupportedStreamConfigRange,
 };
 use retry::{delay::Exponential, retry_with_index};
 use ringbuf::{HeapConsumer, HeapProducer};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// CoreAudio can't capture the output, it has to be played to a virtual input too
+const MACOS_LOOPBACK_HINT: &str = "To react to the music playing on macOS, install a virtual \
+    device like BlackHole, group it with the speakers in a Multi-Output Device in Audio MIDI \
+    Setup, play to that device and set `device_name` to the virtual device, like \"BlackHole\"";
+
+// How long the device can go without delivering samples before the stream is considered lost.
+// Input streams deliver silence too, so this only happens when the device is gone
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What to do when the audio device or pipewire can't be used at startup
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MissingAudioBehavior {
+    /// Stop with an error
+    #[default]
+    Fail,
+    /// Render the effects on silence until the audio device shows up, then listen to it
+    NoAudio,
+}
+
+/// What the audio is captured from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioCapture {
+    /// An input device, like a microphone or the monitor of an output routed by pipewire
+    #[default]
+    Input,
+    /// What an output device is playing, the default output if no device is named. Only
+    /// supported by WASAPI on Windows and by the pulse backend
+    Loopback,
+}
+
+/// Library the audio is captured with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AudioBackend {
+    /// ALSA, which pipewire and pulseaudio also serve, WASAPI on Windows or CoreAudio on macOS
+    #[default]
+    Cpal,
+    /// The pulseaudio server, for the machines without pipewire. The devices are the names of
+    /// the pulseaudio sources, like `alsa_output.pci-0000_00_1f.3.analog-stereo.monitor`. Needs
+    /// turbo_audio to be built with the `pulse` feature
+    Pulse,
+}
+
+// Written by the callbacks of the stream
+pub(super) struct StreamHealth {
+    started_at: Instant,
+    // Milliseconds from `started_at` to the last samples
+    last_samples_ms: AtomicU64,
+    failed: AtomicBool,
+}
+
+impl StreamHealth {
+    pub(super) fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            last_samples_ms: AtomicU64::new(0),
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+
+    pub(super) fn record_samples(&self) {
+        self.last_samples_ms
+            .store(self.elapsed_ms(), Ordering::Relaxed);
+    }
+
+    pub(super) fn record_error(&self) {
+        self.failed.store(true, Ordering::Relaxed);
+    }
+}
+
+// Keeps the stream of a backend open until it's dropped
+#[allow(dead_code)]
+pub(super) enum StreamHandle {
+    Cpal(cpal::Stream),
+    #[cfg(feature = "pulse")]
+    Pulse(super::pulse_stream::PulseStream),
+}
+
+/// Stream of the audio device, which tells when the device went away, like when it's unplugged or
+/// pipewire restarts
+pub struct AudioStream {
+    _handle: StreamHandle,
+    health: Arc<StreamHealth>,
+}
+
+impl AudioStream {
+    pub(super) fn new(handle: StreamHandle, health: Arc<StreamHealth>) -> Self {
+        Self {
+            _handle: handle,
+            health,
+        }
+    }
+
+    /// Whether the stream failed or stopped delivering samples
+    pub fn is_lost(&self) -> bool {
+        let health = &self.health;
+        health.failed.load(Ordering::Relaxed)
+            || health
+                .elapsed_ms()
+                .saturating_sub(health.last_samples_ms.load(Ordering::Relaxed))
+                > STALL_TIMEOUT.as_millis() as u64
+    }
+}
 
 pub fn start_audio_loop(
     device_name: Option<String>,
     sample_rate: u32,
-) -> anyhow::Result<(cpal::Stream, HeapConsumer<f32>)> {
-    let audio_device = get_audio_device(device_name);
-    let input_config = get_input_config(&audio_device, sample_rate);
+    capture: AudioCapture,
+    backend: AudioBackend,
+) -> anyhow::Result<(AudioStream, HeapConsumer<f32>)> {
+    match backend {
+        AudioBackend::Cpal => start_cpal_stream(device_name, sample_rate, capture),
+        #[cfg(feature = "pulse")]
+        AudioBackend::Pulse => {
+            super::pulse_stream::start_pulse_stream(device_name, sample_rate, capture)
+        }
+        #[cfg(not(feature = "pulse"))]
+        AudioBackend::Pulse => bail!("turbo_audio was built without the pulse backend"),
+    }
+}
+
+// Whether the device can be listened to, without opening a stream with cpal
+fn is_device_available(
+    device_name: Option<String>,
+    capture: AudioCapture,
+    backend: AudioBackend,
+) -> bool {
+    match backend {
+        AudioBackend::Cpal => get_audio_device(device_name, capture).is_ok(),
+        #[cfg(feature = "pulse")]
+        AudioBackend::Pulse => super::pulse_stream::is_available(device_name.as_deref(), capture),
+        #[cfg(not(feature = "pulse"))]
+        AudioBackend::Pulse => false,
+    }
+}
+
+fn start_cpal_stream(
+    device_name: Option<String>,
+    sample_rate: u32,
+    capture: AudioCapture,
+) -> anyhow::Result<(AudioStream, HeapConsumer<f32>)> {
+    if cfg!(target_os = "macos") && device_name.is_none() {
+        tracing::info!("Listening to the default input. {MACOS_LOOPBACK_HINT}");
+    }
+    let audio_device = get_audio_device(device_name, capture)?;
+    let input_config = get_input_config(&audio_device, sample_rate, capture)?;
     let sample_format = input_config.sample_format();
     let config: StreamConfig = input_config.into();
     let max_retries: usize = 3;
@@ -21,13 +174,13 @@ pub fn start_audio_loop(
             let stream_result = start_stream(&config, &audio_device, &sample_format);
             match stream_result {
                 Ok(result) => {
-                    log::trace!("Started audio stream");
+                    tracing::trace!("Started audio stream");
                     Ok(result)
                 }
                 Err(err) => {
-                    log::trace!("Failed to start audio stream");
+                    tracing::trace!("Failed to start audio stream");
                     if retry_attempt < max_retries.try_into().unwrap() {
-                        log::trace!("Retrying to start audio stream...");
+                        tracing::trace!("Retrying to start audio stream...");
                     }
                     Err(err)
                 }
@@ -37,45 +190,175 @@ pub fn start_audio_loop(
     .with_context(|| "Failed to start stream")
 }
 
-fn get_audio_device(device_name: Option<String>) -> Device {
+/// Names of the devices that can be listened to
+pub fn list_input_devices() -> anyhow::Result<Vec<String>> {
+    Ok(cpal::default_host()
+        .input_devices()
+        .context("Host has no audio device")?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+fn get_audio_device(device_name: Option<String>, capture: AudioCapture) -> anyhow::Result<Device> {
+    // WASAPI captures an output when an input stream is built on it, the other hosts refuse to
+    if capture == AudioCapture::Loopback && cfg!(target_os = "macos") {
+        bail!("Loopback capture is only supported on Windows. {MACOS_LOOPBACK_HINT}");
+    }
+    if capture == AudioCapture::Loopback && !cfg!(target_os = "windows") {
+        bail!(
+            "Loopback capture is only supported on Windows, route the output to an input instead"
+        );
+    }
     let host = cpal::default_host();
 
-    match device_name {
-        Some(device_name) => host
-            .devices()
-            .expect("Host has no audio device")
-            .find(|device| device.name().unwrap() == device_name)
-            .unwrap_or_else(|| panic!("No suitable audio device found with name {device_name}")),
-        None => host
+    match (device_name, capture) {
+        (Some(device_name), _) => find_device(&host, &device_name),
+        (None, AudioCapture::Input) => host
             .default_input_device()
-            .expect("No default audio input found"),
+            .context("No default audio input found"),
+        (None, AudioCapture::Loopback) => host
+            .default_output_device()
+            .context("No default audio output found"),
     }
 }
 
-fn get_input_config(audio_device: &Device, sample_rate: u32) -> SupportedStreamConfig {
-    audio_device
-        .supported_input_configs()
-        .expect("Device has no supported input configs")
+// Finds a device by its exact name, or else by a part of it like `blackhole` for
+// `BlackHole 2ch`
+fn find_device(host: &cpal::Host, device_name: &str) -> anyhow::Result<Device> {
+    let devices: Vec<(String, Device)> = host
+        .devices()
+        .context("Host has no audio device")?
+        .filter_map(|device| Some((device.name().ok()?, device)))
+        .collect();
+    let lowercase_name = device_name.to_lowercase();
+    let position = devices
+        .iter()
+        .position(|(name, _)| name == device_name)
+        .or_else(|| {
+            devices
+                .iter()
+                .position(|(name, _)| name.to_lowercase().contains(&lowercase_name))
+        });
+    match position {
+        Some(position) => Ok(devices.into_iter().nth(position).unwrap().1),
+        None => {
+            let names: Vec<&str> = devices.iter().map(|(name, _)| name.as_str()).collect();
+            let mut error = format!(
+                "No audio device found with name {device_name}, the devices are: {}",
+                names.join(", ")
+            );
+            if cfg!(target_os = "macos") {
+                error = format!("{error}. {MACOS_LOOPBACK_HINT}");
+            }
+            Err(anyhow!(error))
+        }
+    }
+}
+
+fn get_input_config(
+    audio_device: &Device,
+    sample_rate: u32,
+    capture: AudioCapture,
+) -> anyhow::Result<SupportedStreamConfig> {
+    let mut configs: Box<dyn Iterator<Item = SupportedStreamConfigRange>> = match capture {
+        AudioCapture::Input => Box::new(
+            audio_device
+                .supported_input_configs()
+                .context("Device has no supported input configs")?,
+        ),
+        // The loopback stream has the format of the output
+        AudioCapture::Loopback => Box::new(
+            audio_device
+                .supported_output_configs()
+                .context("Device has no supported output configs")?,
+        ),
+    };
+    Ok(configs
         .next()
-        .expect("Device has no supported input configs")
-        .with_sample_rate(cpal::SampleRate(sample_rate))
+        .context("Device has no supported configs")?
+        .with_sample_rate(cpal::SampleRate(sample_rate)))
+}
+
+/// Checks in the background whether the audio device became available, while the engine runs
+/// without audio.
+pub struct AudioDeviceWatcher {
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+    available: Arc<AtomicBool>,
+}
+
+impl AudioDeviceWatcher {
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+    pub fn new(device_name: Option<String>, capture: AudioCapture, backend: AudioBackend) -> Self {
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        let available: Arc<AtomicBool> = Arc::default();
+        let thread = thread::spawn({
+            let should_quit = should_quit.clone();
+            let available = available.clone();
+            move || {
+                let mut waited = Duration::ZERO;
+                while !should_quit.load(Ordering::Relaxed) {
+                    // Sleep in small steps so that dropping the watcher doesn't wait for a poll
+                    thread::sleep(Duration::from_millis(100));
+                    waited += Duration::from_millis(100);
+                    if waited < Self::POLL_INTERVAL {
+                        continue;
+                    }
+                    waited = Duration::ZERO;
+                    if is_device_available(device_name.clone(), capture, backend) {
+                        available.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                }
+            }
+        });
+
+        Self {
+            thread: Some(thread),
+            should_quit,
+            available,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for AudioDeviceWatcher {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Audio device watcher thread panicked");
+            }
+        }
+        tracing::info!("Audio device watcher stopped.");
+    }
 }
 
 fn build_audio_stream<T: cpal::Sample + cpal::SizedSample>(
     audio_device: &Device,
     config: &StreamConfig,
     mut tx: HeapProducer<f32>,
+    health: Arc<StreamHealth>,
 ) -> Result<cpal::Stream, cpal::BuildStreamError>
 where
     f32: FromSample<T>,
 {
-    let err_fn = |err| {
-        panic!("ERROR: {err:?}");
+    let err_fn = {
+        let health = health.clone();
+        move |err| {
+            tracing::error!("Audio stream error: {err}");
+            health.record_error();
+        }
     };
 
     audio_device.build_input_stream(
         config,
         move |data: &[T], _: &InputCallbackInfo| {
+            health.record_samples();
             for point in data {
                 let _ = tx.push(point.to_sample::<f32>());
             }
@@ -89,18 +372,19 @@ fn start_stream(
     config: &StreamConfig,
     audio_device: &Device,
     sample_format: &SampleFormat,
-) -> Result<(cpal::Stream, HeapConsumer<f32>), cpal::BuildStreamError> {
+) -> Result<(AudioStream, HeapConsumer<f32>), cpal::BuildStreamError> {
     let (tx, rx) = ringbuf::HeapRb::<f32>::new(1024).split();
-    log::info!("Starting audio stream with format: {sample_format}");
+    let health = Arc::new(StreamHealth::new());
+    tracing::info!("Starting audio stream with format: {sample_format}");
     let stream = match sample_format {
-        SampleFormat::U8 => build_audio_stream::<u8>(audio_device, config, tx),
-        SampleFormat::U16 => build_audio_stream::<u16>(audio_device, config, tx),
-        SampleFormat::I16 => build_audio_stream::<i16>(audio_device, config, tx),
-        SampleFormat::F32 => build_audio_stream::<f32>(audio_device, config, tx),
+        SampleFormat::U8 => build_audio_stream::<u8>(audio_device, config, tx, health.clone()),
+        SampleFormat::U16 => build_audio_stream::<u16>(audio_device, config, tx, health.clone()),
+        SampleFormat::I16 => build_audio_stream::<i16>(audio_device, config, tx, health.clone()),
+        SampleFormat::F32 => build_audio_stream::<f32>(audio_device, config, tx, health.clone()),
         format => {
             panic!("Unimplemented format: {format}");
         }
     }?;
 
-    Ok((stream, rx))
+    Ok((AudioStream::new(StreamHandle::Cpal(stream), health), rx))
 }
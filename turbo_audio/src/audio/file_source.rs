@@ -0,0 +1,294 @@
+use super::signal_generator::{SignalGenerator, SyntheticAudio};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::{HeapConsumer, HeapProducer};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+use thiserror::Error;
+
+const PUSH_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Error, Debug)]
+pub enum FileSourceError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Couldn't decode the audio file: {0}")]
+    Decode(#[from] SymphoniaError),
+
+    #[error("The audio file doesn't contain an audio track")]
+    NoTrack,
+
+    #[error("The sample rate of the audio file is unknown")]
+    UnknownSampleRate,
+
+    #[error("The audio file doesn't contain any sample")]
+    Empty,
+}
+
+// Decodes the first audio track of a file into mono samples, starting over at its end
+struct FileDecoder {
+    path: PathBuf,
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    sample_rate: u32,
+    samples: VecDeque<f32>,
+    // Whether a sample was decoded since the file was last opened, to detect empty files
+    decoded_any: bool,
+}
+
+impl FileDecoder {
+    fn open(path: &Path) -> Result<Self, FileSourceError> {
+        let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+            hint.with_extension(extension);
+        }
+        let format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                source,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )?
+            .format;
+        let track = format
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(FileSourceError::NoTrack)?;
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or(FileSourceError::UnknownSampleRate)?;
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            track_id: track.id,
+            format,
+            decoder,
+            sample_rate,
+            samples: VecDeque::new(),
+            decoded_any: false,
+        })
+    }
+
+    fn next_sample(&mut self) -> Result<f32, FileSourceError> {
+        while self.samples.is_empty() {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    if !self.decoded_any {
+                        return Err(FileSourceError::Empty);
+                    }
+                    tracing::debug!("Reached the end of {}, looping", self.path.display());
+                    *self = Self::open(&self.path)?;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupted packet only drops a few milliseconds of audio
+                Err(SymphoniaError::DecodeError(e)) => {
+                    tracing::warn!("Skipping an undecodable packet: {e}");
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let spec = *decoded.spec();
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+
+            let channels = spec.channels.count().max(1);
+            self.samples.extend(
+                buffer
+                    .samples()
+                    .chunks(channels)
+                    .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+            );
+        }
+
+        self.decoded_any = true;
+        Ok(self.samples.pop_front().unwrap())
+    }
+}
+
+/// Plays a local audio file (wav or flac), or a generated signal, in place of the audio device,
+/// in real time and in a loop, so that demos and effects can be developed against the same song
+/// every time.
+pub struct FilePlayback {
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+    // Keeps the file playing on the default output device
+    _output: Option<cpal::Stream>,
+}
+
+impl FilePlayback {
+    /// Starts feeding the file to the returned consumer, like the audio device would. Also
+    /// returns the sample rate of the file, which the audio processor has to use.
+    pub fn start(
+        path: &Path,
+        play_through: bool,
+    ) -> Result<(Self, HeapConsumer<f32>, u32), FileSourceError> {
+        let mut decoder = FileDecoder::open(path)?;
+        let sample_rate = decoder.sample_rate;
+        tracing::info!(
+            "Playing {} at {sample_rate}Hz instead of the audio device",
+            path.display()
+        );
+        let (playback, audio_rx) = Self::feed(sample_rate, play_through, move || {
+            decoder
+                .next_sample()
+                .map_err(|e| tracing::error!("Audio file playback stopped: {e}"))
+                .ok()
+        });
+        Ok((playback, audio_rx, sample_rate))
+    }
+
+    /// Starts feeding the samples of a generated signal to the returned consumer at
+    /// `sample_rate`, like the audio device would
+    pub fn generate(
+        signal: SyntheticAudio,
+        sample_rate: u32,
+        play_through: bool,
+    ) -> (Self, HeapConsumer<f32>) {
+        tracing::info!("Generating {signal:?} at {sample_rate}Hz instead of the audio device");
+        let mut generator = SignalGenerator::new(signal, sample_rate);
+        Self::feed(sample_rate, play_through, move || {
+            Some(generator.next_sample())
+        })
+    }
+
+    // Feeds the samples of `next_sample` in real time until it returns None
+    fn feed(
+        sample_rate: u32,
+        play_through: bool,
+        mut next_sample: impl FnMut() -> Option<f32> + Send + 'static,
+    ) -> (Self, HeapConsumer<f32>) {
+        let (mut audio_tx, audio_rx) = ringbuf::HeapRb::<f32>::new(1024).split();
+
+        let (output, mut output_tx) = if play_through {
+            match start_output(sample_rate) {
+                Ok((stream, output_tx)) => (Some(stream), Some(output_tx)),
+                Err(e) => {
+                    tracing::warn!("Couldn't play the audio on the output device: {e}");
+                    (None, None)
+                }
+            }
+        } else {
+            (None, None)
+        };
+
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        let thread = thread::spawn({
+            let should_quit = should_quit.clone();
+            move || {
+                let started = Instant::now();
+                let mut pushed: u64 = 0;
+                while !should_quit.load(Ordering::Relaxed) {
+                    // The output device sets the pace when the audio is played on it, so that its
+                    // buffer never runs dry. Otherwise the wall clock does.
+                    let due = match &output_tx {
+                        Some(output_tx) => output_tx.free_len() as u64,
+                        None => {
+                            (started.elapsed().as_secs_f64() * sample_rate as f64) as u64 - pushed
+                        }
+                    };
+                    for _ in 0..due {
+                        let Some(sample) = next_sample() else {
+                            return;
+                        };
+                        // Like a live stream, samples the audio processor didn't read are dropped
+                        let _ = audio_tx.push(sample);
+                        if let Some(output_tx) = &mut output_tx {
+                            let _ = output_tx.push(sample);
+                        }
+                    }
+                    pushed += due;
+                    thread::sleep(PUSH_INTERVAL);
+                }
+            }
+        });
+
+        (
+            Self {
+                thread: Some(thread),
+                should_quit,
+                _output: output,
+            },
+            audio_rx,
+        )
+    }
+}
+
+impl Drop for FilePlayback {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Audio file playback thread panicked");
+            }
+        }
+        tracing::info!("Audio playback stopped.");
+    }
+}
+
+fn start_output(
+    sample_rate: u32,
+) -> Result<(cpal::Stream, HeapProducer<f32>), Box<dyn std::error::Error + Send + Sync>> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or("No default audio output found")?;
+    let config: cpal::StreamConfig = device
+        .supported_output_configs()?
+        .find(|config| {
+            config.sample_format() == cpal::SampleFormat::F32
+                && (config.min_sample_rate().0..=config.max_sample_rate().0).contains(&sample_rate)
+        })
+        .ok_or_else(|| format!("The output device doesn't support f32 samples at {sample_rate}Hz"))?
+        .with_sample_rate(cpal::SampleRate(sample_rate))
+        .into();
+
+    // About 50ms of audio, which is also how far the output lags behind the lights
+    let (output_tx, mut output_rx) = ringbuf::HeapRb::<f32>::new(sample_rate as usize / 20).split();
+    let channels = config.channels as usize;
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+            for frame in data.chunks_mut(channels) {
+                frame.fill(output_rx.pop().unwrap_or(0.0));
+            }
+        },
+        |err| tracing::error!("Audio output error: {err}"),
+        None,
+    )?;
+    stream.play()?;
+    Ok((stream, output_tx))
+}
@@ -1,3 +1,15 @@
 pub mod audio_processing;
 pub mod audio_stream;
+pub mod file_source;
+pub mod noise_gate;
+pub mod onset;
+pub mod percussion;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
 pub mod pipewire_listener;
+#[cfg(feature = "pulse")]
+pub mod pulse_stream;
+pub mod recording;
+pub mod signal_generator;
+pub mod smoothing;
+pub mod wav_capture;
+pub mod weighting;
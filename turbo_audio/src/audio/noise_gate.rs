@@ -0,0 +1,167 @@
+use super::audio_processing::bins_rms;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NoiseFloorError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid noise floor: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("The noise floor has {0} bins, expected {1}. Calibrate it again for this fft size")]
+    BinCount(usize, usize),
+}
+
+/// Keeps microphones from picking up the ambient noise, like fans, as sound
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct NoiseGateConfig {
+    /// Root mean square, in [0, 1], under which a frame is silenced once the noise floor is
+    /// subtracted. 0 disables the gate
+    #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+    pub threshold: f32,
+    /// Where the calibrated noise floor is saved, and loaded from at startup
+    pub noise_floor_file: PathBuf,
+}
+
+impl Default for NoiseGateConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.0,
+            noise_floor_file: PathBuf::from("noise_floor.json"),
+        }
+    }
+}
+
+// Energy of the ambient noise in every bin
+#[derive(Serialize, Deserialize)]
+struct NoiseFloor {
+    bins: Vec<f32>,
+}
+
+impl NoiseFloor {
+    fn load(path: &Path, bin_count: usize) -> Result<Self, NoiseFloorError> {
+        let noise_floor: Self = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if noise_floor.bins.len() != bin_count {
+            return Err(NoiseFloorError::BinCount(noise_floor.bins.len(), bin_count));
+        }
+        Ok(noise_floor)
+    }
+
+    fn save(&self, path: &Path) -> Result<(), NoiseFloorError> {
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
+
+// Sum of the bins of the frames measured so far
+struct Calibration {
+    sums: Vec<f64>,
+    frames: usize,
+    remaining_frames: usize,
+}
+
+/// Subtracts the noise floor from the bins of every frame and silences the frames left under the
+/// threshold
+#[derive(Default)]
+pub struct NoiseGate {
+    config: NoiseGateConfig,
+    noise_floor: Option<NoiseFloor>,
+    calibration: Option<Calibration>,
+}
+
+impl NoiseGate {
+    pub fn new(config: NoiseGateConfig, bin_count: usize) -> Self {
+        let noise_floor = match NoiseFloor::load(&config.noise_floor_file, bin_count) {
+            Ok(noise_floor) => {
+                tracing::info!(
+                    "Loaded the noise floor from {}",
+                    config.noise_floor_file.display()
+                );
+                Some(noise_floor)
+            }
+            Err(NoiseFloorError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                tracing::warn!(
+                    "Couldn't load the noise floor from {}: {e}",
+                    config.noise_floor_file.display()
+                );
+                None
+            }
+        };
+        Self {
+            config,
+            noise_floor,
+            calibration: None,
+        }
+    }
+
+    /// Measures the noise floor over the next `frames` frames. The frames are still gated with
+    /// the previous noise floor until it's replaced
+    pub fn calibrate(&mut self, frames: usize) {
+        tracing::info!("Calibrating the noise floor over {frames} frames, keep the room quiet");
+        self.calibration = Some(Calibration {
+            sums: Vec::new(),
+            frames: 0,
+            remaining_frames: frames,
+        });
+    }
+
+    pub fn apply(&mut self, bins: &mut [f32]) {
+        self.update_calibration(bins);
+
+        if let Some(noise_floor) = &self.noise_floor {
+            bins.iter_mut()
+                .zip(&noise_floor.bins)
+                .for_each(|(bin, noise)| *bin = (*bin - noise).max(0.0));
+        }
+        if self.config.threshold > 0.0 && bins_rms(bins) < self.config.threshold {
+            bins.fill(0.0);
+        }
+    }
+
+    fn update_calibration(&mut self, bins: &[f32]) {
+        let Some(calibration) = &mut self.calibration else {
+            return;
+        };
+        calibration.sums.resize(bins.len(), 0.0);
+        calibration
+            .sums
+            .iter_mut()
+            .zip(bins)
+            .for_each(|(sum, bin)| *sum += *bin as f64);
+        calibration.frames += 1;
+        calibration.remaining_frames = calibration.remaining_frames.saturating_sub(1);
+        if calibration.remaining_frames > 0 {
+            return;
+        }
+
+        let noise_floor = NoiseFloor {
+            bins: calibration
+                .sums
+                .iter()
+                .map(|sum| (sum / calibration.frames as f64) as f32)
+                .collect(),
+        };
+        self.calibration = None;
+        match noise_floor.save(&self.config.noise_floor_file) {
+            Ok(()) => tracing::info!(
+                "Saved the noise floor to {}",
+                self.config.noise_floor_file.display()
+            ),
+            Err(e) => tracing::error!(
+                "Couldn't save the noise floor to {}: {e}",
+                self.config.noise_floor_file.display()
+            ),
+        }
+        self.noise_floor = Some(noise_floor);
+    }
+}
@@ -0,0 +1,47 @@
+use super::audio_processing::FftResult;
+
+// How much louder than the recent average a frame has to be to count as an onset
+const ONSET_THRESHOLD: f32 = 2.5;
+// Onsets closer than this are considered to be the same onset
+const ONSET_COOLDOWN_FRAMES: u32 = 6;
+
+/// Detects sudden jumps of energy in a frequency band, like kicks or metronome clicks.
+pub struct OnsetDetector {
+    band: (f32, f32),
+    average_energy: f32,
+    frames_since_onset: u32,
+}
+
+impl OnsetDetector {
+    pub fn new(lower_frequency: f32, upper_frequency: f32) -> Self {
+        Self {
+            band: (lower_frequency, upper_frequency),
+            average_energy: 0.0,
+            frames_since_onset: ONSET_COOLDOWN_FRAMES,
+        }
+    }
+
+    /// Feeds the latest fft frame. Returns true if it starts an onset.
+    pub fn tick(&mut self, fft_result: &FftResult) -> bool {
+        let energy = fft_result
+            .get_average_amplitude(self.band.0, self.band.1)
+            .unwrap_or_default();
+
+        let is_onset = energy > self.average_energy * ONSET_THRESHOLD
+            && energy > f32::EPSILON
+            && self.frames_since_onset >= ONSET_COOLDOWN_FRAMES;
+        self.average_energy += 0.1 * (energy - self.average_energy);
+
+        if is_onset {
+            self.frames_since_onset = 0;
+        } else {
+            self.frames_since_onset = self.frames_since_onset.saturating_add(1);
+        }
+        is_onset
+    }
+
+    /// Number of frames since the last onset
+    pub fn frames_since_onset(&self) -> u32 {
+        self.frames_since_onset
+    }
+}
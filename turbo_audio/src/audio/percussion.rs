@@ -0,0 +1,231 @@
+use super::audio_processing::FftResult;
+use crate::sync::local_time;
+use std::sync::Mutex;
+
+// Bands the drums carry most of their energy in: the body of the kick, the body and snap of the
+// snare, and the hats and cymbals
+const KICK_BAND: (f32, f32) = (40.0, 150.0);
+const SNARE_BAND: (f32, f32) = (200.0, 3000.0);
+const HAT_BAND: (f32, f32) = (6000.0, 16000.0);
+// How much louder than the recent average of its band a frame has to be to count as a hit
+const HIT_THRESHOLD: f32 = 2.5;
+// Ticks during which a drum can't hit again. The hats play faster than the rest
+const KICK_COOLDOWN: u32 = 6;
+const SNARE_COOLDOWN: u32 = 6;
+const HAT_COOLDOWN: u32 = 3;
+// Ticks after a hit during which the bands next to it can't hit, the tail of a drum spreading
+// over them, like the noise of a snare reaching the band of the hats
+const HIT_SPREAD: u32 = 2;
+// How much more a band has to rise than the band below it to win, the attacks of the lower drums
+// reaching up into the next band
+const HIGHER_BAND_MARGIN: f32 = 1.5;
+// Rises are capped to this, past it a band is coming out of silence and how much louder it got
+// says nothing about the drum. A band ties with the band below it there, and loses
+const MAX_RISE: f32 = 50.0;
+// Seconds for the envelope of a hit to decay by a factor of e
+const HIT_DECAY: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Drum {
+    Kick,
+    Snare,
+    Hat,
+}
+
+impl Drum {
+    pub const ALL: [Drum; 3] = [Drum::Kick, Drum::Snare, Drum::Hat];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "kick" => Drum::Kick,
+            "snare" => Drum::Snare,
+            "hat" => Drum::Hat,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Drum::Kick => "kick",
+            Drum::Snare => "snare",
+            Drum::Hat => "hat",
+        }
+    }
+}
+
+/// Hits of a drum so far
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DrumHits {
+    /// Hits since the engine started. Effects that don't tick every frame compare it to the
+    /// previous one to not miss a hit
+    pub count: u64,
+    /// Local time of the last hit, in seconds
+    pub last: Option<f64>,
+}
+
+impl DrumHits {
+    /// 1 on a hit, decaying to 0 over a few tenths of a second
+    pub fn envelope(&self) -> f32 {
+        self.last.map_or(0.0, |last| {
+            (-(local_time() - last).max(0.0) / HIT_DECAY).exp() as f32
+        })
+    }
+}
+
+/// Hits of every drum, updated every tick
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Drums {
+    pub kick: DrumHits,
+    pub snare: DrumHits,
+    pub hat: DrumHits,
+}
+
+impl Drums {
+    pub fn get(&self, drum: Drum) -> DrumHits {
+        match drum {
+            Drum::Kick => self.kick,
+            Drum::Snare => self.snare,
+            Drum::Hat => self.hat,
+        }
+    }
+
+    fn get_mut(&mut self, drum: Drum) -> &mut DrumHits {
+        match drum {
+            Drum::Kick => &mut self.kick,
+            Drum::Snare => &mut self.snare,
+            Drum::Hat => &mut self.hat,
+        }
+    }
+}
+
+static DRUMS: Mutex<Drums> = Mutex::new(Drums {
+    kick: DrumHits {
+        count: 0,
+        last: None,
+    },
+    snare: DrumHits {
+        count: 0,
+        last: None,
+    },
+    hat: DrumHits {
+        count: 0,
+        last: None,
+    },
+});
+
+/// Drums heard in the main audio source
+pub fn drums() -> Drums {
+    *DRUMS.lock().unwrap()
+}
+
+// Energy of a band relative to its recent average
+struct BandRise {
+    band: (f32, f32),
+    average_energy: f32,
+    ticks_since_hit: u32,
+    cooldown: u32,
+}
+
+impl BandRise {
+    fn new(band: (f32, f32), cooldown: u32) -> Self {
+        Self {
+            band,
+            average_energy: 0.0,
+            ticks_since_hit: cooldown,
+            cooldown,
+        }
+    }
+
+    // Ratio of the energy of the frame to the average before it, up to MAX_RISE. 0 in silence
+    fn rise(&mut self, fft_result: &FftResult) -> f32 {
+        let max_frequency = fft_result.get_max_frequency();
+        let energy = fft_result
+            .get_average_amplitude(
+                self.band.0.min(max_frequency),
+                self.band.1.min(max_frequency),
+            )
+            .filter(|energy| energy.is_finite())
+            .unwrap_or_default();
+        let rise = if energy > f32::EPSILON {
+            (energy / self.average_energy.max(f32::EPSILON)).min(MAX_RISE)
+        } else {
+            0.0
+        };
+        self.average_energy += 0.1 * (energy - self.average_energy);
+        self.ticks_since_hit = self.ticks_since_hit.saturating_add(1);
+        rise
+    }
+
+    fn can_hit(&self, rise: f32) -> bool {
+        rise > HIT_THRESHOLD && self.ticks_since_hit > self.cooldown
+    }
+}
+
+/// Tells the kicks, snares and hats apart from how the energy of the onsets spreads over the
+/// spectrum, whatever the genre. A band only hits when it rises more than the bands next to it,
+/// so that the mids of a kick don't count as a snare nor the noise of a snare as a hat. A kick and
+/// a hat can hit together, a snare hitting with either of them only counts as the louder one.
+///
+/// The attack of a drum often spreads over two ticks, reaching some bands before the others, so an
+/// onset is classified on the tick after it starts, from the peak rise of each band over both
+pub struct DrumClassifier {
+    // In the order of Drum::ALL, from the lowest band
+    bands: [BandRise; 3],
+    // Rises of the tick an onset started on
+    pending: Option<[f32; 3]>,
+}
+
+impl Default for DrumClassifier {
+    fn default() -> Self {
+        Self {
+            bands: [
+                BandRise::new(KICK_BAND, KICK_COOLDOWN),
+                BandRise::new(SNARE_BAND, SNARE_COOLDOWN),
+                BandRise::new(HAT_BAND, HAT_COOLDOWN),
+            ],
+            pending: None,
+        }
+    }
+}
+
+impl DrumClassifier {
+    /// Feeds the latest fft frame and publishes the hits for the effects
+    pub fn tick(&mut self, fft_result: &FftResult) {
+        let rises = self.bands.each_mut().map(|band| band.rise(fft_result));
+        let Some(pending) = self.pending.take() else {
+            let onset = self
+                .bands
+                .iter()
+                .zip(rises)
+                .any(|(band, rise)| band.can_hit(rise));
+            self.pending = onset.then_some(rises);
+            return;
+        };
+        let peaks: [f32; 3] = std::array::from_fn(|index| pending[index].max(rises[index]));
+
+        let now = local_time();
+        let mut drums = DRUMS.lock().unwrap();
+        for (index, drum) in Drum::ALL.into_iter().enumerate() {
+            let neighbours = [
+                index.checked_sub(1),
+                Some(index + 1).filter(|next| *next < peaks.len()),
+            ];
+            let hit = self.bands[index].can_hit(peaks[index])
+                && neighbours.into_iter().flatten().all(|neighbour| {
+                    let louder = if neighbour < index {
+                        peaks[index] > peaks[neighbour] * HIGHER_BAND_MARGIN
+                    } else {
+                        peaks[index] >= peaks[neighbour]
+                    };
+                    louder && self.bands[neighbour].ticks_since_hit > HIT_SPREAD
+                });
+            if !hit {
+                continue;
+            }
+            self.bands[index].ticks_since_hit = 0;
+            let hits = drums.get_mut(drum);
+            hits.count += 1;
+            hits.last = Some(now);
+        }
+    }
+}
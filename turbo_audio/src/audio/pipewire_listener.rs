@@ -1,11 +1,16 @@
+use crate::{
+    config_parser::{PortConnections, StreamConnections},
+    info::{StreamConnectionInfo, StreamLinkInfo, StreamLinkState},
+};
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use pipewire::{
+    link::Link,
     prelude::ReadableDict,
+    proxy::ProxyT,
     registry::{GlobalObject, Registry},
     spa::ForeignDict,
     Core, MainLoop,
 };
-use serde::{Deserialize, Serialize};
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
@@ -52,18 +57,40 @@ enum PipewireObjectType {
     Link,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum PortConnections {
-    AllInOrder,
-    Only(Vec<(String, String)>),
+// A link of the stream connections, by the names of its streams and ports
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct LinkRequest {
+    output_stream: String,
+    input_stream: String,
+    output_port: String,
+    input_port: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct StreamConnections {
-    pub output_stream: String,
-    pub input_stream: String,
-    pub port_connections: PortConnections,
+impl LinkRequest {
+    fn new(
+        stream_connection: &StreamConnections,
+        (output_port, input_port): &(String, String),
+    ) -> Self {
+        Self {
+            output_stream: stream_connection.output_stream.clone(),
+            input_stream: stream_connection.input_stream.clone(),
+            output_port: output_port.clone(),
+            input_port: input_port.clone(),
+        }
+    }
+}
+
+// Links requested from pipewire that it didn't register yet, by the id of their proxy, which
+// pipewire reports the errors of the link on
+type PendingLinks = HashMap<u32, (LinkRequest, Link)>;
+
+// State of the stream connections last seen by the pipewire thread
+static STREAM_CONNECTIONS: Mutex<Vec<StreamConnectionInfo>> = Mutex::new(Vec::new());
+
+/// State of the links of the stream connections, updated whenever pipewire adds or removes a
+/// stream, a port or a link
+pub fn stream_connection_info() -> Vec<StreamConnectionInfo> {
+    STREAM_CONNECTIONS.lock().unwrap().clone()
 }
 
 #[derive(Debug)]
@@ -79,6 +106,8 @@ pub struct PipewireController {
 }
 
 impl PipewireController {
+    // Not a Default, since it starts the pipewire thread
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let (sender, receiver) = pipewire::channel::channel();
         let state = Arc::default();
@@ -143,6 +172,26 @@ fn pipewire_thread(
             .context("Couldn't create pipewire registry")?,
     );
 
+    let pending_links = Rc::new(RefCell::new(PendingLinks::new()));
+
+    let _core_listener = core
+        .add_listener_local()
+        .error({
+            let state = state.clone();
+            let stream_connections = stream_connections.clone();
+            let pending_links = pending_links.clone();
+            move |id, _seq, _res, message| {
+                let Some((request, _)) = pending_links.borrow_mut().remove(&id) else {
+                    tracing::error!("Pipewire error on object #{id}: {message}");
+                    return;
+                };
+                let mut state = state.lock().unwrap();
+                state.link_errors.insert(request, message.to_owned());
+                report_status(&state, &stream_connections.borrow());
+            }
+        })
+        .register();
+
     let _listener = registry
         .add_listener_local()
         .global({
@@ -150,59 +199,88 @@ fn pipewire_thread(
             let stream_connections = stream_connections.clone();
             let core = core.clone();
             let registry = registry.clone();
-            move |global| match global.type_ {
-                pipewire::types::ObjectType::Node => {
-                    let mut state = state.lock().unwrap();
-                    state
-                        .add_node(global)
-                        .unwrap_or_else(|err| log::error!("{}", err));
-                }
-                pipewire::types::ObjectType::Port => {
-                    let mut state = state.lock().unwrap();
-                    state
-                        .add_port(global)
-                        .unwrap_or_else(|err| log::error!("{}", err));
-                    add_missing_connections(&core, &state, &stream_connections.borrow_mut());
-                }
-                pipewire::types::ObjectType::Link => {
-                    let mut state = state.lock().unwrap();
-                    state
-                        .add_link(global)
-                        .unwrap_or_else(|err| log::error!("{}", err));
-                    if let Some(new_link) = state.links.get(&global.id) {
-                        check_remove_link(
-                            &state,
+            let pending_links = pending_links.clone();
+            move |global| {
+                let mut state = state.lock().unwrap();
+                match global.type_ {
+                    pipewire::types::ObjectType::Node => {
+                        state
+                            .add_node(global)
+                            .unwrap_or_else(|err| tracing::error!("{}", err));
+                    }
+                    pipewire::types::ObjectType::Port => {
+                        state
+                            .add_port(global)
+                            .unwrap_or_else(|err| tracing::error!("{}", err));
+                        // The ports of a restarted application come one by one, the ports
+                        // paired in order may change until they are all there
+                        sync_connections(
+                            &core,
                             &registry,
-                            new_link,
-                            &stream_connections.borrow_mut(),
-                        )
-                        .unwrap_or_else(|err| log::error!("{}", err));
+                            &mut state,
+                            &stream_connections.borrow(),
+                            &mut pending_links.borrow_mut(),
+                        );
                     }
+                    pipewire::types::ObjectType::Link => {
+                        state
+                            .add_link(global)
+                            .unwrap_or_else(|err| tracing::error!("{}", err));
+                        if let Some(new_link) = state.links.get(&global.id) {
+                            check_remove_link(
+                                &state,
+                                &registry,
+                                new_link,
+                                &stream_connections.borrow_mut(),
+                            )
+                            .unwrap_or_else(|err| tracing::error!("{}", err));
+                        }
+                        pending_links
+                            .borrow_mut()
+                            .retain(|_, (request, _)| !state.is_linked(request));
+                    }
+                    _ => return,
                 }
-                _ => {}
+                report_status(&state, &stream_connections.borrow());
             }
         })
         .global_remove({
             let stream_connections = stream_connections.clone();
             let state = state.clone();
             let core = core.clone();
+            let pending_links = pending_links.clone();
             move |id| {
                 let mut state = state.lock().unwrap();
                 let _ = state.remove_object(id);
-                add_missing_connections(&core, &state, &stream_connections.borrow_mut());
+                // The links of an application that quit are requested again when it restarts,
+                // with new ports
+                forget_missing_links(&mut state, &mut pending_links.borrow_mut());
+                add_missing_connections(
+                    &core,
+                    &mut state,
+                    &stream_connections.borrow_mut(),
+                    &mut pending_links.borrow_mut(),
+                );
+                report_status(&state, &stream_connections.borrow());
             }
         })
         .register();
 
     let _receiver = receiver.attach(&mainloop, {
         move |new_stream_connections| {
-            let state = state.lock().unwrap();
+            let mut state = state.lock().unwrap();
             *stream_connections.borrow_mut() = new_stream_connections;
-            for link in state.links.values() {
-                check_remove_link(&state, &registry, link, &stream_connections.borrow_mut())
-                    .unwrap_or_else(|err| log::error!("{}", err));
-            }
-            add_missing_connections(&core, &state, &stream_connections.borrow_mut());
+            // The links that failed are tried again
+            state.link_errors.clear();
+            pending_links.borrow_mut().clear();
+            sync_connections(
+                &core,
+                &registry,
+                &mut state,
+                &stream_connections.borrow(),
+                &mut pending_links.borrow_mut(),
+            );
+            report_status(&state, &stream_connections.borrow());
         }
     });
 
@@ -218,6 +296,8 @@ struct PipewireState {
     output_to_input_port_links: HashMap<u32, HashSet<u32>>,
     id_types: HashMap<u32, PipewireObjectType>,
     node_name_to_ids: HashMap<String, HashSet<u32>>,
+    // Why pipewire refused some links of the stream connections
+    link_errors: HashMap<LinkRequest, String>,
 }
 
 impl PipewireState {
@@ -298,6 +378,16 @@ impl PipewireState {
         Some(connected_port_names)
     }
 
+    fn is_linked(&self, request: &LinkRequest) -> bool {
+        self.get_connected_port_names_between_node_names(
+            &request.output_stream,
+            &request.input_stream,
+        )
+        .is_some_and(|connected| {
+            connected.contains_key(&(request.output_port.clone(), request.input_port.clone()))
+        })
+    }
+
     fn add_node(&mut self, node: &GlobalObject<ForeignDict>) -> Result<()> {
         let props = node
             .props
@@ -311,7 +401,7 @@ impl PipewireState {
             .unwrap_or_default()
             .to_string();
 
-        log::debug!("Pipewire node: {name}");
+        tracing::debug!("Pipewire node: {name}");
 
         self.nodes.insert(
             node.id,
@@ -469,8 +559,20 @@ impl PipewireState {
     fn remove_node(&mut self, id: u32) -> Result<()> {
         let node = self
             .nodes
-            .remove(&id)
+            .get(&id)
             .with_context(|| format!("Node with id {id} doesn't exist"))?;
+        // Pipewire may remove a node before its ports, which go away with it
+        let port_ids: Vec<u32> = node
+            .input_ports
+            .iter()
+            .chain(&node.output_ports)
+            .copied()
+            .collect();
+        for port_id in port_ids {
+            self.id_types.remove(&port_id);
+            self.remove_port(port_id)?;
+        }
+        let node = self.nodes.remove(&id).unwrap();
         let ids = self
             .node_name_to_ids
             .get_mut(&node.name)
@@ -489,8 +591,14 @@ impl PipewireState {
     fn remove_port(&mut self, id: u32) -> Result<()> {
         let port = self
             .ports
-            .remove(&id)
+            .get(&id)
             .with_context(|| format!("Error removing port #{id}, port doesn't exist"))?;
+        // Same for the links of the port
+        for link_id in port.links.clone() {
+            self.id_types.remove(&link_id);
+            self.remove_link(link_id)?;
+        }
+        let port = self.ports.remove(&id).unwrap();
 
         let parent_node = self.nodes.get_mut(&port.node_id).with_context(|| {
             format!(
@@ -661,8 +769,9 @@ fn get_connection_details_from_port_names(
 
 fn add_missing_connections(
     core: &Core,
-    state: &PipewireState,
+    state: &mut PipewireState,
     stream_connections: &[StreamConnections],
+    pending_links: &mut PendingLinks,
 ) {
     for stream_connection in stream_connections {
         let desired_port_connections = get_port_connections(state, stream_connection);
@@ -680,6 +789,15 @@ fn add_missing_connections(
                 .collect();
 
             for connection_to_add in connections_to_add {
+                let request = LinkRequest::new(stream_connection, connection_to_add);
+                // Requested already, or refused until the stream connections change
+                if state.link_errors.contains_key(&request)
+                    || pending_links
+                        .values()
+                        .any(|(pending, _)| *pending == request)
+                {
+                    continue;
+                }
                 if let Some((output_port, input_port, output_node, input_node)) =
                     get_connection_details_from_port_names(
                         state,
@@ -688,13 +806,57 @@ fn add_missing_connections(
                         connection_to_add,
                     )
                 {
-                    add_link(core, output_port, input_port, output_node, input_node);
+                    match add_link(core, output_port, input_port, output_node, input_node) {
+                        Ok(link) => {
+                            pending_links.insert(link.upcast_ref().id(), (request, link));
+                        }
+                        Err(e) => {
+                            state.link_errors.insert(request, e.to_string());
+                        }
+                    }
                 }
             }
         }
     }
 }
 
+// Removes the links into the input streams that the stream connections don't want, then requests
+// the wanted ones that are missing
+fn sync_connections(
+    core: &Core,
+    registry: &Registry,
+    state: &mut PipewireState,
+    stream_connections: &[StreamConnections],
+    pending_links: &mut PendingLinks,
+) {
+    for link in state.links.values() {
+        check_remove_link(state, registry, link, stream_connections)
+            .unwrap_or_else(|err| tracing::error!("{}", err));
+    }
+    add_missing_connections(core, state, stream_connections, pending_links);
+}
+
+// Forgets the requested and refused links whose ports are gone, like the ones of an application
+// that quit, so that they are requested again once the ports are back
+fn forget_missing_links(state: &mut PipewireState, pending_links: &mut PendingLinks) {
+    let ports_exist = |state: &PipewireState, request: &LinkRequest| {
+        get_connection_details_from_port_names(
+            state,
+            &request.output_stream,
+            &request.input_stream,
+            &(request.output_port.clone(), request.input_port.clone()),
+        )
+        .is_some()
+    };
+    pending_links.retain(|_, (request, _)| ports_exist(state, request));
+    let refused: Vec<LinkRequest> = state.link_errors.keys().cloned().collect();
+    for request in refused {
+        if !ports_exist(state, &request) {
+            state.link_errors.remove(&request);
+        }
+    }
+}
+
 fn check_remove_link(
     state: &PipewireState,
     registry: &Registry,
@@ -744,8 +906,14 @@ fn check_remove_link(
     Ok(())
 }
 
-fn add_link(core: &Core, output_port: u32, input_port: u32, output_node: u32, input_node: u32) {
-    core.create_object::<pipewire::link::Link, _>(
+fn add_link(
+    core: &Core,
+    output_port: u32,
+    input_port: u32,
+    output_node: u32,
+    input_node: u32,
+) -> Result<Link> {
+    let link = core.create_object::<Link, _>(
         "link-factory",
         &pipewire::properties! {
             "link.input.port" => input_port.to_string(),
@@ -754,8 +922,124 @@ fn add_link(core: &Core, output_port: u32, input_port: u32, output_node: u32, in
             "link.output.node"=> output_node.to_string(),
             "object.linger" => "1"
         },
-    )
-    .expect("Failed to add new link");
+    )?;
+    Ok(link)
+}
+
+// State of the links of the stream connections
+fn connection_status(
+    state: &PipewireState,
+    stream_connections: &[StreamConnections],
+) -> Vec<StreamConnectionInfo> {
+    stream_connections
+        .iter()
+        .map(|stream_connection| {
+            let missing_streams = [
+                &stream_connection.output_stream,
+                &stream_connection.input_stream,
+            ]
+            .into_iter()
+            .filter(|&name| !state.node_name_to_ids.contains_key(name))
+            .cloned()
+            .collect();
+            let connected = state
+                .get_connected_port_names_between_node_names(
+                    &stream_connection.output_stream,
+                    &stream_connection.input_stream,
+                )
+                .unwrap_or_default();
+            let links = get_port_connections(state, stream_connection)
+                .into_iter()
+                .map(|ports| {
+                    let request = LinkRequest::new(stream_connection, &ports);
+                    let link_state = if connected.contains_key(&ports) {
+                        StreamLinkState::Linked
+                    } else if let Some(error) = state.link_errors.get(&request) {
+                        StreamLinkState::Failed(error.clone())
+                    } else if get_connection_details_from_port_names(
+                        state,
+                        &stream_connection.output_stream,
+                        &stream_connection.input_stream,
+                        &ports,
+                    )
+                    .is_some()
+                    {
+                        StreamLinkState::Pending
+                    } else {
+                        StreamLinkState::MissingPort
+                    };
+                    StreamLinkInfo {
+                        output_port: request.output_port,
+                        input_port: request.input_port,
+                        state: link_state,
+                    }
+                })
+                .collect();
+            StreamConnectionInfo {
+                output_stream: stream_connection.output_stream.clone(),
+                input_stream: stream_connection.input_stream.clone(),
+                missing_streams,
+                links,
+            }
+        })
+        .collect()
+}
+
+// Publishes the state of the stream connections and logs what changed since it was last
+// published, so that broken routing shows in the logs
+fn report_status(state: &PipewireState, stream_connections: &[StreamConnections]) {
+    let status = connection_status(state, stream_connections);
+    let mut published = STREAM_CONNECTIONS.lock().unwrap();
+    for connection in &status {
+        let previous = published.iter().find(|previous| {
+            previous.output_stream == connection.output_stream
+                && previous.input_stream == connection.input_stream
+        });
+        let was_missing = |stream: &String| {
+            previous.is_some_and(|previous| previous.missing_streams.contains(stream))
+        };
+        for stream in &connection.missing_streams {
+            if !was_missing(stream) {
+                tracing::warn!("Pipewire stream {stream} of the stream connections isn't there");
+            }
+        }
+        for stream in [&connection.output_stream, &connection.input_stream] {
+            if was_missing(stream) && !connection.missing_streams.contains(stream) {
+                tracing::info!("Found pipewire stream {stream}");
+            }
+        }
+        for link in &connection.links {
+            let previous_state = previous
+                .and_then(|previous| {
+                    previous.links.iter().find(|previous| {
+                        previous.output_port == link.output_port
+                            && previous.input_port == link.input_port
+                    })
+                })
+                .map(|previous| &previous.state);
+            if previous_state == Some(&link.state) {
+                continue;
+            }
+            let name = format!(
+                "{}:{} to {}:{}",
+                connection.output_stream,
+                link.output_port,
+                connection.input_stream,
+                link.input_port
+            );
+            match &link.state {
+                StreamLinkState::Linked => tracing::info!("Linked {name}"),
+                StreamLinkState::Pending => tracing::debug!("Linking {name}"),
+                StreamLinkState::MissingPort => {
+                    if previous_state == Some(&StreamLinkState::Linked) {
+                        tracing::warn!("Lost the link from {name}, one of its ports is gone");
+                    }
+                }
+                StreamLinkState::Failed(error) => tracing::error!("Couldn't link {name}: {error}"),
+            }
+        }
+    }
+    *published = status;
 }
 
 fn remove_link(link_id: u32, registry: &Registry) -> Result<()> {
@@ -0,0 +1,121 @@
+use super::audio_stream::{AudioCapture, AudioStream, StreamHandle, StreamHealth};
+use anyhow::anyhow;
+use libpulse_binding::{
+    error::PAErr,
+    sample::{Format, Spec},
+    stream::Direction,
+};
+use libpulse_simple_binding::Simple;
+use ringbuf::HeapConsumer;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+// Samples read at once, about 5ms at 48kHz
+const READ_SAMPLES: usize = 256;
+// Source the server resolves to the monitor of the default output
+const DEFAULT_MONITOR: &str = "@DEFAULT_MONITOR@";
+// The server converts to any rate, so checking whether a source exists doesn't need the real one
+const PROBE_SAMPLE_RATE: u32 = 48000;
+
+/// Records a pulseaudio source on its own thread, since the reads block
+pub struct PulseStream {
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+}
+
+impl Drop for PulseStream {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn connect(
+    device_name: Option<&str>,
+    sample_rate: u32,
+    capture: AudioCapture,
+) -> Result<Simple, PAErr> {
+    let spec = Spec {
+        format: Format::FLOAT32NE,
+        channels: 1,
+        rate: sample_rate,
+    };
+    let device = device_name.or((capture == AudioCapture::Loopback).then_some(DEFAULT_MONITOR));
+    Simple::new(
+        None,
+        "turbo_audio",
+        Direction::Record,
+        device,
+        "Audio capture",
+        &spec,
+        None,
+        None,
+    )
+}
+
+/// Whether the source can be recorded, the default one if no device is named
+pub fn is_available(device_name: Option<&str>, capture: AudioCapture) -> bool {
+    connect(device_name, PROBE_SAMPLE_RATE, capture).is_ok()
+}
+
+pub fn start_pulse_stream(
+    device_name: Option<String>,
+    sample_rate: u32,
+    capture: AudioCapture,
+) -> anyhow::Result<(AudioStream, HeapConsumer<f32>)> {
+    let (mut tx, rx) = ringbuf::HeapRb::<f32>::new(1024).split();
+    let health = Arc::new(StreamHealth::new());
+    let should_quit = Arc::new(AtomicBool::new(false));
+    let (connected_tx, connected_rx) = mpsc::channel();
+
+    let thread = thread::spawn({
+        let health = health.clone();
+        let should_quit = should_quit.clone();
+        move || {
+            let simple = match connect(device_name.as_deref(), sample_rate, capture) {
+                Ok(simple) => simple,
+                Err(e) => {
+                    let _ = connected_tx.send(Err(e));
+                    return;
+                }
+            };
+            let _ = connected_tx.send(Ok(()));
+
+            let mut buffer = [0u8; READ_SAMPLES * std::mem::size_of::<f32>()];
+            while !should_quit.load(Ordering::Relaxed) {
+                if let Err(e) = simple.read(&mut buffer) {
+                    tracing::error!("Pulseaudio stream error: {e}");
+                    health.record_error();
+                    return;
+                }
+                health.record_samples();
+                for sample in buffer.chunks_exact(std::mem::size_of::<f32>()) {
+                    let _ = tx.push(f32::from_ne_bytes(sample.try_into().unwrap()));
+                }
+            }
+        }
+    });
+
+    match connected_rx.recv() {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            let _ = thread.join();
+            return Err(anyhow!("Couldn't record from pulseaudio: {e}"));
+        }
+        Err(_) => return Err(anyhow!("The pulseaudio thread stopped")),
+    }
+    tracing::info!("Recording from pulseaudio");
+
+    let stream = PulseStream {
+        thread: Some(thread),
+        should_quit,
+    };
+    Ok((AudioStream::new(StreamHandle::Pulse(stream), health), rx))
+}
@@ -0,0 +1,132 @@
+use super::audio_processing::{AudioSignalProcessor, FftResult};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+use thiserror::Error;
+
+// Bumped whenever the layout of a recording changes
+const RECORDING_VERSION: u32 = 2;
+
+#[derive(Error, Debug)]
+pub enum RecordingError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Couldn't write the recording: {0}")]
+    Encode(#[from] rmp_serde::encode::Error),
+
+    #[error("Couldn't read the recording: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+
+    #[error("Recording version {0} isn't supported, expected {RECORDING_VERSION}")]
+    UnsupportedVersion(u32),
+
+    #[error("The recording doesn't contain any frame")]
+    Empty,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordingHeader {
+    version: u32,
+    ticks_per_second: u32,
+}
+
+// Fft computed during a single tick
+#[derive(Serialize, Deserialize)]
+struct RecordedFrame {
+    fft_resolution: f32,
+    raw_bins: Vec<f32>,
+}
+
+/// Writes the fft of every tick to a file so it can be replayed in place of live audio. Smoothed
+/// features and beats are derived from the fft so they replay identically.
+pub struct FeatureRecorder {
+    writer: BufWriter<File>,
+}
+
+impl FeatureRecorder {
+    pub fn new(path: impl AsRef<Path>, ticks_per_second: u32) -> Result<Self, RecordingError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        rmp_serde::encode::write_named(
+            &mut writer,
+            &RecordingHeader {
+                version: RECORDING_VERSION,
+                ticks_per_second,
+            },
+        )?;
+        Ok(Self { writer })
+    }
+
+    pub fn record(&mut self, fft_result: &FftResult) -> Result<(), RecordingError> {
+        rmp_serde::encode::write_named(
+            &mut self.writer,
+            &RecordedFrame {
+                fft_resolution: fft_result.fft_resolution(),
+                raw_bins: fft_result.raw_bins().to_vec(),
+            },
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for FeatureRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            tracing::error!("Couldn't flush the feature recording: {e}");
+        }
+    }
+}
+
+/// Plays a recording made by [`FeatureRecorder`] in a loop
+pub struct FeatureReplay {
+    frames: Vec<RecordedFrame>,
+    position: usize,
+}
+
+impl FeatureReplay {
+    pub fn open(path: impl AsRef<Path>, ticks_per_second: u32) -> Result<Self, RecordingError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header: RecordingHeader = rmp_serde::from_read(&mut reader)?;
+        if header.version != RECORDING_VERSION {
+            return Err(RecordingError::UnsupportedVersion(header.version));
+        }
+        if header.ticks_per_second != ticks_per_second {
+            tracing::warn!(
+                "The recording was made at {} ticks per second but is replayed at {ticks_per_second}. It will play at a different speed",
+                header.ticks_per_second
+            );
+        }
+
+        let mut frames = vec![];
+        loop {
+            match rmp_serde::from_read::<_, RecordedFrame>(&mut reader) {
+                Ok(frame) => frames.push(frame),
+                Err(rmp_serde::decode::Error::InvalidMarkerRead(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if frames.is_empty() {
+            return Err(RecordingError::Empty);
+        }
+        tracing::info!("Replaying {} recorded frames", frames.len());
+        Ok(Self {
+            frames,
+            position: 0,
+        })
+    }
+
+    /// Publishes the next recorded frame to the audio processor, starting over at the end
+    pub fn tick(&mut self, audio_processor: &mut AudioSignalProcessor) {
+        let frame = &self.frames[self.position];
+        audio_processor.set_fft(&frame.raw_bins, frame.fft_resolution);
+        self.position = (self.position + 1) % self.frames.len();
+    }
+}
@@ -0,0 +1,209 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{f64::consts::TAU, str::FromStr};
+
+// Range of the sweeps, in Hz
+const SWEEP_FROM: f64 = 20.0;
+const SWEEP_TO: f64 = 20000.0;
+// Pitch of the metronome clicks, higher on the first beat of a bar
+const CLICK_FREQUENCY: f64 = 1000.0;
+const ACCENT_FREQUENCY: f64 = 1500.0;
+const BEATS_PER_BAR: u64 = 4;
+// Time it takes a click to fade to a third, in seconds
+const CLICK_DECAY: f64 = 0.01;
+// A burst starts every BURST_INTERVAL seconds and lasts BURST_LENGTH seconds
+const BURST_INTERVAL: f64 = 0.5;
+const BURST_LENGTH: f64 = 0.1;
+
+/// Audio fed to the fft instead of a live device, to render effects reproducibly and to develop
+/// the fft and the effects without playing music
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntheticAudio {
+    Silence,
+    /// Pure tone at the given frequency
+    Sine(f32),
+    /// Kick drums at the given bpm
+    Kicks(f32),
+    /// White noise from a fixed seed
+    Noise,
+    /// Tone rising from 20Hz to 20kHz over the given seconds, then starting over. The pitch rises
+    /// exponentially, by the same number of octaves every second
+    Sweep(f32),
+    /// Noise with the same energy in every octave, like most music, from a fixed seed
+    PinkNoise,
+    /// Clicks at the given bpm, higher on the first beat of every bar of 4
+    Metronome(f32),
+    /// Bursts of noise between two frequencies in Hz, twice per second
+    Bursts(f32, f32),
+}
+
+impl FromStr for SyntheticAudio {
+    type Err = String;
+
+    /// Parses `silence`, `sine:<frequency>`, `kicks:<bpm>`, `noise`, `sweep:<seconds>`, `pink`,
+    /// `metronome:<bpm>` or `bursts:<low>-<high>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once(':').unwrap_or((s, ""));
+        let number = |value: &str| {
+            value
+                .parse::<f32>()
+                .ok()
+                .filter(|value| value.is_finite() && *value > 0.0)
+                .ok_or_else(|| format!("Expected a number above 0 after `{kind}:`, got `{value}`"))
+        };
+        match kind {
+            "silence" => Ok(SyntheticAudio::Silence),
+            "sine" => Ok(SyntheticAudio::Sine(number(value)?)),
+            "kicks" => Ok(SyntheticAudio::Kicks(number(value)?)),
+            "noise" => Ok(SyntheticAudio::Noise),
+            "sweep" => Ok(SyntheticAudio::Sweep(number(value)?)),
+            "pink" => Ok(SyntheticAudio::PinkNoise),
+            "metronome" => Ok(SyntheticAudio::Metronome(number(value)?)),
+            "bursts" => {
+                let (low, high) = value
+                    .split_once('-')
+                    .ok_or_else(|| format!("Expected `bursts:<low>-<high>`, got `{s}`"))?;
+                let (low, high) = (number(low)?, number(high)?);
+                if low >= high {
+                    return Err(format!("The bursts go from {low}Hz up to {high}Hz"));
+                }
+                Ok(SyntheticAudio::Bursts(low, high))
+            }
+            _ => Err(format!(
+                "Unknown audio `{s}`. Expected silence, sine:<frequency>, kicks:<bpm>, noise, \
+                 sweep:<seconds>, pink, metronome:<bpm> or bursts:<low>-<high>"
+            )),
+        }
+    }
+}
+
+// Band-pass filter of the bursts, from the audio eq cookbook
+#[derive(Debug, Default)]
+struct BandPass {
+    // b0, b2 (b1 is 0), a1 and a2, divided by a0
+    coefficients: [f64; 4],
+    inputs: [f64; 2],
+    outputs: [f64; 2],
+}
+
+impl BandPass {
+    fn new(low: f64, high: f64, sample_rate: f64) -> Self {
+        let high = high.min(sample_rate * 0.45);
+        let low = low.min(high * 0.5);
+        let center = (low * high).sqrt();
+        let q = center / (high - low);
+        let w0 = TAU * center / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        Self {
+            coefficients: [
+                alpha / a0,
+                -alpha / a0,
+                -2.0 * w0.cos() / a0,
+                (1.0 - alpha) / a0,
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn filter(&mut self, input: f64) -> f64 {
+        let [b0, b2, a1, a2] = self.coefficients;
+        let output = b0 * input + b2 * self.inputs[1] - a1 * self.outputs[0] - a2 * self.outputs[1];
+        self.inputs = [input, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+        output
+    }
+}
+
+/// Generates the samples of a [`SyntheticAudio`], between -1 and 1
+pub struct SignalGenerator {
+    audio: SyntheticAudio,
+    sample_rate: u32,
+    sample_index: u64,
+    rng: StdRng,
+    // Phase of the sweep, in turns
+    phase: f64,
+    // State of the filters turning white noise into pink noise
+    pink: [f64; 7],
+    band_pass: BandPass,
+}
+
+impl SignalGenerator {
+    pub fn new(audio: SyntheticAudio, sample_rate: u32) -> Self {
+        let band_pass = match audio {
+            SyntheticAudio::Bursts(low, high) => {
+                BandPass::new(low as f64, high as f64, sample_rate as f64)
+            }
+            _ => BandPass::default(),
+        };
+        Self {
+            audio,
+            sample_rate,
+            sample_index: 0,
+            rng: StdRng::seed_from_u64(0),
+            phase: 0.0,
+            pink: [0.0; 7],
+            band_pass,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        // In f64, so that the tones stay clean after hours of --signal-gen
+        let time = self.sample_index as f64 / self.sample_rate as f64;
+        self.sample_index += 1;
+        let sample = match self.audio {
+            SyntheticAudio::Silence => 0.0,
+            SyntheticAudio::Sine(frequency) => {
+                0.5 * (TAU * (frequency as f64 * time).fract()).sin()
+            }
+            SyntheticAudio::Kicks(bpm) => {
+                let since_beat = time % (60.0 / bpm as f64);
+                (-since_beat * 20.0).exp() * (TAU * 60.0 * since_beat).sin()
+            }
+            SyntheticAudio::Noise => self.rng.gen_range(-0.5f32..0.5) as f64,
+            SyntheticAudio::Sweep(seconds) => {
+                let progress = (time / seconds as f64).fract();
+                let frequency = SWEEP_FROM * (SWEEP_TO / SWEEP_FROM).powf(progress);
+                self.phase = (self.phase + frequency / self.sample_rate as f64).fract();
+                0.5 * (TAU * self.phase).sin()
+            }
+            SyntheticAudio::PinkNoise => self.pink_noise(),
+            SyntheticAudio::Metronome(bpm) => {
+                let period = 60.0 / bpm as f64;
+                let beat = (time / period) as u64;
+                let since_beat = time % period;
+                let frequency = match beat % BEATS_PER_BAR {
+                    0 => ACCENT_FREQUENCY,
+                    _ => CLICK_FREQUENCY,
+                };
+                0.8 * (TAU * frequency * since_beat).sin() * (-since_beat / CLICK_DECAY).exp()
+            }
+            SyntheticAudio::Bursts(..) => {
+                let white = self.rng.gen_range(-1.0..1.0);
+                // Filtered all along so that a burst starts from the state of the filter
+                let filtered = self.band_pass.filter(white);
+                let since_burst = time % BURST_INTERVAL;
+                match since_burst < BURST_LENGTH {
+                    // Faded in and out over the burst, so that its ends don't click
+                    true => filtered * (std::f64::consts::PI * since_burst / BURST_LENGTH).sin(),
+                    false => 0.0,
+                }
+            }
+        };
+        sample as f32
+    }
+
+    // Paul Kellet's filter of white noise, within 0.05dB of pink above 9Hz
+    fn pink_noise(&mut self) -> f64 {
+        let white = self.rng.gen_range(-1.0..1.0);
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.1538520;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b.iter().sum::<f64>() + white * 0.5362;
+        b[6] = white * 0.115926;
+        pink * 0.11
+    }
+}
@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+/// How much the audio features read by an effect are smoothed over time. Strobes want the raw
+/// transients while ambient effects look better with heavily smoothed energy.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SmoothingProfile {
+    #[default]
+    Raw,
+    Fast,
+    Smooth,
+    VerySmooth,
+}
+
+impl SmoothingProfile {
+    pub const ALL: [SmoothingProfile; 4] = [
+        SmoothingProfile::Raw,
+        SmoothingProfile::Fast,
+        SmoothingProfile::Smooth,
+        SmoothingProfile::VerySmooth,
+    ];
+
+    /// Weight of the newest frame in the exponential moving average of the features, with a frame
+    /// per tick
+    pub fn alpha(self) -> f32 {
+        match self {
+            SmoothingProfile::Raw => 1.0,
+            SmoothingProfile::Fast => 0.6,
+            SmoothingProfile::Smooth => 0.3,
+            SmoothingProfile::VerySmooth => 0.1,
+        }
+    }
+
+    /// Weight of the newest frame with frames `frame_duration` seconds apart, for the features to
+    /// take as long to settle as with a frame per tick
+    pub fn alpha_over(self, frame_duration: f32) -> f32 {
+        1.0 - (1.0 - self.alpha()).powf(frame_duration * crate::ticks_per_second() as f32)
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+// Format tag of 32 bit float samples
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const BYTES_PER_SAMPLE: u32 = 4;
+
+/// Writes the samples the fft reads to a mono wav file of 32 bit float samples, as they come out
+/// of the audio input, so that one can listen to what the engine hears
+pub struct WavCapture {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    sample_rate: u32,
+    // Samples left to write before the capture is complete
+    remaining: usize,
+    sample_count: u32,
+    finished: bool,
+}
+
+impl WavCapture {
+    /// Creates the file, to which the next `seconds` of samples are written. Fails if it exists
+    pub fn create(path: &Path, sample_rate: u32, seconds: f32) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create_new(path)?);
+        writer.write_all(&header(sample_rate, 0))?;
+        Ok(Self {
+            writer,
+            path: path.to_owned(),
+            sample_rate,
+            remaining: (seconds * sample_rate as f32).ceil() as usize,
+            sample_count: 0,
+            finished: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Seconds of audio written so far
+    pub fn seconds(&self) -> f32 {
+        self.sample_count as f32 / self.sample_rate as f32
+    }
+
+    /// Writes the samples that still fit in the capture and returns whether it is complete
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<bool> {
+        let samples = &samples[..samples.len().min(self.remaining)];
+        for sample in samples {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.remaining -= samples.len();
+        self.sample_count += samples.len() as u32;
+        Ok(self.remaining == 0)
+    }
+
+    /// Writes the length of the audio into the header. The capture is also finished when dropped,
+    /// like when the engine stops in the middle of it, but the errors are then ignored
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_lengths()
+    }
+
+    fn write_lengths(&mut self) -> io::Result<()> {
+        self.finished = true;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer
+            .write_all(&header(self.sample_rate, self.sample_count))?;
+        self.writer.flush()
+    }
+}
+
+impl Drop for WavCapture {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.write_lengths();
+        }
+    }
+}
+
+// Riff header of a wav file of `sample_count` samples. Files of float samples have a fact chunk
+// holding the number of samples
+fn header(sample_rate: u32, sample_count: u32) -> Vec<u8> {
+    let data_len = sample_count * BYTES_PER_SAMPLE;
+    let mut header = Vec::with_capacity(58);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(50 + data_len).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&18u32.to_le_bytes());
+    header.extend_from_slice(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+    // Mono
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * BYTES_PER_SAMPLE).to_le_bytes());
+    header.extend_from_slice(&(BYTES_PER_SAMPLE as u16).to_le_bytes());
+    header.extend_from_slice(&(BYTES_PER_SAMPLE as u16 * 8).to_le_bytes());
+    // No extension to the format
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(b"fact");
+    header.extend_from_slice(&4u32.to_le_bytes());
+    header.extend_from_slice(&sample_count.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    header
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+// Poles of the A-weighting curve of IEC 61672, in Hz
+const A_POLES: [f32; 4] = [20.6, 107.7, 737.9, 12194.0];
+// Gain of the A-weighting curve at 1kHz, to bring it back to 0dB there
+const A_OFFSET_DB: f32 = 2.0;
+
+/// Curve the bins are weighted by before the bands and features are computed from them, so that
+/// the lights follow the loudness as heard rather than the energy, most of which is in the
+/// sub-bass
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum Weighting {
+    #[default]
+    None,
+    /// The A-weighting of sound level meters, cutting the lows and the highs that the ear barely
+    /// hears
+    A,
+    /// Gains in dB at some frequencies, interpolated in between on a log frequency scale and held
+    /// past the first and last ones
+    Custom(Vec<WeightingPoint>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WeightingPoint {
+    pub frequency: f32,
+    pub gain_db: f32,
+}
+
+impl Weighting {
+    /// Factor of every bin, from 0Hz by steps of `fft_resolution`. None when nothing is weighted
+    pub fn bin_gains(&self, bin_count: usize, fft_resolution: f32) -> Option<Vec<f32>> {
+        let gain_db: Box<dyn Fn(f32) -> f32> = match self {
+            Self::None => return None,
+            Self::A => Box::new(a_weighting_db),
+            Self::Custom(points) => {
+                let mut points: Vec<_> = points
+                    .iter()
+                    .filter(|point| point.frequency > 0.0 && point.gain_db.is_finite())
+                    .copied()
+                    .collect();
+                if points.is_empty() {
+                    tracing::warn!("The custom weighting has no valid point, it's ignored");
+                    return None;
+                }
+                points.sort_by(|a, b| a.frequency.total_cmp(&b.frequency));
+                Box::new(move |frequency| interpolate_db(&points, frequency))
+            }
+        };
+        // The bins are powers, a gain of 10dB multiplies them by 10
+        Some(
+            (0..bin_count)
+                .map(|index| 10f32.powf(gain_db(index as f32 * fft_resolution) / 10.0))
+                .collect(),
+        )
+    }
+}
+
+fn a_weighting_db(frequency: f32) -> f32 {
+    if frequency <= 0.0 {
+        return f32::NEG_INFINITY;
+    }
+    let [p1, p2, p3, p4] = A_POLES.map(|pole| pole * pole);
+    let f2 = frequency * frequency;
+    let response = p4 * f2 * f2 / ((f2 + p1) * ((f2 + p2) * (f2 + p3)).sqrt() * (f2 + p4));
+    20.0 * response.log10() + A_OFFSET_DB
+}
+
+// Gain at `frequency` of the points sorted by frequency, linear in log frequency between them
+fn interpolate_db(points: &[WeightingPoint], frequency: f32) -> f32 {
+    let upper = points.partition_point(|point| point.frequency < frequency);
+    if upper == 0 {
+        return points[0].gain_db;
+    }
+    if upper == points.len() {
+        return points[points.len() - 1].gain_db;
+    }
+    let (low, high) = (points[upper - 1], points[upper]);
+    let t = (frequency / low.frequency).ln() / (high.frequency / low.frequency).ln();
+    low.gain_db + t * (high.gain_db - low.gain_db)
+}
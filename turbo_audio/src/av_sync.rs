@@ -0,0 +1,160 @@
+use crate::{
+    audio::{audio_processing::SharedFftResult, onset::OnsetDetector},
+    resources::ledstrip::Pixel,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AvSyncConfig {
+    /// Delay always applied to the lights. Negative offsets eat into it, so it is the earliest the
+    /// lights can be compared to the audio
+    #[serde(default)]
+    pub base_delay_ms: u32,
+    /// Longest the lights can be held back, which bounds the size of the frame buffer
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u32,
+    /// Offset applied on startup. Positive values delay the lights, negative values advance them
+    #[serde(default)]
+    pub offset_ms: i32,
+    /// Where the calibrated audio to light latency is saved, and loaded from at startup. The
+    /// beat is predicted that far ahead
+    #[serde(default = "default_latency_file")]
+    pub latency_file: PathBuf,
+}
+
+fn default_max_delay_ms() -> u32 {
+    500
+}
+
+fn default_latency_file() -> PathBuf {
+    PathBuf::from("latency.json")
+}
+
+impl Default for AvSyncConfig {
+    fn default() -> Self {
+        Self {
+            base_delay_ms: 0,
+            max_delay_ms: default_max_delay_ms(),
+            offset_ms: 0,
+            latency_file: default_latency_file(),
+        }
+    }
+}
+
+// Frequency band in which metronome clicks carry most of their energy
+const CLICK_BAND: (f32, f32) = (1000.0, 8000.0);
+const FLASH_FRAMES: u32 = 3;
+
+/// Delays the frames sent to the ledstrips so that the lights line up with what the listener
+/// hears, which depends on their position and on the latency of their hardware.
+pub struct AvSync {
+    config: AvSyncConfig,
+    frame_duration_ms: f32,
+    offset_ms: i32,
+    // led strip id to the frames waiting to be sent, oldest first
+    frames: HashMap<usize, VecDeque<Vec<Pixel>>>,
+    fft_result: SharedFftResult,
+    // Flashes the ledstrips on every metronome click heard, so the offset can be tuned by ear
+    click_detector: Option<OnsetDetector>,
+}
+
+impl AvSync {
+    pub fn new(config: AvSyncConfig, ticks_per_second: u32, fft_result: SharedFftResult) -> Self {
+        let offset_ms = config.offset_ms;
+        let mut av_sync = Self {
+            config,
+            frame_duration_ms: 1000.0 / ticks_per_second as f32,
+            offset_ms: 0,
+            frames: Default::default(),
+            fft_result,
+            click_detector: None,
+        };
+        av_sync.set_offset(offset_ms);
+        av_sync
+    }
+
+    pub fn offset_ms(&self) -> i32 {
+        self.offset_ms
+    }
+
+    /// Sets the offset, clamped to what the frame buffer allows. Returns the offset that was
+    /// applied.
+    pub fn set_offset(&mut self, offset_ms: i32) -> i32 {
+        let min = -(self.config.base_delay_ms as i32);
+        let max = self.config.max_delay_ms as i32 - self.config.base_delay_ms as i32;
+        self.offset_ms = offset_ms.clamp(min, max.max(min));
+        if self.offset_ms != offset_ms {
+            tracing::warn!(
+                "Sync offset {offset_ms}ms is out of the buffered range [{min}ms, {max}ms]. Using {}ms",
+                self.offset_ms
+            );
+        }
+        self.offset_ms
+    }
+
+    pub fn test_mode(&self) -> bool {
+        self.click_detector.is_some()
+    }
+
+    pub fn set_test_mode(&mut self, enabled: bool) {
+        if enabled == self.test_mode() {
+            return;
+        }
+        self.click_detector = enabled.then(|| OnsetDetector::new(CLICK_BAND.0, CLICK_BAND.1));
+    }
+
+    /// Renders the test mode flashes on the ledstrips. Returns false when the test mode is off
+    /// and the effects should be rendered instead.
+    pub fn render_test_mode<'a>(
+        &mut self,
+        ledstrips: impl Iterator<Item = &'a mut [Pixel]>,
+    ) -> bool {
+        let Some(click_detector) = &mut self.click_detector else {
+            return false;
+        };
+
+        click_detector.tick(&self.fft_result.load());
+        let color = if click_detector.frames_since_onset() < FLASH_FRAMES {
+            [255.0; 3]
+        } else {
+            Pixel::default()
+        };
+        ledstrips.for_each(|colors| colors.fill(color));
+        true
+    }
+
+    fn delay_frames(&self) -> usize {
+        let delay_ms = self.config.base_delay_ms as i32 + self.offset_ms;
+        (delay_ms.max(0) as f32 / self.frame_duration_ms).round() as usize
+    }
+
+    /// Forgets the queued frames of a ledstrip, which might not have its size anymore
+    pub fn remove(&mut self, ledstrip_id: usize) {
+        self.frames.remove(&ledstrip_id);
+    }
+
+    /// Queues the latest frame of a ledstrip and returns the one that should be sent now
+    pub fn delay(&mut self, ledstrip_id: usize, colors: &[Pixel]) -> &[Pixel] {
+        let delay_frames = self.delay_frames();
+        let frames = self.frames.entry(ledstrip_id).or_default();
+        // The frame leaving the buffer is written over by the new one
+        let mut frame = if frames.len() > delay_frames {
+            frames.pop_front().unwrap_or_default()
+        } else {
+            Vec::with_capacity(colors.len())
+        };
+        frame.clear();
+        frame.extend_from_slice(colors);
+        frames.push_back(frame);
+        // While the delay grows the oldest frame is held until the buffer caught up
+        while frames.len() > delay_frames + 1 {
+            frames.pop_front();
+        }
+        frames.front().unwrap()
+    }
+}
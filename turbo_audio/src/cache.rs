@@ -0,0 +1,172 @@
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    os::unix::fs::{MetadataExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+const DIGEST_SIZE: usize = 32;
+
+/// On-disk cache for artifacts that are expensive to produce at startup (parsed config, compiled
+/// lua chunks). Every entry stores the digest of the source it was produced from, so a stale entry
+/// is simply ignored and overwritten.
+///
+/// The entries are trusted more than their source: the config can load native plugins and LuaJIT
+/// runs bytecode without verifying it. The digest doesn't prove who wrote an entry, so they're
+/// only read from files that nobody but the user running turbo audio can write.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    folder: PathBuf,
+}
+
+impl Cache {
+    pub fn new(folder: impl AsRef<Path>) -> Self {
+        Self {
+            folder: folder.as_ref().to_owned(),
+        }
+    }
+
+    pub fn get(&self, name: &str, source: &[u8]) -> Option<Vec<u8>> {
+        let mut file = File::open(self.entry_path(name)).ok()?;
+        let metadata = file.metadata().ok()?;
+        // Safety: geteuid can't fail
+        let uid = unsafe { libc::geteuid() };
+        if metadata.uid() != uid || metadata.mode() & 0o022 != 0 {
+            tracing::warn!("Ignored the cache entry {name}, which others can write");
+            return None;
+        }
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).ok()?;
+        if data.len() < DIGEST_SIZE || data[..DIGEST_SIZE] != Self::digest(source)[..] {
+            tracing::debug!("Cache entry {name} is stale");
+            return None;
+        }
+        data.drain(..DIGEST_SIZE);
+        Some(data)
+    }
+
+    pub fn put(&self, name: &str, source: &[u8], payload: &[u8]) {
+        let path = self.entry_path(name);
+        // Created anew, so that a file or a link left in its place isn't written through
+        let result = fs::create_dir_all(&self.folder)
+            .and_then(|_| match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            })
+            .and_then(|_| {
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&path)
+            })
+            .and_then(|mut file| {
+                file.write_all(&Self::digest(source))?;
+                file.write_all(payload)
+            });
+        // The cache is only an optimization, failing to write it is not an error
+        if let Err(e) = result {
+            tracing::warn!("Couldn't write cache entry {name}: {e}");
+        }
+    }
+
+    pub fn get_serialized<T: DeserializeOwned>(&self, name: &str, source: &[u8]) -> Option<T> {
+        let data = self.get(name, source)?;
+        rmp_serde::from_slice(&data)
+            .map_err(|e| tracing::warn!("Couldn't deserialize cache entry {name}: {e}"))
+            .ok()
+    }
+
+    pub fn put_serialized<T: Serialize>(&self, name: &str, source: &[u8], value: &T) {
+        match rmp_serde::to_vec_named(value) {
+            Ok(payload) => self.put(name, source, &payload),
+            Err(e) => tracing::warn!("Couldn't serialize cache entry {name}: {e}"),
+        }
+    }
+
+    fn entry_path(&self, name: &str) -> PathBuf {
+        let file_name = Self::digest(name.as_bytes())
+            .iter()
+            .take(8)
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        self.folder.join(file_name)
+    }
+
+    fn digest(source: &[u8]) -> [u8; DIGEST_SIZE] {
+        let mut hasher = Sha256::new();
+        // Entries produced by another version of turbo audio might not be compatible
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update(source);
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use std::{fs, os::unix::fs::PermissionsExt, path::PathBuf};
+
+    // A cache in a fresh folder of the temp dir, removed with its entries
+    struct TempCache(Cache, PathBuf);
+
+    impl TempCache {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("turbo_audio-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            Self(Cache::new(&path), path)
+        }
+    }
+
+    impl Drop for TempCache {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.1);
+        }
+    }
+
+    #[test]
+    fn reads_back_the_entries_of_the_same_source() {
+        let cache = TempCache::new("cache-entries");
+
+        cache.0.put("lua:effect.lua", b"source", b"bytecode");
+
+        assert_eq!(
+            cache.0.get("lua:effect.lua", b"source"),
+            Some(b"bytecode".to_vec())
+        );
+        assert_eq!(cache.0.get("lua:effect.lua", b"changed source"), None);
+        assert_eq!(cache.0.get("lua:other.lua", b"source"), None);
+    }
+
+    #[test]
+    fn ignores_the_entries_others_can_write() {
+        let cache = TempCache::new("cache-permissions");
+        cache.0.put("lua:effect.lua", b"source", b"bytecode");
+        let path = cache.0.entry_path("lua:effect.lua");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+
+        assert_eq!(cache.0.get("lua:effect.lua", b"source"), None);
+    }
+
+    #[test]
+    fn replaces_a_link_instead_of_writing_through_it() {
+        let cache = TempCache::new("cache-link");
+        fs::create_dir(&cache.1).unwrap();
+        let target = cache.1.join("target");
+        fs::write(&target, "keep me").unwrap();
+        std::os::unix::fs::symlink(&target, cache.0.entry_path("config")).unwrap();
+
+        cache.0.put("config", b"source", b"config");
+
+        assert_eq!(fs::read_to_string(&target).unwrap(), "keep me");
+        assert_eq!(cache.0.get("config", b"source"), Some(b"config".to_vec()));
+    }
+}
@@ -0,0 +1,295 @@
+use crate::{
+    color_lut::ColorLut,
+    config_parser::{
+        parse_config, ConfigError, ConfigFormat, EffectConfigType, SettingsConfigType,
+        TurboAudioConfig, CONFIG_VERSION,
+    },
+    plugins::effects::registry::RHAI_EFFECTS_FOLDER,
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CheckConfigError {
+    #[error("Couldn't read {0}: {1}")]
+    Io(String, std::io::Error),
+
+    #[error("{0} can't be parsed or doesn't match the config format")]
+    Parse(String),
+
+    #[error("{0} has {1} problem(s)")]
+    Invalid(String, usize),
+}
+
+/// Parses the settings file and checks that the resources refer to each other, without starting
+/// anything. Prints every problem found
+pub fn run(settings_file: &str, format: ConfigFormat) -> Result<(), CheckConfigError> {
+    let text = std::fs::read_to_string(settings_file)
+        .map_err(|e| CheckConfigError::Io(settings_file.to_owned(), e))?;
+    let parsed = match parse_config(&text, format) {
+        Ok(parsed) => parsed,
+        Err(ConfigError::Invalid {
+            line,
+            column,
+            message,
+        }) => {
+            print_parse_error(settings_file, &text, line, column, &message);
+            return Err(CheckConfigError::Parse(settings_file.to_owned()));
+        }
+        Err(e) => {
+            println!("{settings_file}: {e}");
+            return Err(CheckConfigError::Parse(settings_file.to_owned()));
+        }
+    };
+    if let Some(version) = parsed.migrated_from {
+        println!("{settings_file}: version {version} config, migrated to version {CONFIG_VERSION}");
+        for change in &parsed.migration_changes {
+            println!("  {change}");
+        }
+    }
+    let config = parsed.config;
+
+    let problems = find_problems(&config);
+    if problems.is_empty() {
+        println!("{settings_file} is valid");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{settings_file}: {problem}");
+    }
+    Err(CheckConfigError::Invalid(
+        settings_file.to_owned(),
+        problems.len(),
+    ))
+}
+
+// Prints the error with the line it's on and a caret under its column
+fn print_parse_error(settings_file: &str, text: &str, line: usize, column: usize, message: &str) {
+    println!("{settings_file}:{line}:{column}: {message}");
+    let Some(text_line) = text.lines().nth(line.saturating_sub(1)) else {
+        return;
+    };
+    let number = line.to_string();
+    println!("{number} | {text_line}");
+    println!(
+        "{} | {}^",
+        " ".repeat(number.len()),
+        " ".repeat(column.saturating_sub(1))
+    );
+}
+
+fn duplicates(kind: &str, ids: impl IntoIterator<Item = usize>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    ids.into_iter()
+        .filter(|id| !seen.insert(*id))
+        .map(|id| format!("{kind} {id} is declared twice"))
+        .collect()
+}
+
+fn effect_path(config: &TurboAudioConfig, effect: &EffectConfigType) -> Option<PathBuf> {
+    match effect {
+        EffectConfigType::Lua(file_name) => Some(config.lua_effects_folder.join(file_name)),
+        EffectConfigType::Rhai(file_name) => Some(Path::new(RHAI_EFFECTS_FOLDER).join(file_name)),
+        EffectConfigType::Native(file_name) => Some(PathBuf::from(file_name)),
+        // Registered types are looked up when the effects are loaded
+        EffectConfigType::Type(_) => None,
+    }
+}
+
+pub fn find_problems(config: &TurboAudioConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    problems.extend(duplicates(
+        "Connection",
+        config.devices.iter().map(|device| device.id),
+    ));
+    problems.extend(duplicates(
+        "Settings",
+        config.effect_settings.iter().map(|settings| settings.id),
+    ));
+    problems.extend(duplicates(
+        "Effect",
+        config.effects.iter().map(|effect| effect.effect_id),
+    ));
+    problems.extend(duplicates(
+        "Ledstrip",
+        config.ledstrips.iter().map(|ledstrip| ledstrip.id),
+    ));
+
+    for device in &config.devices {
+        if let Some(path) = &device.color_lut {
+            if let Err(e) = ColorLut::load(path) {
+                problems.push(format!(
+                    "Connection {} loads the color tables {}: {e}",
+                    device.id,
+                    path.display()
+                ));
+            }
+        }
+    }
+
+    let mut audio_sources = HashSet::new();
+    for source in &config.audio_sources {
+        if !audio_sources.insert(source.name.as_str()) {
+            problems.push(format!("Audio source {} is declared twice", source.name));
+        }
+    }
+
+    for settings in &config.effect_settings {
+        if settings.seed.is_some() && matches!(settings.setting, SettingsConfigType::Lua(_)) {
+            problems.push(format!(
+                "Settings {} have a seed beside json settings, which take it as their `seed` field",
+                settings.id
+            ));
+        }
+    }
+
+    let settings: HashSet<usize> = config.effect_settings.iter().map(|s| s.id).collect();
+    let effects: HashSet<usize> = config.effects.iter().map(|e| e.effect_id).collect();
+    let connections: HashSet<usize> = config.devices.iter().map(|d| d.id).collect();
+
+    let ledstrips: HashSet<usize> = config.ledstrips.iter().map(|l| l.id).collect();
+    for route in &config.audio_routing {
+        if !audio_sources.contains(route.audio_source.as_str()) {
+            problems.push(format!(
+                "Ledstrips are routed to the audio source {}, which doesn't exist",
+                route.audio_source
+            ));
+        }
+        for ledstrip_id in &route.ledstrips {
+            if !ledstrips.contains(ledstrip_id) {
+                problems.push(format!(
+                    "Ledstrip {ledstrip_id} is routed to the audio source {}, but doesn't exist",
+                    route.audio_source
+                ));
+            }
+        }
+    }
+    let routes_valid = match config.audio_routes() {
+        Ok(_) => true,
+        Err(e) => {
+            problems.push(e.to_string());
+            false
+        }
+    };
+
+    for effect in &config.effects {
+        if !settings.contains(&effect.settings_id) {
+            problems.push(format!(
+                "Effect {} uses the settings {}, which don't exist",
+                effect.effect_id, effect.settings_id
+            ));
+        }
+        if let Some(path) = effect_path(config, &effect.effect) {
+            if !path.exists() {
+                problems.push(format!(
+                    "Effect {} loads {}, which doesn't exist",
+                    effect.effect_id,
+                    path.display()
+                ));
+            }
+        }
+        // Every effect would fail on a ledstrip routed twice
+        if let (true, Err(e)) = (routes_valid, config.effect_audio_source(effect)) {
+            problems.push(e.to_string());
+        }
+        if let Some(source) = &effect.audio_source {
+            if !audio_sources.contains(source.as_str()) {
+                problems.push(format!(
+                    "Effect {} reacts to the audio source {source}, which doesn't exist",
+                    effect.effect_id
+                ));
+            }
+        }
+    }
+
+    for ledstrip in &config.ledstrips {
+        if !connections.contains(&ledstrip.connection_id) {
+            problems.push(format!(
+                "Ledstrip {} is sent to the connection {}, which doesn't exist",
+                ledstrip.id, ledstrip.connection_id
+            ));
+        }
+        if let Some(fallback_id) = ledstrip.fallback_connection_id {
+            if !connections.contains(&fallback_id) {
+                problems.push(format!(
+                    "Ledstrip {} falls back to the connection {fallback_id}, which doesn't exist",
+                    ledstrip.id
+                ));
+            }
+        }
+        for segment in &ledstrip.effects {
+            if !effects.contains(&segment.effect_id) {
+                problems.push(format!(
+                    "Ledstrip {} renders the effect {}, which doesn't exist",
+                    ledstrip.id, segment.effect_id
+                ));
+            }
+        }
+        if let Err(e) = ledstrip.build() {
+            problems.push(format!("Ledstrip {}: {e}", ledstrip.id));
+        }
+    }
+
+    let mut profiles = HashSet::new();
+    for profile in &config.profiles {
+        if !profiles.insert(profile.name.as_str()) {
+            problems.push(format!("Profile {} is declared twice", profile.name));
+        }
+        for profile_ledstrip in &profile.ledstrips {
+            let Some(ledstrip) = config
+                .ledstrips
+                .iter()
+                .find(|ledstrip| ledstrip.id == profile_ledstrip.ledstrip_id)
+            else {
+                problems.push(format!(
+                    "Profile {} changes the ledstrip {}, which doesn't exist",
+                    profile.name, profile_ledstrip.ledstrip_id
+                ));
+                continue;
+            };
+            if profile_ledstrip.effects.len() > ledstrip.effects.len() {
+                problems.push(format!(
+                    "Profile {} gives {} effects to ledstrip {}, which has {} segments",
+                    profile.name,
+                    profile_ledstrip.effects.len(),
+                    ledstrip.id,
+                    ledstrip.effects.len()
+                ));
+            }
+            for effect_id in &profile_ledstrip.effects {
+                if !effects.contains(effect_id) {
+                    problems.push(format!(
+                        "Profile {} renders the effect {effect_id}, which doesn't exist",
+                        profile.name
+                    ));
+                }
+            }
+        }
+    }
+    for entry in &config.schedule {
+        if let Some(profile) = &entry.profile {
+            if !profiles.contains(profile.as_str()) {
+                problems.push(format!(
+                    "The schedule entry of {} switches to the profile {profile}, which doesn't exist",
+                    entry.at
+                ));
+            }
+        }
+    }
+    if let Some(session) = &config.session {
+        let folder = session
+            .path
+            .parent()
+            .filter(|folder| !folder.as_os_str().is_empty());
+        if folder.is_some_and(|folder| !folder.is_dir()) {
+            problems.push(format!(
+                "The session is saved to {}, in a folder that doesn't exist",
+                session.path.display()
+            ));
+        }
+    }
+    problems
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ColorLutError {
+    #[error("Couldn't read the file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Line {0} isn't a row of 3 values between 0 and 1")]
+    InvalidRow(usize),
+
+    #[error("Line {0} starts a 3D table, only tables per channel are supported")]
+    Unsupported(usize),
+
+    #[error("The tables need at least 2 rows, found {0}")]
+    TooShort(usize),
+}
+
+/// Lookup tables mapping every channel of the frames of a connection to the output that shows
+/// it right on its leds, like the ones measured with a colorimeter, for leds whose response is
+/// too far from a gamma curve. Applied on the 16-bit frames, before they're brought to 8 bits
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorLut {
+    // Output of each channel for inputs evenly spaced from 0 to 1
+    tables: [Vec<f32>; 3],
+}
+
+impl ColorLut {
+    pub fn load(path: &Path) -> Result<Self, ColorLutError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Reads rows of the red, green and blue outputs between 0 and 1, from an input of 0 on the
+    /// first row to 1 on the last one. The lines starting with `#` are comments and the other
+    /// ones starting with a letter are skipped, so that the 1D `.cube` files exported by the
+    /// calibration tools load as they are
+    pub fn parse(text: &str) -> Result<Self, ColorLutError> {
+        let mut tables: [Vec<f32>; 3] = Default::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with("LUT_3D_SIZE") {
+                return Err(ColorLutError::Unsupported(index + 1));
+            }
+            if line.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                continue;
+            }
+            let values: Vec<f32> = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| ColorLutError::InvalidRow(index + 1))?;
+            if values.len() != 3 || values.iter().any(|value| !(0.0..=1.0).contains(value)) {
+                return Err(ColorLutError::InvalidRow(index + 1));
+            }
+            for (table, value) in tables.iter_mut().zip(values) {
+                table.push(value);
+            }
+        }
+        if tables[0].len() < 2 {
+            return Err(ColorLutError::TooShort(tables[0].len()));
+        }
+        Ok(Self { tables })
+    }
+
+    /// Maps the rgb channels of a 16-bit frame, interpolating between the rows of the tables
+    pub fn apply(&self, frame: &mut [u16]) {
+        for pixel in frame.chunks_exact_mut(3) {
+            for (channel, table) in pixel.iter_mut().zip(&self.tables) {
+                let position = *channel as f32 / u16::MAX as f32 * (table.len() - 1) as f32;
+                let below = (position as usize).min(table.len() - 2);
+                let fraction = position - below as f32;
+                let value = table[below] + (table[below + 1] - table[below]) * fraction;
+                *channel = (value * u16::MAX as f32).round() as u16;
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::config_parser::TurboAudioConfig;
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt};
+
+// Top level fields applied to the running engine, the others need a restart
+const IN_PLACE_FIELDS: [&str; 12] = [
+    "audio_routing",
+    "derived_features",
+    "effect_settings",
+    "effects",
+    "render_threads",
+    "effect_budget",
+    "devices",
+    "ledstrips",
+    "profiles",
+    "idle",
+    "schedule",
+    "night_mode",
+];
+
+/// Ids of the resources of one kind that changed between two configs
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub added: Vec<usize>,
+    pub removed: Vec<usize>,
+    pub modified: Vec<usize>,
+}
+
+impl Changes {
+    fn new<T: Serialize>(old: &[T], new: &[T], id: impl Fn(&T) -> usize) -> Self {
+        let by_id = |items: &[T]| -> BTreeMap<usize, Value> {
+            items
+                .iter()
+                .map(|item| (id(item), to_value(item)))
+                .collect()
+        };
+        let (old, new) = (by_id(old), by_id(new));
+        let mut changes = Self {
+            removed: old
+                .keys()
+                .filter(|id| !new.contains_key(id))
+                .copied()
+                .collect(),
+            ..Default::default()
+        };
+        for (id, value) in new {
+            match old.get(&id) {
+                None => changes.added.push(id),
+                Some(old_value) if *old_value != value => changes.modified.push(id),
+                Some(_) => {}
+            }
+        }
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// Resources to drop from the engine, which are the removed and modified ones
+    pub fn dropped(&self) -> impl Iterator<Item = usize> + '_ {
+        self.removed.iter().chain(&self.modified).copied()
+    }
+
+    /// Whether the resource has to be built from the new config, which is the case of the added
+    /// and modified ones
+    pub fn is_built(&self, id: usize) -> bool {
+        self.added.contains(&id) || self.modified.contains(&id)
+    }
+
+    fn describe(&self, kind: &str) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let list = |action: &str, ids: &[usize]| {
+            (!ids.is_empty()).then(|| {
+                let ids: Vec<String> = ids.iter().map(usize::to_string).collect();
+                format!("{action} {}", ids.join(", "))
+            })
+        };
+        let parts: Vec<String> = [
+            list("added", &self.added),
+            list("removed", &self.removed),
+            list("modified", &self.modified),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        Some(format!("{kind} {}", parts.join(", ")))
+    }
+}
+
+fn to_value<T: Serialize>(item: &T) -> Value {
+    serde_json::to_value(item).unwrap_or_default()
+}
+
+/// What changed between the running config and a reloaded one, so that only the affected
+/// resources are rebuilt and the others keep their state, like the phase of the effects
+#[derive(Debug, Default)]
+pub struct ConfigDiff {
+    /// The kept effects are relinked on every change, which follows the new routes
+    pub audio_routing: bool,
+    pub derived_features: bool,
+    pub effect_settings: Changes,
+    /// Effects whose file changed. The ones whose settings or bindings changed are only relinked
+    pub effects: Changes,
+    pub relinked_effects: Vec<usize>,
+    pub render_threads: bool,
+    pub effect_budget: bool,
+    pub devices: Changes,
+    pub ledstrips: Changes,
+    pub profiles: bool,
+    pub idle: bool,
+    pub schedule: bool,
+    pub night_mode: bool,
+    /// The other top level fields that changed, which are only applied by restarting
+    pub restart_fields: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn new(old: &TurboAudioConfig, new: &TurboAudioConfig) -> Self {
+        let mut effects = Changes::new(&old.effects, &new.effects, |effect| effect.effect_id);
+        let effect_file = |config: &TurboAudioConfig, id: usize| {
+            config
+                .effects
+                .iter()
+                .find(|effect| effect.effect_id == id)
+                .map(|effect| to_value(&effect.effect))
+        };
+        let (relinked_effects, modified) = effects
+            .modified
+            .into_iter()
+            .partition(|id| effect_file(old, *id) == effect_file(new, *id));
+        effects.modified = modified;
+
+        let mut diff = Self {
+            audio_routing: old.audio_routing != new.audio_routing,
+            derived_features: to_value(&old.derived_features) != to_value(&new.derived_features),
+            effect_settings: Changes::new(&old.effect_settings, &new.effect_settings, |setting| {
+                setting.id
+            }),
+            effects,
+            relinked_effects,
+            render_threads: old.render_threads != new.render_threads,
+            effect_budget: old.effect_budget != new.effect_budget,
+            devices: Changes::new(&old.devices, &new.devices, |device| device.id),
+            ledstrips: Changes::new(&old.ledstrips, &new.ledstrips, |ledstrip| ledstrip.id),
+            profiles: to_value(&old.profiles) != to_value(&new.profiles),
+            idle: old.idle != new.idle,
+            schedule: old.schedule != new.schedule,
+            night_mode: old.night_mode != new.night_mode,
+            restart_fields: Vec::new(),
+        };
+
+        if let (Value::Object(old), Value::Object(new)) = (to_value(old), to_value(new)) {
+            let mut fields: Vec<&String> = old.keys().chain(new.keys()).collect();
+            fields.sort_unstable();
+            fields.dedup();
+            diff.restart_fields = fields
+                .into_iter()
+                .filter(|field| !IN_PLACE_FIELDS.contains(&field.as_str()))
+                .filter(|field| old.get(*field) != new.get(*field))
+                .cloned()
+                .collect();
+        }
+        // The mqtt client announces the ledstrips and effects to home assistant when it starts
+        let ids_changed =
+            |changes: &Changes| !changes.added.is_empty() || !changes.removed.is_empty();
+        if new.mqtt.is_some()
+            && (ids_changed(&diff.ledstrips) || ids_changed(&diff.effects))
+            && !diff.restart_fields.iter().any(|field| field == "mqtt")
+        {
+            diff.restart_fields.push("mqtt".to_owned());
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.audio_routing
+            && !self.derived_features
+            && self.effect_settings.is_empty()
+            && self.effects.is_empty()
+            && self.relinked_effects.is_empty()
+            && !self.render_threads
+            && !self.effect_budget
+            && self.devices.is_empty()
+            && self.ledstrips.is_empty()
+            && !self.profiles
+            && !self.idle
+            && !self.schedule
+            && !self.night_mode
+            && self.restart_fields.is_empty()
+    }
+
+    pub fn needs_restart(&self) -> bool {
+        !self.restart_fields.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let relinked = (!self.relinked_effects.is_empty()).then(|| {
+            let ids: Vec<String> = self.relinked_effects.iter().map(usize::to_string).collect();
+            format!("effect settings or bindings of {}", ids.join(", "))
+        });
+        let parts: Vec<String> = [
+            self.audio_routing.then(|| "audio routing".to_owned()),
+            self.derived_features.then(|| "derived features".to_owned()),
+            self.effect_settings.describe("effect settings"),
+            self.effects.describe("effects"),
+            relinked,
+            self.render_threads.then(|| "render threads".to_owned()),
+            self.effect_budget.then(|| "effect budget".to_owned()),
+            self.devices.describe("devices"),
+            self.ledstrips.describe("ledstrips"),
+            self.profiles.then(|| "profiles".to_owned()),
+            self.idle.then(|| "idle".to_owned()),
+            self.schedule.then(|| "schedule".to_owned()),
+            self.night_mode.then(|| "night mode".to_owned()),
+            (!self.restart_fields.is_empty())
+                .then(|| format!("{} (needs a restart)", self.restart_fields.join(", "))),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        write!(f, "{}", parts.join("; "))
+    }
+}
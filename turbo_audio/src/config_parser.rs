@@ -1,68 +1,1131 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
 
-use crate::audio::pipewire_listener::StreamConnections;
-use serde::{Deserialize, Serialize};
+use crate::{
+    ableton_link::AbletonLinkConfig,
+    audio::{
+        audio_processing::FftConfig,
+        audio_stream::{AudioBackend, AudioCapture, MissingAudioBehavior},
+        noise_gate::NoiseGateConfig,
+        smoothing::SmoothingProfile,
+        weighting::Weighting,
+    },
+    av_sync::AvSyncConfig,
+    connections::{
+        circuit_breaker::CircuitBreakerConfig, encoder::FrameEncoding, keep_alive::KeepAliveConfig,
+    },
+    idle::IdleConfig,
+    jack_transport::JackTransportConfig,
+    night_mode::NightModeConfig,
+    now_playing::NowPlayingConfig,
+    output_transform::{OutputStage, OutputTransform},
+    overrides::DEFAULT_PRIORITY,
+    parameter_mapping::{EnvelopeConfig, Expression},
+    plugins::effects::lua::LuaSandboxConfig,
+    post_processing::{self, ColorCalibration, PostProcessingStage},
+    resources::ledstrip::{
+        FallbackScene, FallbackScenes, LedStrip, LedStripError, SegmentLayout, UndersizedPolicy,
+        MAX_PIXELS,
+    },
+    schedule::ScheduleEntryConfig,
+    scheduler::EffectBudgetConfig,
+    screen::ScreenCaptureConfig,
+    session::SessionConfig,
+    sync::SyncConfig,
+    watchdog::WatchdogConfig,
+};
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Deserialize, Deserializer, Serialize,
+};
+use serde_json::Value;
+use thiserror::Error;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PortConnections {
+    AllInOrder,
+    Only(Vec<(String, String)>),
+}
+
+/// Pipewire links from the ports of an output stream to the ports of an input stream, kept
+/// while the engine runs. Linux only
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamConnections {
+    pub output_stream: String,
+    pub input_stream: String,
+    pub port_connections: PortConnections,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum EffectConfigType {
     Lua(String),
     Native(String),
+    /// File of the rhai effects folder
+    Rhai(String),
+    /// Registered effect type, like `rainbow` or `raindrop`, built from the linked settings
+    Type(String),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum SettingsConfigType {
     Native,
+    /// Json settings of a lua or rhai effect
     Lua(serde_json::Value),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub enum ConnectionConfigType {
-    Tcp(std::net::SocketAddr),
-    Usb(),
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct EffectConfig {
     pub effect_id: usize,
     pub settings_id: usize,
     pub effect: EffectConfigType,
+    /// Numeric settings recomputed every frame from the audio features and oscillators, like
+    /// `"speed": "0.5 + 2.0 * bass"` or `"hue": "0.5 + 0.5 * sine(0.1)"`, and kept in the range
+    /// of the settings schema. Lua and rhai effects only
+    #[serde(default)]
+    pub bindings: HashMap<String, Expression>,
+    /// Name of the audio source the effect reacts to, the main device if missing. Lua and rhai
+    /// effects only
+    #[serde(default)]
+    pub audio_source: Option<String>,
+}
+
+/// Audio device captured besides the main one, like a microphone next to the DJ deck, that
+/// effects can react to instead
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AudioSourceConfig {
+    pub name: String,
+    pub device_name: Option<String>,
+    #[serde(default)]
+    pub audio_capture: AudioCapture,
+    #[serde(default)]
+    pub stream_connections: Vec<StreamConnections>,
+}
+
+/// Ledstrips whose effects react to an audio source, like the strips of the dance floor following
+/// the DJ feed while the ones of the lounge follow the ambient music
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AudioRouteConfig {
+    /// Name of one of the `audio_sources`
+    pub audio_source: String,
+    pub ledstrips: Vec<usize>,
 }
 
+#[derive(Error, Debug)]
+pub enum AudioRoutingError {
+    #[error("Ledstrip {0} is routed to more than one audio source")]
+    RoutedTwice(usize),
+
+    #[error(
+        "Effect {0} is rendered by ledstrips following different audio sources ({1}), it has to \
+         pick one with its own audio_source"
+    )]
+    Conflict(usize, String),
+}
+
+/// Named signal computed from the audio features, like `kick` = `gate(band(40, 120), 0.6)`
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DerivedFeatureConfig {
+    pub name: String,
+    pub expression: Expression,
+    /// Follows the expression with an attack and a release instead of jumping to its value, like
+    /// a compressor, so that `band(40, 120)` pumps instead of flickering
+    #[serde(default)]
+    pub envelope: Option<EnvelopeConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct EffectSettingConfig {
     pub id: usize,
     pub setting: SettingsConfigType,
+    /// Seed of the random generator of a native effect, for the same frames on every run. Lua
+    /// and rhai effects take theirs as `seed` in their json settings
+    #[serde(default)]
+    pub seed: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LedstripEffectConfig {
     pub effect_id: usize,
     pub effect_size: usize,
+    /// Smoothing applied to the audio features read by the effect on this segment
+    #[serde(default)]
+    pub smoothing: SmoothingProfile,
+    /// What to do if the segment is smaller than the minimum pixel count of the effect
+    #[serde(default)]
+    pub undersized: UndersizedPolicy,
+    /// Radius in pixels of the gaussian blur applied to the segment after the effect. No blur if 0
+    #[serde(default, deserialize_with = "non_negative")]
+    pub blur_radius: f32,
+    /// Renders the effect from the end of the segment
+    #[serde(default)]
+    pub reversed: bool,
+    /// Renders the effect on half of the segment, outward from its center
+    #[serde(default)]
+    pub mirrored: bool,
+    /// Ranges of leds of the segment, inclusive and relative to its start, left off and skipped by
+    /// the effect
+    #[serde(default)]
+    pub skipped: Vec<(usize, usize)>,
+    /// Priority of the effect of the segment, which overrides of a higher priority replace while
+    /// they're set, like the ones sent through the control apis
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+}
+
+fn default_priority() -> u8 {
+    DEFAULT_PRIORITY
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LedstripConfig {
     pub id: usize,
     pub connection_id: usize,
+    /// Connection used while the primary one is down
+    #[serde(default)]
+    pub fallback_connection_id: Option<usize>,
+    /// First led of the ledstrip in the frame of its connection, for controllers with several
+    /// outputs. The ledstrips with an offset on a connection are sent together as one frame,
+    /// the others as their own frame
+    #[serde(default)]
+    pub offset: Option<usize>,
     pub size: usize,
     pub effects: Vec<LedstripEffectConfig>,
+    /// Stages applied in order to the colors before they are sent. Only the brightness if missing.
+    /// Must have the `Brightness` stage, where the brightness controls apply
+    #[serde(
+        default = "post_processing::default_stages",
+        deserialize_with = "post_processing_stages"
+    )]
+    pub post_processing: Vec<PostProcessingStage>,
+    /// Correction of the colors of the leds, so that ledstrips of different batches match
+    #[serde(default)]
+    pub calibration: ColorCalibration,
+    /// Stages turning the pixels the effects render on into the `size` leds, in order, like
+    /// `[{"Group": {"leds": 3}}, "Mirror"]`. The sizes of the segments are then in pixels
+    #[serde(default)]
+    pub transform: Vec<OutputStage>,
+    /// Leds lit by every pixel, so that the effects of a strip of 300 leds with a `group_size`
+    /// of 3 render 100 pixels. Grouped after the stages of the `transform`
+    #[serde(default = "single_led", deserialize_with = "count")]
+    pub group_size: usize,
+    /// Shown until the audio arrives, instead of the effects rendering on silence
+    #[serde(default)]
+    pub startup_scene: Option<FallbackScene>,
+    /// Shown while the audio source is lost, and on the segments whose effect fails, instead of
+    /// the effects rendering on silence and of the last frame of the failed segments
+    #[serde(default)]
+    pub failure_scene: Option<FallbackScene>,
+}
+
+fn single_led() -> usize {
+    1
+}
+
+impl LedstripConfig {
+    /// Lays the segments out one after the other. Fails on the first segment that doesn't fit on
+    /// the ledstrip
+    pub fn build(&self) -> Result<LedStrip, LedStripError> {
+        let mut ledstrip = LedStrip::default();
+        let stages = self.output_stages();
+        let transform = match stages.is_empty() {
+            true if self.size > MAX_PIXELS => return Err(LedStripError::TooManyPixels),
+            true => None,
+            false => {
+                Some(OutputTransform::new(&stages, self.size).ok_or(LedStripError::TooManyPixels)?)
+            }
+        };
+        ledstrip.set_led_count(
+            transform
+                .as_ref()
+                .map_or(self.size, OutputTransform::pixel_count),
+        );
+        ledstrip.transform = transform;
+        ledstrip.scenes = FallbackScenes {
+            startup: self.startup_scene,
+            failure: self.failure_scene,
+        };
+        for (index, effect) in self.effects.iter().enumerate() {
+            ledstrip
+                .add_effect(
+                    effect.effect_id,
+                    effect.effect_size,
+                    effect.smoothing,
+                    effect.undersized,
+                    effect.blur_radius,
+                    SegmentLayout {
+                        reversed: effect.reversed,
+                        mirrored: effect.mirrored,
+                        skipped: effect.skipped.clone(),
+                    },
+                )
+                .map_err(|error| LedStripError::Segment {
+                    segment: index,
+                    effect_id: effect.effect_id,
+                    error,
+                })?;
+            ledstrip.set_priority(index, effect.priority);
+        }
+        Ok(ledstrip)
+    }
+
+    /// Stages of the `transform`, followed by the grouping of the `group_size`
+    pub fn output_stages(&self) -> Vec<OutputStage> {
+        let mut stages = self.transform.clone();
+        if self.group_size > 1 {
+            stages.push(OutputStage::Group {
+                leds: self.group_size,
+            });
+        }
+        stages
+    }
+}
+
+/// Effects a profile renders on the segments of a ledstrip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileLedstripConfig {
+    pub ledstrip_id: usize,
+    /// Effect of every segment, in order. The segments after the last one keep their effect
+    pub effects: Vec<usize>,
+}
+
+fn full_brightness() -> f32 {
+    1.0
+}
+
+/// Named set of effects and brightness, like `party`, `ambient` or `off`, sharing the devices and
+/// the effects of the config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub name: String,
+    /// Global brightness while the profile is active
+    #[serde(default = "full_brightness", deserialize_with = "unit_interval")]
+    pub brightness: f32,
+    /// Ledstrips whose effects the profile changes, the others keep theirs
+    #[serde(default)]
+    pub ledstrips: Vec<ProfileLedstripConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct DeviceConfig {
-    pub connection: ConnectionConfigType,
+    /// Kind of connection, which picks its constructor in the connection factory
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Parameters given to the constructor of the connection
+    pub connection: serde_json::Value,
     pub id: usize,
+    /// Protocol of the device. Raw rgb bytes if missing
+    #[serde(default)]
+    pub encoding: FrameEncoding,
+    /// Frames sent when no new frame is due. Disabled if missing
+    #[serde(default)]
+    pub keep_alive: Option<KeepAliveConfig>,
+    /// Dithers the frames down to 8 bits over time instead of rounding them, for smooth dark
+    /// fades on fast strips like WS2812. Might flicker on slow devices
+    #[serde(default)]
+    pub dithering: bool,
+    /// Milliseconds the frames are held back, to line the device up with the ones on slower
+    /// links, up to 1000
+    #[serde(default)]
+    pub delay_ms: u32,
+    /// Holds the frames back by how much faster than the slowest compensated device this one is
+    /// reached, as measured by the connections whose protocol has acks. The others count as
+    /// instant
+    #[serde(default)]
+    pub compensate_latency: bool,
+    /// File of the lookup tables correcting each channel of the frames sent to the device, as
+    /// read by `ColorLut::parse`. Not corrected if missing
+    #[serde(default)]
+    pub color_lut: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OscConfig {
+    pub address: std::net::SocketAddr,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    pub address: std::net::SocketAddr,
+    /// Token the lua evaluations changing the globals of an effect have to send as
+    /// `Authorization: Bearer <token>`. They're rejected if missing
+    #[serde(default)]
+    pub write_token: Option<String>,
+    /// Folder of the files written by the captures, whose paths are relative to it. The captures
+    /// also need the `write_token`, and are refused if missing
+    #[serde(default)]
+    pub capture_dir: Option<PathBuf>,
+}
+
+/// Serves the api of `proto/turbo_audio.proto`. Needs turbo_audio to be built with the `grpc`
+/// feature
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcConfig {
+    pub address: std::net::SocketAddr,
+}
+
+fn default_control_socket_path() -> PathBuf {
+    PathBuf::from(crate::control::DEFAULT_SOCKET_PATH)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ControlSocketConfig {
+    #[serde(default = "default_control_socket_path")]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MidiMessageType {
+    ControlChange(u8),
+    Note(u8),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum MidiAction {
+    /// Maps the message value (0-127) linearly between `min` and `max`
+    EffectSetting {
+        effect_id: usize,
+        key: String,
+        min: f64,
+        max: f64,
+    },
+    /// Same as `EffectSetting`, for settings shared by several effects
+    Settings {
+        settings_id: usize,
+        key: String,
+        min: f64,
+        max: f64,
+    },
+    Brightness,
+    SwitchEffect {
+        ledstrip_id: usize,
+        segment: usize,
+        effect_id: usize,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MidiMapping {
+    /// Only react to messages on this channel (0-15). Any channel if missing.
+    #[serde(default)]
+    pub channel: Option<u8>,
+    pub message: MidiMessageType,
+    pub action: MidiAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum HotkeyAction {
+    NextProfile,
+    PreviousProfile,
+    /// Raises the global brightness by the step of the hotkeys
+    BrightnessUp,
+    BrightnessDown,
+    /// Sends black frames until pressed again, whatever the effects and the brightness
+    Blackout,
+    /// Keeps sending the current frame without rendering the effects until pressed again
+    Freeze,
+    /// Blacks out every other ledstrip until pressed again, like `{"Solo": 2}`
+    Solo(usize),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HotkeyBinding {
+    /// Keys held together, named like the linux input codes, `KEY_LEFTCTRL` or `KEY_F12`. The
+    /// action happens when the last of them is pressed
+    pub keys: Vec<String>,
+    pub action: HotkeyAction,
+}
+
+fn default_brightness_step() -> f32 {
+    0.1
+}
+
+/// Keyboard shortcuts read from the keyboards of the machine whichever window has the focus, and
+/// without any display. Needs read access to `/dev/input`, usually by being in the `input` group
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HotkeysConfig {
+    /// Part of the name of the keyboards to listen to. Every keyboard if missing
+    #[serde(default)]
+    pub device_name: Option<String>,
+    /// Brightness added or removed by `BrightnessUp` and `BrightnessDown`
+    #[serde(default = "default_brightness_step")]
+    pub brightness_step: f32,
+    pub bindings: Vec<HotkeyBinding>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MidiConfig {
+    /// Part of the name of the midi port to use. The first port is used if missing.
+    #[serde(default)]
+    pub port_name: Option<String>,
+    pub mappings: Vec<MidiMapping>,
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "turbo_audio".to_owned()
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "turbo_audio".to_owned()
+}
+
+fn default_mqtt_discovery_prefix() -> String {
+    "homeassistant".to_owned()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Also used as the home assistant device identifier
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub topic_prefix: String,
+    #[serde(default = "default_mqtt_discovery_prefix")]
+    pub discovery_prefix: String,
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct TurboAudioConfig {
+    /// Version of the config format, 1 if missing. Older configs are migrated when loaded
+    #[serde(default = "unversioned")]
+    pub version: u32,
     pub lua_effects_folder: PathBuf,
     pub device_name: Option<String>,
+    /// Whether the device is an input or an output whose audio is captured
+    #[serde(default)]
+    pub audio_capture: AudioCapture,
+    #[serde(default)]
+    pub audio_backend: AudioBackend,
     pub sample_rate: u32,
+    /// What to do when the audio device or pipewire can't be used at startup
+    #[serde(default)]
+    pub missing_audio: MissingAudioBehavior,
+    #[serde(default)]
+    pub fft: FftConfig,
+    /// Subtracts the calibrated noise floor from the fft and silences the quiet frames
+    #[serde(default)]
+    pub noise_gate: NoiseGateConfig,
+    /// Perceptual weighting of the fft bins, None, A, or a Custom curve
+    #[serde(default)]
+    pub weighting: Weighting,
     pub stream_connections: Vec<StreamConnections>,
+    /// Devices captured besides the main one, each with its own fft
+    #[serde(default)]
+    pub audio_sources: Vec<AudioSourceConfig>,
+    /// Audio sources the effects of the ledstrips react to, unless the effects pick their own.
+    /// The effects of the other ledstrips react to the main device
+    #[serde(default)]
+    pub audio_routing: Vec<AudioRouteConfig>,
+    /// Signals the bindings and the lua effects refer to by name. Each one can use the ones
+    /// before it
+    #[serde(default)]
+    pub derived_features: Vec<DerivedFeatureConfig>,
     pub effect_settings: Vec<EffectSettingConfig>,
     pub effects: Vec<EffectConfig>,
+    /// Threads rendering the effects. The number of cores if missing
+    #[serde(default)]
+    pub render_threads: Option<usize>,
+    #[serde(default)]
+    pub effect_budget: EffectBudgetConfig,
     pub devices: Vec<DeviceConfig>,
     pub ledstrips: Vec<LedstripConfig>,
+    /// Sets of effects and brightness picked at launch with `--profile` or through the control
+    /// API. The ledstrips render the effects above until one is picked
+    #[serde(default)]
+    pub profiles: Vec<ProfileConfig>,
+    #[serde(default)]
+    pub osc: Option<OscConfig>,
+    #[serde(default)]
+    pub http: Option<HttpConfig>,
+    #[serde(default)]
+    pub grpc: Option<GrpcConfig>,
+    #[serde(default)]
+    pub control_socket: Option<ControlSocketConfig>,
+    #[serde(default)]
+    pub midi: Option<MidiConfig>,
+    #[serde(default)]
+    pub hotkeys: Option<HotkeysConfig>,
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
+    pub av_sync: AvSyncConfig,
+    #[serde(default)]
+    pub lua_sandbox: LuaSandboxConfig,
+    /// Effect shown while the audio is silent. The effects keep running on silence if missing
+    #[serde(default)]
+    pub idle: Option<IdleConfig>,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    /// Profiles and brightness changes applied at times of the day
+    #[serde(default)]
+    pub schedule: Vec<ScheduleEntryConfig>,
+    /// Color temperature and brightness cap for the late hours
+    #[serde(default)]
+    pub night_mode: NightModeConfig,
+    /// Edges of the screen captured for the ambilight effects. Nothing is captured if missing
+    #[serde(default)]
+    pub screen_capture: Option<ScreenCaptureConfig>,
+    /// Media player whose track the effects read. No player is followed if missing
+    #[serde(default)]
+    pub now_playing: Option<NowPlayingConfig>,
+    /// Shares the time base, the beat and the profile with other instances on the network
+    #[serde(default)]
+    pub sync: Option<SyncConfig>,
+    /// Follows the tempo and the position of the JACK transport rather than the beats detected
+    #[serde(default)]
+    pub jack_transport: Option<JackTransportConfig>,
+    /// Follows the tempo and the beat phase of the Ableton Link session of the network rather
+    /// than the beats detected
+    #[serde(default)]
+    pub ableton_link: Option<AbletonLinkConfig>,
+    /// Saves the profile, brightness and effects picked through the control api, and restores
+    /// them at the next start. Nothing is saved if missing
+    #[serde(default)]
+    pub session: Option<SessionConfig>,
+}
+
+impl TurboAudioConfig {
+    /// The main device as an audio source, which has no name
+    pub fn main_audio_source(&self) -> AudioSourceConfig {
+        AudioSourceConfig {
+            name: String::new(),
+            device_name: self.device_name.clone(),
+            audio_capture: self.audio_capture,
+            stream_connections: self.stream_connections.clone(),
+        }
+    }
+
+    /// Audio source each routed ledstrip follows
+    pub fn audio_routes(&self) -> Result<HashMap<usize, &str>, AudioRoutingError> {
+        let mut routes = HashMap::new();
+        for route in &self.audio_routing {
+            for ledstrip_id in &route.ledstrips {
+                if routes
+                    .insert(*ledstrip_id, route.audio_source.as_str())
+                    .is_some()
+                {
+                    return Err(AudioRoutingError::RoutedTwice(*ledstrip_id));
+                }
+            }
+        }
+        Ok(routes)
+    }
+
+    /// Audio source an effect reacts to: its own, or else the one of the ledstrips rendering it
+    /// in their segments or profiles. The main device if missing
+    pub fn effect_audio_source<'a>(
+        &'a self,
+        effect: &'a EffectConfig,
+    ) -> Result<Option<&'a str>, AudioRoutingError> {
+        if effect.audio_source.is_some() || self.audio_routing.is_empty() {
+            return Ok(effect.audio_source.as_deref());
+        }
+        let routes = self.audio_routes()?;
+        let segments = self.ledstrips.iter().filter_map(|ledstrip| {
+            let renders = ledstrip
+                .effects
+                .iter()
+                .any(|e| e.effect_id == effect.effect_id);
+            renders.then_some(ledstrip.id)
+        });
+        let profiles = self
+            .profiles
+            .iter()
+            .flat_map(|profile| &profile.ledstrips)
+            .filter(|ledstrip| ledstrip.effects.contains(&effect.effect_id))
+            .map(|ledstrip| ledstrip.ledstrip_id);
+        let mut sources: Vec<Option<&str>> = segments
+            .chain(profiles)
+            .map(|ledstrip_id| routes.get(&ledstrip_id).copied())
+            .collect();
+        sources.sort_unstable();
+        sources.dedup();
+        match sources.as_slice() {
+            [] => Ok(None),
+            [source] => Ok(*source),
+            _ => {
+                let names: Vec<&str> = sources
+                    .iter()
+                    .map(|source| source.unwrap_or("the main device"))
+                    .collect();
+                Err(AudioRoutingError::Conflict(
+                    effect.effect_id,
+                    names.join(", "),
+                ))
+            }
+        }
+    }
+}
+
+/// Version of the config format this build reads. Bumped with a migration in [`migrate`] when a
+/// change breaks the configs written for the previous version
+pub const CONFIG_VERSION: u32 = 2;
+
+// Configs written before the version field, whose devices had a tagged connection like
+// `{"Tcp": "127.0.0.1:42069"}`
+const UNVERSIONED: u32 = 1;
+
+fn unversioned() -> u32 {
+    UNVERSIONED
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("{line}:{column}: {message}")]
+    Invalid {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    #[error("Version {0} is newer than this build, which reads up to version {CONFIG_VERSION}")]
+    TooNew(u32),
+
+    // The migrated config isn't the text of the file anymore, so the errors have no location
+    #[error("{1} (in the config migrated from version {0})")]
+    Migrated(u32, String),
+}
+
+/// Config read from a settings file
+#[derive(Debug)]
+pub struct ParsedConfig {
+    pub config: TurboAudioConfig,
+    /// Version of the file if it was migrated, and what the migration changed
+    pub migrated_from: Option<u32>,
+    pub migration_changes: Vec<String>,
+}
+
+/// Format of a settings file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Format of a file from its extension, json unless it's `.toml`, `.yaml` or `.yml`
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("toml") => Self::Toml,
+            Some("yaml" | "yml") => Self::Yaml,
+            _ => Self::Json,
+        }
+    }
+}
+
+/// Parses a settings file in any format, migrating it first if it was written for an older
+/// version. The errors point at the place of the file they are about
+pub fn parse_config(text: &str, format: ConfigFormat) -> Result<ParsedConfig, ConfigError> {
+    let mut value: Value = deserialize(text, format)?;
+    let version = match value.get("version").and_then(Value::as_u64) {
+        Some(version) => u32::try_from(version).unwrap_or(u32::MAX),
+        None => UNVERSIONED,
+    };
+    if version > CONFIG_VERSION {
+        return Err(ConfigError::TooNew(version));
+    }
+    if version == CONFIG_VERSION {
+        let config = deserialize(text, format).map_err(|e| name_field(text, e))?;
+        return Ok(ParsedConfig {
+            config,
+            migrated_from: None,
+            migration_changes: Vec::new(),
+        });
+    }
+
+    let migration_changes = migrate(&mut value, version);
+    let config =
+        serde_json::from_value(value).map_err(|e| ConfigError::Migrated(version, e.to_string()))?;
+    Ok(ParsedConfig {
+        config,
+        migrated_from: Some(version),
+        migration_changes,
+    })
+}
+
+/// Brings a config from `version` to [`CONFIG_VERSION`], one version at a time. Returns what was
+/// changed
+fn migrate(config: &mut Value, version: u32) -> Vec<String> {
+    let mut changes = Vec::new();
+    if version < 2 {
+        // The type of the connection moved from the tag of its parameters to the `type` field
+        let devices = config.get_mut("devices").and_then(Value::as_array_mut);
+        for device in devices.into_iter().flatten() {
+            let Some(Value::Object(tagged)) = device.get("connection") else {
+                continue;
+            };
+            let Some((kind, parameters)) = tagged.iter().next().filter(|_| tagged.len() == 1)
+            else {
+                continue;
+            };
+            if !["Tcp", "Usb"].contains(&kind.as_str()) {
+                continue;
+            }
+            let kind = kind.clone();
+            // Usb had no parameters, written as an empty tuple
+            let parameters = match kind.as_str() {
+                "Usb" => Value::Null,
+                _ => parameters.clone(),
+            };
+            changes.push(format!(
+                "Device {}: `\"connection\": {{\"{kind}\": ...}}` is now \
+                 `\"type\": \"{kind}\", \"connection\": {parameters}`",
+                device["id"]
+            ));
+            device["type"] = Value::String(kind);
+            device["connection"] = parameters;
+        }
+    }
+    // A config that isn't an object fails to deserialize after the migration
+    if let Some(config) = config.as_object_mut() {
+        config.insert("version".to_owned(), Value::from(CONFIG_VERSION));
+    }
+    changes
+}
+
+// Deserializes the text, with the location of the errors
+fn deserialize<T: DeserializeOwned>(text: &str, format: ConfigFormat) -> Result<T, ConfigError> {
+    let (line, column, message) = match format {
+        ConfigFormat::Json => match serde_json::from_str(text) {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                let location = format!(" at line {} column {}", e.line(), e.column());
+                let message = e.to_string();
+                let message = message.strip_suffix(&location).unwrap_or(&message);
+                (e.line(), e.column(), message.to_owned())
+            }
+        },
+        ConfigFormat::Toml => match toml::from_str(text) {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                let offset = e.span().map_or(0, |span| span.start);
+                let before = text.get(..offset).unwrap_or(text);
+                let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+                let line = before.matches('\n').count() + 1;
+                (line, before.len() - line_start + 1, e.message().to_owned())
+            }
+        },
+        ConfigFormat::Yaml => match serde_yaml::from_str(text) {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                let (line, column) = e
+                    .location()
+                    .map_or((1, 1), |location| (location.line(), location.column()));
+                let location = format!(" at line {line} column {column}");
+                let message = e.to_string();
+                let message = message.strip_suffix(&location).unwrap_or(&message);
+                (line, column, message.to_owned())
+            }
+        },
+    };
+    Err(ConfigError::Invalid {
+        line,
+        column,
+        message,
+    })
+}
+
+// Errors like a wrong type or a value out of range don't say which field they are about, unlike
+// the unknown or missing fields. Only for the errors of a text whose syntax is valid
+fn name_field(text: &str, error: ConfigError) -> ConfigError {
+    let ConfigError::Invalid {
+        line,
+        column,
+        message,
+    } = error
+    else {
+        return error;
+    };
+    let line_start: usize = text
+        .split('\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum();
+    let key = text
+        .get(..(line_start + column).min(text.len()))
+        .and_then(key_before)
+        .filter(|_| !message.contains('`'));
+    let message = match key {
+        Some(key) => format!("`{key}` {message}"),
+        None => message,
+    };
+    ConfigError::Invalid {
+        line,
+        column,
+        message,
+    }
+}
+
+// Last key of the text, which the value at its end belongs to. Works with the `"key": value` of
+// json, the `key: value` of yaml and the `key = value` of toml
+fn key_before(text: &str) -> Option<&str> {
+    text.rmatch_indices([':', '=']).find_map(|(index, _)| {
+        let token = text[..index]
+            .trim_end()
+            .rsplit(|c: char| c.is_whitespace() || "{[,".contains(c))
+            .next()?;
+        let key = token.trim_matches(['"', '\'']);
+        let is_key = !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        is_key.then_some(key)
+    })
+}
+
+/// Deserializes a number between 0 and 1, like a share of the maximum
+pub fn unit_interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err(D::Error::custom(format!(
+            "must be between 0 and 1, found {value}"
+        )));
+    }
+    Ok(value)
+}
+
+/// Deserializes a number between 0 and 1 that can be missing
+pub fn optional_unit_interval<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f32>, D::Error> {
+    #[derive(Deserialize)]
+    struct UnitInterval(#[serde(deserialize_with = "unit_interval")] f32);
+    Ok(Option::<UnitInterval>::deserialize(deserializer)?.map(|UnitInterval(value)| value))
+}
+
+/// Deserializes a color temperature in kelvin, from 1000K to 40000K
+pub fn color_temperature<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if !(1000.0..=40000.0).contains(&value) {
+        return Err(D::Error::custom(format!(
+            "must be between 1000 and 40000 kelvin, found {value}"
+        )));
+    }
+    Ok(value)
+}
+
+/// Deserializes a color temperature in kelvin that can be missing
+pub fn optional_color_temperature<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f32>, D::Error> {
+    #[derive(Deserialize)]
+    struct ColorTemperature(#[serde(deserialize_with = "color_temperature")] f32);
+    Ok(Option::<ColorTemperature>::deserialize(deserializer)?.map(|ColorTemperature(value)| value))
+}
+
+/// Deserializes a number that can't be negative, like a duration
+pub fn non_negative<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if value < 0.0 {
+        return Err(D::Error::custom(format!(
+            "can't be negative, found {value}"
+        )));
+    }
+    Ok(value)
+}
+
+/// Deserializes a whole number above 0, like a number of leds
+pub fn count<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let value = usize::deserialize(deserializer)?;
+    if value == 0 {
+        return Err(D::Error::custom("must be above 0, found 0"));
+    }
+    Ok(value)
+}
+
+/// Deserializes the stages of a post-processing chain, which has to apply the brightness so that
+/// the brightness set through the controls isn't silently ignored
+pub fn post_processing_stages<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<PostProcessingStage>, D::Error> {
+    let stages = Vec::<PostProcessingStage>::deserialize(deserializer)?;
+    if !stages.contains(&PostProcessingStage::Brightness) {
+        return Err(D::Error::custom(
+            "must have the Brightness stage, where the brightness of the controls applies",
+        ));
+    }
+    Ok(stages)
+}
+
+/// Deserializes a number above 0, like an exponent
+pub fn positive<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if value <= 0.0 {
+        return Err(D::Error::custom(format!("must be above 0, found {value}")));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_config, ConfigError, ConfigFormat, CONFIG_VERSION};
+    use serde_json::json;
+
+    // Smallest config that parses, with the devices given
+    fn config(version: Option<u32>, devices: serde_json::Value) -> String {
+        let mut config = json!({
+            "lua_effects_folder": "effects",
+            "sample_rate": 48000,
+            "stream_connections": [],
+            "effect_settings": [],
+            "effects": [],
+            "devices": devices,
+            "ledstrips": [],
+        });
+        if let Some(version) = version {
+            config["version"] = version.into();
+        }
+        config.to_string()
+    }
+
+    #[test]
+    fn reads_the_current_version_as_it_is() {
+        let text = config(
+            Some(CONFIG_VERSION),
+            json!([{ "type": "Tcp", "connection": "127.0.0.1:42069", "id": 1 }]),
+        );
+
+        let parsed = parse_config(&text, ConfigFormat::Json).unwrap();
+
+        assert_eq!(parsed.migrated_from, None);
+        assert!(parsed.migration_changes.is_empty());
+        assert_eq!(parsed.config.devices[0].kind, "Tcp");
+    }
+
+    #[test]
+    fn migrates_the_tagged_connections_of_unversioned_configs() {
+        let text = config(
+            None,
+            json!([
+                { "connection": { "Tcp": "127.0.0.1:42069" }, "id": 1 },
+                { "connection": { "Usb": [] }, "id": 2 },
+            ]),
+        );
+
+        let parsed = parse_config(&text, ConfigFormat::Json).unwrap();
+
+        assert_eq!(parsed.migrated_from, Some(1));
+        assert_eq!(parsed.migration_changes.len(), 2);
+        assert_eq!(parsed.config.version, CONFIG_VERSION);
+        let devices = &parsed.config.devices;
+        assert_eq!(devices[0].kind, "Tcp");
+        assert_eq!(devices[0].connection, json!("127.0.0.1:42069"));
+        assert_eq!(devices[1].kind, "Usb");
+        assert_eq!(devices[1].connection, json!(null));
+    }
+
+    #[test]
+    fn migrates_the_other_formats() {
+        let text = "lua_effects_folder = \"effects\"\n\
+                    sample_rate = 48000\n\
+                    stream_connections = []\n\
+                    effect_settings = []\n\
+                    effects = []\n\
+                    ledstrips = []\n\
+                    [[devices]]\n\
+                    id = 1\n\
+                    connection = { Tcp = \"127.0.0.1:42069\" }\n";
+        let parsed = parse_config(text, ConfigFormat::Toml).unwrap();
+        assert_eq!(parsed.migrated_from, Some(1));
+        assert_eq!(parsed.config.devices[0].kind, "Tcp");
+
+        let text = "version: 1\n\
+                    lua_effects_folder: effects\n\
+                    sample_rate: 48000\n\
+                    stream_connections: []\n\
+                    effect_settings: []\n\
+                    effects: []\n\
+                    ledstrips: []\n\
+                    devices:\n\
+                    - id: 1\n  \
+                      connection: { Usb: [] }\n";
+        let parsed = parse_config(text, ConfigFormat::Yaml).unwrap();
+        assert_eq!(parsed.migrated_from, Some(1));
+        assert_eq!(parsed.config.devices[0].kind, "Usb");
+    }
+
+    #[test]
+    fn rejects_the_newer_versions() {
+        let text = config(Some(CONFIG_VERSION + 1), json!([]));
+        assert!(matches!(
+            parse_config(&text, ConfigFormat::Json),
+            Err(ConfigError::TooNew(version)) if version == CONFIG_VERSION + 1
+        ));
+
+        // Too large for a u32, rather than wrapped to an older version
+        let text = config(None, json!([])).replacen('{', "{\"version\":4294967297,", 1);
+        assert!(matches!(
+            parse_config(&text, ConfigFormat::Json),
+            Err(ConfigError::TooNew(u32::MAX))
+        ));
+    }
+
+    #[test]
+    fn locates_the_errors() {
+        let text = config(Some(CONFIG_VERSION), json!([])).replace("48000", "\"fast\"");
+        let Err(ConfigError::Invalid { line, message, .. }) =
+            parse_config(&text, ConfigFormat::Json)
+        else {
+            panic!("The wrong sample rate was accepted");
+        };
+        assert_eq!(line, 1);
+        assert!(message.starts_with("`sample_rate`"), "{message}");
+
+        // The migrated config has no location left
+        let text = config(
+            None,
+            json!([{ "connection": { "Tcp": "127.0.0.1:42069" } }]),
+        );
+        assert!(matches!(
+            parse_config(&text, ConfigFormat::Json),
+            Err(ConfigError::Migrated(1, _))
+        ));
+
+        assert!(matches!(
+            parse_config("[]", ConfigFormat::Json),
+            Err(ConfigError::Migrated(1, _))
+        ));
+        assert!(matches!(
+            parse_config("{", ConfigFormat::Json),
+            Err(ConfigError::Invalid { .. })
+        ));
+    }
 }
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failed sends before the breaker trips
+    pub failure_threshold: u32,
+    /// How long to stop sending to a connection once its breaker tripped
+    pub cooldown_ms: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown_ms: 5000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    Closed,
+    Open { until: Instant },
+    // The cooldown is over, a single attempt is allowed to check if the connection recovered
+    HalfOpen,
+}
+
+/// Stops sending to a connection that keeps failing, so a dead connection doesn't cost an error
+/// log and a doomed send every tick.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: BreakerState,
+    consecutive_failures: u32,
+    trip_count: u64,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            trip_count: 0,
+        }
+    }
+
+    /// Returns whether a send should be attempted now.
+    pub fn allows_attempt(&mut self) -> bool {
+        if let BreakerState::Open { until } = self.state {
+            if Instant::now() < until {
+                return false;
+            }
+            self.state = BreakerState::HalfOpen;
+        }
+        true
+    }
+
+    /// Returns whether the next attempt is a probe after a cooldown.
+    pub fn is_half_open(&self) -> bool {
+        self.state == BreakerState::HalfOpen
+    }
+
+    /// Returns whether sends are paused until the cooldown is over.
+    pub fn is_open(&self) -> bool {
+        matches!(self.state, BreakerState::Open { .. })
+    }
+
+    /// Returns whether the breaker is open and the cooldown isn't over, so the next attempt would
+    /// be refused.
+    pub fn is_cooling_down(&self) -> bool {
+        matches!(self.state, BreakerState::Open { until } if Instant::now() < until)
+    }
+
+    /// Number of times the breaker tripped since it was created.
+    pub fn trip_count(&self) -> u64 {
+        self.trip_count
+    }
+
+    /// Records a successful send. Returns true if the connection just recovered.
+    pub fn on_success(&mut self) -> bool {
+        let recovered = self.state != BreakerState::Closed;
+        self.state = BreakerState::Closed;
+        self.consecutive_failures = 0;
+        recovered
+    }
+
+    /// Pauses the sends right away, like after too many failures.
+    pub fn trip(&mut self) {
+        self.state = BreakerState::Open {
+            until: Instant::now() + Duration::from_millis(self.config.cooldown_ms),
+        };
+        self.trip_count += 1;
+    }
+
+    /// Records a failed send. Returns true if the breaker just tripped.
+    pub fn on_failure(&mut self) -> bool {
+        self.consecutive_failures += 1;
+        let should_trip = self.state == BreakerState::HalfOpen
+            || self.consecutive_failures >= self.config.failure_threshold;
+        if !should_trip {
+            return false;
+        }
+
+        self.trip();
+        true
+    }
+}
@@ -0,0 +1,445 @@
+use super::stats::FrameStats;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Turns the rgb bytes of a frame into the packets a connection sends, so that every protocol
+/// frames its data the same way no matter where the frame comes from.
+pub trait FrameEncoder: Send {
+    /// Encodes the rgb bytes (3 per led) of a frame into one or more packets
+    fn encode(&mut self, rgb: &[u8]) -> Result<Vec<Vec<u8>>, EncodeError>;
+
+    /// Gives the latest stats of the sender to the encoders that embed them. Called before every
+    /// frame
+    fn update_stats(&mut self, _stats: FrameStats) {}
+
+    /// Makes the device keep showing the next frames after the sender stops, for the protocols
+    /// with a timeout
+    fn hold(&mut self) {}
+
+    /// Packets telling the device to go back to its own effects. Empty for the protocols that
+    /// can't
+    fn release(&mut self) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum EncodeError {
+    #[error("Frame of {0} bytes is too long for a {1:?} length field")]
+    TooLong(usize, LengthField),
+}
+
+/// Protocol spoken by the device at the other end of a connection
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum FrameEncoding {
+    /// The rgb bytes as is
+    #[default]
+    RawRgb,
+    /// The serial protocol of Adalight and its clones (Hyperion, Prismatik)
+    Adalight,
+    /// WLED's realtime protocol. WLED goes back to its own effects after `timeout_s` without
+    /// frames
+    Wled {
+        #[serde(default = "default_wled_timeout_s")]
+        timeout_s: u8,
+    },
+    /// E1.31 (streaming ACN), starting at `universe` and using as many universes as needed
+    Sacn {
+        #[serde(default = "default_sacn_universe")]
+        universe: u16,
+        #[serde(default = "default_sacn_priority")]
+        priority: u8,
+    },
+    /// Distributed Display Protocol (WLED, Falcon, ESPixelStick), split in as many packets as the
+    /// frame needs. Usually sent over udp to port 4048
+    Ddp {
+        /// Output of the controller the pixels go to, 1 being its default output
+        #[serde(default = "default_ddp_destination_id")]
+        destination_id: u8,
+    },
+    /// Fixed header, then optionally the stats of the sender, the length of the data, a sequence
+    /// number, the data and a crc of the data
+    Custom(CustomFraming),
+    /// The magic `TA`, the length of the data and a sequence number (both u16, big endian), the
+    /// data and its crc16, so that a receiver finds the frames in a tcp stream and tells the torn
+    /// and dropped ones. `receivers/turbo_framed.h` parses it
+    Framed,
+}
+
+fn default_wled_timeout_s() -> u8 {
+    2
+}
+
+fn default_ddp_destination_id() -> u8 {
+    1
+}
+
+fn default_sacn_universe() -> u16 {
+    1
+}
+
+fn default_sacn_priority() -> u8 {
+    100
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LengthField {
+    U16Be,
+    U16Le,
+    U32Be,
+    U32Le,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Crc {
+    /// Polynomial 0x07
+    Crc8,
+    /// CCITT-FALSE, big endian
+    Crc16,
+    /// IEEE, little endian
+    Crc32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomFraming {
+    #[serde(default)]
+    pub header: Vec<u8>,
+    /// Whether to put the [`FrameStats`] of the sender right after the header
+    #[serde(default)]
+    pub stats: bool,
+    #[serde(default)]
+    pub length: Option<LengthField>,
+    /// Whether to put a sequence number (u16, big endian) after the length, one more every frame
+    /// and wrapping around
+    #[serde(default)]
+    pub sequence: bool,
+    #[serde(default)]
+    pub crc: Option<Crc>,
+}
+
+// Starts every frame of the framed encoding
+pub(crate) const FRAMED_MAGIC: &[u8] = b"TA";
+
+impl CustomFraming {
+    /// Layout of [`FrameEncoding::Framed`]
+    pub fn framed() -> Self {
+        Self {
+            header: FRAMED_MAGIC.to_vec(),
+            stats: false,
+            length: Some(LengthField::U16Be),
+            sequence: true,
+            crc: Some(Crc::Crc16),
+        }
+    }
+}
+
+impl FrameEncoding {
+    pub fn encoder(&self) -> Box<dyn FrameEncoder> {
+        match self {
+            FrameEncoding::RawRgb => Box::new(RawRgbEncoder),
+            FrameEncoding::Adalight => Box::new(AdalightEncoder),
+            FrameEncoding::Wled { timeout_s } => Box::new(WledEncoder {
+                timeout_s: *timeout_s,
+            }),
+            FrameEncoding::Sacn { universe, priority } => Box::new(SacnEncoder {
+                universe: *universe,
+                priority: *priority,
+                sequence: 0,
+                cid: rand::random(),
+                universe_count: 0,
+            }),
+            FrameEncoding::Ddp { destination_id } => Box::new(DdpEncoder {
+                destination_id: *destination_id,
+                sequence: 0,
+            }),
+            FrameEncoding::Custom(framing) => Box::new(CustomEncoder {
+                framing: framing.clone(),
+                stats: FrameStats::default(),
+                sequence: 0,
+            }),
+            FrameEncoding::Framed => Box::new(CustomEncoder {
+                framing: CustomFraming::framed(),
+                stats: FrameStats::default(),
+                sequence: 0,
+            }),
+        }
+    }
+}
+
+struct RawRgbEncoder;
+
+impl FrameEncoder for RawRgbEncoder {
+    fn encode(&mut self, rgb: &[u8]) -> Result<Vec<Vec<u8>>, EncodeError> {
+        Ok(vec![rgb.to_vec()])
+    }
+}
+
+struct AdalightEncoder;
+
+impl FrameEncoder for AdalightEncoder {
+    fn encode(&mut self, rgb: &[u8]) -> Result<Vec<Vec<u8>>, EncodeError> {
+        let [hi, lo] = ((rgb.len() / 3).saturating_sub(1) as u16).to_be_bytes();
+        let mut packet = Vec::with_capacity(6 + rgb.len());
+        packet.extend_from_slice(b"Ada");
+        packet.extend_from_slice(&[hi, lo, hi ^ lo ^ 0x55]);
+        packet.extend_from_slice(rgb);
+        Ok(vec![packet])
+    }
+}
+
+// Leds that fit in a single packet of the realtime protocol
+const WLED_DRGB_MAX_LEDS: usize = 490;
+const WLED_DNRGB_MAX_LEDS: usize = 489;
+const WLED_DRGB: u8 = 2;
+const WLED_DNRGB: u8 = 4;
+// Timeout of frames that WLED shows until the next one
+const WLED_NO_TIMEOUT: u8 = 255;
+
+struct WledEncoder {
+    timeout_s: u8,
+}
+
+impl FrameEncoder for WledEncoder {
+    fn encode(&mut self, rgb: &[u8]) -> Result<Vec<Vec<u8>>, EncodeError> {
+        if rgb.len() <= WLED_DRGB_MAX_LEDS * 3 {
+            let mut packet = vec![WLED_DRGB, self.timeout_s];
+            packet.extend_from_slice(rgb);
+            return Ok(vec![packet]);
+        }
+
+        // Longer strips are split in packets that each give the index of their first led
+        let packets = rgb
+            .chunks(WLED_DNRGB_MAX_LEDS * 3)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let [start_hi, start_lo] = ((index * WLED_DNRGB_MAX_LEDS) as u16).to_be_bytes();
+                let mut packet = vec![WLED_DNRGB, self.timeout_s, start_hi, start_lo];
+                packet.extend_from_slice(chunk);
+                packet
+            })
+            .collect();
+        Ok(packets)
+    }
+
+    fn hold(&mut self) {
+        self.timeout_s = WLED_NO_TIMEOUT;
+    }
+
+    // A timeout of 0 makes WLED leave the realtime mode right away
+    fn release(&mut self) -> Vec<Vec<u8>> {
+        vec![vec![WLED_DRGB, 0]]
+    }
+}
+
+// Whole leds that fit in the 512 slots of a universe
+const SACN_SLOTS_PER_UNIVERSE: usize = 510;
+const SACN_HEADER_LEN: usize = 126;
+const SACN_SOURCE_NAME: &[u8] = b"turbo_audio";
+// Option of the framing layer telling the receivers that the source stopped
+const SACN_STREAM_TERMINATED: u8 = 0x40;
+// Termination packets sent per universe, as the standard asks
+const SACN_TERMINATION_PACKETS: usize = 3;
+
+struct SacnEncoder {
+    universe: u16,
+    priority: u8,
+    sequence: u8,
+    // Identifies this source to the receivers
+    cid: [u8; 16],
+    // Universes of the last frame
+    universe_count: usize,
+}
+
+impl SacnEncoder {
+    fn packet(&self, universe: u16, options: u8, slots: &[u8]) -> Vec<u8> {
+        let len = SACN_HEADER_LEN + slots.len();
+        let flags_and_length = |offset: usize| (0x7000 | (len - offset) as u16).to_be_bytes();
+        let mut packet = Vec::with_capacity(len);
+        // Root layer
+        packet.extend_from_slice(&[0x00, 0x10, 0x00, 0x00]);
+        packet.extend_from_slice(b"ASC-E1.17\0\0\0");
+        packet.extend_from_slice(&flags_and_length(16));
+        packet.extend_from_slice(&4u32.to_be_bytes());
+        packet.extend_from_slice(&self.cid);
+        // Framing layer
+        packet.extend_from_slice(&flags_and_length(38));
+        packet.extend_from_slice(&2u32.to_be_bytes());
+        let mut source_name = [0u8; 64];
+        source_name[..SACN_SOURCE_NAME.len()].copy_from_slice(SACN_SOURCE_NAME);
+        packet.extend_from_slice(&source_name);
+        packet.push(self.priority);
+        packet.extend_from_slice(&[0x00, 0x00]);
+        packet.push(self.sequence);
+        packet.push(options);
+        packet.extend_from_slice(&universe.to_be_bytes());
+        // DMP layer
+        packet.extend_from_slice(&flags_and_length(115));
+        packet.extend_from_slice(&[0x02, 0xa1, 0x00, 0x00, 0x00, 0x01]);
+        packet.extend_from_slice(&(slots.len() as u16 + 1).to_be_bytes());
+        packet.push(0x00);
+        packet.extend_from_slice(slots);
+        packet
+    }
+}
+
+impl FrameEncoder for SacnEncoder {
+    fn encode(&mut self, rgb: &[u8]) -> Result<Vec<Vec<u8>>, EncodeError> {
+        let packets = rgb
+            .chunks(SACN_SLOTS_PER_UNIVERSE)
+            .enumerate()
+            .map(|(index, slots)| {
+                self.packet(self.universe.wrapping_add(index as u16), 0x00, slots)
+            })
+            .collect::<Vec<_>>();
+        self.universe_count = packets.len();
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(packets)
+    }
+
+    fn release(&mut self) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        for _ in 0..SACN_TERMINATION_PACKETS {
+            for index in 0..self.universe_count {
+                let universe = self.universe.wrapping_add(index as u16);
+                packets.push(self.packet(universe, SACN_STREAM_TERMINATED, &[]));
+            }
+            self.sequence = self.sequence.wrapping_add(1);
+        }
+        packets
+    }
+}
+
+// Whole leds that fit in a packet without going over the usual mtu
+const DDP_MAX_DATA_LEN: usize = 480 * 3;
+const DDP_VERSION_1: u8 = 0x40;
+// Tells the controller to show what it received, set on the last packet of a frame
+const DDP_PUSH: u8 = 0x01;
+// Rgb, 8 bits per channel
+const DDP_TYPE_RGB24: u8 = 0x0b;
+
+struct DdpEncoder {
+    destination_id: u8,
+    // Goes from 1 to 15, 0 would mean that sequence numbers aren't used
+    sequence: u8,
+}
+
+impl FrameEncoder for DdpEncoder {
+    fn encode(&mut self, rgb: &[u8]) -> Result<Vec<Vec<u8>>, EncodeError> {
+        self.sequence = self.sequence % 15 + 1;
+        let chunk_count = rgb.len().div_ceil(DDP_MAX_DATA_LEN).max(1);
+        let packets = (0..chunk_count)
+            .map(|index| {
+                let offset = index * DDP_MAX_DATA_LEN;
+                let data = &rgb[offset..rgb.len().min(offset + DDP_MAX_DATA_LEN)];
+                let flags = if index + 1 == chunk_count {
+                    DDP_VERSION_1 | DDP_PUSH
+                } else {
+                    DDP_VERSION_1
+                };
+                let mut packet = Vec::with_capacity(10 + data.len());
+                packet.extend_from_slice(&[
+                    flags,
+                    self.sequence,
+                    DDP_TYPE_RGB24,
+                    self.destination_id,
+                ]);
+                packet.extend_from_slice(&(offset as u32).to_be_bytes());
+                packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                packet.extend_from_slice(data);
+                packet
+            })
+            .collect();
+        Ok(packets)
+    }
+}
+
+struct CustomEncoder {
+    framing: CustomFraming,
+    stats: FrameStats,
+    // Of the last frame
+    sequence: u16,
+}
+
+impl FrameEncoder for CustomEncoder {
+    fn encode(&mut self, rgb: &[u8]) -> Result<Vec<Vec<u8>>, EncodeError> {
+        let mut packet = self.framing.header.clone();
+        if self.framing.stats {
+            packet.extend_from_slice(&self.stats.to_bytes());
+        }
+        if let Some(field) = self.framing.length {
+            // A truncated length would make the receiver read the frame wrong
+            let too_long = |_| EncodeError::TooLong(rgb.len(), field);
+            match field {
+                LengthField::U16Be => {
+                    let len = u16::try_from(rgb.len()).map_err(too_long)?;
+                    packet.extend_from_slice(&len.to_be_bytes());
+                }
+                LengthField::U16Le => {
+                    let len = u16::try_from(rgb.len()).map_err(too_long)?;
+                    packet.extend_from_slice(&len.to_le_bytes());
+                }
+                LengthField::U32Be => {
+                    let len = u32::try_from(rgb.len()).map_err(too_long)?;
+                    packet.extend_from_slice(&len.to_be_bytes());
+                }
+                LengthField::U32Le => {
+                    let len = u32::try_from(rgb.len()).map_err(too_long)?;
+                    packet.extend_from_slice(&len.to_le_bytes());
+                }
+            }
+        }
+        if self.framing.sequence {
+            self.sequence = self.sequence.wrapping_add(1);
+            packet.extend_from_slice(&self.sequence.to_be_bytes());
+        }
+        packet.extend_from_slice(rgb);
+        match self.framing.crc {
+            Some(Crc::Crc8) => packet.push(crc8(rgb)),
+            Some(Crc::Crc16) => packet.extend_from_slice(&crc16(rgb).to_be_bytes()),
+            Some(Crc::Crc32) => packet.extend_from_slice(&crc32(rgb).to_le_bytes()),
+            None => {}
+        }
+        Ok(vec![packet])
+    }
+
+    fn update_stats(&mut self, stats: FrameStats) {
+        self.stats = stats;
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(0xffff_ffff, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
@@ -0,0 +1,413 @@
+use super::{Connection, ConnectionError, LinkStatus};
+use openssl::{
+    error::ErrorStack,
+    ssl::{HandshakeError, Ssl, SslContext, SslMethod, SslStream, SslVerifyMode, SslVersion},
+};
+use ring_channel::*;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{ToSocketAddrs, UdpSocket},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use thiserror::Error;
+
+const STREAMING_PORT: u16 = 2100;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// Lamps a single message of the v1 streaming protocol can address
+const MAX_LAMPS: usize = 10;
+
+#[derive(Error, Debug)]
+pub enum HueError {
+    #[error("Http error: {0}")]
+    Http(#[from] Box<ureq::Error>),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("The bridge refused the request: {0}")]
+    Bridge(String),
+
+    #[error("Couldn't resolve {0}")]
+    UnknownHost(String),
+
+    #[error("Openssl error: {0}")]
+    Ssl(#[from] ErrorStack),
+
+    #[error("DTLS handshake failed: {0}")]
+    Handshake(String),
+
+    #[error("The entertainment area doesn't have any lamp")]
+    NoLamps,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HueParameters {
+    /// Hostname or ip of the bridge
+    pub bridge: String,
+    /// Application key (username) the bridge gave when pairing
+    pub username: String,
+    /// Client key the bridge gave with the username when pairing with `generateclientkey`, in hex
+    pub client_key: String,
+    /// Id of the entertainment area
+    pub group: u32,
+    /// Leds of the ledstrip whose average color each lamp shows. If missing, the lamps of the
+    /// area are spread over the ledstrip from left to right, according to their position
+    #[serde(default)]
+    pub lamps: Option<Vec<HueLamp>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct HueLamp {
+    pub light: u16,
+    pub start: usize,
+    pub len: usize,
+}
+
+// Lamps and the part of the ledstrip they show
+#[derive(Debug, Clone)]
+enum LampLayout {
+    Ranges(Vec<HueLamp>),
+    // Lights ordered from left to right, each showing an equal share of the ledstrip
+    Spread(Vec<u16>),
+}
+
+#[derive(Debug, Deserialize)]
+struct HueGroup {
+    #[serde(default)]
+    locations: HashMap<String, Vec<f32>>,
+}
+
+/// Streams to the lamps of a Philips Hue entertainment area, through the Hue Entertainment API.
+/// Each lamp shows the average color of its part of the ledstrip. The device should keep the raw
+/// rgb encoding.
+pub struct HueConnection {
+    parameters: Arc<HueParameters>,
+    data_queue: Option<RingSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+    status: Arc<Mutex<LinkStatus>>,
+}
+
+impl HueConnection {
+    pub fn new(parameters: HueParameters) -> Self {
+        let mut connection = Self {
+            parameters: Arc::new(parameters),
+            data_queue: None,
+            thread: None,
+            should_quit: Arc::default(),
+            status: Arc::new(Mutex::new(LinkStatus::Connecting)),
+        };
+        connection.start();
+        connection
+    }
+
+    fn start(&mut self) {
+        let (tx, rx) = ring_channel::<Vec<u8>>(NonZeroUsize::new(4).unwrap());
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        let status = Arc::new(Mutex::new(LinkStatus::Connecting));
+        self.thread = Some(thread::spawn({
+            let parameters = self.parameters.clone();
+            let should_quit = should_quit.clone();
+            let status = status.clone();
+            move || {
+                let _span =
+                    tracing::info_span!("hue_connection", bridge = %parameters.bridge).entered();
+                stream_loop(&parameters, &should_quit, &status, rx);
+                *status.lock().unwrap() = LinkStatus::Disconnected;
+            }
+        }));
+        self.data_queue = Some(tx);
+        self.should_quit = should_quit;
+        self.status = status;
+    }
+
+    fn stop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        self.data_queue.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Hue connection thread panicked");
+            }
+        }
+    }
+}
+
+impl Connection for HueConnection {
+    fn send_frame(&mut self, packet: Vec<u8>) -> Result<(), ConnectionError> {
+        self.data_queue
+            .as_mut()
+            .ok_or(ConnectionError::Closed)?
+            .send(packet)
+            .map(|_| ())
+            .map_err(|_| ConnectionError::Closed)
+    }
+
+    fn status(&self) -> LinkStatus {
+        *self.status.lock().unwrap()
+    }
+
+    fn reconnect(&mut self) {
+        self.stop();
+        tracing::info!("Reconnecting to the Hue bridge {}", self.parameters.bridge);
+        self.start();
+    }
+}
+
+impl Drop for HueConnection {
+    fn drop(&mut self) {
+        self.stop();
+        tracing::info!("Hue connection stopped.");
+    }
+}
+
+fn stream_loop(
+    parameters: &HueParameters,
+    should_quit: &AtomicBool,
+    status: &Mutex<LinkStatus>,
+    rx: RingReceiver<Vec<u8>>,
+) {
+    while !should_quit.load(Ordering::Relaxed) {
+        let (mut stream, layout) = match start_streaming(parameters) {
+            Ok(streaming) => streaming,
+            Err(e) => {
+                tracing::warn!(
+                    "Couldn't start streaming to the Hue bridge: {e}. Retrying in {}s",
+                    RETRY_DELAY.as_secs()
+                );
+                *status.lock().unwrap() = LinkStatus::Connecting;
+                // Sleep in small steps to quit quickly
+                for _ in 0..RETRY_DELAY.as_millis() / 100 {
+                    if should_quit.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+                continue;
+            }
+        };
+        tracing::info!("Streaming to the Hue bridge");
+        *status.lock().unwrap() = LinkStatus::Connected;
+
+        let mut sequence: u8 = 0;
+        loop {
+            let Ok(rgb) = rx.recv() else {
+                // The connection was dropped
+                stop_streaming(parameters);
+                return;
+            };
+            sequence = sequence.wrapping_add(1);
+            if let Err(e) = stream.write_all(&message(&layout, &rgb, sequence)) {
+                tracing::info!(
+                    "Lost the stream to the Hue bridge ({e}). Will attempt to restart it."
+                );
+                *status.lock().unwrap() = LinkStatus::Connecting;
+                break;
+            }
+        }
+    }
+}
+
+fn start_streaming(
+    parameters: &HueParameters,
+) -> Result<(SslStream<UdpChannel>, LampLayout), HueError> {
+    let layout = match &parameters.lamps {
+        Some(lamps) => LampLayout::Ranges(lamps.clone()),
+        None => LampLayout::Spread(fetch_lamps(parameters)?),
+    };
+    let lamp_count = match &layout {
+        LampLayout::Ranges(lamps) => lamps.len(),
+        LampLayout::Spread(lights) => lights.len(),
+    };
+    if lamp_count == 0 {
+        return Err(HueError::NoLamps);
+    }
+    if lamp_count > MAX_LAMPS {
+        tracing::warn!(
+            "Only the first {MAX_LAMPS} lamps of the entertainment area are streamed to"
+        );
+    }
+
+    set_streaming(parameters, true)?;
+    Ok((handshake(parameters)?, layout))
+}
+
+fn stop_streaming(parameters: &HueParameters) {
+    if let Err(e) = set_streaming(parameters, false) {
+        tracing::warn!("Couldn't stop streaming to the Hue bridge: {e}");
+    }
+}
+
+fn group_url(parameters: &HueParameters) -> String {
+    format!(
+        "http://{}/api/{}/groups/{}",
+        parameters.bridge, parameters.username, parameters.group
+    )
+}
+
+// Lights of the entertainment area from left to right
+fn fetch_lamps(parameters: &HueParameters) -> Result<Vec<u16>, HueError> {
+    let group: HueGroup = ureq::get(&group_url(parameters))
+        .timeout(REQUEST_TIMEOUT)
+        .call()
+        .map_err(Box::new)?
+        .into_json()?;
+    let mut lamps: Vec<(u16, f32)> = group
+        .locations
+        .iter()
+        .filter_map(|(light, position)| Some((light.parse().ok()?, *position.first()?)))
+        .collect();
+    lamps.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    Ok(lamps.into_iter().map(|(light, _)| light).collect())
+}
+
+fn set_streaming(parameters: &HueParameters, active: bool) -> Result<(), HueError> {
+    let response: serde_json::Value = ureq::put(&group_url(parameters))
+        .timeout(REQUEST_TIMEOUT)
+        .send_json(serde_json::json!({ "stream": { "active": active } }))
+        .map_err(Box::new)?
+        .into_json()?;
+    // The bridge answers with a list of successes and errors
+    let error = response
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find_map(|result| result.pointer("/error/description")?.as_str());
+    match error {
+        Some(error) => Err(HueError::Bridge(error.to_owned())),
+        None => Ok(()),
+    }
+}
+
+// Datagrams of a connected udp socket, for openssl
+#[derive(Debug)]
+struct UdpChannel(UdpSocket);
+
+impl Read for UdpChannel {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        self.0.recv(buffer)
+    }
+}
+
+impl Write for UdpChannel {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.0.send(buffer)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn handshake(parameters: &HueParameters) -> Result<SslStream<UdpChannel>, HueError> {
+    let address = (parameters.bridge.as_str(), STREAMING_PORT)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| HueError::UnknownHost(parameters.bridge.clone()))?;
+    let socket = UdpSocket::bind(if address.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+    socket.connect(address)?;
+    socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let identity = parameters.username.as_bytes().to_vec();
+    let psk = decode_hex(&parameters.client_key)
+        .ok_or_else(|| HueError::Handshake("the client key isn't valid hex".to_owned()))?;
+    let mut context = SslContext::builder(SslMethod::dtls_client())?;
+    context.set_min_proto_version(Some(SslVersion::DTLS1_2))?;
+    context.set_cipher_list("PSK-AES128-GCM-SHA256")?;
+    // The bridge is authenticated by the pre-shared key, it has no certificate
+    context.set_verify(SslVerifyMode::NONE);
+    context.set_psk_client_callback(move |_, _, identity_buffer, psk_buffer| {
+        if identity.len() >= identity_buffer.len() || psk.len() > psk_buffer.len() {
+            return Err(ErrorStack::get());
+        }
+        // The identity is a null terminated string
+        identity_buffer[..identity.len()].copy_from_slice(&identity);
+        identity_buffer[identity.len()] = 0;
+        psk_buffer[..psk.len()].copy_from_slice(&psk);
+        Ok(psk.len())
+    });
+
+    Ssl::new(&context.build())?
+        .connect(UdpChannel(socket))
+        .map_err(|e| match e {
+            HandshakeError::SetupFailure(e) => HueError::Ssl(e),
+            e => HueError::Handshake(e.to_string()),
+        })
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn average(rgb: &[u8], start: usize, len: usize) -> [u8; 3] {
+    let leds =
+        rgb.chunks_exact(3)
+            .skip(start)
+            .take(len)
+            .fold(([0u32; 3], 0u32), |(sum, count), led| {
+                (
+                    [
+                        sum[0] + led[0] as u32,
+                        sum[1] + led[1] as u32,
+                        sum[2] + led[2] as u32,
+                    ],
+                    count + 1,
+                )
+            });
+    match leds {
+        (_, 0) => [0; 3],
+        (sum, count) => sum.map(|channel| (channel / count) as u8),
+    }
+}
+
+// Message of the v1 streaming protocol, with rgb colors
+fn message(layout: &LampLayout, rgb: &[u8], sequence: u8) -> Vec<u8> {
+    let led_count = rgb.len() / 3;
+    let lamps: Vec<(u16, [u8; 3])> = match layout {
+        LampLayout::Ranges(lamps) => lamps
+            .iter()
+            .map(|lamp| (lamp.light, average(rgb, lamp.start, lamp.len)))
+            .collect(),
+        LampLayout::Spread(lights) => lights
+            .iter()
+            .enumerate()
+            .map(|(index, light)| {
+                let start = index * led_count / lights.len();
+                let end = (index + 1) * led_count / lights.len();
+                (*light, average(rgb, start, end - start))
+            })
+            .collect(),
+    };
+
+    let mut message = Vec::with_capacity(16 + MAX_LAMPS * 9);
+    message.extend_from_slice(b"HueStream");
+    message.extend_from_slice(&[0x01, 0x00, sequence, 0x00, 0x00, 0x00, 0x00]);
+    for (light, color) in lamps.into_iter().take(MAX_LAMPS) {
+        message.push(0x00);
+        message.extend_from_slice(&light.to_be_bytes());
+        for channel in color {
+            // Channels are 16 bits
+            message.extend_from_slice(&(channel as u16 * 257).to_be_bytes());
+        }
+    }
+    message
+}
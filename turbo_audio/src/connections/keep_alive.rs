@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+/// What a keep-alive frame contains
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeepAliveColor {
+    /// Repeat the last frame sent to the ledstrip
+    #[default]
+    RepeatLast,
+    Black,
+}
+
+/// Frames sent to a connection when no new frame is due, so that receivers with a realtime
+/// timeout (like WLED) don't switch back to their own mode while the engine is paused.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeepAliveConfig {
+    /// Longest time a ledstrip can go without receiving a frame
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default)]
+    pub color: KeepAliveColor,
+    /// Don't send a frame identical to the last one, unless it was sent more than `interval_ms`
+    /// ago. Saves the bandwidth of static effects like a solid color
+    #[serde(default)]
+    pub skip_unchanged: bool,
+}
+
+fn default_interval_ms() -> u64 {
+    1000
+}
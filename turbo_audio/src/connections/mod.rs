@@ -1,9 +1,229 @@
-use self::{tcp::TcpConnection, usb::UsbConnection};
+use self::{
+    openrgb::OpenRgbConnection,
+    plugin::PluginConnection,
+    tcp::{TcpConnection, TcpParameters},
+    udp::{UdpConnection, UdpParameters},
+    usb::UsbConnection,
+    websocket::{WebSocketConnection, WebSocketParameters},
+};
+use crate::mdns::{self, MdnsError};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use thiserror::Error;
 
+pub mod circuit_breaker;
+pub mod encoder;
+#[cfg(feature = "hue")]
+pub mod hue;
+pub mod keep_alive;
+pub mod openrgb;
+pub mod plugin;
+pub mod sender;
+pub mod stats;
 pub mod tcp;
+pub mod tls;
+pub mod udp;
 pub mod usb;
+pub mod websocket;
+#[cfg(feature = "rpi")]
+pub mod ws281x;
 
-pub enum Connection {
-    Tcp(TcpConnection),
-    Usb(UsbConnection),
+#[derive(Error, Debug)]
+pub enum ConnectionError {
+    #[error("Unknown connection type: {0}")]
+    UnknownType(String),
+
+    #[error("Invalid parameters for a {0} connection: {1}")]
+    InvalidParameters(String, serde_json::Error),
+
+    #[error("The connection is closed")]
+    Closed,
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid url {0}: {1}")]
+    InvalidUrl(String, String),
+
+    #[error("Couldn't resolve the address of the device: {0}")]
+    Resolve(#[from] MdnsError),
+
+    #[error("Couldn't load the connection plugin {0}: {1}")]
+    Plugin(String, String),
+
+    #[error("Connection plugin error: {0}")]
+    Backend(String),
+
+    #[error("Couldn't encode the frame: {0}")]
+    Encode(#[from] encoder::EncodeError),
+
+    #[error("{0} connections aren't implemented yet")]
+    Unimplemented(&'static str),
+
+    #[error("Couldn't set up tls: {0}")]
+    Tls(String),
+
+    #[cfg(any(not(feature = "hue"), not(feature = "rpi"), not(feature = "tls")))]
+    #[error("turbo_audio was built without {0} support")]
+    FeatureDisabled(&'static str),
+}
+
+/// State of the link with the device, as far as the connection knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LinkStatus {
+    Connected,
+    Connecting,
+    Disconnected,
+}
+
+/// Transport carrying the encoded frames to a device.
+///
+/// New transports implement this trait and register a constructor in the [`ConnectionFactory`]
+/// under the `type` used in the config. Transports kept out of the tree implement
+/// [`turbo_plugin::connection_plugin::ConnectionBackend`] instead and are loaded as `Plugin`
+/// connections.
+pub trait Connection: Send {
+    fn send_frame(&mut self, packet: Vec<u8>) -> Result<(), ConnectionError>;
+
+    fn status(&self) -> LinkStatus;
+
+    /// Starts over after the connection gave up on the device
+    fn reconnect(&mut self);
+
+    /// Moving average of the time the device takes to acknowledge a message, for the protocols
+    /// that have acks. None until measured
+    fn round_trip(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Builds a connection from the `connection` parameters of a device config
+pub type ConnectionConstructor =
+    fn(&serde_json::Value) -> Result<Box<dyn Connection>, ConnectionError>;
+
+/// Connection constructors keyed by the `type` of the device config
+pub struct ConnectionFactory {
+    constructors: HashMap<String, ConnectionConstructor>,
+}
+
+impl ConnectionFactory {
+    pub fn register(&mut self, kind: &str, constructor: ConnectionConstructor) {
+        self.constructors.insert(kind.to_owned(), constructor);
+    }
+
+    pub fn create(
+        &self,
+        kind: &str,
+        parameters: &serde_json::Value,
+    ) -> Result<Box<dyn Connection>, ConnectionError> {
+        let constructor = self
+            .constructors
+            .get(kind)
+            .ok_or_else(|| ConnectionError::UnknownType(kind.to_owned()))?;
+        constructor(parameters)
+    }
+}
+
+impl Default for ConnectionFactory {
+    fn default() -> Self {
+        let mut factory = Self {
+            constructors: HashMap::new(),
+        };
+        factory.register("Tcp", |parameters| {
+            let parameters: TcpParameters = match parameters {
+                serde_json::Value::String(_) => TcpParameters {
+                    address: parse_parameters("Tcp", parameters)?,
+                    tls: None,
+                    token: None,
+                },
+                _ => parse_parameters("Tcp", parameters)?,
+            };
+            if parameters.token.is_some() && parameters.tls.is_none() {
+                tracing::warn!(
+                    "The token of the device at {} is sent in clear, anyone on the network can \
+                     read and replay it. Set `tls` to encrypt the connection.",
+                    parameters.address.0
+                );
+            }
+            let tls = parameters
+                .tls
+                .as_ref()
+                .map(|tls| tls.connector(tls::host_of(&parameters.address.0)))
+                .transpose()?;
+            Ok(Box::new(TcpConnection::with_tls(
+                parameters.address.resolve()?,
+                parameters.handshake(),
+                tls,
+            )))
+        });
+        factory.register("Udp", |parameters| {
+            let parameters: UdpParameters = match parameters {
+                serde_json::Value::String(_) => UdpParameters {
+                    address: parse_parameters("Udp", parameters)?,
+                    max_datagram_size: None,
+                },
+                _ => parse_parameters("Udp", parameters)?,
+            };
+            Ok(Box::new(UdpConnection::new(
+                parameters.address.resolve()?,
+                parameters.max_datagram_size,
+            )))
+        });
+        factory.register("WebSocket", |parameters| {
+            let parameters: WebSocketParameters = match parameters {
+                serde_json::Value::String(_) => WebSocketParameters {
+                    url: parse_parameters("WebSocket", parameters)?,
+                    tls: None,
+                    token: None,
+                },
+                _ => parse_parameters("WebSocket", parameters)?,
+            };
+            Ok(Box::new(WebSocketConnection::new(&parameters)?))
+        });
+        factory.register("OpenRgb", |parameters| {
+            let parameters = parse_parameters("OpenRgb", parameters)?;
+            Ok(Box::new(OpenRgbConnection::new(parameters)?))
+        });
+        #[cfg(feature = "hue")]
+        factory.register("Hue", |parameters| {
+            let parameters = parse_parameters("Hue", parameters)?;
+            Ok(Box::new(hue::HueConnection::new(parameters)))
+        });
+        #[cfg(not(feature = "hue"))]
+        factory.register("Hue", |_| Err(ConnectionError::FeatureDisabled("hue")));
+        factory.register("Usb", |_| Ok(Box::new(UsbConnection {})));
+        factory.register("Plugin", |parameters| {
+            let parameters = parse_parameters("Plugin", parameters)?;
+            Ok(Box::new(PluginConnection::new(parameters)?))
+        });
+        #[cfg(feature = "rpi")]
+        factory.register("Ws281x", |parameters| {
+            let parameters = parse_parameters("Ws281x", parameters)?;
+            Ok(Box::new(ws281x::Ws281xConnection::new(parameters)?))
+        });
+        #[cfg(not(feature = "rpi"))]
+        factory.register("Ws281x", |_| Err(ConnectionError::FeatureDisabled("rpi")));
+        factory
+    }
+}
+
+/// Address of a device as `host:port`, where the host is an ip, a hostname or the name of a
+/// controller advertised over mDNS (as listed by `turbo_audio browse`). It's resolved when the
+/// connection is created, so a device that changed ip is only found again after a restart.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct DeviceAddress(pub String);
+
+impl DeviceAddress {
+    pub fn resolve(&self) -> Result<SocketAddr, ConnectionError> {
+        Ok(mdns::resolve(&self.0)?)
+    }
+}
+
+fn parse_parameters<T: DeserializeOwned>(
+    kind: &str,
+    parameters: &serde_json::Value,
+) -> Result<T, ConnectionError> {
+    serde_json::from_value(parameters.clone())
+        .map_err(|e| ConnectionError::InvalidParameters(kind.to_owned(), e))
 }
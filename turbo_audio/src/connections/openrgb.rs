@@ -0,0 +1,80 @@
+use super::{tcp::TcpConnection, Connection, ConnectionError, DeviceAddress, LinkStatus};
+use serde::Deserialize;
+
+const CLIENT_NAME: &str = "TurboAudio";
+
+// Packet ids of the OpenRGB SDK protocol
+const SET_CLIENT_NAME: u32 = 50;
+const UPDATE_LEDS: u32 = 1050;
+const SET_CUSTOM_MODE: u32 = 1100;
+
+#[derive(Debug, Deserialize)]
+pub struct OpenRgbParameters {
+    /// Address of the OpenRGB SDK server
+    #[serde(default = "default_address")]
+    pub address: DeviceAddress,
+    /// Index of the device in the OpenRGB device list
+    pub device: u32,
+}
+
+fn default_address() -> DeviceAddress {
+    DeviceAddress("127.0.0.1:6742".to_owned())
+}
+
+/// Drives a device (motherboard, RAM, keyboard...) of an OpenRGB SDK server as if it was a
+/// ledstrip. The ledstrip should have as many leds as the device, in the order OpenRGB lists
+/// them, and its device should keep the raw rgb encoding.
+pub struct OpenRgbConnection {
+    tcp: TcpConnection,
+    device: u32,
+}
+
+impl OpenRgbConnection {
+    pub fn new(parameters: OpenRgbParameters) -> Result<Self, ConnectionError> {
+        // The device has to be in its direct control mode to take colors from the SDK
+        let mut handshake = packet(0, SET_CLIENT_NAME, &[CLIENT_NAME.as_bytes(), &[0]].concat());
+        handshake.extend(packet(parameters.device, SET_CUSTOM_MODE, &[]));
+        Ok(Self {
+            tcp: TcpConnection::with_handshake(parameters.address.resolve()?, handshake),
+            device: parameters.device,
+        })
+    }
+}
+
+impl Connection for OpenRgbConnection {
+    fn send_frame(&mut self, packet: Vec<u8>) -> Result<(), ConnectionError> {
+        self.tcp.send_frame(update_leds(self.device, &packet))
+    }
+
+    fn status(&self) -> LinkStatus {
+        self.tcp.status()
+    }
+
+    fn reconnect(&mut self) {
+        self.tcp.reconnect();
+    }
+}
+
+fn packet(device: u32, id: u32, data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(16 + data.len());
+    packet.extend_from_slice(b"ORGB");
+    packet.extend_from_slice(&device.to_le_bytes());
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+// The colors are sent as r, g, b and a padding byte, after the size of the data and the color count
+fn update_leds(device: u32, rgb: &[u8]) -> Vec<u8> {
+    let color_count = rgb.len() / 3;
+    let data_len = 4 + 2 + color_count * 4;
+    let mut data = Vec::with_capacity(data_len);
+    data.extend_from_slice(&(data_len as u32).to_le_bytes());
+    data.extend_from_slice(&(color_count as u16).to_le_bytes());
+    for color in rgb.chunks_exact(3) {
+        data.extend_from_slice(color);
+        data.push(0);
+    }
+    packet(device, UPDATE_LEDS, &data)
+}
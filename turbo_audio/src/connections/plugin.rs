@@ -0,0 +1,122 @@
+use super::{Connection, ConnectionError, LinkStatus};
+use serde::Deserialize;
+use std::{
+    cell::Cell,
+    ffi::{c_char, c_void, CStr},
+    path::PathBuf,
+};
+use turbo_plugin::connection_plugin::{
+    BackendHealth, ConnectionBackendVTable, CONNECTION_PLUGIN_ABI_VERSION,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginParameters {
+    /// Shared library exporting the backend with `make_connection_backend!`
+    pub library: PathBuf,
+    /// Given as json to the `connect` of the backend
+    #[serde(default)]
+    pub parameters: serde_json::Value,
+}
+
+/// Connection whose transport is a [`turbo_plugin::connection_plugin::ConnectionBackend`] loaded
+/// from a shared library, so that a proprietary protocol doesn't need changes to turbo_audio
+pub struct PluginConnection {
+    backend: *mut c_void,
+    vtable: *const ConnectionBackendVTable,
+    // Whether a health that isn't a BackendHealth was already logged
+    invalid_health: Cell<bool>,
+    // Unloaded after the backend is destroyed
+    _library: libloading::Library,
+}
+
+// The backend is a ConnectionBackend, which is Send, and the vtable only holds function pointers
+unsafe impl Send for PluginConnection {}
+
+impl PluginConnection {
+    pub fn new(parameters: PluginParameters) -> Result<Self, ConnectionError> {
+        let plugin_error =
+            |e: String| ConnectionError::Plugin(parameters.library.display().to_string(), e);
+        unsafe {
+            let library = libloading::Library::new(&parameters.library)
+                .map_err(|e| plugin_error(e.to_string()))?;
+
+            // Checked before anything reads the vtable, whose layout depends on the version
+            let abi_version = library
+                .get::<extern "C" fn() -> u32>(b"_connection_plugin_abi_version")
+                .map_err(|e| plugin_error(e.to_string()))?();
+            if abi_version != CONNECTION_PLUGIN_ABI_VERSION {
+                return Err(plugin_error(format!(
+                    "built for connection plugin abi {abi_version} instead of \
+                     {CONNECTION_PLUGIN_ABI_VERSION}"
+                )));
+            }
+
+            let vtable_fn = library
+                .get::<extern "C" fn() -> *const c_void>(b"_connection_backend_vtable")
+                .map_err(|e| plugin_error(e.to_string()))?;
+            let vtable = vtable_fn() as *const ConnectionBackendVTable;
+
+            let json = parameters.parameters.to_string();
+            let mut error: *mut c_char = std::ptr::null_mut();
+            let backend = ((*vtable).connect)(json.as_ptr(), json.len(), &mut error);
+            if backend.is_null() {
+                return Err(plugin_error(take_error(vtable, error)));
+            }
+            Ok(Self {
+                backend,
+                vtable,
+                invalid_health: Cell::new(false),
+                _library: library,
+            })
+        }
+    }
+}
+
+// Copies the error returned by the backend and frees it
+unsafe fn take_error(vtable: *const ConnectionBackendVTable, error: *mut c_char) -> String {
+    if error.is_null() {
+        return "Unknown error".to_owned();
+    }
+    let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+    ((*vtable).free_error)(error);
+    message
+}
+
+impl Connection for PluginConnection {
+    fn send_frame(&mut self, packet: Vec<u8>) -> Result<(), ConnectionError> {
+        unsafe {
+            let error = ((*self.vtable).send_frame)(self.backend, packet.as_ptr(), packet.len());
+            if error.is_null() {
+                return Ok(());
+            }
+            Err(ConnectionError::Backend(take_error(self.vtable, error)))
+        }
+    }
+
+    fn status(&self) -> LinkStatus {
+        let health = unsafe { ((*self.vtable).health)(self.backend) };
+        match BackendHealth::try_from(health) {
+            Ok(BackendHealth::Connected) => LinkStatus::Connected,
+            Ok(BackendHealth::Connecting) => LinkStatus::Connecting,
+            Ok(BackendHealth::Disconnected) => LinkStatus::Disconnected,
+            // Logged once, as the status is polled every frame
+            Err(health) => {
+                if !self.invalid_health.replace(true) {
+                    tracing::error!("Connection plugin returned the unknown health {health}.");
+                }
+                LinkStatus::Disconnected
+            }
+        }
+    }
+
+    fn reconnect(&mut self) {
+        unsafe { ((*self.vtable).reconnect)(self.backend) }
+    }
+}
+
+impl Drop for PluginConnection {
+    fn drop(&mut self) {
+        unsafe { ((*self.vtable).destroy)(self.backend) }
+        tracing::info!("Plugin connection stopped.");
+    }
+}
@@ -0,0 +1,240 @@
+use super::{Connection, ConnectionError, LinkStatus};
+use ring_channel::{ring_channel, RingReceiver, RingSender};
+use std::{
+    collections::VecDeque,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// Frames held back on a delayed connection. Enough for the longest delay at 60 frames per second
+const DELAY_QUEUE_SIZE: usize = 64;
+/// Longest delay a connection is held back by
+pub const MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Outcome of a frame sent by the sender thread
+pub struct SendReport {
+    pub result: Result<(), ConnectionError>,
+    pub latency: Duration,
+    /// Bytes of the packets of the frame
+    pub bytes: usize,
+    /// Older frames that were due as well, dropped for this one since the connection can't keep
+    /// up
+    pub skipped: u32,
+}
+
+/// How long the frames of a connection are held back before being sent, to line the devices on
+/// fast links up with the ones on slow links
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DispatchDelay {
+    /// Delay set in the config
+    pub fixed: Duration,
+    /// Adds the difference between the latency of the slowest compensated connection and the
+    /// one of this connection
+    pub compensate: bool,
+}
+
+impl DispatchDelay {
+    pub fn is_none(&self) -> bool {
+        self.fixed.is_zero() && !self.compensate
+    }
+}
+
+struct QueuedFrame {
+    packets: Vec<Vec<u8>>,
+    queued_at: Instant,
+}
+
+/// Runs a connection on its own thread, so that a device that is slow to write to doesn't hold
+/// the run loop up. Only the latest frame waits to be sent: a newer one replaces it. The frames
+/// of a delayed connection wait for their delay instead
+pub struct ConnectionSender {
+    frames: Option<RingSender<QueuedFrame>>,
+    reports: mpsc::Receiver<SendReport>,
+    reconnect: Arc<AtomicBool>,
+    status: Arc<Mutex<LinkStatus>>,
+    round_trip: Arc<Mutex<Option<Duration>>>,
+    delay: Arc<Mutex<Duration>>,
+    queued: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ConnectionSender {
+    pub fn new(
+        connection_id: usize,
+        connection: Box<dyn Connection>,
+        delay: DispatchDelay,
+    ) -> Self {
+        let queue_size = match delay.is_none() {
+            true => NonZeroUsize::MIN,
+            false => NonZeroUsize::new(DELAY_QUEUE_SIZE).unwrap(),
+        };
+        let (frames, frames_rx) = ring_channel(queue_size);
+        let (reports_tx, reports) = mpsc::channel();
+        let shared = Shared {
+            reconnect: Arc::default(),
+            status: Arc::new(Mutex::new(connection.status())),
+            round_trip: Arc::default(),
+            delay: Arc::new(Mutex::new(delay.fixed.min(MAX_DELAY))),
+            queued: Arc::default(),
+        };
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("connection-{connection_id}"))
+                .spawn(move || {
+                    send_loop(connection, frames_rx, reports_tx, &shared);
+                })
+                .expect("Couldn't start the connection thread")
+        };
+        Self {
+            frames: Some(frames),
+            reports,
+            reconnect: shared.reconnect,
+            status: shared.status,
+            round_trip: shared.round_trip,
+            delay: shared.delay,
+            queued: shared.queued,
+            thread: Some(thread),
+        }
+    }
+
+    /// Queues the packets of a frame. Returns true when they replaced a frame the thread hadn't
+    /// picked up yet, which is dropped
+    pub fn send(&self, packets: Vec<Vec<u8>>) -> Result<bool, ConnectionError> {
+        let frames = self.frames.as_ref().ok_or(ConnectionError::Closed)?;
+        let frame = QueuedFrame {
+            packets,
+            queued_at: Instant::now(),
+        };
+        // Counted before the thread can pick it up, which uncounts it
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        match frames.send(frame) {
+            Ok(None) => Ok(false),
+            Ok(Some(_)) => {
+                self.queued.fetch_sub(1, Ordering::Relaxed);
+                Ok(true)
+            }
+            Err(_) => {
+                self.queued.fetch_sub(1, Ordering::Relaxed);
+                Err(ConnectionError::Closed)
+            }
+        }
+    }
+
+    /// Frames waiting to be sent. Only the delayed connections keep more than one
+    pub fn queued_frames(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Round trip to the device last measured by the connection, if its protocol has acks
+    pub fn round_trip(&self) -> Option<Duration> {
+        *self.round_trip.lock().unwrap()
+    }
+
+    pub fn delay(&self) -> Duration {
+        *self.delay.lock().unwrap()
+    }
+
+    /// Holds the frames queued from now on back by `delay`, up to [`MAX_DELAY`]. Only delays
+    /// connections created with a [`DispatchDelay`]
+    pub fn set_delay(&self, delay: Duration) {
+        *self.delay.lock().unwrap() = delay.min(MAX_DELAY);
+    }
+
+    /// Reconnects before sending the next frame
+    pub fn reconnect(&self) {
+        self.reconnect.store(true, Ordering::Relaxed);
+    }
+
+    /// Status of the link after the last frame sent
+    pub fn status(&self) -> LinkStatus {
+        *self.status.lock().unwrap()
+    }
+
+    /// Outcomes of the frames sent since the last call
+    pub fn reports(&self) -> mpsc::TryIter<'_, SendReport> {
+        self.reports.try_iter()
+    }
+}
+
+// State shared between the sender and its thread
+#[derive(Clone)]
+struct Shared {
+    reconnect: Arc<AtomicBool>,
+    status: Arc<Mutex<LinkStatus>>,
+    round_trip: Arc<Mutex<Option<Duration>>>,
+    delay: Arc<Mutex<Duration>>,
+    queued: Arc<AtomicUsize>,
+}
+
+fn send_loop(
+    mut connection: Box<dyn Connection>,
+    frames: RingReceiver<QueuedFrame>,
+    reports: mpsc::Sender<SendReport>,
+    shared: &Shared,
+) {
+    let mut queue = VecDeque::new();
+    loop {
+        // Ends once the sender is dropped and the last frame is sent
+        if queue.is_empty() {
+            match frames.recv() {
+                Ok(frame) => queue.push_back(frame),
+                Err(_) => break,
+            }
+        }
+        while let Ok(frame) = frames.try_recv() {
+            queue.push_back(frame);
+        }
+        let delay = *shared.delay.lock().unwrap();
+        let now = Instant::now();
+        let mut skipped = 0;
+        while queue
+            .get(1)
+            .is_some_and(|next: &QueuedFrame| next.queued_at + delay <= now)
+        {
+            queue.pop_front();
+            skipped += 1;
+        }
+        let frame = queue.pop_front().unwrap();
+        shared.queued.fetch_sub(skipped + 1, Ordering::Relaxed);
+        thread::sleep((frame.queued_at + delay).saturating_duration_since(now));
+
+        if shared.reconnect.swap(false, Ordering::Relaxed) {
+            connection.reconnect();
+        }
+        let bytes = frame.packets.iter().map(Vec::len).sum();
+        let send_start = Instant::now();
+        let result = frame
+            .packets
+            .into_iter()
+            .try_for_each(|packet| connection.send_frame(packet));
+        *shared.status.lock().unwrap() = connection.status();
+        *shared.round_trip.lock().unwrap() = connection.round_trip();
+        let report = SendReport {
+            result,
+            latency: send_start.elapsed(),
+            bytes,
+            skipped: skipped as u32,
+        };
+        if reports.send(report).is_err() {
+            break;
+        }
+    }
+}
+
+impl Drop for ConnectionSender {
+    fn drop(&mut self) {
+        // Closing the channel lets the thread send the frames left, like the last one of a fade
+        self.frames.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("The connection thread panicked");
+            }
+        }
+    }
+}
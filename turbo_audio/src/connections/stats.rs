@@ -0,0 +1,185 @@
+use crate::info::ConnectionInfo;
+use arc_swap::ArcSwap;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Health of the sender, as embedded in the frames of the encodings that have room for it.
+///
+/// On the wire it takes 4 bytes: the frames per second sent on the connection, the engine load
+/// in percent (saturating at 255) and the number of frames dropped on the connection since it
+/// was added, as a wrapping big endian u16.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub fps: u8,
+    pub engine_load_percent: u8,
+    pub dropped_frames: u16,
+}
+
+impl FrameStats {
+    pub const LEN: usize = 4;
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        let [dropped_hi, dropped_lo] = self.dropped_frames.to_be_bytes();
+        [self.fps, self.engine_load_percent, dropped_hi, dropped_lo]
+    }
+}
+
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+// Weight of the newest send in the moving average of the latency
+const LATENCY_SMOOTHING: f32 = 0.1;
+
+/// Counts the frames sent, dropped and skipped on a connection
+#[derive(Debug)]
+pub struct ConnectionStats {
+    window_start: Instant,
+    frames_in_window: u32,
+    bytes_in_window: u64,
+    dropped_in_window: u32,
+    fps: u32,
+    bytes_per_second: u64,
+    dropped_per_second: u32,
+    dropped_frames: u64,
+    skipped_frames: u64,
+    // Moving average of the time taken to hand a frame to the connection, in milliseconds
+    latency_ms: Option<f32>,
+    last_error: Option<String>,
+}
+
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        Self {
+            window_start: Instant::now(),
+            frames_in_window: 0,
+            bytes_in_window: 0,
+            dropped_in_window: 0,
+            fps: 0,
+            bytes_per_second: 0,
+            dropped_per_second: 0,
+            dropped_frames: 0,
+            skipped_frames: 0,
+            latency_ms: None,
+            last_error: None,
+        }
+    }
+}
+
+impl ConnectionStats {
+    /// A frame of `bytes` was sent, in `latency`
+    pub fn on_sent(&mut self, latency: Duration, bytes: usize) {
+        let latency_ms = latency.as_secs_f32() * 1000.0;
+        self.latency_ms = Some(match self.latency_ms {
+            Some(average) => average + (latency_ms - average) * LATENCY_SMOOTHING,
+            None => latency_ms,
+        });
+
+        self.frames_in_window += 1;
+        self.bytes_in_window += bytes as u64;
+        self.roll_window();
+    }
+
+    /// Frames that weren't sent, either because the send failed or because the circuit breaker
+    /// is open
+    pub fn on_dropped(&mut self) {
+        self.dropped_frames += 1;
+        self.dropped_in_window += 1;
+        self.roll_window();
+    }
+
+    // Turns the counts of the window into rates once it's over
+    fn roll_window(&mut self) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < FPS_WINDOW {
+            return;
+        }
+        self.fps = per_second(self.frames_in_window as u64, elapsed) as u32;
+        self.bytes_per_second = per_second(self.bytes_in_window, elapsed);
+        self.dropped_per_second = per_second(self.dropped_in_window as u64, elapsed) as u32;
+        self.frames_in_window = 0;
+        self.bytes_in_window = 0;
+        self.dropped_in_window = 0;
+        self.window_start = Instant::now();
+    }
+
+    // Rate of a count of the window, or the rate of the last window while this one isn't over.
+    // The window is also rolled when read, so that a connection that stopped sending goes down to
+    // 0 instead of keeping the rate of its last frames
+    fn rate(&self, last_rate: u64, count_in_window: u64) -> u64 {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < FPS_WINDOW {
+            return last_rate;
+        }
+        // A frame sent after the first second of the window would have rolled it, so nothing was
+        // sent during the last second
+        if elapsed >= 2 * FPS_WINDOW {
+            return 0;
+        }
+        per_second(count_in_window, elapsed)
+    }
+
+    /// A frame identical to the last one wasn't sent, on a connection skipping those
+    pub fn on_skipped(&mut self) {
+        self.skipped_frames += 1;
+    }
+
+    /// The send failed with `error`
+    pub fn on_failed(&mut self, error: String) {
+        self.on_dropped();
+        self.last_error = Some(error);
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.rate(self.fps as u64, self.frames_in_window as u64) as u32
+    }
+
+    /// Bytes handed to the connection during the last second, its packets framing included
+    pub fn bytes_per_second(&self) -> u64 {
+        self.rate(self.bytes_per_second, self.bytes_in_window)
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    /// Frames dropped during the last second
+    pub fn dropped_per_second(&self) -> u32 {
+        self.rate(
+            self.dropped_per_second as u64,
+            self.dropped_in_window as u64,
+        ) as u32
+    }
+
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped_frames
+    }
+
+    pub fn latency_ms(&self) -> Option<f32> {
+        self.latency_ms
+    }
+
+    /// Starts the latency average over, after the connection was restarted
+    pub fn reset_latency(&mut self) {
+        self.latency_ms = None;
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    pub fn frame_stats(&self, engine_load: f32) -> FrameStats {
+        FrameStats {
+            fps: self.fps().min(u8::MAX as u32) as u8,
+            engine_load_percent: (engine_load * 100.0).round().clamp(0.0, 255.0) as u8,
+            dropped_frames: self.dropped_frames as u16,
+        }
+    }
+}
+
+fn per_second(count: u64, elapsed: Duration) -> u64 {
+    (count as f32 / elapsed.as_secs_f32()).round() as u64
+}
+
+/// State of every connection as of the last frame sent, stored by the controller and read by the
+/// effects showing it on the leds
+pub type SharedConnectionInfo = Arc<ArcSwap<Vec<ConnectionInfo>>>;
@@ -1,4 +1,9 @@
+use super::{
+    tls::{Stream, TlsConnector, TlsParameters},
+    Connection, ConnectionError, DeviceAddress, LinkStatus,
+};
 use ring_channel::*;
+use serde::Deserialize;
 use std::{
     io::Write,
     net::TcpStream,
@@ -8,10 +13,46 @@ use std::{
     time::Duration,
 };
 
+// Starts the packet giving the token of a device, before its length (u16, big endian) and the token
+pub(crate) const AUTH_MAGIC: &[u8] = b"TAUTH";
+
+/// Parameters of a tcp connection, also written as just the address like `"192.168.1.50:7777"`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpParameters {
+    pub address: DeviceAddress,
+    /// Encrypts the connection
+    #[serde(default)]
+    pub tls: Option<TlsParameters>,
+    /// Shared secret sent first on every connection, so that the device can ignore the senders
+    /// that don't know it. Only secret on an encrypted connection
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl TcpParameters {
+    /// Packets sent first on every connection, giving the token
+    pub fn handshake(&self) -> Vec<u8> {
+        let Some(token) = &self.token else {
+            return Vec::new();
+        };
+        let mut packet = AUTH_MAGIC.to_vec();
+        packet.extend_from_slice(&(token.len() as u16).to_be_bytes());
+        packet.extend_from_slice(token.as_bytes());
+        packet
+    }
+}
+
 pub struct TcpConnection {
+    ip: std::net::SocketAddr,
+    // Sent first every time the connection is established
+    handshake: Arc<[u8]>,
+    // Encrypts the connection if set
+    tls: Option<TlsConnector>,
     data_queue: Option<ring_channel::RingSender<Vec<u8>>>,
     connection_thread: Option<JoinHandle<Result<(), TcpConnectionError>>>,
     should_quit: Arc<Mutex<bool>>,
+    status: Arc<Mutex<LinkStatus>>,
 }
 
 #[allow(dead_code)]
@@ -29,22 +70,61 @@ enum ConnectionAttemptError {
 
 impl TcpConnection {
     pub fn new(ip: std::net::SocketAddr) -> Self {
+        Self::with_handshake(ip, Vec::new())
+    }
+
+    /// Connection that sends `handshake` before any frame, again after every reconnection, for
+    /// the protocols that need some setup
+    pub fn with_handshake(ip: std::net::SocketAddr, handshake: Vec<u8>) -> Self {
+        Self::with_tls(ip, handshake, None)
+    }
+
+    /// Connection encrypted by `tls` if set, sending `handshake` once the session is established
+    pub fn with_tls(
+        ip: std::net::SocketAddr,
+        handshake: Vec<u8>,
+        tls: Option<TlsConnector>,
+    ) -> Self {
+        let handshake: Arc<[u8]> = handshake.into();
         let should_quit: Arc<Mutex<bool>> = Arc::default();
-        let (tx, handle) = TcpConnection::start_connection_thread(ip, should_quit.clone());
+        let status = Arc::new(Mutex::new(LinkStatus::Connecting));
+        let (tx, handle) = TcpConnection::start_connection_thread(
+            ip,
+            handshake.clone(),
+            tls.clone(),
+            should_quit.clone(),
+            status.clone(),
+        );
         Self {
+            ip,
+            handshake,
+            tls,
             data_queue: Some(tx),
             connection_thread: handle.into(),
             should_quit,
+            status,
         }
     }
 
-    pub fn send_data(&mut self, packet: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
-        self.data_queue.as_mut().unwrap().send(packet).map(|_| ())
+    fn stop(&mut self) {
+        {
+            let mut should_quit = self.should_quit.lock().unwrap();
+            *should_quit = true;
+        }
+        self.data_queue.take();
+        if let Some(connection_thread) = self.connection_thread.take() {
+            if let Err(e) = connection_thread.join() {
+                tracing::error!("Error in connection thread {:?}", e);
+            }
+        }
     }
 
     fn start_connection_thread(
         ip: std::net::SocketAddr,
+        handshake: Arc<[u8]>,
+        tls: Option<TlsConnector>,
         should_quit: Arc<Mutex<bool>>,
+        status: Arc<Mutex<LinkStatus>>,
     ) -> (
         ring_channel::RingSender<Vec<u8>>,
         JoinHandle<Result<(), TcpConnectionError>>,
@@ -52,47 +132,88 @@ impl TcpConnection {
         let buffer_size: NonZeroUsize = NonZeroUsize::new(64).unwrap();
         let (tx, rx) = ring_channel::<Vec<u8>>(buffer_size);
         let connection_thread = thread::spawn(move || -> Result<(), TcpConnectionError> {
-            let mut disconnect_error = None;
-            // This loop essures we keep reconnecting if possible
-            loop {
-                let connection_result =
-                    TcpConnection::attempt_connection(ip, should_quit.clone(), None, None);
-                if let Err(ConnectionAttemptError::EarlyQuit) = connection_result {
-                    log::info!("Closing Tcp Connection Thread because of an early quit while trying to connect");
+            let _span = tracing::info_span!("tcp_connection", %ip).entered();
+            let result = TcpConnection::connection_loop(
+                ip,
+                &handshake,
+                tls.as_ref(),
+                should_quit,
+                &status,
+                rx,
+            );
+            *status.lock().unwrap() = LinkStatus::Disconnected;
+            result
+        });
+
+        (tx, connection_thread)
+    }
+
+    fn connection_loop(
+        ip: std::net::SocketAddr,
+        handshake: &[u8],
+        tls: Option<&TlsConnector>,
+        should_quit: Arc<Mutex<bool>>,
+        status: &Mutex<LinkStatus>,
+        rx: RingReceiver<Vec<u8>>,
+    ) -> Result<(), TcpConnectionError> {
+        let mut disconnect_error = None;
+        // This loop essures we keep reconnecting if possible
+        loop {
+            let connection_result =
+                TcpConnection::attempt_connection(ip, should_quit.clone(), None, None);
+            if let Err(ConnectionAttemptError::EarlyQuit) = connection_result {
+                tracing::info!("Closing Tcp Connection Thread because of an early quit while trying to connect");
+            }
+            let stream = connection_result.map_err(|attempt_error| {
+                match disconnect_error {
+                    Some(disconnect_error) => {
+                        // This error comes from the last disconnect
+                        TcpConnectionError::UnableToReconnect(attempt_error, disconnect_error)
+                    }
+                    None => TcpConnectionError::ConnectionFailed(attempt_error),
                 }
-                let mut connection = connection_result.map_err(|attempt_error| {
-                    match disconnect_error {
-                        Some(disconnect_error) => {
-                            // This error comes from the last disconnect
-                            TcpConnectionError::UnableToReconnect(attempt_error, disconnect_error)
+            })?;
+            let mut connection: Box<dyn Stream> = match tls {
+                // A device failing the handshake, like with the wrong certificate, won't pass it
+                // on the next attempt
+                Some(tls) => tls.wrap(stream).map_err(|e| {
+                    tracing::warn!("The tls handshake with {ip} failed: {e}");
+                    TcpConnectionError::ConnectionFailed(
+                        ConnectionAttemptError::ConfigurationFailed(e),
+                    )
+                })?,
+                None => Box::new(stream),
+            };
+            if let Err(e) = connection.write_all(handshake) {
+                tracing::info!(
+                    "Lost connection with {ip} during the handshake. Will attempt to reconnect."
+                );
+                disconnect_error = Some(e);
+                continue;
+            }
+            *status.lock().unwrap() = LinkStatus::Connected;
+
+            // This loop sends the packets in data_queue through the TCP socket
+            loop {
+                match rx.recv() {
+                    Ok(data) => {
+                        if let Err(e) = connection.write_all(&data) {
+                            disconnect_error = Some(e);
+                            *status.lock().unwrap() = LinkStatus::Connecting;
+                            // We break from this loop to allow reconnection to happen
+                            tracing::info!("Lost connection with {ip}. Will attempt to reconnect.");
+                            break;
                         }
-                        None => TcpConnectionError::ConnectionFailed(attempt_error),
                     }
-                })?;
-
-                // This loop sends the packets in data_queue through the TCP socket
-                loop {
-                    match rx.recv() {
-                        Ok(data) => {
-                            if let Err(e) = connection.write_all(&data) {
-                                disconnect_error = Some(e);
-                                // We break from this loop to allow reconnection to happen
-                                log::info!("Lost connection with {ip}. Will attempt to reconnect.");
-                                break;
-                            }
-                        }
-                        // If an error occurs, the data_queue has no more sender
-                        // and meaning the thread can exit correctly
-                        Err(_) => {
-                            log::info!("Closing connection with {ip}.");
-                            return Ok(());
-                        }
+                    // If an error occurs, the data_queue has no more sender
+                    // and meaning the thread can exit correctly
+                    Err(_) => {
+                        tracing::info!("Closing connection with {ip}.");
+                        return Ok(());
                     }
                 }
             }
-        });
-
-        (tx, connection_thread)
+        }
     }
 
     fn attempt_connection(
@@ -107,19 +228,19 @@ impl TcpConnection {
             {
                 let should_quit = should_quit.lock().unwrap();
                 if *should_quit {
-                    log::info!("Stopping connection attempts to {ip}");
+                    tracing::info!("Stopping connection attempts to {ip}");
                     return Err(ConnectionAttemptError::EarlyQuit);
                 }
             }
             // Ici on doit pouvoir skur
             let stream = TcpStream::connect_timeout(&ip, connection_timeout);
-            log::info!("[{i}/{max_connection_attempts}] Attempting to connect to {ip}");
+            tracing::info!("[{i}/{max_connection_attempts}] Attempting to connect to {ip}");
             match stream {
                 Ok(stream) => {
                     stream
                         .set_write_timeout(Some(Duration::from_millis(100)))
                         .map_err(ConnectionAttemptError::ConfigurationFailed)?;
-                    log::info!("Connected to {ip}");
+                    tracing::info!("Connected to {ip}");
                     return Ok(stream);
                 }
                 Err(_) => continue,
@@ -129,17 +250,44 @@ impl TcpConnection {
     }
 }
 
+impl Connection for TcpConnection {
+    fn send_frame(&mut self, packet: Vec<u8>) -> Result<(), ConnectionError> {
+        self.data_queue
+            .as_mut()
+            .unwrap()
+            .send(packet)
+            .map(|_| ())
+            .map_err(|_| ConnectionError::Closed)
+    }
+
+    fn status(&self) -> LinkStatus {
+        *self.status.lock().unwrap()
+    }
+
+    /// Restarts the connection thread. Used to try again after the thread gave up on reconnecting.
+    fn reconnect(&mut self) {
+        self.stop();
+        tracing::info!("Reconnecting to {}", self.ip);
+        let should_quit: Arc<Mutex<bool>> = Arc::default();
+        let status = Arc::new(Mutex::new(LinkStatus::Connecting));
+        let (tx, handle) = TcpConnection::start_connection_thread(
+            self.ip,
+            self.handshake.clone(),
+            self.tls.clone(),
+            should_quit.clone(),
+            status.clone(),
+        );
+        self.data_queue = Some(tx);
+        self.connection_thread = Some(handle);
+        self.should_quit = should_quit;
+        self.status = status;
+    }
+}
+
 impl Drop for TcpConnection {
     fn drop(&mut self) {
-        log::info!("Closing tcp connection");
-        {
-            let mut should_quit = self.should_quit.lock().unwrap();
-            *should_quit = true;
-        }
-        self.data_queue.take();
-        if let Err(e) = self.connection_thread.take().unwrap().join() {
-            log::error!("Error in connection thread {:?}", e);
-        }
-        log::info!("Tcp connection thread joined.");
+        tracing::info!("Closing tcp connection");
+        self.stop();
+        tracing::info!("Tcp connection thread joined.");
     }
 }
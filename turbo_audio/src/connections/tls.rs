@@ -0,0 +1,121 @@
+use super::ConnectionError;
+use serde::Deserialize;
+use std::{
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+/// Encrypts a connection to a device reachable over a network that isn't trusted, checking its
+/// certificate. Needs turbo_audio to be built with the `tls` feature
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsParameters {
+    /// Pem file of the certificate authorities trusted on top of the usual public ones, like the
+    /// one that signed the certificate of the device
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+    /// Name the certificate of the device has to be for. The host of its address if missing
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
+impl TlsParameters {
+    /// Connector for the device at `host`
+    pub fn connector(&self, host: &str) -> Result<TlsConnector, ConnectionError> {
+        #[cfg(feature = "tls")]
+        return TlsConnector::new(self, host);
+        #[cfg(not(feature = "tls"))]
+        {
+            let _ = host;
+            Err(ConnectionError::FeatureDisabled("tls"))
+        }
+    }
+}
+
+/// Byte stream to a device, encrypted or not
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Host of a `host:port` address, without the brackets of an ipv6
+pub fn host_of(address: &str) -> &str {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(feature = "tls")]
+pub use connector::TlsConnector;
+
+/// Stands for the connector in the builds without tls, where it can't be created
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+pub enum TlsConnector {}
+
+#[cfg(not(feature = "tls"))]
+impl TlsConnector {
+    pub fn wrap(&self, _stream: std::net::TcpStream) -> std::io::Result<Box<dyn Stream>> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "tls")]
+mod connector {
+    use super::{Stream, TlsParameters};
+    use crate::connections::ConnectionError;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+    use rustls_pki_types::{pem::PemObject, CertificateDer, ServerName};
+    use std::{io, net::TcpStream, sync::Arc, time::Duration};
+
+    // Longest the device takes to answer during the handshake
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+
+    /// Starts the tls session of the connections to a device
+    #[derive(Clone)]
+    pub struct TlsConnector {
+        config: Arc<ClientConfig>,
+        server_name: ServerName<'static>,
+    }
+
+    impl TlsConnector {
+        pub fn new(parameters: &TlsParameters, host: &str) -> Result<Self, ConnectionError> {
+            let tls_error = |e: &dyn std::fmt::Display| ConnectionError::Tls(e.to_string());
+            let mut roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            if let Some(ca_file) = &parameters.ca_file {
+                let certificates = CertificateDer::pem_file_iter(ca_file)
+                    .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+                    .map_err(|e| {
+                        ConnectionError::Tls(format!("Couldn't read {}: {e}", ca_file.display()))
+                    })?;
+                for certificate in certificates {
+                    roots.add(certificate).map_err(|e| tls_error(&e))?;
+                }
+            }
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let config = ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .map_err(|e| tls_error(&e))?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            let server_name = parameters.server_name.as_deref().unwrap_or(host).to_owned();
+            let server_name = ServerName::try_from(server_name).map_err(|e| tls_error(&e))?;
+            Ok(Self {
+                config: Arc::new(config),
+                server_name,
+            })
+        }
+
+        /// Encrypts a stream, once the handshake with the device succeeded
+        pub fn wrap(&self, mut stream: TcpStream) -> io::Result<Box<dyn Stream>> {
+            let mut connection =
+                ClientConnection::new(self.config.clone(), self.server_name.clone())
+                    .map_err(io::Error::other)?;
+            stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+            while connection.is_handshaking() {
+                connection.complete_io(&mut stream)?;
+            }
+            Ok(Box::new(StreamOwned::new(connection, stream)))
+        }
+    }
+}
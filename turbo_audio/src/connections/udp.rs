@@ -0,0 +1,75 @@
+use super::{Connection, ConnectionError, DeviceAddress, LinkStatus};
+use serde::Deserialize;
+use std::{
+    net::{SocketAddr, UdpSocket},
+    num::NonZeroUsize,
+};
+
+/// Parameters of a udp connection, also written as just the address like `"192.168.1.50:7777"`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UdpParameters {
+    pub address: DeviceAddress,
+    /// Longest datagram sent. Longer packets are split in consecutive datagrams, so that they fit
+    /// in the MTU of the network or the buffer of a microcontroller. A multiple of 3 keeps the
+    /// leds of the raw rgb encoding whole
+    #[serde(default)]
+    pub max_datagram_size: Option<NonZeroUsize>,
+}
+
+/// Sends every packet as a datagram, for the realtime protocols of WLED, DDP and sACN, or the
+/// raw bytes of the tcp connections to the controllers on a flaky wifi, which would rather lose
+/// a frame than wait for it to be sent again
+pub struct UdpConnection {
+    address: SocketAddr,
+    socket: Option<UdpSocket>,
+    // Packets are split in datagrams of this size. Sent whole if None
+    max_datagram_size: Option<NonZeroUsize>,
+}
+
+impl UdpConnection {
+    /// Connection splitting the packets longer than `max_datagram_size` in several datagrams
+    pub fn new(address: SocketAddr, max_datagram_size: Option<NonZeroUsize>) -> Self {
+        let mut connection = Self {
+            address,
+            socket: None,
+            max_datagram_size,
+        };
+        connection.reconnect();
+        connection
+    }
+}
+
+impl Connection for UdpConnection {
+    fn send_frame(&mut self, packet: Vec<u8>) -> Result<(), ConnectionError> {
+        let socket = self.socket.as_ref().ok_or(ConnectionError::Closed)?;
+        let Some(max_datagram_size) = self.max_datagram_size else {
+            socket.send(&packet)?;
+            return Ok(());
+        };
+        for datagram in packet.chunks(max_datagram_size.get()) {
+            socket.send(datagram)?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> LinkStatus {
+        // There is no handshake, so an open socket is as connected as it gets
+        match self.socket {
+            Some(_) => LinkStatus::Connected,
+            None => LinkStatus::Disconnected,
+        }
+    }
+
+    fn reconnect(&mut self) {
+        let bind_address: SocketAddr = if self.address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        self.socket = UdpSocket::bind(bind_address)
+            .and_then(|socket| socket.connect(self.address).map(|_| socket))
+            .map_err(|e| tracing::warn!("Couldn't open a udp socket to {}: {e}", self.address))
+            .ok();
+    }
+}
@@ -1 +1,15 @@
+use super::{Connection, ConnectionError, LinkStatus};
+
 pub struct UsbConnection {}
+
+impl Connection for UsbConnection {
+    fn send_frame(&mut self, _packet: Vec<u8>) -> Result<(), ConnectionError> {
+        Err(ConnectionError::Unimplemented("Usb"))
+    }
+
+    fn status(&self) -> LinkStatus {
+        LinkStatus::Disconnected
+    }
+
+    fn reconnect(&mut self) {}
+}
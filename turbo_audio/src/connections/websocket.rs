@@ -0,0 +1,385 @@
+use super::{
+    tls::{Stream, TlsConnector, TlsParameters},
+    Connection, ConnectionError, DeviceAddress, LinkStatus,
+};
+use ring_channel::{ring_channel, RingReceiver, RingSender};
+use serde::Deserialize;
+use std::{
+    io,
+    net::{SocketAddr, TcpStream},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use tungstenite::{
+    client::IntoClientRequest,
+    handshake::HandshakeError,
+    http::{header::AUTHORIZATION, HeaderValue},
+    protocol::WebSocketConfig,
+    Message, WebSocket,
+};
+
+// Packets waiting to be sent. The oldest are dropped while the server doesn't keep up
+const QUEUE_SIZE: usize = 4;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+// Bytes of unsent messages after which the packets are dropped, when the server doesn't read
+// them fast enough
+const MAX_WRITE_BUFFER_SIZE: usize = 1 << 20;
+// Time between two pings measuring the round trip, and after which a ping is given up on
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+// Weight of the newest measure in the moving average of the round trip
+const ROUND_TRIP_SMOOTHING: f32 = 0.2;
+
+/// Parameters of a websocket connection, also written as just the url like `"ws://host:8080"`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebSocketParameters {
+    pub url: String,
+    /// Checks the certificate of a `wss://` server against other certificate authorities
+    #[serde(default)]
+    pub tls: Option<TlsParameters>,
+    /// Shared secret sent as a bearer token when connecting, so that the server can refuse the
+    /// senders that don't know it. Only secret on a `wss://` url
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Pushes every packet as a binary message to a websocket server, like a simulator in a browser
+/// or a relay to the cloud. Connects to `ws://` urls, and to `wss://` ones when built with the
+/// `tls` feature. Reconnects until the connection is dropped
+pub struct WebSocketConnection {
+    url: String,
+    address: SocketAddr,
+    tls: Option<TlsConnector>,
+    authorization: Option<HeaderValue>,
+    queue: Option<RingSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+    status: Arc<Mutex<LinkStatus>>,
+    round_trip: Arc<Mutex<Option<Duration>>>,
+}
+
+impl WebSocketConnection {
+    pub fn new(parameters: &WebSocketParameters) -> Result<Self, ConnectionError> {
+        let url = parameters.url.as_str();
+        let invalid = |reason: String| ConnectionError::InvalidUrl(url.to_owned(), reason);
+        let request = url
+            .into_client_request()
+            .map_err(|e| invalid(e.to_string()))?;
+        let uri = request.uri();
+        let (secure, default_port) = match uri.scheme_str() {
+            Some("ws") if parameters.tls.is_some() => {
+                return Err(invalid("tls needs a wss:// url".to_owned()))
+            }
+            Some("ws") => (false, 80),
+            Some("wss") => (true, 443),
+            _ => {
+                return Err(invalid(
+                    "only ws:// and wss:// urls are supported".to_owned(),
+                ))
+            }
+        };
+        let host = uri
+            .host()
+            .ok_or_else(|| invalid("it has no host".to_owned()))?;
+        let address = DeviceAddress(format!("{host}:{}", uri.port_u16().unwrap_or(default_port)))
+            .resolve()?;
+        let tls = match secure {
+            true => Some(
+                parameters
+                    .tls
+                    .clone()
+                    .unwrap_or_default()
+                    .connector(host.trim_start_matches('[').trim_end_matches(']'))?,
+            ),
+            false => None,
+        };
+        let authorization = parameters
+            .token
+            .as_ref()
+            .map(|token| HeaderValue::try_from(format!("Bearer {token}")))
+            .transpose()
+            .map_err(|_| invalid("the token isn't a valid header".to_owned()))?;
+        if authorization.is_some() && !secure {
+            tracing::warn!(
+                "The token for {url} is sent in clear, anyone on the network can read and replay \
+                 it. Use a wss:// url to encrypt the connection."
+            );
+        }
+
+        let mut connection = Self {
+            url: url.to_owned(),
+            address,
+            tls,
+            authorization,
+            queue: None,
+            thread: None,
+            should_quit: Arc::default(),
+            status: Arc::new(Mutex::new(LinkStatus::Connecting)),
+            round_trip: Arc::default(),
+        };
+        connection.start();
+        Ok(connection)
+    }
+
+    fn start(&mut self) {
+        let (tx, rx) = ring_channel(NonZeroUsize::new(QUEUE_SIZE).unwrap());
+        self.should_quit = Arc::default();
+        self.status = Arc::new(Mutex::new(LinkStatus::Connecting));
+        let target = Target {
+            url: self.url.clone(),
+            address: self.address,
+            tls: self.tls.clone(),
+            authorization: self.authorization.clone(),
+        };
+        let should_quit = self.should_quit.clone();
+        let status = self.status.clone();
+        let round_trip = self.round_trip.clone();
+        self.thread = Some(thread::spawn(move || {
+            let _span = tracing::info_span!("websocket_connection", url = %target.url).entered();
+            send_loop(&target, &should_quit, &status, &round_trip, rx);
+            *status.lock().unwrap() = LinkStatus::Disconnected;
+        }));
+        self.queue = Some(tx);
+    }
+
+    fn stop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        // Wakes the thread up if it's waiting for a packet
+        self.queue.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("The websocket connection thread panicked");
+            }
+        }
+    }
+}
+
+// Server the connection thread connects to
+struct Target {
+    url: String,
+    address: SocketAddr,
+    tls: Option<TlsConnector>,
+    authorization: Option<HeaderValue>,
+}
+
+// Pings the server to measure the round trip. Its pongs are only read after sending a packet, so
+// the pong is taken to arrive halfway between the last read that didn't find it and the one that
+// did
+#[derive(Default)]
+struct Probe {
+    sequence: u64,
+    // Payload of the ping waiting for its pong, and when it was sent
+    pending: Option<(u64, Instant)>,
+    sent_at: Option<Instant>,
+    // When the reads last found no pong
+    checked_at: Option<Instant>,
+}
+
+impl Probe {
+    fn ping(&mut self, socket: &mut WebSocket<Box<dyn Stream>>) -> Result<(), tungstenite::Error> {
+        if self
+            .pending
+            .is_some_and(|(_, sent_at)| sent_at.elapsed() < PING_TIMEOUT)
+            || self
+                .sent_at
+                .is_some_and(|sent_at| sent_at.elapsed() < PING_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        let now = Instant::now();
+        self.pending = Some((self.sequence, now));
+        self.sent_at = Some(now);
+        self.checked_at = Some(now);
+        write(
+            socket,
+            Message::Ping(self.sequence.to_be_bytes().to_vec().into()),
+        )
+    }
+
+    // Round trip of the ping answered by `payload`
+    fn on_pong(&mut self, payload: &[u8]) -> Option<Duration> {
+        let (sequence, sent_at) = self.pending?;
+        if payload != sequence.to_be_bytes() {
+            return None;
+        }
+        self.pending = None;
+        let checked_at = self.checked_at.unwrap_or(sent_at);
+        Some((checked_at.duration_since(sent_at) + sent_at.elapsed()) / 2)
+    }
+
+    fn on_checked(&mut self) {
+        if self.pending.is_some() {
+            self.checked_at = Some(Instant::now());
+        }
+    }
+}
+
+// Sends the packets of the queue, connecting again whenever the connection is lost. Returns once
+// the queue is dropped
+fn send_loop(
+    target: &Target,
+    should_quit: &AtomicBool,
+    status: &Mutex<LinkStatus>,
+    round_trip: &Mutex<Option<Duration>>,
+    rx: RingReceiver<Vec<u8>>,
+) {
+    let url = &target.url;
+    // Only the first of the failed attempts in a row is a warning
+    let mut failed = false;
+    while !should_quit.load(Ordering::Relaxed) {
+        let mut socket = match connect(target) {
+            Ok(socket) => socket,
+            Err(e) if failed => {
+                tracing::debug!("Couldn't connect to {url}: {e}");
+                thread::sleep(RECONNECT_DELAY);
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!("Couldn't connect to {url}, will keep trying: {e}");
+                failed = true;
+                thread::sleep(RECONNECT_DELAY);
+                continue;
+            }
+        };
+        tracing::info!("Connected to {url}");
+        failed = false;
+        *status.lock().unwrap() = LinkStatus::Connected;
+
+        let mut probe = Probe::default();
+        loop {
+            let Ok(packet) = rx.recv() else {
+                tracing::info!("Closing the connection with {url}.");
+                let _ = socket.close(None);
+                let _ = socket.flush();
+                return;
+            };
+            if let Err(e) = send(&mut socket, packet, &mut probe, round_trip) {
+                tracing::info!("Lost the connection with {url}. Will attempt to reconnect: {e}");
+                *status.lock().unwrap() = LinkStatus::Connecting;
+                break;
+            }
+        }
+    }
+}
+
+fn connect(target: &Target) -> Result<WebSocket<Box<dyn Stream>>, tungstenite::Error> {
+    let tcp = TcpStream::connect_timeout(&target.address, CONNECT_TIMEOUT)?;
+    tcp.set_nodelay(true)?;
+    tcp.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+    tcp.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    // Kept to change the socket under the tls session once connected
+    let handle = tcp.try_clone()?;
+    let stream: Box<dyn Stream> = match &target.tls {
+        Some(tls) => tls.wrap(tcp)?,
+        None => Box::new(tcp),
+    };
+    let mut request = target.url.as_str().into_client_request()?;
+    if let Some(authorization) = &target.authorization {
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, authorization.clone());
+    }
+    // Every message is written as soon as it's sent
+    let config = WebSocketConfig::default()
+        .write_buffer_size(0)
+        .max_write_buffer_size(MAX_WRITE_BUFFER_SIZE);
+    let (socket, _) = tungstenite::client::client_with_config(request, stream, Some(config))
+        .map_err(|e| match e {
+            HandshakeError::Failure(e) => e,
+            HandshakeError::Interrupted(_) => io::Error::from(io::ErrorKind::TimedOut).into(),
+        })?;
+    // Reading the messages of the server, like its pings, mustn't wait for them
+    handle.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+fn would_block(e: &tungstenite::Error) -> bool {
+    matches!(e, tungstenite::Error::Io(e) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+fn write(
+    socket: &mut WebSocket<Box<dyn Stream>>,
+    message: Message,
+) -> Result<(), tungstenite::Error> {
+    match socket.send(message) {
+        // Left in the write buffer, which is sent with the next packet
+        Err(e) if would_block(&e) => Ok(()),
+        // The packet is dropped until the server reads what was sent
+        Err(tungstenite::Error::WriteBufferFull(_)) => Ok(()),
+        result => result,
+    }
+}
+
+// Sends a packet, then answers the pings of the server, reads the pongs of the probe and drops
+// the rest of what it sent
+fn send(
+    socket: &mut WebSocket<Box<dyn Stream>>,
+    packet: Vec<u8>,
+    probe: &mut Probe,
+    round_trip: &Mutex<Option<Duration>>,
+) -> Result<(), tungstenite::Error> {
+    write(socket, Message::Binary(packet.into()))?;
+    probe.ping(socket)?;
+    loop {
+        match socket.read() {
+            Ok(Message::Pong(payload)) => {
+                let Some(measured) = probe.on_pong(&payload) else {
+                    continue;
+                };
+                let mut round_trip = round_trip.lock().unwrap();
+                *round_trip = Some(match *round_trip {
+                    Some(average) => {
+                        average.mul_f32(1.0 - ROUND_TRIP_SMOOTHING)
+                            + measured.mul_f32(ROUND_TRIP_SMOOTHING)
+                    }
+                    None => measured,
+                });
+            }
+            Ok(_) => {}
+            Err(e) if would_block(&e) => {
+                probe.on_checked();
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl Connection for WebSocketConnection {
+    fn send_frame(&mut self, packet: Vec<u8>) -> Result<(), ConnectionError> {
+        self.queue
+            .as_mut()
+            .ok_or(ConnectionError::Closed)?
+            .send(packet)
+            .map(|_| ())
+            .map_err(|_| ConnectionError::Closed)
+    }
+
+    fn status(&self) -> LinkStatus {
+        *self.status.lock().unwrap()
+    }
+
+    fn reconnect(&mut self) {
+        self.stop();
+        tracing::info!("Reconnecting to {}", self.url);
+        self.start();
+    }
+
+    fn round_trip(&self) -> Option<Duration> {
+        *self.round_trip.lock().unwrap()
+    }
+}
+
+impl Drop for WebSocketConnection {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
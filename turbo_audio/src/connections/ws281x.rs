@@ -0,0 +1,182 @@
+use super::{Connection, ConnectionError, LinkStatus};
+use crate::simd;
+use ring_channel::*;
+use serde::Deserialize;
+use spidev::{SpiModeFlags, Spidev, SpidevOptions};
+use std::{
+    io::Write,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+// Each bit of the leds takes 3 bits of spi (100 for 0 and 110 for 1), so 2.4MHz of spi gives the
+// 800kHz the leds expect
+const SPI_SPEED_HZ: u32 = 2_400_000;
+// The line has to stay low for more than 280us for the leds to latch the frame
+const RESET_LEN: usize = 96;
+// Spi bytes of every channel value, looked up rather than built bit by bit on every frame
+const SPI_BYTES: [[u8; 3]; 256] = spi_bytes();
+
+const fn spi_bytes() -> [[u8; 3]; 256] {
+    let mut table = [[0; 3]; 256];
+    let mut value = 0;
+    while value < 256 {
+        let mut bits = 0u32;
+        let mut index = 8;
+        while index > 0 {
+            index -= 1;
+            let pattern = if value >> index & 1 == 1 {
+                0b110
+            } else {
+                0b100
+            };
+            bits = bits << 3 | pattern;
+        }
+        table[value] = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        value += 1;
+    }
+    table
+}
+
+/// Order in which the leds expect the channels
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub enum ColorOrder {
+    Rgb,
+    #[default]
+    Grb,
+    Brg,
+}
+
+impl ColorOrder {
+    // Rgb channel sent first, second and third
+    fn channels(self) -> [usize; 3] {
+        match self {
+            ColorOrder::Rgb => [0, 1, 2],
+            ColorOrder::Grb => [1, 0, 2],
+            ColorOrder::Brg => [2, 0, 1],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Ws281xParameters {
+    /// Spi device the data line of the strip is wired to
+    #[serde(default = "default_device")]
+    pub device: PathBuf,
+    #[serde(default)]
+    pub color_order: ColorOrder,
+}
+
+fn default_device() -> PathBuf {
+    PathBuf::from("/dev/spidev0.0")
+}
+
+/// Drives a WS2811/WS2812 strip wired to the spi MOSI pin (GPIO 10 on a Raspberry Pi), without
+/// any network device. Strips of more than about 450 leds need a bigger spi buffer than the
+/// default 4096 bytes (`spidev.bufsiz=65536` on the kernel command line), or the frames are cut.
+pub struct Ws281xConnection {
+    parameters: Ws281xParameters,
+    data_queue: Option<RingSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    status: Arc<Mutex<LinkStatus>>,
+}
+
+impl Ws281xConnection {
+    pub fn new(parameters: Ws281xParameters) -> Result<Self, ConnectionError> {
+        let mut connection = Self {
+            parameters,
+            data_queue: None,
+            thread: None,
+            status: Arc::new(Mutex::new(LinkStatus::Disconnected)),
+        };
+        connection.start()?;
+        Ok(connection)
+    }
+
+    fn start(&mut self) -> Result<(), ConnectionError> {
+        let mut spi = Spidev::open(&self.parameters.device)?;
+        spi.configure(
+            &SpidevOptions::new()
+                .bits_per_word(8)
+                .max_speed_hz(SPI_SPEED_HZ)
+                .mode(SpiModeFlags::SPI_MODE_0)
+                .build(),
+        )?;
+
+        // Writing a frame blocks for a few milliseconds, so it's done away from the render loop
+        let (tx, rx) = ring_channel::<Vec<u8>>(NonZeroUsize::new(1).unwrap());
+        let status = Arc::new(Mutex::new(LinkStatus::Connected));
+        let color_order = self.parameters.color_order;
+        let device = self.parameters.device.clone();
+        self.thread = Some(thread::spawn({
+            let status = status.clone();
+            move || {
+                // Stops once the connection drops its sender
+                while let Ok(rgb) = rx.recv() {
+                    if let Err(e) = spi.write_all(&encode(&rgb, color_order)) {
+                        tracing::error!("Couldn't write to {}: {e}", device.display());
+                        break;
+                    }
+                }
+                *status.lock().unwrap() = LinkStatus::Disconnected;
+            }
+        }));
+        self.data_queue = Some(tx);
+        self.status = status;
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.data_queue.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("WS281x connection thread panicked");
+            }
+        }
+    }
+}
+
+impl Connection for Ws281xConnection {
+    fn send_frame(&mut self, packet: Vec<u8>) -> Result<(), ConnectionError> {
+        if *self.status.lock().unwrap() != LinkStatus::Connected {
+            return Err(ConnectionError::Closed);
+        }
+        self.data_queue
+            .as_mut()
+            .ok_or(ConnectionError::Closed)?
+            .send(packet)
+            .map(|_| ())
+            .map_err(|_| ConnectionError::Closed)
+    }
+
+    fn status(&self) -> LinkStatus {
+        *self.status.lock().unwrap()
+    }
+
+    fn reconnect(&mut self) {
+        self.stop();
+        if let Err(e) = self.start() {
+            tracing::warn!("Couldn't reopen {}: {e}", self.parameters.device.display());
+        }
+    }
+}
+
+impl Drop for Ws281xConnection {
+    fn drop(&mut self) {
+        self.stop();
+        tracing::info!("WS281x connection stopped.");
+    }
+}
+
+fn encode(rgb: &[u8], color_order: ColorOrder) -> Vec<u8> {
+    let mut channels = Vec::with_capacity(rgb.len());
+    simd::reorder(rgb, color_order.channels(), &mut channels);
+    let mut spi = Vec::with_capacity(channels.len() * 3 + RESET_LEN);
+    for channel in channels {
+        spi.extend_from_slice(&SPI_BYTES[channel as usize]);
+    }
+    spi.resize(spi.len() + RESET_LEN, 0);
+    spi
+}
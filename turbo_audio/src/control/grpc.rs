@@ -0,0 +1,441 @@
+// The handlers have to return tonic's Status, however large it is
+#![allow(clippy::result_large_err)]
+
+use super::{ControlCommand, ControlSender};
+use crate::{
+    info::{self, ConnectionHealth},
+    now_playing::{self, PlaybackStatus},
+    parameter_mapping::AudioFeatures,
+};
+use proto::turbo_audio_server::{TurboAudio, TurboAudioServer};
+use std::{collections::HashMap, net::SocketAddr, sync::mpsc, time::Duration};
+use tokio::{
+    net::TcpListener,
+    runtime::Runtime,
+    sync::oneshot,
+    task::JoinHandle,
+    time::{self, MissedTickBehavior},
+};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{transport::Server, Request, Response, Status};
+
+#[allow(clippy::all)]
+mod proto {
+    tonic::include_proto!("turbo_audio.v1");
+}
+
+// How long a request waits for the run loop to answer before giving up
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+// How long the server waits for the calls in progress when it stops, the feature streams never
+// ending by themselves
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_STREAM_RATE_HZ: f32 = 30.0;
+const MAX_STREAM_RATE_HZ: f32 = 120.0;
+const MIN_STREAM_RATE_HZ: f32 = 0.1;
+
+/// Typed control API of `proto/turbo_audio.proto` for the companion apps, covering the state of
+/// the engine, the effects, their settings and a stream of the audio features. It runs on its
+/// own tokio runtime, stopped when this is dropped
+pub struct GrpcServer {
+    runtime: Option<Runtime>,
+    server: Option<JoinHandle<Result<(), tonic::transport::Error>>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl GrpcServer {
+    pub fn new(
+        address: SocketAddr,
+        sender: ControlSender,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("grpc")
+            .enable_all()
+            .build()?;
+        // Bound here rather than by the server for the errors to be reported to the caller
+        let listener = runtime.block_on(TcpListener::bind(address))?;
+        tracing::info!("Listening for gRPC requests on {address}");
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let server = runtime.spawn(
+            Server::builder()
+                .add_service(TurboAudioServer::new(ControlService { sender }))
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                    let _ = shutdown_rx.await;
+                }),
+        );
+        Ok(Self {
+            runtime: Some(runtime),
+            server: Some(server),
+            shutdown: Some(shutdown_tx),
+        })
+    }
+}
+
+impl Drop for GrpcServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let (Some(runtime), Some(server)) = (self.runtime.take(), self.server.take()) {
+            match runtime.block_on(async { time::timeout(SHUTDOWN_TIMEOUT, server).await }) {
+                Ok(Ok(Ok(()))) | Err(_) => {}
+                Ok(Ok(Err(e))) => tracing::error!("gRPC server error: {e}"),
+                Ok(Err(_)) => tracing::error!("gRPC server task panicked"),
+            }
+            runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+        }
+        tracing::info!("gRPC server stopped.");
+    }
+}
+
+struct ControlService {
+    sender: ControlSender,
+}
+
+#[tonic::async_trait]
+impl TurboAudio for ControlService {
+    async fn get_info(
+        &self,
+        _request: Request<proto::GetInfoRequest>,
+    ) -> Result<Response<proto::EngineInfo>, Status> {
+        let info = ask(&self.sender, ControlCommand::GetInfo).await?;
+        Ok(Response::new(info.into()))
+    }
+
+    async fn list_effect_types(
+        &self,
+        _request: Request<proto::ListEffectTypesRequest>,
+    ) -> Result<Response<proto::ListEffectTypesResponse>, Status> {
+        let effect_types = ask(&self.sender, ControlCommand::GetEffectTypes).await?;
+        Ok(Response::new(proto::ListEffectTypesResponse {
+            effect_types,
+        }))
+    }
+
+    async fn get_effect_schema(
+        &self,
+        request: Request<proto::GetEffectSchemaRequest>,
+    ) -> Result<Response<proto::GetEffectSchemaResponse>, Status> {
+        let effect_type = request.into_inner().effect_type;
+        let schema = command(&self.sender, |reply| ControlCommand::GetEffectSchema {
+            effect_type,
+            reply,
+        })
+        .await?;
+        Ok(Response::new(proto::GetEffectSchemaResponse {
+            schema_json: schema.map(|schema| schema.to_string()),
+        }))
+    }
+
+    async fn create_effect(
+        &self,
+        request: Request<proto::CreateEffectRequest>,
+    ) -> Result<Response<proto::CreateEffectResponse>, Status> {
+        let request = request.into_inner();
+        let settings = parse_json(&request.settings_json, "settings_json")?;
+        let effect_id = command(&self.sender, |reply| ControlCommand::CreateEffect {
+            effect_type: request.effect_type,
+            settings,
+            reply,
+        })
+        .await?;
+        Ok(Response::new(proto::CreateEffectResponse {
+            effect_id: effect_id as u64,
+        }))
+    }
+
+    async fn assign_effect(
+        &self,
+        request: Request<proto::AssignEffectRequest>,
+    ) -> Result<Response<proto::AssignEffectResponse>, Status> {
+        let request = request.into_inner();
+        command(&self.sender, |reply| ControlCommand::AssignEffect {
+            ledstrip_id: request.ledstrip_id as usize,
+            segment: request.segment as usize,
+            effect_id: request.effect_id as usize,
+            reply,
+        })
+        .await?;
+        Ok(Response::new(proto::AssignEffectResponse {}))
+    }
+
+    async fn destroy_effect(
+        &self,
+        request: Request<proto::DestroyEffectRequest>,
+    ) -> Result<Response<proto::DestroyEffectResponse>, Status> {
+        let effect_id = request.into_inner().effect_id as usize;
+        command(&self.sender, |reply| ControlCommand::DestroyEffect {
+            effect_id,
+            reply,
+        })
+        .await?;
+        Ok(Response::new(proto::DestroyEffectResponse {}))
+    }
+
+    async fn get_settings(
+        &self,
+        request: Request<proto::GetSettingsRequest>,
+    ) -> Result<Response<proto::GetSettingsResponse>, Status> {
+        let settings_id = request.into_inner().settings_id as usize;
+        let settings = command(&self.sender, |reply| ControlCommand::GetSettings {
+            settings_id,
+            reply,
+        })
+        .await?;
+        Ok(Response::new(proto::GetSettingsResponse {
+            settings_json: settings.to_string(),
+        }))
+    }
+
+    async fn patch_settings(
+        &self,
+        request: Request<proto::PatchSettingsRequest>,
+    ) -> Result<Response<proto::PatchSettingsResponse>, Status> {
+        let request = request.into_inner();
+        let patch = parse_json(&request.patch_json, "patch_json")?;
+        command(&self.sender, |reply| ControlCommand::ApplySettingsPatch {
+            settings_id: request.settings_id as usize,
+            patch,
+            reply,
+        })
+        .await?;
+        Ok(Response::new(proto::PatchSettingsResponse {}))
+    }
+
+    async fn set_profile(
+        &self,
+        request: Request<proto::SetProfileRequest>,
+    ) -> Result<Response<proto::SetProfileResponse>, Status> {
+        let name = request.into_inner().name;
+        command(&self.sender, |reply| ControlCommand::SetProfile {
+            name,
+            reply,
+        })
+        .await?;
+        Ok(Response::new(proto::SetProfileResponse {}))
+    }
+
+    async fn set_brightness(
+        &self,
+        request: Request<proto::SetBrightnessRequest>,
+    ) -> Result<Response<proto::SetBrightnessResponse>, Status> {
+        let brightness = request.into_inner().brightness;
+        if !(0.0..=1.0).contains(&brightness) {
+            return Err(Status::invalid_argument(
+                "The brightness is between 0 and 1",
+            ));
+        }
+        send(&self.sender, ControlCommand::SetBrightness(brightness))?;
+        Ok(Response::new(proto::SetBrightnessResponse {}))
+    }
+
+    async fn set_paused(
+        &self,
+        request: Request<proto::SetPausedRequest>,
+    ) -> Result<Response<proto::SetPausedResponse>, Status> {
+        let paused = request.into_inner().paused;
+        send(&self.sender, ControlCommand::SetPaused(paused))?;
+        Ok(Response::new(proto::SetPausedResponse {}))
+    }
+
+    async fn set_night_mode(
+        &self,
+        request: Request<proto::SetNightModeRequest>,
+    ) -> Result<Response<proto::SetNightModeResponse>, Status> {
+        let on = request.into_inner().on;
+        send(&self.sender, ControlCommand::SetNightMode(on))?;
+        Ok(Response::new(proto::SetNightModeResponse {}))
+    }
+
+    type StreamFeaturesStream = ReceiverStream<Result<proto::AudioFeatures, Status>>;
+
+    async fn stream_features(
+        &self,
+        request: Request<proto::StreamFeaturesRequest>,
+    ) -> Result<Response<Self::StreamFeaturesStream>, Status> {
+        let rate_hz = request.into_inner().rate_hz;
+        let rate_hz = if rate_hz > 0.0 {
+            // A tiny rate would be a period too long for a Duration
+            rate_hz.clamp(MIN_STREAM_RATE_HZ, MAX_STREAM_RATE_HZ)
+        } else {
+            DEFAULT_STREAM_RATE_HZ
+        };
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        let sender = self.sender.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs_f32(1.0 / rate_hz));
+            // A slow client gets the latest features rather than a backlog of old ones
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                let features = ask(&sender, ControlCommand::GetFeatures)
+                    .await
+                    .map(|(features, derived)| to_proto_features(features, derived));
+                let failed = features.is_err();
+                // Stops once the client cancels the call, or the engine stops
+                if tx.send(features).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+fn send(sender: &ControlSender, command: ControlCommand) -> Result<(), Status> {
+    sender
+        .send(command)
+        .map_err(|_| Status::unavailable("Engine is stopped"))
+}
+
+/// Sends a command to the run loop and waits for its answer, off the threads of the runtime
+async fn ask<T: Send + 'static>(
+    sender: &ControlSender,
+    command: impl FnOnce(mpsc::Sender<T>) -> ControlCommand,
+) -> Result<T, Status> {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    send(sender, command(reply_tx))?;
+    tokio::task::spawn_blocking(move || reply_rx.recv_timeout(REPLY_TIMEOUT))
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|_| Status::unavailable("Engine didn't answer"))
+}
+
+/// Sends a command that can fail, whose error is answered as an invalid argument
+async fn command<T: Send + 'static>(
+    sender: &ControlSender,
+    command: impl FnOnce(mpsc::Sender<Result<T, String>>) -> ControlCommand,
+) -> Result<T, Status> {
+    ask(sender, command)
+        .await?
+        .map_err(Status::invalid_argument)
+}
+
+// Null if empty, like a missing field of the http api
+fn parse_json(json: &str, field: &str) -> Result<serde_json::Value, Status> {
+    if json.is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+    serde_json::from_str(json)
+        .map_err(|e| Status::invalid_argument(format!("Invalid {field}: {e}")))
+}
+
+fn to_proto_features(
+    features: AudioFeatures,
+    derived: HashMap<String, f32>,
+) -> proto::AudioFeatures {
+    proto::AudioFeatures {
+        bass: features.bass,
+        mids: features.mids,
+        treble: features.treble,
+        volume: features.volume,
+        time: features.time,
+        beat: features.beat,
+        tempo_phase: features.tempo_phase,
+        bar_phase: features.bar_phase,
+        kick_hit: features.kick_hit,
+        snare_hit: features.snare_hit,
+        hat_hit: features.hat_hit,
+        flux: features.flux,
+        centroid: features.centroid,
+        rolloff: features.rolloff,
+        flatness: features.flatness,
+        note: features.note,
+        derived,
+    }
+}
+
+impl From<info::EngineInfo> for proto::EngineInfo {
+    fn from(info: info::EngineInfo) -> Self {
+        Self {
+            version: info.version.to_owned(),
+            features: info.features.into_iter().map(str::to_owned).collect(),
+            uptime_secs: info.uptime_secs,
+            audio_backend: info.audio_backend.to_owned(),
+            audio_available: info.audio_available,
+            idle: info.idle,
+            ledstrips: info.ledstrips.into_iter().map(Into::into).collect(),
+            connections: info.connections.into_iter().map(Into::into).collect(),
+            effects: info.effects.into_iter().map(Into::into).collect(),
+            pixel_count: info.pixel_count as u64,
+            sync_offset_ms: info.sync_offset_ms,
+            latency_ms: info.latency_ms,
+            paused: info.paused,
+            blackout: info.blackout,
+            night_mode: info.night_mode,
+            engine_load: info.engine_load,
+            profile: info.profile,
+            profiles: info.profiles,
+            now_playing: info.now_playing.map(Into::into),
+            brightness: info.brightness,
+        }
+    }
+}
+
+impl From<info::LedstripInfo> for proto::LedstripInfo {
+    fn from(ledstrip: info::LedstripInfo) -> Self {
+        Self {
+            id: ledstrip.id as u64,
+            size: ledstrip.size as u64,
+            connection_id: ledstrip.connection_id.map(|id| id as u64),
+            errors: ledstrip.errors,
+            brightness: ledstrip.brightness,
+            segments: ledstrip.segments.into_iter().map(|id| id as u64).collect(),
+        }
+    }
+}
+
+impl From<info::ConnectionInfo> for proto::ConnectionInfo {
+    fn from(connection: info::ConnectionInfo) -> Self {
+        let health = match connection.health {
+            ConnectionHealth::Connected => proto::ConnectionHealth::Connected,
+            ConnectionHealth::Reconnecting => proto::ConnectionHealth::Reconnecting,
+            ConnectionHealth::Dead => proto::ConnectionHealth::Dead,
+        };
+        Self {
+            id: connection.id as u64,
+            health: health.into(),
+            fps: connection.fps,
+            dropped_frames: connection.dropped_frames,
+            skipped_frames: connection.skipped_frames,
+            bytes_per_second: connection.bytes_per_second,
+            queued_frames: connection.queued_frames as u64,
+            dropped_per_second: connection.dropped_per_second,
+            latency_ms: connection.latency_ms,
+            round_trip_ms: connection.round_trip_ms,
+            delay_ms: connection.delay_ms,
+            last_error: connection.last_error,
+        }
+    }
+}
+
+impl From<info::EffectInfo> for proto::EffectInfo {
+    fn from(effect: info::EffectInfo) -> Self {
+        Self {
+            id: effect.id as u64,
+            settings_id: effect.settings_id.map(|id| id as u64),
+            render_time_ms: effect.render_time_ms,
+            max_render_time_ms: effect.max_render_time_ms,
+            slow_frames: effect.slow_frames,
+            frozen: effect.frozen,
+        }
+    }
+}
+
+impl From<now_playing::NowPlaying> for proto::NowPlaying {
+    fn from(now_playing: now_playing::NowPlaying) -> Self {
+        let status = match now_playing.status {
+            PlaybackStatus::Playing => proto::PlaybackStatus::Playing,
+            PlaybackStatus::Paused => proto::PlaybackStatus::Paused,
+            PlaybackStatus::Stopped => proto::PlaybackStatus::Stopped,
+        };
+        Self {
+            player: now_playing.player,
+            title: now_playing.title,
+            artist: now_playing.artist,
+            album: now_playing.album,
+            status: status.into(),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use super::{ControlCommand, ControlSender};
+use crate::config_parser::{HotkeyAction, HotkeysConfig};
+use evdev::{Device, InputEventKind, Key};
+use std::{
+    collections::HashSet,
+    io,
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use thiserror::Error;
+
+// Time between two reads of the keyboards, short enough for the blackout to feel instant
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Error, Debug)]
+pub enum HotkeysError {
+    #[error("Unknown key {0:?}, keys are named like KEY_F12")]
+    UnknownKey(String),
+
+    #[error("A hotkey has no key")]
+    NoKeys,
+
+    #[error("No keyboard has the keys of the hotkeys, can turbo_audio read /dev/input?")]
+    NoKeyboard,
+
+    #[error("Couldn't read the keyboard {0}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+struct Hotkey {
+    keys: Vec<Key>,
+    action: HotkeyAction,
+}
+
+/// Reads the keyboards of the machine and translates the configured key combinations into
+/// [`ControlCommand`]s, whichever window has the focus. The keys still reach the other programs.
+/// The keyboards are closed when this is dropped
+pub struct HotkeyListener {
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+}
+
+impl HotkeyListener {
+    pub fn new(config: &HotkeysConfig, sender: ControlSender) -> Result<Self, HotkeysError> {
+        let hotkeys = config
+            .bindings
+            .iter()
+            .map(|binding| {
+                let keys = binding
+                    .keys
+                    .iter()
+                    .map(|name| {
+                        name.parse()
+                            .map_err(|_| HotkeysError::UnknownKey(name.clone()))
+                    })
+                    .collect::<Result<Vec<Key>, _>>()?;
+                if keys.is_empty() {
+                    return Err(HotkeysError::NoKeys);
+                }
+                Ok(Hotkey {
+                    keys,
+                    action: binding.action,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let keyboards = open_keyboards(config.device_name.as_deref(), &hotkeys)?;
+        let brightness_step = config.brightness_step;
+
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        let thread = thread::spawn({
+            let should_quit = should_quit.clone();
+            move || Self::listen(keyboards, hotkeys, brightness_step, sender, should_quit)
+        });
+        Ok(Self {
+            thread: Some(thread),
+            should_quit,
+        })
+    }
+
+    fn listen(
+        mut keyboards: Vec<(PathBuf, Device)>,
+        hotkeys: Vec<Hotkey>,
+        brightness_step: f32,
+        sender: ControlSender,
+        should_quit: Arc<AtomicBool>,
+    ) {
+        // Keys held on any of the keyboards
+        let mut held = HashSet::new();
+        while !should_quit.load(Ordering::Relaxed) {
+            let mut pressed = Vec::new();
+            keyboards.retain_mut(|(path, keyboard)| {
+                let events = match keyboard.fetch_events() {
+                    Ok(events) => events,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                    Err(e) => {
+                        tracing::warn!("Stopped reading the keyboard {}: {e}", path.display());
+                        return false;
+                    }
+                };
+                for event in events {
+                    let InputEventKind::Key(key) = event.kind() else {
+                        continue;
+                    };
+                    // 1 on a press, 2 when it repeats and 0 on a release
+                    match event.value() {
+                        1 => {
+                            held.insert(key);
+                            pressed.push(key);
+                        }
+                        0 => {
+                            held.remove(&key);
+                        }
+                        _ => {}
+                    }
+                }
+                true
+            });
+
+            for key in pressed {
+                let triggered = hotkeys.iter().filter(|hotkey| {
+                    hotkey.keys.last() == Some(&key)
+                        && hotkey.keys.iter().all(|key| held.contains(key))
+                });
+                for hotkey in triggered {
+                    tracing::debug!("Hotkey {:?} pressed", hotkey.action);
+                    let _ = sender.send(to_command(hotkey.action, brightness_step));
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for HotkeyListener {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn to_command(action: HotkeyAction, brightness_step: f32) -> ControlCommand {
+    match action {
+        HotkeyAction::NextProfile => ControlCommand::StepProfile(1),
+        HotkeyAction::PreviousProfile => ControlCommand::StepProfile(-1),
+        HotkeyAction::BrightnessUp => ControlCommand::StepBrightness(brightness_step),
+        HotkeyAction::BrightnessDown => ControlCommand::StepBrightness(-brightness_step),
+        HotkeyAction::Blackout => ControlCommand::ToggleBlackout,
+        HotkeyAction::Freeze => ControlCommand::ToggleFreeze,
+        HotkeyAction::Solo(ledstrip_id) => ControlCommand::ToggleSolo(ledstrip_id),
+    }
+}
+
+// Opens the keyboards whose name contains `device_name` and that have every key of a hotkey,
+// without blocking on their reads
+fn open_keyboards(
+    device_name: Option<&str>,
+    hotkeys: &[Hotkey],
+) -> Result<Vec<(PathBuf, Device)>, HotkeysError> {
+    let mut keyboards = Vec::new();
+    for (path, keyboard) in evdev::enumerate() {
+        let name = keyboard.name().unwrap_or_default();
+        if device_name.is_some_and(|device_name| !name.contains(device_name)) {
+            continue;
+        }
+        let Some(keys) = keyboard.supported_keys() else {
+            continue;
+        };
+        let has_hotkey = hotkeys
+            .iter()
+            .any(|hotkey| hotkey.keys.iter().all(|key| keys.contains(*key)));
+        if !has_hotkey {
+            continue;
+        }
+        // Reads return WouldBlock rather than waiting for a key, for the thread to check if it
+        // should quit
+        let result = unsafe { libc::fcntl(keyboard.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) };
+        if result == -1 {
+            return Err(HotkeysError::Io(path, io::Error::last_os_error()));
+        }
+        tracing::info!("Listening for hotkeys on {name} ({})", path.display());
+        keyboards.push((path, keyboard));
+    }
+    if keyboards.is_empty() {
+        return Err(HotkeysError::NoKeyboard);
+    }
+    Ok(keyboards)
+}
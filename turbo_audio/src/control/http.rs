@@ -0,0 +1,630 @@
+use super::{ControlCommand, ControlSender};
+use crate::{
+    audio::audio_stream::list_input_devices, overrides::SegmentOverride, test_pattern::TestPattern,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    io::Read,
+    net::SocketAddr,
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+// How long a request waits for the run loop to answer before giving up
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+// Time between two frames of the live preview
+const PREVIEW_INTERVAL: Duration = Duration::from_millis(33);
+const WEB_UI: &str = include_str!("web_ui.html");
+
+/// Json HTTP API used by remote UIs to query and control the engine.
+///
+/// Endpoints:
+/// - `GET /`: web UI showing the spectrum and the ledstrips live, to pick their effects and
+///   brightness from a browser.
+/// - `GET /preview`: websocket sending the spectrum and the colors of the ledstrips as json about
+///   30 times per second.
+/// - `GET /info`: version, compiled features, uptime, ledstrips and audio backend.
+/// - `GET /metrics`: fps, tick time, audio levels and connection latencies of every second of
+///   the last 10 minutes, oldest first.
+/// - `GET /effect_types`: names of the effect types that can be created.
+/// - `GET /effect_types/<name>/schema`: json schema of the settings of an effect type, to build
+///   a form for them. Null if the effect doesn't have settings.
+/// - `POST /effects`: creates an effect of a type from a body like
+///   `{"type": "rainbow", "settings": {...}}` and answers `{"effect_id": ...}`.
+/// - `PUT /ledstrips/<ledstrip_id>/segments/<segment>`: renders the effect of a body like
+///   `{"effect_id": ...}` on the `<segment>`th segment of the ledstrip.
+/// - `DELETE /effects/<effect_id>`: drops an effect that isn't rendered on any segment.
+/// - `PUT /overrides/<channel>`: renders an effect on the segments of a lower priority from a
+///   body like `{"effect_id": 3, "ledstrip_id": 1, "priority": 200, "duration_ms": 2000}`, in
+///   place of the previous override of the channel. `DELETE` releases it.
+/// - `POST /effects/<effect_id>/eval`: evaluates the lua of a body like `{"code": "Colors[1]"}`
+///   in the environment of a lua effect and answers its results as a string. The globals of the
+///   effect are read-only unless the body has `"write": true`, which is only accepted with the
+///   `write_token` of the settings as a bearer token.
+/// - `PUT /connections/<connection_id>/test_pattern`: shows the pattern of a body like
+///   `{"pattern": "Chase"}` on a connection instead of its ledstrips. `DELETE` stops it.
+/// - `PUT /profile`: switches to the profile of a body like `{"name": "party"}`.
+/// - `PUT /brightness`: sets the global brightness from a body like `{"brightness": 0.5}`.
+/// - `PUT /ledstrips/<ledstrip_id>/brightness`: same for a single ledstrip, on top of the global
+///   brightness.
+/// - `PUT /night_mode`: turns the night mode on or off from a body like `{"on": true}`, until its
+///   hours next start or end.
+/// - `PUT /blackout`: sends black frames to every ledstrip from a body like `{"on": true}`, and
+///   `PUT /ledstrips/<ledstrip_id>/blackout` to a single one.
+/// - `PUT /freeze`: keeps sending the current frame without rendering the effects from a body
+///   like `{"on": true}`, and `PUT /ledstrips/<ledstrip_id>/freeze` for a single ledstrip.
+/// - `PUT /solo`: blacks out every ledstrip but the one of a body like `{"ledstrip_id": 2}`, or
+///   ends the solo with `{"ledstrip_id": null}`.
+pub struct HttpServer {
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+}
+
+impl HttpServer {
+    pub fn new(
+        address: SocketAddr,
+        write_token: Option<String>,
+        capture_dir: Option<PathBuf>,
+        sender: ControlSender,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let server = Server::http(address)?;
+        tracing::info!("Listening for http requests on {address}");
+
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        let thread = thread::spawn({
+            let should_quit = should_quit.clone();
+            move || {
+                while !should_quit.load(Ordering::Relaxed) {
+                    match server.recv_timeout(Duration::from_millis(100)) {
+                        Ok(Some(request)) => match (request.method(), request.url()) {
+                            (Method::Get, "/") => serve_web_ui(request),
+                            (Method::Get, "/preview") => {
+                                stream_preview(request, sender.clone(), should_quit.clone())
+                            }
+                            _ => handle_request(
+                                request,
+                                write_token.as_deref(),
+                                capture_dir.as_deref(),
+                                &sender,
+                            ),
+                        },
+                        Ok(None) => {}
+                        Err(e) => {
+                            tracing::error!("Http server error: {e}");
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            thread: Some(thread),
+            should_quit,
+        })
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Http server thread panicked");
+            }
+        }
+        tracing::info!("Http server stopped.");
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateEffectRequest {
+    #[serde(rename = "type")]
+    effect_type: String,
+    #[serde(default)]
+    settings: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct SetAudioDeviceRequest {
+    /// The default input if missing
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CalibrateNoiseFloorRequest {
+    seconds: f32,
+}
+
+#[derive(Deserialize)]
+struct CaptureAudioRequest {
+    /// Path of the wav file in the capture folder
+    path: PathBuf,
+    seconds: f32,
+}
+
+#[derive(Deserialize)]
+struct CaptureFramesRequest {
+    /// Path of the gif, png or json file in the capture folder
+    path: PathBuf,
+    seconds: f32,
+    /// Every ledstrip when empty
+    #[serde(default)]
+    ledstrips: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct CalibrateLatencyRequest {
+    seconds: f32,
+}
+
+#[derive(Deserialize)]
+struct AssignEffectRequest {
+    effect_id: usize,
+}
+
+#[derive(Deserialize)]
+struct SetTestPatternRequest {
+    pattern: TestPattern,
+}
+
+#[derive(Deserialize)]
+struct SetProfileRequest {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct EvalLuaRequest {
+    code: String,
+    /// Allows the code to change the globals of the effect
+    #[serde(default)]
+    write: bool,
+}
+
+#[derive(Deserialize)]
+struct SetBrightnessRequest {
+    brightness: f32,
+}
+
+/// Body of the endpoints turning something on or off
+#[derive(Deserialize)]
+struct SwitchRequest {
+    on: bool,
+}
+
+#[derive(Deserialize)]
+struct SetSoloRequest {
+    /// Ends the solo if missing
+    ledstrip_id: Option<usize>,
+}
+
+impl SetBrightnessRequest {
+    fn brightness(&self) -> Result<f32, ErrorResponse> {
+        match (0.0..=1.0).contains(&self.brightness) {
+            true => Ok(self.brightness),
+            false => Err(error(400, "The brightness is between 0 and 1")),
+        }
+    }
+}
+
+fn serve_web_ui(request: Request) {
+    let response = Response::from_string(WEB_UI)
+        .with_header(Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap());
+    if let Err(e) = request.respond(response) {
+        tracing::warn!("Couldn't serve the web UI: {e}");
+    }
+}
+
+// Upgrades the request to a websocket and sends it the live preview from its own thread, until
+// the client leaves or the server stops
+fn stream_preview(request: Request, sender: ControlSender, should_quit: Arc<AtomicBool>) {
+    let key = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+        .map(|header| derive_accept_key(header.value.as_bytes()));
+    let Some(accept) = key else {
+        let (status, body) = error(400, "Expected a websocket");
+        let response = Response::from_string(body.to_string()).with_status_code(status);
+        let _ = request.respond(response);
+        return;
+    };
+    let response = Response::empty(StatusCode(101))
+        .with_header(Header::from_bytes("Sec-WebSocket-Accept", accept).unwrap());
+    let stream = request.upgrade("websocket", response);
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    // Not joined, a client that stopped reading could block it on a write. It quits on its next
+    // frame once the server stops
+    thread::spawn(move || {
+        while !should_quit.load(Ordering::Relaxed) {
+            let Ok(preview) = ask(&sender, ControlCommand::GetPreview) else {
+                break;
+            };
+            let message = serde_json::to_string(&preview).unwrap();
+            if socket.send(Message::Text(message.into())).is_err() {
+                break;
+            }
+            thread::sleep(PREVIEW_INTERVAL);
+        }
+        let _ = socket.close(None);
+        let _ = socket.flush();
+    });
+}
+
+fn handle_request(
+    mut request: Request,
+    write_token: Option<&str>,
+    capture_dir: Option<&Path>,
+    sender: &ControlSender,
+) {
+    tracing::debug!("{} {}", request.method(), request.url());
+    let method = request.method().clone();
+    let url = request.url().to_owned();
+    let authorized = is_authorized(&request, write_token);
+    let (status, body) = answer(
+        &method,
+        &url,
+        request.as_reader(),
+        authorized,
+        capture_dir,
+        sender,
+    );
+
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap());
+    if let Err(e) = request.respond(response) {
+        tracing::warn!("Couldn't respond to http request: {e}");
+    }
+}
+
+/// Status and json body answering a request to one of the endpoints of the api, `authorized`
+/// telling whether it has the `write_token` of the settings, and `capture_dir` the folder of the
+/// captures. Public for the fuzz targets
+pub fn answer(
+    method: &Method,
+    url: &str,
+    mut reader: impl Read,
+    authorized: bool,
+    capture_dir: Option<&Path>,
+    sender: &ControlSender,
+) -> (u16, serde_json::Value) {
+    let path: Vec<&str> = url.trim_matches('/').split('/').collect();
+    let result = match (method, path.as_slice()) {
+        (Method::Get, ["info"]) => query(sender, ControlCommand::GetInfo),
+        (Method::Get, ["metrics"]) => query(sender, ControlCommand::GetMetrics),
+        (Method::Get, ["effect_types"]) => query(sender, ControlCommand::GetEffectTypes),
+        (Method::Get, ["effect_types", effect_type, "schema"]) => {
+            command(sender, |reply| ControlCommand::GetEffectSchema {
+                effect_type: effect_type.to_string(),
+                reply,
+            })
+        }
+        (Method::Post, ["effects"]) => {
+            read_body::<CreateEffectRequest>(&mut reader).and_then(|body| {
+                let effect_id = command(sender, |reply| ControlCommand::CreateEffect {
+                    effect_type: body.effect_type,
+                    settings: body.settings,
+                    reply,
+                })?;
+                Ok(serde_json::json!({ "effect_id": effect_id }))
+            })
+        }
+        (Method::Post, ["effects", effect_id, "eval"]) => match effect_id.parse() {
+            Ok(effect_id) => read_body::<EvalLuaRequest>(&mut reader).and_then(|body| {
+                if body.write && !authorized {
+                    return Err(error(
+                        403,
+                        "Changing the effects needs the write_token of the http settings",
+                    ));
+                }
+                command(sender, |reply| ControlCommand::EvalLua {
+                    effect_id,
+                    code: body.code,
+                    write: body.write,
+                    reply,
+                })
+            }),
+            Err(_) => Err(not_found()),
+        },
+        (Method::Get, ["audio_devices"]) => list_input_devices()
+            .map(|devices| serde_json::json!(devices))
+            .map_err(|e| error(500, format!("{e:#}"))),
+        (Method::Put, ["audio_device"]) => read_body::<SetAudioDeviceRequest>(&mut reader)
+            .and_then(|body| {
+                command(sender, |reply| ControlCommand::SetAudioDevice {
+                    device_name: body.name,
+                    reply,
+                })
+            }),
+        (Method::Post, ["noise_floor", "calibrate"]) => {
+            read_body::<CalibrateNoiseFloorRequest>(&mut reader).and_then(|body| {
+                command(sender, |reply| ControlCommand::CalibrateNoiseFloor {
+                    seconds: body.seconds,
+                    reply,
+                })
+            })
+        }
+        (Method::Post, ["audio", "capture"]) => read_body::<CaptureAudioRequest>(&mut reader)
+            .and_then(|body| {
+                let path = capture_path(authorized, capture_dir, &body.path)?;
+                command(sender, |reply| ControlCommand::CaptureAudio {
+                    path,
+                    seconds: body.seconds,
+                    reply,
+                })
+            }),
+        (Method::Post, ["frames", "capture"]) => read_body::<CaptureFramesRequest>(&mut reader)
+            .and_then(|body| {
+                let path = capture_path(authorized, capture_dir, &body.path)?;
+                command(sender, |reply| ControlCommand::CaptureFrames {
+                    path,
+                    seconds: body.seconds,
+                    ledstrips: body.ledstrips,
+                    reply,
+                })
+            }),
+        (Method::Post, ["latency", "calibrate"]) => {
+            read_body::<CalibrateLatencyRequest>(&mut reader).and_then(|body| {
+                command(sender, |reply| ControlCommand::CalibrateLatency {
+                    seconds: body.seconds,
+                    reply,
+                })
+            })
+        }
+        (Method::Put, ["ledstrips", ledstrip_id, "segments", segment]) => {
+            match (ledstrip_id.parse(), segment.parse()) {
+                (Ok(ledstrip_id), Ok(segment)) => read_body::<AssignEffectRequest>(&mut reader)
+                    .and_then(|body| {
+                        command(sender, |reply| ControlCommand::AssignEffect {
+                            ledstrip_id,
+                            segment,
+                            effect_id: body.effect_id,
+                            reply,
+                        })
+                    }),
+                _ => Err(not_found()),
+            }
+        }
+        (Method::Delete, ["effects", effect_id]) => match effect_id.parse() {
+            Ok(effect_id) => command(sender, |reply| ControlCommand::DestroyEffect {
+                effect_id,
+                reply,
+            }),
+            Err(_) => Err(not_found()),
+        },
+        (Method::Put, ["overrides", channel]) => read_body::<SegmentOverride>(&mut reader)
+            .and_then(|segment_override| {
+                command(sender, |reply| ControlCommand::SetOverride {
+                    channel: channel.to_string(),
+                    segment_override,
+                    reply,
+                })
+            }),
+        (Method::Delete, ["overrides", channel]) => {
+            command(sender, |reply| ControlCommand::ReleaseOverride {
+                channel: channel.to_string(),
+                reply,
+            })
+        }
+        (Method::Put, ["connections", connection_id, "test_pattern"]) => {
+            match connection_id.parse() {
+                Ok(connection_id) => {
+                    read_body::<SetTestPatternRequest>(&mut reader).and_then(|body| {
+                        command(sender, |reply| ControlCommand::SetTestPattern {
+                            connection_id,
+                            pattern: Some(body.pattern),
+                            reply,
+                        })
+                    })
+                }
+                Err(_) => Err(not_found()),
+            }
+        }
+        (Method::Delete, ["connections", connection_id, "test_pattern"]) => {
+            match connection_id.parse() {
+                Ok(connection_id) => command(sender, |reply| ControlCommand::SetTestPattern {
+                    connection_id,
+                    pattern: None,
+                    reply,
+                }),
+                Err(_) => Err(not_found()),
+            }
+        }
+        (Method::Get, ["settings", settings_id]) => match settings_id.parse() {
+            Ok(settings_id) => command(sender, |reply| ControlCommand::GetSettings {
+                settings_id,
+                reply,
+            }),
+            Err(_) => Err(not_found()),
+        },
+        (Method::Patch, ["settings", settings_id]) => match settings_id.parse() {
+            Ok(settings_id) => read_body::<serde_json::Value>(&mut reader).and_then(|patch| {
+                command(sender, |reply| ControlCommand::ApplySettingsPatch {
+                    settings_id,
+                    patch,
+                    reply,
+                })
+            }),
+            Err(_) => Err(not_found()),
+        },
+        (Method::Put, ["brightness"]) => {
+            read_body::<SetBrightnessRequest>(&mut reader).and_then(|body| {
+                let brightness = body.brightness()?;
+                send(sender, ControlCommand::SetBrightness(brightness))
+            })
+        }
+        (Method::Put, ["night_mode"]) => read_body::<SwitchRequest>(&mut reader)
+            .and_then(|body| send(sender, ControlCommand::SetNightMode(body.on))),
+        (Method::Put, ["blackout"]) => read_body::<SwitchRequest>(&mut reader).and_then(|body| {
+            send(
+                sender,
+                ControlCommand::SetBlackout {
+                    ledstrip_id: None,
+                    on: body.on,
+                },
+            )
+        }),
+        (Method::Put, ["freeze"]) => read_body::<SwitchRequest>(&mut reader).and_then(|body| {
+            send(
+                sender,
+                ControlCommand::FreezeFrame {
+                    ledstrip_id: None,
+                    frozen: body.on,
+                },
+            )
+        }),
+        (Method::Put, ["solo"]) => read_body::<SetSoloRequest>(&mut reader)
+            .and_then(|body| send(sender, ControlCommand::SetSolo(body.ledstrip_id))),
+        (Method::Put, ["ledstrips", ledstrip_id, "blackout"]) => match ledstrip_id.parse() {
+            Ok(ledstrip_id) => read_body::<SwitchRequest>(&mut reader).and_then(|body| {
+                send(
+                    sender,
+                    ControlCommand::SetBlackout {
+                        ledstrip_id: Some(ledstrip_id),
+                        on: body.on,
+                    },
+                )
+            }),
+            Err(_) => Err(not_found()),
+        },
+        (Method::Put, ["ledstrips", ledstrip_id, "freeze"]) => match ledstrip_id.parse() {
+            Ok(ledstrip_id) => read_body::<SwitchRequest>(&mut reader).and_then(|body| {
+                send(
+                    sender,
+                    ControlCommand::FreezeFrame {
+                        ledstrip_id: Some(ledstrip_id),
+                        frozen: body.on,
+                    },
+                )
+            }),
+            Err(_) => Err(not_found()),
+        },
+        (Method::Put, ["ledstrips", ledstrip_id, "brightness"]) => match ledstrip_id.parse() {
+            Ok(ledstrip_id) => read_body::<SetBrightnessRequest>(&mut reader).and_then(|body| {
+                let brightness = body.brightness()?;
+                send(
+                    sender,
+                    ControlCommand::SetLedstripBrightness {
+                        ledstrip_id,
+                        brightness,
+                    },
+                )
+            }),
+            Err(_) => Err(not_found()),
+        },
+        (Method::Put, ["profile"]) => {
+            read_body::<SetProfileRequest>(&mut reader).and_then(|body| {
+                command(sender, |reply| ControlCommand::SetProfile {
+                    name: body.name,
+                    reply,
+                })
+            })
+        }
+        _ => Err(not_found()),
+    };
+    result.map_or_else(|error| error, |body| (200, body))
+}
+
+// Status and body of a request that failed
+type ErrorResponse = (u16, serde_json::Value);
+
+fn error(status: u16, message: impl Into<String>) -> ErrorResponse {
+    (status, serde_json::json!({ "error": message.into() }))
+}
+
+// Whether the request has the bearer token of the settings. Never without a token
+fn is_authorized(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return false;
+    };
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+        .is_some_and(|value| value == token)
+}
+
+// Path of a capture in the capture folder. The captures write files, so they need the token, and
+// their path can't leave the folder
+fn capture_path(
+    authorized: bool,
+    capture_dir: Option<&Path>,
+    path: &Path,
+) -> Result<PathBuf, ErrorResponse> {
+    let Some(capture_dir) = capture_dir.filter(|_| authorized) else {
+        return Err(error(
+            403,
+            "Captures need the write_token and the capture_dir of the http settings",
+        ));
+    };
+    let relative = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !relative || path.as_os_str().is_empty() {
+        return Err(error(
+            400,
+            "The path of a capture is relative to the capture_dir, without ..",
+        ));
+    }
+    Ok(capture_dir.join(path))
+}
+
+fn not_found() -> ErrorResponse {
+    error(404, "Not found")
+}
+
+fn read_body<T: DeserializeOwned>(reader: impl Read) -> Result<T, ErrorResponse> {
+    serde_json::from_reader(reader).map_err(|e| error(400, format!("Invalid body: {e}")))
+}
+
+/// Sends a command that isn't answered
+fn send(
+    sender: &ControlSender,
+    command: ControlCommand,
+) -> Result<serde_json::Value, ErrorResponse> {
+    sender
+        .send(command)
+        .map_err(|_| error(503, "Engine is stopped"))?;
+    Ok(serde_json::Value::Null)
+}
+
+/// Sends a command to the run loop and waits for its answer
+fn ask<T>(
+    sender: &ControlSender,
+    command: impl FnOnce(std::sync::mpsc::Sender<T>) -> ControlCommand,
+) -> Result<T, ErrorResponse> {
+    let (reply_tx, reply_rx): (_, Receiver<T>) = std::sync::mpsc::channel();
+    if sender.send(command(reply_tx)).is_err() {
+        return Err(error(503, "Engine is stopped"));
+    }
+
+    reply_rx
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|_| error(503, "Engine didn't answer"))
+}
+
+fn query<T: Serialize>(
+    sender: &ControlSender,
+    command: impl FnOnce(std::sync::mpsc::Sender<T>) -> ControlCommand,
+) -> Result<serde_json::Value, ErrorResponse> {
+    Ok(serde_json::to_value(ask(sender, command)?).unwrap())
+}
+
+/// Sends a command that can fail, whose error is answered as a bad request
+fn command<T: Serialize>(
+    sender: &ControlSender,
+    command: impl FnOnce(std::sync::mpsc::Sender<Result<T, String>>) -> ControlCommand,
+) -> Result<serde_json::Value, ErrorResponse> {
+    let reply = ask(sender, command)?.map_err(|e| error(400, e))?;
+    Ok(serde_json::to_value(reply).unwrap())
+}
@@ -0,0 +1,121 @@
+use super::{ControlCommand, ControlSender};
+use crate::config_parser::{MidiAction, MidiConfig, MidiMapping, MidiMessageType};
+use midir::{MidiInput, MidiInputConnection};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MidiError {
+    #[error("Couldn't initialize the midi input: {0}")]
+    Init(#[from] midir::InitError),
+
+    #[error("No midi input port matching {0:?}")]
+    PortNotFound(Option<String>),
+
+    #[error("Couldn't connect to the midi port: {0}")]
+    Connect(#[from] midir::ConnectError<MidiInput>),
+}
+
+/// Listens to a midi input port and translates the mapped messages into [`ControlCommand`]s.
+/// The port is closed when this is dropped.
+pub struct MidiListener {
+    _connection: MidiInputConnection<()>,
+}
+
+impl MidiListener {
+    pub fn new(config: &MidiConfig, sender: ControlSender) -> Result<Self, MidiError> {
+        let midi_input = MidiInput::new("turbo_audio")?;
+        let ports = midi_input.ports();
+        let port = ports
+            .iter()
+            .find(|port| {
+                let Ok(name) = midi_input.port_name(port) else {
+                    return false;
+                };
+                match &config.port_name {
+                    Some(port_name) => name.contains(port_name.as_str()),
+                    None => true,
+                }
+            })
+            .ok_or_else(|| MidiError::PortNotFound(config.port_name.clone()))?;
+        tracing::info!(
+            "Listening for midi messages on {}",
+            midi_input.port_name(port).unwrap_or_default()
+        );
+
+        let mappings = config.mappings.clone();
+        let connection = midi_input.connect(
+            port,
+            "turbo_audio-input",
+            move |_timestamp, message, _| {
+                for command in to_commands(&mappings, message) {
+                    let _ = sender.send(command);
+                }
+            },
+            (),
+        )?;
+
+        Ok(Self {
+            _connection: connection,
+        })
+    }
+}
+
+/// Returns the `(channel, message type, value)` of a note or control change message.
+fn parse_message(message: &[u8]) -> Option<(u8, MidiMessageType, u8)> {
+    let [status, data1, data2] = *message else {
+        return None;
+    };
+    let channel = status & 0x0F;
+    match status & 0xF0 {
+        0xB0 => Some((channel, MidiMessageType::ControlChange(data1), data2)),
+        // A note on with a velocity of 0 is a note off
+        0x90 if data2 > 0 => Some((channel, MidiMessageType::Note(data1), data2)),
+        _ => None,
+    }
+}
+
+fn to_commands<'a>(
+    mappings: &'a [MidiMapping],
+    message: &[u8],
+) -> impl Iterator<Item = ControlCommand> + 'a {
+    let parsed = parse_message(message);
+    mappings.iter().filter_map(move |mapping| {
+        let (channel, message_type, value) = parsed?;
+        if mapping.message != message_type || mapping.channel.is_some_and(|c| c != channel) {
+            return None;
+        }
+        let value = value as f64 / 127.0;
+
+        Some(match &mapping.action {
+            MidiAction::EffectSetting {
+                effect_id,
+                key,
+                min,
+                max,
+            } => ControlCommand::UpdateEffectSetting {
+                effect_id: *effect_id,
+                key: key.clone(),
+                value: (min + (max - min) * value).into(),
+            },
+            MidiAction::Settings {
+                settings_id,
+                key,
+                min,
+                max,
+            } => ControlCommand::PatchSettings {
+                settings_id: *settings_id,
+                patch: serde_json::json!({ key: min + (max - min) * value }),
+            },
+            MidiAction::Brightness => ControlCommand::SetBrightness(value as f32),
+            MidiAction::SwitchEffect {
+                ledstrip_id,
+                segment,
+                effect_id,
+            } => ControlCommand::SwitchLedstripEffect {
+                ledstrip_id: *ledstrip_id,
+                segment: *segment,
+                effect_id: *effect_id,
+            },
+        })
+    })
+}
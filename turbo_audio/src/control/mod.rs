@@ -0,0 +1,207 @@
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(all(target_os = "linux", feature = "hotkeys"))]
+pub mod hotkeys;
+pub mod http;
+#[cfg(feature = "midi")]
+pub mod midi;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod osc;
+#[cfg(unix)]
+pub mod socket;
+
+use crate::{
+    info::{EngineInfo, LivePreview},
+    metrics::MetricsSample,
+    overrides::SegmentOverride,
+    parameter_mapping::AudioFeatures,
+    test_pattern::TestPattern,
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::mpsc::{Receiver, Sender},
+};
+
+/// Control socket used by `turbo_audio ctl` when none is given
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/turbo_audio.sock";
+
+/// Commands sent from the control servers (OSC, MIDI, MQTT, HTTP, ...) to the run loop. They are
+/// applied to the controller between ticks so that the servers never touch the effects directly.
+#[derive(Debug)]
+pub enum ControlCommand {
+    UpdateEffectSetting {
+        effect_id: usize,
+        key: String,
+        value: serde_json::Value,
+    },
+    /// Applies a json merge patch to settings, shared by every effect linked to them
+    PatchSettings {
+        settings_id: usize,
+        patch: serde_json::Value,
+    },
+    /// Same as `PatchSettings`, replying whether the patch was applied
+    ApplySettingsPatch {
+        settings_id: usize,
+        patch: serde_json::Value,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Current values of settings
+    GetSettings {
+        settings_id: usize,
+        reply: Sender<Result<serde_json::Value, String>>,
+    },
+    /// Global brightness between 0 and 1
+    SetBrightness(f32),
+    /// Adds to the global brightness, which stays between 0 and 1
+    StepBrightness(f32),
+    /// Sends black frames whatever the effects and the brightness, or stops doing so
+    ToggleBlackout,
+    /// Starts or ends the blackout of a ledstrip, or the global one if missing
+    SetBlackout {
+        ledstrip_id: Option<usize>,
+        on: bool,
+    },
+    /// Keeps sending the current frame without rendering the effects, or renders them again
+    ToggleFreeze,
+    /// Same as `ToggleFreeze` for a ledstrip, or every ledstrip if missing
+    FreezeFrame {
+        ledstrip_id: Option<usize>,
+        frozen: bool,
+    },
+    /// Blacks out every ledstrip but one, or ends the solo if missing
+    SetSolo(Option<usize>),
+    /// Solos a ledstrip, or ends the solo if it's the one soloed
+    ToggleSolo(usize),
+    /// Brightness of a single ledstrip between 0 and 1, applied on top of the global brightness
+    SetLedstripBrightness {
+        ledstrip_id: usize,
+        brightness: f32,
+    },
+    SwitchLedstripEffect {
+        ledstrip_id: usize,
+        segment: usize,
+        effect_id: usize,
+    },
+    /// Stops advancing an effect. The segments rendering it keep showing its last output
+    SetEffectFrozen {
+        effect_id: usize,
+        frozen: bool,
+    },
+    /// Loads a new instance of a registered effect type with its own settings and replies with
+    /// its id
+    CreateEffect {
+        effect_type: String,
+        settings: serde_json::Value,
+        reply: Sender<Result<usize, String>>,
+    },
+    /// Same as `SwitchLedstripEffect`, replying whether the effect could be assigned
+    AssignEffect {
+        ledstrip_id: usize,
+        segment: usize,
+        effect_id: usize,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Drops an effect that isn't rendered on any segment
+    DestroyEffect {
+        effect_id: usize,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Renders an effect on some segments above a priority, in place of the override previously
+    /// set on the channel, until it's released or its duration is over
+    SetOverride {
+        channel: String,
+        segment_override: SegmentOverride,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Gives the segments overridden on a channel back to their effect
+    ReleaseOverride {
+        channel: String,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Sends a test pattern to a connection instead of its ledstrips, to identify them. Back to
+    /// the ledstrips if the pattern is missing
+    SetTestPattern {
+        connection_id: usize,
+        pattern: Option<TestPattern>,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Switches to a profile of the config
+    SetProfile {
+        name: String,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Switches to the profile that many after the active one in the config, or before it if
+    /// negative, wrapping around
+    StepProfile(i32),
+    /// Stops rendering the effects. Connections with a keep-alive keep receiving frames
+    SetPaused(bool),
+    /// Turns the night mode on or off until its hours next start or end
+    SetNightMode(bool),
+    /// Audio to light offset in ms. Positive values delay the lights
+    SetSyncOffset(i32),
+    /// Replaces the effects by flashes on every metronome click, to tune the sync offset
+    SetSyncTestMode(bool),
+    /// Starts playing clicks for a few seconds to measure how long after a sound is heard the
+    /// lights react, which the beat is then predicted ahead by
+    CalibrateLatency {
+        seconds: f32,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Names of the registered effect types
+    GetEffectTypes(Sender<Vec<String>>),
+    /// Json schema of the settings of an effect type, null if it doesn't have settings
+    GetEffectSchema {
+        effect_type: String,
+        reply: Sender<Result<Option<serde_json::Value>, String>>,
+    },
+    GetInfo(Sender<EngineInfo>),
+    /// Spectrum and ledstrip colors of the current frame, for the live preview of the web UI
+    GetPreview(Sender<LivePreview>),
+    /// Audio features of the current frame, and the values of the derived features
+    GetFeatures(Sender<(AudioFeatures, HashMap<String, f32>)>),
+    /// History of the metrics sampled every second, oldest first
+    GetMetrics(Sender<Vec<MetricsSample>>),
+    /// Evaluates a line of lua in the environment of an effect and replies with its results
+    EvalLua {
+        effect_id: usize,
+        code: String,
+        /// Allows the line to change the globals of the effect
+        write: bool,
+        reply: Sender<Result<String, String>>,
+    },
+    /// Listens to another audio device, or to the default input if the name is missing, until
+    /// the engine restarts
+    SetAudioDevice {
+        device_name: Option<String>,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Starts measuring the noise floor of the audio input over a few seconds, after which it's
+    /// subtracted from the fft
+    CalibrateNoiseFloor {
+        seconds: f32,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Writes the next seconds of the audio input to a wav file on the machine of the engine
+    CaptureAudio {
+        path: PathBuf,
+        seconds: f32,
+        reply: Sender<Result<(), String>>,
+    },
+    /// Writes the next seconds of frames of the ledstrips to a gif, a png or a json file on the
+    /// machine of the engine. Every ledstrip is captured when none are given
+    CaptureFrames {
+        path: PathBuf,
+        seconds: f32,
+        ledstrips: Vec<usize>,
+        reply: Sender<Result<(), String>>,
+    },
+}
+
+pub type ControlSender = Sender<ControlCommand>;
+pub type ControlReceiver = Receiver<ControlCommand>;
+
+pub fn channel() -> (ControlSender, ControlReceiver) {
+    std::sync::mpsc::channel()
+}
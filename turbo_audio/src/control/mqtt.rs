@@ -0,0 +1,286 @@
+use super::{ControlCommand, ControlSender};
+use crate::config_parser::MqttConfig;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// State of a ledstrip as seen by Home Assistant. Uses the json schema of the mqtt light
+/// integration for both the state and command topics.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightState {
+    state: String,
+    brightness: u8,
+    effect: Option<String>,
+}
+
+impl Default for LightState {
+    fn default() -> Self {
+        Self {
+            state: "ON".to_owned(),
+            brightness: u8::MAX,
+            effect: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LightCommand {
+    state: Option<String>,
+    brightness: Option<u8>,
+    effect: Option<String>,
+}
+
+/// Exposes every ledstrip as a Home Assistant light over MQTT. Discovery messages are published
+/// on every (re)connection so the lights show up automatically.
+pub struct MqttClient {
+    client: Client,
+    availability_topic: String,
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+}
+
+impl MqttClient {
+    pub fn new(
+        config: &MqttConfig,
+        ledstrip_ids: Vec<usize>,
+        effect_ids: Vec<usize>,
+        sender: ControlSender,
+    ) -> Self {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(5));
+        options.set_last_will(LastWill::new(
+            availability_topic(config),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            options.set_credentials(username, password);
+        }
+
+        let (client, mut connection) = Client::new(options, 64);
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        let thread = thread::spawn({
+            let client = client.clone();
+            let should_quit = should_quit.clone();
+            let config = config.clone();
+            move || {
+                let mut states: HashMap<usize, LightState> = ledstrip_ids
+                    .iter()
+                    .map(|id| (*id, LightState::default()))
+                    .collect();
+
+                for notification in connection.iter() {
+                    if should_quit.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    match notification {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                            tracing::info!("Connected to mqtt broker {}", config.host);
+                            on_connect(&client, &config, &states, &effect_ids);
+                        }
+                        Ok(Event::Incoming(Packet::Publish(publish))) => {
+                            on_command(
+                                &client,
+                                &config,
+                                &mut states,
+                                &publish.topic,
+                                &publish.payload,
+                                &sender,
+                            );
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            tracing::warn!("Mqtt connection error: {e}. Retrying.");
+                            thread::sleep(Duration::from_secs(1));
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            client,
+            availability_topic: availability_topic(config),
+            thread: Some(thread),
+            should_quit,
+        }
+    }
+}
+
+impl Drop for MqttClient {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        // Publishing is best effort. The last will takes care of it if we can't.
+        let _ = self.client.try_publish(
+            self.availability_topic.clone(),
+            QoS::AtLeastOnce,
+            true,
+            "offline",
+        );
+        let _ = self.client.try_disconnect();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Mqtt thread panicked");
+            }
+        }
+        tracing::info!("Mqtt client stopped.");
+    }
+}
+
+fn availability_topic(config: &MqttConfig) -> String {
+    format!("{}/availability", config.topic_prefix)
+}
+
+fn ledstrip_topic(config: &MqttConfig, ledstrip_id: usize) -> String {
+    format!("{}/ledstrip/{ledstrip_id}", config.topic_prefix)
+}
+
+fn publish_state(client: &Client, config: &MqttConfig, ledstrip_id: usize, state: &LightState) {
+    let payload = serde_json::to_vec(state).unwrap();
+    if let Err(e) = client.try_publish(
+        format!("{}/state", ledstrip_topic(config, ledstrip_id)),
+        QoS::AtLeastOnce,
+        true,
+        payload,
+    ) {
+        tracing::error!("Couldn't publish the state of ledstrip {ledstrip_id}: {e}");
+    }
+}
+
+fn on_connect(
+    client: &Client,
+    config: &MqttConfig,
+    states: &HashMap<usize, LightState>,
+    effect_ids: &[usize],
+) {
+    let effect_list: Vec<String> = effect_ids.iter().map(|id| id.to_string()).collect();
+    for (ledstrip_id, state) in states {
+        let topic = ledstrip_topic(config, *ledstrip_id);
+        let unique_id = format!("{}_ledstrip_{ledstrip_id}", config.client_id);
+        let discovery = serde_json::json!({
+            "name": format!("Ledstrip {ledstrip_id}"),
+            "unique_id": unique_id,
+            "schema": "json",
+            "command_topic": format!("{topic}/set"),
+            "state_topic": format!("{topic}/state"),
+            "availability_topic": availability_topic(config),
+            "brightness": true,
+            "effect": true,
+            "effect_list": effect_list,
+            "device": {
+                "identifiers": [config.client_id],
+                "name": "TurboAudio",
+                "sw_version": env!("CARGO_PKG_VERSION"),
+            },
+        });
+
+        let published = client
+            .try_publish(
+                format!(
+                    "{}/light/{}/ledstrip_{ledstrip_id}/config",
+                    config.discovery_prefix, config.client_id
+                ),
+                QoS::AtLeastOnce,
+                true,
+                serde_json::to_vec(&discovery).unwrap(),
+            )
+            .and_then(|_| client.try_subscribe(format!("{topic}/set"), QoS::AtLeastOnce));
+        if let Err(e) = published {
+            tracing::error!("Couldn't register ledstrip {ledstrip_id} to home assistant: {e}");
+        }
+        publish_state(client, config, *ledstrip_id, state);
+    }
+
+    if let Err(e) = client.try_publish(availability_topic(config), QoS::AtLeastOnce, true, "online")
+    {
+        tracing::error!("Couldn't publish availability: {e}");
+    }
+}
+
+fn on_command(
+    client: &Client,
+    config: &MqttConfig,
+    states: &mut HashMap<usize, LightState>,
+    topic: &str,
+    payload: &[u8],
+    sender: &ControlSender,
+) {
+    let Some(ledstrip_id) = command_ledstrip(&config.topic_prefix, topic) else {
+        return;
+    };
+    let Some(state) = states.get_mut(&ledstrip_id) else {
+        return;
+    };
+    match apply_command(state, ledstrip_id, payload) {
+        Ok(commands) => {
+            for command in commands {
+                let _ = sender.send(command);
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Invalid mqtt command for ledstrip {ledstrip_id}: {e}");
+            return;
+        }
+    }
+
+    publish_state(client, config, ledstrip_id, state);
+}
+
+/// Ledstrip of a command topic, like `<topic_prefix>/ledstrip/3/set`
+pub fn command_ledstrip(topic_prefix: &str, topic: &str) -> Option<usize> {
+    topic
+        .strip_prefix(&format!("{topic_prefix}/ledstrip/"))
+        .and_then(|topic| topic.strip_suffix("/set"))
+        .and_then(|id| id.parse::<usize>().ok())
+}
+
+/// Applies the json payload of a command to the state of a ledstrip, giving the commands for
+/// the engine. Public for the fuzz targets
+pub fn apply_command(
+    state: &mut LightState,
+    ledstrip_id: usize,
+    payload: &[u8],
+) -> Result<Vec<ControlCommand>, serde_json::Error> {
+    let command: LightCommand = serde_json::from_slice(payload)?;
+
+    if let Some(on_off) = command.state {
+        state.state = on_off;
+    }
+    if let Some(brightness) = command.brightness {
+        state.brightness = brightness;
+    }
+    let brightness = if state.state == "ON" {
+        state.brightness as f32 / u8::MAX as f32
+    } else {
+        0.0
+    };
+    let mut commands = vec![ControlCommand::SetLedstripBrightness {
+        ledstrip_id,
+        brightness,
+    }];
+
+    if let Some(effect) = command.effect {
+        match effect.parse::<usize>() {
+            Ok(effect_id) => {
+                commands.push(ControlCommand::SwitchLedstripEffect {
+                    ledstrip_id,
+                    segment: 0,
+                    effect_id,
+                });
+                state.effect = Some(effect);
+            }
+            Err(_) => tracing::warn!("Unknown effect {effect} for ledstrip {ledstrip_id}"),
+        }
+    }
+    Ok(commands)
+}
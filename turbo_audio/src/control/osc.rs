@@ -0,0 +1,560 @@
+use super::{ControlCommand, ControlSender};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use thiserror::Error;
+
+// Bundles within bundles decoded, so that a hostile packet can't overflow the stack
+const MAX_BUNDLE_DEPTH: usize = 8;
+
+#[derive(Error, Debug)]
+pub enum OscError {
+    #[error("Packet is truncated")]
+    Truncated,
+
+    #[error("String is not valid utf-8")]
+    InvalidString,
+
+    #[error("Unsupported argument type: {0}")]
+    UnsupportedType(char),
+
+    #[error("Unknown address: {0}")]
+    UnknownAddress(String),
+
+    #[error("Message to {0} has no argument")]
+    MissingArgument(String),
+
+    #[error("Bundles are nested deeper than {MAX_BUNDLE_DEPTH} levels")]
+    TooDeep,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArgument {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub arguments: Vec<OscArgument>,
+}
+
+/// Listens for OSC messages on a UDP socket and translates them into [`ControlCommand`]s.
+///
+/// Supported addresses:
+/// - `/turbo/effect/<effect_id>/<setting>`: sets `<setting>` in the settings of `<effect_id>`
+///   to the first argument of the message.
+/// - `/turbo/brightness`: sets the global brightness (0 to 1).
+/// - `/turbo/ledstrip/<ledstrip_id>/brightness`: sets the brightness of a ledstrip (0 to 1).
+/// - `/turbo/ledstrip/<ledstrip_id>/<segment>/effect`: renders the effect whose id is the first
+///   argument on the `<segment>`th segment of the ledstrip.
+/// - `/turbo/freeze/<effect_id>`: freezes (true) or unfreezes (false) the effect, which keeps
+///   showing its last output while frozen.
+/// - `/turbo/pause`: pauses (true) or resumes (false) the rendering of the effects.
+/// - `/turbo/blackout`: sends black frames to every ledstrip (true) or stops doing so (false).
+/// - `/turbo/ledstrip/<ledstrip_id>/blackout`: same for a single ledstrip.
+/// - `/turbo/freeze`: keeps sending the current frame without rendering the effects (true) or
+///   renders them again (false).
+/// - `/turbo/ledstrip/<ledstrip_id>/freeze`: same for a single ledstrip.
+/// - `/turbo/solo`: blacks out every ledstrip but the one whose id is the first argument, or
+///   ends the solo if it's negative.
+/// - `/turbo/night_mode`: turns the night mode on (true) or off (false) until its hours next
+///   start or end.
+/// - `/turbo/sync/offset`: sets the audio to light offset in ms.
+/// - `/turbo/sync/test`: toggles the sync test mode, which flashes the ledstrips on clicks.
+pub struct OscServer {
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+}
+
+impl OscServer {
+    pub fn new(address: SocketAddr, sender: ControlSender) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        // Wake up regularly to check if we should quit
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        tracing::info!("Listening for OSC messages on {address}");
+
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        let thread = thread::spawn({
+            let should_quit = should_quit.clone();
+            move || Self::listen(socket, sender, should_quit)
+        });
+
+        Ok(Self {
+            thread: Some(thread),
+            should_quit,
+        })
+    }
+
+    fn listen(socket: UdpSocket, sender: ControlSender, should_quit: Arc<AtomicBool>) {
+        let mut buffer = [0u8; 1536];
+        while !should_quit.load(Ordering::Relaxed) {
+            let size = match socket.recv(&mut buffer) {
+                Ok(size) => size,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    tracing::error!("OSC socket error: {e}");
+                    return;
+                }
+            };
+
+            let messages = match decode_packet(&buffer[..size]) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    tracing::warn!("Dropping invalid OSC packet: {e}");
+                    continue;
+                }
+            };
+
+            for message in messages {
+                match to_command(message) {
+                    Ok(command) => {
+                        if sender.send(command).is_err() {
+                            // The run loop is gone, nobody will ever read our commands
+                            return;
+                        }
+                    }
+                    Err(e) => tracing::warn!("Ignoring OSC message: {e}"),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for OscServer {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("OSC server thread panicked");
+            }
+        }
+        tracing::info!("OSC server stopped.");
+    }
+}
+
+/// The command an OSC message stands for
+pub fn to_command(message: OscMessage) -> Result<ControlCommand, OscError> {
+    let parse_id = |id: &str| {
+        id.parse::<usize>()
+            .map_err(|_| OscError::UnknownAddress(message.address.clone()))
+    };
+    let value: serde_json::Value = match message.arguments.first() {
+        Some(argument) => argument.clone().into(),
+        None => return Err(OscError::MissingArgument(message.address.clone())),
+    };
+
+    let parts: Vec<&str> = message.address.split('/').skip(1).collect();
+    match parts.as_slice() {
+        ["turbo", "effect", effect_id, key] => Ok(ControlCommand::UpdateEffectSetting {
+            effect_id: parse_id(effect_id)?,
+            key: key.to_string(),
+            value,
+        }),
+        ["turbo", "settings", settings_id, key] => Ok(ControlCommand::PatchSettings {
+            settings_id: parse_id(settings_id)?,
+            patch: serde_json::json!({ *key: value }),
+        }),
+        ["turbo", "brightness"] => match value.as_f64() {
+            Some(brightness) => Ok(ControlCommand::SetBrightness(brightness as f32)),
+            None => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "ledstrip", ledstrip_id, "brightness"] => match value.as_f64() {
+            Some(brightness) => Ok(ControlCommand::SetLedstripBrightness {
+                ledstrip_id: parse_id(ledstrip_id)?,
+                brightness: brightness as f32,
+            }),
+            None => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "ledstrip", ledstrip_id, segment, "effect"] => match value.as_u64() {
+            Some(effect_id) => Ok(ControlCommand::SwitchLedstripEffect {
+                ledstrip_id: parse_id(ledstrip_id)?,
+                segment: parse_id(segment)?,
+                effect_id: effect_id as usize,
+            }),
+            None => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "freeze", effect_id] => match (value.as_bool(), value.as_i64()) {
+            (Some(frozen), _) => Ok(ControlCommand::SetEffectFrozen {
+                effect_id: parse_id(effect_id)?,
+                frozen,
+            }),
+            (None, Some(frozen)) => Ok(ControlCommand::SetEffectFrozen {
+                effect_id: parse_id(effect_id)?,
+                frozen: frozen != 0,
+            }),
+            _ => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "blackout"] => match (value.as_bool(), value.as_i64()) {
+            (Some(on), _) => Ok(ControlCommand::SetBlackout {
+                ledstrip_id: None,
+                on,
+            }),
+            (None, Some(on)) => Ok(ControlCommand::SetBlackout {
+                ledstrip_id: None,
+                on: on != 0,
+            }),
+            _ => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "ledstrip", ledstrip_id, "blackout"] => match (value.as_bool(), value.as_i64()) {
+            (Some(on), _) => Ok(ControlCommand::SetBlackout {
+                ledstrip_id: Some(parse_id(ledstrip_id)?),
+                on,
+            }),
+            (None, Some(on)) => Ok(ControlCommand::SetBlackout {
+                ledstrip_id: Some(parse_id(ledstrip_id)?),
+                on: on != 0,
+            }),
+            _ => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "freeze"] => match (value.as_bool(), value.as_i64()) {
+            (Some(frozen), _) => Ok(ControlCommand::FreezeFrame {
+                ledstrip_id: None,
+                frozen,
+            }),
+            (None, Some(frozen)) => Ok(ControlCommand::FreezeFrame {
+                ledstrip_id: None,
+                frozen: frozen != 0,
+            }),
+            _ => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "ledstrip", ledstrip_id, "freeze"] => match (value.as_bool(), value.as_i64()) {
+            (Some(frozen), _) => Ok(ControlCommand::FreezeFrame {
+                ledstrip_id: Some(parse_id(ledstrip_id)?),
+                frozen,
+            }),
+            (None, Some(frozen)) => Ok(ControlCommand::FreezeFrame {
+                ledstrip_id: Some(parse_id(ledstrip_id)?),
+                frozen: frozen != 0,
+            }),
+            _ => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "solo"] => match value.as_i64() {
+            Some(ledstrip_id) => Ok(ControlCommand::SetSolo(usize::try_from(ledstrip_id).ok())),
+            None => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "pause"] => match (value.as_bool(), value.as_i64()) {
+            (Some(paused), _) => Ok(ControlCommand::SetPaused(paused)),
+            (None, Some(paused)) => Ok(ControlCommand::SetPaused(paused != 0)),
+            _ => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "night_mode"] => match (value.as_bool(), value.as_i64()) {
+            (Some(on), _) => Ok(ControlCommand::SetNightMode(on)),
+            (None, Some(on)) => Ok(ControlCommand::SetNightMode(on != 0)),
+            _ => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "sync", "offset"] => match value.as_f64() {
+            Some(offset_ms) => Ok(ControlCommand::SetSyncOffset(offset_ms.round() as i32)),
+            None => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        ["turbo", "sync", "test"] => match (value.as_bool(), value.as_i64()) {
+            (Some(enabled), _) => Ok(ControlCommand::SetSyncTestMode(enabled)),
+            (None, Some(enabled)) => Ok(ControlCommand::SetSyncTestMode(enabled != 0)),
+            _ => Err(OscError::UnknownAddress(message.address.clone())),
+        },
+        _ => Err(OscError::UnknownAddress(message.address.clone())),
+    }
+}
+
+impl From<OscArgument> for serde_json::Value {
+    fn from(argument: OscArgument) -> Self {
+        match argument {
+            OscArgument::Int(value) => value.into(),
+            OscArgument::Long(value) => value.into(),
+            OscArgument::Float(value) => value.into(),
+            OscArgument::Double(value) => value.into(),
+            OscArgument::String(value) => value.into(),
+            OscArgument::Bool(value) => value.into(),
+        }
+    }
+}
+
+/// Decodes an OSC packet, flattening bundles into the messages they contain.
+pub fn decode_packet(data: &[u8]) -> Result<Vec<OscMessage>, OscError> {
+    let mut messages = vec![];
+    decode_packet_into(data, &mut messages, 0)?;
+    Ok(messages)
+}
+
+fn decode_packet_into(
+    data: &[u8],
+    messages: &mut Vec<OscMessage>,
+    depth: usize,
+) -> Result<(), OscError> {
+    let mut reader = OscReader { data, position: 0 };
+    if data.starts_with(b"#bundle\0") {
+        if depth == MAX_BUNDLE_DEPTH {
+            return Err(OscError::TooDeep);
+        }
+        reader.position = 8;
+        // Time tag. Everything is applied immediately.
+        reader.take(8)?;
+        while reader.position < data.len() {
+            let size = reader.read_i32()? as usize;
+            decode_packet_into(reader.take(size)?, messages, depth + 1)?;
+        }
+        return Ok(());
+    }
+
+    let address = reader.read_string()?;
+    // Some old implementations don't send the type tag string
+    if reader.position == data.len() {
+        messages.push(OscMessage {
+            address,
+            arguments: vec![],
+        });
+        return Ok(());
+    }
+
+    let type_tags = reader.read_string()?;
+    let mut arguments = vec![];
+    for tag in type_tags.chars().skip_while(|c| *c == ',') {
+        let argument = match tag {
+            'i' => OscArgument::Int(reader.read_i32()?),
+            'h' => OscArgument::Long(i64::from_be_bytes(reader.take_array()?)),
+            'f' => OscArgument::Float(f32::from_be_bytes(reader.take_array()?)),
+            'd' => OscArgument::Double(f64::from_be_bytes(reader.take_array()?)),
+            's' => OscArgument::String(reader.read_string()?),
+            'T' => OscArgument::Bool(true),
+            'F' => OscArgument::Bool(false),
+            tag => return Err(OscError::UnsupportedType(tag)),
+        };
+        arguments.push(argument);
+    }
+
+    messages.push(OscMessage { address, arguments });
+    Ok(())
+}
+
+struct OscReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> OscReader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], OscError> {
+        let end = self.position.checked_add(size).ok_or(OscError::Truncated)?;
+        let slice = self
+            .data
+            .get(self.position..end)
+            .ok_or(OscError::Truncated)?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], OscError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn read_i32(&mut self) -> Result<i32, OscError> {
+        Ok(i32::from_be_bytes(self.take_array()?))
+    }
+
+    /// Reads a null terminated string padded to a multiple of 4 bytes
+    fn read_string(&mut self) -> Result<String, OscError> {
+        let remaining = &self.data[self.position..];
+        let length = remaining
+            .iter()
+            .position(|c| *c == 0)
+            .ok_or(OscError::Truncated)?;
+        let string = std::str::from_utf8(&remaining[..length])
+            .map_err(|_| OscError::InvalidString)?
+            .to_owned();
+        let padded_length = (length + 4) & !3;
+        self.take(padded_length.min(remaining.len()))?;
+        Ok(string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_packet, to_command, OscArgument, OscError, OscMessage, MAX_BUNDLE_DEPTH};
+    use crate::control::ControlCommand;
+
+    // Null terminated and padded to a multiple of 4 bytes
+    fn string(value: &str) -> Vec<u8> {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.resize((bytes.len() + 4) & !3, 0);
+        bytes
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = b"#bundle\0".to_vec();
+        bytes.extend(1u64.to_be_bytes());
+        for element in elements {
+            bytes.extend((element.len() as i32).to_be_bytes());
+            bytes.extend(element);
+        }
+        bytes
+    }
+
+    fn message(address: &str, argument: OscArgument) -> OscMessage {
+        OscMessage {
+            address: address.to_owned(),
+            arguments: vec![argument],
+        }
+    }
+
+    #[test]
+    fn decodes_the_arguments() {
+        let mut packet = string("/turbo/brightness");
+        packet.extend(string(",ifsT"));
+        packet.extend(7i32.to_be_bytes());
+        packet.extend(0.5f32.to_be_bytes());
+        packet.extend(string("rainbow"));
+
+        let messages = decode_packet(&packet).unwrap();
+
+        assert_eq!(
+            messages,
+            vec![OscMessage {
+                address: "/turbo/brightness".to_owned(),
+                arguments: vec![
+                    OscArgument::Int(7),
+                    OscArgument::Float(0.5),
+                    OscArgument::String("rainbow".to_owned()),
+                    OscArgument::Bool(true),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn flattens_the_bundles() {
+        let mut first = string("/turbo/pause");
+        first.extend(string(",T"));
+        let mut second = string("/turbo/blackout");
+        second.extend(string(",F"));
+        let packet = bundle(&[first, bundle(&[second])]);
+
+        let messages = decode_packet(&packet).unwrap();
+
+        let addresses: Vec<&str> = messages.iter().map(|m| m.address.as_str()).collect();
+        assert_eq!(addresses, ["/turbo/pause", "/turbo/blackout"]);
+    }
+
+    #[test]
+    fn rejects_the_malformed_packets() {
+        let mut packet = string("/turbo/brightness");
+        packet.extend(string(",f"));
+        packet.extend([0, 0]);
+        assert!(matches!(decode_packet(&packet), Err(OscError::Truncated)));
+
+        assert!(matches!(
+            decode_packet(b"/turbo/brightness"),
+            Err(OscError::Truncated)
+        ));
+
+        let mut packet = string("/turbo/brightness");
+        packet.extend(string(",b"));
+        assert!(matches!(
+            decode_packet(&packet),
+            Err(OscError::UnsupportedType('b'))
+        ));
+
+        assert!(matches!(
+            decode_packet(b"/\xff\0\0"),
+            Err(OscError::InvalidString)
+        ));
+
+        // A bundle claiming more bytes than the packet has
+        let mut packet = bundle(&[]);
+        packet.extend(i32::MAX.to_be_bytes());
+        assert!(matches!(decode_packet(&packet), Err(OscError::Truncated)));
+        let mut packet = bundle(&[]);
+        packet.extend((-1i32).to_be_bytes());
+        assert!(matches!(decode_packet(&packet), Err(OscError::Truncated)));
+    }
+
+    #[test]
+    fn limits_the_nesting_of_the_bundles() {
+        let mut packet = string("/turbo/pause");
+        packet.extend(string(",T"));
+        for _ in 0..MAX_BUNDLE_DEPTH {
+            packet = bundle(&[packet]);
+        }
+        assert_eq!(decode_packet(&packet).unwrap().len(), 1);
+
+        let packet = bundle(&[packet]);
+        assert!(matches!(decode_packet(&packet), Err(OscError::TooDeep)));
+    }
+
+    #[test]
+    fn maps_the_addresses_to_commands() {
+        assert!(matches!(
+            to_command(message("/turbo/brightness", OscArgument::Float(0.5))),
+            Ok(ControlCommand::SetBrightness(brightness)) if brightness == 0.5
+        ));
+        assert!(matches!(
+            to_command(message("/turbo/ledstrip/2/1/effect", OscArgument::Int(4))),
+            Ok(ControlCommand::SwitchLedstripEffect {
+                ledstrip_id: 2,
+                segment: 1,
+                effect_id: 4,
+            })
+        ));
+        assert!(matches!(
+            to_command(message("/turbo/ledstrip/3/freeze", OscArgument::Int(1))),
+            Ok(ControlCommand::FreezeFrame {
+                ledstrip_id: Some(3),
+                frozen: true,
+            })
+        ));
+        assert!(matches!(
+            to_command(message("/turbo/solo", OscArgument::Int(-1))),
+            Ok(ControlCommand::SetSolo(None))
+        ));
+        assert!(matches!(
+            to_command(message("/turbo/effect/5/speed", OscArgument::Double(2.0))),
+            Ok(ControlCommand::UpdateEffectSetting { effect_id: 5, ref key, ref value })
+                if key == "speed" && value.as_f64() == Some(2.0)
+        ));
+    }
+
+    #[test]
+    fn rejects_the_unknown_messages() {
+        assert!(matches!(
+            to_command(message("/turbo/volume", OscArgument::Float(1.0))),
+            Err(OscError::UnknownAddress(_))
+        ));
+        assert!(matches!(
+            to_command(message(
+                "/turbo/ledstrip/-1/brightness",
+                OscArgument::Float(1.0)
+            )),
+            Err(OscError::UnknownAddress(_))
+        ));
+        assert!(matches!(
+            to_command(message(
+                "/turbo/brightness",
+                OscArgument::String("full".to_owned())
+            )),
+            Err(OscError::UnknownAddress(_))
+        ));
+        assert!(matches!(
+            to_command(OscMessage {
+                address: "/turbo/pause".to_owned(),
+                arguments: vec![],
+            }),
+            Err(OscError::MissingArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,362 @@
+use super::{ControlCommand, ControlSender};
+use crate::{
+    audio::audio_stream::list_input_devices, overrides::SegmentOverride, test_pattern::TestPattern,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// How long a request waits for the run loop to answer before giving up
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Request sent by `turbo_audio ctl`, one json object per line
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SocketRequest {
+    /// Evaluates lua in the environment of an effect
+    Lua {
+        effect_id: usize,
+        code: String,
+        write: bool,
+    },
+    /// Names of the audio devices that can be listened to, one per line
+    ListAudioDevices,
+    /// Listens to another audio device, or to the default input
+    SetAudioDevice { name: Option<String> },
+    /// Measures the noise floor of the audio input
+    CalibrateNoiseFloor { seconds: f32 },
+    /// Writes the audio input to a wav file
+    CaptureAudio { path: PathBuf, seconds: f32 },
+    /// Writes the frames of the ledstrips to a gif, png or json file, every ledstrip if empty
+    CaptureFrames {
+        path: PathBuf,
+        seconds: f32,
+        ledstrips: Vec<usize>,
+    },
+    /// Measures the audio to light latency with clicks
+    CalibrateLatency { seconds: f32 },
+    /// Shows a test pattern on a connection instead of its ledstrips, stops it if missing
+    SetTestPattern {
+        connection_id: usize,
+        pattern: Option<TestPattern>,
+    },
+    /// Switches to a profile of the config
+    SetProfile { name: String },
+    /// Renders an effect on some segments in place of the previous override of the channel
+    SetOverride {
+        channel: String,
+        segment_override: SegmentOverride,
+    },
+    /// Gives the segments overridden on a channel back to their effect
+    ReleaseOverride { channel: String },
+    /// Starts or ends the blackout of a ledstrip, or the global one if missing
+    SetBlackout {
+        ledstrip_id: Option<usize>,
+        on: bool,
+    },
+    /// Freezes or unfreezes the frame of a ledstrip, or of every ledstrip if missing
+    FreezeFrame {
+        ledstrip_id: Option<usize>,
+        frozen: bool,
+    },
+    /// Blacks out every ledstrip but one, or ends the solo if missing
+    SetSolo { ledstrip_id: Option<usize> },
+}
+
+/// Answer to a [`SocketRequest`], one json object per line
+pub type SocketResponse = Result<String, String>;
+
+/// Local control socket used by the `turbo_audio ctl` commands.
+///
+/// Requests are [`SocketRequest`]s and each one is answered with a [`SocketResponse`]. The lua
+/// console is read-only unless the request opts in to writing.
+pub struct ControlSocket {
+    path: PathBuf,
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+}
+
+impl ControlSocket {
+    pub fn new(
+        path: &Path,
+        sender: ControlSender,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        // A previous instance that didn't stop cleanly leaves its socket behind, which refuses
+        // connections. Anything else is kept, in case the path is mistyped or the instance still
+        // runs
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => match UnixStream::connect(path) {
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => std::fs::remove_file(path)?,
+                Ok(_) => {
+                    return Err(
+                        format!("An instance is already running on {}", path.display()).into(),
+                    );
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "{} may belong to an instance already running: {e}",
+                        path.display()
+                    )
+                    .into());
+                }
+            },
+            Ok(_) => {
+                return Err(format!("{} exists and isn't a socket", path.display()).into());
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        tracing::info!("Listening for control requests on {}", path.display());
+
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        let thread = thread::spawn({
+            let should_quit = should_quit.clone();
+            move || {
+                while !should_quit.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let sender = sender.clone();
+                            let should_quit = should_quit.clone();
+                            thread::spawn(move || {
+                                if let Err(e) = handle_client(stream, &sender, &should_quit) {
+                                    tracing::warn!("Control socket client error: {e}");
+                                }
+                            });
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                        Err(e) => {
+                            tracing::error!("Control socket error: {e}");
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_owned(),
+            thread: Some(thread),
+            should_quit,
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Control socket thread panicked");
+            }
+        }
+        let _ = std::fs::remove_file(&self.path);
+        tracing::info!("Control socket stopped.");
+    }
+}
+
+fn handle_client(
+    stream: UnixStream,
+    sender: &ControlSender,
+    should_quit: &AtomicBool,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    // Wake up regularly so the client doesn't outlive the engine
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    while !should_quit.load(Ordering::Relaxed) {
+        match reader.read_line(&mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e),
+        }
+
+        let response = answer(&line, sender);
+        line.clear();
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+/// Answers a line of json holding a [`SocketRequest`]. Public for the fuzz targets
+pub fn answer(line: &str, sender: &ControlSender) -> SocketResponse {
+    match serde_json::from_str::<SocketRequest>(line) {
+        Ok(request) => handle_request(request, sender),
+        Err(e) => Err(format!("Invalid request: {e}")),
+    }
+}
+
+fn handle_request(request: SocketRequest, sender: &ControlSender) -> SocketResponse {
+    tracing::debug!("Control socket request: {request:?}");
+    match request {
+        SocketRequest::Lua {
+            effect_id,
+            code,
+            write,
+        } => ask(sender, |reply| ControlCommand::EvalLua {
+            effect_id,
+            code,
+            write,
+            reply,
+        }),
+        SocketRequest::ListAudioDevices => list_input_devices()
+            .map(|devices| devices.join("\n"))
+            .map_err(|e| format!("{e:#}")),
+        SocketRequest::SetAudioDevice { name } => {
+            ask(sender, |reply| ControlCommand::SetAudioDevice {
+                device_name: name.clone(),
+                reply,
+            })
+            .map(|()| {
+                format!(
+                    "Listening to {}",
+                    name.as_deref().unwrap_or("the default input")
+                )
+            })
+        }
+        SocketRequest::CalibrateNoiseFloor { seconds } => ask(sender, |reply| {
+            ControlCommand::CalibrateNoiseFloor { seconds, reply }
+        })
+        .map(|()| format!("Measuring the noise floor for {seconds}s, keep the room quiet")),
+        SocketRequest::CaptureAudio { path, seconds } => {
+            ask(sender, |reply| ControlCommand::CaptureAudio {
+                path: path.clone(),
+                seconds,
+                reply,
+            })
+            .map(|()| format!("Capturing {seconds}s of audio to {}", path.display()))
+        }
+        SocketRequest::CaptureFrames {
+            path,
+            seconds,
+            ledstrips,
+        } => ask(sender, |reply| ControlCommand::CaptureFrames {
+            path: path.clone(),
+            seconds,
+            ledstrips,
+            reply,
+        })
+        .map(|()| format!("Capturing {seconds}s of frames to {}", path.display())),
+        SocketRequest::CalibrateLatency { seconds } => ask(sender, |reply| {
+            ControlCommand::CalibrateLatency { seconds, reply }
+        })
+        .map(|()| {
+            format!("Playing clicks for {seconds}s, turn the speakers up and keep the room quiet")
+        }),
+        SocketRequest::SetTestPattern {
+            connection_id,
+            pattern,
+        } => ask(sender, |reply| ControlCommand::SetTestPattern {
+            connection_id,
+            pattern,
+            reply,
+        })
+        .map(|()| match pattern {
+            Some(pattern) => format!("Showing {pattern:?} on connection {connection_id}"),
+            None => format!("Connection {connection_id} shows its ledstrips again"),
+        }),
+        SocketRequest::SetProfile { name } => ask(sender, |reply| ControlCommand::SetProfile {
+            name: name.clone(),
+            reply,
+        })
+        .map(|()| format!("Switched to the profile {name}")),
+        SocketRequest::SetOverride {
+            channel,
+            segment_override,
+        } => {
+            let effect_id = segment_override.effect_id;
+            ask(sender, |reply| ControlCommand::SetOverride {
+                channel: channel.clone(),
+                segment_override,
+                reply,
+            })
+            .map(|()| format!("Overriding the segments with effect {effect_id} on {channel}"))
+        }
+        SocketRequest::ReleaseOverride { channel } => {
+            ask(sender, |reply| ControlCommand::ReleaseOverride {
+                channel: channel.clone(),
+                reply,
+            })
+            .map(|()| format!("Released the override of {channel}"))
+        }
+        SocketRequest::SetBlackout { ledstrip_id, on } => {
+            tell(sender, ControlCommand::SetBlackout { ledstrip_id, on }).map(|()| {
+                let action = if on {
+                    "Blacking out"
+                } else {
+                    "Ending the blackout of"
+                };
+                format!("{action} {}", ledstrips(ledstrip_id))
+            })
+        }
+        SocketRequest::FreezeFrame {
+            ledstrip_id,
+            frozen,
+        } => tell(
+            sender,
+            ControlCommand::FreezeFrame {
+                ledstrip_id,
+                frozen,
+            },
+        )
+        .map(|()| {
+            let action = if frozen { "Freezing" } else { "Unfreezing" };
+            format!("{action} {}", ledstrips(ledstrip_id))
+        }),
+        SocketRequest::SetSolo { ledstrip_id } => {
+            tell(sender, ControlCommand::SetSolo(ledstrip_id)).map(|()| match ledstrip_id {
+                Some(ledstrip_id) => format!("Soloing ledstrip {ledstrip_id}"),
+                None => "Ending the solo".to_owned(),
+            })
+        }
+    }
+}
+
+// Ledstrips a command applies to, every one if missing
+fn ledstrips(ledstrip_id: Option<usize>) -> String {
+    match ledstrip_id {
+        Some(ledstrip_id) => format!("ledstrip {ledstrip_id}"),
+        None => "every ledstrip".to_owned(),
+    }
+}
+
+/// Sends a command that isn't answered to the run loop
+fn tell(sender: &ControlSender, command: ControlCommand) -> Result<(), String> {
+    sender
+        .send(command)
+        .map_err(|_| "Engine is stopped".to_owned())
+}
+
+/// Sends a command to the run loop and waits for its answer
+fn ask<T>(
+    sender: &ControlSender,
+    command: impl FnOnce(std::sync::mpsc::Sender<Result<T, String>>) -> ControlCommand,
+) -> Result<T, String> {
+    let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+    if sender.send(command(reply_tx)).is_err() {
+        return Err("Engine is stopped".to_owned());
+    }
+
+    reply_rx
+        .recv_timeout(REPLY_TIMEOUT)
+        .unwrap_or_else(|_| Err("Engine didn't answer".to_owned()))
+}
@@ -1,39 +1,382 @@
 use crate::{
-    audio::audio_processing::AudioSignalProcessor,
+    audio::{
+        audio_processing::{AudioSignalProcessor, FftResult, SharedFftResult},
+        percussion::{self, DrumClassifier},
+        smoothing::SmoothingProfile,
+    },
+    av_sync::{AvSync, AvSyncConfig},
+    cache::Cache,
+    color_lut::ColorLut,
+    config_parser::ProfileConfig,
+    connections::{
+        circuit_breaker::{CircuitBreaker, CircuitBreakerConfig},
+        encoder::{FrameEncoder, FrameEncoding},
+        keep_alive::{KeepAliveColor, KeepAliveConfig},
+        sender::{ConnectionSender, DispatchDelay, SendReport},
+        stats::{ConnectionStats, SharedConnectionInfo},
+        Connection, ConnectionError, LinkStatus,
+    },
+    control::ControlCommand,
+    frame_capture::{FrameCapture, FrameCaptureFormat, MAX_CAPTURE_SECONDS},
     hot_reloader::{HotReloader, WatchablePath},
-    plugins::effects::{lua::LuaEffectsManager, native::NativeEffectsManager},
-    resources::ledstrip::LedStrip,
-    Connection, Effect, EffectSettings,
+    idle::{IdleConfig, SilenceDetector},
+    info::{
+        ConnectionHealth, ConnectionInfo, ConnectionStatus, EffectInfo, EngineInfo, LedstripInfo,
+        LivePreview,
+    },
+    interpolation::FrameInterpolation,
+    latency::{Latency, LatencyCalibration},
+    night_mode::{NightMode, NightModeConfig},
+    overrides::{OverrideInfo, Overrides, SegmentOverride},
+    parameter_mapping::{
+        is_builtin_feature, AudioFeatures, BeatEnvelope, DerivedFeatures, EnvelopeConfig,
+        EvalContext, Expression, ExpressionError, SettingRange,
+    },
+    plugins::effects::{
+        lua::{LuaEffectSettings, LuaEffectsManager, LuaSandboxConfig},
+        merge_patch,
+        native::NativeEffectsManager,
+        registry::{
+            EffectRegistry, EffectSource, EffectTypeError, NATIVE_EFFECTS_FOLDER,
+            RHAI_EFFECTS_FOLDER,
+        },
+        rhai::RhaiEffectsManager,
+        Effect, EffectInstance, EffectSettings, SettingsError, SettingsHandle, TickError,
+    },
+    post_processing::{self, PostProcessingChain, ProcessingContext, TemporalDithering},
+    resources::{
+        ledstrip::{
+            self, EffectInterval, FallbackScene, FallbackScenes, LedStrip, LedStripEffect, Pixel,
+            UndersizedPolicy,
+        },
+        registry::{Registry, RegistryError},
+    },
+    schedule::{self, Schedule, ScheduleEntryConfig},
+    scheduler::{EffectBudgetConfig, EffectScheduler, RenderJob, RenderTarget},
+    screen::SharedScreenColors,
+    session::{SavedSession, SessionState},
+    sync::{
+        self, BeatCount, BeatTiming, BeatTracker, PhaseLock, SharedTransport, SyncClock, SyncPeer,
+        SyncRole,
+    },
+    test_pattern::{TestPattern, TestPatternRenderer},
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum EffectInstanceError {
+    #[error(transparent)]
+    Registry(#[from] RegistryError),
+
+    #[error(transparent)]
+    Type(#[from] EffectTypeError),
+
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
+
+    #[error("Couldn't load the effect {0}")]
+    Load(String),
+
+    #[error("Effect {0} doesn't exist")]
+    UnknownEffect(usize),
+
+    #[error("Effects of type {0} read the audio feature {1}, which the config doesn't derive")]
+    MissingFeature(String, String),
+
+    #[error("Ledstrip {0} doesn't have a segment {1}")]
+    UnknownSegment(usize, usize),
+
+    #[error("Audio source {0} doesn't exist")]
+    UnknownAudioSource(String),
+
+    #[error("Settings {0} don't exist")]
+    UnknownSettings(usize),
+
+    #[error("Settings {0} are native settings, which don't have values to change")]
+    NativeSettings(usize),
+
+    #[error("Effect {0} rejects the settings: {1}")]
+    RejectedSettings(usize, SettingsError),
+
+    #[error("Effect {0} is native, it can only react to the main audio device")]
+    NativeAudioSource(usize),
+
+    #[error("Effect {effect_id} is still rendered on segment {segment} of ledstrip {ledstrip_id}")]
+    StillAssigned {
+        effect_id: usize,
+        ledstrip_id: usize,
+        segment: usize,
+    },
+
+    #[error("Ledstrip {0} doesn't exist")]
+    UnknownLedstrip(usize),
+
+    #[error("Nothing overrides the segments on channel {0}")]
+    UnknownOverride(String),
+}
+
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    #[error("Profile {0} doesn't exist")]
+    Unknown(String),
+
+    #[error("The config has no profile")]
+    NoProfiles,
+
+    #[error(transparent)]
+    Effect(#[from] EffectInstanceError),
+}
+
+/// Why an effect wasn't rendered on a segment. The segment is skipped and the rest of the
+/// ledstrips keep rendering
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error(
+        "Segment {interval:?} of effect {effect_id} is out of the {size} leds of the ledstrip"
+    )]
+    InvalidInterval {
+        effect_id: usize,
+        interval: EffectInterval,
+        size: usize,
+    },
+
+    #[error("Effect {0} doesn't exist")]
+    MissingEffect(usize),
+
+    #[error("Effect {0} doesn't have settings")]
+    MissingSettings(usize),
+
+    #[error("Effect {0} failed to render: {1}")]
+    Tick(usize, TickError),
+}
+
+// Frame sent to a connection every tick, made of one ledstrip or of all the ledstrips with an
+// offset on the connection
+#[derive(Clone)]
+struct OutgoingFrame {
+    // Ledstrip the frame is kept and routed under, the first one of the multiplexed ledstrips.
+    // They fail over together to its fallback connection
+    ledstrip_id: usize,
+    connection_id: usize,
+    // Ledstrips in the frame with the index of their first led in it
+    parts: Vec<(usize, usize)>,
+}
+
+impl OutgoingFrame {
+    fn led_count(&self, led_strips: &Registry<LedStrip>) -> usize {
+        self.parts
+            .iter()
+            .filter_map(|(ledstrip_id, offset)| {
+                Some(offset + led_strips.get(*ledstrip_id)?.led_count())
+            })
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+// Frame that was last sent to a ledstrip
+struct SentFrame {
+    data: Vec<u8>,
+    sent_at: Instant,
+}
+
+// Buffers an outgoing frame is built in, kept from one tick to the next so that sending doesn't
+// allocate
+#[derive(Default)]
+struct FrameBuffers {
+    channels: Vec<u16>,
+    bytes: Vec<u8>,
+}
 
 #[allow(unused)]
 pub struct Controller {
-    // settings id to EffectsSettings
-    settings: HashMap<usize, EffectSettings>,
-    // effect id to Effect. Is an option so that we can drop them first
-    effects: Option<HashMap<usize, Effect>>,
-    // effect id to settings id.
-    effect_settings: HashMap<usize, usize>,
-
-    // connection id to connection
-    connections: HashMap<usize, Connection>,
+    // settings id to the settings, shared with the effects linked to them
+    settings: Registry<SettingsHandle>,
+    // effect id to the effect and its settings. Is an option so that we can drop them first
+    effects: Option<Registry<EffectInstance>>,
+    // effect id to the settings recomputed every frame and their expression
+    parameter_bindings: HashMap<usize, Vec<(String, Expression, SettingRange)>>,
+    derived_features: DerivedFeatures,
+    // Edges of the screen, stored by the screen capture and read by the lua effects
+    screen_colors: SharedScreenColors,
+    // State of the connections as of the last frame sent, read by the effects
+    connection_snapshot: SharedConnectionInfo,
+    // Read by the parameter bindings
+    fft_result: SharedFftResult,
+    // Fft results of the main device and of the other audio sources by name, for the effects
+    main_fft_results: Arc<HashMap<SmoothingProfile, SharedFftResult>>,
+    audio_sources: HashMap<String, Arc<HashMap<SmoothingProfile, SharedFftResult>>>,
+    started_at: Instant,
+
+    // connection id to the thread sending to the connection
+    connections: Registry<ConnectionSender>,
     // led strip id to ledstrip
-    led_strips: HashMap<usize, LedStrip>,
+    led_strips: Registry<LedStrip>,
 
-    // led strip id to connection id
-    led_strip_connections: HashMap<usize, usize>,
+    // led strip id to connection id. Ordered so that the ledstrips are sent to in the same order
+    // every frame
+    led_strip_connections: BTreeMap<usize, usize>,
+    // led strip id to the index of its first led in the frame of its connection. The ledstrips
+    // with an offset share one frame per connection
+    led_strip_offsets: HashMap<usize, usize>,
+    // led strip id to the connection id used while its connection is down
+    led_strip_fallbacks: HashMap<usize, usize>,
+    // Ledstrips currently sent to their fallback
+    failed_over: HashSet<usize>,
+    // Frames to send every tick, updated when the ledstrips are linked
+    outgoing_frames: Vec<OutgoingFrame>,
+    // led strip id of an outgoing frame to its buffers
+    frame_buffers: HashMap<usize, FrameBuffers>,
+
+    // connection id to the circuit breaker guarding its sends
+    connection_breakers: HashMap<usize, CircuitBreaker>,
+    circuit_breaker_config: CircuitBreakerConfig,
+    // connection id to the encoder framing the colors for its protocol
+    encoders: HashMap<usize, Box<dyn FrameEncoder>>,
+    // connection id to the keep-alive of its ledstrips
+    keep_alive: HashMap<usize, KeepAliveConfig>,
+    // Connections whose frames are dithered down to 8 bits instead of rounded
+    dithered_connections: HashSet<usize>,
+    // Connection id to the lookup tables correcting its frames
+    color_luts: HashMap<usize, ColorLut>,
+    // connection id to how long its frames are held back, for the delayed connections
+    dispatch_delays: HashMap<usize, DispatchDelay>,
+    // led strip id to the dithering of its frames, for the ledstrips sent to a dithered connection
+    dithering: HashMap<usize, TemporalDithering>,
+    // led strip id to the frame last sent to it
+    last_frames: HashMap<usize, SentFrame>,
+    // connection id to the frames sent and dropped on it
+    connection_stats: HashMap<usize, ConnectionStats>,
+    // Fraction of the tick spent working during the last tick. Above 1 the engine can't keep up
+    engine_load: f32,
 
     // Effects registry. Effect path to all its instance ids
     effects_registry: HashMap<PathBuf, Vec<usize>>,
+    // Effect type name to its constructor
+    effect_types: EffectRegistry,
+    // Settings allocated for the effects created at runtime, dropped with them
+    runtime_settings: HashSet<usize>,
 
     native_effect_manager: NativeEffectsManager,
     lua_effects_manager: LuaEffectsManager,
+    rhai_effects_manager: RhaiEffectsManager,
 
     hot_reloader: Option<HotReloader>,
+
+    // Global brightness applied to every ledstrip before sending, between 0 and 1
+    brightness: f32,
+    // led strip id to brightness. Missing strips are at full brightness
+    led_strip_brightness: HashMap<usize, f32>,
+    // led strip id to the stages applied to its colors before sending. Missing strips use the
+    // default chain
+    post_processing: HashMap<usize, PostProcessingChain>,
+
+    av_sync: AvSync,
+    // Frames sent in between the ticks, when more than one frame is sent per tick
+    interpolation: Option<FrameInterpolation>,
+    // Audio to light latency the beat is predicted ahead by, and where it's saved once calibrated
+    latency: Latency,
+    latency_file: PathBuf,
+    latency_calibration: Option<LatencyCalibration>,
+    // Effects aren't rendered while paused
+    paused: bool,
+    // Sends black frames whatever the effects and the brightness, until toggled off
+    blackout: bool,
+    // Same for single ledstrips
+    blackout_ledstrips: HashSet<usize>,
+    // Only ledstrip that isn't blacked out, if any
+    solo: Option<usize>,
+    // Keeps sending the current frame without rendering the effects
+    frame_frozen: bool,
+    // Same for single ledstrips, whose segments aren't rendered
+    frozen_ledstrips: HashSet<usize>,
+    // Follows the kicks for the `beat` feature of the bindings
+    beat_envelope: BeatEnvelope,
+    // Value of the beat envelope on the last frame
+    beat: f32,
+    // Effects that aren't ticked, so that their segments keep their last output
+    frozen_effects: HashSet<usize>,
+    // Effects replacing the ones of some segments for a while, on the channels of the control apis
+    overrides: Overrides,
+    // False when running without audio because the audio device isn't available
+    audio_available: bool,
+    // False until the first samples came in, while the ledstrips show their startup scene
+    audio_received: bool,
+    // Switches to the idle effect on silence. None if the config doesn't have one
+    silence_detector: Option<SilenceDetector>,
+    idle: bool,
+
+    // Renders the effects on several threads
+    scheduler: EffectScheduler,
+
+    // (led strip id, effect id) pairs already warned about being on a too small segment
+    undersized_warnings: HashSet<(usize, usize)>,
+    // (led strip id, first led) of the segments that failed to render on the last frame, to the
+    // error. Errors are logged when they show up rather than on every frame
+    render_errors: BTreeMap<(usize, usize), String>,
+    // connection id to the test pattern sent to it instead of its ledstrips
+    test_patterns: HashMap<usize, TestPatternRenderer>,
+    // Records the frames of some ledstrips for a few seconds, then writes them to a file
+    frame_capture: Option<FrameCapture>,
+
+    profiles: Vec<ProfileConfig>,
+    // Name of the last profile applied
+    active_profile: Option<String>,
+    // Profiles and brightness applied at times of the day. None without entries
+    schedule: Option<Schedule>,
+    // When the schedule was last checked against the local time
+    schedule_checked_at: Instant,
+    // Brightness fading toward the one of a schedule entry
+    brightness_fade: Option<BrightnessFade>,
+    night_mode: NightMode,
+    // Follows the beat for the shared clock of the effects
+    beat_tracker: BeatTracker,
+    drum_classifier: DrumClassifier,
+    phase_lock: PhaseLock,
+    // Shares the clock and the profile with the other instances. None without a sync config
+    sync: Option<SyncPeer>,
+    // Position of the JACK transport or the Ableton Link session, which drives the beat while
+    // it's playing
+    transport: SharedTransport,
+}
+
+// Seconds without a new position of the transport before the detected beat takes over
+const TRANSPORT_TIMEOUT: f64 = 0.5;
+
+// Time between two checks of the schedule against the local time
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Global brightness going from one value to another over a duration
+#[derive(Debug)]
+struct BrightnessFade {
+    from: f32,
+    to: f32,
+    started_at: Instant,
+    duration: Duration,
+}
+
+impl BrightnessFade {
+    fn brightness(&self) -> f32 {
+        if self.duration.is_zero() {
+            return self.to;
+        }
+        let progress = self.started_at.elapsed().as_secs_f32() / self.duration.as_secs_f32();
+        self.from + (self.to - self.from) * progress.min(1.0)
+    }
+
+    fn is_over(&self) -> bool {
+        self.started_at.elapsed() >= self.duration
+    }
+}
+
+fn available_cores() -> usize {
+    std::thread::available_parallelism().map_or(1, |threads| threads.get())
 }
 
 impl Drop for Controller {
@@ -44,50 +387,145 @@ impl Drop for Controller {
 }
 
 impl Controller {
-    pub fn new(audio_processor: &AudioSignalProcessor, lua_package_root: impl AsRef<Path>) -> Self {
+    pub fn new(
+        audio_processor: &AudioSignalProcessor,
+        lua_package_root: impl AsRef<Path>,
+        cache: Option<Cache>,
+        circuit_breaker_config: CircuitBreakerConfig,
+        av_sync_config: AvSyncConfig,
+        lua_sandbox_config: LuaSandboxConfig,
+    ) -> Self {
         let hot_reloader = HotReloader::new(&[
             WatchablePath::recursive(lua_package_root.as_ref()),
-            WatchablePath::recursive(PathBuf::from("../effects/bin").as_ref()),
+            WatchablePath::recursive(Path::new(NATIVE_EFFECTS_FOLDER)),
+            WatchablePath::recursive(Path::new(RHAI_EFFECTS_FOLDER)),
         ]);
 
         // Don't propagate the error. Simply log that the hot reloader couldn't be initialized and
         // continue execution
         if let Err(e) = &hot_reloader {
-            log::error!("Could not start the effects hot reloader: {e}");
+            tracing::error!("Could not start the effects hot reloader: {e}");
         }
 
+        let derived_features = DerivedFeatures::default();
+        let screen_colors = SharedScreenColors::default();
+        let connection_snapshot = SharedConnectionInfo::default();
         Self {
-            settings: Default::default(),
-            effects: Some(Default::default()),
-            effect_settings: Default::default(),
-            connections: Default::default(),
-            led_strips: Default::default(),
+            settings: Registry::new("effect settings"),
+            effects: Some(Registry::new("effect")),
+            parameter_bindings: Default::default(),
+            lua_effects_manager: LuaEffectsManager::new(
+                audio_processor,
+                &lua_package_root,
+                cache,
+                derived_features.values(),
+                screen_colors.clone(),
+                connection_snapshot.clone(),
+                lua_sandbox_config,
+            ),
+            rhai_effects_manager: RhaiEffectsManager::new(
+                audio_processor,
+                derived_features.values(),
+                connection_snapshot.clone(),
+                lua_sandbox_config,
+            ),
+            derived_features,
+            screen_colors,
+            connection_snapshot,
+            fft_result: audio_processor.fft_result.clone(),
+            main_fft_results: Arc::new(audio_processor.smoothed_fft_results()),
+            audio_sources: Default::default(),
+            started_at: Instant::now(),
+            connections: Registry::new("connection"),
+            led_strips: Registry::new("ledstrip"),
             led_strip_connections: Default::default(),
+            led_strip_offsets: Default::default(),
+            outgoing_frames: Default::default(),
+            frame_buffers: Default::default(),
+            led_strip_fallbacks: Default::default(),
+            failed_over: Default::default(),
+            connection_breakers: Default::default(),
+            circuit_breaker_config,
+            encoders: Default::default(),
+            keep_alive: Default::default(),
+            dithered_connections: Default::default(),
+            color_luts: Default::default(),
+            dispatch_delays: Default::default(),
+            dithering: Default::default(),
+            last_frames: Default::default(),
+            connection_stats: Default::default(),
+            engine_load: 0.0,
             effects_registry: Default::default(),
+            effect_types: EffectRegistry::new(lua_package_root.as_ref()),
+            runtime_settings: Default::default(),
             native_effect_manager: NativeEffectsManager::new(audio_processor),
-            lua_effects_manager: LuaEffectsManager::new(audio_processor, &lua_package_root),
             hot_reloader: hot_reloader.ok(),
+            brightness: 1.0,
+            led_strip_brightness: Default::default(),
+            post_processing: Default::default(),
+            latency: Latency::load(&av_sync_config.latency_file).unwrap_or_default(),
+            latency_file: av_sync_config.latency_file.clone(),
+            latency_calibration: None,
+            av_sync: AvSync::new(
+                av_sync_config,
+                crate::ticks_per_second(),
+                audio_processor.fft_result.clone(),
+            ),
+            interpolation: (crate::frames_per_tick() > 1).then(FrameInterpolation::default),
+            paused: false,
+            blackout: false,
+            blackout_ledstrips: Default::default(),
+            solo: None,
+            frame_frozen: false,
+            frozen_ledstrips: Default::default(),
+            beat_envelope: Default::default(),
+            beat: 0.0,
+            frozen_effects: Default::default(),
+            overrides: Default::default(),
+            audio_available: true,
+            audio_received: false,
+            silence_detector: None,
+            idle: false,
+            scheduler: EffectScheduler::new(available_cores()),
+            undersized_warnings: Default::default(),
+            render_errors: Default::default(),
+            test_patterns: Default::default(),
+            frame_capture: None,
+            profiles: Vec::new(),
+            active_profile: None,
+            schedule: None,
+            schedule_checked_at: Instant::now(),
+            night_mode: NightMode::new(NightModeConfig::default()),
+            brightness_fade: None,
+            beat_tracker: Default::default(),
+            drum_classifier: Default::default(),
+            phase_lock: Default::default(),
+            sync: None,
+            transport: Default::default(),
         }
     }
 
     fn on_file_change(&mut self, path: &Path, effects: &[usize]) {
         let all_lua = effects
             .iter()
-            .all(|id| matches!(self.effects.as_ref().unwrap().get(id), Some(Effect::Lua(_))));
+            .all(|id| matches!(self.effect(*id), Some(Effect::Lua(_))));
 
-        let all_native = effects.iter().all(|id| {
-            matches!(
-                self.effects.as_ref().unwrap().get(id),
-                Some(Effect::Native(_))
-            )
-        });
+        let all_native = effects
+            .iter()
+            .all(|id| matches!(self.effect(*id), Some(Effect::Native(_))));
+
+        let all_rhai = effects
+            .iter()
+            .all(|id| matches!(self.effect(*id), Some(Effect::Rhai(_))));
 
         if all_lua {
             self.lua_effects_manager.on_file_changed(path);
+        } else if all_rhai {
+            self.rhai_effects_manager.on_file_changed(path);
         } else if all_native {
             self.native_effect_manager.on_file_changed(path);
         } else {
-            log::error!(
+            tracing::error!(
                 "Not all effects loaded from the file {} are of the same type. This is impossible",
                 path.display()
             );
@@ -111,11 +549,11 @@ impl Controller {
             };
 
             for effect_id in &effects {
-                let Some(effect) = self.effects.as_mut().unwrap().get_mut(effect_id) else {
+                let Some(instance) = self.effects.as_mut().unwrap().get_mut(*effect_id) else {
                     continue;
                 };
 
-                if let Effect::Native(effect) = effect {
+                if let Effect::Native(effect) = &mut instance.effect {
                     self.native_effect_manager.pre_reload_effect(effect);
                 };
             }
@@ -123,28 +561,38 @@ impl Controller {
             self.on_file_change(path.as_ref(), &effects);
 
             for effect_id in effects {
-                let Some(effect) = self.effects.as_mut().unwrap().get_mut(&effect_id) else {
+                let Some(instance) = self.effects.as_mut().unwrap().get_mut(effect_id) else {
                     continue;
                 };
 
-                match effect {
+                match &mut instance.effect {
                     Effect::Native(effect) => {
                         self.native_effect_manager.reload_effect(effect);
                     }
                     Effect::Lua(effect) => {
                         self.lua_effects_manager.reload_effect(effect);
                     }
+                    Effect::Rhai(effect) => {
+                        self.rhai_effects_manager.reload_effect(effect);
+                    }
                 };
             }
         }
     }
 
-    pub fn add_lua_effect(&mut self, id: usize, effect_path: impl AsRef<Path>) {
+    /// Loads a lua effect. Only fails if the id is taken, an effect that can't be loaded is logged
+    /// and left out
+    pub fn add_lua_effect(
+        &mut self,
+        id: usize,
+        effect_path: impl AsRef<Path>,
+    ) -> Result<(), RegistryError> {
+        self.effects.as_ref().unwrap().check_vacant(id)?;
         let canonicalized_effect_path = match std::fs::canonicalize(&effect_path) {
             Ok(x) => x,
             Err(e) => {
-                log::error!("Couldn't load {}, {e}", effect_path.as_ref().display());
-                return;
+                tracing::error!("Couldn't load {}, {e}", effect_path.as_ref().display());
+                return Ok(());
             }
         };
 
@@ -154,21 +602,62 @@ impl Controller {
 
         let effect = match effect {
             Err(e) => {
-                log::error!(
+                tracing::error!(
                     "Couln't add lua effect: {}. {e:#?}",
                     effect_path.as_ref().display()
                 );
-                return;
+                return Ok(());
+            }
+            Ok(x) => x,
+        };
+
+        self.on_effect_add(id, canonicalized_effect_path, effect)
+    }
+
+    /// Loads a rhai effect. Only fails if the id is taken, an effect that can't be loaded is
+    /// logged and left out
+    pub fn add_rhai_effect(
+        &mut self,
+        id: usize,
+        effect_path: impl AsRef<Path>,
+    ) -> Result<(), RegistryError> {
+        self.effects.as_ref().unwrap().check_vacant(id)?;
+        let canonicalized_effect_path = match std::fs::canonicalize(&effect_path) {
+            Ok(x) => x,
+            Err(e) => {
+                tracing::error!("Couldn't load {}, {e}", effect_path.as_ref().display());
+                return Ok(());
+            }
+        };
+
+        let effect = self
+            .rhai_effects_manager
+            .create_effect(&canonicalized_effect_path);
+
+        let effect = match effect {
+            Err(e) => {
+                tracing::error!(
+                    "Couln't add rhai effect: {}. {e}",
+                    effect_path.as_ref().display()
+                );
+                return Ok(());
             }
             Ok(x) => x,
         };
 
-        self.on_effect_add(id, canonicalized_effect_path, effect);
+        self.on_effect_add(id, canonicalized_effect_path, effect)
     }
 
-    pub fn add_native_effect(&mut self, id: usize, effect_path: impl AsRef<Path>) {
+    /// Loads a native effect. Only fails if the id is taken, an effect that can't be loaded is
+    /// logged and left out
+    pub fn add_native_effect(
+        &mut self,
+        id: usize,
+        effect_path: impl AsRef<Path>,
+    ) -> Result<(), RegistryError> {
+        self.effects.as_ref().unwrap().check_vacant(id)?;
         let Ok(canonicalized_effect_path) = std::fs::canonicalize(&effect_path) else {
-            return;
+            return Ok(());
         };
 
         let effect = self
@@ -177,141 +666,2426 @@ impl Controller {
 
         let effect = match effect {
             Err(e) => {
-                log::error!(
+                tracing::error!(
                     "Couln't add native effect: {}. {e:#?}",
                     effect_path.as_ref().display()
                 );
-                return;
+                return Ok(());
             }
             Ok(x) => x,
         };
 
-        self.on_effect_add(id, canonicalized_effect_path, effect);
+        self.on_effect_add(id, canonicalized_effect_path, effect)
     }
 
-    fn on_effect_add(&mut self, id: usize, effect_path: PathBuf, effect: Effect) {
-        match self.effects.as_mut().unwrap().entry(id) {
-            std::collections::hash_map::Entry::Occupied(_) => {
-                log::error!("Couldn't add effect with id {id} because it is already occupied");
-            }
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                entry.insert(effect);
-            }
-        }
+    /// Drops an effect with its links to its settings and bindings. The ledstrips still render
+    /// its segments until they are rebuilt
+    pub fn remove_effect(&mut self, id: usize) {
+        self.effects.as_mut().unwrap().remove(id);
+        self.effects_registry.retain(|_, ids| {
+            ids.retain(|effect_id| *effect_id != id);
+            !ids.is_empty()
+        });
+        self.parameter_bindings.remove(&id);
+        self.frozen_effects.remove(&id);
+        self.scheduler.remove(id);
+        self.undersized_warnings
+            .retain(|(_, effect_id)| *effect_id != id);
+    }
 
+    fn on_effect_add(
+        &mut self,
+        id: usize,
+        effect_path: PathBuf,
+        effect: Effect,
+    ) -> Result<(), RegistryError> {
+        self.effects
+            .as_mut()
+            .unwrap()
+            .insert(id, EffectInstance::new(effect))?;
         self.effects_registry
             .entry(effect_path)
             .or_default()
             .push(id);
+        Ok(())
     }
 
-    pub fn add_settings(&mut self, id: usize, settings: EffectSettings) {
-        self.settings.insert(id, settings);
+    pub fn effect_types(&self) -> Vec<String> {
+        self.effect_types.names().map(str::to_owned).collect()
     }
 
-    pub fn link_effect_to_settings(&mut self, effect_id: usize, settings_id: usize) -> bool {
-        if self.settings.contains_key(&settings_id) {
-            self.effect_settings.insert(effect_id, settings_id);
-            true
-        } else {
-            false
+    fn load_effect(&mut self, id: usize, source: &EffectSource) -> Result<(), RegistryError> {
+        match source {
+            EffectSource::Lua(effect_path) => self.add_lua_effect(id, effect_path),
+            EffectSource::Native(effect_path) => self.add_native_effect(id, effect_path),
+            EffectSource::Rhai(effect_path) => self.add_rhai_effect(id, effect_path),
         }
     }
 
-    pub fn add_connection(&mut self, connection_id: usize, connection: Connection) {
-        self.connections.insert(connection_id, connection);
+    /// Loads an effect of a registered type, built from the settings it is linked to. Like the
+    /// other effects, one that can't be loaded is logged and left out
+    pub fn add_effect_of_type(
+        &mut self,
+        id: usize,
+        effect_type: &str,
+        settings_id: usize,
+    ) -> Result<(), EffectInstanceError> {
+        let settings = match self.settings.get(settings_id).map(SettingsHandle::load) {
+            Some(settings) => match settings.as_ref() {
+                EffectSettings::Lua(settings) => settings.settings.clone(),
+                EffectSettings::Native(_) => serde_json::Value::Null,
+            },
+            None => serde_json::Value::Null,
+        };
+        let spec = self.effect_types.create(effect_type, &settings)?;
+        self.check_features(effect_type, &spec.features)?;
+        Ok(self.load_effect(id, &spec.source)?)
     }
 
-    pub fn add_led_strip(&mut self, led_strip_id: usize, led_strip: LedStrip) {
-        self.led_strips.insert(led_strip_id, led_strip);
+    // The features an effect package reads have to be built-in or derived by the config
+    fn check_features(
+        &self,
+        effect_type: &str,
+        features: &[String],
+    ) -> Result<(), EffectInstanceError> {
+        let missing = features
+            .iter()
+            .find(|name| !is_builtin_feature(name) && !self.derived_features.contains(name));
+        match missing {
+            Some(name) => Err(EffectInstanceError::MissingFeature(
+                effect_type.to_owned(),
+                name.clone(),
+            )),
+            None => Ok(()),
+        }
     }
 
-    pub fn link_led_strip_to_connection(
+    /// Loads a new instance of an effect type with its own settings and returns its id. It isn't
+    /// rendered until it is assigned to a segment
+    pub fn create_effect(
         &mut self,
-        led_strip_id: usize,
-        connection_id: usize,
-    ) -> bool {
-        if self.connections.contains_key(&connection_id) {
-            self.led_strip_connections
-                .insert(led_strip_id, connection_id);
-            true
-        } else {
-            false
+        effect_type: &str,
+        settings: serde_json::Value,
+    ) -> Result<usize, EffectInstanceError> {
+        let mut spec = self.effect_types.create(effect_type, &settings)?;
+        self.check_features(effect_type, &spec.features)?;
+        let id = self.next_effect_id();
+        self.load_effect(id, &spec.source)?;
+        let Some(effect) = self.effect(id) else {
+            return Err(EffectInstanceError::Load(effect_type.to_owned()));
+        };
+        let valid = effect
+            .normalize_settings(&mut spec.settings)
+            .and_then(|_| effect.validate_settings(&spec.settings));
+        if let Err(e) = valid {
+            self.remove_effect(id);
+            return Err(e.into());
         }
+
+        let settings_id = self.allocate_settings(spec.settings);
+        self.runtime_settings.insert(settings_id);
+        self.link_effect_to_settings(id, settings_id);
+        tracing::info!("Created effect {id} of type {effect_type}");
+        Ok(id)
     }
 
-    pub fn update_led_strips(&mut self) {
-        for (led_strip_id, led_strip) in self.led_strips.iter_mut() {
-            for (effect_id, interval) in &led_strip.effects {
-                let leds = match led_strip.colors.get_mut(interval.0..=interval.1) {
-                    Some(leds) => leds,
-                    None => {
-                        // TODO fix le probleme
-                        log::warn!("Effect {effect_id} has invalid interval ({interval:?}) on ledstrip {led_strip_id} of size {}. Skipping.", led_strip.size);
-                        continue;
-                    }
-                };
+    /// Json schema of the settings of an effect type, None if it doesn't have settings. Scripted
+    /// effects declare it when they are loaded, so the effect is loaded to read it
+    pub fn effect_type_schema(
+        &mut self,
+        effect_type: &str,
+    ) -> Result<Option<serde_json::Value>, EffectInstanceError> {
+        let spec = self
+            .effect_types
+            .create(effect_type, &serde_json::Value::Null)?;
+        let effect = match spec.source {
+            EffectSource::Lua(effect_path) => self
+                .lua_effects_manager
+                .create_effect(&effect_path)
+                .map_err(|_| EffectInstanceError::Load(effect_type.to_owned()))?,
+            EffectSource::Rhai(effect_path) => self
+                .rhai_effects_manager
+                .create_effect(&effect_path)
+                .map_err(|_| EffectInstanceError::Load(effect_type.to_owned()))?,
+            EffectSource::Native(_) => return Ok(None),
+        };
+        Ok(effect.settings_schema().cloned())
+    }
 
-                let effect = match self.effects.as_mut().unwrap().get_mut(effect_id) {
-                    Some(effect) => effect,
-                    None => {
-                        // TODO fix le probleme
-                        log::warn!("Effect {effect_id} doesn't exist. Skipping.");
-                        continue;
-                    }
-                };
+    /// Renders the effect on the `segment`th segment of the ledstrip
+    pub fn assign_effect(
+        &mut self,
+        ledstrip_id: usize,
+        segment: usize,
+        effect_id: usize,
+    ) -> Result<(), EffectInstanceError> {
+        if !self.contains_effect(effect_id) {
+            return Err(EffectInstanceError::UnknownEffect(effect_id));
+        }
+        let assigned = self
+            .led_strips
+            .get_mut(ledstrip_id)
+            .is_some_and(|ledstrip| ledstrip.set_effect(segment, effect_id));
+        if !assigned {
+            return Err(EffectInstanceError::UnknownSegment(ledstrip_id, segment));
+        }
+        Ok(())
+    }
 
-                let setting_id = match self.effect_settings.get(effect_id) {
-                    Some(effect) => effect,
-                    None => {
-                        // TODO fix le probleme
-                        log::warn!("Settings for effect {effect_id} doesn't exist. Skipping.");
-                        continue;
-                    }
-                };
+    /// Drops an effect that no segment renders anymore, with the settings it was created with.
+    /// The overrides rendering it are released
+    pub fn destroy_effect(&mut self, effect_id: usize) -> Result<(), EffectInstanceError> {
+        if !self.contains_effect(effect_id) {
+            return Err(EffectInstanceError::UnknownEffect(effect_id));
+        }
+        let assigned = self.led_strips.iter().find_map(|(ledstrip_id, ledstrip)| {
+            let segment = ledstrip
+                .effects
+                .iter()
+                .position(|effect| effect.effect_id == effect_id)?;
+            Some((ledstrip_id, segment))
+        });
+        if let Some((ledstrip_id, segment)) = assigned {
+            return Err(EffectInstanceError::StillAssigned {
+                effect_id,
+                ledstrip_id,
+                segment,
+            });
+        }
 
-                let setting = self.settings.get(setting_id);
-                match (effect, setting) {
-                    (Effect::Lua(lua), Some(EffectSettings::Lua(settings))) => {
-                        if let Err(e) = lua.tick(leds, settings) {
-                            log::error!("Error when executing lua function: {:?}", e);
-                        }
-                    }
-                    (Effect::Native(native), Some(EffectSettings::Native(_settings))) => {
-                        native.tick(leds).unwrap();
-                    }
-                    _ => panic!("Effect doesn't match settings"),
+        let settings_id = self
+            .effects
+            .as_ref()
+            .unwrap()
+            .get(effect_id)
+            .and_then(|instance| Some(instance.settings.as_ref()?.id()));
+        if let Some(settings_id) = settings_id {
+            if self.runtime_settings.remove(&settings_id) {
+                self.remove_settings(settings_id);
+            }
+        }
+        self.overrides.release_effect(effect_id);
+        self.remove_effect(effect_id);
+        tracing::info!("Destroyed effect {effect_id}");
+        Ok(())
+    }
+
+    /// Renders the effect of the override on the segments it covers whose priority is lower, in
+    /// place of the override previously set on the channel
+    pub fn set_override(
+        &mut self,
+        channel: &str,
+        segment_override: SegmentOverride,
+    ) -> Result<(), EffectInstanceError> {
+        if !self.contains_effect(segment_override.effect_id) {
+            return Err(EffectInstanceError::UnknownEffect(
+                segment_override.effect_id,
+            ));
+        }
+        if let Some(ledstrip_id) = segment_override.ledstrip_id {
+            let Some(ledstrip) = self.led_strips.get(ledstrip_id) else {
+                return Err(EffectInstanceError::UnknownLedstrip(ledstrip_id));
+            };
+            if let Some(segment) = segment_override.segment {
+                if segment >= ledstrip.effects.len() {
+                    return Err(EffectInstanceError::UnknownSegment(ledstrip_id, segment));
                 }
             }
         }
+        tracing::info!(
+            "Overriding the segments with effect {} at priority {} on channel {channel}",
+            segment_override.effect_id,
+            segment_override.priority
+        );
+        self.overrides.set(channel, segment_override);
+        Ok(())
     }
 
-    pub fn send_ledstrip_colors(&mut self) {
-        self.led_strip_connections
-            .retain(|ledstrip_id, connection_id| {
-                if let Some(ledstrip) = self.led_strips.get(ledstrip_id) {
-                    if let Some(connection) = self.connections.get_mut(connection_id) {
-                        let data: &[u8] = bytemuck::cast_slice(ledstrip.colors.as_slice());
-
-                        assert!(data.len() == ledstrip.colors.len() * 3);
-
-                        match connection {
-                            Connection::Tcp(tcp_connection) => {
-                                // If send failsrust use Path for Pathbuf key, connection is closed.
-                                if let Err(error) = tcp_connection.send_data(data.to_vec()) {
-                                    log::error!("{:?}", error);
-                                    self.connections.remove(connection_id);
-                                    return false;
-                                }
-                            }
-                            Connection::Usb(_terminal) => {
-                                todo!("Implement Usb connection");
-                            }
-                        }
-                        return true;
-                    }
+    /// Gives the segments overridden on the channel back to their effect
+    pub fn release_override(&mut self, channel: &str) -> Result<(), EffectInstanceError> {
+        if !self.overrides.release(channel) {
+            return Err(EffectInstanceError::UnknownOverride(channel.to_owned()));
+        }
+        tracing::info!("Released the override of channel {channel}");
+        Ok(())
+    }
+
+    pub fn override_info(&self) -> Vec<OverrideInfo> {
+        self.overrides.info()
+    }
+
+    /// Makes the results of an audio source available to the effects under its name. Returns
+    /// false if the name is taken
+    pub fn add_audio_source(&mut self, name: &str, audio_processor: &AudioSignalProcessor) -> bool {
+        if self.audio_sources.contains_key(name) {
+            return false;
+        }
+        self.audio_sources.insert(
+            name.to_owned(),
+            Arc::new(audio_processor.smoothed_fft_results()),
+        );
+        true
+    }
+
+    /// Makes the effect react to an audio source, or to the main device if `audio_source` is
+    /// missing. Like the effects that can't be loaded, a missing effect is left out
+    pub fn set_effect_audio_source(
+        &mut self,
+        effect_id: usize,
+        audio_source: Option<&str>,
+    ) -> Result<(), EffectInstanceError> {
+        let fft_results = match audio_source {
+            Some(name) => self
+                .audio_sources
+                .get(name)
+                .ok_or_else(|| EffectInstanceError::UnknownAudioSource(name.to_owned()))?,
+            None => &self.main_fft_results,
+        };
+        let effect = self
+            .effects
+            .as_mut()
+            .unwrap()
+            .get_mut(effect_id)
+            .map(|instance| &mut instance.effect);
+        match effect {
+            Some(Effect::Lua(effect)) => effect.set_fft_results(fft_results.clone()),
+            Some(Effect::Rhai(effect)) => effect.set_fft_results(fft_results.clone()),
+            Some(Effect::Native(_)) if audio_source.is_some() => {
+                return Err(EffectInstanceError::NativeAudioSource(effect_id))
+            }
+            Some(Effect::Native(_)) | None => {}
+        }
+        Ok(())
+    }
+
+    pub fn contains_effect(&self, id: usize) -> bool {
+        self.effects.as_ref().unwrap().contains(id)
+    }
+
+    fn effect(&self, id: usize) -> Option<&Effect> {
+        let instance = self.effects.as_ref().unwrap().get(id)?;
+        Some(&instance.effect)
+    }
+
+    // Settings the effect renders with, as they are now
+    fn effect_settings(&self, id: usize) -> Option<Arc<EffectSettings>> {
+        let instance = self.effects.as_ref().unwrap().get(id)?;
+        Some(instance.settings.as_ref()?.load())
+    }
+
+    /// Id the next effect added without a configured id should use
+    pub fn next_effect_id(&self) -> usize {
+        self.effects.as_ref().unwrap().next_id()
+    }
+
+    pub fn add_settings(
+        &mut self,
+        id: usize,
+        settings: EffectSettings,
+    ) -> Result<(), RegistryError> {
+        self.settings.insert(id, SettingsHandle::new(id, settings))
+    }
+
+    /// Drops the settings from the registry. The effects linked to them keep rendering with them
+    /// until they are linked to other settings
+    pub fn remove_settings(&mut self, id: usize) {
+        self.settings.remove(id);
+    }
+
+    /// Adds settings under a new id and returns it
+    pub fn allocate_settings(&mut self, settings: EffectSettings) -> usize {
+        self.settings
+            .allocate_with(|id| SettingsHandle::new(id, settings))
+    }
+
+    /// Checks the settings linked to the effect against its schema. Effects that couldn't be
+    /// loaded have nothing to check
+    pub fn validate_effect_settings(&self, effect_id: usize) -> Result<(), SettingsError> {
+        match self.effects.as_ref().unwrap().get(effect_id) {
+            Some(instance) => instance.validate_settings(),
+            None => Ok(()),
+        }
+    }
+
+    /// Current values of settings. Native settings don't have any
+    pub fn settings_values(
+        &self,
+        settings_id: usize,
+    ) -> Result<serde_json::Value, EffectInstanceError> {
+        let handle = self
+            .settings
+            .get(settings_id)
+            .ok_or(EffectInstanceError::UnknownSettings(settings_id))?;
+        match handle.load().as_ref() {
+            EffectSettings::Lua(settings) => Ok(settings.settings.clone()),
+            EffectSettings::Native(_) => Ok(serde_json::Value::Null),
+        }
+    }
+
+    /// Applies a json merge patch to settings if every effect linked to them accepts the result.
+    /// The effects see the new settings at once from the next frame, and are told about the
+    /// change so that they can rebuild what they derived from the settings
+    pub fn patch_settings(
+        &mut self,
+        settings_id: usize,
+        patch: &serde_json::Value,
+    ) -> Result<(), EffectInstanceError> {
+        let handle = self
+            .settings
+            .get(settings_id)
+            .ok_or(EffectInstanceError::UnknownSettings(settings_id))?
+            .clone();
+        let EffectSettings::Lua(mut settings) = EffectSettings::clone(&handle.load()) else {
+            return Err(EffectInstanceError::NativeSettings(settings_id));
+        };
+        merge_patch(&mut settings.settings, patch);
+
+        let linked = |instance: &EffectInstance| {
+            instance
+                .settings
+                .as_ref()
+                .is_some_and(|linked| linked.shares(&handle))
+        };
+        let mut settings = EffectSettings::Lua(settings);
+        for (effect_id, instance) in self.effects.as_ref().unwrap().iter() {
+            if linked(instance) {
+                instance
+                    .effect
+                    .normalize_settings(&mut settings)
+                    .and_then(|_| instance.effect.validate_settings(&settings))
+                    .map_err(|e| EffectInstanceError::RejectedSettings(effect_id, e))?;
+            }
+        }
+        handle.store(settings);
+        tracing::info!("Changed settings {settings_id}");
+
+        let settings = handle.load();
+        for (effect_id, instance) in self.effects.as_mut().unwrap().iter_mut() {
+            if !linked(instance) {
+                continue;
+            }
+            if let Err(e) = instance.effect.settings_changed(&settings) {
+                tracing::warn!("Effect {effect_id} failed to take its new settings in: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the effect render with the settings, which it then shares with the other effects
+    /// linked to them. Returns false if the settings don't exist. Effects that couldn't be loaded
+    /// have nothing to link
+    pub fn link_effect_to_settings(&mut self, effect_id: usize, settings_id: usize) -> bool {
+        let Some(settings) = self.settings.get(settings_id) else {
+            return false;
+        };
+        if let Some(instance) = self.effects.as_mut().unwrap().get_mut(effect_id) {
+            instance.settings = Some(settings.clone());
+        }
+        true
+    }
+
+    /// Adds a named signal that the bindings added after it and the lua effects can read
+    pub fn add_derived_feature(
+        &mut self,
+        name: String,
+        expression: Expression,
+        envelope: Option<EnvelopeConfig>,
+    ) -> Result<(), ExpressionError> {
+        self.derived_features.add(name, expression, envelope)
+    }
+
+    /// Colors of the edges of the screen the lua effects read, for the screen capture to store
+    pub fn screen_colors(&self) -> SharedScreenColors {
+        self.screen_colors.clone()
+    }
+
+    /// Position of the transport the effects follow, for the JACK client or the Ableton Link
+    /// session to store
+    pub fn transport(&self) -> SharedTransport {
+        self.transport.clone()
+    }
+
+    pub fn clear_derived_features(&mut self) {
+        self.derived_features.clear();
+    }
+
+    /// Recomputes the settings of the effect from their expression every frame. Only lua effects
+    /// have settings that can change
+    pub fn add_parameter_bindings(
+        &mut self,
+        effect_id: usize,
+        bindings: &HashMap<String, Expression>,
+    ) -> Result<(), ExpressionError> {
+        self.parameter_bindings.remove(&effect_id);
+        if bindings.is_empty() {
+            return Ok(());
+        }
+        for expression in bindings.values() {
+            expression.check_names(|name| self.derived_features.contains(name))?;
+        }
+        let is_lua = self
+            .effect_settings(effect_id)
+            .is_some_and(|settings| matches!(settings.as_ref(), EffectSettings::Lua(_)));
+        if !is_lua {
+            tracing::warn!(
+                "Ignoring the bindings of effect {effect_id} because it doesn't have lua settings"
+            );
+            return Ok(());
+        }
+        let schema = self.effect(effect_id).and_then(Effect::settings_schema);
+        let bindings = bindings
+            .iter()
+            .map(|(key, expression)| {
+                let range = schema.map_or(Ok(SettingRange::default()), |schema| {
+                    SettingRange::from_schema(schema, key)
+                })?;
+                Ok((key.clone(), expression.clone(), range))
+            })
+            .collect::<Result<_, ExpressionError>>()?;
+        self.parameter_bindings.insert(effect_id, bindings);
+        Ok(())
+    }
+
+    // Features of an fft frame, with the beat of the last frame
+    fn audio_features(&self, fft_result: &FftResult, clock: &SyncClock) -> AudioFeatures {
+        let drums = percussion::drums();
+        AudioFeatures {
+            beat: self.beat,
+            tempo_phase: clock.tempo_phase().unwrap_or_default(),
+            bar_phase: clock.bar_phase().unwrap_or_default(),
+            kick_hit: drums.kick.envelope(),
+            snare_hit: drums.snare.envelope(),
+            hat_hit: drums.hat.envelope(),
+            ..AudioFeatures::new(fft_result, self.started_at.elapsed().as_secs_f32())
+        }
+    }
+
+    // Computes the derived features, then the settings bound to the features
+    fn apply_parameter_bindings(&mut self) {
+        let fft_result = self.fft_result.load();
+        let clock = sync::clock();
+        // Every frame, for the onsets to be seen whenever the features are read
+        self.beat = self.beat_envelope.update(&fft_result, &clock);
+        if self.parameter_bindings.is_empty() && self.derived_features.is_empty() {
+            return;
+        }
+        let features = self.audio_features(&fft_result, &clock);
+        let derived = self.derived_features.update(features, &fft_result);
+        let context = EvalContext {
+            features,
+            fft_result: &fft_result,
+            derived: &derived,
+        };
+        let values: Vec<(usize, Vec<(String, serde_json::Value)>)> = self
+            .parameter_bindings
+            .iter()
+            .map(|(effect_id, bindings)| {
+                let values = bindings
+                    .iter()
+                    .map(|(key, expression, range)| {
+                        (key.clone(), range.to_setting(expression.eval(&context)))
+                    })
+                    .collect();
+                (*effect_id, values)
+            })
+            .collect();
+        drop(fft_result);
+        // The bound settings of an effect change together
+        for (effect_id, values) in values {
+            self.set_effect_settings(effect_id, values);
+        }
+    }
+
+    /// Sets a setting of an effect if its settings stay valid. Returns whether it has settings
+    /// that can be changed
+    pub fn update_effect_setting(
+        &mut self,
+        effect_id: usize,
+        key: String,
+        value: serde_json::Value,
+    ) -> Result<bool, SettingsError> {
+        let settings = self.effect_settings(effect_id);
+        if let (Some(effect), Some(EffectSettings::Lua(settings))) =
+            (self.effect(effect_id), settings.as_deref())
+        {
+            let mut updated = settings.settings.clone();
+            if !updated.is_object() {
+                updated = serde_json::Value::Object(Default::default());
+            }
+            updated[&key] = value.clone();
+            effect.validate_settings(&EffectSettings::Lua(LuaEffectSettings {
+                settings: updated,
+            }))?;
+        }
+        Ok(self.set_effect_setting(effect_id, key, value))
+    }
+
+    pub fn set_effect_setting(
+        &mut self,
+        effect_id: usize,
+        key: String,
+        value: serde_json::Value,
+    ) -> bool {
+        self.set_effect_settings(effect_id, [(key, value)])
+    }
+
+    /// Sets several settings of an effect in one edit, which the other effects sharing its
+    /// settings see too. Returns whether it has settings that can be changed
+    pub fn set_effect_settings(
+        &mut self,
+        effect_id: usize,
+        values: impl IntoIterator<Item = (String, serde_json::Value)>,
+    ) -> bool {
+        let Some(handle) = self
+            .effects
+            .as_ref()
+            .unwrap()
+            .get(effect_id)
+            .and_then(|instance| instance.settings.as_ref())
+        else {
+            return false;
+        };
+        if !matches!(handle.load().as_ref(), EffectSettings::Lua(_)) {
+            return false;
+        }
+        handle.update(|settings| {
+            if let EffectSettings::Lua(settings) = settings {
+                if !settings.settings.is_object() {
+                    settings.settings = serde_json::Value::Object(Default::default());
                 }
-                false
-            });
+                for (key, value) in values {
+                    settings.settings[key] = value;
+                }
+            }
+        });
+        true
+    }
+
+    pub fn handle_command(&mut self, command: ControlCommand) {
+        match command {
+            ControlCommand::UpdateEffectSetting {
+                effect_id,
+                key,
+                value,
+            } => {
+                tracing::debug!("Setting {key} to {value} for effect {effect_id}");
+                match self.update_effect_setting(effect_id, key, value) {
+                    Ok(true) => {}
+                    Ok(false) => tracing::warn!(
+                        "Effect {effect_id} doesn't have settings that can be changed"
+                    ),
+                    Err(e) => tracing::warn!("Can't change a setting of effect {effect_id}: {e}"),
+                }
+            }
+            ControlCommand::SetBrightness(brightness) => {
+                self.brightness = brightness.clamp(0.0, 1.0);
+                self.brightness_fade = None;
+            }
+            ControlCommand::StepBrightness(step) => {
+                let brightness = (self.brightness + step).clamp(0.0, 1.0);
+                tracing::info!("Brightness set to {brightness:.2}");
+                self.brightness = brightness;
+                self.brightness_fade = None;
+            }
+            ControlCommand::ToggleBlackout => {
+                if let Err(e) = self.set_blackout(None, !self.blackout) {
+                    tracing::warn!("Can't black out: {e}");
+                }
+            }
+            ControlCommand::SetBlackout { ledstrip_id, on } => {
+                if let Err(e) = self.set_blackout(ledstrip_id, on) {
+                    tracing::warn!("Can't black out: {e}");
+                }
+            }
+            ControlCommand::ToggleFreeze => {
+                if let Err(e) = self.freeze_frame(None, !self.frame_frozen) {
+                    tracing::warn!("Can't freeze the frame: {e}");
+                }
+            }
+            ControlCommand::FreezeFrame {
+                ledstrip_id,
+                frozen,
+            } => {
+                if let Err(e) = self.freeze_frame(ledstrip_id, frozen) {
+                    tracing::warn!("Can't freeze the frame: {e}");
+                }
+            }
+            ControlCommand::SetSolo(ledstrip_id) => {
+                if let Err(e) = self.set_solo(ledstrip_id) {
+                    tracing::warn!("Can't solo: {e}");
+                }
+            }
+            ControlCommand::ToggleSolo(ledstrip_id) => {
+                let ledstrip_id = (self.solo != Some(ledstrip_id)).then_some(ledstrip_id);
+                if let Err(e) = self.set_solo(ledstrip_id) {
+                    tracing::warn!("Can't solo: {e}");
+                }
+            }
+            ControlCommand::SetLedstripBrightness {
+                ledstrip_id,
+                brightness,
+            } => {
+                self.led_strip_brightness
+                    .insert(ledstrip_id, brightness.clamp(0.0, 1.0));
+            }
+            ControlCommand::SwitchLedstripEffect {
+                ledstrip_id,
+                segment,
+                effect_id,
+            } => {
+                if let Err(e) = self.assign_effect(ledstrip_id, segment, effect_id) {
+                    tracing::warn!("Can't switch to effect {effect_id}: {e}");
+                }
+            }
+            ControlCommand::CreateEffect {
+                effect_type,
+                settings,
+                reply,
+            } => {
+                let result = self.create_effect(&effect_type, settings);
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            ControlCommand::AssignEffect {
+                ledstrip_id,
+                segment,
+                effect_id,
+                reply,
+            } => {
+                let result = self.assign_effect(ledstrip_id, segment, effect_id);
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            ControlCommand::DestroyEffect { effect_id, reply } => {
+                let result = self.destroy_effect(effect_id);
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            ControlCommand::SetOverride {
+                channel,
+                segment_override,
+                reply,
+            } => {
+                let result = self.set_override(&channel, segment_override);
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            ControlCommand::ReleaseOverride { channel, reply } => {
+                let result = self.release_override(&channel);
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            ControlCommand::SetTestPattern {
+                connection_id,
+                pattern,
+                reply,
+            } => {
+                let result = if self.set_test_pattern(connection_id, pattern) {
+                    Ok(())
+                } else {
+                    Err(format!("Connection {connection_id} doesn't exist"))
+                };
+                let _ = reply.send(result);
+            }
+            ControlCommand::PatchSettings { settings_id, patch } => {
+                if let Err(e) = self.patch_settings(settings_id, &patch) {
+                    tracing::warn!("Can't change settings {settings_id}: {e}");
+                }
+            }
+            ControlCommand::ApplySettingsPatch {
+                settings_id,
+                patch,
+                reply,
+            } => {
+                let result = self.patch_settings(settings_id, &patch);
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            ControlCommand::GetSettings { settings_id, reply } => {
+                let result = self.settings_values(settings_id);
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            ControlCommand::SetEffectFrozen { effect_id, frozen } => {
+                if !self.effects.as_ref().unwrap().contains(effect_id) {
+                    tracing::warn!("Can't freeze effect {effect_id} because it doesn't exist");
+                    return;
+                }
+                tracing::info!(
+                    "{} effect {effect_id}",
+                    if frozen { "Freezing" } else { "Unfreezing" }
+                );
+                if frozen {
+                    self.frozen_effects.insert(effect_id);
+                } else {
+                    self.frozen_effects.remove(&effect_id);
+                }
+            }
+            ControlCommand::SetProfile { name, reply } => {
+                let result = self.apply_profile(&name);
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            ControlCommand::StepProfile(steps) => {
+                if let Err(e) = self.step_profile(steps) {
+                    tracing::warn!("Can't switch profile: {e}");
+                }
+            }
+            ControlCommand::SetNightMode(on) => {
+                tracing::info!("{} the night mode", if on { "Starting" } else { "Ending" });
+                self.night_mode.set_on(on);
+            }
+            ControlCommand::SetPaused(paused) => {
+                tracing::info!("{}", if paused { "Pausing" } else { "Resuming" });
+                self.paused = paused;
+            }
+            ControlCommand::SetSyncOffset(offset_ms) => {
+                let offset_ms = self.av_sync.set_offset(offset_ms);
+                tracing::info!("Audio to light offset set to {offset_ms}ms");
+            }
+            ControlCommand::SetSyncTestMode(enabled) => {
+                self.av_sync.set_test_mode(enabled);
+            }
+            ControlCommand::CalibrateLatency { seconds, reply } => {
+                let _ = reply.send(self.calibrate_latency(seconds));
+            }
+            ControlCommand::GetEffectTypes(reply) => {
+                let _ = reply.send(self.effect_types());
+            }
+            ControlCommand::GetEffectSchema { effect_type, reply } => {
+                let result = self.effect_type_schema(&effect_type);
+                let _ = reply.send(result.map_err(|e| e.to_string()));
+            }
+            ControlCommand::GetInfo(reply) => {
+                let _ = reply.send(EngineInfo::new(self));
+            }
+            ControlCommand::GetPreview(reply) => {
+                let _ = reply.send(LivePreview::new(self, &self.fft_result.load()));
+            }
+            ControlCommand::GetFeatures(reply) => {
+                let features = self.audio_features(&self.fft_result.load(), &sync::clock());
+                let derived = self.derived_features.values().read().unwrap().clone();
+                let _ = reply.send((features, derived));
+            }
+            ControlCommand::CaptureFrames {
+                path,
+                seconds,
+                ledstrips,
+                reply,
+            } => {
+                let _ = reply.send(self.capture_frames(&path, seconds, ledstrips));
+            }
+            // Answered by the run loop, which keeps the history across config reloads
+            ControlCommand::GetMetrics(_) => {}
+            // Answered by the run loop, which owns the audio input
+            ControlCommand::SetAudioDevice { .. }
+            | ControlCommand::CalibrateNoiseFloor { .. }
+            | ControlCommand::CaptureAudio { .. } => {}
+            ControlCommand::EvalLua {
+                effect_id,
+                code,
+                write,
+                reply,
+            } => {
+                let result = match self.effect(effect_id) {
+                    Some(Effect::Lua(effect)) => {
+                        effect.eval(&code, write).map_err(|e| e.to_string())
+                    }
+                    Some(Effect::Native(_) | Effect::Rhai(_)) => {
+                        Err(format!("Effect {effect_id} isn't a lua effect"))
+                    }
+                    None => Err(format!("Effect {effect_id} doesn't exist")),
+                };
+                let _ = reply.send(result);
+            }
+        }
+    }
+
+    pub fn ledstrip_info(&self) -> Vec<LedstripInfo> {
+        self.led_strips
+            .iter()
+            .map(|(id, ledstrip)| LedstripInfo {
+                id,
+                size: ledstrip.led_count(),
+                connection_id: self
+                    .led_strip_connections
+                    .get(&id)
+                    .map(|connection_id| match self.failed_over.contains(&id) {
+                        true => self.led_strip_fallbacks[&id],
+                        false => *connection_id,
+                    }),
+                brightness: self.led_strip_brightness.get(&id).copied().unwrap_or(1.0),
+                blackout: self.blackout_ledstrips.contains(&id),
+                frozen: self.frozen_ledstrips.contains(&id),
+                segments: ledstrip
+                    .effects
+                    .iter()
+                    .map(|effect| effect.effect_id)
+                    .collect(),
+                errors: self
+                    .render_errors
+                    .range((id, 0)..=(id, usize::MAX))
+                    .map(|(_, error)| error.clone())
+                    .collect(),
+            })
+            .collect()
+    }
+
+    pub fn connection_info(&self) -> Vec<ConnectionInfo> {
+        self.connections
+            .iter()
+            .map(|(id, connection)| {
+                let breaker = self.connection_breakers.get(&id);
+                let status = match breaker {
+                    Some(breaker) if breaker.is_open() => ConnectionStatus::Paused,
+                    Some(breaker) if breaker.is_half_open() => ConnectionStatus::Retrying,
+                    _ => ConnectionStatus::Ok,
+                };
+                let link = connection.status();
+                let health = match status {
+                    ConnectionStatus::Paused => ConnectionHealth::Dead,
+                    ConnectionStatus::Retrying => ConnectionHealth::Reconnecting,
+                    ConnectionStatus::Ok if link != LinkStatus::Connected => {
+                        ConnectionHealth::Reconnecting
+                    }
+                    ConnectionStatus::Ok => ConnectionHealth::Connected,
+                };
+                let stats = self.connection_stats.get(&id);
+                ConnectionInfo {
+                    id,
+                    status,
+                    link,
+                    trip_count: breaker.map(CircuitBreaker::trip_count).unwrap_or_default(),
+                    fps: stats.map(ConnectionStats::fps).unwrap_or_default(),
+                    bytes_per_second: stats
+                        .map(ConnectionStats::bytes_per_second)
+                        .unwrap_or_default(),
+                    queued_frames: connection.queued_frames(),
+                    dropped_frames: stats
+                        .map(ConnectionStats::dropped_frames)
+                        .unwrap_or_default(),
+                    dropped_per_second: stats
+                        .map(ConnectionStats::dropped_per_second)
+                        .unwrap_or_default(),
+                    skipped_frames: stats
+                        .map(ConnectionStats::skipped_frames)
+                        .unwrap_or_default(),
+                    health,
+                    latency_ms: stats.and_then(ConnectionStats::latency_ms),
+                    round_trip_ms: connection
+                        .round_trip()
+                        .map(|round_trip| round_trip.as_secs_f32() * 1000.0),
+                    delay_ms: connection.delay().as_secs_f32() * 1000.0,
+                    last_error: stats.and_then(|stats| stats.last_error().map(str::to_owned)),
+                }
+            })
+            .collect()
+    }
+
+    pub fn effect_info(&self) -> Vec<EffectInfo> {
+        self.effects
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|(id, instance)| {
+                let timing = self.scheduler.timing(id).copied().unwrap_or_default();
+                EffectInfo {
+                    id,
+                    settings_id: instance.settings.as_ref().map(SettingsHandle::id),
+                    render_time_ms: timing.average.as_secs_f32() * 1000.0,
+                    max_render_time_ms: timing.max.as_secs_f32() * 1000.0,
+                    slow_frames: timing.slow_frames,
+                    frozen: self.frozen_effects.contains(&id),
+                }
+            })
+            .collect()
+    }
+
+    /// Colors of the ledstrips rounded to 8 bits, before the post processing
+    pub fn led_strip_colors(&self) -> impl Iterator<Item = (usize, Vec<turbo_plugin::Color>)> + '_ {
+        self.led_strips.iter().map(|(id, led_strip)| {
+            (
+                id,
+                led_strip.colors.iter().map(ledstrip::to_color).collect(),
+            )
+        })
+    }
+
+    /// Records the next `seconds` of frames of the ledstrips, all of them if none are given, and
+    /// writes them to `path` as a gif, a png or json depending on its extension
+    pub fn capture_frames(
+        &mut self,
+        path: &Path,
+        seconds: f32,
+        ledstrips: Vec<usize>,
+    ) -> Result<(), String> {
+        if let Some(capture) = &self.frame_capture {
+            return Err(format!(
+                "Already capturing the frames to {}",
+                capture.path().display()
+            ));
+        }
+        let Some(format) = FrameCaptureFormat::from_path(path) else {
+            return Err(format!(
+                "Unknown format for {}, expected a .gif, .png or .json file",
+                path.display()
+            ));
+        };
+        if !(seconds > 0.0 && seconds <= MAX_CAPTURE_SECONDS) {
+            return Err(format!(
+                "Invalid capture duration {seconds}, expected up to {MAX_CAPTURE_SECONDS}s"
+            ));
+        }
+        if let Some(ledstrip_id) = ledstrips.iter().find(|id| !self.led_strips.contains(**id)) {
+            return Err(format!("Ledstrip {ledstrip_id} doesn't exist"));
+        }
+        let ledstrips = match ledstrips.is_empty() {
+            true => self.led_strips.iter().map(|(id, _)| id).collect(),
+            false => ledstrips,
+        };
+        let capture = FrameCapture::create(path, format, seconds, ledstrips)
+            .map_err(|e| format!("Couldn't create {}: {e}", path.display()))?;
+        tracing::info!("Capturing {seconds}s of frames to {}", path.display());
+        self.frame_capture = Some(capture);
+        Ok(())
+    }
+
+    // Records the frame of the tick, and writes the capture out of the run loop once complete
+    fn capture_frame(&mut self) {
+        let Some(capture) = &mut self.frame_capture else {
+            return;
+        };
+        let led_strips = &self.led_strips;
+        let complete = capture.record(|ledstrip_id| {
+            let ledstrip = led_strips.get(ledstrip_id)?;
+            Some(ledstrip.colors.iter().map(ledstrip::to_color).collect())
+        });
+        if !complete {
+            return;
+        }
+        let Some(capture) = self.frame_capture.take() else {
+            return;
+        };
+        std::thread::spawn(move || {
+            let path = capture.path().to_owned();
+            match capture.write() {
+                Ok(()) => tracing::info!("Wrote the captured frames to {}", path.display()),
+                Err(e) => tracing::error!("Couldn't write the frames to {}: {e}", path.display()),
+            }
+        });
+    }
+
+    /// Global brightness between 0 and 1, moving along the fade of the schedule if any
+    pub fn brightness(&self) -> f32 {
+        self.brightness
+    }
+
+    pub fn frozen_effects(&self) -> Vec<usize> {
+        let mut frozen_effects: Vec<usize> = self.frozen_effects.iter().copied().collect();
+        frozen_effects.sort_unstable();
+        frozen_effects
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_blackout(&self) -> bool {
+        self.blackout
+    }
+
+    pub fn is_frame_frozen(&self) -> bool {
+        self.frame_frozen
+    }
+
+    pub fn solo(&self) -> Option<usize> {
+        self.solo
+    }
+
+    fn check_ledstrip(&self, ledstrip_id: Option<usize>) -> Result<(), EffectInstanceError> {
+        match ledstrip_id {
+            Some(ledstrip_id) if !self.led_strips.contains(ledstrip_id) => {
+                Err(EffectInstanceError::UnknownLedstrip(ledstrip_id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends black frames to the ledstrip, or to every ledstrip if missing, whatever the effects
+    /// and the brightness
+    pub fn set_blackout(
+        &mut self,
+        ledstrip_id: Option<usize>,
+        on: bool,
+    ) -> Result<(), EffectInstanceError> {
+        self.check_ledstrip(ledstrip_id)?;
+        let action = if on { "Starting" } else { "Ending" };
+        match ledstrip_id {
+            Some(ledstrip_id) => {
+                tracing::info!("{action} the blackout of ledstrip {ledstrip_id}");
+                if on {
+                    self.blackout_ledstrips.insert(ledstrip_id);
+                } else {
+                    self.blackout_ledstrips.remove(&ledstrip_id);
+                }
+            }
+            None => {
+                tracing::info!("{action} the blackout");
+                self.blackout = on;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps sending the current frame of the ledstrip, or of every ledstrip if missing, without
+    /// rendering its effects
+    pub fn freeze_frame(
+        &mut self,
+        ledstrip_id: Option<usize>,
+        frozen: bool,
+    ) -> Result<(), EffectInstanceError> {
+        self.check_ledstrip(ledstrip_id)?;
+        let action = if frozen { "Freezing" } else { "Unfreezing" };
+        match ledstrip_id {
+            Some(ledstrip_id) => {
+                tracing::info!("{action} the frame of ledstrip {ledstrip_id}");
+                if frozen {
+                    self.frozen_ledstrips.insert(ledstrip_id);
+                } else {
+                    self.frozen_ledstrips.remove(&ledstrip_id);
+                }
+            }
+            None => {
+                tracing::info!("{action} the frame");
+                self.frame_frozen = frozen;
+            }
+        }
+        Ok(())
+    }
+
+    /// Blacks out every ledstrip but this one, or none if missing
+    pub fn set_solo(&mut self, ledstrip_id: Option<usize>) -> Result<(), EffectInstanceError> {
+        self.check_ledstrip(ledstrip_id)?;
+        match ledstrip_id {
+            Some(ledstrip_id) => tracing::info!("Soloing ledstrip {ledstrip_id}"),
+            None => tracing::info!("Ending the solo"),
+        }
+        self.solo = ledstrip_id;
+        Ok(())
+    }
+
+    pub fn set_audio_available(&mut self, audio_available: bool) {
+        self.audio_available = audio_available;
+    }
+
+    /// Ends the startup scenes of the ledstrips once samples came in
+    pub fn set_audio_received(&mut self) {
+        self.audio_received = true;
+    }
+
+    // Scene shown on the whole ledstrip instead of its effects, if any
+    fn fallback_scene(&self, scenes: &FallbackScenes) -> Option<FallbackScene> {
+        if !self.audio_received {
+            scenes.startup
+        } else if !self.audio_available {
+            scenes.failure
+        } else {
+            None
+        }
+    }
+
+    pub fn is_audio_available(&self) -> bool {
+        self.audio_available
+    }
+
+    /// Replaces the idle config, keeping the silence counted so far when it doesn't change
+    pub fn set_idle(&mut self, config: Option<IdleConfig>) {
+        if self.silence_detector.as_ref().map(SilenceDetector::config) == config {
+            return;
+        }
+        self.silence_detector = config.map(SilenceDetector::new);
+        self.idle = false;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    // Follows the silence of the audio, returns whether the engine is idle
+    fn update_idle(&mut self) -> bool {
+        let Some(silence_detector) = &mut self.silence_detector else {
+            return false;
+        };
+        let idle = silence_detector.update(self.fft_result.load().rms());
+        if idle != self.idle {
+            let config = silence_detector.config();
+            match config.effect_id {
+                _ if !idle => tracing::info!("Sound is back, switching back to the effects"),
+                Some(effect_id) if self.contains_effect(effect_id) => tracing::info!(
+                    "No sound for {}s, switching to the idle effect {effect_id}",
+                    config.after_secs
+                ),
+                Some(effect_id) => tracing::warn!(
+                    "No sound for {}s but the idle effect {effect_id} doesn't exist, turning the ledstrips off",
+                    config.after_secs
+                ),
+                None => tracing::info!(
+                    "No sound for {}s, turning the ledstrips off",
+                    config.after_secs
+                ),
+            }
+            self.idle = idle;
+        }
+        idle
+    }
+
+    /// Renders the effects on this many threads, or on one thread per core if None
+    pub fn set_render_threads(&mut self, threads: Option<usize>) {
+        self.scheduler
+            .set_threads(threads.unwrap_or_else(available_cores));
+    }
+
+    pub fn set_effect_budget(&mut self, budget: EffectBudgetConfig) {
+        self.scheduler.set_budget(budget);
+    }
+
+    pub fn set_engine_load(&mut self, engine_load: f32) {
+        self.engine_load = engine_load;
+    }
+
+    pub fn engine_load(&self) -> f32 {
+        self.engine_load
+    }
+
+    pub fn sync_offset_ms(&self) -> i32 {
+        self.av_sync.offset_ms()
+    }
+
+    pub fn add_connection(
+        &mut self,
+        connection_id: usize,
+        connection: Box<dyn Connection>,
+        encoding: &FrameEncoding,
+        keep_alive: Option<KeepAliveConfig>,
+        dithering: bool,
+        delay: DispatchDelay,
+    ) -> Result<(), RegistryError> {
+        self.connections.insert(
+            connection_id,
+            ConnectionSender::new(connection_id, connection, delay),
+        )?;
+        if !delay.is_none() {
+            self.dispatch_delays.insert(connection_id, delay);
+        }
+        self.encoders.insert(connection_id, encoding.encoder());
+        if let Some(keep_alive) = keep_alive {
+            self.keep_alive.insert(connection_id, keep_alive);
+        }
+        if dithering {
+            self.dithered_connections.insert(connection_id);
+        }
+        Ok(())
+    }
+
+    /// Corrects the frames sent to a connection with lookup tables, or stops correcting them
+    pub fn set_color_lut(&mut self, connection_id: usize, color_lut: Option<ColorLut>) {
+        match color_lut {
+            Some(color_lut) => self.color_luts.insert(connection_id, color_lut),
+            None => self.color_luts.remove(&connection_id),
+        };
+    }
+
+    /// Closes a connection. The ledstrips linked to it are left linked, so that a connection added
+    /// back under the same id takes over
+    pub fn remove_connection(&mut self, connection_id: usize) {
+        self.connections.remove(connection_id);
+        self.encoders.remove(&connection_id);
+        self.keep_alive.remove(&connection_id);
+        self.dithered_connections.remove(&connection_id);
+        self.color_luts.remove(&connection_id);
+        self.dispatch_delays.remove(&connection_id);
+        self.connection_breakers.remove(&connection_id);
+        self.connection_stats.remove(&connection_id);
+        self.test_patterns.remove(&connection_id);
+    }
+
+    /// Replaces the profiles that can be applied. The active profile is applied again, so that
+    /// its changes show after a config reload
+    pub fn set_profiles(&mut self, profiles: Vec<ProfileConfig>) {
+        self.profiles = profiles;
+        let Some(name) = self.active_profile.take() else {
+            return;
+        };
+        if let Err(e) = self.apply_profile(&name) {
+            tracing::warn!("Can't apply the profile {name} again: {e}");
+        }
+    }
+
+    /// Renders the effects of a profile and sets its brightness
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), ProfileError> {
+        let profile = self
+            .profiles
+            .iter()
+            .find(|profile| profile.name == name)
+            .cloned()
+            .ok_or_else(|| ProfileError::Unknown(name.to_owned()))?;
+        for ledstrip in &profile.ledstrips {
+            for (segment, effect_id) in ledstrip.effects.iter().enumerate() {
+                self.assign_effect(ledstrip.ledstrip_id, segment, *effect_id)?;
+            }
+        }
+        self.brightness = profile.brightness;
+        self.brightness_fade = None;
+        self.active_profile = Some(profile.name);
+        tracing::info!("Switched to the profile {name}");
+        Ok(())
+    }
+
+    /// Applies the profile `steps` after the active one in the order of the config, wrapping
+    /// around. Counts from before the first profile when none is active
+    pub fn step_profile(&mut self, steps: i32) -> Result<(), ProfileError> {
+        if self.profiles.is_empty() {
+            return Err(ProfileError::NoProfiles);
+        }
+        let count = self.profiles.len() as i64;
+        let active = self.active_profile.as_deref().and_then(|name| {
+            self.profiles
+                .iter()
+                .position(|profile| profile.name == name)
+        });
+        let index = match active {
+            Some(index) => index as i64 + steps as i64,
+            None if steps > 0 => steps as i64 - 1,
+            None => steps as i64,
+        }
+        .rem_euclid(count);
+        let name = self.profiles[index as usize].name.clone();
+        self.apply_profile(&name)
+    }
+
+    /// Replaces the schedule, which catches up on the entries of the past day when it changes
+    pub fn set_schedule(&mut self, entries: Vec<ScheduleEntryConfig>) {
+        if self.schedule.as_ref().map(Schedule::entries) == Some(entries.as_slice()) {
+            return;
+        }
+        self.schedule = (!entries.is_empty()).then(|| Schedule::new(entries));
+        self.brightness_fade = None;
+        // Checked on the next frame
+        self.schedule_checked_at = Instant::now() - SCHEDULE_CHECK_INTERVAL;
+    }
+
+    /// Replaces the night mode config. Whether it's on is computed again on the next frame
+    pub fn set_night_mode(&mut self, config: NightModeConfig) {
+        if self.night_mode.config() == config {
+            return;
+        }
+        self.night_mode = NightMode::new(config);
+        self.schedule_checked_at = Instant::now() - SCHEDULE_CHECK_INTERVAL;
+    }
+
+    pub fn is_night_mode(&self) -> bool {
+        self.night_mode.is_on()
+    }
+
+    // Applies the schedule entries that became due, and moves the brightness along its fade
+    fn update_schedule(&mut self) {
+        if self.schedule_checked_at.elapsed() >= SCHEDULE_CHECK_INTERVAL {
+            self.schedule_checked_at = Instant::now();
+            if self.night_mode.update(schedule::local_now().time()) {
+                tracing::info!(
+                    "{} the night mode",
+                    if self.night_mode.is_on() {
+                        "Starting"
+                    } else {
+                        "Ending"
+                    }
+                );
+            }
+            let due: Vec<(ScheduleEntryConfig, Duration)> = self
+                .schedule
+                .as_mut()
+                .map(|schedule| {
+                    schedule
+                        .due(schedule::local_now())
+                        .into_iter()
+                        .map(|(entry, late)| (entry.clone(), late))
+                        .collect()
+                })
+                .unwrap_or_default();
+            for (entry, late) in due {
+                tracing::info!("Applying the schedule entry of {}", entry.at);
+                if let Some(profile) = &entry.profile {
+                    if let Err(e) = self.apply_profile(profile) {
+                        tracing::warn!("Can't apply the scheduled profile {profile}: {e}");
+                    }
+                }
+                if let Some(brightness) = entry.brightness {
+                    // Entries missed while the engine wasn't running pick their fade up midway
+                    self.brightness_fade = Some(BrightnessFade {
+                        from: self.brightness,
+                        to: brightness,
+                        started_at: Instant::now()
+                            .checked_sub(late)
+                            .unwrap_or_else(Instant::now),
+                        duration: entry.fade(),
+                    });
+                }
+            }
+        }
+
+        if let Some(fade) = &self.brightness_fade {
+            self.brightness = fade.brightness();
+            if fade.is_over() {
+                self.brightness_fade = None;
+            }
+        }
+    }
+
+    pub fn set_sync(&mut self, sync: Option<SyncPeer>) {
+        self.sync = sync;
+    }
+
+    /// Starts playing clicks to measure the audio to light latency over `seconds`, after which
+    /// the beat is predicted that far ahead
+    pub fn calibrate_latency(&mut self, seconds: f32) -> Result<(), String> {
+        if !(seconds > 0.0 && seconds.is_finite()) {
+            return Err(format!("Invalid calibration duration {seconds}"));
+        }
+        if self.latency_calibration.is_some() {
+            return Err("The latency is already being calibrated".to_owned());
+        }
+        let calibration = LatencyCalibration::start(Duration::from_secs_f32(seconds))
+            .map_err(|e| e.to_string())?;
+        self.latency_calibration = Some(calibration);
+        Ok(())
+    }
+
+    pub fn latency(&self) -> Latency {
+        self.latency
+    }
+
+    // Times the clicks heard, then adds the time the frames take to reach the devices
+    fn update_latency_calibration(&mut self) {
+        let Some(calibration) = &mut self.latency_calibration else {
+            return;
+        };
+        let Some(result) = calibration.tick(&self.fft_result.load()) else {
+            return;
+        };
+        self.latency_calibration = None;
+        let audio = match result {
+            Ok(audio) => audio,
+            Err(e) => {
+                tracing::warn!("Couldn't calibrate the audio to light latency: {e}");
+                return;
+            }
+        };
+
+        // Time to hand a frame to the connection, then for the device to get it
+        let lights_ms: Vec<f32> = self
+            .connections
+            .iter()
+            .map(|(connection_id, connection)| {
+                let send_ms = self
+                    .connection_stats
+                    .get(&connection_id)
+                    .and_then(ConnectionStats::latency_ms)
+                    .unwrap_or_default();
+                let network_ms = connection
+                    .round_trip()
+                    .map(|round_trip| round_trip.as_secs_f32() * 500.0)
+                    .unwrap_or_default();
+                send_ms + network_ms
+            })
+            .collect();
+        self.latency = Latency {
+            audio_ms: audio.as_secs_f32() * 1000.0,
+            lights_ms: lights_ms.iter().sum::<f32>() / lights_ms.len().max(1) as f32,
+        };
+        tracing::info!(
+            "The lights react {:.0}ms after the audio ({:.0}ms to hear it, {:.0}ms to send the frames), predicting the beat that far ahead",
+            self.latency.total().as_secs_f32() * 1000.0,
+            self.latency.audio_ms,
+            self.latency.lights_ms
+        );
+        match self.latency.save(&self.latency_file) {
+            Ok(()) => tracing::info!(
+                "Saved the audio to light latency to {}",
+                self.latency_file.display()
+            ),
+            Err(e) => tracing::error!(
+                "Couldn't save the audio to light latency to {}: {e}",
+                self.latency_file.display()
+            ),
+        }
+    }
+
+    // Follows the beat, then shares the clock and the profile with the other instances. The
+    // effects read the clock of the leader when following one
+    fn update_sync(&mut self) {
+        let mut clock = sync::clock();
+        let fft_result = self.fft_result.load();
+        let detected = self.beat_tracker.tick(&fft_result, clock.time());
+        let transport = self.transport_beat(&clock);
+        let beat = transport.map(|(beat, _)| beat).or(detected);
+        let mut switched_to = None;
+        match &mut self.sync {
+            Some(peer) if peer.role() == SyncRole::Follower => {
+                switched_to = peer.receive();
+                clock = SyncClock {
+                    offset: peer.offset(),
+                    beat: peer.leader_beat().or(beat),
+                    ..clock
+                };
+            }
+            Some(peer) => {
+                peer.broadcast(beat, self.active_profile.as_deref());
+                clock.beat = beat;
+            }
+            None => clock.beat = beat,
+        }
+        match transport {
+            // Unless following the leader
+            Some((beat, count)) if clock.beat == Some(beat) => {
+                // The transport is what is heard, only the lights lag
+                clock.lead = self.latency.lights_ms as f64 / 1000.0;
+                clock.beat_count = Some(count);
+                self.phase_lock = PhaseLock::default();
+            }
+            _ => {
+                clock.lead = self.latency.total().as_secs_f64();
+                clock.beat_count = self
+                    .phase_lock
+                    .update(clock.beat, clock.time() + clock.lead);
+            }
+        }
+        sync::set_clock(clock);
+
+        if let Some(profile) = switched_to {
+            if let Err(e) = self.apply_profile(&profile) {
+                tracing::warn!("Can't apply the profile {profile} of the sync leader: {e}");
+            }
+        }
+    }
+
+    // Beat and beats counted at the tempo and position of the transport, now. None while it isn't
+    // playing or stopped being read, like when the JACK server quit
+    fn transport_beat(&self, clock: &SyncClock) -> Option<(BeatTiming, BeatCount)> {
+        let now = sync::local_time();
+        let position = (*self.transport.lock().unwrap())
+            .filter(|position| now - position.read_at < TRANSPORT_TIMEOUT)?;
+        let beats = position.beats_at(now);
+        let interval = 60.0 / position.bpm;
+        let time = clock.time();
+        Some((
+            BeatTiming {
+                last: time - beats.rem_euclid(1.0) * interval,
+                interval,
+            },
+            BeatCount { beats, time },
+        ))
+    }
+
+    // Holds the compensated connections back by how much faster than the slowest of them they
+    // reach their device, so that they all show a frame at the same time. The connections that
+    // can't measure their latency count as instant
+    fn update_dispatch_delays(&mut self) {
+        let latency = |connection_id: usize| {
+            self.connections
+                .get(connection_id)
+                .and_then(ConnectionSender::round_trip)
+                .map(|round_trip| round_trip / 2)
+                .unwrap_or_default()
+        };
+        let slowest = self
+            .dispatch_delays
+            .iter()
+            .filter(|(_, delay)| delay.compensate)
+            .map(|(connection_id, _)| latency(*connection_id))
+            .max()
+            .unwrap_or_default();
+        for (connection_id, delay) in &self.dispatch_delays {
+            let Some(connection) = self.connections.get(*connection_id) else {
+                continue;
+            };
+            let compensation = match delay.compensate {
+                true => slowest.saturating_sub(latency(*connection_id)),
+                false => Duration::ZERO,
+            };
+            connection.set_delay(delay.fixed + compensation);
+        }
+    }
+
+    pub fn active_profile(&self) -> Option<&str> {
+        self.active_profile.as_deref()
+    }
+
+    /// Profile, brightness and effects of the segments, which the control api changes
+    pub fn session_state(&self) -> SessionState {
+        SessionState {
+            profile: self.active_profile.clone(),
+            brightness: self.brightness,
+            ledstrip_brightness: self
+                .led_strip_brightness
+                .iter()
+                .map(|(id, brightness)| (*id, *brightness))
+                .collect(),
+            ledstrip_effects: self
+                .led_strips
+                .iter()
+                .map(|(id, ledstrip)| {
+                    let effects = ledstrip.effects.iter().map(|effect| effect.effect_id);
+                    (id, effects.collect())
+                })
+                .collect(),
+        }
+    }
+
+    /// Applies a state saved before a restart. What the config doesn't have anymore, like the
+    /// effects created through the control api, is skipped. The schedule only applies the
+    /// entries due since the save
+    pub fn restore_session(&mut self, saved: &SavedSession) {
+        let state = &saved.state;
+        if let Some(name) = &state.profile {
+            if let Err(e) = self.apply_profile(name) {
+                tracing::warn!("Can't restore the profile {name}: {e}");
+            }
+        }
+        self.brightness = state.brightness.clamp(0.0, 1.0);
+        self.brightness_fade = None;
+        for (ledstrip_id, brightness) in &state.ledstrip_brightness {
+            if self.led_strips.contains(*ledstrip_id) {
+                self.led_strip_brightness
+                    .insert(*ledstrip_id, brightness.clamp(0.0, 1.0));
+            }
+        }
+        for (ledstrip_id, effects) in &state.ledstrip_effects {
+            for (segment, effect_id) in effects.iter().enumerate() {
+                if let Err(e) = self.assign_effect(*ledstrip_id, segment, *effect_id) {
+                    tracing::warn!("Can't restore effect {effect_id}: {e}");
+                }
+            }
+        }
+        if let Some(schedule) = &mut self.schedule {
+            schedule.resume_from(saved.saved_at);
+        }
+        tracing::info!("Restored the session saved at {}", saved.saved_at);
+    }
+
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles
+            .iter()
+            .map(|profile| profile.name.clone())
+            .collect()
+    }
+
+    /// Sends a test pattern to a connection instead of its ledstrips, or goes back to the
+    /// ledstrips if `pattern` is missing. Returns false if the connection doesn't exist
+    pub fn set_test_pattern(&mut self, connection_id: usize, pattern: Option<TestPattern>) -> bool {
+        if !self.connections.contains(connection_id) {
+            return false;
+        }
+        let Some(pattern) = pattern else {
+            if self.test_patterns.remove(&connection_id).is_some() {
+                tracing::info!("Stopped the test pattern of connection {connection_id}");
+            }
+            // Sends the ledstrips again even if their colors didn't change since
+            self.last_frames.retain(|ledstrip_id, _| {
+                self.led_strip_connections.get(ledstrip_id) != Some(&connection_id)
+            });
+            return true;
+        };
+
+        let mut led_count = 0;
+        let mut segments = Vec::new();
+        for (ledstrip_id, _) in self
+            .led_strip_connections
+            .iter()
+            .filter(|(_, id)| **id == connection_id)
+        {
+            let Some(ledstrip) = self.led_strips.get(*ledstrip_id) else {
+                continue;
+            };
+            let offset = self
+                .led_strip_offsets
+                .get(ledstrip_id)
+                .copied()
+                .unwrap_or(0);
+            led_count = led_count.max(offset + ledstrip.led_count());
+            // The segments are in pixels, which don't line up with the leds once transformed
+            if ledstrip.effects.is_empty() || ledstrip.transform.is_some() {
+                segments.push(offset..offset + ledstrip.led_count());
+                continue;
+            }
+            segments.extend(ledstrip.effects.iter().map(|effect| {
+                let (start, end) = effect.interval;
+                offset + start..offset + end + 1
+            }));
+        }
+        tracing::info!("Showing the {pattern:?} test pattern on connection {connection_id}");
+        self.test_patterns.insert(
+            connection_id,
+            TestPatternRenderer::new(pattern, led_count, segments),
+        );
+        true
+    }
+
+    pub fn add_led_strip(
+        &mut self,
+        led_strip_id: usize,
+        led_strip: LedStrip,
+    ) -> Result<(), RegistryError> {
+        self.led_strips.insert(led_strip_id, led_strip)
+    }
+
+    /// Drops a ledstrip with its links and the frames kept for it. Its brightness is kept since
+    /// it's set at runtime
+    pub fn remove_led_strip(&mut self, led_strip_id: usize) {
+        self.led_strips.remove(led_strip_id);
+        self.led_strip_connections.remove(&led_strip_id);
+        self.led_strip_offsets.remove(&led_strip_id);
+        self.update_outgoing_frames();
+        self.frame_buffers.remove(&led_strip_id);
+        self.led_strip_fallbacks.remove(&led_strip_id);
+        self.failed_over.remove(&led_strip_id);
+        self.post_processing.remove(&led_strip_id);
+        self.dithering.remove(&led_strip_id);
+        self.last_frames.remove(&led_strip_id);
+        self.av_sync.remove(led_strip_id);
+        if let Some(interpolation) = &mut self.interpolation {
+            interpolation.remove(led_strip_id);
+        }
+        self.undersized_warnings
+            .retain(|(ledstrip_id, _)| *ledstrip_id != led_strip_id);
+    }
+
+    /// Adds a ledstrip under a new id and returns it
+    pub fn allocate_led_strip(&mut self, led_strip: LedStrip) -> usize {
+        self.led_strips.allocate(led_strip)
+    }
+
+    pub fn set_post_processing(&mut self, led_strip_id: usize, chain: PostProcessingChain) {
+        self.post_processing.insert(led_strip_id, chain);
+    }
+
+    pub fn link_led_strip_to_connection(
+        &mut self,
+        led_strip_id: usize,
+        connection_id: usize,
+    ) -> bool {
+        if self.connections.contains(connection_id) {
+            self.led_strip_connections
+                .insert(led_strip_id, connection_id);
+            self.update_outgoing_frames();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Sends the ledstrip as part of the frame of its connection, starting at the led `offset`,
+    /// along with the other ledstrips of the connection that have an offset
+    pub fn set_led_strip_offset(&mut self, led_strip_id: usize, offset: usize) {
+        self.led_strip_offsets.insert(led_strip_id, offset);
+        self.update_outgoing_frames();
+    }
+
+    pub fn link_led_strip_to_fallback(
+        &mut self,
+        led_strip_id: usize,
+        connection_id: usize,
+    ) -> bool {
+        if self.connections.contains(connection_id) {
+            self.led_strip_fallbacks.insert(led_strip_id, connection_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Picks the connection a ledstrip is sent to: its fallback while its connection is paused by
+    /// the circuit breaker, unless the fallback is paused too. Once the cooldown is over, frames
+    /// go to the primary connection again to check if it recovered.
+    fn route(&mut self, led_strip_id: usize, connection_id: usize) -> usize {
+        let is_down = |connection_id: &usize| {
+            self.connection_breakers
+                .get(connection_id)
+                .is_some_and(CircuitBreaker::is_cooling_down)
+        };
+        let fallback_id = self
+            .led_strip_fallbacks
+            .get(&led_strip_id)
+            .copied()
+            .filter(|fallback_id| is_down(&connection_id) && !is_down(fallback_id));
+
+        match fallback_id {
+            Some(fallback_id) => {
+                if self.failed_over.insert(led_strip_id) {
+                    tracing::warn!("Connection {connection_id} is down, sending ledstrip {led_strip_id} to its fallback connection {fallback_id}");
+                }
+                fallback_id
+            }
+            None => {
+                if self.failed_over.remove(&led_strip_id) && !is_down(&connection_id) {
+                    tracing::info!("Trying to send ledstrip {led_strip_id} to connection {connection_id} again");
+                }
+                connection_id
+            }
+        }
+    }
+
+    pub fn update_led_strips(&mut self) {
+        self.update_latency_calibration();
+        if self.paused || self.frame_frozen {
+            return;
+        }
+
+        self.drum_classifier.tick(&self.fft_result.load());
+        self.apply_parameter_bindings();
+        self.update_schedule();
+        self.update_sync();
+        self.update_dispatch_delays();
+
+        for channel in self.overrides.expire(Instant::now()) {
+            tracing::info!("The override of channel {channel} is over");
+        }
+
+        // Ledstrips showing a scene instead of their effects, before the audio arrives or once
+        // it's lost
+        let fallback_scenes: HashMap<usize, FallbackScene> = self
+            .led_strips
+            .iter()
+            .filter_map(|(led_strip_id, led_strip)| {
+                Some((led_strip_id, self.fallback_scene(&led_strip.scenes)?))
+            })
+            .collect();
+
+        // While idle every segment renders the idle effect, or nothing unless it's overridden
+        let idle = self.update_idle();
+        let idle_effect = if idle {
+            let idle_effect = self
+                .silence_detector
+                .as_ref()
+                .and_then(|silence_detector| silence_detector.config().effect_id)
+                .filter(|effect_id| self.contains_effect(*effect_id));
+            if idle_effect.is_none() {
+                self.led_strips
+                    .iter_mut()
+                    .filter(|(led_strip_id, _)| {
+                        !self.frozen_ledstrips.contains(led_strip_id)
+                            && !fallback_scenes.contains_key(led_strip_id)
+                    })
+                    .for_each(|(_, led_strip)| led_strip.colors.fill(Pixel::default()));
+                if self.overrides.is_empty() {
+                    self.apply_fallback_scenes(&fallback_scenes, &BTreeMap::new());
+                    return;
+                }
+            }
+            idle_effect
+        } else {
+            None
+        };
+
+        if self.av_sync.render_test_mode(
+            self.led_strips
+                .values_mut()
+                .map(|led_strip| led_strip.colors.as_mut_slice()),
+        ) {
+            return;
+        }
+
+        // Every effect to render this frame, with the segments it renders on
+        let mut targets: BTreeMap<usize, Vec<RenderTarget>> = BTreeMap::new();
+        let mut errors: BTreeMap<(usize, usize), RenderError> = BTreeMap::new();
+        for (led_strip_id, led_strip) in self.led_strips.iter_mut() {
+            if self.frozen_ledstrips.contains(&led_strip_id)
+                || fallback_scenes.contains_key(&led_strip_id)
+            {
+                continue;
+            }
+            for (
+                index,
+                LedStripEffect {
+                    effect_id,
+                    interval,
+                    smoothing,
+                    undersized,
+                    blur,
+                    layout,
+                    priority,
+                },
+            ) in led_strip.effects.iter_mut().enumerate()
+            {
+                let overridden = self.overrides.effect(led_strip_id, index, *priority);
+                let effect_id = match overridden.as_ref().or(idle_effect.as_ref()) {
+                    Some(effect_id) => effect_id,
+                    // Left off while idle without an idle effect
+                    None if idle => continue,
+                    None => effect_id,
+                };
+                if self.frozen_effects.contains(effect_id) {
+                    continue;
+                }
+
+                let segment = (led_strip_id, interval.0);
+                let Some(leds) = led_strip.colors.get(interval.0..=interval.1) else {
+                    let error = RenderError::InvalidInterval {
+                        effect_id: *effect_id,
+                        interval: *interval,
+                        size: led_strip.size,
+                    };
+                    errors.insert(segment, error);
+                    continue;
+                };
+                let Some(instance) = self.effects.as_ref().unwrap().get(*effect_id) else {
+                    errors.insert(segment, RenderError::MissingEffect(*effect_id));
+                    continue;
+                };
+                if instance.settings.is_none() {
+                    errors.insert(segment, RenderError::MissingSettings(*effect_id));
+                    continue;
+                }
+                let effect = &instance.effect;
+
+                let requirements = effect.pixel_requirements();
+                let effect_size = layout.effect_size(leds.len());
+                if effect_size < requirements.min as usize
+                    && self.undersized_warnings.insert((led_strip_id, *effect_id))
+                {
+                    let action = match undersized {
+                        UndersizedPolicy::Resample => "Resampling it",
+                        UndersizedPolicy::Warn => "It might not render properly",
+                    };
+                    tracing::warn!(
+                        "Effect {effect_id} needs at least {} pixels but only has {} on ledstrip {led_strip_id}. {action}.",
+                        requirements.min,
+                        effect_size
+                    );
+                }
+
+                targets.entry(*effect_id).or_default().push(RenderTarget {
+                    led_strip_id,
+                    interval: *interval,
+                    smoothing: *smoothing,
+                    render_size: undersized.render_size(requirements, effect_size),
+                    // Moved back once rendered, so that its buffer is kept between frames
+                    blur: blur.take(),
+                    layout: layout.clone(),
+                    colors: leds.to_vec(),
+                    error: None,
+                });
+            }
+        }
+
+        let jobs: Vec<RenderJob> = self
+            .effects
+            .as_mut()
+            .unwrap()
+            .iter_mut()
+            .filter_map(|(effect_id, instance)| {
+                Some(RenderJob {
+                    effect_id,
+                    effect: &mut instance.effect,
+                    // Loaded once, so that every segment of the effect renders with the same ones
+                    settings: instance.settings.as_ref().map(SettingsHandle::load),
+                    targets: targets.remove(&effect_id)?,
+                })
+            })
+            .collect();
+        for (effect_id, mut target) in self.scheduler.run(jobs) {
+            let Some(led_strip) = self.led_strips.get_mut(target.led_strip_id) else {
+                continue;
+            };
+            if let Some(effect) = led_strip
+                .effects
+                .iter_mut()
+                .find(|effect| effect.interval == target.interval)
+            {
+                effect.blur = target.blur.take();
+            }
+            if let Some(error) = target.error {
+                let segment = (target.led_strip_id, target.interval.0);
+                errors.insert(segment, RenderError::Tick(effect_id, error));
+                continue;
+            }
+            if let Some(leds) = led_strip
+                .colors
+                .get_mut(target.interval.0..=target.interval.1)
+            {
+                leds.copy_from_slice(&target.colors);
+            }
+        }
+
+        for effect_id in self.scheduler.take_over_budget() {
+            let budget = *self.scheduler.budget();
+            let average_ms = self
+                .scheduler
+                .timing(effect_id)
+                .map(|timing| timing.average.as_secs_f32() * 1000.0)
+                .unwrap_or_default();
+            tracing::warn!(
+                "Effect {effect_id} took {average_ms:.1}ms a frame, over {}ms for {} frames",
+                budget.budget_ms,
+                budget.max_slow_frames
+            );
+            if budget.freeze {
+                tracing::warn!("Freezing effect {effect_id}. Unfreeze it to give it another try");
+                self.frozen_effects.insert(effect_id);
+            }
+        }
+        self.apply_fallback_scenes(&fallback_scenes, &errors);
+        self.report_render_errors(errors);
+    }
+
+    // Shows the scenes of the ledstrips that have one, and the failure scene of their ledstrip on
+    // the segments that failed to render
+    fn apply_fallback_scenes(
+        &mut self,
+        fallback_scenes: &HashMap<usize, FallbackScene>,
+        errors: &BTreeMap<(usize, usize), RenderError>,
+    ) {
+        for (led_strip_id, scene) in fallback_scenes {
+            if let Some(led_strip) = self.led_strips.get_mut(*led_strip_id) {
+                scene.apply(&mut led_strip.colors);
+            }
+        }
+        for (led_strip_id, first_led) in errors.keys() {
+            let Some(led_strip) = self.led_strips.get_mut(*led_strip_id) else {
+                continue;
+            };
+            let Some(scene) = led_strip.scenes.failure else {
+                continue;
+            };
+            let Some(effect) = led_strip
+                .effects
+                .iter()
+                .find(|effect| effect.interval.0 == *first_led)
+            else {
+                continue;
+            };
+            let end = effect
+                .interval
+                .1
+                .min(led_strip.colors.len().saturating_sub(1));
+            if let Some(pixels) = led_strip.colors.get_mut(*first_led..=end) {
+                scene.apply(pixels);
+            }
+        }
+    }
+
+    fn report_render_errors(&mut self, errors: BTreeMap<(usize, usize), RenderError>) {
+        let errors: BTreeMap<(usize, usize), String> = errors
+            .into_iter()
+            .map(|(segment, error)| (segment, error.to_string()))
+            .collect();
+        for ((led_strip_id, first_led), error) in &errors {
+            if self.render_errors.get(&(*led_strip_id, *first_led)) != Some(error) {
+                tracing::error!(
+                    "Skipping the segment at led {first_led} of ledstrip {led_strip_id}: {error}"
+                );
+            }
+        }
+        for (led_strip_id, first_led) in self.render_errors.keys() {
+            if !errors.contains_key(&(*led_strip_id, *first_led)) {
+                tracing::info!(
+                    "The segment at led {first_led} of ledstrip {led_strip_id} renders again"
+                );
+            }
+        }
+        self.render_errors = errors;
+    }
+
+    pub fn send_ledstrip_colors(&mut self) {
+        let test_frames: Vec<(usize, Vec<u8>)> = self
+            .test_patterns
+            .iter_mut()
+            .map(|(connection_id, renderer)| (*connection_id, renderer.next_frame()))
+            .collect();
+        for (connection_id, frame) in test_frames {
+            self.send_frame(connection_id, &frame);
+        }
+
+        if self.paused {
+            return;
+        }
+        self.capture_frame();
+
+        if let Some(interpolation) = &mut self.interpolation {
+            for (ledstrip_id, ledstrip) in self.led_strips.iter() {
+                interpolation.push(
+                    ledstrip_id,
+                    self.av_sync.delay(ledstrip_id, &ledstrip.colors),
+                );
+            }
+        }
+        self.send_outgoing_frames(0.0);
+        // Read by the effects on the next tick
+        self.connection_snapshot
+            .store(Arc::new(self.connection_info()));
+    }
+
+    /// Sends a frame to the ledstrips in between two ticks, `progress` of the way from the
+    /// previous rendered frame to the latest one. Only when more than one frame is sent per tick
+    pub fn send_interpolated_frames(&mut self, progress: f32) {
+        if self.paused || self.interpolation.is_none() {
+            return;
+        }
+        self.send_outgoing_frames(progress);
+    }
+
+    fn send_outgoing_frames(&mut self, progress: f32) {
+        // The frames and their buffers are taken out while they're sent, and put back for the next
+        // tick
+        let frames = std::mem::take(&mut self.outgoing_frames);
+        for frame in &frames {
+            let mut buffers = self
+                .frame_buffers
+                .remove(&frame.ledstrip_id)
+                .unwrap_or_default();
+            self.send_outgoing_frame(frame, progress, &mut buffers);
+            self.frame_buffers.insert(frame.ledstrip_id, buffers);
+        }
+        self.outgoing_frames = frames;
+    }
+
+    fn send_outgoing_frame(
+        &mut self,
+        frame: &OutgoingFrame,
+        progress: f32,
+        buffers: &mut FrameBuffers,
+    ) {
+        buffers.channels.clear();
+        buffers
+            .channels
+            .resize(frame.led_count(&self.led_strips) * 3, 0);
+        for (ledstrip_id, offset) in &frame.parts {
+            let blacked_out = self.blackout_ledstrips.contains(ledstrip_id)
+                || self.solo.is_some_and(|solo| solo != *ledstrip_id);
+            let Some(ledstrip) = self.led_strips.get_mut(*ledstrip_id) else {
+                continue;
+            };
+            let mut colors = match &mut self.interpolation {
+                Some(interpolation) => interpolation.frame(*ledstrip_id, progress),
+                None => self.av_sync.delay(*ledstrip_id, &ledstrip.colors),
+            };
+            if let Some(transform) = &mut ledstrip.transform {
+                colors = transform.apply(colors);
+            }
+            let context = ProcessingContext {
+                brightness: self.night_mode.brightness(self.brightness)
+                    * self
+                        .led_strip_brightness
+                        .get(ledstrip_id)
+                        .copied()
+                        .unwrap_or(1.0),
+            };
+            let processed = self
+                .post_processing
+                .entry(*ledstrip_id)
+                .or_default()
+                .process(colors, &context);
+
+            assert!(processed.len() == colors.len() * 3);
+            let channels = &mut buffers.channels[offset * 3..][..processed.len()];
+            if blacked_out {
+                channels.fill(0);
+            } else {
+                channels.copy_from_slice(processed);
+            }
+        }
+        // After the post processing, so that it applies whatever the chain of the ledstrip
+        self.night_mode.apply(&mut buffers.channels);
+        // After the post processing, some chains may not scale by the brightness
+        if self.blackout {
+            buffers.channels.fill(0);
+        }
+
+        let (ledstrip_id, connection_id) = (frame.ledstrip_id, frame.connection_id);
+        let output_id = self.route(ledstrip_id, connection_id);
+        if self.test_patterns.contains_key(&output_id) {
+            return;
+        }
+        // On the 16-bit frame, so that the corrected dark levels don't band
+        if let Some(color_lut) = self.color_luts.get(&output_id) {
+            color_lut.apply(&mut buffers.channels);
+        }
+        if self.dithered_connections.contains(&output_id) {
+            self.dithering
+                .entry(ledstrip_id)
+                .or_default()
+                .apply(&buffers.channels, &mut buffers.bytes);
+        } else {
+            post_processing::quantize(&buffers.channels, &mut buffers.bytes);
+        }
+        if self.is_unchanged_frame(ledstrip_id, connection_id, &buffers.bytes) {
+            self.connection_stats
+                .entry(output_id)
+                .or_default()
+                .on_skipped();
+            return;
+        }
+
+        let sent_at = Instant::now();
+        self.last_frames
+            .entry(ledstrip_id)
+            .and_modify(|last_frame| {
+                last_frame.data.clone_from(&buffers.bytes);
+                last_frame.sent_at = sent_at;
+            })
+            .or_insert_with(|| SentFrame {
+                data: buffers.bytes.clone(),
+                sent_at,
+            });
+        self.send_frame(output_id, &buffers.bytes);
+    }
+
+    // Works out the frames to send every tick. The ledstrips with an offset are combined with
+    // the other ones of their connection
+    fn update_outgoing_frames(&mut self) {
+        let mut frames: Vec<OutgoingFrame> = Vec::new();
+        for (ledstrip_id, connection_id) in &self.led_strip_connections {
+            let Some(offset) = self.led_strip_offsets.get(ledstrip_id) else {
+                frames.push(OutgoingFrame {
+                    ledstrip_id: *ledstrip_id,
+                    connection_id: *connection_id,
+                    parts: vec![(*ledstrip_id, 0)],
+                });
+                continue;
+            };
+            let multiplexed = frames.iter_mut().find(|frame| {
+                frame.connection_id == *connection_id
+                    && self.led_strip_offsets.contains_key(&frame.ledstrip_id)
+            });
+            match multiplexed {
+                Some(frame) => frame.parts.push((*ledstrip_id, *offset)),
+                None => frames.push(OutgoingFrame {
+                    ledstrip_id: *ledstrip_id,
+                    connection_id: *connection_id,
+                    parts: vec![(*ledstrip_id, *offset)],
+                }),
+            }
+        }
+        self.outgoing_frames = frames;
+    }
+
+    // Whether the frame is the one last sent to the ledstrip, on a connection that skips those
+    // until they are due for a refresh
+    fn is_unchanged_frame(&self, ledstrip_id: usize, connection_id: usize, data: &[u8]) -> bool {
+        let Some(keep_alive) = self
+            .keep_alive
+            .get(&connection_id)
+            .filter(|keep_alive| keep_alive.skip_unchanged)
+        else {
+            return false;
+        };
+        self.last_frames
+            .get(&ledstrip_id)
+            .is_some_and(|last_frame| {
+                last_frame.data == data
+                    && last_frame.sent_at.elapsed() < Duration::from_millis(keep_alive.interval_ms)
+            })
+    }
+
+    /// Resends a frame to the ledstrips whose connection has a keep-alive and that didn't get one
+    /// for too long, like when the engine is paused
+    pub fn send_keep_alive_frames(&mut self) {
+        let now = Instant::now();
+        let frames: Vec<(usize, usize, Vec<u8>)> = self
+            .outgoing_frames
+            .iter()
+            .filter_map(|frame| {
+                if self.test_patterns.contains_key(&frame.connection_id) {
+                    return None;
+                }
+                let keep_alive = self.keep_alive.get(&frame.connection_id)?;
+                let last_frame = self.last_frames.get(&frame.ledstrip_id);
+                let interval = Duration::from_millis(keep_alive.interval_ms);
+                if last_frame.is_some_and(|frame| now.duration_since(frame.sent_at) < interval) {
+                    return None;
+                }
+
+                let data = match (keep_alive.color, last_frame) {
+                    (KeepAliveColor::RepeatLast, Some(last_frame)) => last_frame.data.clone(),
+                    _ => vec![0; frame.led_count(&self.led_strips) * 3],
+                };
+                Some((frame.ledstrip_id, frame.connection_id, data))
+            })
+            .collect();
+
+        for (ledstrip_id, connection_id, data) in frames {
+            tracing::trace!("Sending keep-alive frame to ledstrip {ledstrip_id}");
+            self.last_frames
+                .entry(ledstrip_id)
+                .or_insert_with(|| SentFrame {
+                    data: data.clone(),
+                    sent_at: now,
+                })
+                .sent_at = now;
+            let connection_id = self.route(ledstrip_id, connection_id);
+            self.send_frame(connection_id, &data);
+        }
+    }
+
+    /// Restarts the connections whose sends take longer than `max_latency` on average, since they
+    /// can't keep up with the frames. Their sends are paused by their circuit breaker, and the
+    /// connection starts over once the cooldown is over
+    pub fn restart_stalled_connections(&mut self, max_latency: Duration) {
+        let max_latency_ms = max_latency.as_secs_f32() * 1000.0;
+        for (connection_id, stats) in &mut self.connection_stats {
+            let Some(latency_ms) = stats.latency_ms().filter(|ms| *ms > max_latency_ms) else {
+                continue;
+            };
+            tracing::warn!(
+                "Sending to connection {connection_id} takes {latency_ms:.0}ms, pausing it for {}ms and restarting it",
+                self.circuit_breaker_config.cooldown_ms
+            );
+            self.connection_breakers
+                .entry(*connection_id)
+                .or_insert_with(|| CircuitBreaker::new(self.circuit_breaker_config))
+                .trip();
+            stats.reset_latency();
+        }
+    }
+
+    /// Sends the last frame of every ledstrip again, dimmed to `level` between 0 and 1, to fade
+    /// them out before the engine stops. The ledstrips without a frame are turned off
+    pub fn send_faded_frames(&mut self, level: f32) {
+        for frame in self.outgoing_frames.clone() {
+            let data = match self.last_frames.get(&frame.ledstrip_id) {
+                Some(last_frame) => last_frame
+                    .data
+                    .iter()
+                    .map(|value| (*value as f32 * level).round() as u8)
+                    .collect(),
+                None => vec![0; frame.led_count(&self.led_strips) * 3],
+            };
+            let connection_id = self.route(frame.ledstrip_id, frame.connection_id);
+            self.send_frame(connection_id, &data);
+        }
+    }
+
+    /// Sends the last frame of every ledstrip again, telling the devices to keep it after the
+    /// engine stops
+    pub fn hold_last_frames(&mut self) {
+        self.encoders
+            .values_mut()
+            .for_each(|encoder| encoder.hold());
+        self.send_faded_frames(1.0);
+    }
+
+    /// Tells the devices to go back to their own effects. The ones whose protocol can't are
+    /// turned off instead
+    pub fn release_connections(&mut self) {
+        let mut released = HashSet::new();
+        for (connection_id, encoder) in &mut self.encoders {
+            let packets = encoder.release();
+            let Some(connection) = self.connections.get(*connection_id) else {
+                continue;
+            };
+            if packets.is_empty() {
+                continue;
+            }
+            match connection.send(packets) {
+                Ok(_) => {
+                    released.insert(*connection_id);
+                }
+                Err(e) => tracing::warn!("Couldn't release connection {connection_id}: {e}"),
+            }
+        }
+
+        for frame in self.outgoing_frames.clone() {
+            let connection_id = self.route(frame.ledstrip_id, frame.connection_id);
+            if released.contains(&connection_id) {
+                continue;
+            }
+            tracing::debug!("Connection {connection_id} can't be released, turning it off");
+            let data = vec![0; frame.led_count(&self.led_strips) * 3];
+            self.send_frame(connection_id, &data);
+        }
+    }
+
+    // Hands the frame to the thread of the connection, after accounting for the frames it sent
+    // since the last one
+    fn send_frame(&mut self, connection_id: usize, data: &[u8]) {
+        let Some(connection) = self.connections.get(connection_id) else {
+            return;
+        };
+
+        let stats = self.connection_stats.entry(connection_id).or_default();
+        let breaker = self
+            .connection_breakers
+            .entry(connection_id)
+            .or_insert_with(|| CircuitBreaker::new(self.circuit_breaker_config));
+        for report in connection.reports() {
+            on_send_report(
+                connection_id,
+                report,
+                stats,
+                breaker,
+                &self.circuit_breaker_config,
+            );
+        }
+        if !breaker.allows_attempt() {
+            stats.on_dropped();
+            return;
+        }
+
+        let packets = match self.encoders.get_mut(&connection_id) {
+            Some(encoder) => {
+                encoder.update_stats(stats.frame_stats(self.engine_load));
+                encoder.encode(data)
+            }
+            None => Ok(vec![data.to_vec()]),
+        };
+        if breaker.is_half_open() {
+            connection.reconnect();
+        }
+        // A frame that can't be encoded counts as a failed send
+        let result = packets
+            .map_err(ConnectionError::from)
+            .and_then(|packets| connection.send(packets));
+        match result {
+            Ok(true) => stats.on_dropped(),
+            Ok(false) => (),
+            Err(error) => {
+                let report = SendReport {
+                    result: Err(error),
+                    latency: Duration::ZERO,
+                    bytes: 0,
+                    skipped: 0,
+                };
+                on_send_report(
+                    connection_id,
+                    report,
+                    stats,
+                    breaker,
+                    &self.circuit_breaker_config,
+                );
+            }
+        }
+    }
+}
+
+fn on_send_report(
+    connection_id: usize,
+    report: SendReport,
+    stats: &mut ConnectionStats,
+    breaker: &mut CircuitBreaker,
+    config: &CircuitBreakerConfig,
+) {
+    for _ in 0..report.skipped {
+        stats.on_dropped();
+    }
+    match report.result {
+        Ok(()) => {
+            stats.on_sent(report.latency, report.bytes);
+            if breaker.on_success() {
+                tracing::info!("Connection {connection_id} recovered");
+            }
+        }
+        Err(error) => {
+            stats.on_failed(error.to_string());
+            if breaker.on_failure() {
+                tracing::warn!(
+                    "Connection {connection_id} keeps failing ({error}). Pausing sends for {}ms. Tripped {} time(s) so far.",
+                    config.cooldown_ms,
+                    breaker.trip_count()
+                );
+            } else {
+                tracing::debug!("Failed to send to connection {connection_id}: {error}");
+            }
+        }
     }
 }
@@ -0,0 +1,344 @@
+use crate::{
+    control::{
+        socket::{SocketRequest, SocketResponse},
+        DEFAULT_SOCKET_PATH,
+    },
+    overrides::{SegmentOverride, DEFAULT_OVERRIDE_PRIORITY},
+    repl,
+    test_pattern::TestPattern,
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CtlError {
+    #[error("Couldn't connect to {0}: {1}. Is the control_socket enabled in the settings?")]
+    Connect(PathBuf, std::io::Error),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Json error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("The engine closed the connection")]
+    Disconnected,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CtlArgs {
+    /// Control socket of the running instance
+    #[arg(long, default_value = DEFAULT_SOCKET_PATH)]
+    pub socket: PathBuf,
+
+    #[command(subcommand)]
+    pub command: CtlCommand,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum CtlCommand {
+    /// Interactive lua console in the environment of an effect
+    Lua {
+        effect_id: usize,
+
+        /// Allow the console to change the globals of the effect
+        #[arg(long)]
+        write: bool,
+    },
+    /// Lists the audio devices that can be listened to
+    AudioDevices,
+    /// Listens to another audio device until the engine restarts
+    SetAudioDevice {
+        /// Name of the device, as listed by audio-devices. The default input if missing
+        name: Option<String>,
+    },
+    /// Measures the noise floor of the audio input, which is then subtracted from the fft. The
+    /// room should be as quiet as it is when nothing plays
+    CalibrateNoise {
+        /// Duration of the measure
+        #[arg(long, default_value_t = 5.0)]
+        seconds: f32,
+    },
+    /// Writes the next seconds of the audio input to a wav file, to check that the engine hears
+    /// the stream it should when the lights don't react
+    CaptureAudio {
+        /// Wav file to write
+        path: PathBuf,
+
+        /// Duration of the capture
+        #[arg(long, default_value_t = 10.0)]
+        seconds: f32,
+    },
+    /// Writes the next seconds of frames of the ledstrips to a file, as an animated gif to share
+    /// a preview of the effects, a png with one row per frame or json
+    CaptureFrames {
+        /// File to write, its extension picks the format: .gif, .png or .json
+        path: PathBuf,
+
+        /// Duration of the capture
+        #[arg(long, default_value_t = 5.0)]
+        seconds: f32,
+
+        /// Ledstrip to capture, can be repeated. Every ledstrip by default
+        #[arg(long = "ledstrip")]
+        ledstrips: Vec<usize>,
+    },
+    /// Measures how long after a sound is heard the lights react by playing clicks on the default
+    /// output, which the microphone has to pick up. The beat is then predicted that far ahead
+    CalibrateLatency {
+        /// Duration of the measure
+        #[arg(long, default_value_t = 10.0)]
+        seconds: f32,
+    },
+    /// Shows a test pattern on a connection instead of its ledstrips
+    TestPattern {
+        connection_id: usize,
+
+        /// Pattern to show. Stops the test pattern if missing
+        #[arg(value_enum)]
+        pattern: Option<TestPattern>,
+    },
+    /// Switches to a profile of the config until the engine stops
+    Profile { name: String },
+    /// Renders an effect on the segments of a lower priority, like a flash for a notification,
+    /// in place of the previous override of the channel
+    Override {
+        /// Name of the channel, which the override is released through
+        channel: String,
+
+        effect_id: usize,
+
+        /// Ledstrip to override, every ledstrip if missing
+        #[arg(long)]
+        ledstrip: Option<usize>,
+
+        /// Segment of the ledstrips to override, every segment if missing
+        #[arg(long)]
+        segment: Option<usize>,
+
+        /// Only the segments of a lower priority are overridden
+        #[arg(long, default_value_t = DEFAULT_OVERRIDE_PRIORITY)]
+        priority: u8,
+
+        /// Releases the override after that many ms. It lasts until released if missing
+        #[arg(long)]
+        duration_ms: Option<u64>,
+    },
+    /// Gives the segments overridden on a channel back to their effect
+    Release { channel: String },
+    /// Sends black frames whatever the effects and the brightness
+    Blackout {
+        /// Only blacks out this ledstrip
+        #[arg(long)]
+        ledstrip: Option<usize>,
+
+        /// Ends the blackout
+        #[arg(long)]
+        off: bool,
+    },
+    /// Keeps sending the current frame without rendering the effects
+    Freeze {
+        /// Only freezes this ledstrip
+        #[arg(long)]
+        ledstrip: Option<usize>,
+
+        /// Renders the effects again
+        #[arg(long)]
+        off: bool,
+    },
+    /// Blacks out every ledstrip but one
+    Solo {
+        /// Ledstrip to keep. Ends the solo if missing
+        ledstrip_id: Option<usize>,
+    },
+}
+
+pub fn run(args: &CtlArgs) -> Result<(), CtlError> {
+    let stream =
+        UnixStream::connect(&args.socket).map_err(|e| CtlError::Connect(args.socket.clone(), e))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    match args.command {
+        CtlCommand::Lua { effect_id, write } => repl::console(effect_id, write, |code| {
+            let request = SocketRequest::Lua {
+                effect_id,
+                code: code.to_owned(),
+                write,
+            };
+            send(&mut writer, &mut reader, &request)
+        }),
+        CtlCommand::AudioDevices => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::ListAudioDevices,
+            )?);
+            Ok(())
+        }
+        CtlCommand::SetAudioDevice { ref name } => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::SetAudioDevice { name: name.clone() },
+            )?);
+            Ok(())
+        }
+        CtlCommand::CalibrateNoise { seconds } => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::CalibrateNoiseFloor { seconds },
+            )?);
+            Ok(())
+        }
+        CtlCommand::CaptureAudio { ref path, seconds } => {
+            // The engine may run from another folder
+            let path = std::path::absolute(path)?;
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::CaptureAudio { path, seconds },
+            )?);
+            Ok(())
+        }
+        CtlCommand::CaptureFrames {
+            ref path,
+            seconds,
+            ref ledstrips,
+        } => {
+            let path = std::path::absolute(path)?;
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::CaptureFrames {
+                    path,
+                    seconds,
+                    ledstrips: ledstrips.clone(),
+                },
+            )?);
+            Ok(())
+        }
+        CtlCommand::CalibrateLatency { seconds } => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::CalibrateLatency { seconds },
+            )?);
+            Ok(())
+        }
+        CtlCommand::TestPattern {
+            connection_id,
+            pattern,
+        } => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::SetTestPattern {
+                    connection_id,
+                    pattern,
+                },
+            )?);
+            Ok(())
+        }
+        CtlCommand::Profile { ref name } => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::SetProfile { name: name.clone() },
+            )?);
+            Ok(())
+        }
+        CtlCommand::Override {
+            ref channel,
+            effect_id,
+            ledstrip,
+            segment,
+            priority,
+            duration_ms,
+        } => {
+            let segment_override = SegmentOverride {
+                effect_id,
+                ledstrip_id: ledstrip,
+                segment,
+                priority,
+                duration_ms,
+            };
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::SetOverride {
+                    channel: channel.clone(),
+                    segment_override,
+                },
+            )?);
+            Ok(())
+        }
+        CtlCommand::Release { ref channel } => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::ReleaseOverride {
+                    channel: channel.clone(),
+                },
+            )?);
+            Ok(())
+        }
+        CtlCommand::Blackout { ledstrip, off } => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::SetBlackout {
+                    ledstrip_id: ledstrip,
+                    on: !off,
+                },
+            )?);
+            Ok(())
+        }
+        CtlCommand::Freeze { ledstrip, off } => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::FreezeFrame {
+                    ledstrip_id: ledstrip,
+                    frozen: !off,
+                },
+            )?);
+            Ok(())
+        }
+        CtlCommand::Solo { ledstrip_id } => {
+            print_response(send(
+                &mut writer,
+                &mut reader,
+                &SocketRequest::SetSolo { ledstrip_id },
+            )?);
+            Ok(())
+        }
+    }
+}
+
+fn print_response(response: SocketResponse) {
+    match response {
+        Ok(result) => println!("{result}"),
+        Err(error) => eprintln!("error: {error}"),
+    }
+}
+
+fn send(
+    writer: &mut UnixStream,
+    reader: &mut BufReader<UnixStream>,
+    request: &SocketRequest,
+) -> Result<SocketResponse, CtlError> {
+    serde_json::to_writer(&mut *writer, request)?;
+    writer.write_all(b"\n")?;
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(CtlError::Disconnected);
+    }
+    Ok(serde_json::from_str(&line)?)
+}
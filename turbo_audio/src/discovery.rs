@@ -0,0 +1,303 @@
+use crate::{
+    config_parser::{DeviceConfig, LedstripConfig, LedstripEffectConfig},
+    connections::encoder::FrameEncoding,
+    mdns::{self, MdnsError},
+    overrides::DEFAULT_PRIORITY,
+    post_processing,
+};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+use thiserror::Error;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum DiscoveryError {
+    #[error("Http error: {0}")]
+    Http(#[from] Box<ureq::Error>),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Json error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Couldn't resolve the controller: {0}")]
+    Resolve(#[from] MdnsError),
+
+    #[error("The device doesn't report any led")]
+    NoLeds,
+}
+
+/// Controllers whose led layout can be queried
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum DeviceKind {
+    /// WLED, through its json API. Its segments become segments of the ledstrip
+    Wled,
+    /// Hyperion, through its json-rpc server. It has no segments so the ledstrip has a single one
+    Hyperion,
+}
+
+impl DeviceKind {
+    fn default_api_port(self) -> u16 {
+        match self {
+            DeviceKind::Wled => 80,
+            DeviceKind::Hyperion => 19444,
+        }
+    }
+
+    fn default_stream_port(self) -> u16 {
+        match self {
+            // DDP
+            DeviceKind::Wled => 4048,
+            DeviceKind::Hyperion => 42069,
+        }
+    }
+}
+
+/// Queries the led layout of a controller and prints the matching devices and ledstrips config
+#[derive(clap::Args, Debug, Clone)]
+pub struct DiscoverArgs {
+    #[arg(value_enum)]
+    pub kind: DeviceKind,
+
+    /// Hostname, ip or mDNS name of the controller
+    pub host: String,
+
+    /// Port of the controller's API. Defaults to the usual port of its kind
+    #[arg(long)]
+    pub api_port: Option<u16>,
+
+    /// Port the generated connection streams the colors to. Defaults to the usual port of its kind
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Id given to the generated device
+    #[arg(long, default_value_t = 1)]
+    pub device_id: usize,
+
+    /// Id given to the generated ledstrip
+    #[arg(long, default_value_t = 1)]
+    pub ledstrip_id: usize,
+
+    /// Effect rendered on every generated segment until the config is edited
+    #[arg(long, default_value_t = 1)]
+    pub effect_id: usize,
+}
+
+/// Contiguous range of leds the controller groups together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscoveredSegment {
+    pub start: usize,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredLayout {
+    pub led_count: usize,
+    pub segments: Vec<DiscoveredSegment>,
+}
+
+#[derive(Deserialize)]
+struct WledSegment {
+    start: usize,
+    stop: usize,
+}
+
+#[derive(Deserialize)]
+struct WledState {
+    #[serde(default)]
+    seg: Vec<WledSegment>,
+}
+
+#[derive(Deserialize)]
+struct WledLeds {
+    count: usize,
+}
+
+#[derive(Deserialize)]
+struct WledInfo {
+    leds: WledLeds,
+}
+
+#[derive(Deserialize)]
+struct WledResponse {
+    state: WledState,
+    info: WledInfo,
+}
+
+#[derive(Deserialize)]
+struct HyperionInfo {
+    #[serde(default)]
+    leds: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct HyperionResponse {
+    info: HyperionInfo,
+}
+
+pub fn fetch_layout(
+    kind: DeviceKind,
+    api_address: SocketAddr,
+) -> Result<DiscoveredLayout, DiscoveryError> {
+    let layout = match kind {
+        DeviceKind::Wled => fetch_wled_layout(api_address)?,
+        DeviceKind::Hyperion => fetch_hyperion_layout(api_address)?,
+    };
+    if layout.led_count == 0 {
+        return Err(DiscoveryError::NoLeds);
+    }
+    Ok(layout)
+}
+
+fn fetch_wled_layout(api_address: SocketAddr) -> Result<DiscoveredLayout, DiscoveryError> {
+    let response: WledResponse = ureq::get(&format!("http://{api_address}/json"))
+        .timeout(REQUEST_TIMEOUT)
+        .call()
+        .map_err(Box::new)?
+        .into_json()?;
+
+    let mut segments: Vec<DiscoveredSegment> = response
+        .state
+        .seg
+        .iter()
+        .filter(|segment| segment.stop > segment.start)
+        .map(|segment| DiscoveredSegment {
+            start: segment.start,
+            len: segment.stop - segment.start,
+        })
+        .collect();
+    segments.sort_by_key(|segment| segment.start);
+    Ok(DiscoveredLayout {
+        led_count: response.info.leds.count,
+        segments,
+    })
+}
+
+fn fetch_hyperion_layout(api_address: SocketAddr) -> Result<DiscoveredLayout, DiscoveryError> {
+    let mut stream = TcpStream::connect_timeout(&api_address, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.write_all(b"{\"command\":\"serverinfo\",\"tan\":1}\n")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: HyperionResponse = serde_json::from_str(&line)?;
+    let led_count = response.info.leds.len();
+    Ok(DiscoveredLayout {
+        led_count,
+        segments: vec![DiscoveredSegment {
+            start: 0,
+            len: led_count,
+        }],
+    })
+}
+
+/// Turns the segments of the controller into the segments of a ledstrip. Ledstrip segments are
+/// contiguous so leds that aren't in any segment are given to the segment before them.
+pub fn ledstrip_effects(layout: &DiscoveredLayout, effect_id: usize) -> Vec<LedstripEffectConfig> {
+    let segments: Vec<&DiscoveredSegment> = layout
+        .segments
+        .iter()
+        .filter(|segment| segment.start < layout.led_count)
+        .collect();
+    if segments.is_empty() {
+        return vec![LedstripEffectConfig {
+            effect_id,
+            effect_size: layout.led_count,
+            smoothing: Default::default(),
+            undersized: Default::default(),
+            blur_radius: 0.0,
+            reversed: false,
+            mirrored: false,
+            skipped: Vec::new(),
+            priority: DEFAULT_PRIORITY,
+        }];
+    }
+
+    segments
+        .iter()
+        .enumerate()
+        .map(|(index, segment)| {
+            let start = if index == 0 { 0 } else { segment.start };
+            let end = segments
+                .get(index + 1)
+                .map_or(layout.led_count, |next| next.start);
+            if end > segment.start + segment.len {
+                tracing::warn!(
+                    "Leds {} to {} aren't in any segment, they are added to the segment starting at {}",
+                    segment.start + segment.len,
+                    end - 1,
+                    segment.start
+                );
+            }
+            LedstripEffectConfig {
+                effect_id,
+                effect_size: end - start,
+                smoothing: Default::default(),
+                undersized: Default::default(),
+                blur_radius: 0.0,
+            reversed: false,
+            mirrored: false,
+            skipped: Vec::new(),
+            priority: DEFAULT_PRIORITY,
+            }
+        })
+        .collect()
+}
+
+/// Prints the devices and ledstrips config matching the layout of the controller, to be merged
+/// into the settings file
+pub fn run(args: &DiscoverArgs) -> Result<(), DiscoveryError> {
+    let api_port = args.api_port.unwrap_or(args.kind.default_api_port());
+    let api_address = mdns::resolve(&format!("{}:{api_port}", args.host))?;
+    tracing::info!("Querying the layout of {api_address}");
+    let layout = fetch_layout(args.kind, api_address)?;
+    tracing::info!(
+        "Found {} leds in {} segment(s)",
+        layout.led_count,
+        layout.segments.len()
+    );
+
+    // The host is kept as given so the config still finds the controller if its ip changes
+    let port = args.port.unwrap_or(args.kind.default_stream_port());
+    let address = format!("{}:{port}", args.host);
+    let (kind, encoding) = match args.kind {
+        DeviceKind::Wled => ("Udp", FrameEncoding::Ddp { destination_id: 1 }),
+        DeviceKind::Hyperion => ("Tcp", FrameEncoding::RawRgb),
+    };
+    let config = serde_json::json!({
+        "devices": [DeviceConfig {
+            kind: kind.to_owned(),
+            connection: serde_json::json!(address),
+            id: args.device_id,
+            encoding,
+            keep_alive: None,
+            dithering: false,
+            delay_ms: 0,
+            compensate_latency: false,
+            color_lut: None,
+        }],
+        "ledstrips": [LedstripConfig {
+            id: args.ledstrip_id,
+            connection_id: args.device_id,
+            fallback_connection_id: None,
+            offset: None,
+            size: layout.led_count,
+            effects: ledstrip_effects(&layout, args.effect_id),
+            post_processing: post_processing::default_stages(),
+            calibration: Default::default(),
+            transform: Vec::new(),
+            group_size: 1,
+            startup_scene: None,
+            failure_scene: None,
+        }],
+    });
+    println!("{}", serde_json::to_string_pretty(&config)?);
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use crate::{
+    audio::audio_stream::start_audio_loop,
+    check_config::find_problems,
+    config_parser::{EffectConfigType, StreamConnections, TurboAudioConfig},
+    connections::{ConnectionFactory, LinkStatus},
+};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+// Time the connections and the pipewire links get to come up before they're reported as failed
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Error, Debug)]
+pub enum DryRunError {
+    #[error("{0} check(s) failed")]
+    Failed(usize),
+}
+
+/// Goes through the startup of the engine without rendering anything, and prints every check on
+/// the way along with what would run, for deployment pipelines
+#[derive(Debug, Default)]
+pub struct DryRun {
+    failures: usize,
+}
+
+impl DryRun {
+    pub fn section(&self, title: &str) {
+        println!("{title}");
+    }
+
+    pub fn pass(&self, check: impl AsRef<str>) {
+        println!("  ok    {}", check.as_ref());
+    }
+
+    pub fn fail(&mut self, check: impl AsRef<str>) {
+        println!("  FAIL  {}", check.as_ref());
+        self.failures += 1;
+    }
+
+    /// Checks that the resources of the config refer to each other, like `check-config` does
+    pub fn check_config(&mut self, settings_file: &str, config: &TurboAudioConfig) {
+        self.section("Config");
+        let problems = find_problems(config);
+        if problems.is_empty() {
+            self.pass(format!("{settings_file} is valid"));
+        }
+        for problem in problems {
+            self.fail(problem);
+        }
+    }
+
+    /// Prints the ledstrips, the effects of their segments and the audio sources they follow
+    pub fn print_plan(&self, config: &TurboAudioConfig) {
+        self.section("Effects");
+        for effect in &config.effects {
+            let audio_source = match config.effect_audio_source(effect) {
+                Ok(Some(source)) => format!(", following the audio source {source}"),
+                _ => String::new(),
+            };
+            let (kind, name) = match &effect.effect {
+                EffectConfigType::Lua(file_name) => ("Lua", file_name),
+                EffectConfigType::Native(file_name) => ("Native", file_name),
+                EffectConfigType::Rhai(file_name) => ("Rhai", file_name),
+                EffectConfigType::Type(effect_type) => ("Type", effect_type),
+            };
+            println!(
+                "        {} {kind} {name} with the settings {}{audio_source}",
+                effect.effect_id, effect.settings_id
+            );
+        }
+        self.section("Ledstrips");
+        for ledstrip in &config.ledstrips {
+            println!(
+                "        {} {} leds on connection {}",
+                ledstrip.id, ledstrip.size, ledstrip.connection_id
+            );
+            for (segment, effect) in ledstrip.effects.iter().enumerate() {
+                println!(
+                    "          segment {segment}: effect {} on {} leds",
+                    effect.effect_id, effect.effect_size
+                );
+            }
+        }
+        if !config.profiles.is_empty() {
+            let names: Vec<&str> = config
+                .profiles
+                .iter()
+                .map(|profile| profile.name.as_str())
+                .collect();
+            self.section(&format!("Profiles: {}", names.join(", ")));
+        }
+    }
+
+    /// Opens the audio devices of the main device and of the audio sources, and links their
+    /// pipewire streams
+    pub fn check_audio(&mut self, config: &TurboAudioConfig) {
+        self.section("Audio");
+        let sources: Vec<_> = std::iter::once(config.main_audio_source())
+            .chain(config.audio_sources.iter().cloned())
+            .collect();
+        // Kept open while the stream connections are linked to them
+        let mut streams = Vec::new();
+        for source in &sources {
+            let name = match source.name.is_empty() {
+                true => "The main device".to_owned(),
+                false => format!("The audio source {}", source.name),
+            };
+            let device = source
+                .device_name
+                .as_deref()
+                .unwrap_or("the default device");
+            match start_audio_loop(
+                source.device_name.clone(),
+                config.sample_rate,
+                source.audio_capture,
+                config.audio_backend,
+            ) {
+                Ok(stream) => {
+                    self.pass(format!("{name} listens to {device}"));
+                    streams.push(stream);
+                }
+                Err(e) => self.fail(format!("{name} can't listen to {device}: {e:#}")),
+            }
+        }
+
+        let stream_connections: Vec<StreamConnections> = sources
+            .into_iter()
+            .flat_map(|source| source.stream_connections)
+            .collect();
+        if !stream_connections.is_empty() {
+            self.check_stream_connections(stream_connections);
+        }
+    }
+
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    fn check_stream_connections(&mut self, stream_connections: Vec<StreamConnections>) {
+        use crate::{
+            audio::pipewire_listener::{stream_connection_info, PipewireController},
+            info::{StreamConnectionInfo, StreamLinkState},
+        };
+
+        let pipewire_controller = PipewireController::new();
+        if let Err(e) = pipewire_controller.set_stream_connections(stream_connections) {
+            self.fail(format!("Couldn't set the stream connections: {e:#}"));
+            return;
+        }
+        let linked = |status: &[StreamConnectionInfo]| {
+            !status.is_empty()
+                && status.iter().all(|connection| {
+                    !connection.links.is_empty()
+                        && connection
+                            .links
+                            .iter()
+                            .all(|link| link.state == StreamLinkState::Linked)
+                })
+        };
+        let start = Instant::now();
+        let mut status = stream_connection_info();
+        while !linked(&status) && start.elapsed() < SETTLE_TIMEOUT {
+            std::thread::sleep(POLL_INTERVAL);
+            status = stream_connection_info();
+        }
+
+        if status.is_empty() {
+            self.fail("Pipewire didn't report the stream connections, is it running?");
+        }
+        for connection in &status {
+            let name = format!(
+                "{} -> {}",
+                connection.output_stream, connection.input_stream
+            );
+            if !connection.missing_streams.is_empty() {
+                self.fail(format!(
+                    "{name}: pipewire doesn't have {}",
+                    connection.missing_streams.join(", ")
+                ));
+            } else if connection.links.is_empty() {
+                self.fail(format!("{name}: the ports to link aren't known"));
+            }
+            for link in &connection.links {
+                let name = format!("{name}, {} -> {}", link.output_port, link.input_port);
+                match &link.state {
+                    StreamLinkState::Linked => self.pass(format!("{name} is linked")),
+                    StreamLinkState::Pending => self.fail(format!(
+                        "{name} still isn't linked after {}s",
+                        SETTLE_TIMEOUT.as_secs()
+                    )),
+                    StreamLinkState::MissingPort => self.fail(format!("{name}: a port is missing")),
+                    StreamLinkState::Failed(e) => self.fail(format!("{name}: {e}")),
+                }
+            }
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "pipewire")))]
+    fn check_stream_connections(&mut self, _stream_connections: Vec<StreamConnections>) {
+        self.fail("The stream connections need pipewire, on linux");
+    }
+
+    /// Opens every connection and waits for them to connect to their device. No frame is sent
+    pub fn check_connections(&mut self, config: &TurboAudioConfig) {
+        self.section("Connections");
+        let factory = ConnectionFactory::default();
+        let mut opened = Vec::new();
+        for device in &config.devices {
+            let name = format!(
+                "Connection {} ({} {})",
+                device.id, device.kind, device.connection
+            );
+            match factory.create(&device.kind, &device.connection) {
+                Ok(connection) => opened.push((name, connection)),
+                Err(e) => self.fail(format!("{name} can't be opened: {e}")),
+            }
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < SETTLE_TIMEOUT
+            && opened
+                .iter()
+                .any(|(_, connection)| connection.status() == LinkStatus::Connecting)
+        {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        for (name, connection) in &opened {
+            match connection.status() {
+                LinkStatus::Connected => self.pass(format!("{name} is connected")),
+                LinkStatus::Connecting => self.fail(format!(
+                    "{name} is still connecting after {}s",
+                    SETTLE_TIMEOUT.as_secs()
+                )),
+                LinkStatus::Disconnected => self.fail(format!("{name} is disconnected")),
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), DryRunError> {
+        if self.failures > 0 {
+            return Err(DryRunError::Failed(self.failures));
+        }
+        println!("Every check passed");
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use turbo_plugin::Color;
+
+/// Longest capture of the frames, which are kept in memory until it's complete
+pub const MAX_CAPTURE_SECONDS: f32 = 60.0;
+
+// Side of the square drawn for every led in the gifs, so that the leds can be told apart
+const GIF_LED_SIZE: usize = 8;
+
+#[derive(Error, Debug)]
+pub enum FrameCaptureError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Json error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Couldn't encode the png: {0}")]
+    Png(#[from] png::EncodingError),
+
+    #[error("Couldn't encode the gif: {0}")]
+    Gif(#[from] gif::EncodingError),
+
+    #[error("The ledstrips are too long for a gif")]
+    TooLarge,
+}
+
+/// Format of the file the frames are exported to, picked from its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameCaptureFormat {
+    /// Animation with a row of squares per ledstrip, to share a preview
+    Gif,
+    /// Image with one row per tick and the ledstrips side by side, like `render` writes them
+    Png,
+    /// Colors of every frame of every ledstrip
+    Json,
+}
+
+impl FrameCaptureFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gif" => Some(Self::Gif),
+            "png" => Some(Self::Png),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonCapture<'a> {
+    fps: u32,
+    ledstrips: &'a BTreeMap<usize, Vec<Vec<Color>>>,
+}
+
+/// Frames of some ledstrips recorded as they're rendered, for the previews of the effects and
+/// the bug reports. Written to the file once every frame is recorded
+#[derive(Debug)]
+pub struct FrameCapture {
+    file: File,
+    path: PathBuf,
+    format: FrameCaptureFormat,
+    fps: u32,
+    // Ticks left to record before the capture is complete
+    remaining: usize,
+    frames: BTreeMap<usize, Vec<Vec<Color>>>,
+}
+
+impl FrameCapture {
+    /// Creates the file, to which the next `seconds` of frames of the ledstrips are written. Fails
+    /// if it exists
+    pub fn create(
+        path: &Path,
+        format: FrameCaptureFormat,
+        seconds: f32,
+        ledstrips: impl IntoIterator<Item = usize>,
+    ) -> io::Result<Self> {
+        let fps = crate::ticks_per_second();
+        Ok(Self {
+            file: File::create_new(path)?,
+            path: path.to_owned(),
+            format,
+            fps,
+            remaining: ((seconds * fps as f32).ceil() as usize).max(1),
+            frames: ledstrips
+                .into_iter()
+                .map(|ledstrip_id| (ledstrip_id, Vec::new()))
+                .collect(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records the colors of a tick of every captured ledstrip that still exists, and returns
+    /// whether the capture is complete
+    pub fn record(&mut self, colors: impl Fn(usize) -> Option<Vec<Color>>) -> bool {
+        for (ledstrip_id, frames) in &mut self.frames {
+            if let Some(colors) = colors(*ledstrip_id) {
+                frames.push(colors);
+            }
+        }
+        self.remaining = self.remaining.saturating_sub(1);
+        self.remaining == 0
+    }
+
+    pub fn write(self) -> Result<(), FrameCaptureError> {
+        let writer = BufWriter::new(self.file);
+        match self.format {
+            FrameCaptureFormat::Json => serde_json::to_writer(
+                writer,
+                &JsonCapture {
+                    fps: self.fps,
+                    ledstrips: &self.frames,
+                },
+            )?,
+            FrameCaptureFormat::Png => write_png(writer, &self.frames)?,
+            FrameCaptureFormat::Gif => write_gif(writer, self.fps, &self.frames)?,
+        }
+        Ok(())
+    }
+}
+
+// Leds of the longest frame of the ledstrip, which the shorter ones are padded to
+fn led_count(frames: &[Vec<Color>]) -> usize {
+    frames.iter().map(Vec::len).max().unwrap_or(0)
+}
+
+fn tick_count(frames: &BTreeMap<usize, Vec<Vec<Color>>>) -> usize {
+    frames.values().map(Vec::len).max().unwrap_or(0)
+}
+
+fn write_png(
+    writer: BufWriter<File>,
+    frames: &BTreeMap<usize, Vec<Vec<Color>>>,
+) -> Result<(), FrameCaptureError> {
+    let widths: Vec<usize> = frames.values().map(|frames| led_count(frames)).collect();
+    let width: usize = widths.iter().sum();
+    let ticks = tick_count(frames);
+    let mut data = Vec::with_capacity(width * ticks * 3);
+    for tick in 0..ticks {
+        for (frames, width) in frames.values().zip(&widths) {
+            let frame = frames.get(tick).map(Vec::as_slice).unwrap_or_default();
+            data.extend_from_slice(bytemuck::cast_slice(frame));
+            data.resize(data.len() + (width - frame.len()) * 3, 0);
+        }
+    }
+
+    let mut encoder = png::Encoder::new(writer, width as u32, ticks as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+fn write_gif(
+    writer: BufWriter<File>,
+    fps: u32,
+    frames: &BTreeMap<usize, Vec<Vec<Color>>>,
+) -> Result<(), FrameCaptureError> {
+    let leds = frames
+        .values()
+        .map(|frames| led_count(frames))
+        .max()
+        .unwrap_or(0);
+    let (width, height) = (leds * GIF_LED_SIZE, frames.len() * GIF_LED_SIZE);
+    let (Ok(gif_width), Ok(gif_height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(FrameCaptureError::TooLarge);
+    };
+    let mut encoder = gif::Encoder::new(writer, gif_width, gif_height, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    // The delays are in hundredths of a second, the players round the rates that don't fit
+    let delay = (100.0 / fps as f32).round().max(1.0) as u16;
+
+    let mut pixels = vec![0u8; width * height * 3];
+    for tick in 0..tick_count(frames) {
+        pixels.fill(0);
+        for (row, frames) in frames.values().enumerate() {
+            let frame = frames.get(tick).map(Vec::as_slice).unwrap_or_default();
+            for (led, color) in frame.iter().enumerate() {
+                for y in row * GIF_LED_SIZE..(row + 1) * GIF_LED_SIZE {
+                    let start = (y * width + led * GIF_LED_SIZE) * 3;
+                    for pixel in pixels[start..start + GIF_LED_SIZE * 3].chunks_exact_mut(3) {
+                        pixel.copy_from_slice(&[color.r, color.g, color.b]);
+                    }
+                }
+            }
+        }
+        let mut frame = gif::Frame::from_rgb_speed(gif_width, gif_height, &pixels, 10);
+        frame.delay = delay;
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}
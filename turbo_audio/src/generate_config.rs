@@ -0,0 +1,361 @@
+use crate::{
+    audio::{
+        audio_processing::FftConfig,
+        audio_stream::{AudioBackend, AudioCapture, MissingAudioBehavior},
+        noise_gate::NoiseGateConfig,
+        smoothing::SmoothingProfile,
+        weighting::Weighting,
+    },
+    config_parser::{
+        ConfigFormat, DerivedFeatureConfig, DeviceConfig, EffectConfig, EffectConfigType,
+        EffectSettingConfig, LedstripConfig, LedstripEffectConfig, ProfileConfig,
+        ProfileLedstripConfig, SettingsConfigType, TurboAudioConfig, CONFIG_VERSION,
+    },
+    connections::{
+        encoder::FrameEncoding,
+        keep_alive::{KeepAliveColor, KeepAliveConfig},
+    },
+    overrides::DEFAULT_PRIORITY,
+    parameter_mapping::{EnvelopeConfig, Expression},
+    post_processing,
+    resources::ledstrip::UndersizedPolicy,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GenerateConfigError {
+    #[error("{0} already exists, pass --force to overwrite it")]
+    Exists(String),
+
+    #[error("Couldn't write {0}: {1}")]
+    Io(String, std::io::Error),
+
+    #[error("Couldn't serialize the config: {0}")]
+    Serialize(String),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GenerateConfigArgs {
+    /// Overwrite the settings file if it already exists
+    #[arg(long)]
+    force: bool,
+}
+
+// Written above the first key of each name, in the formats that have comments
+const COMMENTS: &[(&str, &str)] = &[
+    (
+        "version",
+        "Version of the config format. Older files are migrated when they're loaded",
+    ),
+    (
+        "lua_effects_folder",
+        "Folder of the lua effects, relative to where turbo_audio runs",
+    ),
+    (
+        "audio_capture",
+        "Input to listen to an input device, Loopback to what an output plays",
+    ),
+    (
+        "audio_backend",
+        "Cpal, or Pulse when turbo_audio is built with the pulse feature",
+    ),
+    (
+        "missing_audio",
+        "Fail, or NoAudio to render on silence until the audio device shows up",
+    ),
+    (
+        "stream_connections",
+        "Pipewire links from the output of a player, like spotify, to turbo_audio",
+    ),
+    (
+        "audio_sources",
+        "Other audio devices, that effects react to with their audio_source",
+    ),
+    (
+        "audio_routing",
+        "Audio sources the effects of some ledstrips react to, the main device for the others",
+    ),
+    (
+        "fft",
+        "Samples of the windows the audio is analyzed in, and between two windows",
+    ),
+    (
+        "noise_gate",
+        "Calibrate the noise floor with `turbo_audio ctl calibrate-noise`",
+    ),
+    (
+        "weighting",
+        "A to follow the loudness as heard rather than the bass, or None",
+    ),
+    (
+        "derived_features",
+        "Signals computed from the audio, read by name by the bindings and effects",
+    ),
+    (
+        "effect_settings",
+        "Settings of the effects, checked against the schema of the effect",
+    ),
+    (
+        "effects",
+        "Lua, Rhai or Native files, or a Type of the effects folders",
+    ),
+    (
+        "bindings",
+        "Settings recomputed every frame from the audio features",
+    ),
+    (
+        "devices",
+        "Where the frames are sent. WLED listens for realtime frames on udp port 21324",
+    ),
+    ("encoding", "RawRgb, Adalight, Wled, Sacn, Ddp or Framed"),
+    (
+        "keep_alive",
+        "Frames sent while paused, so that WLED doesn't go back to its own effects",
+    ),
+    (
+        "ledstrips",
+        "Leds of a device, split in segments that each render an effect",
+    ),
+    ("smoothing", "Raw, Fast, Smooth or VerySmooth"),
+    (
+        "undersized",
+        "Resample or Warn when the segment is smaller than the effect needs",
+    ),
+    (
+        "skipped",
+        "Ranges of leds of the segment left off, like the ones behind a corner",
+    ),
+    (
+        "post_processing",
+        "Brightness, Gamma, WhiteBalance, Limiter and Dithering, in order",
+    ),
+    (
+        "night_mode",
+        "Warmer and dimmer lights during its hours, or turned on through the apis",
+    ),
+    (
+        "calibration",
+        "Channel gains and white point matching the leds to the other ledstrips",
+    ),
+    (
+        "circuit_breaker",
+        "Stops sending for a while to a device that keeps failing",
+    ),
+    (
+        "av_sync",
+        "Delays the lights to line them up with the audio",
+    ),
+    (
+        "effect_budget",
+        "Reports, or freezes with freeze, the effects that keep rendering slower than budget_ms",
+    ),
+    ("lua_sandbox", "Limits of the lua effects"),
+    (
+        "watchdog",
+        "Restarts the audio streams and the connections that stall the engine",
+    ),
+    (
+        "profiles",
+        "Effects and brightness picked with --profile or `turbo_audio ctl profile`",
+    ),
+];
+
+// A WLED strip over the network rendering a lua and a rhai effect
+fn example_config() -> TurboAudioConfig {
+    let segment = |effect_id| LedstripEffectConfig {
+        effect_id,
+        effect_size: 75,
+        smoothing: SmoothingProfile::Smooth,
+        undersized: UndersizedPolicy::default(),
+        blur_radius: 0.0,
+        reversed: false,
+        mirrored: false,
+        skipped: Vec::new(),
+        priority: DEFAULT_PRIORITY,
+    };
+    let profile = |name: &str, brightness, effect_id| ProfileConfig {
+        name: name.to_owned(),
+        brightness,
+        ledstrips: vec![ProfileLedstripConfig {
+            ledstrip_id: 1,
+            effects: vec![effect_id, effect_id],
+        }],
+    };
+    TurboAudioConfig {
+        version: CONFIG_VERSION,
+        lua_effects_folder: PathBuf::from("../effects/lua/"),
+        device_name: None,
+        audio_capture: AudioCapture::default(),
+        audio_backend: AudioBackend::default(),
+        sample_rate: 48000,
+        missing_audio: MissingAudioBehavior::NoAudio,
+        fft: FftConfig::default(),
+        noise_gate: NoiseGateConfig::default(),
+        weighting: Weighting::default(),
+        stream_connections: Vec::new(),
+        audio_sources: Vec::new(),
+        audio_routing: Vec::new(),
+        derived_features: vec![DerivedFeatureConfig {
+            name: "kick".to_owned(),
+            expression: Expression::parse("gate(band(40, 120), 0.6)").unwrap(),
+            envelope: Some(EnvelopeConfig::default()),
+        }],
+        effect_settings: vec![
+            EffectSettingConfig {
+                id: 1,
+                setting: SettingsConfigType::Lua(serde_json::json!({
+                    "enable_beep_boops": false,
+                    "intensity": 5,
+                })),
+                seed: None,
+            },
+            EffectSettingConfig {
+                id: 2,
+                setting: SettingsConfigType::Lua(serde_json::json!({ "speed": 1.0 })),
+                seed: None,
+            },
+        ],
+        effects: vec![
+            EffectConfig {
+                effect_id: 1,
+                settings_id: 1,
+                effect: EffectConfigType::Lua("sketchers.lua".to_owned()),
+                bindings: HashMap::new(),
+                audio_source: None,
+            },
+            EffectConfig {
+                effect_id: 2,
+                settings_id: 2,
+                effect: EffectConfigType::Rhai("bass_rainbow.rhai".to_owned()),
+                bindings: HashMap::from([(
+                    "speed".to_owned(),
+                    Expression::parse("1 + 4 * kick").unwrap(),
+                )]),
+                audio_source: None,
+            },
+        ],
+        render_threads: None,
+        effect_budget: Default::default(),
+        devices: vec![DeviceConfig {
+            kind: "Udp".to_owned(),
+            connection: serde_json::json!("wled.local:21324"),
+            id: 1,
+            encoding: FrameEncoding::Wled { timeout_s: 2 },
+            keep_alive: Some(KeepAliveConfig {
+                interval_ms: 1000,
+                color: KeepAliveColor::RepeatLast,
+                skip_unchanged: false,
+            }),
+            dithering: false,
+            delay_ms: 0,
+            compensate_latency: false,
+            color_lut: None,
+        }],
+        ledstrips: vec![LedstripConfig {
+            id: 1,
+            connection_id: 1,
+            fallback_connection_id: None,
+            offset: None,
+            size: 150,
+            effects: vec![segment(1), segment(2)],
+            post_processing: post_processing::default_stages(),
+            calibration: Default::default(),
+            transform: Vec::new(),
+            group_size: 1,
+            startup_scene: None,
+            failure_scene: None,
+        }],
+        profiles: vec![profile("party", 1.0, 2), profile("ambient", 0.3, 1)],
+        osc: None,
+        http: None,
+        grpc: None,
+        control_socket: None,
+        midi: None,
+        hotkeys: None,
+        mqtt: None,
+        circuit_breaker: Default::default(),
+        av_sync: Default::default(),
+        lua_sandbox: Default::default(),
+        idle: None,
+        watchdog: Default::default(),
+        schedule: Vec::new(),
+        night_mode: Default::default(),
+        screen_capture: None,
+        now_playing: None,
+        sync: None,
+        jack_transport: None,
+        ableton_link: None,
+        session: None,
+    }
+}
+
+// Headers like `[devices.encoding.Wled]` are commented for the innermost key that has a comment
+fn toml_key(line: &str) -> Option<&str> {
+    match line.strip_prefix('[') {
+        Some(header) => header
+            .trim_matches(['[', ']'])
+            .rsplit('.')
+            .find(|key| COMMENTS.iter().any(|(name, _)| name == key)),
+        None => line.split_once(" = ").map(|(key, _)| key),
+    }
+}
+
+fn yaml_key(line: &str) -> Option<&str> {
+    let line = line.strip_prefix("- ").unwrap_or(line);
+    line.split_once(':').map(|(key, _)| key)
+}
+
+fn add_comments(text: &str, key_of: fn(&str) -> Option<&str>) -> String {
+    let mut commented = HashSet::new();
+    let mut output = String::new();
+    for line in text.lines() {
+        let content = line.trim_start();
+        let comment = key_of(content)
+            .and_then(|key| COMMENTS.iter().find(|(name, _)| *name == key))
+            .filter(|(name, _)| commented.insert(*name));
+        if let Some((_, comment)) = comment {
+            let indent = &line[..line.len() - content.len()];
+            output.push_str(&format!("{indent}# {comment}\n"));
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+fn serialize(config: &TurboAudioConfig, format: ConfigFormat) -> Result<String, String> {
+    match format {
+        ConfigFormat::Json => serde_json::to_string_pretty(config).map_err(|e| e.to_string()),
+        ConfigFormat::Toml => toml::to_string(config)
+            .map(|text| add_comments(&text, toml_key))
+            .map_err(|e| e.to_string()),
+        ConfigFormat::Yaml => serde_yaml::to_string(config)
+            .map(|text| add_comments(&text, yaml_key))
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Writes an example config using most of the settings to the settings file, as a starting point
+/// for a new setup. Json has no comments, toml and yaml have a comment on every section
+pub fn run(
+    settings_file: &str,
+    format: ConfigFormat,
+    args: &GenerateConfigArgs,
+) -> Result<(), GenerateConfigError> {
+    if Path::new(settings_file).exists() && !args.force {
+        return Err(GenerateConfigError::Exists(settings_file.to_owned()));
+    }
+    let text = serialize(&example_config(), format).map_err(GenerateConfigError::Serialize)?;
+    std::fs::write(settings_file, text)
+        .map_err(|e| GenerateConfigError::Io(settings_file.to_owned(), e))?;
+
+    println!("Wrote an example config to {settings_file}");
+    if format == ConfigFormat::Json {
+        println!("Json has no comments, a .toml or .yaml settings file explains every section");
+    }
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use crate::{
+    audio::{
+        audio_processing::{AudioSignalProcessor, FftConfig},
+        recording::{FeatureRecorder, FeatureReplay, RecordingError},
+        signal_generator::{SignalGenerator, SyntheticAudio},
+    },
+    av_sync::AvSyncConfig,
+    connections::circuit_breaker::CircuitBreakerConfig,
+    controller::Controller,
+    plugins::effects::{
+        lua::{LuaEffectSettings, LuaSandboxConfig},
+        native::NativeEffectSettings,
+        EffectSettings, SettingsError,
+    },
+    resources::ledstrip::{LedStrip, SegmentError, SegmentLayout, UndersizedPolicy},
+    ticks_per_second,
+};
+use ringbuf::HeapProducer;
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use turbo_plugin::Color;
+
+#[derive(Error, Debug)]
+pub enum HeadlessError {
+    #[error("Couldn't load the effect {0}")]
+    InvalidEffect(PathBuf),
+
+    #[error("Json error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
+
+    #[error(transparent)]
+    Segment(#[from] SegmentError),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Recording(#[from] RecordingError),
+
+    #[error("Couldn't encode the png: {0}")]
+    PngEncoding(#[from] png::EncodingError),
+
+    #[error("Couldn't decode the png: {0}")]
+    PngDecoding(#[from] png::DecodingError),
+
+    #[error("Unsupported output format: {0}. Expected a .png or .json file")]
+    UnsupportedOutput(PathBuf),
+
+    #[error("The rendered frames don't match {0}")]
+    Mismatch(PathBuf),
+}
+
+/// Renders an effect without audio device or ledstrip and dumps its frames
+#[derive(clap::Args, Debug, Clone)]
+pub struct RenderArgs {
+    /// Lua (.lua), rhai (.rhai) or native effect to render
+    pub effect: PathBuf,
+
+    /// Json settings given to a lua or rhai effect, or the `seed` of a native one like
+    /// `{"seed": 7}`
+    #[arg(long, default_value_t = String::from("{}"))]
+    pub settings: String,
+
+    /// Folder the lua effects load their libraries from
+    #[arg(long, default_value = "../effects/lua/")]
+    pub lua_root: PathBuf,
+
+    #[arg(long, default_value_t = 60)]
+    pub pixels: usize,
+
+    #[arg(long, default_value_t = 120)]
+    pub ticks: usize,
+
+    /// silence, sine:<frequency>, kicks:<bpm>, noise, sweep:<seconds>, pink, metronome:<bpm> or
+    /// bursts:<low>-<high>
+    #[arg(long, default_value = "silence")]
+    pub audio: SyntheticAudio,
+
+    /// Replay audio features recorded with --record instead of the synthetic audio
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+
+    /// Record the audio features the effect was rendered with
+    #[arg(long, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 48000)]
+    pub sample_rate: u32,
+
+    /// File the frames are written to. A .png has one row per tick, a .json a list of frames
+    #[arg(long)]
+    pub output: PathBuf,
+
+    /// Compare the frames to the existing output instead of overwriting it, for golden tests
+    #[arg(long)]
+    pub check: bool,
+}
+
+/// An effect rendered alone on a ledstrip, without audio device, for the render command and
+/// the benchmarks
+pub struct EffectRenderer {
+    controller: Controller,
+    audio_processor: AudioSignalProcessor,
+    audio_tx: HeapProducer<f32>,
+    signal: SignalGenerator,
+    ledstrip_id: usize,
+    samples_per_tick: usize,
+}
+
+impl EffectRenderer {
+    /// Loads the effect with its json `settings` on a ledstrip of `pixels` leds
+    pub fn new(
+        effect: &Path,
+        settings: &str,
+        lua_root: &Path,
+        pixels: usize,
+        audio: SyntheticAudio,
+        sample_rate: u32,
+    ) -> Result<Self, HeadlessError> {
+        let fft_config = FftConfig::default();
+        let (audio_tx, audio_rx) = ringbuf::HeapRb::<f32>::new(fft_config.size).split();
+        let audio_processor = AudioSignalProcessor::new(audio_rx, sample_rate, fft_config);
+        let signal = SignalGenerator::new(audio, sample_rate);
+
+        let mut controller = Controller::new(
+            &audio_processor,
+            lua_root,
+            None,
+            CircuitBreakerConfig::default(),
+            AvSyncConfig::default(),
+            LuaSandboxConfig::default(),
+        );
+        let extension = effect.extension().and_then(|extension| extension.to_str());
+        // The controller is empty so neither the allocated ids nor the effect id can collide
+        let effect_id = controller.next_effect_id();
+        let settings: serde_json::Value = serde_json::from_str(settings)?;
+        let settings_id = if let Some(extension @ ("lua" | "rhai")) = extension {
+            let _ = if extension == "lua" {
+                controller.add_lua_effect(effect_id, effect)
+            } else {
+                controller.add_rhai_effect(effect_id, effect)
+            };
+            controller.allocate_settings(EffectSettings::Lua(LuaEffectSettings { settings }))
+        } else {
+            let _ = controller.add_native_effect(effect_id, effect);
+            controller.allocate_settings(EffectSettings::Native(NativeEffectSettings::from_json(
+                &settings,
+            )))
+        };
+        if !controller.contains_effect(effect_id) {
+            return Err(HeadlessError::InvalidEffect(effect.to_path_buf()));
+        }
+        controller.link_effect_to_settings(effect_id, settings_id);
+        controller.validate_effect_settings(effect_id)?;
+
+        let mut ledstrip = LedStrip::default();
+        ledstrip.set_led_count(pixels);
+        ledstrip.add_effect(
+            effect_id,
+            pixels,
+            Default::default(),
+            UndersizedPolicy::default(),
+            0.0,
+            SegmentLayout::default(),
+        )?;
+        let ledstrip_id = controller.allocate_led_strip(ledstrip);
+
+        Ok(Self {
+            controller,
+            audio_processor,
+            audio_tx,
+            signal,
+            ledstrip_id,
+            samples_per_tick: sample_rate as usize / ticks_per_second() as usize,
+        })
+    }
+
+    /// Feeds a tick of the synthetic audio to the fft
+    pub fn feed_audio(&mut self) {
+        for _ in 0..self.samples_per_tick {
+            // Like a live stream, samples that don't fit before the next fft are dropped
+            let _ = self.audio_tx.push(self.signal.next_sample());
+        }
+        self.audio_processor.compute_fft();
+    }
+
+    /// The fft the effect reacts to, to replay or record audio features
+    pub fn audio_processor_mut(&mut self) -> &mut AudioSignalProcessor {
+        &mut self.audio_processor
+    }
+
+    /// Renders a frame and returns the colors of the ledstrip
+    pub fn render(&mut self) -> Vec<Color> {
+        self.controller.update_led_strips();
+        self.controller
+            .led_strip_colors()
+            .find(|(id, _)| *id == self.ledstrip_id)
+            .map(|(_, colors)| colors)
+            .unwrap_or_default()
+    }
+}
+
+/// Runs the effect for the requested number of ticks and returns every frame
+pub fn render_frames(args: &RenderArgs) -> Result<Vec<Vec<Color>>, HeadlessError> {
+    let mut renderer = EffectRenderer::new(
+        &args.effect,
+        &args.settings,
+        &args.lua_root,
+        args.pixels,
+        args.audio,
+        args.sample_rate,
+    )?;
+    let mut replay = match &args.replay {
+        Some(path) => Some(FeatureReplay::open(path, ticks_per_second())?),
+        None => None,
+    };
+    let mut recorder = match &args.record {
+        Some(path) => Some(FeatureRecorder::new(path, ticks_per_second())?),
+        None => None,
+    };
+
+    let mut frames = Vec::with_capacity(args.ticks);
+    for _ in 0..args.ticks {
+        match &mut replay {
+            Some(replay) => replay.tick(renderer.audio_processor_mut()),
+            None => renderer.feed_audio(),
+        }
+        if let Some(recorder) = &mut recorder {
+            recorder.record(&renderer.audio_processor_mut().fft_result.load())?;
+        }
+        frames.push(renderer.render());
+    }
+
+    Ok(frames)
+}
+
+/// Renders the effect and writes the frames to the output, or compares them to it with `--check`
+pub fn run(args: &RenderArgs) -> Result<(), HeadlessError> {
+    let frames = render_frames(args)?;
+    let extension = args
+        .output
+        .extension()
+        .and_then(|extension| extension.to_str());
+
+    match (extension, args.check) {
+        (Some("json"), false) => {
+            serde_json::to_writer(BufWriter::new(File::create(&args.output)?), &frames)?;
+        }
+        (Some("json"), true) => {
+            let expected: Vec<Vec<Color>> = serde_json::from_reader(File::open(&args.output)?)?;
+            if expected.len() != frames.len() || to_rgb_bytes(&expected) != to_rgb_bytes(&frames) {
+                return Err(HeadlessError::Mismatch(args.output.clone()));
+            }
+        }
+        (Some("png"), false) => write_png(&args.output, args.pixels, &frames)?,
+        (Some("png"), true) => {
+            if read_png(&args.output)? != to_rgb_bytes(&frames) {
+                return Err(HeadlessError::Mismatch(args.output.clone()));
+            }
+        }
+        _ => return Err(HeadlessError::UnsupportedOutput(args.output.clone())),
+    }
+
+    if args.check {
+        tracing::info!("{} frames match {}", frames.len(), args.output.display());
+    } else {
+        tracing::info!("Wrote {} frames to {}", frames.len(), args.output.display());
+    }
+    Ok(())
+}
+
+fn to_rgb_bytes(frames: &[Vec<Color>]) -> Vec<u8> {
+    frames
+        .iter()
+        .flat_map(|frame| bytemuck::cast_slice::<Color, u8>(frame).iter().copied())
+        .collect()
+}
+
+/// Writes the frames as an image with one pixel per led and one row per tick
+fn write_png(path: &Path, pixels: usize, frames: &[Vec<Color>]) -> Result<(), HeadlessError> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        pixels as u32,
+        frames.len() as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()?
+        .write_image_data(&to_rgb_bytes(frames))?;
+    Ok(())
+}
+
+fn read_png(path: &Path) -> Result<Vec<u8>, HeadlessError> {
+    let mut reader = png::Decoder::new(File::open(path)?).read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    data.truncate(info.buffer_size());
+    Ok(data)
+}
@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+/// Switches every ledstrip to an idle effect after the audio has been silent for a while, and
+/// back to the effects of the config once sound comes back. For the installs that run all day.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct IdleConfig {
+    /// Root mean square of the samples, in [0, 1], under which the audio is silent
+    #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+    pub rms_threshold: f32,
+    /// Seconds of silence before switching to the idle effect
+    #[serde(deserialize_with = "crate::config_parser::non_negative")]
+    pub after_secs: f32,
+    /// Effect rendered on every segment while idle. The ledstrips are turned off if missing
+    pub effect_id: Option<usize>,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            rms_threshold: 0.01,
+            after_secs: 30.0,
+            effect_id: None,
+        }
+    }
+}
+
+/// Tells from the rms of every tick whether the audio has been silent for long enough to idle
+#[derive(Debug)]
+pub struct SilenceDetector {
+    config: IdleConfig,
+    // Ticks since the audio went silent
+    silent_ticks: u32,
+}
+
+impl SilenceDetector {
+    pub fn new(config: IdleConfig) -> Self {
+        Self {
+            config,
+            silent_ticks: 0,
+        }
+    }
+
+    pub fn config(&self) -> IdleConfig {
+        self.config
+    }
+
+    /// Accounts for the rms of a tick and returns whether the engine is idle
+    pub fn update(&mut self, rms: f32) -> bool {
+        self.silent_ticks = if rms < self.config.rms_threshold {
+            self.silent_ticks.saturating_add(1)
+        } else {
+            0
+        };
+        self.is_idle()
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.silent_ticks as f32 >= self.config.after_secs * crate::ticks_per_second() as f32
+    }
+}
@@ -0,0 +1,301 @@
+use crate::{
+    audio::audio_processing::FftResult,
+    connections::LinkStatus,
+    controller::Controller,
+    now_playing::{self, NowPlaying},
+    overrides::OverrideInfo,
+};
+use serde::Serialize;
+use std::{fmt::Write, sync::OnceLock, time::Instant};
+
+// Bands of the spectrum of the live preview
+const PREVIEW_SPECTRUM_BANDS: usize = 64;
+const PREVIEW_SPECTRUM_RANGE: (f32, f32) = (20.0, 16000.0);
+
+/// Time at which the process started. Unlike the controller, it survives config reloads.
+pub static START_TIME: OnceLock<Instant> = OnceLock::new();
+
+#[derive(Debug, Serialize)]
+pub struct LedstripInfo {
+    pub id: usize,
+    pub size: usize,
+    /// Connection the ledstrip is sent to, which is its fallback while the primary is down
+    pub connection_id: Option<usize>,
+    /// Brightness of the ledstrip between 0 and 1, applied on top of the global brightness
+    pub brightness: f32,
+    /// True while the ledstrip is sent black frames, on top of the global blackout and the solo
+    pub blackout: bool,
+    /// True while the ledstrip keeps its current frame without rendering its effects
+    pub frozen: bool,
+    /// Effect rendered on each segment, in the order of the segments
+    pub segments: Vec<usize>,
+    /// Why some of its segments weren't rendered on the last frame, like a missing effect
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum ConnectionStatus {
+    Ok,
+    /// The circuit breaker tripped and sends are paused
+    Paused,
+    /// The cooldown is over and the next send checks if the connection recovered
+    Retrying,
+}
+
+/// Health of a connection, combining its circuit breaker and its link with the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ConnectionHealth {
+    Connected,
+    /// The link is being established again, or the next send checks if the connection recovered
+    Reconnecting,
+    /// The connection keeps failing and sends are paused. Its ledstrips use their fallback
+    Dead,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub id: usize,
+    pub status: ConnectionStatus,
+    pub link: LinkStatus,
+    pub trip_count: u64,
+    /// Frames sent on the connection during the last second
+    pub fps: u32,
+    /// Bytes sent on the connection during the last second, the framing of its protocol included
+    pub bytes_per_second: u64,
+    /// Frames waiting to be sent, more than one only on the delayed connections
+    pub queued_frames: usize,
+    /// Frames that failed or were skipped by the circuit breaker since the connection was added
+    pub dropped_frames: u64,
+    /// Frames dropped during the last second
+    pub dropped_per_second: u32,
+    /// Frames identical to the last one that weren't sent, on the connections skipping them
+    pub skipped_frames: u64,
+    pub health: ConnectionHealth,
+    /// Moving average of the time taken to send a frame. None until a frame was sent
+    pub latency_ms: Option<f32>,
+    /// Moving average of the round trip to the device, for the protocols with acks
+    pub round_trip_ms: Option<f32>,
+    /// Time the frames are held back to line the device up with the others
+    pub delay_ms: f32,
+    pub last_error: Option<String>,
+}
+
+/// State of a pipewire link requested by the stream connections of the audio source
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum StreamLinkState {
+    Linked,
+    /// Both ports are there and the link was requested from pipewire
+    Pending,
+    /// A stream or a port of the link isn't there
+    MissingPort,
+    /// Pipewire refused to create the link
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StreamLinkInfo {
+    pub output_port: String,
+    pub input_port: String,
+    pub state: StreamLinkState,
+}
+
+/// Routing between two pipewire streams requested by the config, like from the monitor of the
+/// speakers to the audio input of the engine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StreamConnectionInfo {
+    pub output_stream: String,
+    pub input_stream: String,
+    /// Streams of the connection that pipewire doesn't have
+    pub missing_streams: Vec<String>,
+    /// Links between the ports of the streams, empty while the ports to link aren't known
+    pub links: Vec<StreamLinkInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EffectInfo {
+    pub id: usize,
+    /// Settings the effect renders with, which other effects may share
+    pub settings_id: Option<usize>,
+    /// Moving average of the time taken to render a frame of the effect, on all its segments
+    pub render_time_ms: f32,
+    pub max_render_time_ms: f32,
+    /// Frames rendered over the effect budget since the effect was added
+    pub slow_frames: u64,
+    /// Whether the effect is frozen, by the control API or for going over budget
+    pub frozen: bool,
+}
+
+/// Capabilities and state of the running instance, so that remote UIs can adapt to it.
+#[derive(Debug, Serialize)]
+pub struct EngineInfo {
+    pub version: &'static str,
+    pub features: Vec<&'static str>,
+    pub uptime_secs: u64,
+    pub audio_backend: &'static str,
+    /// False while the engine runs without audio, waiting for the audio device
+    pub audio_available: bool,
+    /// True while the audio is silent and the ledstrips show the idle effect
+    pub idle: bool,
+    pub ledstrips: Vec<LedstripInfo>,
+    pub connections: Vec<ConnectionInfo>,
+    pub effects: Vec<EffectInfo>,
+    pub pixel_count: usize,
+    pub sync_offset_ms: i32,
+    /// Calibrated time from a sound being heard to the lights reacting to it, which the beat is
+    /// predicted ahead by
+    pub latency_ms: f32,
+    pub paused: bool,
+    /// Global brightness between 0 and 1
+    pub brightness: f32,
+    /// True while the ledstrips are sent black frames, whatever the effects and the brightness
+    pub blackout: bool,
+    /// True while the current frame is sent again without rendering the effects
+    pub frame_frozen: bool,
+    /// Only ledstrip that isn't blacked out, if any
+    pub solo: Option<usize>,
+    /// True while the white is warmer and the brightness capped for the late hours
+    pub night_mode: bool,
+    /// Effects whose animation is frozen on their last output
+    pub frozen_effects: Vec<usize>,
+    /// Fraction of the tick spent working. Above 1 the engine can't keep up
+    pub engine_load: f32,
+    /// Profile applied last, if any
+    pub profile: Option<String>,
+    pub profiles: Vec<String>,
+    /// Track of the media player followed, if any
+    pub now_playing: Option<NowPlaying>,
+    /// Pipewire links of the stream connections of the audio source
+    pub stream_connections: Vec<StreamConnectionInfo>,
+    /// Overrides replacing the effects of some segments, by channel
+    pub overrides: Vec<OverrideInfo>,
+}
+
+impl EngineInfo {
+    pub fn new(controller: &Controller) -> Self {
+        let mut ledstrips = controller.ledstrip_info();
+        ledstrips.sort_by_key(|ledstrip| ledstrip.id);
+        let mut connections = controller.connection_info();
+        connections.sort_by_key(|connection| connection.id);
+        let mut effects = controller.effect_info();
+        effects.sort_by_key(|effect| effect.id);
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            features: compiled_features(),
+            uptime_secs: START_TIME
+                .get()
+                .map(|start_time| start_time.elapsed().as_secs())
+                .unwrap_or_default(),
+            audio_backend: cpal::default_host().id().name(),
+            audio_available: controller.is_audio_available(),
+            idle: controller.is_idle(),
+            pixel_count: ledstrips.iter().map(|ledstrip| ledstrip.size).sum(),
+            ledstrips,
+            connections,
+            effects,
+            sync_offset_ms: controller.sync_offset_ms(),
+            latency_ms: controller.latency().total().as_secs_f32() * 1000.0,
+            paused: controller.is_paused(),
+            brightness: controller.brightness(),
+            blackout: controller.is_blackout(),
+            frame_frozen: controller.is_frame_frozen(),
+            solo: controller.solo(),
+            night_mode: controller.is_night_mode(),
+            frozen_effects: controller.frozen_effects(),
+            engine_load: controller.engine_load(),
+            profile: controller.active_profile().map(str::to_owned),
+            profiles: controller.profile_names(),
+            now_playing: now_playing::now_playing(),
+            stream_connections: stream_connection_info(),
+            overrides: controller.override_info(),
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+fn stream_connection_info() -> Vec<StreamConnectionInfo> {
+    crate::audio::pipewire_listener::stream_connection_info()
+}
+
+#[cfg(not(all(target_os = "linux", feature = "pipewire")))]
+fn stream_connection_info() -> Vec<StreamConnectionInfo> {
+    Vec::new()
+}
+
+/// What the web UI draws live: the spectrum of the audio and the colors of the ledstrips
+#[derive(Debug, Serialize)]
+pub struct LivePreview {
+    /// Average amplitudes of bands from 20Hz to 16kHz on a log frequency scale
+    pub spectrum: Vec<f32>,
+    pub ledstrips: Vec<LedstripPreview>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LedstripPreview {
+    pub id: usize,
+    /// Colors of the leds as hex `rrggbb`, one after the other, before the brightness
+    pub colors: String,
+}
+
+impl LivePreview {
+    pub fn new(controller: &Controller, fft_result: &FftResult) -> Self {
+        let mut ledstrips: Vec<_> = controller
+            .led_strip_colors()
+            .map(|(id, colors)| LedstripPreview {
+                id,
+                colors: colors.iter().fold(String::new(), |mut hex, color| {
+                    let _ = write!(hex, "{:02x}{:02x}{:02x}", color.r, color.g, color.b);
+                    hex
+                }),
+            })
+            .collect();
+        ledstrips.sort_by_key(|ledstrip| ledstrip.id);
+        Self {
+            spectrum: fft_result.log_bands(PREVIEW_SPECTRUM_BANDS, PREVIEW_SPECTRUM_RANGE),
+            ledstrips,
+        }
+    }
+}
+
+fn compiled_features() -> Vec<&'static str> {
+    let mut features = vec!["lua", "native", "tcp", "osc", "http"];
+    if cfg!(feature = "grpc") {
+        features.push("grpc");
+    }
+    if cfg!(all(target_os = "linux", feature = "hotkeys")) {
+        features.push("hotkeys");
+    }
+    if cfg!(feature = "hue") {
+        features.push("hue");
+    }
+    if cfg!(feature = "jack") {
+        features.push("jack");
+    }
+    if cfg!(feature = "link") {
+        features.push("link");
+    }
+    if cfg!(feature = "midi") {
+        features.push("midi");
+    }
+    if cfg!(feature = "mpris") {
+        features.push("mpris");
+    }
+    if cfg!(feature = "mqtt") {
+        features.push("mqtt");
+    }
+    if cfg!(feature = "rpi") {
+        features.push("rpi");
+    }
+    if cfg!(feature = "screen") {
+        features.push("screen");
+    }
+    if cfg!(all(not(target_os = "macos"), feature = "simulator")) {
+        features.push("simulator");
+    }
+    if cfg!(feature = "tls") {
+        features.push("tls");
+    }
+    if cfg!(feature = "tui") {
+        features.push("tui");
+    }
+    features
+}
@@ -0,0 +1,46 @@
+use crate::resources::ledstrip::Pixel;
+use std::collections::HashMap;
+
+/// Frames sent to the ledstrips in between the ticks, when they refresh faster than the effects
+/// render. They fade linearly from the previous rendered frame to the latest one, so the lights
+/// lag a tick behind the effects in exchange for a smooth motion.
+#[derive(Debug, Default)]
+pub struct FrameInterpolation {
+    // led strip id to its previous and latest rendered frames
+    frames: HashMap<usize, (Vec<Pixel>, Vec<Pixel>)>,
+    interpolated: Vec<Pixel>,
+}
+
+impl FrameInterpolation {
+    /// Keeps the latest rendered frame of a ledstrip, the previous one becoming the start of the
+    /// fade
+    pub fn push(&mut self, ledstrip_id: usize, colors: &[Pixel]) {
+        let (previous, latest) = self.frames.entry(ledstrip_id).or_default();
+        std::mem::swap(previous, latest);
+        latest.clear();
+        latest.extend_from_slice(colors);
+        // A resized ledstrip starts over from its new frame
+        if previous.len() != latest.len() {
+            previous.clone_from(latest);
+        }
+    }
+
+    /// The frame of the ledstrip `progress` of the way, between 0 and 1, from its previous frame
+    /// to its latest one
+    pub fn frame(&mut self, ledstrip_id: usize, progress: f32) -> &[Pixel] {
+        let Some((previous, latest)) = self.frames.get(&ledstrip_id) else {
+            return &[];
+        };
+        self.interpolated.clear();
+        self.interpolated
+            .extend(previous.iter().zip(latest).map(|(from, to)| {
+                [0, 1, 2].map(|channel| from[channel] + (to[channel] - from[channel]) * progress)
+            }));
+        &self.interpolated
+    }
+
+    /// Forgets the frames of a ledstrip
+    pub fn remove(&mut self, ledstrip_id: usize) {
+        self.frames.remove(&ledstrip_id);
+    }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+/// Follows the transport of a JACK server, like the one of a DAW, whose tempo and bar and beat
+/// position drive the beat of the effects instead of the beats detected in the audio. Needs
+/// turbo_audio to be built with the `jack` feature
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct JackTransportConfig {
+    /// Name of the JACK client reading the transport
+    pub client_name: String,
+}
+
+impl Default for JackTransportConfig {
+    fn default() -> Self {
+        Self {
+            client_name: "turbo_audio".to_owned(),
+        }
+    }
+}
+
+#[cfg(feature = "jack")]
+pub use client::{JackTransport, JackTransportError};
+
+#[cfg(feature = "jack")]
+mod client {
+    use super::JackTransportConfig;
+    use crate::sync::{local_time, SharedTransport, TransportPosition};
+    use jack::{
+        AsyncClient, Client, ClientOptions, Control, ProcessHandler, ProcessScope, Transport,
+        TransportState,
+    };
+    use thiserror::Error;
+
+    #[derive(Error, Debug)]
+    pub enum JackTransportError {
+        #[error("Couldn't connect to the JACK server: {0}")]
+        Connect(jack::Error),
+
+        #[error("Couldn't start the JACK client: {0}")]
+        Activate(jack::Error),
+    }
+
+    // Reads the transport at the start of every cycle, on the real-time thread of JACK
+    struct TransportReader {
+        transport: Transport,
+        position: SharedTransport,
+    }
+
+    impl ProcessHandler for TransportReader {
+        fn process(&mut self, _: &Client, _: &ProcessScope) -> Control {
+            let position = self.transport.query().ok().and_then(|state| {
+                if state.state != TransportState::Rolling {
+                    return None;
+                }
+                // Only there while an application, like the DAW, is the timebase master
+                let bbt = state.pos.bbt()?;
+                let beats = bbt.bar.saturating_sub(1) as f64 * bbt.sig_num as f64
+                    + bbt.beat.saturating_sub(1) as f64
+                    + bbt.tick as f64 / bbt.ticks_per_beat;
+                (bbt.bpm > 0.0).then_some(TransportPosition {
+                    beats,
+                    bpm: bbt.bpm,
+                    read_at: local_time(),
+                })
+            });
+            // Skipped rather than waiting on the controller, the next cycle is soon
+            if let Ok(mut shared) = self.position.try_lock() {
+                *shared = position;
+            }
+            Control::Continue
+        }
+    }
+
+    /// JACK client storing the position of the transport for the controller every cycle. It's
+    /// cleared once the client stops
+    pub struct JackTransport {
+        client: Option<AsyncClient<(), TransportReader>>,
+        position: SharedTransport,
+    }
+
+    impl JackTransport {
+        pub fn new(
+            config: &JackTransportConfig,
+            position: SharedTransport,
+        ) -> Result<Self, JackTransportError> {
+            let (client, _) = Client::new(&config.client_name, ClientOptions::NO_START_SERVER)
+                .map_err(JackTransportError::Connect)?;
+            let reader = TransportReader {
+                transport: client.transport(),
+                position: position.clone(),
+            };
+            let client = client
+                .activate_async((), reader)
+                .map_err(JackTransportError::Activate)?;
+            tracing::info!(
+                "Following the JACK transport as {}",
+                client.as_client().name()
+            );
+            Ok(Self {
+                client: Some(client),
+                position,
+            })
+        }
+    }
+
+    impl Drop for JackTransport {
+        fn drop(&mut self) {
+            if let Some(client) = self.client.take() {
+                if let Err(e) = client.deactivate() {
+                    tracing::error!("Couldn't stop the JACK client: {e}");
+                }
+            }
+            *self.position.lock().unwrap() = None;
+        }
+    }
+}
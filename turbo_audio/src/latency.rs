@@ -0,0 +1,204 @@
+use crate::audio::{audio_processing::FftResult, onset::OnsetDetector};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::HeapConsumer;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    f32::consts::TAU,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+// Frequency band in which the clicks carry most of their energy, like the ones of av_sync
+const CLICK_BAND: (f32, f32) = (1000.0, 8000.0);
+const CLICK_FREQUENCY: f32 = 3000.0;
+// Seconds for a click to decay by a factor of e, and seconds it lasts
+const CLICK_DECAY: f32 = 0.004;
+const CLICK_LENGTH: f32 = 0.03;
+const CLICK_VOLUME: f32 = 0.8;
+// Off the usual tick rates, so that the clicks fall at every point of a tick
+const CLICK_INTERVAL: Duration = Duration::from_millis(737);
+// Clicks heard later than this are taken for noise
+const MAX_LAG: Duration = Duration::from_millis(500);
+// Fewest clicks heard for the measure to count
+const MIN_CLICKS: usize = 3;
+
+#[derive(Error, Debug)]
+pub enum LatencyError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid latency: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Couldn't play the clicks: {0}")]
+    Output(String),
+
+    #[error("Heard {0} of the {1} clicks played, is the input picking up the speakers?")]
+    TooFewClicks(usize, usize),
+}
+
+/// Measured time from a sound being heard to the lights reacting to it. The beat is predicted
+/// this far ahead so that the flashes land on it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    /// From the sound leaving the speakers to the engine detecting it
+    pub audio_ms: f32,
+    /// From the engine sending a frame to the device receiving it
+    pub lights_ms: f32,
+}
+
+impl Latency {
+    pub fn total(&self) -> Duration {
+        Duration::from_secs_f32((self.audio_ms + self.lights_ms).max(0.0) / 1000.0)
+    }
+
+    /// Loads the latency saved by the last calibration. None if it was never calibrated
+    pub fn load(path: &Path) -> Option<Self> {
+        let result = File::open(path)
+            .map_err(LatencyError::from)
+            .and_then(|file| Ok(serde_json::from_reader(BufReader::new(file))?));
+        match result {
+            Ok(latency) => {
+                tracing::info!("Loaded the audio to light latency from {}", path.display());
+                Some(latency)
+            }
+            Err(LatencyError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                tracing::warn!(
+                    "Couldn't load the audio to light latency from {}: {e}",
+                    path.display()
+                );
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), LatencyError> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
+
+/// Plays clicks on the default output and times how long after they leave the speakers the
+/// audio input hears them, through the air and the whole audio pipeline
+pub struct LatencyCalibration {
+    // Plays the clicks until dropped
+    _output: cpal::Stream,
+    // When the clicks played leave the speakers
+    played: HeapConsumer<Instant>,
+    pending: VecDeque<Instant>,
+    played_count: usize,
+    detector: OnsetDetector,
+    lags: Vec<Duration>,
+    until: Instant,
+}
+
+impl LatencyCalibration {
+    pub fn start(duration: Duration) -> Result<Self, LatencyError> {
+        let (output, played) = play_clicks().map_err(|e| LatencyError::Output(e.to_string()))?;
+        tracing::info!(
+            "Calibrating the audio to light latency over {}s, keep the room quiet",
+            duration.as_secs_f32()
+        );
+        Ok(Self {
+            _output: output,
+            played,
+            pending: VecDeque::new(),
+            played_count: 0,
+            detector: OnsetDetector::new(CLICK_BAND.0, CLICK_BAND.1),
+            lags: Vec::new(),
+            until: Instant::now() + duration,
+        })
+    }
+
+    /// Feeds the latest fft frame. Returns the median lag of the clicks heard once the
+    /// calibration is over
+    pub fn tick(&mut self, fft_result: &FftResult) -> Option<Result<Duration, LatencyError>> {
+        let now = Instant::now();
+        while let Some(played_at) = self.played.pop() {
+            self.pending.push_back(played_at);
+            self.played_count += 1;
+        }
+        if self.detector.tick(fft_result) {
+            // The last click that left the speakers before the onset, the earlier ones went
+            // unheard
+            let heard = self.pending.iter().rposition(|played_at| *played_at <= now);
+            if let Some(index) = heard {
+                let lag = now - self.pending[index];
+                self.pending.drain(..=index);
+                if lag <= MAX_LAG {
+                    tracing::debug!("Heard a click after {}ms", lag.as_millis());
+                    self.lags.push(lag);
+                }
+            }
+        }
+
+        if now < self.until {
+            return None;
+        }
+        if self.lags.len() < MIN_CLICKS {
+            return Some(Err(LatencyError::TooFewClicks(
+                self.lags.len(),
+                self.played_count,
+            )));
+        }
+        self.lags.sort();
+        Some(Ok(self.lags[self.lags.len() / 2]))
+    }
+}
+
+// Plays a click every CLICK_INTERVAL on the default output, giving when each of them leaves the
+// speakers
+fn play_clicks(
+) -> Result<(cpal::Stream, HeapConsumer<Instant>), Box<dyn std::error::Error + Send + Sync>> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or("No default audio output found")?;
+    let supported = device.default_output_config()?;
+    if supported.sample_format() != cpal::SampleFormat::F32 {
+        return Err("The output device doesn't support f32 samples".into());
+    }
+    let config: cpal::StreamConfig = supported.into();
+    let sample_rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let interval = (CLICK_INTERVAL.as_secs_f32() * sample_rate) as u64;
+    let length = (CLICK_LENGTH * sample_rate) as u64;
+
+    let (mut played_tx, played_rx) = ringbuf::HeapRb::<Instant>::new(64).split();
+    let mut position = 0u64;
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+            let now = Instant::now();
+            let timestamp = info.timestamp();
+            // Time the first sample of the buffer waits before leaving the speakers
+            let ahead = timestamp
+                .playback
+                .duration_since(&timestamp.callback)
+                .unwrap_or_default();
+            for (index, frame) in data.chunks_mut(channels).enumerate() {
+                let in_click = position % interval;
+                if in_click == 0 {
+                    let offset = Duration::from_secs_f32(index as f32 / sample_rate);
+                    let _ = played_tx.push(now + ahead + offset);
+                }
+                let sample = if in_click < length {
+                    let t = in_click as f32 / sample_rate;
+                    (TAU * CLICK_FREQUENCY * t).sin() * (-t / CLICK_DECAY).exp() * CLICK_VOLUME
+                } else {
+                    0.0
+                };
+                frame.fill(sample);
+                position += 1;
+            }
+        },
+        |err| tracing::error!("Audio output error: {err}"),
+        None,
+    )?;
+    stream.play()?;
+    Ok((stream, played_rx))
+}
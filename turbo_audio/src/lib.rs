@@ -0,0 +1,91 @@
+pub mod ableton_link;
+pub mod audio;
+pub mod av_sync;
+pub mod cache;
+pub mod check_config;
+pub mod color_lut;
+pub mod config_diff;
+pub mod config_parser;
+pub mod connections;
+pub mod control;
+pub mod controller;
+#[cfg(unix)]
+pub mod ctl;
+pub mod discovery;
+pub mod dry_run;
+pub mod frame_capture;
+pub mod generate_config;
+pub mod headless;
+pub mod hot_reloader;
+pub mod idle;
+pub mod info;
+pub mod interpolation;
+pub mod jack_transport;
+pub mod latency;
+pub mod list_devices;
+pub mod mdns;
+pub mod metrics;
+pub mod night_mode;
+pub mod now_playing;
+pub mod output_transform;
+pub mod overrides;
+pub mod pacing;
+pub mod parameter_mapping;
+pub mod plugins;
+pub mod post_processing;
+pub mod repl;
+pub mod resources;
+pub mod schedule;
+pub mod scheduler;
+pub mod screen;
+pub mod session;
+pub mod simd;
+#[cfg(all(not(target_os = "macos"), feature = "simulator"))]
+pub mod simulator;
+pub mod sync;
+#[cfg(target_os = "linux")]
+pub mod systemd;
+pub mod test_output;
+pub mod test_pattern;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod watchdog;
+pub mod wled_import;
+
+use std::sync::{atomic::AtomicBool, OnceLock};
+
+/// Ticks per second of the engine, unless the run sets another rate with --fps
+pub const DEFAULT_TICKS_PER_SECOND: u32 = 60;
+
+static TICKS_PER_SECOND: OnceLock<u32> = OnceLock::new();
+
+/// Ticks per second of the engine. Every tick renders the effects and sends a frame
+pub fn ticks_per_second() -> u32 {
+    TICKS_PER_SECOND
+        .get()
+        .copied()
+        .unwrap_or(DEFAULT_TICKS_PER_SECOND)
+}
+
+/// Sets the tick rate for the rest of the run. Only the first call has an effect
+pub fn set_ticks_per_second(ticks_per_second: u32) {
+    TICKS_PER_SECOND.get_or_init(|| ticks_per_second);
+}
+
+static FRAMES_PER_TICK: OnceLock<u32> = OnceLock::new();
+
+/// Frames sent to the ledstrips every tick, 1 unless the run sends more with --output-fps. The ones
+/// past the first are interpolated between the last two rendered frames
+pub fn frames_per_tick() -> u32 {
+    FRAMES_PER_TICK.get().copied().unwrap_or(1)
+}
+
+/// Sets the frames sent per tick for the rest of the run. Only the first call has an effect
+pub fn set_frames_per_tick(frames_per_tick: u32) {
+    FRAMES_PER_TICK.get_or_init(|| frames_per_tick.max(1));
+}
+
+/// Stops the engine at the end of the tick once set, like on ctrl-c
+pub static SHOULD_QUIT: AtomicBool = AtomicBool::new(false);
@@ -0,0 +1,25 @@
+use crate::{
+    audio::audio_stream::list_input_devices,
+    mdns::{self, BrowseArgs, MdnsError},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ListDevicesArgs {
+    #[command(flatten)]
+    browse: BrowseArgs,
+}
+
+/// Prints the audio devices that can be listened to and the controllers found on the local
+/// network, with the names the config can use for them
+pub fn run(args: &ListDevicesArgs) -> Result<(), MdnsError> {
+    println!("Audio devices:");
+    match list_input_devices() {
+        Ok(devices) if devices.is_empty() => println!("  No audio device found"),
+        Ok(devices) => devices.iter().for_each(|device| println!("  {device}")),
+        Err(e) => println!("  Couldn't list the audio devices: {e:#}"),
+    }
+
+    println!();
+    println!("Controllers:");
+    mdns::run(&args.browse)
+}
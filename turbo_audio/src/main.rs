@@ -1,91 +1,570 @@
-mod audio;
-mod config_parser;
-mod connections;
-mod controller;
-mod hot_reloader;
-mod plugins;
-mod resources;
-
-use crate::hot_reloader::{HotReloader, WatchablePath};
-use crate::resources::ledstrip::LedStrip;
+use anyhow::Context;
 use audio::audio_processing::AudioSignalProcessor;
-use audio::{audio_stream::start_audio_loop, pipewire_listener::PipewireController};
-use clap::Parser;
-use config_parser::{ConnectionConfigType, EffectConfigType, SettingsConfigType, TurboAudioConfig};
-use connections::{tcp::TcpConnection, usb::UsbConnection, Connection};
+use audio::audio_stream::{
+    start_audio_loop, AudioDeviceWatcher, AudioStream, MissingAudioBehavior,
+};
+use audio::file_source::FilePlayback;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+use audio::pipewire_listener::PipewireController;
+use audio::recording::{FeatureRecorder, FeatureReplay};
+use audio::signal_generator::SyntheticAudio;
+use cache::Cache;
+use clap::{Parser, Subcommand, ValueEnum};
+use color_lut::ColorLut;
+use config_diff::ConfigDiff;
+use config_parser::{
+    parse_config, AudioSourceConfig, ConfigError, ConfigFormat, DeviceConfig, EffectConfig,
+    EffectConfigType, EffectSettingConfig, LedstripConfig, SettingsConfigType, TurboAudioConfig,
+    CONFIG_VERSION,
+};
+use connections::{sender::DispatchDelay, ConnectionFactory};
+use control::{http::HttpServer, osc::OscServer, ControlCommand, ControlReceiver};
 use controller::Controller;
+use metrics::MetricsHistory;
 use plugins::effects::{
-    lua::LuaEffectSettings, native::NativeEffectSettings, Effect, EffectSettings,
+    lua::LuaEffectSettings, native::NativeEffectSettings, registry::RHAI_EFFECTS_FOLDER,
+    EffectSettings,
 };
+use post_processing::PostProcessingChain;
+use ringbuf::HeapConsumer;
+#[cfg(unix)]
+use signal_hook::consts::{SIGHUP, SIGTERM};
+use std::path::Path;
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::atomic::{self, AtomicBool};
-use std::{fs::File, path::Path};
+use std::sync::Arc;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+#[cfg(unix)]
+use turbo_audio::ctl;
+use turbo_audio::hot_reloader::{HotReloader, WatchablePath};
+use turbo_audio::resources::registry::RegistryError;
+#[cfg(all(not(target_os = "macos"), feature = "simulator"))]
+use turbo_audio::simulator::Simulator;
+#[cfg(target_os = "linux")]
+use turbo_audio::systemd;
+#[cfg(feature = "tui")]
+use turbo_audio::tui;
+use turbo_audio::{
+    audio, cache, check_config, color_lut, config_diff, config_parser, connections, control,
+    controller, discovery, dry_run::DryRun, frames_per_tick, generate_config, headless, info,
+    list_devices, mdns, metrics, pacing, plugins, post_processing, repl, session::Session,
+    set_frames_per_tick, set_ticks_per_second, simd, sync::SyncPeer, test_output, ticks_per_second,
+    watchdog, wled_import, DEFAULT_TICKS_PER_SECOND, SHOULD_QUIT,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+/// What the leds show once the engine stopped
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ExitBehavior {
+    /// The leds fade to black over --fade-ms
+    Blank,
+    /// The leds keep the last frame. WLED is told to keep it instead of timing out
+    Hold,
+    /// The controllers go back to their own effects, for WLED and sACN. The other ones are turned
+    /// off
+    Release,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the engine, which is also what happens without a command
+    Run(RunArgs),
+    /// Parse the settings file and check that its resources refer to each other, without
+    /// starting anything
+    CheckConfig,
+    /// Write an example settings file to start from, in the format of its extension
+    GenerateConfig(generate_config::GenerateConfigArgs),
+    /// List the audio devices and the controllers advertised on the local network
+    ListDevices(list_devices::ListDevicesArgs),
+    /// Show a test pattern on a connection of the settings file, to check the wiring, the led
+    /// count and the segment boundaries
+    TestOutput(test_output::TestOutputArgs),
+    /// Render an effect against synthetic audio and dump its frames, without audio device or
+    /// ledstrips
+    Render(headless::RenderArgs),
+    /// Talk to a running instance through its control socket
+    #[cfg(unix)]
+    Ctl(ctl::CtlArgs),
+    /// Lua console in the environment of an effect of a running instance, through its http
+    /// server, to live-code an effect from another machine
+    Repl(repl::ReplArgs),
+    /// Query the led layout of a WLED or Hyperion controller and print the matching config
+    Discover(discovery::DiscoverArgs),
+    /// List the WLED and ESPHome controllers advertised on the local network over mDNS
+    Browse(mdns::BrowseArgs),
+    /// Convert the segments, effects and presets exported from WLED into the matching config
+    ImportWled(wled_import::ImportWledArgs),
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Settings file
-    #[arg(long, default_value_t = String::from("Settings.json"))]
+    #[arg(long, global = true, default_value_t = String::from("Settings.json"))]
     settings_file: String,
+
+    /// Format of the settings file, guessed from its extension if missing
+    #[arg(long, global = true, value_enum)]
+    format: Option<ConfigFormat>,
+
+    /// Folder where the parsed config and compiled lua effects are cached between runs
+    #[arg(long, global = true, default_value_t = String::from(".turbo_cache"))]
+    cache_folder: String,
+
+    /// Don't read or write the startup cache
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Process the frames with plain loops instead of the vectorized kernels, to compare them
+    #[arg(long, global = true)]
+    no_simd: bool,
+
+    /// Log level or filter directives (e.g. `debug` or `info,turbo_audio::connections=trace`).
+    /// Overridden by the RUST_LOG environment variable.
+    #[arg(long, global = true, default_value_t = String::from("info"))]
+    log_level: String,
+
+    /// Format of the log output
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// Show a live dashboard in the terminal. Logs are written to turbo_audio.log instead
+    #[arg(long)]
+    tui: bool,
+
+    /// Open a window rendering the ledstrips, to develop effects without hardware
+    #[arg(long)]
+    simulate: bool,
+
+    /// Record the audio features of every tick to this file
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Replay audio features recorded with --record instead of listening to the audio device
+    #[arg(long, conflicts_with = "record")]
+    replay: Option<PathBuf>,
+
+    /// Play a wav or flac file in a loop instead of listening to the audio device
+    #[arg(long, conflicts_with = "replay", group = "playback")]
+    audio_file: Option<PathBuf>,
+
+    /// Generate a signal instead of listening to the audio device, to develop the effects
+    /// without music: sine:<frequency>, sweep:<seconds>, pink, noise, metronome:<bpm>,
+    /// kicks:<bpm>, bursts:<low>-<high> or silence
+    #[arg(long, conflicts_with = "replay", group = "playback")]
+    signal_gen: Option<SyntheticAudio>,
+
+    /// Also play the --audio-file or the --signal-gen on the default output device
+    #[arg(long, requires = "playback")]
+    play_through: bool,
+
+    /// Profile of the config to start with. The effects of the ledstrips if missing
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Run as a systemd service of Type=notify, notifying it when the engine is ready and pinging
+    /// its watchdog
+    #[arg(long, conflicts_with_all = ["tui", "simulate"])]
+    daemon: bool,
+
+    /// What the leds show when the engine stops on ctrl-c or SIGTERM
+    #[arg(long, value_enum, default_value_t = ExitBehavior::Hold)]
+    on_exit: ExitBehavior,
+
+    /// Duration of the fade of --on-exit blank
+    #[arg(long, default_value_t = 0)]
+    fade_ms: u64,
+
+    /// Frames rendered per second, and sent unless --output-fps sends more
+    #[arg(
+        long,
+        default_value_t = DEFAULT_TICKS_PER_SECOND,
+        value_parser = clap::value_parser!(u32).range(1..=240)
+    )]
+    fps: u32,
+
+    /// Frames sent to the ledstrips per second, a multiple of --fps. The frames in between the
+    /// rendered ones are interpolated, so fast ledstrips stay smooth with the effects rendered at
+    /// a lower rate. The lights lag a rendered frame behind in exchange
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=480))]
+    output_fps: Option<u32>,
+
+    /// Load the config, open the audio devices, the pipewire links and the connections, and
+    /// print what would run without rendering. Exits with an error if anything fails
+    #[arg(long, conflicts_with_all = ["tui", "simulate", "daemon"])]
+    dry_run: bool,
+}
+
+const TUI_LOG_FILE: &str = "turbo_audio.log";
+
+fn init_logging(log_level: &str, log_format: LogFormat, log_file: Option<&str>) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level));
+    let writer = match log_file.map(std::fs::File::create) {
+        Some(Ok(file)) => BoxMakeWriter::new(std::sync::Mutex::new(file)),
+        Some(Err(e)) => {
+            eprintln!("Couldn't create the log file, logs are discarded: {e}");
+            BoxMakeWriter::new(std::io::sink)
+        }
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    // Also installs a bridge so that the `log` records of our dependencies end up in the same
+    // output
+    match log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
 }
 
+// Longest capture of the audio input to a wav file, about 100MB at 48kHz
+const MAX_CAPTURE_SECONDS: f32 = 600.0;
+
+// Exit code of the config errors, so that a service manager doesn't restart the engine until the
+// config is fixed. EX_CONFIG in sysexits.h
+const CONFIG_ERROR_EXIT_CODE: u8 = 78;
+
 #[derive(Debug)]
 enum RunLoopError {
     LoadConfigFile,
     StartAudioLoop,
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
     StartPipewireStream,
+    Render,
+    #[cfg(unix)]
+    Ctl,
+    Discover,
+    Browse,
+    Repl,
+    TestOutput,
+    GenerateConfig,
+    ImportWled,
+    DryRun,
+}
+
+impl RunLoopError {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            Self::LoadConfigFile => ExitCode::from(CONFIG_ERROR_EXIT_CODE),
+            _ => ExitCode::FAILURE,
+        }
+    }
 }
 
-pub static SHOULD_QUIT: AtomicBool = AtomicBool::new(false);
+/// How the engine runs as a service and stops
+struct ServiceOptions {
+    on_exit: ExitBehavior,
+    fade: std::time::Duration,
+    // Set in daemon mode when systemd expects notifications
+    #[cfg(target_os = "linux")]
+    notifier: Option<systemd::Notifier>,
+}
+
+impl ServiceOptions {
+    fn new(daemon: bool, on_exit: ExitBehavior, fade: std::time::Duration) -> Self {
+        #[cfg(target_os = "linux")]
+        let notifier = daemon.then(systemd::Notifier::from_env).flatten();
+        #[cfg(target_os = "linux")]
+        if daemon && notifier.is_none() {
+            tracing::warn!("Running as a daemon but systemd doesn't expect notifications");
+        }
+        #[cfg(not(target_os = "linux"))]
+        if daemon {
+            tracing::warn!("Systemd notifications are only sent on linux");
+        }
+        Self {
+            on_exit,
+            fade,
+            #[cfg(target_os = "linux")]
+            notifier,
+        }
+    }
+
+    fn ready(&self) {
+        #[cfg(target_os = "linux")]
+        if let Some(notifier) = &self.notifier {
+            notifier.ready();
+        }
+    }
+
+    fn restarting(&self) {
+        #[cfg(target_os = "linux")]
+        if let Some(notifier) = &self.notifier {
+            notifier.restarting();
+        }
+    }
+
+    /// Shows the exit behavior on the leds and tells systemd that the engine is stopping
+    fn stop(&self, controller: &mut Controller) {
+        #[cfg(target_os = "linux")]
+        if let Some(notifier) = &self.notifier {
+            notifier.stopping();
+        }
+        match self.on_exit {
+            ExitBehavior::Blank => {
+                tracing::info!("Turning off the leds");
+                let tick = std::time::Duration::from_secs(1) / ticks_per_second();
+                let steps = (self.fade.as_millis() / tick.as_millis()).max(1) as u32;
+                for step in (0..steps).rev() {
+                    controller.send_faded_frames(step as f32 / steps as f32);
+                    if step > 0 {
+                        std::thread::sleep(tick);
+                    }
+                }
+            }
+            ExitBehavior::Hold => controller.hold_last_frames(),
+            ExitBehavior::Release => {
+                tracing::info!("Releasing the controllers");
+                controller.release_connections();
+            }
+        }
+    }
+
+    fn tick(&self) {
+        #[cfg(target_os = "linux")]
+        if let Some(notifier) = &self.notifier {
+            notifier.tick();
+        }
+    }
+}
+
+/// How the run loop gets and keeps the audio features, besides the audio processor's own stream
+struct AudioFeatureSources<'a> {
+    recorder: Option<&'a mut FeatureRecorder>,
+    replay: Option<&'a mut FeatureReplay>,
+    // Set when listening to the audio device rather than to a file or a replay
+    live_audio: Option<&'a mut LiveAudio>,
+    extra_sources: &'a mut [ExtraAudioSource],
+}
+
+/// The controller and the config it was loaded from, with where to reload the config from
+struct LoadedConfig<'a> {
+    controller: Controller,
+    config: TurboAudioConfig,
+    settings_file: &'a str,
+    format: ConfigFormat,
+    cache: Option<&'a Cache>,
+    // Set by SIGHUP
+    reload_requested: &'a AtomicBool,
+    // Profile applied again when the engine restarts
+    profile: &'a mut Option<String>,
+    service: &'a ServiceOptions,
+    session: Option<Session>,
+}
 
 fn run_loop(
     mut audio_processor: AudioSignalProcessor,
-    mut controller: Controller,
+    mut loaded: LoadedConfig,
+    control_rx: ControlReceiver,
+    AudioFeatureSources {
+        mut recorder,
+        mut replay,
+        mut live_audio,
+        extra_sources,
+    }: AudioFeatureSources,
+    metrics: &mut MetricsHistory,
+    #[cfg(feature = "tui")] mut dashboard: Option<&mut tui::Dashboard>,
+    #[cfg(all(not(target_os = "macos"), feature = "simulator"))] simulator: Option<&Simulator>,
 ) -> Result<(), RunLoopError> {
-    log::info!("Creating watcher on Settings.json");
+    tracing::info!("Creating watcher on {}", loaded.settings_file);
     let config_hot_reload = HotReloader::new(&[WatchablePath::non_recursive(&PathBuf::from(
-        "Settings.json",
+        loaded.settings_file,
     ))]);
 
     if let Err(e) = &config_hot_reload {
-        log::error!("Couldn't start watching the config for hot reload: {e}");
+        tracing::error!("Couldn't start watching the config for hot reload: {e}");
     }
 
     let config_hot_reload = config_hot_reload.ok();
 
-    let mut lag = chrono::Duration::zero();
-    let duration_per_tick: chrono::Duration = chrono::Duration::seconds(1) / 60;
-    let mut last_loop_start = std::time::Instant::now();
+    // With more frames sent than rendered, the loop wakes up for every frame and only renders on
+    // the ticks
+    let frames_per_tick = frames_per_tick();
+    let mut pacer = pacing::FramePacer::new(ticks_per_second() * frames_per_tick);
+    let mut frame = 0u64;
+    let mut watchdog = watchdog::Watchdog::default();
+    loaded.service.ready();
     loop {
         if SHOULD_QUIT.load(atomic::Ordering::Relaxed) {
-            log::info!("Quitting");
+            tracing::info!("Quitting");
+            if let Some(session) = &mut loaded.session {
+                session.save(loaded.controller.session_state());
+            }
+            loaded.service.stop(&mut loaded.controller);
             break Ok(());
         }
+        loaded.service.tick();
 
-        lag = lag
-            .checked_add(&chrono::Duration::from_std(last_loop_start.elapsed()).unwrap())
-            .unwrap();
-        last_loop_start = std::time::Instant::now();
-        let current_sleep_duration = std::cmp::max(
-            chrono::Duration::zero(),
-            duration_per_tick.checked_sub(&lag).unwrap(),
-        );
-        std::thread::sleep(current_sleep_duration.to_std().unwrap());
-        audio_processor.compute_fft();
+        let skipped = pacer.wait();
+        if skipped > 0 {
+            tracing::debug!("Skipped {skipped} frames to catch up");
+        }
+        frame += skipped as u64;
+        let frame_in_tick = (frame % frames_per_tick as u64) as u32;
+        frame += 1;
+        if frame_in_tick > 0 {
+            let progress = frame_in_tick as f32 / frames_per_tick as f32;
+            loaded.controller.send_interpolated_frames(progress);
+            continue;
+        }
+        let work_start = std::time::Instant::now();
+        match &mut replay {
+            Some(replay) => replay.tick(&mut audio_processor),
+            None => audio_processor.compute_fft(),
+        }
+        for source in extra_sources.iter_mut() {
+            source.audio_processor.compute_fft();
+        }
+        if replay.is_some() || audio_processor.last_sample_count() > 0 {
+            loaded.controller.set_audio_received();
+        }
+        if let Some(Err(e)) = recorder
+            .as_mut()
+            .map(|recorder| recorder.record(&audio_processor.fft_result.load()))
+        {
+            tracing::error!("Stopping the feature recording: {e}");
+            recorder = None;
+        }
+
+        for command in control_rx.try_iter() {
+            match command {
+                ControlCommand::GetMetrics(reply) => {
+                    let _ = reply.send(metrics.samples());
+                }
+                ControlCommand::SetAudioDevice { device_name, reply } => {
+                    let result = match &mut live_audio {
+                        Some(live_audio) => {
+                            let result = live_audio.switch(
+                                device_name,
+                                &loaded.config,
+                                &mut audio_processor,
+                            );
+                            loaded.controller.set_audio_available(result.is_ok());
+                            result
+                        }
+                        None => Err("Not listening to an audio device".to_owned()),
+                    };
+                    let _ = reply.send(result);
+                }
+                ControlCommand::CalibrateNoiseFloor { seconds, reply } => {
+                    let result = if replay.is_some() {
+                        Err("Can't calibrate the noise floor while replaying".to_owned())
+                    } else if !(seconds > 0.0 && seconds.is_finite()) {
+                        Err(format!("Invalid calibration duration {seconds}"))
+                    } else {
+                        audio_processor.calibrate_noise_floor(seconds);
+                        Ok(())
+                    };
+                    let _ = reply.send(result);
+                }
+                ControlCommand::CaptureAudio {
+                    path,
+                    seconds,
+                    reply,
+                } => {
+                    let result = if replay.is_some() {
+                        Err("Can't capture the audio while replaying".to_owned())
+                    } else if !(seconds > 0.0 && seconds <= MAX_CAPTURE_SECONDS) {
+                        Err(format!(
+                            "Invalid capture duration {seconds}, expected up to \
+                             {MAX_CAPTURE_SECONDS}s"
+                        ))
+                    } else {
+                        audio_processor.capture_audio(&path, seconds)
+                    };
+                    let _ = reply.send(result);
+                }
+                command => loaded.controller.handle_command(command),
+            }
+        }
 
-        let _fft_result_read_lock = audio_processor.fft_result.read().unwrap();
-        controller.check_hot_reload();
-        controller.update_led_strips();
-        controller.send_ledstrip_colors();
+        let fft_result = audio_processor.fft_result.load();
+        loaded.controller.check_hot_reload();
+        loaded.controller.update_led_strips();
+        loaded.controller.send_ledstrip_colors();
+        loaded.controller.send_keep_alive_frames();
+        if let Some(session) = loaded.session.as_mut().filter(|session| session.is_due()) {
+            session.save(loaded.controller.session_state());
+        }
+        #[cfg(feature = "tui")]
+        if let Some(dashboard) = &mut dashboard {
+            dashboard.update(&fft_result, &loaded.controller);
+        }
+        #[cfg(all(not(target_os = "macos"), feature = "simulator"))]
+        if let Some(simulator) = simulator {
+            simulator.update(&loaded.controller);
+        }
 
-        if let Some(config_hot_reload) = &config_hot_reload {
-            if !config_hot_reload.poll_events().is_empty() {
-                log::info!("Config changed. Restarting.");
-                return Ok(());
+        let file_changed = config_hot_reload
+            .as_ref()
+            .is_some_and(|config_hot_reload| !config_hot_reload.poll_events().is_empty());
+        let reload_requested = loaded
+            .reload_requested
+            .swap(false, atomic::Ordering::Relaxed);
+        if (file_changed || reload_requested) && !reload_config(&mut loaded) {
+            *loaded.profile = loaded.controller.active_profile().map(str::to_owned);
+            loaded.service.restarting();
+            return Ok(());
+        }
+
+        if let Some(live_audio) = &mut live_audio {
+            if let Some(available) = live_audio.poll(&loaded.config, &mut audio_processor) {
+                loaded.controller.set_audio_available(available);
+            }
+        }
+        for source in extra_sources.iter_mut() {
+            if let Some(live_audio) = &mut source.live_audio {
+                live_audio.poll(&loaded.config, &mut source.audio_processor);
+            }
+        }
+
+        let watchdog_config = &loaded.config.watchdog;
+        watchdog.tick(watchdog_config);
+        // Waiting streams are silent on purpose
+        if let Some(live_audio) = &mut live_audio {
+            let has_samples = live_audio.is_waiting() || audio_processor.last_sample_count() > 0;
+            if watchdog.audio_stalled("", has_samples, watchdog_config) {
+                live_audio.restart(&loaded.config, &mut audio_processor);
+                loaded.controller.set_audio_available(false);
+            }
+        }
+        for source in extra_sources.iter_mut() {
+            if let Some(live_audio) = &mut source.live_audio {
+                let has_samples =
+                    live_audio.is_waiting() || source.audio_processor.last_sample_count() > 0;
+                if watchdog.audio_stalled(&source.name, has_samples, watchdog_config) {
+                    live_audio.restart(&loaded.config, &mut source.audio_processor);
+                }
             }
         }
+        loaded
+            .controller
+            .restart_stalled_connections(std::time::Duration::from_millis(
+                watchdog_config.send_stall_ms,
+            ));
 
-        lag = lag.checked_sub(&duration_per_tick).unwrap();
+        let work = work_start.elapsed();
+        loaded
+            .controller
+            .set_engine_load(work.as_secs_f32() / (pacer.period() * frames_per_tick).as_secs_f32());
+        metrics.record_tick(work, &fft_result, &loaded.controller);
     }
 }
 
@@ -94,124 +573,1143 @@ enum LoadControllerError {
     Invalid,
 }
 
+fn id_collision(e: RegistryError) -> LoadControllerError {
+    tracing::error!("{e}");
+    LoadControllerError::Invalid
+}
+
 fn load_controller(
     config: &TurboAudioConfig,
     audio_processor: &AudioSignalProcessor,
-    lua_effects_foler: impl AsRef<Path>,
+    extra_sources: &[ExtraAudioSource],
+    cache: Option<Cache>,
 ) -> Result<Controller, LoadControllerError> {
-    let mut controller = Controller::new(audio_processor, &lua_effects_foler);
+    let mut controller = Controller::new(
+        audio_processor,
+        &config.lua_effects_folder,
+        cache,
+        config.circuit_breaker,
+        config.av_sync.clone(),
+        config.lua_sandbox,
+    );
+    if config.render_threads.is_some() {
+        controller.set_render_threads(config.render_threads);
+    }
+    controller.set_effect_budget(config.effect_budget);
+    controller.set_idle(config.idle);
+    add_derived_features(&mut controller, config)?;
+    for source in extra_sources {
+        if !controller.add_audio_source(&source.name, &source.audio_processor) {
+            tracing::error!("Audio source {} is declared twice", source.name);
+            return Err(LoadControllerError::Invalid);
+        }
+    }
+    if let Err(e) = config.audio_routes() {
+        tracing::error!("{e}");
+        return Err(LoadControllerError::Invalid);
+    }
+
+    let connection_factory = ConnectionFactory::default();
     for connection_config in config.devices.iter() {
-        match &connection_config.connection {
-            ConnectionConfigType::Tcp(ip) => controller.add_connection(
-                connection_config.id,
-                Connection::Tcp(TcpConnection::new(*ip)),
-            ),
-            ConnectionConfigType::Usb() => {
-                controller.add_connection(connection_config.id, Connection::Usb(UsbConnection {}))
-            }
+        add_connection(&mut controller, &connection_factory, connection_config)?;
+    }
+    for setting_config in config.effect_settings.iter() {
+        add_settings(&mut controller, setting_config)?;
+    }
+    for effect_config in config.effects.iter() {
+        add_effect(&mut controller, effect_config, config)?;
+    }
+    for ledstrip_config in config.ledstrips.iter() {
+        add_led_strip(&mut controller, ledstrip_config)?;
+    }
+    controller.set_profiles(config.profiles.clone());
+    controller.set_schedule(config.schedule.clone());
+    controller.set_night_mode(config.night_mode);
+    if let Some(sync_config) = &config.sync {
+        match SyncPeer::new(sync_config) {
+            Ok(peer) => controller.set_sync(Some(peer)),
+            Err(e) => tracing::error!("Couldn't start syncing with the other instances: {e}"),
         }
     }
 
+    Ok(controller)
+}
+
+/// Rebuilds the resources that changed in the config and relinks the others, which keep their
+/// state. A failure leaves the controller half updated, so the caller restarts from scratch.
+fn apply_config_diff(
+    controller: &mut Controller,
+    diff: &ConfigDiff,
+    config: &TurboAudioConfig,
+) -> Result<(), LoadControllerError> {
+    for ledstrip_id in diff.ledstrips.dropped() {
+        controller.remove_led_strip(ledstrip_id);
+    }
+    for effect_id in diff.effects.dropped() {
+        controller.remove_effect(effect_id);
+    }
+    for settings_id in diff.effect_settings.dropped() {
+        controller.remove_settings(settings_id);
+    }
+    for connection_id in diff.devices.dropped() {
+        controller.remove_connection(connection_id);
+    }
+
+    if diff.derived_features {
+        controller.clear_derived_features();
+        add_derived_features(controller, config)?;
+    }
+    if diff.render_threads {
+        controller.set_render_threads(config.render_threads);
+    }
+    if diff.effect_budget {
+        controller.set_effect_budget(config.effect_budget);
+    }
+    if diff.idle {
+        controller.set_idle(config.idle);
+    }
+
+    let connection_factory = ConnectionFactory::default();
+    for connection_config in config.devices.iter() {
+        if diff.devices.is_built(connection_config.id) {
+            add_connection(controller, &connection_factory, connection_config)?;
+        }
+    }
     for setting_config in config.effect_settings.iter() {
-        match &setting_config.setting {
-            SettingsConfigType::Lua(settings) => controller.add_settings(
-                setting_config.id,
-                EffectSettings::Lua(LuaEffectSettings {
-                    settings: settings.clone(),
-                }),
-            ),
-            SettingsConfigType::Native => controller.add_settings(
-                setting_config.id,
-                EffectSettings::Native(NativeEffectSettings {}),
-            ),
+        if diff.effect_settings.is_built(setting_config.id) {
+            add_settings(controller, setting_config)?;
+        }
+    }
+    // The kept effects are relinked too, to check that their settings and the features their
+    // bindings read still exist
+    for effect_config in config.effects.iter() {
+        if diff.effects.is_built(effect_config.effect_id) {
+            add_effect(controller, effect_config, config)?;
+        } else {
+            link_effect(controller, effect_config, config)?;
+        }
+    }
+    for ledstrip_config in config.ledstrips.iter() {
+        if diff.ledstrips.is_built(ledstrip_config.id) {
+            add_led_strip(controller, ledstrip_config)?;
+        } else {
+            link_led_strip(controller, ledstrip_config)?;
         }
     }
+    // Applies the active profile again on the rebuilt ledstrips
+    if diff.profiles || !diff.ledstrips.is_empty() {
+        controller.set_profiles(config.profiles.clone());
+    }
+    if diff.schedule {
+        controller.set_schedule(config.schedule.clone());
+    }
+    if diff.night_mode {
+        controller.set_night_mode(config.night_mode);
+    }
+    Ok(())
+}
+
+fn add_derived_features(
+    controller: &mut Controller,
+    config: &TurboAudioConfig,
+) -> Result<(), LoadControllerError> {
+    for derived_feature in config.derived_features.iter() {
+        controller
+            .add_derived_feature(
+                derived_feature.name.clone(),
+                derived_feature.expression.clone(),
+                derived_feature.envelope,
+            )
+            .map_err(|e| {
+                tracing::error!("Invalid derived feature {}: {e}", derived_feature.name);
+                LoadControllerError::Invalid
+            })?;
+    }
+    Ok(())
+}
+
+fn add_connection(
+    controller: &mut Controller,
+    connection_factory: &ConnectionFactory,
+    connection_config: &DeviceConfig,
+) -> Result<(), LoadControllerError> {
+    let connection = connection_factory
+        .create(&connection_config.kind, &connection_config.connection)
+        .map_err(|e| {
+            tracing::error!("Couldn't create connection {}: {e}", connection_config.id);
+            LoadControllerError::Invalid
+        })?;
+    controller
+        .add_connection(
+            connection_config.id,
+            connection,
+            &connection_config.encoding,
+            connection_config.keep_alive,
+            connection_config.dithering,
+            DispatchDelay {
+                fixed: std::time::Duration::from_millis(connection_config.delay_ms as u64),
+                compensate: connection_config.compensate_latency,
+            },
+        )
+        .map_err(id_collision)?;
+    if let Some(path) = &connection_config.color_lut {
+        let color_lut = ColorLut::load(path).map_err(|e| {
+            tracing::error!(
+                "Couldn't load the color tables {} of connection {}: {e}",
+                path.display(),
+                connection_config.id
+            );
+            LoadControllerError::Invalid
+        })?;
+        controller.set_color_lut(connection_config.id, Some(color_lut));
+    }
+    Ok(())
+}
 
-    for effect_settings in config.effects.iter() {
-        match &effect_settings.effect {
-            EffectConfigType::Lua(file_name) => {
-                let effect_path = lua_effects_foler.as_ref().to_owned().join(file_name);
-                controller.add_lua_effect(effect_settings.effect_id, effect_path);
+fn add_settings(
+    controller: &mut Controller,
+    setting_config: &EffectSettingConfig,
+) -> Result<(), LoadControllerError> {
+    let settings = match &setting_config.setting {
+        SettingsConfigType::Lua(settings) => EffectSettings::Lua(LuaEffectSettings {
+            settings: settings.clone(),
+        }),
+        SettingsConfigType::Native => EffectSettings::Native(NativeEffectSettings {
+            seed: setting_config.seed,
+        }),
+    };
+    controller
+        .add_settings(setting_config.id, settings)
+        .map_err(id_collision)
+}
+
+fn add_effect(
+    controller: &mut Controller,
+    effect_config: &EffectConfig,
+    config: &TurboAudioConfig,
+) -> Result<(), LoadControllerError> {
+    match &effect_config.effect {
+        EffectConfigType::Lua(file_name) => {
+            let effect_path = config.lua_effects_folder.join(file_name);
+            controller
+                .add_lua_effect(effect_config.effect_id, effect_path)
+                .map_err(id_collision)?;
+        }
+        EffectConfigType::Rhai(file_name) => {
+            let effect_path = Path::new(RHAI_EFFECTS_FOLDER).join(file_name);
+            controller
+                .add_rhai_effect(effect_config.effect_id, effect_path)
+                .map_err(id_collision)?;
+        }
+        EffectConfigType::Native(file_name) => {
+            let effect_path = std::path::PathBuf::from(file_name);
+            controller
+                .add_native_effect(effect_config.effect_id, effect_path)
+                .map_err(id_collision)?;
+        }
+        EffectConfigType::Type(effect_type) => {
+            controller
+                .add_effect_of_type(
+                    effect_config.effect_id,
+                    effect_type,
+                    effect_config.settings_id,
+                )
+                .map_err(|e| {
+                    tracing::error!("Couldn't add effect {}: {e}", effect_config.effect_id);
+                    LoadControllerError::Invalid
+                })?;
+        }
+    }
+    link_effect(controller, effect_config, config)
+}
+
+// Links the effect to its settings, audio source and bindings
+fn link_effect(
+    controller: &mut Controller,
+    effect_config: &EffectConfig,
+    config: &TurboAudioConfig,
+) -> Result<(), LoadControllerError> {
+    if !controller.link_effect_to_settings(effect_config.effect_id, effect_config.settings_id) {
+        return Err(LoadControllerError::Invalid);
+    }
+    let audio_source = config.effect_audio_source(effect_config).map_err(|e| {
+        tracing::error!("{e}");
+        LoadControllerError::Invalid
+    })?;
+    controller
+        .set_effect_audio_source(effect_config.effect_id, audio_source)
+        .map_err(|e| {
+            tracing::error!("Audio source of effect {}: {e}", effect_config.effect_id);
+            LoadControllerError::Invalid
+        })?;
+    controller
+        .validate_effect_settings(effect_config.effect_id)
+        .map_err(|e| {
+            tracing::error!(
+                "Settings {} of effect {}: {e}",
+                effect_config.settings_id,
+                effect_config.effect_id
+            );
+            LoadControllerError::Invalid
+        })?;
+    controller
+        .add_parameter_bindings(effect_config.effect_id, &effect_config.bindings)
+        .map_err(|e| {
+            tracing::error!(
+                "Invalid bindings for effect {}: {e}",
+                effect_config.effect_id
+            );
+            LoadControllerError::Invalid
+        })
+}
+
+fn add_led_strip(
+    controller: &mut Controller,
+    ledstrip_config: &LedstripConfig,
+) -> Result<(), LoadControllerError> {
+    let ledstrip = ledstrip_config.build().map_err(|e| {
+        tracing::error!("Ledstrip {}: {e}", ledstrip_config.id);
+        LoadControllerError::Invalid
+    })?;
+    controller
+        .add_led_strip(ledstrip_config.id, ledstrip)
+        .map_err(id_collision)?;
+    controller.set_post_processing(
+        ledstrip_config.id,
+        PostProcessingChain::new(
+            &ledstrip_config.post_processing,
+            &ledstrip_config.calibration,
+        ),
+    );
+    if let Some(offset) = ledstrip_config.offset {
+        controller.set_led_strip_offset(ledstrip_config.id, offset);
+    }
+    link_led_strip(controller, ledstrip_config)
+}
+
+// Links the ledstrip to its connection and fallback
+fn link_led_strip(
+    controller: &mut Controller,
+    ledstrip_config: &LedstripConfig,
+) -> Result<(), LoadControllerError> {
+    if !controller.link_led_strip_to_connection(ledstrip_config.id, ledstrip_config.connection_id) {
+        return Err(LoadControllerError::Invalid);
+    }
+    if let Some(fallback_id) = ledstrip_config.fallback_connection_id {
+        if !controller.link_led_strip_to_fallback(ledstrip_config.id, fallback_id) {
+            tracing::error!(
+                "Fallback connection {fallback_id} of ledstrip {} doesn't exist",
+                ledstrip_config.id
+            );
+            return Err(LoadControllerError::Invalid);
+        }
+    }
+    Ok(())
+}
+
+struct AudioInput {
+    stream: AudioStream,
+    // Keeps the configured streams linked to the device
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    _pipewire_controller: PipewireController,
+}
+
+/// Input from the audio device, which is restarted in place when the device goes away and comes
+/// back, so that the effects keep running on silence in between. Another device can be switched
+/// to until the engine restarts
+struct LiveAudio {
+    source: AudioSourceConfig,
+    state: LiveAudioState,
+}
+
+enum LiveAudioState {
+    Running(AudioInput),
+    Waiting(AudioDeviceWatcher),
+}
+
+impl LiveAudio {
+    fn running(source: AudioSourceConfig, audio_input: AudioInput) -> Self {
+        Self {
+            source,
+            state: LiveAudioState::Running(audio_input),
+        }
+    }
+
+    fn waiting(source: AudioSourceConfig, config: &TurboAudioConfig) -> Self {
+        Self {
+            state: LiveAudioState::Waiting(AudioDeviceWatcher::new(
+                source.device_name.clone(),
+                source.audio_capture,
+                config.audio_backend,
+            )),
+            source,
+        }
+    }
+
+    fn is_waiting(&self) -> bool {
+        matches!(self.state, LiveAudioState::Waiting(_))
+    }
+
+    fn device(&self) -> &str {
+        self.source.device_name.as_deref().unwrap_or("default")
+    }
+
+    /// Switches to silence when the device went away and back to the device once it's available.
+    /// Returns whether the device is available when that changed
+    fn poll(
+        &mut self,
+        config: &TurboAudioConfig,
+        audio_processor: &mut AudioSignalProcessor,
+    ) -> Option<bool> {
+        match &self.state {
+            LiveAudioState::Running(audio_input) if audio_input.stream.is_lost() => {
+                tracing::warn!(
+                    "Lost the audio device {}, its effects won't react to the music until it's back",
+                    self.device()
+                );
+                *self = Self::waiting(self.source.clone(), config);
+                audio_processor.set_audio_rx(silent_audio_rx());
+                Some(false)
             }
-            EffectConfigType::Native(file_name) => {
-                let effect_path = std::path::PathBuf::from(file_name);
-                controller.add_native_effect(effect_settings.effect_id, effect_path);
+            LiveAudioState::Waiting(watcher) if watcher.is_available() => {
+                match start_live_audio(config, &self.source) {
+                    Ok((audio_input, audio_rx)) => {
+                        tracing::info!(
+                            "The audio device {} is available, listening to it again",
+                            self.device()
+                        );
+                        self.state = LiveAudioState::Running(audio_input);
+                        audio_processor.set_audio_rx(audio_rx);
+                        Some(true)
+                    }
+                    Err((_, e)) => {
+                        tracing::warn!(
+                            "Couldn't start the audio device {}, retrying: {e:#}",
+                            self.device()
+                        );
+                        *self = Self::waiting(self.source.clone(), config);
+                        None
+                    }
+                }
             }
+            _ => None,
         }
-        if !controller
-            .link_effect_to_settings(effect_settings.effect_id, effect_settings.settings_id)
-        {
-            return Err(LoadControllerError::Invalid);
+    }
+
+    /// Closes a stream that stopped giving samples. It's started again once the device is
+    /// available, like a lost device
+    fn restart(&mut self, config: &TurboAudioConfig, audio_processor: &mut AudioSignalProcessor) {
+        tracing::warn!(
+            "The audio device {} stopped giving samples, restarting it",
+            self.device()
+        );
+        *self = Self::waiting(self.source.clone(), config);
+        audio_processor.set_audio_rx(silent_audio_rx());
+    }
+
+    /// Listens to another device, or to the default input if `device_name` is missing. Goes back
+    /// to the current device if the other one can't be started
+    fn switch(
+        &mut self,
+        device_name: Option<String>,
+        config: &TurboAudioConfig,
+        audio_processor: &mut AudioSignalProcessor,
+    ) -> Result<(), String> {
+        // Some backends only open one stream per device, so the current one is closed first and
+        // reopened by the watcher if the switch fails
+        self.state = LiveAudioState::Waiting(AudioDeviceWatcher::new(
+            self.source.device_name.clone(),
+            self.source.audio_capture,
+            config.audio_backend,
+        ));
+        let source = AudioSourceConfig {
+            device_name,
+            ..self.source.clone()
+        };
+        match start_live_audio(config, &source) {
+            Ok((audio_input, audio_rx)) => {
+                *self = Self::running(source, audio_input);
+                tracing::info!("Switched to the audio device {}", self.device());
+                audio_processor.set_audio_rx(audio_rx);
+                Ok(())
+            }
+            Err((_, e)) => {
+                let error = format!("{e:#}");
+                tracing::warn!("Couldn't switch the audio device: {error}");
+                audio_processor.set_audio_rx(silent_audio_rx());
+                Err(error)
+            }
         }
     }
+}
 
-    for ledstrip_config in config.ledstrips.iter() {
-        let mut ledstrip = LedStrip::default();
-        ledstrip.set_led_count(ledstrip_config.size);
-        for effect in ledstrip_config.effects.iter() {
-            if !ledstrip.add_effect(effect.effect_id, effect.effect_size) {
-                return Err(LoadControllerError::Invalid);
+/// Audio source declared besides the main device, with its own fft. It stays silent while its
+/// device is missing instead of stopping the engine
+struct ExtraAudioSource {
+    name: String,
+    audio_processor: AudioSignalProcessor,
+    // Missing when the main audio comes from a file or a replay
+    live_audio: Option<LiveAudio>,
+}
+
+impl ExtraAudioSource {
+    fn new(source: &AudioSourceConfig, config: &TurboAudioConfig, listen: bool) -> Self {
+        let (live_audio, audio_rx) = if !listen {
+            (None, silent_audio_rx())
+        } else {
+            match start_live_audio(config, source) {
+                Ok((audio_input, audio_rx)) => (
+                    Some(LiveAudio::running(source.clone(), audio_input)),
+                    audio_rx,
+                ),
+                Err((_, e)) => {
+                    tracing::warn!(
+                        "Starting the audio source {} without audio until its device is available: {e:#}",
+                        source.name
+                    );
+                    (
+                        Some(LiveAudio::waiting(source.clone(), config)),
+                        silent_audio_rx(),
+                    )
+                }
             }
+        };
+        let mut audio_processor =
+            AudioSignalProcessor::new(audio_rx, config.sample_rate, config.fft);
+        audio_processor.set_weighting(&config.weighting);
+        Self {
+            name: source.name.clone(),
+            audio_processor,
+            live_audio,
         }
-        controller.add_led_strip(ledstrip_config.id, ledstrip);
-        if !controller
-            .link_led_strip_to_connection(ledstrip_config.id, ledstrip_config.connection_id)
-        {
-            return Err(LoadControllerError::Invalid);
+    }
+}
+
+// Stream that never has samples, for the effects to run on silence
+fn silent_audio_rx() -> HeapConsumer<f32> {
+    ringbuf::HeapRb::<f32>::new(1).split().1
+}
+
+/// Starts listening to the device of the audio source and routes its streams to it
+fn start_live_audio(
+    config: &TurboAudioConfig,
+    source: &AudioSourceConfig,
+) -> Result<(AudioInput, HeapConsumer<f32>), (RunLoopError, anyhow::Error)> {
+    tracing::info!("Starting audio loop.");
+    let (stream, audio_rx) = start_audio_loop(
+        source.device_name.clone(),
+        config.sample_rate,
+        source.audio_capture,
+        config.audio_backend,
+    )
+    .map_err(|e| (RunLoopError::StartAudioLoop, e))?;
+
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    {
+        tracing::info!("Creating pipewire listener.");
+        let pipewire_controller = PipewireController::new();
+        tracing::info!("Setting pipewire connections.");
+        pipewire_controller
+            .set_stream_connections(source.stream_connections.clone())
+            .map_err(|e| (RunLoopError::StartPipewireStream, e))?;
+        Ok((
+            AudioInput {
+                stream,
+                _pipewire_controller: pipewire_controller,
+            },
+            audio_rx,
+        ))
+    }
+    #[cfg(not(all(target_os = "linux", feature = "pipewire")))]
+    {
+        if !source.stream_connections.is_empty() {
+            tracing::warn!("Ignoring the stream connections, they need pipewire");
         }
+        Ok((AudioInput { stream }, audio_rx))
     }
+}
 
-    Ok(controller)
+fn load_config(
+    settings_file: &str,
+    format: ConfigFormat,
+    cache: Option<&Cache>,
+) -> anyhow::Result<TurboAudioConfig> {
+    let settings =
+        std::fs::read(settings_file).with_context(|| format!("Couldn't read {settings_file}"))?;
+    // The same bytes can be a valid file in several formats, which don't give the same config
+    let source = [format!("{format:?}\n").as_bytes(), &settings].concat();
+    if let Some(config) = cache.and_then(|cache| cache.get_serialized("config", &source)) {
+        tracing::info!("Using cached config.");
+        return Ok(config);
+    }
+
+    let text = std::str::from_utf8(&settings)
+        .with_context(|| format!("{settings_file} isn't valid utf-8"))?;
+    let parsed = parse_config(text, format).map_err(|e| match e {
+        ConfigError::Invalid { .. } => anyhow::anyhow!("{settings_file}:{e}"),
+        _ => anyhow::anyhow!("{settings_file}: {e}"),
+    })?;
+    if let Some(version) = parsed.migrated_from {
+        tracing::warn!(
+            "{settings_file} is a version {version} config, migrated to version {CONFIG_VERSION}. \
+             Apply these changes and set \"version\": {CONFIG_VERSION} to skip the migration:"
+        );
+        for change in &parsed.migration_changes {
+            tracing::warn!("  {change}");
+        }
+    }
+    // Migrated configs aren't cached so that the warning shows until the file is updated
+    if let Some(cache) = cache.filter(|_| parsed.migrated_from.is_none()) {
+        cache.put_serialized("config", &source, &parsed.config);
+    }
+    Ok(parsed.config)
+}
+
+/// Applies the changes of the config file to the running engine. Returns false if the engine
+/// has to restart to apply them
+fn reload_config(loaded: &mut LoadedConfig) -> bool {
+    let config = match load_config(loaded.settings_file, loaded.format, loaded.cache) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Keeping the current config: {e:#}");
+            return true;
+        }
+    };
+    if let (Some(session), Ok(settings)) =
+        (&mut loaded.session, std::fs::read(loaded.settings_file))
+    {
+        session.set_settings(&settings);
+    }
+    let diff = ConfigDiff::new(&loaded.config, &config);
+    if diff.is_empty() {
+        return true;
+    }
+    tracing::info!("Config changed: {diff}");
+    if diff.needs_restart() {
+        tracing::info!("Restarting.");
+        return false;
+    }
+    if let Err(e) = apply_config_diff(&mut loaded.controller, &diff, &config) {
+        tracing::error!("Couldn't apply the changes ({e:?}). Restarting.");
+        return false;
+    }
+    loaded.config = config;
+    true
 }
 
-fn main() -> Result<(), RunLoopError> {
-    env_logger::init();
+/// Checks the config, the audio and the connections, then loads the effects and the ledstrips
+/// like the engine does, but returns instead of running
+fn run_dry_run(
+    settings_file: &str,
+    format: ConfigFormat,
+    profile: Option<&str>,
+    listen: bool,
+) -> Result<(), RunLoopError> {
+    let config = load_config(settings_file, format, None).map_err(|e| {
+        tracing::error!("{e:#}");
+        RunLoopError::LoadConfigFile
+    })?;
+    let mut dry_run = DryRun::default();
+    dry_run.check_config(settings_file, &config);
+    dry_run.print_plan(&config);
+    if listen {
+        dry_run.check_audio(&config);
+    }
+    dry_run.check_connections(&config);
+
+    dry_run.section("Engine");
+    let audio_processor =
+        AudioSignalProcessor::new(silent_audio_rx(), config.sample_rate, config.fft);
+    let extra_sources: Vec<ExtraAudioSource> = config
+        .audio_sources
+        .iter()
+        .map(|source| ExtraAudioSource::new(source, &config, false))
+        .collect();
+    match load_controller(&config, &audio_processor, &extra_sources, None) {
+        Ok(mut controller) => {
+            dry_run.pass("The effects and the ledstrips are loaded");
+            if let Some(name) = profile {
+                match controller.apply_profile(name) {
+                    Ok(()) => dry_run.pass(format!("The profile {name} is applied")),
+                    Err(e) => dry_run.fail(format!("Couldn't apply the profile: {e}")),
+                }
+            }
+        }
+        Err(_) => dry_run.fail("Couldn't load the effects and the ledstrips, see the errors above"),
+    }
+    dry_run.finish().map_err(|e| {
+        tracing::error!("{e}");
+        RunLoopError::DryRun
+    })
+}
+
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            e.exit_code()
+        }
+    }
+}
+
+fn try_main() -> Result<(), RunLoopError> {
+    let Args {
+        command,
+        settings_file,
+        format,
+        cache_folder,
+        no_cache,
+        no_simd,
+        log_level,
+        log_format,
+        run,
+    } = Args::parse();
+    simd::set_enabled(!no_simd);
+    let format = format.unwrap_or_else(|| ConfigFormat::from_path(Path::new(&settings_file)));
+    let command = command.unwrap_or(Command::Run(run));
+    let tui = matches!(&command, Command::Run(run_args) if run_args.tui);
+    init_logging(&log_level, log_format, tui.then_some(TUI_LOG_FILE));
+
+    let RunArgs {
+        tui,
+        simulate,
+        record,
+        replay,
+        audio_file,
+        signal_gen,
+        play_through,
+        mut profile,
+        daemon,
+        on_exit,
+        fade_ms,
+        fps,
+        output_fps,
+        dry_run,
+    } = match command {
+        Command::Run(run_args) => run_args,
+        Command::CheckConfig => {
+            return check_config::run(&settings_file, format).map_err(|e| {
+                tracing::error!("{e}");
+                RunLoopError::LoadConfigFile
+            });
+        }
+        Command::GenerateConfig(generate_config_args) => {
+            return generate_config::run(&settings_file, format, &generate_config_args).map_err(
+                |e| {
+                    tracing::error!("{e}");
+                    RunLoopError::GenerateConfig
+                },
+            );
+        }
+        Command::ListDevices(list_devices_args) => {
+            return list_devices::run(&list_devices_args).map_err(|e| {
+                tracing::error!("{e}");
+                RunLoopError::Browse
+            });
+        }
+        Command::TestOutput(test_output_args) => {
+            let config = load_config(&settings_file, format, None).map_err(|e| {
+                tracing::error!("{e:#}");
+                RunLoopError::LoadConfigFile
+            })?;
+            return test_output::run(&config, &test_output_args).map_err(|e| {
+                tracing::error!("{e}");
+                RunLoopError::TestOutput
+            });
+        }
+        Command::Render(render_args) => {
+            return headless::run(&render_args).map_err(|e| {
+                tracing::error!("{e}");
+                RunLoopError::Render
+            });
+        }
+        #[cfg(unix)]
+        Command::Ctl(ctl_args) => {
+            return ctl::run(&ctl_args).map_err(|e| {
+                tracing::error!("{e}");
+                RunLoopError::Ctl
+            });
+        }
+        Command::Discover(discover_args) => {
+            return discovery::run(&discover_args).map_err(|e| {
+                tracing::error!("{e}");
+                RunLoopError::Discover
+            });
+        }
+        Command::Browse(browse_args) => {
+            return mdns::run(&browse_args).map_err(|e| {
+                tracing::error!("{e}");
+                RunLoopError::Browse
+            });
+        }
+        Command::Repl(repl_args) => {
+            return repl::run(&repl_args).map_err(|e| {
+                tracing::error!("{e}");
+                RunLoopError::Repl
+            });
+        }
+        Command::ImportWled(import_args) => {
+            return wled_import::run(&import_args).map_err(|e| {
+                tracing::error!("{e}");
+                RunLoopError::ImportWled
+            });
+        }
+    };
+    if dry_run {
+        let listen = replay.is_none() && audio_file.is_none() && signal_gen.is_none();
+        return run_dry_run(&settings_file, format, profile.as_deref(), listen);
+    }
+    info::START_TIME.get_or_init(std::time::Instant::now);
+    set_ticks_per_second(fps);
+    if let Some(output_fps) = output_fps {
+        let frames_per_tick = (output_fps / fps).max(1);
+        if output_fps % fps != 0 {
+            tracing::warn!(
+                "The output rate isn't a multiple of --fps, sending {} frames per second",
+                fps * frames_per_tick
+            );
+        }
+        set_frames_per_tick(frames_per_tick);
+    }
 
     ctrlc::set_handler(|| {
-        log::info!("Received ctrl-c, requesting to quit");
+        tracing::info!("Received ctrl-c, requesting to quit");
         SHOULD_QUIT.store(true, atomic::Ordering::Relaxed);
     })
     .expect("Couldn't set the CTRL-C handler");
+    #[cfg(unix)]
+    // SAFETY: storing to an atomic is async-signal-safe
+    if let Err(e) = unsafe {
+        signal_hook::low_level::register(SIGTERM, || {
+            SHOULD_QUIT.store(true, atomic::Ordering::Relaxed)
+        })
+    } {
+        tracing::error!("Couldn't listen to SIGTERM, it stops without --on-exit: {e}");
+    }
+    let service = ServiceOptions::new(daemon, on_exit, std::time::Duration::from_millis(fade_ms));
 
-    let Args { settings_file } = Args::parse();
+    let cache = (!no_cache).then(|| Cache::new(cache_folder));
 
-    loop {
-        log::info!("Parsing config.");
-        let config: TurboAudioConfig =
-            serde_json::from_reader(&File::open(settings_file.clone()).unwrap()).unwrap();
-        log::info!("Starting audio loop.");
-        let (_stream, audio_rx) = start_audio_loop(config.device_name.clone(), config.sample_rate)
-            .map_err(|e| {
-                log::error!("{:?}", e);
-                RunLoopError::StartAudioLoop
-            })?;
+    let mut recorder = record.and_then(|path| {
+        FeatureRecorder::new(&path, ticks_per_second())
+            .map_err(|e| tracing::error!("Couldn't record to {}: {e}", path.display()))
+            .ok()
+    });
+    let mut replay = match replay {
+        Some(path) => Some(FeatureReplay::open(&path, ticks_per_second()).map_err(|e| {
+            tracing::error!("Couldn't replay {}: {e}", path.display());
+            RunLoopError::StartAudioLoop
+        })?),
+        None => None,
+    };
 
-        log::info!("Creating pipewire listener.");
-        let pipewire_controller = PipewireController::new();
-        log::info!("Setting pipewire connections.");
-        pipewire_controller
-            .set_stream_connections(config.stream_connections.clone())
-            .map_err(|e| {
-                log::error!("{:?}", e);
-                RunLoopError::StartPipewireStream
-            })?;
+    #[cfg(feature = "tui")]
+    let mut dashboard = tui
+        .then(|| {
+            tui::Dashboard::new()
+                .map_err(|e| tracing::error!("Couldn't start the dashboard: {e}"))
+                .ok()
+        })
+        .flatten();
+    #[cfg(not(feature = "tui"))]
+    if tui {
+        tracing::warn!("The dashboard was requested but turbo_audio was built without tui support");
+    }
 
-        log::info!("Creating audio processor.");
-        let fft_buffer_size: usize = 1024;
-        let audio_processor =
-            AudioSignalProcessor::new(audio_rx, config.sample_rate, fft_buffer_size);
+    #[cfg(all(not(target_os = "macos"), feature = "simulator"))]
+    let simulator = simulate
+        .then(|| {
+            Simulator::new()
+                .map_err(|e| tracing::error!("Couldn't open the simulator: {e}"))
+                .ok()
+        })
+        .flatten();
+    #[cfg(not(all(not(target_os = "macos"), feature = "simulator")))]
+    if simulate {
+        tracing::warn!(
+            "The simulator was requested but turbo_audio was built without simulator support"
+        );
+    }
 
-        log::info!("Loading config into controller.");
-        let controller = load_controller(&config, &audio_processor, &config.lua_effects_folder)
-            .map_err(|e| {
-                log::error!("{:?}", e);
+    let reload_requested = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    if let Err(e) = signal_hook::flag::register(SIGHUP, reload_requested.clone()) {
+        tracing::error!("Couldn't listen to SIGHUP, the config is only reloaded on change: {e}");
+    }
+
+    let mut metrics = MetricsHistory::default();
+    let mut had_live_audio = false;
+    loop {
+        let _span = tracing::info_span!("config", file = %settings_file).entered();
+        tracing::info!("Parsing config.");
+        let config = load_config(&settings_file, format, cache.as_ref()).map_err(|e| {
+            tracing::error!("{e:#}");
+            RunLoopError::LoadConfigFile
+        })?;
+        let mut sample_rate = config.sample_rate;
+        let (mut live_audio, _file_playback, audio_rx) = if replay.is_some() {
+            tracing::info!("Replaying recorded audio features, the audio device isn't used.");
+            (None, None, silent_audio_rx())
+        } else if let Some(audio_file) = &audio_file {
+            let (playback, audio_rx, file_sample_rate) =
+                FilePlayback::start(audio_file, play_through).map_err(|e| {
+                    tracing::error!("Couldn't play {}: {e}", audio_file.display());
+                    RunLoopError::StartAudioLoop
+                })?;
+            sample_rate = file_sample_rate;
+            (None, Some(playback), audio_rx)
+        } else if let Some(signal) = signal_gen {
+            let (playback, audio_rx) = FilePlayback::generate(signal, sample_rate, play_through);
+            (None, Some(playback), audio_rx)
+        } else {
+            match start_live_audio(&config, &config.main_audio_source()) {
+                Ok((audio_input, audio_rx)) => {
+                    had_live_audio = true;
+                    (
+                        Some(LiveAudio::running(config.main_audio_source(), audio_input)),
+                        None,
+                        audio_rx,
+                    )
+                }
+                // Once the engine ran with the device, losing it doesn't stop it anymore
+                Err((_, e))
+                    if config.missing_audio == MissingAudioBehavior::NoAudio || had_live_audio =>
+                {
+                    tracing::warn!(
+                        "Starting without audio, the effects won't react to the music until the audio device is available: {e:#}"
+                    );
+                    (
+                        Some(LiveAudio::waiting(config.main_audio_source(), &config)),
+                        None,
+                        silent_audio_rx(),
+                    )
+                }
+                Err((error, e)) => {
+                    tracing::error!("{:?}", e);
+                    return Err(error);
+                }
+            }
+        };
+
+        tracing::info!("Creating audio processor.");
+        let mut audio_processor = AudioSignalProcessor::new(audio_rx, sample_rate, config.fft);
+        audio_processor.set_noise_gate(config.noise_gate.clone());
+        audio_processor.set_weighting(&config.weighting);
+        let listen = replay.is_none() && audio_file.is_none() && signal_gen.is_none();
+        let mut extra_sources: Vec<ExtraAudioSource> = config
+            .audio_sources
+            .iter()
+            .map(|source| ExtraAudioSource::new(source, &config, listen))
+            .collect();
+
+        tracing::info!("Loading config into controller.");
+        let mut controller =
+            load_controller(&config, &audio_processor, &extra_sources, cache.clone()).map_err(
+                |e| {
+                    tracing::error!("{:?}", e);
+                    RunLoopError::LoadConfigFile
+                },
+            )?;
+        controller.set_audio_available(!live_audio.as_ref().is_some_and(LiveAudio::is_waiting));
+        let mut session = config.session.clone().map(|session_config| {
+            Session::new(
+                session_config,
+                &std::fs::read(&settings_file).unwrap_or_default(),
+            )
+        });
+        match session.as_mut().map(Session::load) {
+            Some(Ok(Some(saved))) => controller.restore_session(&saved),
+            Some(Err(e)) => tracing::warn!("Couldn't restore the session: {e}"),
+            Some(Ok(None)) | None => {}
+        }
+        if let Some(name) = &profile {
+            controller.apply_profile(name).map_err(|e| {
+                tracing::error!("Couldn't apply the profile: {e}");
                 RunLoopError::LoadConfigFile
             })?;
+        }
+
+        let (control_tx, control_rx) = control::channel();
+        let _osc_server = config.osc.as_ref().and_then(|osc_config| {
+            OscServer::new(osc_config.address, control_tx.clone())
+                .map_err(|e| tracing::error!("Couldn't start the OSC server: {e}"))
+                .ok()
+        });
+
+        let _http_server = config.http.as_ref().and_then(|http_config| {
+            HttpServer::new(
+                http_config.address,
+                http_config.write_token.clone(),
+                http_config.capture_dir.clone(),
+                control_tx.clone(),
+            )
+            .map_err(|e| tracing::error!("Couldn't start the http server: {e}"))
+            .ok()
+        });
+
+        #[cfg(feature = "grpc")]
+        let _grpc_server = config.grpc.as_ref().and_then(|grpc_config| {
+            control::grpc::GrpcServer::new(grpc_config.address, control_tx.clone())
+                .map_err(|e| tracing::error!("Couldn't start the gRPC server: {e}"))
+                .ok()
+        });
+        #[cfg(not(feature = "grpc"))]
+        if config.grpc.is_some() {
+            tracing::warn!(
+                "A gRPC config is present but turbo_audio was built without gRPC support"
+            );
+        }
+
+        #[cfg(unix)]
+        let _control_socket = config.control_socket.as_ref().and_then(|socket_config| {
+            control::socket::ControlSocket::new(&socket_config.path, control_tx.clone())
+                .map_err(|e| tracing::error!("Couldn't start the control socket: {e}"))
+                .ok()
+        });
+        #[cfg(not(unix))]
+        if config.control_socket.is_some() {
+            tracing::warn!("The control socket is only available on unix");
+        }
+
+        #[cfg(feature = "midi")]
+        let _midi_listener = config.midi.as_ref().and_then(|midi_config| {
+            control::midi::MidiListener::new(midi_config, control_tx.clone())
+                .map_err(|e| tracing::error!("Couldn't start the midi listener: {e}"))
+                .ok()
+        });
+        #[cfg(not(feature = "midi"))]
+        if config.midi.is_some() {
+            tracing::warn!(
+                "A midi config is present but turbo_audio was built without midi support"
+            );
+        }
+
+        #[cfg(all(target_os = "linux", feature = "hotkeys"))]
+        let _hotkey_listener = config.hotkeys.as_ref().and_then(|hotkeys_config| {
+            control::hotkeys::HotkeyListener::new(hotkeys_config, control_tx.clone())
+                .map_err(|e| tracing::error!("Couldn't start the hotkey listener: {e}"))
+                .ok()
+        });
+        #[cfg(not(all(target_os = "linux", feature = "hotkeys")))]
+        if config.hotkeys.is_some() {
+            tracing::warn!(
+                "Hotkeys are configured but turbo_audio was built without hotkeys support"
+            );
+        }
+
+        #[cfg(feature = "screen")]
+        let _screen_capture = config.screen_capture.as_ref().and_then(|screen_config| {
+            turbo_audio::screen::ScreenCapture::new(screen_config, controller.screen_colors())
+                .map_err(|e| tracing::error!("Couldn't start the screen capture: {e}"))
+                .ok()
+        });
+        #[cfg(not(feature = "screen"))]
+        if config.screen_capture.is_some() {
+            tracing::warn!(
+                "The screen capture needs turbo_audio to be built with the screen feature"
+            );
+        }
+
+        #[cfg(feature = "jack")]
+        let _jack_transport = config.jack_transport.as_ref().and_then(|jack_config| {
+            turbo_audio::jack_transport::JackTransport::new(jack_config, controller.transport())
+                .map_err(|e| tracing::error!("Couldn't follow the JACK transport: {e}"))
+                .ok()
+        });
+        #[cfg(not(feature = "jack"))]
+        if config.jack_transport.is_some() {
+            tracing::warn!(
+                "Following the JACK transport needs turbo_audio to be built with the jack feature"
+            );
+        }
+
+        // Both would store their position for the controller
+        let ableton_link = match (&config.ableton_link, &config.jack_transport) {
+            (Some(_), Some(_)) => {
+                tracing::warn!("Following the JACK transport rather than the Ableton Link session");
+                None
+            }
+            (ableton_link, _) => ableton_link.as_ref(),
+        };
+        #[cfg(feature = "link")]
+        let _ableton_link = ableton_link.map(|link_config| {
+            turbo_audio::ableton_link::AbletonLink::new(link_config, controller.transport())
+        });
+        #[cfg(not(feature = "link"))]
+        if ableton_link.is_some() {
+            tracing::warn!(
+                "Following the Ableton Link session needs turbo_audio to be built with the link \
+                 feature"
+            );
+        }
+
+        #[cfg(feature = "mpris")]
+        let _now_playing = config.now_playing.as_ref().and_then(|now_playing_config| {
+            turbo_audio::now_playing::NowPlayingWatcher::new(now_playing_config)
+                .map_err(|e| tracing::error!("Couldn't follow the media player: {e}"))
+                .ok()
+        });
+        #[cfg(not(feature = "mpris"))]
+        if config.now_playing.is_some() {
+            tracing::warn!(
+                "Following the media player needs turbo_audio to be built with the mpris feature"
+            );
+        }
+
+        #[cfg(feature = "mqtt")]
+        let _mqtt_client = config.mqtt.as_ref().map(|mqtt_config| {
+            control::mqtt::MqttClient::new(
+                mqtt_config,
+                config
+                    .ledstrips
+                    .iter()
+                    .map(|ledstrip| ledstrip.id)
+                    .collect(),
+                config
+                    .effects
+                    .iter()
+                    .map(|effect| effect.effect_id)
+                    .collect(),
+                control_tx.clone(),
+            )
+        });
+        #[cfg(not(feature = "mqtt"))]
+        if config.mqtt.is_some() {
+            tracing::warn!(
+                "A mqtt config is present but turbo_audio was built without mqtt support"
+            );
+        }
 
-        log::info!("Starting run loop.");
-        run_loop(audio_processor, controller)?;
+        tracing::info!("Starting run loop.");
+        run_loop(
+            audio_processor,
+            LoadedConfig {
+                controller,
+                config,
+                settings_file: &settings_file,
+                format,
+                cache: cache.as_ref(),
+                reload_requested: &reload_requested,
+                profile: &mut profile,
+                service: &service,
+                session,
+            },
+            control_rx,
+            AudioFeatureSources {
+                recorder: recorder.as_mut(),
+                replay: replay.as_mut(),
+                live_audio: live_audio.as_mut(),
+                extra_sources: &mut extra_sources,
+            },
+            &mut metrics,
+            #[cfg(feature = "tui")]
+            dashboard.as_mut(),
+            #[cfg(all(not(target_os = "macos"), feature = "simulator"))]
+            simulator.as_ref(),
+        )?;
         if SHOULD_QUIT.load(atomic::Ordering::Relaxed) {
-            log::info!("Quitting");
+            tracing::info!("Quitting");
             break Ok(());
         }
         std::thread::sleep(std::time::Duration::from_millis(100));
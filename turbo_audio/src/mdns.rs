@@ -0,0 +1,169 @@
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use std::{
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// Service types advertised by the controllers we can stream to, with the name shown for them
+const SERVICE_TYPES: [(&str, &str); 2] = [
+    ("_wled._tcp.local.", "WLED"),
+    ("_esphomelib._tcp.local.", "ESPHome"),
+];
+
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Error, Debug)]
+pub enum MdnsError {
+    #[error("mDNS error: {0}")]
+    Mdns(#[from] mdns_sd::Error),
+
+    #[error("Invalid address {0}, expected host:port")]
+    InvalidAddress(String),
+
+    #[error("Couldn't find {0} on the network")]
+    NotFound(String),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct BrowseArgs {
+    /// Seconds spent listening for the controllers
+    #[arg(long, default_value_t = 3)]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredService {
+    pub kind: &'static str,
+    /// Instance name of the service, like `WLED-Kitchen`
+    pub name: String,
+    /// Hostname without the trailing dot, like `wled-kitchen.local`
+    pub hostname: String,
+    /// Ipv4 addresses first
+    pub addresses: Vec<IpAddr>,
+}
+
+impl DiscoveredService {
+    /// Whether `host` is the instance name or the hostname (with or without `.local`) of the
+    /// service
+    fn matches(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        self.name.eq_ignore_ascii_case(host)
+            || self.hostname.eq_ignore_ascii_case(host)
+            || self
+                .hostname
+                .strip_suffix(".local")
+                .is_some_and(|hostname| hostname.eq_ignore_ascii_case(host))
+    }
+}
+
+/// Lists the controllers advertised on the local network during `timeout`
+pub fn browse(timeout: Duration) -> Result<Vec<DiscoveredService>, MdnsError> {
+    browse_until(timeout, |_| false)
+}
+
+/// Browses for `timeout`, or until `found` returns true for one of the services
+fn browse_until(
+    timeout: Duration,
+    mut found: impl FnMut(&DiscoveredService) -> bool,
+) -> Result<Vec<DiscoveredService>, MdnsError> {
+    let daemon = ServiceDaemon::new()?;
+    let receivers = SERVICE_TYPES
+        .iter()
+        .map(|(service_type, kind)| Ok((daemon.browse(service_type)?, *service_type, *kind)))
+        .collect::<Result<Vec<_>, mdns_sd::Error>>()?;
+
+    let deadline = Instant::now() + timeout;
+    let mut services: Vec<DiscoveredService> = Vec::new();
+    'browse: while Instant::now() < deadline {
+        for (receiver, service_type, kind) in &receivers {
+            while let Ok(event) = receiver.try_recv() {
+                let ServiceEvent::ServiceResolved(info) = event else {
+                    continue;
+                };
+                let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+                addresses.sort_by_key(|address| (address.is_ipv6(), *address));
+                let service = DiscoveredService {
+                    kind,
+                    name: info
+                        .get_fullname()
+                        .strip_suffix(service_type)
+                        .unwrap_or(info.get_fullname())
+                        .trim_end_matches('.')
+                        .to_owned(),
+                    hostname: info.get_hostname().trim_end_matches('.').to_owned(),
+                    addresses,
+                };
+                let done = found(&service);
+                // A service is resolved again when its records are refreshed
+                services.retain(|known| known.kind != *kind || known.name != service.name);
+                services.push(service);
+                if done {
+                    break 'browse;
+                }
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    if let Err(e) = daemon.shutdown() {
+        tracing::warn!("Couldn't stop the mDNS daemon: {e}");
+    }
+    Ok(services)
+}
+
+/// Resolves a `host:port` address whose host is an ip, a hostname, or the name of a controller
+/// advertised over mDNS. mDNS is only browsed when the system resolver doesn't know the host,
+/// since it doesn't resolve `.local` hostnames on systems without avahi.
+pub fn resolve(address: &str) -> Result<SocketAddr, MdnsError> {
+    if let Some(address) = address
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+    {
+        return Ok(address);
+    }
+
+    let (host, port) = address
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        .ok_or_else(|| MdnsError::InvalidAddress(address.to_owned()))?;
+    tracing::info!("Looking for {host} over mDNS");
+    let services = browse_until(RESOLVE_TIMEOUT, |service| {
+        service.matches(host) && !service.addresses.is_empty()
+    })?;
+    let ip = services
+        .iter()
+        .filter(|service| service.matches(host))
+        .find_map(|service| service.addresses.first())
+        .ok_or_else(|| MdnsError::NotFound(host.to_owned()))?;
+    tracing::info!("Found {host} at {ip}");
+    Ok(SocketAddr::new(*ip, port))
+}
+
+/// Prints the controllers found on the local network, with the names the config can use for them
+pub fn run(args: &BrowseArgs) -> Result<(), MdnsError> {
+    tracing::info!("Browsing the network for {}s", args.timeout);
+    let mut services = browse(Duration::from_secs(args.timeout))?;
+    if services.is_empty() {
+        println!("No controller found");
+        return Ok(());
+    }
+
+    services.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+    for service in services {
+        let addresses = service
+            .addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!(
+            "{:<8} {:<24} {:<28} {addresses}",
+            service.kind, service.name, service.hostname
+        );
+    }
+    Ok(())
+}
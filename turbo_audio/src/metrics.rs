@@ -0,0 +1,118 @@
+use crate::{
+    audio::audio_processing::FftResult, controller::Controller, info::START_TIME,
+    parameter_mapping::AudioFeatures,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+// 10 minutes of samples
+const HISTORY_LEN: usize = 600;
+
+/// Metrics of the engine over one second
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSample {
+    /// Seconds since the process started, at the end of the second
+    pub uptime_secs: u64,
+    /// Ticks run during the second
+    pub fps: u32,
+    /// Average time spent working on a tick
+    pub tick_time_ms: f32,
+    /// Longest time spent working on a tick. Above the duration of a tick the lights stuttered
+    pub max_tick_time_ms: f32,
+    /// Average amplitudes of the audio features
+    pub bass: f32,
+    pub mids: f32,
+    pub treble: f32,
+    pub volume: f32,
+    /// Connection id to its send latency, for the connections that sent a frame
+    pub connection_latency_ms: BTreeMap<usize, f32>,
+    /// Effect id to the time it takes to render a frame
+    pub effect_render_time_ms: BTreeMap<usize, f32>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    ticks: u32,
+    total_work: Duration,
+    max_work: Duration,
+    audio: AudioFeatures,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            ticks: 0,
+            total_work: Duration::ZERO,
+            max_work: Duration::ZERO,
+            audio: AudioFeatures::default(),
+        }
+    }
+}
+
+/// Short in-memory history of the engine metrics, sampled every second, to see what happened
+/// right before a stutter. It's kept across config reloads.
+#[derive(Debug, Default)]
+pub struct MetricsHistory {
+    samples: VecDeque<MetricsSample>,
+    window: Window,
+}
+
+impl MetricsHistory {
+    /// Accounts for a tick that took `work` to run, and adds a sample once a second went by
+    pub fn record_tick(&mut self, work: Duration, fft_result: &FftResult, controller: &Controller) {
+        let window = &mut self.window;
+        window.ticks += 1;
+        window.total_work += work;
+        window.max_work = window.max_work.max(work);
+        let audio = AudioFeatures::new(fft_result, 0.0);
+        window.audio.bass += audio.bass;
+        window.audio.mids += audio.mids;
+        window.audio.treble += audio.treble;
+        window.audio.volume += audio.volume;
+
+        if window.start.elapsed() < SAMPLE_INTERVAL {
+            return;
+        }
+
+        let ticks = window.ticks as f32;
+        let sample = MetricsSample {
+            uptime_secs: START_TIME
+                .get()
+                .map(|start_time| start_time.elapsed().as_secs())
+                .unwrap_or_default(),
+            fps: (ticks / window.start.elapsed().as_secs_f32()).round() as u32,
+            tick_time_ms: window.total_work.as_secs_f32() * 1000.0 / ticks,
+            max_tick_time_ms: window.max_work.as_secs_f32() * 1000.0,
+            bass: window.audio.bass / ticks,
+            mids: window.audio.mids / ticks,
+            treble: window.audio.treble / ticks,
+            volume: window.audio.volume / ticks,
+            connection_latency_ms: controller
+                .connection_info()
+                .into_iter()
+                .filter_map(|connection| Some((connection.id, connection.latency_ms?)))
+                .collect(),
+            effect_render_time_ms: controller
+                .effect_info()
+                .into_iter()
+                .map(|effect| (effect.id, effect.render_time_ms))
+                .collect(),
+        };
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        self.window = Window::default();
+    }
+
+    /// Samples from the oldest to the newest
+    pub fn samples(&self) -> Vec<MetricsSample> {
+        self.samples.iter().cloned().collect()
+    }
+}
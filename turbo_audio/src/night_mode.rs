@@ -0,0 +1,123 @@
+use crate::{post_processing, schedule::TimeOfDay};
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+// Color temperature of the white of the effects, close to the white of sRGB
+const NEUTRAL_WHITE: f32 = 6500.0;
+
+/// Warmer and dimmer lights for the late hours, on top of every effect: the white is shifted to
+/// a color temperature and the global brightness is capped. It's on during its `hours`, and the
+/// control apis turn it on or off until they next start or end.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct NightModeConfig {
+    /// Color temperature in kelvin of the white, like 2700 for the warm white of a bulb
+    #[serde(deserialize_with = "crate::config_parser::color_temperature")]
+    pub color_temperature: f32,
+    /// Highest global brightness, between 0 and 1
+    #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+    pub max_brightness: f32,
+    /// Local times it's on between. Only turned on through the control apis if missing
+    pub hours: Option<NightHours>,
+}
+
+impl Default for NightModeConfig {
+    fn default() -> Self {
+        Self {
+            color_temperature: 2700.0,
+            max_brightness: 0.3,
+            hours: None,
+        }
+    }
+}
+
+/// Hours between two local times, across midnight when `end` is before `start`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NightHours {
+    /// Like `22:00`
+    pub start: TimeOfDay,
+    /// Like `07:00`
+    pub end: TimeOfDay,
+}
+
+impl NightHours {
+    fn contains(&self, time: NaiveTime) -> bool {
+        let (start, end) = (self.start.0, self.end.0);
+        if start <= end {
+            start <= time && time < end
+        } else {
+            start <= time || time < end
+        }
+    }
+}
+
+/// Tells whether the night mode is on, following its hours and the control apis
+#[derive(Debug)]
+pub struct NightMode {
+    config: NightModeConfig,
+    gains: [f32; 3],
+    // Whether the local time was in the hours at the last update. None before the first one
+    in_hours: Option<bool>,
+    // Set by the control apis, until the hours next start or end
+    forced: Option<bool>,
+}
+
+impl NightMode {
+    pub fn new(config: NightModeConfig) -> Self {
+        Self {
+            config,
+            gains: post_processing::white_shift(NEUTRAL_WHITE, config.color_temperature),
+            in_hours: None,
+            forced: None,
+        }
+    }
+
+    pub fn config(&self) -> NightModeConfig {
+        self.config
+    }
+
+    /// Follows the local time, returns whether the night mode turned on or off
+    pub fn update(&mut self, now: NaiveTime) -> bool {
+        let was_on = self.is_on();
+        let in_hours = self.config.hours.is_some_and(|hours| hours.contains(now));
+        if self
+            .in_hours
+            .is_some_and(|was_in_hours| was_in_hours != in_hours)
+        {
+            self.forced = None;
+        }
+        self.in_hours = Some(in_hours);
+        was_on != self.is_on()
+    }
+
+    /// Turns it on or off until the hours next start or end
+    pub fn set_on(&mut self, on: bool) {
+        self.forced = Some(on);
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.forced.unwrap_or(self.in_hours == Some(true))
+    }
+
+    /// Caps the global brightness while it's on
+    pub fn brightness(&self, brightness: f32) -> f32 {
+        match self.is_on() {
+            true => brightness.min(self.config.max_brightness),
+            false => brightness,
+        }
+    }
+
+    /// Shifts the white of a 16-bit rgb frame while it's on
+    pub fn apply(&self, channels: &mut [u16]) {
+        if !self.is_on() {
+            return;
+        }
+        for pixel in channels.chunks_exact_mut(3) {
+            for (channel, gain) in pixel.iter_mut().zip(self.gains) {
+                *channel = (*channel as f32 * gain).round() as u16;
+            }
+        }
+    }
+}
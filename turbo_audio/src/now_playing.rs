@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// Follows the track of a desktop media player over MPRIS, their D-Bus interface, for the
+/// effects changing with the track or stopping with the music. Needs turbo_audio to be built
+/// with the `mpris` feature
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct NowPlayingConfig {
+    /// Name of the player to follow, like `Spotify` or `mpv`, whatever the case. The one playing,
+    /// or else the one paused, if missing
+    pub player: Option<String>,
+    /// Milliseconds between two reads of the player
+    pub poll_ms: u64,
+}
+
+impl Default for NowPlayingConfig {
+    fn default() -> Self {
+        Self {
+            player: None,
+            poll_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PlaybackStatus {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+/// Track of the player followed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NowPlaying {
+    /// Name of the player, like `Spotify`
+    pub player: String,
+    /// Empty when the player doesn't tell, like the artist and the album
+    pub title: String,
+    /// Artists of the track, separated by commas
+    pub artist: String,
+    pub album: String,
+    pub status: PlaybackStatus,
+}
+
+static NOW_PLAYING: Mutex<Option<NowPlaying>> = Mutex::new(None);
+
+/// Track of the player followed. None while no player is found, or when nothing follows one
+pub fn now_playing() -> Option<NowPlaying> {
+    NOW_PLAYING.lock().unwrap().clone()
+}
+
+#[cfg(feature = "mpris")]
+pub use watcher::{NowPlayingError, NowPlayingWatcher};
+
+#[cfg(feature = "mpris")]
+mod watcher {
+    use super::{NowPlaying, NowPlayingConfig, PlaybackStatus, NOW_PLAYING};
+    use mpris::{DBusError, PlayerFinder};
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Arc,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+    use thiserror::Error;
+
+    #[derive(Error, Debug)]
+    pub enum NowPlayingError {
+        #[error("Couldn't connect to the D-Bus session: {0}")]
+        DBus(#[from] DBusError),
+    }
+
+    /// Reads the player on its own thread, storing its track for the effects. It's cleared once
+    /// the watcher stops
+    pub struct NowPlayingWatcher {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl NowPlayingWatcher {
+        pub fn new(config: &NowPlayingConfig) -> Result<Self, NowPlayingError> {
+            let period = Duration::from_millis(config.poll_ms.max(1));
+            let player = config.player.clone();
+            let stop: Arc<AtomicBool> = Arc::default();
+            // The connection to D-Bus can't be moved to another thread, it's opened on the one
+            // of the watcher which tells whether it succeeded
+            let (connected_tx, connected_rx) = mpsc::sync_channel(1);
+            let thread = {
+                let stop = stop.clone();
+                thread::Builder::new()
+                    .name("now-playing".to_owned())
+                    .spawn(move || {
+                        let finder = match PlayerFinder::new() {
+                            Ok(finder) => {
+                                let _ = connected_tx.send(Ok(()));
+                                finder
+                            }
+                            Err(e) => {
+                                let _ = connected_tx.send(Err(e));
+                                return;
+                            }
+                        };
+                        while !stop.load(Ordering::Relaxed) {
+                            set_now_playing(read_player(&finder, player.as_deref()));
+                            thread::sleep(period);
+                        }
+                        set_now_playing(None);
+                    })
+                    .expect("Couldn't start the now playing thread")
+            };
+            if let Ok(Err(e)) = connected_rx.recv() {
+                let _ = thread.join();
+                return Err(e.into());
+            }
+            match &config.player {
+                Some(player) => tracing::info!("Following the tracks played by {player}"),
+                None => tracing::info!("Following the tracks played by the active player"),
+            }
+            Ok(Self {
+                stop,
+                thread: Some(thread),
+            })
+        }
+    }
+
+    impl Drop for NowPlayingWatcher {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                if thread.join().is_err() {
+                    tracing::error!("The now playing thread panicked");
+                }
+            }
+        }
+    }
+
+    fn set_now_playing(now_playing: Option<NowPlaying>) {
+        let mut current = NOW_PLAYING.lock().unwrap();
+        if *current != now_playing {
+            tracing::debug!("Now playing: {now_playing:?}");
+            *current = now_playing;
+        }
+    }
+
+    // Track of the player named `name`, or of the active one. None if there's no such player
+    fn read_player(finder: &PlayerFinder, name: Option<&str>) -> Option<NowPlaying> {
+        let player = match name {
+            Some(name) => finder.find_by_name(name),
+            None => finder.find_active(),
+        }
+        .ok()?;
+        let metadata = player.get_metadata().ok()?;
+        let status = match player.get_playback_status().ok()? {
+            mpris::PlaybackStatus::Playing => PlaybackStatus::Playing,
+            mpris::PlaybackStatus::Paused => PlaybackStatus::Paused,
+            mpris::PlaybackStatus::Stopped => PlaybackStatus::Stopped,
+        };
+        Some(NowPlaying {
+            player: player.identity().to_owned(),
+            title: metadata.title().unwrap_or_default().to_owned(),
+            artist: metadata
+                .artists()
+                .map(|artists| artists.join(", "))
+                .unwrap_or_default(),
+            album: metadata.album_name().unwrap_or_default().to_owned(),
+            status,
+        })
+    }
+}
@@ -0,0 +1,129 @@
+use crate::resources::ledstrip::{resample, GaussianBlur, Pixel, SegmentLayout, MAX_PIXELS};
+use serde::{Deserialize, Serialize};
+
+/// A stage turning the pixels the effects render on into the leds of a ledstrip, as written in
+/// the config of the ledstrip
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum OutputStage {
+    /// Gaussian blur reaching `radius` pixels on each side. Unlike the blur of a segment, it
+    /// blends the segments where they meet
+    Blur {
+        #[serde(deserialize_with = "crate::config_parser::non_negative")]
+        radius: f32,
+    },
+    /// The pixels are laid on half of the leds and mirrored, the first one being in the center
+    Mirror,
+    /// Every pixel lights `leds` leds in a row, for dense strips the effects would look thin on
+    Group {
+        #[serde(deserialize_with = "crate::config_parser::count")]
+        leds: usize,
+    },
+    /// Every led shows the average of `pixels` pixels, for effects rendered finer than the leds
+    Downsample {
+        #[serde(deserialize_with = "crate::config_parser::count")]
+        pixels: usize,
+    },
+}
+
+impl OutputStage {
+    // Number of pixels the stage takes for `output` pixels out
+    fn input_size(&self, output: usize) -> usize {
+        match *self {
+            OutputStage::Blur { .. } => output,
+            OutputStage::Mirror => output.div_ceil(2),
+            OutputStage::Group { leds } => output.div_ceil(leds.max(1)),
+            OutputStage::Downsample { pixels } => output.saturating_mul(pixels.max(1)),
+        }
+    }
+}
+
+/// Stages of a ledstrip applied in order to the pixels of its effects, so that they render at a
+/// resolution of their own whatever the density of the leds. The effects render on
+/// [`OutputTransform::pixel_count`] pixels, which the stages turn into the leds of the strip.
+#[derive(Debug, Clone)]
+pub struct OutputTransform {
+    stages: Vec<OutputStage>,
+    // Blur of each stage that blurs
+    blurs: Vec<Option<GaussianBlur>>,
+    // Number of pixels before each stage, then of leds after the last one
+    sizes: Vec<usize>,
+    pixels: Vec<Pixel>,
+    scratch: Vec<Pixel>,
+}
+
+impl OutputTransform {
+    /// Transform of the pixels of the effects into `led_count` leds, or None if a stage would
+    /// need more than [`MAX_PIXELS`]
+    pub fn new(stages: &[OutputStage], led_count: usize) -> Option<Self> {
+        let mut sizes = vec![led_count];
+        for stage in stages.iter().rev() {
+            sizes.push(stage.input_size(*sizes.last().unwrap()));
+        }
+        if sizes.iter().any(|size| *size > MAX_PIXELS) {
+            return None;
+        }
+        sizes.reverse();
+        let blurs = stages
+            .iter()
+            .zip(&sizes)
+            .map(|(stage, size)| match *stage {
+                OutputStage::Blur { radius } => GaussianBlur::new(radius, *size),
+                _ => None,
+            })
+            .collect();
+        Some(Self {
+            stages: stages.to_vec(),
+            blurs,
+            sizes,
+            pixels: Vec::new(),
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Number of pixels the effects render on
+    pub fn pixel_count(&self) -> usize {
+        self.sizes[0]
+    }
+
+    /// Number of leds of the ledstrip
+    pub fn led_count(&self) -> usize {
+        self.sizes[self.sizes.len() - 1]
+    }
+
+    /// The leds lit by the `pixels` of the effects. The buffers are kept from one frame to the
+    /// next
+    pub fn apply(&mut self, pixels: &[Pixel]) -> &[Pixel] {
+        self.pixels.clear();
+        self.pixels.extend_from_slice(pixels);
+        self.pixels.resize(self.pixel_count(), Pixel::default());
+        for (index, stage) in self.stages.iter().enumerate() {
+            let output = self.sizes[index + 1];
+            self.scratch.clear();
+            self.scratch.resize(output, Pixel::default());
+            match *stage {
+                OutputStage::Blur { .. } => {
+                    self.scratch.copy_from_slice(&self.pixels);
+                    if let Some(blur) = &mut self.blurs[index] {
+                        blur.apply(&mut self.scratch);
+                    }
+                }
+                OutputStage::Mirror => {
+                    let layout = SegmentLayout {
+                        mirrored: true,
+                        ..Default::default()
+                    };
+                    layout.scatter(&self.pixels, &mut self.scratch);
+                }
+                OutputStage::Group { leds } => {
+                    for (led, pixel) in self.scratch.iter_mut().enumerate() {
+                        *pixel = self.pixels[led / leds.max(1)];
+                    }
+                }
+                OutputStage::Downsample { .. } => resample(&self.pixels, &mut self.scratch),
+            }
+            std::mem::swap(&mut self.pixels, &mut self.scratch);
+        }
+        &self.pixels
+    }
+}
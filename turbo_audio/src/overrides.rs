@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Priority of the segments that don't set one, like the default priority of sACN
+pub const DEFAULT_PRIORITY: u8 = 100;
+
+/// Priority of the overrides that don't set one, above the segments that keep the default
+pub const DEFAULT_OVERRIDE_PRIORITY: u8 = 150;
+
+/// Effect rendered instead of the ones of some segments while it's set, like a flash for a
+/// notification, on the segments whose priority is lower than its own
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SegmentOverride {
+    pub effect_id: usize,
+    /// Ledstrip whose segments are overridden, every ledstrip if missing
+    #[serde(default)]
+    pub ledstrip_id: Option<usize>,
+    /// Segment of the ledstrip that is overridden, every segment if missing
+    #[serde(default)]
+    pub segment: Option<usize>,
+    #[serde(default = "default_override_priority")]
+    pub priority: u8,
+    /// Released on its own after that many ms, only by the control apis if missing
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+}
+
+fn default_override_priority() -> u8 {
+    DEFAULT_OVERRIDE_PRIORITY
+}
+
+impl SegmentOverride {
+    fn covers(&self, ledstrip_id: usize, segment: usize) -> bool {
+        self.ledstrip_id.is_none_or(|id| id == ledstrip_id)
+            && self.segment.is_none_or(|index| index == segment)
+    }
+}
+
+/// Override set on a channel, as reported by the control apis
+#[derive(Debug, Clone, Serialize)]
+pub struct OverrideInfo {
+    pub channel: String,
+    #[serde(flatten)]
+    pub segment_override: SegmentOverride,
+    /// Ms left before it's released, None if it's only released by the control apis
+    pub remaining_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+struct ActiveOverride {
+    segment_override: SegmentOverride,
+    until: Option<Instant>,
+    // Order in which the overrides were set, the latest wins between equal priorities
+    sequence: u64,
+}
+
+/// Overrides set on named channels, each holding one override that replaces the previous one
+/// set on the channel. Between the overrides of a segment, the highest priority wins, like the
+/// sources of a universe in sACN
+#[derive(Debug, Default)]
+pub struct Overrides {
+    channels: HashMap<String, ActiveOverride>,
+    sequence: u64,
+}
+
+impl Overrides {
+    pub fn set(&mut self, channel: &str, segment_override: SegmentOverride) {
+        self.sequence += 1;
+        let until = segment_override
+            .duration_ms
+            .map(|duration_ms| Instant::now() + Duration::from_millis(duration_ms));
+        self.channels.insert(
+            channel.to_owned(),
+            ActiveOverride {
+                segment_override,
+                until,
+                sequence: self.sequence,
+            },
+        );
+    }
+
+    /// Returns false if nothing was set on the channel
+    pub fn release(&mut self, channel: &str) -> bool {
+        self.channels.remove(channel).is_some()
+    }
+
+    /// Releases the overrides rendering the effect, so that it can be dropped
+    pub fn release_effect(&mut self, effect_id: usize) {
+        self.channels
+            .retain(|_, active| active.segment_override.effect_id != effect_id);
+    }
+
+    /// Releases the overrides whose duration is over, returning their channels
+    pub fn expire(&mut self, now: Instant) -> Vec<String> {
+        let expired: Vec<String> = self
+            .channels
+            .iter()
+            .filter(|(_, active)| active.until.is_some_and(|until| until <= now))
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        for channel in &expired {
+            self.channels.remove(channel);
+        }
+        expired
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// Effect of the override winning on a segment of `priority`, if any is above it
+    pub fn effect(&self, ledstrip_id: usize, segment: usize, priority: u8) -> Option<usize> {
+        self.channels
+            .values()
+            .filter(|active| {
+                active.segment_override.priority > priority
+                    && active.segment_override.covers(ledstrip_id, segment)
+            })
+            .max_by_key(|active| (active.segment_override.priority, active.sequence))
+            .map(|active| active.segment_override.effect_id)
+    }
+
+    pub fn info(&self) -> Vec<OverrideInfo> {
+        let now = Instant::now();
+        let mut overrides: Vec<OverrideInfo> = self
+            .channels
+            .iter()
+            .map(|(channel, active)| OverrideInfo {
+                channel: channel.clone(),
+                segment_override: active.segment_override.clone(),
+                remaining_ms: active
+                    .until
+                    .map(|until| until.saturating_duration_since(now).as_millis() as u64),
+            })
+            .collect();
+        overrides.sort_by(|a, b| a.channel.cmp(&b.channel));
+        overrides
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::{Duration, Instant};
+
+// Ticks the loop runs back to back to catch up after a slow one. Further behind, the missed
+// ticks are skipped so that a long stall doesn't end in a burst of frames
+const MAX_CATCH_UP_TICKS: u32 = 3;
+
+/// Wakes the run loop up at a fixed rate. The deadlines are absolute, so that the time spent
+/// rendering and sending doesn't make the ticks drift
+#[derive(Debug)]
+pub struct FramePacer {
+    period: Duration,
+    next_tick: Instant,
+}
+
+impl FramePacer {
+    pub fn new(ticks_per_second: u32) -> Self {
+        Self {
+            period: Duration::from_secs(1) / ticks_per_second.max(1),
+            next_tick: Instant::now(),
+        }
+    }
+
+    /// Time between two ticks
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Sleeps until the next tick is due. Returns the number of ticks skipped because the loop
+    /// fell too far behind
+    pub fn wait(&mut self) -> u32 {
+        let now = Instant::now();
+        let mut skipped = 0;
+        match self.next_tick.checked_duration_since(now) {
+            Some(early) => std::thread::sleep(early),
+            None => {
+                let late = now - self.next_tick;
+                if late > self.period * MAX_CATCH_UP_TICKS {
+                    skipped = (late.as_nanos() / self.period.as_nanos()) as u32;
+                    self.next_tick += self.period * skipped;
+                }
+            }
+        }
+        self.next_tick += self.period;
+        skipped
+    }
+}
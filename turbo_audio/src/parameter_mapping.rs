@@ -0,0 +1,996 @@
+use crate::{
+    audio::{audio_processing::FftResult, onset::OnsetDetector},
+    sync::SyncClock,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    f32::consts::TAU,
+    fmt,
+    sync::{Arc, RwLock},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ExpressionError {
+    #[error("Unexpected character '{0}' at {1}")]
+    UnexpectedCharacter(char, usize),
+
+    #[error("Unexpected end of the expression")]
+    UnexpectedEnd,
+
+    #[error("The expression nests deeper than {MAX_NESTING} levels")]
+    TooDeep,
+
+    #[error("The expression is longer than {MAX_TOKENS} numbers, names and operators")]
+    TooLong,
+
+    #[error("Unexpected '{0}'")]
+    UnexpectedToken(String),
+
+    #[error("Unknown audio feature: {0}")]
+    UnknownFeature(String),
+
+    #[error("Invalid name for a derived feature: {0}")]
+    InvalidName(String),
+
+    #[error("Unknown function: {0}")]
+    UnknownFunction(String),
+
+    #[error("{0} isn't a numeric setting of the effect")]
+    NotNumeric(String),
+
+    #[error("{function} takes {expected} argument(s) but was given {given}")]
+    WrongArgumentCount {
+        function: &'static str,
+        expected: usize,
+        given: usize,
+    },
+}
+
+/// Audio features the expressions can read, refreshed every frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AudioFeatures {
+    /// Average amplitude from 20Hz to 250Hz
+    pub bass: f32,
+    /// Average amplitude from 250Hz to 4kHz
+    pub mids: f32,
+    /// Average amplitude from 4kHz to 16kHz
+    pub treble: f32,
+    /// Average amplitude of the whole spectrum
+    pub volume: f32,
+    /// Seconds since the engine started, for the couplings that also move with time
+    pub time: f32,
+    /// 1 on a kick, decaying to 0 over about half a second. Given by a [`BeatEnvelope`]
+    pub beat: f32,
+    /// Position in [0, 1) between two beats, running at the tempo. 0 until the beat is known
+    pub tempo_phase: f32,
+    /// Position in [0, 1) in a bar of 4 beats. 0 until the beat is known
+    pub bar_phase: f32,
+    /// 1 on a kick, snare or hat hit, decaying to 0 over a few tenths of a second. Given by a
+    /// [`DrumClassifier`](crate::audio::percussion::DrumClassifier)
+    pub kick_hit: f32,
+    pub snare_hit: f32,
+    pub hat_hit: f32,
+    /// Spectral flux, how much the spectrum grew since the previous frame
+    pub flux: f32,
+    /// Spectral centroid in Hz, higher for brighter sounds
+    pub centroid: f32,
+    /// Spectral rolloff in Hz, below which 85% of the energy is
+    pub rolloff: f32,
+    /// Spectral flatness, from 0 for a pure tone to 1 for noise
+    pub flatness: f32,
+    /// Pitch class of the dominant note, from 0 for C to 11 for B, or -1 in silence
+    pub note: f32,
+}
+
+impl AudioFeatures {
+    pub fn new(fft_result: &FftResult, time: f32) -> Self {
+        if fft_result.raw_bins().len() < 2 {
+            return Self {
+                time,
+                note: -1.0,
+                ..Default::default()
+            };
+        }
+        let max_frequency = fft_result.get_max_frequency();
+        let spectral = fft_result.spectral_features();
+        let band = |lower: f32, upper: f32| {
+            fft_result
+                .get_average_amplitude(lower.min(max_frequency), upper.min(max_frequency))
+                .filter(|amplitude| amplitude.is_finite())
+                .unwrap_or_default()
+        };
+        Self {
+            bass: band(20.0, 250.0),
+            mids: band(250.0, 4000.0),
+            treble: band(4000.0, 16000.0),
+            volume: band(20.0, max_frequency),
+            time,
+            beat: 0.0,
+            tempo_phase: 0.0,
+            bar_phase: 0.0,
+            kick_hit: 0.0,
+            snare_hit: 0.0,
+            hat_hit: 0.0,
+            flux: spectral.flux,
+            centroid: spectral.centroid,
+            rolloff: spectral.rolloff,
+            flatness: spectral.flatness,
+            note: fft_result.chroma().dominant as f32,
+        }
+    }
+}
+
+// Band the kicks are detected in
+const BEAT_BAND: (f32, f32) = (40.0, 150.0);
+// Frames for the beat envelope to decay by a factor of e
+const BEAT_DECAY_FRAMES: f32 = 10.0;
+
+/// Envelope following the kicks, which needs to see every frame to detect them
+pub struct BeatEnvelope {
+    detector: OnsetDetector,
+}
+
+impl Default for BeatEnvelope {
+    fn default() -> Self {
+        Self {
+            detector: OnsetDetector::new(BEAT_BAND.0, BEAT_BAND.1),
+        }
+    }
+}
+
+impl BeatEnvelope {
+    /// Follows the kicks detected, or the beat predicted ahead by the lead of `clock` once it
+    /// is known, so that the lights land on the beat heard
+    pub fn update(&mut self, fft_result: &FftResult, clock: &SyncClock) -> f32 {
+        self.detector.tick(fft_result);
+        let frames_since_beat = match (clock.beat, clock.beat_phase()) {
+            (Some(beat), Some(phase)) if clock.lead > 0.0 => {
+                phase * beat.interval as f32 * crate::ticks_per_second() as f32
+            }
+            _ => self.detector.frames_since_onset() as f32,
+        };
+        (-frames_since_beat / BEAT_DECAY_FRAMES).exp()
+    }
+}
+
+/// Attack and release of an [`EnvelopeFollower`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct EnvelopeConfig {
+    /// Milliseconds to rise about two thirds of the way to a higher value
+    #[serde(deserialize_with = "crate::config_parser::non_negative")]
+    pub attack_ms: f32,
+    /// Milliseconds to fall about two thirds of the way to a lower value
+    #[serde(deserialize_with = "crate::config_parser::non_negative")]
+    pub release_ms: f32,
+}
+
+impl Default for EnvelopeConfig {
+    fn default() -> Self {
+        Self {
+            attack_ms: 10.0,
+            release_ms: 250.0,
+        }
+    }
+}
+
+/// Follows a signal updated every tick, quickly when it rises and slowly when it falls
+#[derive(Debug)]
+pub struct EnvelopeFollower {
+    // Share of the way to the signal covered in a tick, when rising and when falling
+    attack: f32,
+    release: f32,
+    value: f32,
+}
+
+impl EnvelopeFollower {
+    pub fn new(config: EnvelopeConfig) -> Self {
+        let tick_ms = 1000.0 / crate::ticks_per_second() as f32;
+        // A time constant of 0 jumps to the signal
+        let coefficient = |time_ms: f32| 1.0 - (-tick_ms / time_ms.max(0.0)).exp();
+        Self {
+            attack: coefficient(config.attack_ms),
+            release: coefficient(config.release_ms),
+            value: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, signal: f32) -> f32 {
+        // NaN would stick to the envelope
+        if !signal.is_finite() {
+            return self.value;
+        }
+        let coefficient = if signal > self.value {
+            self.attack
+        } else {
+            self.release
+        };
+        self.value += (signal - self.value) * coefficient;
+        self.value
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Feature {
+    Bass,
+    Mids,
+    Treble,
+    Volume,
+    Time,
+    Beat,
+    TempoPhase,
+    BarPhase,
+    KickHit,
+    SnareHit,
+    HatHit,
+    Flux,
+    Centroid,
+    Rolloff,
+    Flatness,
+    Note,
+}
+
+impl Feature {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "bass" => Feature::Bass,
+            "mids" => Feature::Mids,
+            "treble" => Feature::Treble,
+            "volume" => Feature::Volume,
+            "time" => Feature::Time,
+            "beat" => Feature::Beat,
+            "tempo_phase" => Feature::TempoPhase,
+            "bar_phase" => Feature::BarPhase,
+            "kick_hit" => Feature::KickHit,
+            "snare_hit" => Feature::SnareHit,
+            "hat_hit" => Feature::HatHit,
+            "flux" => Feature::Flux,
+            "centroid" => Feature::Centroid,
+            "rolloff" => Feature::Rolloff,
+            "flatness" => Feature::Flatness,
+            "note" => Feature::Note,
+            _ => return None,
+        })
+    }
+}
+
+/// Whether the engine computes the feature itself, like `bass`, rather than the config deriving it
+pub fn is_builtin_feature(name: &str) -> bool {
+    Feature::parse(name).is_some()
+}
+
+/// What the expressions are evaluated against
+pub struct EvalContext<'a> {
+    pub features: AudioFeatures,
+    pub fft_result: &'a FftResult,
+    /// Values of the derived features computed so far this frame
+    pub derived: &'a HashMap<String, f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Abs,
+    Sqrt,
+    Sin,
+    Cos,
+    Min,
+    Max,
+    Clamp,
+    Band,
+    Gate,
+    Sine,
+    Triangle,
+    Chroma,
+}
+
+impl Function {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Function::Abs,
+            "sqrt" => Function::Sqrt,
+            "sin" => Function::Sin,
+            "cos" => Function::Cos,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "clamp" => Function::Clamp,
+            "band" => Function::Band,
+            "gate" => Function::Gate,
+            "sine" => Function::Sine,
+            "triangle" => Function::Triangle,
+            "chroma" => Function::Chroma,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Function::Abs => "abs",
+            Function::Sqrt => "sqrt",
+            Function::Sin => "sin",
+            Function::Cos => "cos",
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Clamp => "clamp",
+            Function::Band => "band",
+            Function::Gate => "gate",
+            Function::Sine => "sine",
+            Function::Triangle => "triangle",
+            Function::Chroma => "chroma",
+        }
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Abs
+            | Function::Sqrt
+            | Function::Sin
+            | Function::Cos
+            | Function::Sine
+            | Function::Triangle
+            | Function::Chroma => 1,
+            Function::Min | Function::Max | Function::Band | Function::Gate => 2,
+            Function::Clamp => 3,
+        }
+    }
+
+    fn apply(self, arguments: &[f32], context: &EvalContext) -> f32 {
+        let fft_result = context.fft_result;
+        // Phase of a low frequency oscillator between 0 and 1
+        let phase = |rate: f32| (context.features.time * rate).rem_euclid(1.0);
+        match (self, arguments) {
+            (Function::Abs, [x]) => x.abs(),
+            (Function::Sqrt, [x]) => x.max(0.0).sqrt(),
+            (Function::Sin, [x]) => x.sin(),
+            (Function::Cos, [x]) => x.cos(),
+            (Function::Min, [a, b]) => a.min(*b),
+            (Function::Max, [a, b]) => a.max(*b),
+            (Function::Clamp, [x, min, max]) => x.max(*min).min(*max),
+            (Function::Band, [lower, upper]) => {
+                let max_frequency = fft_result.get_max_frequency();
+                fft_result
+                    .get_average_amplitude(lower.min(max_frequency), upper.min(max_frequency))
+                    .filter(|amplitude| amplitude.is_finite())
+                    .unwrap_or_default()
+            }
+            (Function::Gate, [x, threshold]) if x >= threshold => *x,
+            (Function::Gate, [_, _]) => 0.0,
+            (Function::Sine, [rate]) => (TAU * phase(*rate)).sin(),
+            (Function::Triangle, [rate]) => 1.0 - 4.0 * (phase(*rate) - 0.5).abs(),
+            (Function::Chroma, [pitch_class]) => {
+                fft_result.chroma().pitch_classes
+                    [(pitch_class.round() as i32).rem_euclid(12) as usize]
+            }
+            // The arity is checked when parsing
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f32),
+    Feature(Feature),
+    // Derived feature defined in the config
+    Named(String),
+    Negate(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+impl Node {
+    fn eval(&self, context: &EvalContext) -> f32 {
+        let features = &context.features;
+        match self {
+            Node::Number(value) => *value,
+            Node::Feature(Feature::Bass) => features.bass,
+            Node::Feature(Feature::Mids) => features.mids,
+            Node::Feature(Feature::Treble) => features.treble,
+            Node::Feature(Feature::Volume) => features.volume,
+            Node::Feature(Feature::Time) => features.time,
+            Node::Feature(Feature::Beat) => features.beat,
+            Node::Feature(Feature::TempoPhase) => features.tempo_phase,
+            Node::Feature(Feature::BarPhase) => features.bar_phase,
+            Node::Feature(Feature::KickHit) => features.kick_hit,
+            Node::Feature(Feature::SnareHit) => features.snare_hit,
+            Node::Feature(Feature::HatHit) => features.hat_hit,
+            Node::Feature(Feature::Flux) => features.flux,
+            Node::Feature(Feature::Centroid) => features.centroid,
+            Node::Feature(Feature::Rolloff) => features.rolloff,
+            Node::Feature(Feature::Flatness) => features.flatness,
+            Node::Feature(Feature::Note) => features.note,
+            Node::Named(name) => context.derived.get(name).copied().unwrap_or_default(),
+            Node::Negate(node) => -node.eval(context),
+            Node::Binary(operator, left, right) => {
+                let (left, right) = (left.eval(context), right.eval(context));
+                match operator {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide => left / right,
+                    Operator::Power => left.powf(right),
+                }
+            }
+            Node::Call(function, arguments) => {
+                let arguments: Vec<f32> = arguments
+                    .iter()
+                    .map(|argument| argument.eval(context))
+                    .collect();
+                function.apply(&arguments, context)
+            }
+        }
+    }
+
+    fn names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Node::Named(name) => names.push(name),
+            Node::Negate(node) => node.names(names),
+            Node::Binary(_, left, right) => {
+                left.names(names);
+                right.names(names);
+            }
+            Node::Call(_, arguments) => arguments.iter().for_each(|argument| argument.names(names)),
+            Node::Number(_) | Node::Feature(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Identifier(String),
+    Operator(char),
+    OpenParenthesis,
+    CloseParenthesis,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{value}"),
+            Token::Identifier(name) => write!(f, "{name}"),
+            Token::Operator(operator) => write!(f, "{operator}"),
+            Token::OpenParenthesis => write!(f, "("),
+            Token::CloseParenthesis => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut characters = source.char_indices().peekable();
+    while let Some(&(index, character)) = characters.peek() {
+        match character {
+            ' ' | '\t' => {
+                characters.next();
+            }
+            '0'..='9' | '.' => {
+                let mut end = index;
+                while let Some(&(index, '0'..='9' | '.')) = characters.peek() {
+                    end = index + 1;
+                    characters.next();
+                }
+                let number = &source[index..end];
+                tokens.push(Token::Number(
+                    number
+                        .parse()
+                        .map_err(|_| ExpressionError::UnexpectedToken(number.to_owned()))?,
+                ));
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let mut end = index;
+                while let Some(&(index, 'a'..='z' | 'A'..='Z' | '0'..='9' | '_')) =
+                    characters.peek()
+                {
+                    end = index + 1;
+                    characters.next();
+                }
+                tokens.push(Token::Identifier(source[index..end].to_owned()));
+            }
+            '+' | '-' | '*' | '/' | '^' => {
+                tokens.push(Token::Operator(character));
+                characters.next();
+            }
+            '(' => {
+                tokens.push(Token::OpenParenthesis);
+                characters.next();
+            }
+            ')' => {
+                tokens.push(Token::CloseParenthesis);
+                characters.next();
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                characters.next();
+            }
+            _ => return Err(ExpressionError::UnexpectedCharacter(character, index)),
+        }
+    }
+    Ok(tokens)
+}
+
+// Parentheses, calls and negations within each other parsed, and tokens in an expression, so that
+// a hostile expression can't overflow the stack while it's parsed or evaluated
+const MAX_NESTING: usize = 64;
+const MAX_TOKENS: usize = 1024;
+
+// Recursive descent over the tokens, from the lowest precedence to the highest:
+// sums, products, negations, powers and finally numbers, features, calls and parentheses
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    // Runs a parse one level deeper
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Node, ExpressionError>,
+    ) -> Result<Node, ExpressionError> {
+        if self.depth == MAX_NESTING {
+            return Err(ExpressionError::TooDeep);
+        }
+        self.depth += 1;
+        let node = parse(self);
+        self.depth -= 1;
+        node
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, ExpressionError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(ExpressionError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ExpressionError> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(ExpressionError::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Node, ExpressionError> {
+        let mut node = self.product()?;
+        while let Some(Token::Operator(operator @ ('+' | '-'))) = self.peek() {
+            let operator = if *operator == '+' {
+                Operator::Add
+            } else {
+                Operator::Subtract
+            };
+            self.position += 1;
+            node = Node::Binary(operator, Box::new(node), Box::new(self.product()?));
+        }
+        Ok(node)
+    }
+
+    fn product(&mut self) -> Result<Node, ExpressionError> {
+        let mut node = self.negation()?;
+        while let Some(Token::Operator(operator @ ('*' | '/'))) = self.peek() {
+            let operator = if *operator == '*' {
+                Operator::Multiply
+            } else {
+                Operator::Divide
+            };
+            self.position += 1;
+            node = Node::Binary(operator, Box::new(node), Box::new(self.negation()?));
+        }
+        Ok(node)
+    }
+
+    fn negation(&mut self) -> Result<Node, ExpressionError> {
+        if let Some(Token::Operator('-')) = self.peek() {
+            self.position += 1;
+            return Ok(Node::Negate(Box::new(self.nested(Self::negation)?)));
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Node, ExpressionError> {
+        let node = self.atom()?;
+        if let Some(Token::Operator('^')) = self.peek() {
+            self.position += 1;
+            // Right associative, and binds tighter than a negation on its left: -2^2 is -4
+            return Ok(Node::Binary(
+                Operator::Power,
+                Box::new(node),
+                Box::new(self.nested(Self::negation)?),
+            ));
+        }
+        Ok(node)
+    }
+
+    fn atom(&mut self) -> Result<Node, ExpressionError> {
+        match self.next()? {
+            Token::Number(value) => Ok(Node::Number(value)),
+            Token::OpenParenthesis => {
+                let node = self.nested(Self::sum)?;
+                self.expect(Token::CloseParenthesis)?;
+                Ok(node)
+            }
+            Token::Identifier(name) if self.peek() == Some(&Token::OpenParenthesis) => {
+                let function = Function::parse(&name)
+                    .ok_or_else(|| ExpressionError::UnknownFunction(name.clone()))?;
+                self.position += 1;
+                let mut arguments = vec![self.nested(Self::sum)?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.position += 1;
+                    arguments.push(self.nested(Self::sum)?);
+                }
+                self.expect(Token::CloseParenthesis)?;
+                if arguments.len() != function.arity() {
+                    return Err(ExpressionError::WrongArgumentCount {
+                        function: function.name(),
+                        expected: function.arity(),
+                        given: arguments.len(),
+                    });
+                }
+                Ok(Node::Call(function, arguments))
+            }
+            // Other names can only be checked once the derived features are known
+            Token::Identifier(name) => Ok(match Feature::parse(&name) {
+                Some(feature) => Node::Feature(feature),
+                None => Node::Named(name),
+            }),
+            token => Err(ExpressionError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+/// Arithmetic on the audio features, written in the config to drive an effect parameter without
+/// writing a lua effect, like `0.5 + 2.0 * bass`.
+///
+/// Supports numbers, the features of [`AudioFeatures`] (`bass`, `mids`, `treble`, `volume`,
+/// `time`, `beat`, `tempo_phase`, `bar_phase`, `kick_hit`, `snare_hit`, `hat_hit`, `flux`,
+/// `centroid`, `rolloff`, `flatness` and `note`), the names of the [`DerivedFeatures`],
+/// `+ - * / ^`, parentheses and the functions `abs`, `sqrt`, `sin`, `cos`, `min`, `max`,
+/// `clamp(x, min, max)`, `band(lower_hz, upper_hz)` (average amplitude of a frequency band),
+/// `gate(x, threshold)` (x, or 0 below threshold), `chroma(pitch_class)` (energy of a pitch class
+/// from 0 for C to 11 for B, the strongest being 1) and the oscillators `sine(rate_hz)` and
+/// `triangle(rate_hz)` going from -1 to 1, like `2 + 0.5 * sine(0.25)` for a depth of 0.5 around 2.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, ExpressionError> {
+        let tokens = tokenize(source)?;
+        if tokens.len() > MAX_TOKENS {
+            return Err(ExpressionError::TooLong);
+        }
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+        };
+        let root = parser.sum()?;
+        if let Some(token) = parser.peek() {
+            return Err(ExpressionError::UnexpectedToken(token.to_string()));
+        }
+        Ok(Self {
+            source: source.to_owned(),
+            root,
+        })
+    }
+
+    pub fn eval(&self, context: &EvalContext) -> f32 {
+        self.root.eval(context)
+    }
+
+    /// Fails on the first name that isn't a derived feature for which `is_known` returns true
+    pub fn check_names(&self, is_known: impl Fn(&str) -> bool) -> Result<(), ExpressionError> {
+        let mut names = Vec::new();
+        self.root.names(&mut names);
+        match names.into_iter().find(|name| !is_known(name)) {
+            Some(name) => Err(ExpressionError::UnknownFeature(name.to_owned())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TryFrom<String> for Expression {
+    type Error = ExpressionError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Self::parse(&source)
+    }
+}
+
+impl From<Expression> for String {
+    fn from(expression: Expression) -> Self {
+        expression.source
+    }
+}
+
+/// Bounds of a numeric setting, read from the settings schema of the effect, that the values
+/// bound to it are kept in
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SettingRange {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    pub integer: bool,
+}
+
+impl SettingRange {
+    /// Range of the `key` property of a settings schema. Fails if the schema declares it as
+    /// something else than a number
+    pub fn from_schema(schema: &serde_json::Value, key: &str) -> Result<Self, ExpressionError> {
+        let Some(property) = schema
+            .get("properties")
+            .and_then(|properties| properties.get(key))
+        else {
+            return Ok(Self::default());
+        };
+        let integer = match property.get("type").and_then(serde_json::Value::as_str) {
+            Some("integer") => true,
+            Some("number") | None => false,
+            Some(_) => return Err(ExpressionError::NotNumeric(key.to_owned())),
+        };
+        let bound = |name: &str| {
+            property
+                .get(name)
+                .and_then(serde_json::Value::as_f64)
+                .map(|bound| bound as f32)
+        };
+        Ok(Self {
+            min: bound("minimum"),
+            max: bound("maximum"),
+            integer,
+        })
+    }
+
+    /// Json value of the setting for a computed value
+    pub fn to_setting(self, value: f32) -> serde_json::Value {
+        // NaN and infinities aren't valid json numbers
+        if !value.is_finite() {
+            return serde_json::Value::Null;
+        }
+        let value = self.min.map_or(value, |min| value.max(min));
+        let value = self.max.map_or(value, |max| value.min(max));
+        if self.integer {
+            return (value.round() as i64).into();
+        }
+        serde_json::Number::from_f64(value as f64)
+            .map(serde_json::Value::Number)
+            .unwrap_or_default()
+    }
+}
+
+/// Named signals defined once in the config, like `kick = gate(band(40, 120), 0.6)`, which the
+/// bindings and the lua effects (through the `Features` global) then refer to by name.
+///
+/// They are computed in order every frame, so each one can use the ones defined before it. The
+/// ones with an envelope give the envelope of their expression.
+#[derive(Debug, Default)]
+pub struct DerivedFeatures {
+    features: Vec<(String, Expression, Option<EnvelopeFollower>)>,
+    // Shared with the lua effects
+    values: Arc<RwLock<HashMap<String, f32>>>,
+}
+
+impl DerivedFeatures {
+    pub fn values(&self) -> Arc<RwLock<HashMap<String, f32>>> {
+        self.values.clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.features.iter().any(|(known, ..)| known == name)
+    }
+
+    pub fn add(
+        &mut self,
+        name: String,
+        expression: Expression,
+        envelope: Option<EnvelopeConfig>,
+    ) -> Result<(), ExpressionError> {
+        let is_identifier = name
+            .chars()
+            .next()
+            .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && name
+                .chars()
+                .all(|character| character.is_ascii_alphanumeric() || character == '_');
+        if !is_identifier || Feature::parse(&name).is_some() || self.contains(&name) {
+            return Err(ExpressionError::InvalidName(name));
+        }
+        expression.check_names(|known| self.contains(known))?;
+        self.features
+            .push((name, expression, envelope.map(EnvelopeFollower::new)));
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.features.clear();
+        self.values.write().unwrap().clear();
+    }
+
+    /// Computes the features for this frame
+    pub fn update(
+        &mut self,
+        features: AudioFeatures,
+        fft_result: &FftResult,
+    ) -> HashMap<String, f32> {
+        let mut derived = HashMap::with_capacity(self.features.len());
+        for (name, expression, envelope) in &mut self.features {
+            let value = expression.eval(&EvalContext {
+                features,
+                fft_result,
+                derived: &derived,
+            });
+            let value = match envelope {
+                Some(envelope) => envelope.update(value),
+                None => value,
+            };
+            derived.insert(name.clone(), value);
+        }
+        self.values.write().unwrap().clone_from(&derived);
+        derived
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioFeatures, EvalContext, Expression, ExpressionError, MAX_NESTING, MAX_TOKENS};
+    use crate::audio::audio_processing::FftResult;
+    use std::collections::HashMap;
+
+    fn eval(source: &str) -> f32 {
+        let features = AudioFeatures {
+            bass: 0.5,
+            ..Default::default()
+        };
+        let derived = HashMap::from([("drive".to_owned(), 3.0)]);
+        let context = EvalContext {
+            features,
+            fft_result: &FftResult::default(),
+            derived: &derived,
+        };
+        Expression::parse(source).unwrap().eval(&context)
+    }
+
+    #[test]
+    fn follows_the_precedence_of_the_operators() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("8 / 4 / 2"), 1.0);
+        assert_eq!(eval("1 - 2 - 3"), -4.0);
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("2^3^2"), 512.0);
+        assert_eq!(eval("2^-1"), 0.5);
+        assert_eq!(eval("--2"), 2.0);
+    }
+
+    #[test]
+    fn reads_the_features_and_calls_the_functions() {
+        assert_eq!(eval("0.5 + 2.0 * bass"), 1.5);
+        assert_eq!(eval("drive * 2"), 6.0);
+        assert_eq!(eval("clamp(drive, 0, 1)"), 1.0);
+        assert_eq!(eval("max(bass, min(4, 2))"), 2.0);
+        assert_eq!(eval("gate(bass, 0.6)"), 0.0);
+        assert_eq!(eval("undefined + 1"), 1.0);
+    }
+
+    #[test]
+    fn checks_the_number_of_arguments() {
+        assert!(matches!(
+            Expression::parse("clamp(1, 2)"),
+            Err(ExpressionError::WrongArgumentCount {
+                function: "clamp",
+                expected: 3,
+                given: 2
+            })
+        ));
+        assert!(matches!(
+            Expression::parse("abs(1, 2)"),
+            Err(ExpressionError::WrongArgumentCount { given: 2, .. })
+        ));
+        assert!(matches!(
+            Expression::parse("abs()"),
+            Err(ExpressionError::UnexpectedToken(token)) if token == ")"
+        ));
+        assert!(matches!(
+            Expression::parse("smooth(bass)"),
+            Err(ExpressionError::UnknownFunction(name)) if name == "smooth"
+        ));
+    }
+
+    #[test]
+    fn refuses_expressions_too_deep_or_too_long() {
+        let nested = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Expression::parse(&nested(MAX_NESTING)).is_ok());
+        assert!(matches!(
+            Expression::parse(&nested(MAX_NESTING + 1)),
+            Err(ExpressionError::TooDeep)
+        ));
+        assert!(matches!(
+            Expression::parse(&format!("{}1", "-".repeat(10_000))),
+            Err(ExpressionError::TooLong)
+        ));
+        assert!(matches!(
+            Expression::parse(&format!("{}1", "-".repeat(MAX_NESTING + 1))),
+            Err(ExpressionError::TooDeep)
+        ));
+
+        // Every `1+` is two tokens
+        let sum = |tokens: usize| format!("{}1", "1+".repeat(tokens / 2));
+        assert!(Expression::parse(&sum(MAX_TOKENS - 1)).is_ok());
+        assert!(matches!(
+            Expression::parse(&sum(MAX_TOKENS + 1)),
+            Err(ExpressionError::TooLong)
+        ));
+    }
+
+    #[test]
+    fn refuses_malformed_expressions() {
+        for number in ["1.2.3", ".", "1..2"] {
+            assert!(
+                matches!(
+                    Expression::parse(number),
+                    Err(ExpressionError::UnexpectedToken(token)) if token == number
+                ),
+                "{number} was parsed"
+            );
+        }
+        assert!(matches!(
+            Expression::parse("2 % 3"),
+            Err(ExpressionError::UnexpectedCharacter('%', 2))
+        ));
+        assert!(matches!(
+            Expression::parse("1 2"),
+            Err(ExpressionError::UnexpectedToken(token)) if token == "2"
+        ));
+        assert!(matches!(
+            Expression::parse("(1 + 2"),
+            Err(ExpressionError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            Expression::parse("1 +"),
+            Err(ExpressionError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            Expression::parse(""),
+            Err(ExpressionError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn checks_the_names_of_the_derived_features() {
+        let expression = Expression::parse("bass * drive + sustain").unwrap();
+
+        assert!(expression
+            .check_names(|name| ["drive", "sustain"].contains(&name))
+            .is_ok());
+        assert!(matches!(
+            expression.check_names(|name| name == "drive"),
+            Err(ExpressionError::UnknownFeature(name)) if name == "sustain"
+        ));
+    }
+}
@@ -1,24 +1,54 @@
-use turbo_plugin::audio_api::AudioApi;
+use turbo_plugin::audio_api::{AudioApi, Chroma, SpectralFeatures};
 
-use crate::audio::audio_processing::FftResult;
+use crate::audio::{
+    audio_processing::{FftResult, SharedFftResult},
+    smoothing::SmoothingProfile,
+};
+use arc_swap::ArcSwap;
 use std::{
     boxed::Box,
-    sync::{Arc, RwLock},
+    collections::HashMap,
+    sync::{Arc, Mutex},
 };
 
-pub fn create_audio_api(fft_result: Arc<RwLock<FftResult>>) -> AudioApi {
+/// The audio features exposed to a native library. The smoothing profile is switched before
+/// ticking each effect so that it reads the features of its own assignment.
+#[derive(Debug)]
+pub struct AudioApiState {
+    fft_results: HashMap<SmoothingProfile, SharedFftResult>,
+    smoothing: Mutex<SmoothingProfile>,
+}
+
+impl AudioApiState {
+    pub fn new(fft_results: HashMap<SmoothingProfile, SharedFftResult>) -> Self {
+        Self {
+            fft_results,
+            smoothing: Default::default(),
+        }
+    }
+
+    pub fn set_smoothing(&self, smoothing: SmoothingProfile) {
+        *self.smoothing.lock().unwrap() = smoothing;
+    }
+
+    fn fft_result(&self) -> &ArcSwap<FftResult> {
+        &self.fft_results[&self.smoothing.lock().unwrap()]
+    }
+}
+
+pub fn create_audio_api(state: Arc<AudioApiState>) -> AudioApi {
     extern "C" fn get_average_amplitude(
         instance: *const std::ffi::c_void,
         lower_frequency: std::ffi::c_float,
         upper_frequency: std::ffi::c_float,
     ) -> std::ffi::c_float {
-        let fft_result = unsafe { &*(instance as *const Arc<RwLock<FftResult>>) };
-        fft_result
-            .read()
-            .unwrap()
+        let state = unsafe { &*(instance as *const Arc<AudioApiState>) };
+        state
+            .fft_result()
+            .load()
             .get_average_amplitude(lower_frequency, upper_frequency)
             .unwrap_or_else(|| {
-                log::error!("Invalid frequencies: {lower_frequency} & {upper_frequency}");
+                tracing::error!("Invalid frequencies: {lower_frequency} & {upper_frequency}");
                 0.0f32
             })
     }
@@ -27,35 +57,94 @@ pub fn create_audio_api(fft_result: Arc<RwLock<FftResult>>) -> AudioApi {
         instance: *const std::ffi::c_void,
         frequency: std::ffi::c_float,
     ) -> std::ffi::c_float {
-        let fft_result = unsafe { &*(instance as *const Arc<RwLock<FftResult>>) };
-        fft_result
-            .read()
-            .unwrap()
+        let state = unsafe { &*(instance as *const Arc<AudioApiState>) };
+        state
+            .fft_result()
+            .load()
             .get_frequency_amplitude(frequency)
             .unwrap_or_else(|| {
-                log::error!("Invalid frequency: {frequency}");
+                tracing::error!("Invalid frequency: {frequency}");
                 0.0f32
             })
     }
 
     extern "C" fn get_max_frequency(instance: *const std::ffi::c_void) -> std::ffi::c_float {
-        let fft_result = unsafe { &*(instance as *const Arc<RwLock<FftResult>>) };
-        fft_result.read().unwrap().get_max_frequency()
+        let state = unsafe { &*(instance as *const Arc<AudioApiState>) };
+        state.fft_result().load().get_max_frequency()
+    }
+
+    extern "C" fn get_spectral_features(instance: *const std::ffi::c_void) -> SpectralFeatures {
+        let state = unsafe { &*(instance as *const Arc<AudioApiState>) };
+        state.fft_result().load().spectral_features()
+    }
+
+    extern "C" fn get_chroma(instance: *const std::ffi::c_void) -> Chroma {
+        let state = unsafe { &*(instance as *const Arc<AudioApiState>) };
+        state.fft_result().load().chroma()
+    }
+
+    extern "C" fn get_samples(
+        instance: *const std::ffi::c_void,
+        samples: *mut std::ffi::c_float,
+        count: usize,
+    ) -> usize {
+        let state = unsafe { &*(instance as *const Arc<AudioApiState>) };
+        let fft_result = state.fft_result().load();
+        let recent = fft_result.samples(count);
+        if samples.is_null() {
+            return 0;
+        }
+        let samples = unsafe { std::slice::from_raw_parts_mut(samples, recent.len()) };
+        samples.copy_from_slice(recent);
+        recent.len()
+    }
+
+    extern "C" fn get_waveform_rms(
+        instance: *const std::ffi::c_void,
+        count: usize,
+    ) -> std::ffi::c_float {
+        let state = unsafe { &*(instance as *const Arc<AudioApiState>) };
+        state.fft_result().load().waveform_rms(count)
+    }
+
+    extern "C" fn get_history(
+        instance: *const std::ffi::c_void,
+        lower_frequency: std::ffi::c_float,
+        upper_frequency: std::ffi::c_float,
+        frames_back: usize,
+    ) -> std::ffi::c_float {
+        let state = unsafe { &*(instance as *const Arc<AudioApiState>) };
+        state
+            .fft_result()
+            .load()
+            .get_past_average_amplitude(frames_back, lower_frequency, upper_frequency)
+            .unwrap_or(0.0)
+    }
+
+    extern "C" fn get_history_length(instance: *const std::ffi::c_void) -> usize {
+        let state = unsafe { &*(instance as *const Arc<AudioApiState>) };
+        state.fft_result().load().history_len()
     }
 
     extern "C" fn free(instance: *const std::ffi::c_void) {
         unsafe {
-            drop(Box::from_raw(instance as *mut Arc<RwLock<FftResult>>));
+            drop(Box::from_raw(instance as *mut Arc<AudioApiState>));
         }
     }
 
-    let fft_result = Box::new(fft_result);
+    let state = Box::new(state);
 
     AudioApi::new(
-        Box::into_raw(fft_result) as *const _,
+        Box::into_raw(state) as *const _,
         get_average_amplitude,
         get_frequency_amplitude,
         get_max_frequency,
+        get_spectral_features,
+        get_chroma,
+        get_samples,
+        get_waveform_rms,
+        get_history,
+        get_history_length,
         free,
     )
 }
@@ -1,14 +1,34 @@
-use super::Effect;
-use crate::audio::{audio_processing::AudioSignalProcessor, audio_processing::FftResult};
+use super::{registry::MANIFEST_FILE, Effect};
+use crate::{
+    audio::{
+        audio_processing::AudioSignalProcessor,
+        audio_processing::SharedFftResult,
+        percussion::{self, Drum},
+        smoothing::SmoothingProfile,
+    },
+    cache::Cache,
+    connections::stats::SharedConnectionInfo,
+    info::ConnectionInfo,
+    now_playing,
+    resources::color::ConfigColor,
+    screen::SharedScreenColors,
+};
 use jsonschema::JSONSchema;
-use mlua::{Error, Function, Lua, LuaSerdeExt, Table, Value};
+use mlua::{
+    ChunkMode, Error, Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, MultiValue,
+    SerializeOptions, StdLib, Table, Value,
+};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs,
     os::unix::prelude::OsStrExt,
     path::{Path, PathBuf},
-    sync::{Arc, RwLock},
+    sync::{Arc, OnceLock, RwLock},
+    time::{Duration, Instant},
 };
-use turbo_plugin::Color;
+use thiserror::Error;
+use turbo_plugin::{effect_plugin::PixelRequirements, Color};
 
 #[derive(Debug)]
 pub enum InvalidEffectError {
@@ -21,26 +41,334 @@ pub enum LuaEffectLoadError {
     File(std::io::Error),
     Lua(Error),
     Effect(InvalidEffectError),
+    Sandbox(SandboxViolation),
 }
 
-#[derive(Debug)]
+/// Limits of the sandbox the lua effects run in, so that a buggy or malicious script can't
+/// stall the tick loop or eat the memory of the machine.
+///
+/// Effects only get the base, `table`, `string`, `math`, `bit` and `package` libraries, without
+/// `io`, `os`, the loading of files, bytecode or native modules, and the JIT, whose compiled code
+/// the instruction hook can't interrupt. An effect going over a limit while it ticks is disabled
+/// until it's reloaded.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct LuaSandboxConfig {
+    /// Instructions a single call into an effect can run
+    pub max_instructions: u64,
+    /// Time an effect can take to render a tick
+    pub tick_budget_ms: u64,
+    /// Time an effect can take to run its top level when it's loaded
+    pub load_budget_ms: u64,
+    /// Memory the lua state of an effect can use
+    pub max_memory_mb: usize,
+}
+
+impl Default for LuaSandboxConfig {
+    fn default() -> Self {
+        Self {
+            max_instructions: 20_000_000,
+            tick_budget_ms: 20,
+            load_budget_ms: 1000,
+            max_memory_mb: 64,
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy)]
+pub enum SandboxViolation {
+    #[error("ran more than {0} instructions")]
+    Instructions(u64),
+
+    #[error("took more than {0} ms")]
+    Time(u64),
+
+    #[error("used more than {0} MB of memory")]
+    Memory(usize),
+}
+
+// Instructions between two checks of the budget
+const HOOK_INSTRUCTIONS: u32 = 10_000;
+
+// Budget of the call into the effect that is running, checked by the instruction hook
+struct CallBudget {
+    limits: LuaSandboxConfig,
+    budget_ms: u64,
+    // None outside of the calls into the effect, when only turbo_audio's own code runs
+    deadline: Option<Instant>,
+    instructions: u64,
+    violation: Option<SandboxViolation>,
+}
+
+impl CallBudget {
+    fn check(&mut self, used_memory: usize) -> Result<(), SandboxViolation> {
+        // Sticky so that a script catching the error with pcall keeps getting it
+        if let Some(violation) = self.violation {
+            return Err(violation);
+        }
+        let Some(deadline) = self.deadline else {
+            return Ok(());
+        };
+        let violation = if self.instructions > self.limits.max_instructions {
+            SandboxViolation::Instructions(self.limits.max_instructions)
+        } else if Instant::now() > deadline {
+            SandboxViolation::Time(self.budget_ms)
+        } else if used_memory > self.limits.max_memory_mb * 1024 * 1024 {
+            SandboxViolation::Memory(self.limits.max_memory_mb)
+        } else {
+            return Ok(());
+        };
+        self.violation = Some(violation);
+        Err(violation)
+    }
+}
+
+// Version of the LuaJIT compiling the effects, like `LuaJIT 2.1.1700008891`
+fn luajit_version() -> Option<&'static str> {
+    static VERSION: OnceLock<Option<String>> = OnceLock::new();
+    VERSION
+        .get_or_init(|| {
+            Lua::new_with(StdLib::JIT, LuaOptions::default())
+                .and_then(|lua| lua.load("return jit.version").eval())
+                .map_err(|e| tracing::warn!("Couldn't read the version of LuaJIT: {e}"))
+                .ok()
+        })
+        .as_deref()
+}
+
+// Creates a lua state with the restricted libraries and the instruction hook of the sandbox
+fn new_sandbox(limits: LuaSandboxConfig) -> Result<Lua, Error> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::BIT | StdLib::PACKAGE,
+        LuaOptions::default(),
+    )?;
+    // Coroutines run on their own thread, which the hook doesn't follow
+    for name in ["dofile", "loadfile", "load", "loadstring", "coroutine"] {
+        lua.globals().raw_set(name, Value::Nil)?;
+    }
+    // Not every allocator can be limited, the hook checks the memory too
+    let _ = lua.set_memory_limit(limits.max_memory_mb * 1024 * 1024);
+
+    lua.set_app_data(CallBudget {
+        limits,
+        budget_ms: 0,
+        deadline: None,
+        instructions: 0,
+        violation: None,
+    });
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+        sandbox_hook,
+    );
+    Ok(lua)
+}
+
+// The default searchers of `require` load bytecode and native libraries, which would bypass the
+// sandbox. Only the preloaded modules and the lua source files of the search path are kept
+fn restrict_searchers(lua: &Lua, package: &Table) -> Result<(), Error> {
+    let loaders: Table = package.get("loaders")?;
+    let preload: Function = loaders.get(1)?;
+    let search_path_key = lua.create_registry_value(package.get::<_, Function>("searchpath")?)?;
+    let package_key = lua.create_registry_value(package.clone())?;
+    let source_searcher = lua.create_function(move |lua, name: String| {
+        let search_path: Function = lua.registry_value(&search_path_key)?;
+        let package: Table = lua.registry_value(&package_key)?;
+        let path: String = package.get("path")?;
+        let (file, message): (Option<String>, Option<String>) =
+            search_path.call((name.as_str(), path))?;
+        let Some(file) = file else {
+            return Ok(Value::String(
+                lua.create_string(message.unwrap_or_default())?,
+            ));
+        };
+        let source = fs::read_to_string(&file).map_err(Error::external)?;
+        let chunk = lua
+            .load(source)
+            .set_name(file.as_str())
+            .set_mode(ChunkMode::Text)
+            .into_function()?;
+        Ok(Value::Function(chunk))
+    })?;
+
+    package.set(
+        "loaders",
+        lua.create_sequence_from([preload, source_searcher])?,
+    )?;
+    package.set("cpath", "")?;
+    package.set("loadlib", Value::Nil)
+}
+
+// Checks the budget of the running call. Once it's violated the hook runs on every instruction,
+// so that a script catching the error with pcall can't run any further
+fn sandbox_hook(lua: &Lua, _: mlua::Debug) -> Result<(), Error> {
+    let used_memory = lua.used_memory();
+    let (result, first_violation) = {
+        let mut budget = lua.app_data_mut::<CallBudget>().unwrap();
+        let first_violation = budget.violation.is_none();
+        budget.instructions += HOOK_INSTRUCTIONS as u64;
+        (budget.check(used_memory), first_violation)
+    };
+    result.map_err(|violation| {
+        if first_violation {
+            lua.set_hook(HookTriggers::new().every_nth_instruction(1), sandbox_hook);
+        }
+        Error::RuntimeError(format!("Effect {violation}"))
+    })
+}
+
+// Runs a call into the effect with a budget of `budget_ms`. The outer error is the violation of
+// the sandbox that stopped the call, if any
+fn sandboxed<T>(
+    lua: &Lua,
+    budget_ms: u64,
+    call: impl FnOnce() -> Result<T, Error>,
+) -> Result<Result<T, Error>, SandboxViolation> {
+    {
+        let mut budget = lua.app_data_mut::<CallBudget>().unwrap();
+        budget.budget_ms = budget_ms;
+        budget.deadline = Some(Instant::now() + Duration::from_millis(budget_ms));
+        budget.instructions = 0;
+        budget.violation = None;
+    }
+    let result = call();
+
+    let used_memory = lua.used_memory();
+    let mut budget = lua.app_data_mut::<CallBudget>().unwrap();
+    budget.deadline = None;
+    if let Some(violation) = budget.violation.take() {
+        drop(budget);
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+            sandbox_hook,
+        );
+        return Err(violation);
+    }
+    if matches!(result, Err(Error::MemoryError(_)))
+        || used_memory > budget.limits.max_memory_mb * 1024 * 1024
+    {
+        return Err(SandboxViolation::Memory(budget.limits.max_memory_mb));
+    }
+    Ok(result)
+}
+
+// Helpers preloaded into every effect as the `Turbo` global
+const TURBO_LIBRARY: &str = include_str!("turbo.lua");
+
+// Registry key of the table mapping the read-only proxies of the console to what they point to
+const READ_ONLY_ORIGINALS: &str = "turbo_read_only_originals";
+
+// Wraps the globals so that the console can read them without changing the effect. The proxies
+// hide their metatable, what they point to is only recorded in the table the chunk is called with.
+// The functions handing out tables past the proxies are replaced by read-only versions
+const READ_ONLY_ENVIRONMENT: &str = r#"
+local originals = ...
+local message = "the console is read-only, reconnect with --write to change the effect"
+local proxies = setmetatable({}, { __mode = "k" })
+local function read_only(value)
+    if type(value) ~= "table" then
+        return value
+    end
+    if proxies[value] then
+        return proxies[value]
+    end
+    if originals[value] then
+        return value
+    end
+    local proxy = setmetatable({}, {
+        __metatable = false,
+        __index = function(_, key)
+            return read_only(value[key])
+        end,
+        __newindex = function()
+            error(message, 2)
+        end,
+        __len = function()
+            return #value
+        end,
+        __pairs = function(self)
+            local key
+            return function()
+                local item
+                key, item = next(value, key)
+                return key, rawget(self, key) or read_only(item)
+            end
+        end,
+    })
+    proxies[value] = proxy
+    originals[proxy] = value
+    return proxy
+end
+local function refuse()
+    error(message, 2)
+end
+local getfenv, getmetatable, require = getfenv, getmetatable, require
+local environment = read_only(_G)
+-- Set in the proxy itself, so that they're found before the globals
+rawset(environment, "getfenv", function(target)
+    -- Levels count from the caller of this function
+    if target == nil then
+        target = 1
+    end
+    if type(target) == "number" and target > 0 then
+        target = target + 1
+    end
+    return read_only(getfenv(target))
+end)
+rawset(environment, "getmetatable", function(value)
+    return read_only(getmetatable(value))
+end)
+rawset(environment, "require", function(name)
+    return read_only(require(name))
+end)
+rawset(environment, "setfenv", refuse)
+rawset(environment, "rawset", refuse)
+return environment
+"#;
+
+#[derive(Error, Debug)]
 pub enum LuaEffectRuntimeError {
+    #[error(transparent)]
     Lua(Error),
+
+    #[error("The effect changed the number of colors")]
     WrongColorsLen,
+
+    #[error("The effect doesn't have a Tick function")]
     MissingTickFunction,
+
+    #[error("The effect doesn't import the framework")]
     MissingFrameworkImport,
 }
 
 pub struct LuaEffectsManager {
     package_root: PathBuf,
-    fft_result: Arc<RwLock<FftResult>>,
+    fft_results: Arc<HashMap<SmoothingProfile, SharedFftResult>>,
+    derived_features: Arc<RwLock<HashMap<String, f32>>>,
+    screen_colors: SharedScreenColors,
+    connections: SharedConnectionInfo,
+    cache: Option<Cache>,
+    sandbox: LuaSandboxConfig,
 }
 
 impl LuaEffectsManager {
-    pub fn new(audio_processor: &AudioSignalProcessor, package_root: impl AsRef<Path>) -> Self {
+    pub fn new(
+        audio_processor: &AudioSignalProcessor,
+        package_root: impl AsRef<Path>,
+        cache: Option<Cache>,
+        derived_features: Arc<RwLock<HashMap<String, f32>>>,
+        screen_colors: SharedScreenColors,
+        connections: SharedConnectionInfo,
+        sandbox: LuaSandboxConfig,
+    ) -> Self {
         Self {
             package_root: package_root.as_ref().to_owned(),
-            fft_result: audio_processor.fft_result.clone(),
+            fft_results: Arc::new(audio_processor.smoothed_fft_results()),
+            derived_features,
+            screen_colors,
+            connections,
+            cache,
+            sandbox,
         }
     }
 
@@ -50,8 +378,8 @@ impl LuaEffectsManager {
     ) -> Result<Effect, LuaEffectLoadError> {
         let effect = Effect::Lua(LuaEffect::new(
             &effect_path,
-            &self.package_root,
-            self.fft_result.clone(),
+            self,
+            self.fft_results.clone(),
         )?);
         Ok(effect)
     }
@@ -61,10 +389,10 @@ impl LuaEffectsManager {
     pub fn reload_effect(&mut self, effect_to_reload: &mut LuaEffect) {
         let Ok(new_effect) = LuaEffect::new(
             &effect_to_reload.path,
-            &self.package_root,
-            self.fft_result.clone(),
+            self,
+            effect_to_reload.fft_results.clone(),
         ) else {
-            log::error!("cringe");
+            tracing::error!("cringe");
             return;
         };
 
@@ -77,17 +405,29 @@ impl LuaEffectsManager {
 pub struct LuaEffect {
     path: PathBuf,
     lua: Lua,
-    json_schema: String,
+    fft_results: Arc<HashMap<SmoothingProfile, SharedFftResult>>,
+    // Smoothing profile of the fft result currently bound to the `Fft_Result` global
+    smoothing: Option<SmoothingProfile>,
+    json_schema: serde_json::Value,
     compiled_json_schema: JSONSchema,
+    pixel_requirements: PixelRequirements,
+    // Ticks so far, for the frame timing of the `Turbo` library
+    frame: u64,
+    // `seed` of the settings `math.random` was last seeded with
+    seed: Option<u64>,
+    tick_budget_ms: u64,
+    // Set once the effect went over a limit of the sandbox, it renders black until it's reloaded
+    disabled: bool,
 }
 
+/// Json settings of the scripted effects, lua and rhai
 #[derive(Clone, Debug)]
 pub struct LuaEffectSettings {
     pub settings: serde_json::Value,
 }
 
 struct LuaFftResult {
-    fft_result: Arc<RwLock<FftResult>>,
+    fft_result: SharedFftResult,
 }
 
 impl mlua::UserData for LuaFftResult {
@@ -97,11 +437,12 @@ impl mlua::UserData for LuaFftResult {
             |_, this, (lower_frequency, upper_frequency): (f32, f32)| {
                 let result = this
                     .fft_result
-                    .read()
-                    .unwrap()
+                    .load()
                     .get_average_amplitude(lower_frequency, upper_frequency)
                     .unwrap_or_else(|| {
-                        log::error!("Invalid frequencies: {lower_frequency} & {upper_frequency}");
+                        tracing::error!(
+                            "Invalid frequencies: {lower_frequency} & {upper_frequency}"
+                        );
                         0.0f32
                     });
                 Ok(result)
@@ -111,18 +452,158 @@ impl mlua::UserData for LuaFftResult {
         methods.add_method("get_frequency_amplitude", |_, this, frequency: f32| {
             let result = this
                 .fft_result
-                .read()
-                .unwrap()
+                .load()
                 .get_frequency_amplitude(frequency)
                 .unwrap_or_else(|| {
-                    log::error!("Invalid frequency: {frequency}");
+                    tracing::error!("Invalid frequency: {frequency}");
                     0.0f32
                 });
             Ok(result)
         });
 
         methods.add_method("get_max_frequency", |_, this, _: ()| {
-            Ok(this.fft_result.read().unwrap().get_max_frequency())
+            Ok(this.fft_result.load().get_max_frequency())
+        });
+
+        methods.add_method("get_spectral_flux", |_, this, _: ()| {
+            Ok(this.fft_result.load().spectral_features().flux)
+        });
+
+        methods.add_method("get_spectral_centroid", |_, this, _: ()| {
+            Ok(this.fft_result.load().spectral_features().centroid)
+        });
+
+        methods.add_method("get_spectral_rolloff", |_, this, _: ()| {
+            Ok(this.fft_result.load().spectral_features().rolloff)
+        });
+
+        methods.add_method("get_spectral_flatness", |_, this, _: ()| {
+            Ok(this.fft_result.load().spectral_features().flatness)
+        });
+
+        // Energy of the pitch classes, from C at index 1 to B at index 12
+        methods.add_method("get_chroma", |_, this, _: ()| {
+            Ok(this.fft_result.load().chroma().pitch_classes.to_vec())
+        });
+
+        // Pitch class of the dominant note, from 0 for C to 11 for B, or nil in silence
+        methods.add_method("get_dominant_note", |_, this, _: ()| {
+            Ok(this.fft_result.load().chroma().dominant_note())
+        });
+
+        // Average amplitude between two frequencies `frames_back` fft frames ago, 0 being the
+        // current one. nil past the frames kept, or for invalid frequencies
+        methods.add_method(
+            "get_history",
+            |_, this, (lower_frequency, upper_frequency, frames_back): (f32, f32, usize)| {
+                Ok(this.fft_result.load().get_past_average_amplitude(
+                    frames_back,
+                    lower_frequency,
+                    upper_frequency,
+                ))
+            },
+        );
+
+        // Frames `get_history` can read back, the current one included
+        methods.add_method("get_history_length", |_, this, _: ()| {
+            Ok(this.fft_result.load().history_len())
+        });
+
+        // Last `count` samples of the waveform, oldest first in [-1, 1], the whole fft window
+        // without a count
+        methods.add_method("get_samples", |_, this, count: Option<usize>| {
+            Ok(this
+                .fft_result
+                .load()
+                .samples(count.unwrap_or(usize::MAX))
+                .to_vec())
+        });
+
+        // Root mean square of the last `count` samples, of the whole fft window without a count
+        methods.add_method("get_waveform_rms", |_, this, count: Option<usize>| {
+            Ok(this
+                .fft_result
+                .load()
+                .waveform_rms(count.unwrap_or(usize::MAX)))
+        });
+    }
+}
+
+// What `Turbo.connections()` returns, the state of every connection by id
+fn connections_table<'lua>(
+    lua: &'lua Lua,
+    connections: &[ConnectionInfo],
+) -> Result<Table<'lua>, Error> {
+    // The missing latencies and errors are nil rather than null
+    let options = SerializeOptions::new().serialize_none_to_null(false);
+    let table = lua.create_table_with_capacity(0, connections.len())?;
+    for connection in connections {
+        table.set(connection.id, lua.to_value_with(connection, options)?)?;
+    }
+    Ok(table)
+}
+
+// Updates the `drums` table of the `Turbo` library in place, recreating it if the effect replaced
+// it
+fn set_drums<'lua>(lua: &'lua Lua, turbo: &Table<'lua>) -> Result<(), Error> {
+    let table = match turbo.get::<_, Value>("drums")? {
+        Value::Table(table) => table,
+        _ => {
+            let table = lua.create_table()?;
+            turbo.set("drums", table.clone())?;
+            table
+        }
+    };
+    let drums = percussion::drums();
+    for drum in Drum::ALL {
+        let hits = drums.get(drum);
+        table.set(drum.name(), hits.envelope())?;
+        table.set(format!("{}_hits", drum.name()), hits.count)?;
+    }
+    Ok(())
+}
+
+// Colors of the edges of the screen, read like `Screen:get_around(0.25)`. Black, and empty
+// edges, while the screen isn't captured
+struct LuaScreen {
+    colors: SharedScreenColors,
+}
+
+impl mlua::UserData for LuaScreen {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("is_captured", |_, this, _: ()| {
+            Ok(!this.colors.load().is_empty())
+        });
+        // Colors of the zones of an edge, a list of { r, g, b } tables
+        methods.add_method("get_edge", |lua, this, name: String| {
+            let colors = this.colors.load();
+            let Some(edge) = colors.edge(&name) else {
+                return Err(mlua::Error::runtime(format!("Unknown screen edge: {name}")));
+            };
+            lua.create_sequence_from(
+                edge.iter()
+                    .map(|color| {
+                        lua.create_table_from([("r", color.r), ("g", color.g), ("b", color.b)])
+                    })
+                    .collect::<mlua::Result<Vec<_>>>()?,
+            )
+        });
+        methods.add_method("get_around", |_, this, t: f32| {
+            let color = this.colors.load().around(t);
+            Ok((color.r, color.g, color.b))
+        });
+    }
+}
+
+// Derived features of the config, read like `Features.kick`. Unknown names are nil
+struct LuaDerivedFeatures {
+    values: Arc<RwLock<HashMap<String, f32>>>,
+}
+
+impl mlua::UserData for LuaDerivedFeatures {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(mlua::MetaMethod::Index, |_, this, name: String| {
+            Ok(this.values.read().unwrap().get(&name).copied())
         });
     }
 }
@@ -130,60 +611,171 @@ impl mlua::UserData for LuaFftResult {
 impl LuaEffect {
     fn new(
         effect_path: impl AsRef<Path>,
-        package_root: impl AsRef<Path>,
-        fft_result: Arc<RwLock<FftResult>>,
+        manager: &LuaEffectsManager,
+        fft_results: Arc<HashMap<SmoothingProfile, SharedFftResult>>,
     ) -> Result<Self, LuaEffectLoadError> {
-        log::info!("Loading lua effect: {}", effect_path.as_ref().display());
-        let (lua, json_schema, compiled_json_schema) =
-            Self::load_lua_effect(&effect_path, &package_root, fft_result)?;
+        tracing::info!("Loading lua effect: {}", effect_path.as_ref().display());
+        let sandbox = manager.sandbox;
+        let (lua, json_schema, compiled_json_schema) = Self::load_lua_effect(
+            &effect_path,
+            &manager.package_root,
+            manager.connections.clone(),
+            manager.cache.as_ref(),
+            sandbox,
+        )?;
+        lua.globals()
+            .set(
+                "Features",
+                LuaDerivedFeatures {
+                    values: manager.derived_features.clone(),
+                },
+            )
+            .and_then(|_| {
+                lua.globals().set(
+                    "Screen",
+                    LuaScreen {
+                        colors: manager.screen_colors.clone(),
+                    },
+                )
+            })
+            .map_err(LuaEffectLoadError::Lua)?;
+        let pixel_requirements = Self::get_pixel_requirements(&lua);
         Ok(Self {
             path: effect_path.as_ref().to_path_buf(),
             lua,
+            fft_results,
+            smoothing: None,
             json_schema,
             compiled_json_schema,
+            pixel_requirements,
+            frame: 0,
+            seed: None,
+            tick_budget_ms: sandbox.tick_budget_ms,
+            disabled: false,
         })
     }
 
+    pub fn pixel_requirements(&self) -> PixelRequirements {
+        self.pixel_requirements
+    }
+
+    /// Json schema of the settings, declared by the `SettingsSchema` global of the effect
+    pub fn settings_schema(&self) -> &serde_json::Value {
+        &self.json_schema
+    }
+
+    /// Reacts to the fft results of another audio source from the next tick
+    pub fn set_fft_results(
+        &mut self,
+        fft_results: Arc<HashMap<SmoothingProfile, SharedFftResult>>,
+    ) {
+        self.fft_results = fft_results;
+        self.smoothing = None;
+    }
+
+    /// Checks the settings against the schema of the effect. Returns an error per invalid field
+    pub fn validate_settings(&self, settings: &serde_json::Value) -> Result<(), Vec<String>> {
+        super::validate_with_schema(&self.compiled_json_schema, settings)
+    }
+
     pub fn tick(
         &mut self,
         leds: &mut [Color],
         settings: &LuaEffectSettings,
+        smoothing: SmoothingProfile,
     ) -> Result<(), LuaEffectRuntimeError> {
+        if self.disabled {
+            leds.fill(Color::default());
+            return Ok(());
+        }
+
+        if self.smoothing != Some(smoothing) {
+            let fft_result = self.fft_results[&smoothing].clone();
+            self.lua
+                .globals()
+                .set("Fft_Result", LuaFftResult { fft_result })
+                .map_err(LuaEffectRuntimeError::Lua)?;
+            self.smoothing = Some(smoothing);
+        }
+
         self.lua
-            .globals()
-            .set("settings", self.lua.to_value(&settings.settings).unwrap())
+            .to_value(&settings.settings)
+            .and_then(|settings| self.lua.globals().set("settings", settings))
             .map_err(LuaEffectRuntimeError::Lua)?;
+        self.seed_random(&settings.settings)?;
+
+        self.frame += 1;
+        // The effect may have replaced the library with something else
+        if let Ok(turbo) = self.lua.globals().get::<_, Table>("Turbo") {
+            let ticks_per_second = crate::ticks_per_second() as f64;
+            let sync_clock = crate::sync::clock();
+            turbo
+                .set("frame", self.frame)
+                .and_then(|_| turbo.set("time", self.frame as f64 / ticks_per_second))
+                .and_then(|_| turbo.set("delta", 1.0 / ticks_per_second))
+                .and_then(|_| turbo.set("clock", crate::schedule::seconds_since_midnight()))
+                .and_then(|_| turbo.set("sync_time", sync_clock.time()))
+                .and_then(|_| turbo.set("beat_phase", sync_clock.beat_phase()))
+                .and_then(|_| turbo.set("tempo_phase", sync_clock.tempo_phase()))
+                .and_then(|_| turbo.set("bar_phase", sync_clock.bar_phase()))
+                .and_then(|_| turbo.set("beats", sync_clock.beats()))
+                .and_then(|_| turbo.set("bpm", sync_clock.bpm()))
+                .and_then(|_| set_drums(&self.lua, &turbo))
+                .and_then(|_| self.lua.to_value(&now_playing::now_playing()))
+                .and_then(|track| turbo.set("track", track))
+                .map_err(LuaEffectRuntimeError::Lua)?;
+        }
 
         let resize_fn: Function = self
             .lua
             .globals()
             .get("Resize_Colors")
             .map_err(|_| LuaEffectRuntimeError::MissingFrameworkImport)?;
-
-        resize_fn
-            .call::<_, Value>(leds.len())
-            .map_err(LuaEffectRuntimeError::Lua)?;
-
+        let load_colors_fn: Function = self
+            .lua
+            .globals()
+            .get("Load_colors")
+            .map_err(|_| LuaEffectRuntimeError::MissingFrameworkImport)?;
         let tick_fn: Function = self
             .lua
             .globals()
             .get("Tick")
             .map_err(|_| LuaEffectRuntimeError::MissingTickFunction)?;
-
-        tick_fn
-            .call::<_, ()>(())
-            .map_err(LuaEffectRuntimeError::Lua)?;
-
         let set_colors_fn: Function = self
             .lua
             .globals()
             .get("Set_colors")
             .map_err(|_| LuaEffectRuntimeError::MissingFrameworkImport)?;
 
-        set_colors_fn
-            .call::<_, ()>(())
+        // The effect renders over the colors of the previous frame
+        let previous: Vec<u8> = leds
+            .iter()
+            .flat_map(|color| [color.r, color.g, color.b])
+            .collect();
+        let previous = self
+            .lua
+            .create_string(&previous)
             .map_err(LuaEffectRuntimeError::Lua)?;
 
+        let result = sandboxed(&self.lua, self.tick_budget_ms, || {
+            resize_fn.call::<_, Value>(leds.len())?;
+            load_colors_fn.call::<_, ()>(previous)?;
+            tick_fn.call::<_, ()>(())?;
+            set_colors_fn.call::<_, ()>(())
+        });
+        match result {
+            Ok(result) => result.map_err(LuaEffectRuntimeError::Lua)?,
+            Err(violation) => {
+                tracing::error!(
+                    "Disabled the lua effect {}, it {violation}",
+                    self.path.display()
+                );
+                self.disabled = true;
+                leds.fill(Color::default());
+                return Ok(());
+            }
+        }
+
         let data = self
             .lua
             .globals()
@@ -209,42 +801,281 @@ impl LuaEffect {
         Ok(())
     }
 
+    // Seeds the generator of `math.random`, which every lua state has its own of, with the `seed`
+    // of the settings when it changes, so that the effect draws the same numbers on every run
+    fn seed_random(&mut self, settings: &serde_json::Value) -> Result<(), LuaEffectRuntimeError> {
+        let seed = settings.get("seed").and_then(serde_json::Value::as_u64);
+        if seed == self.seed {
+            return Ok(());
+        }
+        self.seed = seed;
+        let Some(seed) = seed else {
+            return Ok(());
+        };
+        // The effect may have replaced the library with something else
+        let Ok(randomseed) = self
+            .lua
+            .globals()
+            .get::<_, Table>("math")
+            .and_then(|math| math.get::<_, Function>("randomseed"))
+        else {
+            return Ok(());
+        };
+        // Lua numbers are doubles, which keep the seeds up to 2^53 exact
+        randomseed
+            .call::<_, ()>(seed as f64)
+            .map_err(LuaEffectRuntimeError::Lua)
+    }
+
+    /// Calls the optional `SettingsChanged` function of the effect once its settings were changed
+    /// through the control api, with the new ones in `settings`, so that it can rebuild what it
+    /// derived from them
+    pub fn settings_changed(
+        &mut self,
+        settings: &LuaEffectSettings,
+    ) -> Result<(), LuaEffectRuntimeError> {
+        if self.disabled {
+            return Ok(());
+        }
+        self.lua
+            .to_value(&settings.settings)
+            .and_then(|settings| self.lua.globals().set("settings", settings))
+            .map_err(LuaEffectRuntimeError::Lua)?;
+        self.seed_random(&settings.settings)?;
+        let Ok(Some(settings_changed_fn)) = self
+            .lua
+            .globals()
+            .get::<_, Option<Function>>("SettingsChanged")
+        else {
+            return Ok(());
+        };
+        match sandboxed(&self.lua, self.tick_budget_ms, || {
+            settings_changed_fn.call::<_, ()>(())
+        }) {
+            Ok(result) => result.map_err(LuaEffectRuntimeError::Lua),
+            Err(violation) => {
+                tracing::error!(
+                    "Disabled the lua effect {}, it {violation}",
+                    self.path.display()
+                );
+                self.disabled = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Evaluates a console line in the environment of the effect and returns its results. Unless
+    /// `write` is set the globals of the effect can't be assigned. The functions of the effect
+    /// still run as usual though, so calling one from a read-only console can change its state.
+    pub fn eval(&self, code: &str, write: bool) -> Result<String, Error> {
+        let environment: Value = if write {
+            Value::Table(self.lua.globals())
+        } else {
+            self.lua
+                .load(READ_ONLY_ENVIRONMENT)
+                .set_name("read_only_environment")
+                .call(self.read_only_originals()?)?
+        };
+
+        // Lines are evaluated as expressions first so that `Colors[1]` prints something
+        let values: MultiValue = sandboxed(&self.lua, self.tick_budget_ms, || {
+            match self
+                .lua
+                .load(format!("return {code}"))
+                .set_name("console")
+                .set_mode(ChunkMode::Text)
+                .set_environment(environment.clone())
+                .into_function()
+            {
+                Ok(function) => function.call(()),
+                Err(_) => self
+                    .lua
+                    .load(code)
+                    .set_name("console")
+                    .set_mode(ChunkMode::Text)
+                    .set_environment(environment)
+                    .eval(),
+            }
+        })
+        .map_err(|violation| Error::RuntimeError(format!("The console line {violation}")))??;
+
+        Ok(values
+            .iter()
+            .map(|value| self.format_value(value))
+            .collect::<Vec<_>>()
+            .join("\t"))
+    }
+
+    fn read_only_originals(&self) -> Result<Table<'_>, Error> {
+        if let Ok(originals) = self.lua.named_registry_value::<Table>(READ_ONLY_ORIGINALS) {
+            return Ok(originals);
+        }
+        // Weak keys, so that the proxies are collected with the console lines using them
+        let originals = self.lua.create_table()?;
+        let metatable = self.lua.create_table()?;
+        metatable.set("__mode", "k")?;
+        originals.set_metatable(Some(metatable));
+        self.lua
+            .set_named_registry_value(READ_ONLY_ORIGINALS, originals.clone())?;
+        Ok(originals)
+    }
+
+    fn format_value(&self, value: &Value) -> String {
+        match value {
+            Value::Table(table) => {
+                // Print what read-only proxies point to instead of the empty proxies
+                let original = self
+                    .read_only_originals()
+                    .and_then(|originals| originals.raw_get::<_, Option<Table>>(table.clone()))
+                    .ok()
+                    .flatten();
+                let table = original.unwrap_or_else(|| table.clone());
+                match self
+                    .lua
+                    .from_value::<serde_json::Value>(Value::Table(table))
+                {
+                    Ok(json) => json.to_string(),
+                    Err(_) => format!("{value:?}"),
+                }
+            }
+            Value::String(string) => string.to_string_lossy().into_owned(),
+            Value::Nil => "nil".to_string(),
+            Value::Boolean(value) => value.to_string(),
+            Value::Integer(value) => value.to_string(),
+            Value::Number(value) => value.to_string(),
+            _ => format!("{value:?}"),
+        }
+    }
+
     fn load_lua_effect(
         path: impl AsRef<Path>,
         package_path: impl AsRef<Path>,
-        fft_result: Arc<RwLock<FftResult>>,
-    ) -> Result<(Lua, String, JSONSchema), LuaEffectLoadError> {
-        let lua_src = fs::read_to_string(path).map_err(LuaEffectLoadError::File)?;
-        let lua = Lua::new();
+        connections: SharedConnectionInfo,
+        cache: Option<&Cache>,
+        sandbox: LuaSandboxConfig,
+    ) -> Result<(Lua, serde_json::Value, JSONSchema), LuaEffectLoadError> {
+        let _span =
+            tracing::debug_span!("load_lua_effect", path = %path.as_ref().display()).entered();
+        let lua_src = fs::read_to_string(&path).map_err(LuaEffectLoadError::File)?;
+        let lua = new_sandbox(sandbox).map_err(LuaEffectLoadError::Lua)?;
 
         {
-            // Add our package path to lua's package path so that it can find the libraries
+            // Only our package path is searched, so that effects can't require other lua files
+            // of the machine, and the folder of an effect package for the modules it brings
             let package = lua.globals().get::<_, mlua::Table>("package").unwrap();
-            let path = package.get::<_, mlua::String>("path").unwrap();
-            let mut new_str = Vec::from(path.as_bytes());
-            new_str.extend_from_slice(b";");
-            new_str.extend_from_slice(package_path.as_ref().as_os_str().as_bytes());
-            if new_str.last() != Some(&b'/') {
-                new_str.extend_from_slice(b"/");
+            let search_path = |folder: &Path, new_str: &mut Vec<u8>| {
+                new_str.extend_from_slice(folder.as_os_str().as_bytes());
+                if new_str.last() != Some(&b'/') {
+                    new_str.extend_from_slice(b"/");
+                }
+                new_str.extend_from_slice(b"?.lua");
+            };
+            let mut new_str = Vec::new();
+            search_path(package_path.as_ref(), &mut new_str);
+            if let Some(folder) = path.as_ref().parent() {
+                if folder.join(MANIFEST_FILE).is_file() {
+                    new_str.push(b';');
+                    search_path(folder, &mut new_str);
+                }
             }
-            new_str.extend_from_slice(b"?.lua");
             package
                 .set("path", lua.create_string(&new_str).unwrap())
-                .unwrap(); // Append the new search path to package.path
+                .unwrap(); // Replace package.path with the new search path
 
+            restrict_searchers(&lua, &package).map_err(LuaEffectLoadError::Lua)?;
             lua.globals().set("package", package).unwrap(); // Update the package
         }
 
-        lua.load(&lua_src).exec().map_err(LuaEffectLoadError::Lua)?;
+        // Loaded before the effect so that it can use the library at the top level too
+        let turbo: Table = lua
+            .load(TURBO_LIBRARY)
+            .set_name("turbo")
+            .eval()
+            .map_err(LuaEffectLoadError::Lua)?;
+        // Colors written like in the settings, as in `local r, g, b = Turbo.color("#FF8800")`
+        lua.create_function(|lua, value: Value| {
+            let value: serde_json::Value = lua.from_value(value)?;
+            let ConfigColor(Color { r, g, b }) =
+                ConfigColor::try_from(&value).map_err(|e| Error::RuntimeError(e.to_string()))?;
+            Ok((r, g, b))
+        })
+        .and_then(|color| turbo.set("color", color))
+        .map_err(LuaEffectLoadError::Lua)?;
+        // Built when called, so that the effects not showing the connections don't pay for them
+        lua.create_function(move |lua, ()| connections_table(lua, &connections.load()))
+            .and_then(|connections| turbo.set("connections", connections))
+            .map_err(LuaEffectLoadError::Lua)?;
+        lua.globals()
+            .get::<_, Table>("package")
+            .and_then(|package| package.get::<_, Table>("loaded"))
+            .and_then(|loaded| loaded.set("turbo", turbo.clone()))
+            .and_then(|_| lua.globals().set("Turbo", turbo))
+            .map_err(LuaEffectLoadError::Lua)?;
+
+        {
+            let effect = Self::compile(&lua, path.as_ref(), &lua_src, cache, sandbox)?;
+            sandboxed(&lua, sandbox.load_budget_ms, || effect.call::<_, ()>(()))
+                .map_err(LuaEffectLoadError::Sandbox)?
+                .map_err(LuaEffectLoadError::Lua)?;
+        }
         let schema = Self::get_lua_schema(&lua)?;
         let compiled_schema = JSONSchema::compile(&schema)
             .map_err(|_| LuaEffectLoadError::Effect(InvalidEffectError::InvalidSchema))?;
 
-        lua.globals()
-            .set("Fft_Result", LuaFftResult { fft_result })
-            .unwrap();
+        Ok((lua, schema, compiled_schema))
+    }
+
+    /// Compiles the source of the effect, reusing the bytecode from the cache when neither the
+    /// source, LuaJIT nor the sandbox changed since it was last compiled
+    fn compile<'lua>(
+        lua: &'lua Lua,
+        path: &Path,
+        lua_src: &str,
+        cache: Option<&Cache>,
+        sandbox: LuaSandboxConfig,
+    ) -> Result<Function<'lua>, LuaEffectLoadError> {
+        let name = path.display().to_string();
+        // The bytecode format changes with LuaJIT, without it the cache isn't used
+        let cache = cache.zip(luajit_version());
+        let cache_name = format!("lua:{name}");
+        let mut source = Vec::new();
+        if let Some((_, version)) = cache {
+            source.extend_from_slice(format!("{version}\n{sandbox:?}\n").as_bytes());
+            source.extend_from_slice(lua_src.as_bytes());
+        }
 
-        Ok((lua, schema.to_string(), compiled_schema))
+        if let Some(bytecode) = cache.and_then(|(cache, _)| cache.get(&cache_name, &source)) {
+            match lua
+                .load(bytecode)
+                .set_name(&name)
+                .set_mode(ChunkMode::Binary)
+                .into_function()
+            {
+                Ok(function) => return Ok(function),
+                Err(e) => tracing::warn!("Invalid cached bytecode for {name}: {e}"),
+            }
+        }
+
+        let function = lua
+            .load(lua_src)
+            .set_name(&name)
+            .set_mode(ChunkMode::Text)
+            .into_function()
+            .map_err(LuaEffectLoadError::Lua)?;
+        if let Some((cache, _)) = cache {
+            cache.put(&cache_name, &source, &function.dump(false));
+        }
+        Ok(function)
+    }
+
+    fn get_pixel_requirements(lua: &Lua) -> PixelRequirements {
+        let Ok(requirements) = lua.globals().get::<_, Table>("PixelRequirements") else {
+            return PixelRequirements::default();
+        };
+        PixelRequirements {
+            min: requirements.get("min").unwrap_or_default(),
+            native: requirements.get("native").unwrap_or_default(),
+        }
     }
 
     fn get_lua_schema(lua: &Lua) -> Result<serde_json::Value, LuaEffectLoadError> {
@@ -256,3 +1087,68 @@ impl LuaEffect {
         Ok(schema)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{LuaEffect, LuaEffectsManager};
+    use std::path::Path;
+
+    fn rainbow() -> LuaEffect {
+        let effects = Path::new(env!("CARGO_MANIFEST_DIR")).join("../effects/lua");
+        let manager = LuaEffectsManager {
+            package_root: effects.clone(),
+            fft_results: Default::default(),
+            derived_features: Default::default(),
+            screen_colors: Default::default(),
+            connections: Default::default(),
+            cache: None,
+            sandbox: Default::default(),
+        };
+        LuaEffect::new(effects.join("rainbow.lua"), &manager, Default::default()).unwrap()
+    }
+
+    #[test]
+    fn read_only_console_reads_the_globals() {
+        let effect = rainbow();
+
+        assert_eq!(effect.eval("SettingsSchema", false).unwrap(), "{}");
+        assert_eq!(
+            effect.eval("type(getfenv(0).Tick)", false).unwrap(),
+            "function"
+        );
+    }
+
+    #[test]
+    fn read_only_console_refuses_the_writes() {
+        let effect = rainbow();
+        let writes = [
+            "Written = 1",
+            "_G.Written = 1",
+            "getfenv(0).Written = 1",
+            "getfenv(Tick).Written = 1",
+            "getfenv().Written = 1",
+            "setfenv(Tick, {})",
+            "getmetatable('').__index.Written = 1",
+            "rawset(_G, 'Written', 1)",
+            "rawset(getfenv(0), 'Written', 1)",
+            "require('libs.colors').Written = 1",
+            "package.loaded.string.Written = 1",
+        ];
+
+        for code in writes {
+            assert!(effect.eval(code, false).is_err(), "{code} wasn't refused");
+        }
+        assert_eq!(effect.eval("Written", true).unwrap(), "nil");
+        assert_eq!(effect.eval("string.Written", true).unwrap(), "nil");
+        assert_eq!(effect.eval("type(getfenv(Tick))", true).unwrap(), "table");
+    }
+
+    #[test]
+    fn console_writes_with_write() {
+        let effect = rainbow();
+
+        effect.eval("Written = 1", true).unwrap();
+
+        assert_eq!(effect.eval("Written", false).unwrap(), "1");
+    }
+}
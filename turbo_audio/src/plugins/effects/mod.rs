@@ -1,19 +1,266 @@
 use self::{
-    lua::{LuaEffect, LuaEffectSettings},
+    lua::{LuaEffect, LuaEffectRuntimeError, LuaEffectSettings},
     native::{NativeEffect, NativeEffectSettings},
+    rhai::RhaiEffect,
 };
+use crate::{audio::smoothing::SmoothingProfile, resources::color};
+use arc_swap::ArcSwap;
+use jsonschema::JSONSchema;
+use std::sync::Arc;
+use thiserror::Error;
+use turbo_plugin::{effect_plugin::PixelRequirements, Color};
 
 pub mod lua;
 pub mod native;
+pub mod registry;
+pub mod rhai;
 
 #[derive(Debug)]
 pub enum Effect {
     Lua(LuaEffect),
     Native(NativeEffect),
+    Rhai(Box<RhaiEffect>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum EffectSettings {
     Lua(LuaEffectSettings),
     Native(NativeEffectSettings),
 }
+
+/// Settings shared by every effect instance linked to them. An edit swaps the whole settings,
+/// so that all the instances see it at once and a frame being rendered keeps the settings it
+/// started with
+#[derive(Debug, Clone)]
+pub struct SettingsHandle {
+    id: usize,
+    settings: Arc<ArcSwap<EffectSettings>>,
+}
+
+impl SettingsHandle {
+    pub fn new(id: usize, settings: EffectSettings) -> Self {
+        Self {
+            id,
+            settings: Arc::new(ArcSwap::from_pointee(settings)),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn load(&self) -> Arc<EffectSettings> {
+        self.settings.load_full()
+    }
+
+    pub fn store(&self, settings: EffectSettings) {
+        self.settings.store(Arc::new(settings));
+    }
+
+    /// Applies the edit to a copy of the settings and swaps it in
+    pub fn update(&self, edit: impl FnOnce(&mut EffectSettings)) {
+        let mut settings = EffectSettings::clone(&self.settings.load());
+        edit(&mut settings);
+        self.store(settings);
+    }
+
+    /// Whether both handles share the same settings
+    pub fn shares(&self, other: &SettingsHandle) -> bool {
+        Arc::ptr_eq(&self.settings, &other.settings)
+    }
+}
+
+/// A loaded effect and the settings it renders with
+#[derive(Debug)]
+pub struct EffectInstance {
+    pub effect: Effect,
+    /// None until the instance is linked to settings
+    pub settings: Option<SettingsHandle>,
+}
+
+impl EffectInstance {
+    pub fn new(effect: Effect) -> Self {
+        Self {
+            effect,
+            settings: None,
+        }
+    }
+
+    /// Checks the linked settings against the schema of the effect, once their colors are
+    /// written the way it reads them
+    pub fn validate_settings(&self) -> Result<(), SettingsError> {
+        let Some(handle) = &self.settings else {
+            return Ok(());
+        };
+        let mut settings = EffectSettings::clone(&handle.load());
+        if self.effect.normalize_settings(&mut settings)? {
+            self.effect.validate_settings(&settings)?;
+            handle.store(settings);
+            return Ok(());
+        }
+        self.effect.validate_settings(&settings)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("{0} effects can't use {1} settings")]
+    WrongKind(&'static str, &'static str),
+
+    #[error("Invalid settings: {}", .0.join(", "))]
+    Invalid(Vec<String>),
+}
+
+/// Why an effect couldn't render a frame
+#[derive(Error, Debug)]
+pub enum TickError {
+    #[error("Lua error: {0}")]
+    Lua(#[from] LuaEffectRuntimeError),
+
+    #[error("Rhai error: {0}")]
+    Rhai(#[from] Box<::rhai::EvalAltResult>),
+
+    #[error(transparent)]
+    Native(#[from] native::Error),
+
+    #[error("The effect has no settings")]
+    MissingSettings,
+
+    #[error(transparent)]
+    Settings(#[from] SettingsError),
+}
+
+impl Effect {
+    pub fn pixel_requirements(&self) -> PixelRequirements {
+        match self {
+            Effect::Lua(effect) => effect.pixel_requirements(),
+            Effect::Native(effect) => effect.pixel_requirements(),
+            Effect::Rhai(effect) => effect.pixel_requirements(),
+        }
+    }
+
+    /// Json schema of the settings of the effect, None if it doesn't have any
+    pub fn settings_schema(&self) -> Option<&serde_json::Value> {
+        match self {
+            Effect::Lua(effect) => Some(effect.settings_schema()),
+            Effect::Rhai(effect) => Some(effect.settings_schema()),
+            Effect::Native(_) => None,
+        }
+    }
+
+    /// Rewrites the colors of the settings written as hex strings, names or hsv into the rgb
+    /// objects of the schema. Returns whether anything was rewritten
+    pub fn normalize_settings(&self, settings: &mut EffectSettings) -> Result<bool, SettingsError> {
+        match (self.settings_schema(), settings) {
+            (Some(schema), EffectSettings::Lua(settings)) => {
+                color::normalize_colors(schema, &mut settings.settings)
+                    .map_err(SettingsError::Invalid)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    pub fn validate_settings(&self, settings: &EffectSettings) -> Result<(), SettingsError> {
+        match (self, settings) {
+            (Effect::Lua(effect), EffectSettings::Lua(settings)) => effect
+                .validate_settings(&settings.settings)
+                .map_err(SettingsError::Invalid),
+            (Effect::Rhai(effect), EffectSettings::Lua(settings)) => effect
+                .validate_settings(&settings.settings)
+                .map_err(SettingsError::Invalid),
+            (Effect::Native(_), EffectSettings::Native(_)) => Ok(()),
+            (effect, settings) => Err(effect.wrong_kind(settings)),
+        }
+    }
+
+    fn wrong_kind(&self, settings: &EffectSettings) -> SettingsError {
+        let effect = match self {
+            Effect::Lua(_) => "Lua",
+            Effect::Native(_) => "Native",
+            Effect::Rhai(_) => "Rhai",
+        };
+        let settings = match settings {
+            EffectSettings::Lua(_) => "lua",
+            EffectSettings::Native(_) => "native",
+        };
+        SettingsError::WrongKind(effect, settings)
+    }
+
+    /// Tells the effect that its settings were changed through the control api. Native effects
+    /// don't have settings to react to
+    pub fn settings_changed(&mut self, settings: &EffectSettings) -> Result<(), TickError> {
+        match (self, settings) {
+            (Effect::Lua(lua), EffectSettings::Lua(settings)) => lua.settings_changed(settings)?,
+            (Effect::Rhai(rhai), EffectSettings::Lua(settings)) => {
+                rhai.settings_changed(settings)?
+            }
+            (Effect::Native(_), EffectSettings::Native(_)) => {}
+            (effect, settings) => return Err(effect.wrong_kind(settings).into()),
+        }
+        Ok(())
+    }
+
+    /// Renders a frame over `leds`, which hold the colors of the segment on the previous frame.
+    /// Effects read and modify them, as `Colors` for lua effects
+    pub fn tick(
+        &mut self,
+        setting: Option<&EffectSettings>,
+        leds: &mut [Color],
+        smoothing: SmoothingProfile,
+    ) -> Result<(), TickError> {
+        let Some(setting) = setting else {
+            return Err(TickError::MissingSettings);
+        };
+        match (self, setting) {
+            (Effect::Lua(lua), EffectSettings::Lua(settings)) => {
+                lua.tick(leds, settings, smoothing)?
+            }
+            (Effect::Rhai(rhai), EffectSettings::Lua(settings)) => {
+                rhai.tick(leds, settings, smoothing)?
+            }
+            (Effect::Native(native), EffectSettings::Native(settings)) => {
+                native.tick(leds, settings, smoothing)?
+            }
+            (effect, settings) => return Err(effect.wrong_kind(settings).into()),
+        }
+        Ok(())
+    }
+}
+
+/// Applies a json merge patch (RFC 7386) to `target`: the fields of `patch` replace the ones of
+/// `target`, recursively for objects, and null fields remove them
+pub fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(Default::default());
+    }
+    let serde_json::Value::Object(target) = target else {
+        return;
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key).or_insert(serde_json::Value::Null), value);
+        }
+    }
+}
+
+// Checks settings against the schema of a scripted effect. Returns an error per invalid field
+fn validate_with_schema(
+    schema: &JSONSchema,
+    settings: &serde_json::Value,
+) -> Result<(), Vec<String>> {
+    schema.validate(settings).map_err(|errors| {
+        errors
+            .map(|error| {
+                let field = error.instance_path.to_string();
+                let field = if field.is_empty() { "/" } else { &field };
+                format!("{field}: {error}")
+            })
+            .collect()
+    })
+}
@@ -1,39 +1,46 @@
 use crate::{
-    audio::audio_processing::{AudioSignalProcessor, FftResult},
-    plugins::audio_api::create_audio_api,
+    audio::{audio_processing::AudioSignalProcessor, smoothing::SmoothingProfile},
+    plugins::audio_api::{create_audio_api, AudioApiState},
 };
 use libloading::os::unix::{RTLD_LOCAL, RTLD_NOW};
 use std::{
     collections::HashMap,
     io,
     path::{Path, PathBuf},
-    sync::{Arc, RwLock},
+    sync::Arc,
 };
 use thiserror::Error;
-use turbo_plugin::{effect_plugin::NativeEffectPluginVTable, Color};
+use turbo_plugin::{
+    effect_plugin::{NativeEffectPluginVTable, PixelRequirements, EFFECT_PLUGIN_ABI_VERSION},
+    Color,
+};
 
 use super::Effect;
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Error when loading native library: {0}")]
-    LoadError(#[from] libloading::Error),
+    Load(#[from] libloading::Error),
 
     #[error("IO error: {0}")]
-    IoError(#[from] io::Error),
+    Io(#[from] io::Error),
+
+    #[error("Library built for effect plugin abi {0} instead of {EFFECT_PLUGIN_ABI_VERSION}")]
+    AbiVersion(u32),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct NativeEffectsManager {
     libraries: HashMap<PathBuf, Arc<Library>>,
-    fft_result: Arc<RwLock<FftResult>>,
+    audio_api_state: Arc<AudioApiState>,
 }
 
 #[derive(Debug)]
 struct Library {
     library: Option<libloading::Library>,
     vtable: *const NativeEffectPluginVTable,
+    audio_api_state: Arc<AudioApiState>,
 }
 
 unsafe impl Send for Library {}
@@ -45,7 +52,7 @@ impl Drop for Library {
             ((*self.vtable).unload)();
         }
         self.library.take().unwrap().close().unwrap();
-        log::info!("Dropping library");
+        tracing::info!("Dropping library");
     }
 }
 
@@ -53,7 +60,7 @@ impl NativeEffectsManager {
     pub fn new(audio_processor: &AudioSignalProcessor) -> Self {
         Self {
             libraries: Default::default(),
-            fft_result: audio_processor.fft_result.clone(),
+            audio_api_state: Arc::new(AudioApiState::new(audio_processor.smoothed_fft_results())),
         }
     }
 
@@ -63,7 +70,7 @@ impl NativeEffectsManager {
         let library = match self.libraries.entry(path) {
             std::collections::hash_map::Entry::Occupied(occupied) => occupied.into_mut(),
             std::collections::hash_map::Entry::Vacant(vacant) => {
-                let library = Self::load_library(&self.fft_result, vacant.key())?;
+                let library = Self::load_library(&self.audio_api_state, vacant.key())?;
                 vacant.insert(Arc::new(library))
             }
         };
@@ -74,15 +81,16 @@ impl NativeEffectsManager {
             pointer: plugin,
             library: Some(library.clone()),
             is_dropped: false,
+            seed: None,
         }))
     }
 
     pub fn on_file_changed(&mut self, path: impl AsRef<Path>) {
         self.libraries.remove(&path.as_ref().to_owned());
-        log::info!("Reloading library: {}", path.as_ref().display());
+        tracing::info!("Reloading library: {}", path.as_ref().display());
 
-        let Ok(library) = Self::load_library(&self.fft_result, path.as_ref()) else {
-            log::error!("Error");
+        let Ok(library) = Self::load_library(&self.audio_api_state, path.as_ref()) else {
+            tracing::error!("Error");
             return;
         };
 
@@ -101,38 +109,57 @@ impl NativeEffectsManager {
     }
 
     pub fn reload_effect(&mut self, effect: &mut NativeEffect) {
-        log::info!("Reloading native effect");
+        tracing::info!("Reloading native effect");
         let Ok(Effect::Native(new_effect)) = self.create_effect(&effect.path) else {
-            log::error!("Decaliss");
+            tracing::error!("Decaliss");
             return;
         };
         let _ = std::mem::replace(effect, new_effect);
     }
 
-    fn load_library(fft_result: &Arc<RwLock<FftResult>>, path: &Path) -> Result<Library> {
+    fn load_library(audio_api_state: &Arc<AudioApiState>, path: &Path) -> Result<Library> {
         unsafe {
             let library = libloading::os::unix::Library::open(Some(path), RTLD_NOW | RTLD_LOCAL)?;
 
+            // Checked before anything reads the vtable, whose layout depends on the version
+            let abi_version = library.get::<extern "C" fn() -> u32>(b"_plugin_abi_version")?();
+            if abi_version != EFFECT_PLUGIN_ABI_VERSION {
+                return Err(Error::AbiVersion(abi_version));
+            }
+
             let vtable_fn =
                 library.get::<extern "C" fn() -> *const std::ffi::c_void>(b"_plugin_vtable")?;
 
             let vtable =
                 vtable_fn() as *const turbo_plugin::effect_plugin::NativeEffectPluginVTable;
 
-            let audio_api = create_audio_api(fft_result.clone());
+            let audio_api = create_audio_api(audio_api_state.clone());
 
             ((*vtable).load)(audio_api);
 
             Ok(Library {
                 library: Some(library.into()),
                 vtable,
+                audio_api_state: audio_api_state.clone(),
             })
         }
     }
 }
 
-#[derive(Debug)]
-pub struct NativeEffectSettings {}
+#[derive(Debug, Clone, Default)]
+pub struct NativeEffectSettings {
+    /// Seed of the random generator of the effect, from entropy when there is none
+    pub seed: Option<u64>,
+}
+
+impl NativeEffectSettings {
+    /// Settings of a native effect created from json settings, like `{ "seed": 7 }`
+    pub fn from_json(settings: &serde_json::Value) -> Self {
+        Self {
+            seed: settings.get("seed").and_then(serde_json::Value::as_u64),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct NativeEffect {
@@ -140,8 +167,14 @@ pub struct NativeEffect {
     pointer: *mut std::ffi::c_void,
     library: Option<Arc<Library>>,
     is_dropped: bool,
+    // Seed given to the plugin, so that it's only seeded again when the settings change
+    seed: Option<u64>,
 }
 
+// The effect behind the pointer is a NativeEffectPlugin, which is Send + Sync, and the vtable
+// only holds function pointers. The effects are rendered on the effect scheduler's threads
+unsafe impl Send for NativeEffect {}
+
 impl Drop for NativeEffect {
     fn drop(&mut self) {
         if self.is_dropped {
@@ -149,19 +182,38 @@ impl Drop for NativeEffect {
         }
 
         if let Some(library) = &self.library {
-            log::info!("Dropping native effect");
+            tracing::info!("Dropping native effect");
             unsafe {
                 ((*library.vtable).plugin_destroy)(self.pointer);
             }
         } else {
-            log::error!("Couldn't drop effect because the library isn't loaded");
+            tracing::error!("Couldn't drop effect because the library isn't loaded");
         }
     }
 }
 
 impl NativeEffect {
-    pub fn tick(&mut self, leds: &mut [Color]) -> Result<()> {
+    pub fn pixel_requirements(&self) -> PixelRequirements {
+        match &self.library {
+            Some(library) => unsafe { ((*library.vtable).pixel_requirements)(self.pointer) },
+            None => PixelRequirements::default(),
+        }
+    }
+
+    pub fn tick(
+        &mut self,
+        leds: &mut [Color],
+        settings: &NativeEffectSettings,
+        smoothing: SmoothingProfile,
+    ) -> Result<()> {
         if let Some(library) = &self.library {
+            library.audio_api_state.set_smoothing(smoothing);
+            if settings.seed != self.seed {
+                if let Some(seed) = settings.seed {
+                    unsafe { ((*library.vtable).seed)(self.pointer, seed) };
+                }
+                self.seed = settings.seed;
+            }
             unsafe {
                 ((*library.vtable).tick)(self.pointer, leds.as_mut_ptr(), leds.len() as _);
             }
@@ -0,0 +1,339 @@
+use super::{lua::LuaEffectSettings, merge_patch, native::NativeEffectSettings, EffectSettings};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Folder the native effect crates are built into
+pub const NATIVE_EFFECTS_FOLDER: &str = "../effects/bin";
+
+/// Folder of the rhai effects, which they import their modules from
+pub const RHAI_EFFECTS_FOLDER: &str = "../effects/rhai";
+
+#[derive(Error, Debug)]
+pub enum EffectTypeError {
+    #[error("Unknown effect type: {0}")]
+    UnknownType(String),
+
+    #[error("Invalid settings for a {0} effect: {1}")]
+    InvalidSettings(String, serde_json::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid manifest: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    #[error("The entry point {0} isn't a file of the package")]
+    InvalidEntry(PathBuf),
+}
+
+/// File an effect is loaded from
+#[derive(Debug, Clone)]
+pub enum EffectSource {
+    Lua(PathBuf),
+    Native(PathBuf),
+    Rhai(PathBuf),
+}
+
+/// Effect to load and the settings it starts with
+#[derive(Debug)]
+pub struct EffectSpec {
+    pub source: EffectSource,
+    pub settings: EffectSettings,
+    /// Audio features the effect reads, which have to exist for it to load
+    pub features: Vec<String>,
+}
+
+/// File of an effect package describing it
+pub const MANIFEST_FILE: &str = "effect.json";
+
+/// Manifest of an effect package, a folder of the lua effects folder with its script, the lua
+/// modules it requires and this manifest, so that it can be shared as a whole
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EffectManifest {
+    /// Name of the effect type, the name of the folder if missing
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// Script of the package the effect is loaded from
+    #[serde(default = "default_entry")]
+    pub entry: PathBuf,
+    /// Settings the effect starts with, that the settings given to it are merged into
+    #[serde(default)]
+    pub settings: serde_json::Value,
+    /// Built-in or derived audio features the effect reads, like `bass` or `kick`
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+fn default_entry() -> PathBuf {
+    PathBuf::from("main.lua")
+}
+
+impl EffectManifest {
+    pub fn load(package: &Path) -> Result<Self, ManifestError> {
+        let manifest: Self =
+            serde_json::from_str(&std::fs::read_to_string(package.join(MANIFEST_FILE))?)?;
+        // The script is looked up in the package only
+        let is_inside = manifest
+            .entry
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !is_inside || !package.join(&manifest.entry).is_file() {
+            return Err(ManifestError::InvalidEntry(manifest.entry));
+        }
+        Ok(manifest)
+    }
+}
+
+/// Builds an effect from the settings json given by the config or the control api
+pub type EffectConstructor =
+    Box<dyn Fn(&serde_json::Value) -> Result<EffectSpec, EffectTypeError> + Send>;
+
+#[derive(Deserialize)]
+struct ScriptTypeSettings {
+    file: PathBuf,
+    #[serde(default)]
+    settings: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct NativeTypeSettings {
+    library: PathBuf,
+    #[serde(default)]
+    seed: Option<u64>,
+}
+
+/// Effect constructors keyed by the name of their type.
+///
+/// Every lua effect of the effects folder and rhai effect of [`RHAI_EFFECTS_FOLDER`] is
+/// registered under its file name without the extension (`rainbow` for `rainbow.lua`) and every
+/// native effect crate built into [`NATIVE_EFFECTS_FOLDER`] under its library name (`raindrop`
+/// for `libraindrop.so`), so a new effect only has to be dropped in one of them. Every package of
+/// the lua effects folder, a folder with an [`EffectManifest`], is registered under the name its
+/// manifest gives, or the name of the folder. `lua`, `rhai` and `native` load any file, given as
+/// `{"file": ..., "settings": ...}` and `{"library": ...}`.
+pub struct EffectRegistry {
+    constructors: BTreeMap<String, EffectConstructor>,
+}
+
+impl EffectRegistry {
+    pub fn new(lua_effects_folder: &Path) -> Self {
+        let mut registry = Self {
+            constructors: BTreeMap::new(),
+        };
+
+        for path in list_files(lua_effects_folder) {
+            if path.extension().is_some_and(|extension| extension == "lua") {
+                let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let name = name.to_owned();
+                registry.register(
+                    &name,
+                    Box::new(move |settings| {
+                        Ok(EffectSpec {
+                            source: EffectSource::Lua(path.clone()),
+                            settings: lua_settings(settings.clone()),
+                            features: Vec::new(),
+                        })
+                    }),
+                );
+            }
+        }
+        // After the lone scripts, so that a package replaces the script with its name
+        for package in list_folders(lua_effects_folder) {
+            if !package.join(MANIFEST_FILE).is_file() {
+                continue;
+            }
+            let manifest = match EffectManifest::load(&package) {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    tracing::warn!("Ignoring the effect package {}: {e}", package.display());
+                    continue;
+                }
+            };
+            let name = match manifest.name {
+                Some(name) => name,
+                None => match package.file_name().and_then(|name| name.to_str()) {
+                    Some(name) => name.to_owned(),
+                    None => continue,
+                },
+            };
+            let path = package.join(&manifest.entry);
+            let defaults = manifest.settings;
+            let features = manifest.features;
+            registry.register(
+                &name,
+                Box::new(move |settings| {
+                    let mut merged = defaults.clone();
+                    if !settings.is_null() {
+                        merge_patch(&mut merged, settings);
+                    }
+                    Ok(EffectSpec {
+                        source: EffectSource::Lua(path.clone()),
+                        settings: lua_settings(merged),
+                        features: features.clone(),
+                    })
+                }),
+            );
+        }
+        for path in list_files(Path::new(RHAI_EFFECTS_FOLDER)) {
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "rhai")
+            {
+                let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let name = name.to_owned();
+                registry.register(
+                    &name,
+                    Box::new(move |settings| {
+                        Ok(EffectSpec {
+                            source: EffectSource::Rhai(path.clone()),
+                            settings: lua_settings(settings.clone()),
+                            features: Vec::new(),
+                        })
+                    }),
+                );
+            }
+        }
+        for path in list_files(Path::new(NATIVE_EFFECTS_FOLDER)) {
+            let is_library = path.extension().is_some_and(|extension| {
+                ["so", "dylib", "dll"]
+                    .iter()
+                    .any(|library_extension| extension == *library_extension)
+            });
+            if is_library {
+                let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let name = name.strip_prefix("lib").unwrap_or(name).to_owned();
+                registry.register(
+                    &name,
+                    Box::new(move |settings| {
+                        Ok(EffectSpec {
+                            source: EffectSource::Native(path.clone()),
+                            settings: EffectSettings::Native(NativeEffectSettings::from_json(
+                                settings,
+                            )),
+                            features: Vec::new(),
+                        })
+                    }),
+                );
+            }
+        }
+
+        let lua_effects_folder = lua_effects_folder.to_path_buf();
+        registry.register(
+            "lua",
+            Box::new(move |settings| {
+                let ScriptTypeSettings { file, settings } = parse_settings("lua", settings)?;
+                Ok(EffectSpec {
+                    source: EffectSource::Lua(lua_effects_folder.join(file)),
+                    settings: lua_settings(settings),
+                    features: Vec::new(),
+                })
+            }),
+        );
+        registry.register(
+            "rhai",
+            Box::new(|settings| {
+                let ScriptTypeSettings { file, settings } = parse_settings("rhai", settings)?;
+                Ok(EffectSpec {
+                    source: EffectSource::Rhai(Path::new(RHAI_EFFECTS_FOLDER).join(file)),
+                    settings: lua_settings(settings),
+                    features: Vec::new(),
+                })
+            }),
+        );
+        registry.register(
+            "native",
+            Box::new(|settings| {
+                let NativeTypeSettings { library, seed } = parse_settings("native", settings)?;
+                Ok(EffectSpec {
+                    source: EffectSource::Native(library),
+                    settings: EffectSettings::Native(NativeEffectSettings { seed }),
+                    features: Vec::new(),
+                })
+            }),
+        );
+        registry
+    }
+
+    /// Adds an effect type, replacing the one with the same name
+    pub fn register(&mut self, name: &str, constructor: EffectConstructor) {
+        self.constructors.insert(name.to_owned(), constructor);
+    }
+
+    pub fn create(
+        &self,
+        name: &str,
+        settings: &serde_json::Value,
+    ) -> Result<EffectSpec, EffectTypeError> {
+        let constructor = self
+            .constructors
+            .get(name)
+            .ok_or_else(|| EffectTypeError::UnknownType(name.to_owned()))?;
+        constructor(settings)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.constructors.keys().map(String::as_str)
+    }
+}
+
+// Missing settings of a scripted effect are empty
+fn lua_settings(settings: serde_json::Value) -> EffectSettings {
+    let settings = match settings {
+        serde_json::Value::Null => serde_json::Value::Object(Default::default()),
+        settings => settings,
+    };
+    EffectSettings::Lua(LuaEffectSettings { settings })
+}
+
+fn parse_settings<T: DeserializeOwned>(
+    name: &str,
+    settings: &serde_json::Value,
+) -> Result<T, EffectTypeError> {
+    T::deserialize(settings).map_err(|e| EffectTypeError::InvalidSettings(name.to_owned(), e))
+}
+
+// Files of a folder, sorted so that the same file wins every time when two have the same name
+fn list_files(folder: &Path) -> Vec<PathBuf> {
+    list_entries(folder, Path::is_file)
+}
+
+fn list_folders(folder: &Path) -> Vec<PathBuf> {
+    list_entries(folder, Path::is_dir)
+}
+
+fn list_entries(folder: &Path, keep: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            tracing::warn!("Couldn't list the effects in {}: {e}", folder.display());
+            return Vec::new();
+        }
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| keep(path))
+        .collect();
+    files.sort();
+    files
+}
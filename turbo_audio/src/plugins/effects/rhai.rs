@@ -0,0 +1,664 @@
+use super::{
+    lua::{InvalidEffectError, LuaEffectSettings, LuaSandboxConfig, SandboxViolation},
+    registry::RHAI_EFFECTS_FOLDER,
+    Effect,
+};
+use crate::{
+    audio::{
+        audio_processing::{AudioSignalProcessor, Chroma, FftResult, SharedFftResult},
+        percussion::{self, Drum},
+        smoothing::SmoothingProfile,
+    },
+    connections::stats::SharedConnectionInfo,
+    now_playing,
+};
+use jsonschema::JSONSchema;
+use rhai::{
+    module_resolvers::FileModuleResolver, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs,
+    Position, Scope, AST,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use turbo_plugin::{effect_plugin::PixelRequirements, Color};
+
+#[derive(Error, Debug)]
+pub enum RhaiEffectLoadError {
+    #[error(transparent)]
+    Rhai(Box<EvalAltResult>),
+
+    #[error("Invalid effect: {0:?}")]
+    Effect(InvalidEffectError),
+
+    #[error("The effect {0}")]
+    Sandbox(SandboxViolation),
+}
+
+// Operations between two checks of the time budget
+const PROGRESS_CHECK_OPERATIONS: u64 = 1024;
+
+// Rough size of an item of an array or a map, to bound them with the memory limit
+const ITEM_SIZE: usize = 64;
+
+pub struct RhaiEffectsManager {
+    fft_results: Arc<HashMap<SmoothingProfile, SharedFftResult>>,
+    derived_features: Arc<RwLock<HashMap<String, f32>>>,
+    connections: SharedConnectionInfo,
+    sandbox: LuaSandboxConfig,
+}
+
+impl RhaiEffectsManager {
+    pub fn new(
+        audio_processor: &AudioSignalProcessor,
+        derived_features: Arc<RwLock<HashMap<String, f32>>>,
+        connections: SharedConnectionInfo,
+        sandbox: LuaSandboxConfig,
+    ) -> Self {
+        Self {
+            fft_results: Arc::new(audio_processor.smoothed_fft_results()),
+            derived_features,
+            connections,
+            sandbox,
+        }
+    }
+
+    pub fn create_effect(
+        &mut self,
+        effect_path: impl AsRef<Path>,
+    ) -> Result<Effect, RhaiEffectLoadError> {
+        let effect = Effect::Rhai(Box::new(RhaiEffect::new(
+            &effect_path,
+            self.fft_results.clone(),
+            self.derived_features.clone(),
+            self.connections.clone(),
+            self.sandbox,
+        )?));
+        Ok(effect)
+    }
+
+    pub fn on_file_changed(&mut self, _file: impl AsRef<Path>) {}
+
+    pub fn reload_effect(&mut self, effect_to_reload: &mut RhaiEffect) {
+        match RhaiEffect::new(
+            &effect_to_reload.path,
+            effect_to_reload.fft_results.clone(),
+            self.derived_features.clone(),
+            self.connections.clone(),
+            self.sandbox,
+        ) {
+            Ok(new_effect) => *effect_to_reload = new_effect,
+            Err(e) => tracing::error!(
+                "Couldn't reload the rhai effect {}: {e}",
+                effect_to_reload.path.display()
+            ),
+        }
+    }
+}
+
+// What the host functions of an effect read, updated before every call into the script
+#[derive(Debug)]
+struct Host {
+    fft_result: Option<SharedFftResult>,
+    settings: Dynamic,
+    frame: u64,
+    budget_ms: u64,
+    // None outside of the calls into the script
+    deadline: Option<Instant>,
+}
+
+/// Colors of the segment given to `tick`. Clones share the colors, so that the script writes to
+/// the ones turbo_audio reads back
+#[derive(Debug, Clone)]
+struct Leds(Arc<RwLock<Vec<Color>>>);
+
+/// Effect written in [rhai](https://rhai.rs), a scripting language close to rust that doesn't
+/// need a C library.
+///
+/// The script has the same api as the lua effects:
+/// - `fn tick(leds)` renders a frame over `leds`, which hold the colors of the previous frame.
+///   They are indexed from 0 and hold `Color`s with `r`, `g` and `b` fields in [0, 255]
+/// - `fn settings_schema()` returns the json schema of the settings, as a map
+/// - `fn pixel_requirements()`, optional, returns `#{ min: ..., native: ... }`
+/// - `fn init()`, optional, sets up the state of the effect
+/// - `fn settings_changed()`, optional, is called when the settings are changed through the
+///   control api, to rebuild the state derived from them
+///
+/// Scripts can't read global variables from their functions, the state kept across ticks lives
+/// in the `this` map of `init` and `tick`. They can call `settings()`, `feature(name)` for the
+/// derived features, `average_amplitude(low, high)`, `frequency_amplitude(frequency)`,
+/// `max_frequency()`, the `spectral_flux()`, `spectral_centroid()`, `spectral_rolloff()` and
+/// `spectral_flatness()` of the frame, `chroma()`, the energy of the pitch classes from C to B,
+/// `dominant_note()`, its strongest pitch class or `()` in silence, `samples()` and
+/// `samples(count)`, the last samples of the waveform in [-1, 1], and their `waveform_rms()` and
+/// `waveform_rms(count)`, `history(low, high, frames_back)`, the average amplitude `frames_back`
+/// fft frames ago or `()` past the `history_length()` frames kept, `frame()`, `time()` and
+/// `dt()` in seconds, `connections()`, the state of the connections like `Turbo.connections()`
+/// keyed by their id as a string, `rgb(r, g, b)`, `hsv(h, s, v)`, `clamp(x, min, max)` and
+/// `lerp(a, b, t)`. The limits of the lua sandbox apply too.
+#[derive(Debug)]
+pub struct RhaiEffect {
+    path: PathBuf,
+    engine: Engine,
+    ast: AST,
+    host: Arc<RwLock<Host>>,
+    fft_results: Arc<HashMap<SmoothingProfile, SharedFftResult>>,
+    leds: Leds,
+    // `this` of the script
+    state: Dynamic,
+    json_schema: serde_json::Value,
+    compiled_json_schema: JSONSchema,
+    pixel_requirements: PixelRequirements,
+    sandbox: LuaSandboxConfig,
+    // Set once the effect went over a limit of the sandbox, it renders black until it's reloaded
+    disabled: bool,
+}
+
+impl RhaiEffect {
+    fn new(
+        effect_path: impl AsRef<Path>,
+        fft_results: Arc<HashMap<SmoothingProfile, SharedFftResult>>,
+        derived_features: Arc<RwLock<HashMap<String, f32>>>,
+        connections: SharedConnectionInfo,
+        sandbox: LuaSandboxConfig,
+    ) -> Result<Self, RhaiEffectLoadError> {
+        tracing::info!("Loading rhai effect: {}", effect_path.as_ref().display());
+        let host = Arc::new(RwLock::new(Host {
+            fft_result: None,
+            settings: Dynamic::from_map(Default::default()),
+            frame: 0,
+            budget_ms: 0,
+            deadline: None,
+        }));
+        let engine = new_engine(&host, derived_features, connections, sandbox);
+        let ast = engine
+            .compile_file(effect_path.as_ref().to_path_buf())
+            .map_err(RhaiEffectLoadError::Rhai)?;
+
+        let mut effect = Self {
+            path: effect_path.as_ref().to_path_buf(),
+            engine,
+            ast,
+            host,
+            fft_results,
+            leds: Leds(Default::default()),
+            state: Dynamic::from_map(Default::default()),
+            json_schema: serde_json::Value::Null,
+            compiled_json_schema: JSONSchema::compile(&serde_json::Value::Bool(true))
+                .expect("true is a valid schema"),
+            pixel_requirements: PixelRequirements::default(),
+            sandbox,
+            disabled: false,
+        };
+
+        let budget_ms = sandbox.load_budget_ms;
+        if effect.has_function("init") {
+            effect
+                .call::<()>("init", (), budget_ms)
+                .map_err(RhaiEffectLoadError::Sandbox)?
+                .map_err(RhaiEffectLoadError::Rhai)?;
+        }
+        if !effect.has_function("settings_schema") {
+            return Err(RhaiEffectLoadError::Effect(
+                InvalidEffectError::MissingSchema,
+            ));
+        }
+        let schema = effect
+            .call::<Dynamic>("settings_schema", (), budget_ms)
+            .map_err(RhaiEffectLoadError::Sandbox)?
+            .map_err(RhaiEffectLoadError::Rhai)?;
+        effect.json_schema = rhai::serde::from_dynamic(&schema)
+            .map_err(|_| RhaiEffectLoadError::Effect(InvalidEffectError::InvalidSchema))?;
+        effect.compiled_json_schema = JSONSchema::compile(&effect.json_schema)
+            .map_err(|_| RhaiEffectLoadError::Effect(InvalidEffectError::InvalidSchema))?;
+
+        if effect.has_function("pixel_requirements") {
+            let requirements = effect
+                .call::<rhai::Map>("pixel_requirements", (), budget_ms)
+                .map_err(RhaiEffectLoadError::Sandbox)?
+                .map_err(RhaiEffectLoadError::Rhai)?;
+            let count = |name: &str| {
+                requirements
+                    .get(name)
+                    .and_then(|count| count.as_int().ok())
+                    .unwrap_or_default() as u32
+            };
+            effect.pixel_requirements = PixelRequirements {
+                min: count("min"),
+                native: count("native"),
+            };
+        }
+        Ok(effect)
+    }
+
+    pub fn pixel_requirements(&self) -> PixelRequirements {
+        self.pixel_requirements
+    }
+
+    /// Json schema of the settings, returned by the `settings_schema` function of the effect
+    pub fn settings_schema(&self) -> &serde_json::Value {
+        &self.json_schema
+    }
+
+    /// Reacts to the fft results of another audio source from the next tick
+    pub fn set_fft_results(
+        &mut self,
+        fft_results: Arc<HashMap<SmoothingProfile, SharedFftResult>>,
+    ) {
+        self.fft_results = fft_results;
+    }
+
+    /// Checks the settings against the schema of the effect. Returns an error per invalid field
+    pub fn validate_settings(&self, settings: &serde_json::Value) -> Result<(), Vec<String>> {
+        super::validate_with_schema(&self.compiled_json_schema, settings)
+    }
+
+    pub fn tick(
+        &mut self,
+        leds: &mut [Color],
+        settings: &LuaEffectSettings,
+        smoothing: SmoothingProfile,
+    ) -> Result<(), Box<EvalAltResult>> {
+        if self.disabled {
+            leds.fill(Color::default());
+            return Ok(());
+        }
+
+        {
+            let mut host = self.host.write().unwrap();
+            host.fft_result = Some(self.fft_results[&smoothing].clone());
+            host.settings = rhai::serde::to_dynamic(&settings.settings)?;
+            host.frame += 1;
+        }
+        *self.leds.0.write().unwrap() = leds.to_vec();
+
+        match self.call::<()>("tick", (self.leds.clone(),), self.sandbox.tick_budget_ms) {
+            Ok(result) => result?,
+            Err(violation) => {
+                tracing::error!(
+                    "Disabled the rhai effect {}, it {violation}",
+                    self.path.display()
+                );
+                self.disabled = true;
+                leds.fill(Color::default());
+                return Ok(());
+            }
+        }
+
+        leds.copy_from_slice(&self.leds.0.read().unwrap());
+        Ok(())
+    }
+
+    /// Calls the optional `settings_changed` function of the script once its settings were
+    /// changed through the control api
+    pub fn settings_changed(
+        &mut self,
+        settings: &LuaEffectSettings,
+    ) -> Result<(), Box<EvalAltResult>> {
+        if self.disabled {
+            return Ok(());
+        }
+        self.host.write().unwrap().settings = rhai::serde::to_dynamic(&settings.settings)?;
+        if !self.has_function("settings_changed") {
+            return Ok(());
+        }
+        match self.call::<()>("settings_changed", (), self.sandbox.tick_budget_ms) {
+            Ok(result) => result,
+            Err(violation) => {
+                tracing::error!(
+                    "Disabled the rhai effect {}, it {violation}",
+                    self.path.display()
+                );
+                self.disabled = true;
+                Ok(())
+            }
+        }
+    }
+
+    fn has_function(&self, name: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|function| function.name == name)
+    }
+
+    // Calls a function of the script with its state as `this` and a budget of `budget_ms`. The
+    // outer error is the violation of the sandbox that stopped the call, if any
+    fn call<T: Clone + Send + Sync + 'static>(
+        &mut self,
+        name: &str,
+        args: impl FuncArgs,
+        budget_ms: u64,
+    ) -> Result<Result<T, Box<EvalAltResult>>, SandboxViolation> {
+        {
+            let mut host = self.host.write().unwrap();
+            host.budget_ms = budget_ms;
+            host.deadline = Some(Instant::now() + Duration::from_millis(budget_ms));
+        }
+        // The top level isn't evaluated again, only the function runs
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let result =
+            self.engine
+                .call_fn_with_options(options, &mut Scope::new(), &self.ast, name, args);
+        self.host.write().unwrap().deadline = None;
+
+        match result {
+            Err(e) => match violation(&e, budget_ms, &self.sandbox) {
+                Some(violation) => Err(violation),
+                None => Ok(Err(e)),
+            },
+            Ok(value) => Ok(Ok(value)),
+        }
+    }
+}
+
+// Violation of the sandbox that stopped a script, looking through the errors of nested calls
+fn violation(
+    error: &EvalAltResult,
+    budget_ms: u64,
+    sandbox: &LuaSandboxConfig,
+) -> Option<SandboxViolation> {
+    match error {
+        EvalAltResult::ErrorInFunctionCall(.., error, _)
+        | EvalAltResult::ErrorInModule(_, error, _) => violation(error, budget_ms, sandbox),
+        EvalAltResult::ErrorTerminated(..) => Some(SandboxViolation::Time(budget_ms)),
+        EvalAltResult::ErrorTooManyOperations(_) => {
+            Some(SandboxViolation::Instructions(sandbox.max_instructions))
+        }
+        EvalAltResult::ErrorDataTooLarge(..) => {
+            Some(SandboxViolation::Memory(sandbox.max_memory_mb))
+        }
+        _ => None,
+    }
+}
+
+// Creates an engine with the api of the effects and the limits of the sandbox
+fn new_engine(
+    host: &Arc<RwLock<Host>>,
+    derived_features: Arc<RwLock<HashMap<String, f32>>>,
+    connections: SharedConnectionInfo,
+    sandbox: LuaSandboxConfig,
+) -> Engine {
+    let mut engine = Engine::new();
+
+    // Modules are only imported from the effects folder
+    engine.set_module_resolver(FileModuleResolver::new_with_path(RHAI_EFFECTS_FOLDER));
+    let memory = sandbox.max_memory_mb * 1024 * 1024;
+    engine
+        .set_max_operations(sandbox.max_instructions)
+        .set_max_string_size(memory)
+        .set_max_array_size(memory / ITEM_SIZE)
+        .set_max_map_size(memory / ITEM_SIZE);
+    let progress_host = host.clone();
+    engine.on_progress(move |operations| {
+        if operations % PROGRESS_CHECK_OPERATIONS != 0 {
+            return None;
+        }
+        let deadline = progress_host.read().unwrap().deadline?;
+        (Instant::now() > deadline).then_some(Dynamic::UNIT)
+    });
+
+    engine
+        .register_type_with_name::<Color>("Color")
+        .register_get_set(
+            "r",
+            |c: &mut Color| c.r as i64,
+            |c: &mut Color, v: i64| c.r = channel(v),
+        )
+        .register_get_set(
+            "g",
+            |c: &mut Color| c.g as i64,
+            |c: &mut Color, v: i64| c.g = channel(v),
+        )
+        .register_get_set(
+            "b",
+            |c: &mut Color| c.b as i64,
+            |c: &mut Color, v: i64| c.b = channel(v),
+        )
+        .register_fn("rgb", |r: i64, g: i64, b: i64| Color {
+            r: channel(r),
+            g: channel(g),
+            b: channel(b),
+        })
+        .register_fn("hsv", hsv)
+        .register_fn("clamp", |x: f64, min: f64, max: f64| x.max(min).min(max))
+        .register_fn("lerp", |a: f64, b: f64, t: f64| a + (b - a) * t);
+
+    engine
+        .register_type_with_name::<Leds>("Leds")
+        .register_fn("len", |leds: &mut Leds| leds.0.read().unwrap().len() as i64)
+        .register_indexer_get_set(
+            |leds: &mut Leds, index: i64| -> Result<Color, Box<EvalAltResult>> {
+                let colors = leds.0.read().unwrap();
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|index| colors.get(index).copied())
+                    .ok_or_else(|| out_of_bounds(colors.len(), index))
+            },
+            |leds: &mut Leds, index: i64, color: Color| -> Result<(), Box<EvalAltResult>> {
+                let mut colors = leds.0.write().unwrap();
+                let len = colors.len();
+                let pixel = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| colors.get_mut(index))
+                    .ok_or_else(|| out_of_bounds(len, index))?;
+                *pixel = color;
+                Ok(())
+            },
+        );
+
+    let settings_host = host.clone();
+    engine.register_fn("settings", move || {
+        settings_host.read().unwrap().settings.clone()
+    });
+    engine.register_fn("feature", move |name: &str| {
+        match derived_features.read().unwrap().get(name) {
+            Some(value) => Dynamic::from_float(*value as f64),
+            None => Dynamic::UNIT,
+        }
+    });
+
+    let frame_host = host.clone();
+    engine.register_fn("frame", move || frame_host.read().unwrap().frame as i64);
+    let time_host = host.clone();
+    engine.register_fn("time", move || {
+        time_host.read().unwrap().frame as f64 / crate::ticks_per_second() as f64
+    });
+    engine.register_fn("dt", || 1.0 / crate::ticks_per_second() as f64);
+    engine.register_fn("sync_time", || crate::sync::clock().time());
+    engine.register_fn("beat_phase", || match crate::sync::clock().beat_phase() {
+        Some(phase) => Dynamic::from_float(phase as f64),
+        None => Dynamic::UNIT,
+    });
+    engine.register_fn("tempo_phase", || match crate::sync::clock().tempo_phase() {
+        Some(phase) => Dynamic::from_float(phase as f64),
+        None => Dynamic::UNIT,
+    });
+    engine.register_fn("bar_phase", || match crate::sync::clock().bar_phase() {
+        Some(phase) => Dynamic::from_float(phase as f64),
+        None => Dynamic::UNIT,
+    });
+    engine.register_fn("bpm", || match crate::sync::clock().bpm() {
+        Some(bpm) => Dynamic::from_float(bpm as f64),
+        None => Dynamic::UNIT,
+    });
+    engine.register_fn("drum", |name: &str| match Drum::parse(name) {
+        Some(drum) => Dynamic::from_float(percussion::drums().get(drum).envelope() as f64),
+        None => Dynamic::UNIT,
+    });
+    engine.register_fn("drum_hits", |name: &str| match Drum::parse(name) {
+        Some(drum) => Dynamic::from_int(percussion::drums().get(drum).count as i64),
+        None => Dynamic::UNIT,
+    });
+    engine.register_fn("track", || {
+        rhai::serde::to_dynamic(now_playing::now_playing()).unwrap_or(Dynamic::UNIT)
+    });
+    engine.register_fn("connections", move || -> rhai::Map {
+        connections
+            .load()
+            .iter()
+            .map(|connection| {
+                let info = rhai::serde::to_dynamic(connection).unwrap_or(Dynamic::UNIT);
+                (connection.id.to_string().into(), info)
+            })
+            .collect()
+    });
+
+    // The fft result is None in `init`, the audio is only read while ticking
+    let fft_host = host.clone();
+    engine.register_fn("average_amplitude", move |low: f64, high: f64| {
+        read_fft(&fft_host, |fft| {
+            fft.get_average_amplitude(low as f32, high as f32)
+        })
+    });
+    let fft_host = host.clone();
+    engine.register_fn("frequency_amplitude", move |frequency: f64| {
+        read_fft(&fft_host, |fft| {
+            fft.get_frequency_amplitude(frequency as f32)
+        })
+    });
+    let fft_host = host.clone();
+    engine.register_fn("max_frequency", move || {
+        read_fft(&fft_host, |fft| Some(fft.get_max_frequency()))
+    });
+    let fft_host = host.clone();
+    engine.register_fn("spectral_flux", move || {
+        read_fft(&fft_host, |fft| Some(fft.spectral_features().flux))
+    });
+    let fft_host = host.clone();
+    engine.register_fn("spectral_centroid", move || {
+        read_fft(&fft_host, |fft| Some(fft.spectral_features().centroid))
+    });
+    let fft_host = host.clone();
+    engine.register_fn("spectral_rolloff", move || {
+        read_fft(&fft_host, |fft| Some(fft.spectral_features().rolloff))
+    });
+    let fft_host = host.clone();
+    engine.register_fn("spectral_flatness", move || {
+        read_fft(&fft_host, |fft| Some(fft.spectral_features().flatness))
+    });
+    let chroma_host = host.clone();
+    engine.register_fn("chroma", move || -> rhai::Array {
+        let chroma = read_chroma(&chroma_host);
+        chroma
+            .pitch_classes
+            .iter()
+            .map(|energy| Dynamic::from_float(*energy as f64))
+            .collect()
+    });
+    let chroma_host = host.clone();
+    engine.register_fn("dominant_note", move || {
+        match read_chroma(&chroma_host).dominant_note() {
+            Some(pitch_class) => Dynamic::from_int(pitch_class as i64),
+            None => Dynamic::UNIT,
+        }
+    });
+    let fft_host = host.clone();
+    engine.register_fn("history", move |low: f64, high: f64, frames_back: i64| {
+        let host = fft_host.read().unwrap();
+        let amplitude = host.fft_result.as_ref().and_then(|fft_result| {
+            fft_result.load().get_past_average_amplitude(
+                usize::try_from(frames_back).ok()?,
+                low as f32,
+                high as f32,
+            )
+        });
+        match amplitude {
+            Some(amplitude) => Dynamic::from_float(amplitude as f64),
+            None => Dynamic::UNIT,
+        }
+    });
+    let fft_host = host.clone();
+    engine.register_fn("history_length", move || {
+        let host = fft_host.read().unwrap();
+        host.fft_result
+            .as_ref()
+            .map_or(0, |fft_result| fft_result.load().history_len() as i64)
+    });
+    let samples_host = host.clone();
+    engine.register_fn("samples", move || read_samples(&samples_host, usize::MAX));
+    let samples_host = host.clone();
+    engine.register_fn("samples", move |count: i64| {
+        read_samples(&samples_host, count.max(0) as usize)
+    });
+    let fft_host = host.clone();
+    engine.register_fn("waveform_rms", move || {
+        read_fft(&fft_host, |fft| Some(fft.waveform_rms(usize::MAX)))
+    });
+    let fft_host = host.clone();
+    engine.register_fn("waveform_rms", move |count: i64| {
+        read_fft(&fft_host, |fft| {
+            Some(fft.waveform_rms(count.max(0) as usize))
+        })
+    });
+    engine
+}
+
+fn read_samples(host: &RwLock<Host>, count: usize) -> rhai::Array {
+    let host = host.read().unwrap();
+    let Some(fft_result) = &host.fft_result else {
+        return rhai::Array::new();
+    };
+    fft_result
+        .load()
+        .samples(count)
+        .iter()
+        .map(|sample| Dynamic::from_float(*sample as f64))
+        .collect()
+}
+
+fn read_chroma(host: &RwLock<Host>) -> Chroma {
+    let host = host.read().unwrap();
+    host.fft_result
+        .as_ref()
+        .map(|fft_result| fft_result.load().chroma())
+        .unwrap_or_default()
+}
+
+fn read_fft(host: &RwLock<Host>, read: impl FnOnce(&FftResult) -> Option<f32>) -> f64 {
+    let host = host.read().unwrap();
+    let Some(fft_result) = &host.fft_result else {
+        return 0.0;
+    };
+    let value = read(&fft_result.load()).unwrap_or_else(|| {
+        tracing::error!("Invalid frequencies given to the fft result");
+        0.0
+    });
+    value as f64
+}
+
+fn out_of_bounds(len: usize, index: i64) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorArrayBounds(len, index, Position::NONE))
+}
+
+fn channel(value: i64) -> u8 {
+    value.clamp(0, 255) as u8
+}
+
+// Converts a hue, saturation and value in [0, 1] to a color. The hue wraps around
+fn hsv(h: f64, s: f64, v: f64) -> Color {
+    let h = (h - h.floor()) * 6.0;
+    let s = s.clamp(0.0, 1.0);
+    let v = v.clamp(0.0, 1.0);
+    let sector = h.floor();
+    let f = h - sector;
+    let (p, q, t) = (v * (1.0 - s), v * (1.0 - s * f), v * (1.0 - s * (1.0 - f)));
+    let (r, g, b) = match sector as u8 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+    let channel = |value: f64| (value * 255.0).round() as u8;
+    Color {
+        r: channel(r),
+        g: channel(g),
+        b: channel(b),
+    }
+}
@@ -0,0 +1,232 @@
+-- Helpers preloaded into every lua effect as the `Turbo` global, also returned by
+-- `require("turbo")`. The frame timing fields are updated by turbo_audio before each `Tick`.
+
+local Turbo = {
+	-- Number of ticks of the effect, 1 in its first `Tick`
+	frame = 0,
+	-- Seconds of frames rendered by the effect, `frame` / ticks per second. It doesn't move
+	-- while the effect is frozen or paused
+	time = 0,
+	-- Seconds between two ticks
+	delta = 0,
+	-- Local time of the day, in seconds since midnight, for the effects following the clock
+	clock = 0,
+	-- Seconds of the time base shared with the other instances, the one of the sync leader when
+	-- following one. The same on every instance, unlike `time`
+	sync_time = 0,
+	-- Position in [0, 1) between two beats of the music, 0 on the beats. nil until the beat is
+	-- known. Follows the beat of the sync leader when following one, and is predicted ahead by the
+	-- calibrated audio to light latency so that flashes on 0 land on the beat heard
+	beat_phase = nil,
+	-- Position in [0, 1) between two beats like `beat_phase`, but running smoothly at the tempo
+	-- rather than jumping when a beat is detected a little early or late, for the sweeps and
+	-- chases locked to the music
+	tempo_phase = nil,
+	-- Position in [0, 1) in a bar of 4 beats. The bars are counted from the first beat heard,
+	-- they don't know where the bars of the music start
+	bar_phase = nil,
+	-- Beats counted at the tempo since it's known, never going back
+	beats = nil,
+	-- Tempo of the music in beats per minute, nil until the beat is known
+	bpm = nil,
+	-- Drums heard, told apart by the bands their hits spread over. `kick`, `snare` and `hat` are
+	-- 1 on a hit and decay to 0 over a few tenths of a second, `kick_hits`, `snare_hits` and
+	-- `hat_hits` count the hits, for the effects that want to act once per hit
+	drums = {
+		kick = 0,
+		snare = 0,
+		hat = 0,
+		kick_hits = 0,
+		snare_hits = 0,
+		hat_hits = 0,
+	},
+	-- Track of the media player followed over MPRIS, nil without one. A table of `title`,
+	-- `artist`, `album`, `player` and `status`, which is "Playing", "Paused" or "Stopped", for
+	-- the effects changing their palette with the track or going dark when the music stops
+	track = nil,
+}
+
+-- `Turbo.connections()` returns the state of the connections to the devices by id, as of the
+-- last frame sent. Tables of `health` ("Connected", "Reconnecting" or "Dead"), `fps`,
+-- `bytes_per_second`, `queued_frames`, `dropped_frames`, `dropped_per_second` and `last_error`,
+-- nil without one, like `GET /info` gives them. Built on every call, so call it once per `Tick`
+
+-- Math
+
+function Turbo.clamp(x, min, max)
+	return math.max(min, math.min(max, x))
+end
+
+function Turbo.lerp(a, b, t)
+	return a + (b - a) * t
+end
+
+-- Maps x from [in_min, in_max] to [out_min, out_max], without clamping
+function Turbo.map(x, in_min, in_max, out_min, out_max)
+	return out_min + (x - in_min) * (out_max - out_min) / (in_max - in_min)
+end
+
+function Turbo.map_clamped(x, in_min, in_max, out_min, out_max)
+	local t = Turbo.clamp((x - in_min) / (in_max - in_min), 0, 1)
+	return Turbo.lerp(out_min, out_max, t)
+end
+
+-- Fractional part, in [0, 1) for negative numbers too
+function Turbo.fract(x)
+	return x - math.floor(x)
+end
+
+-- Colors
+
+-- Converts a hue, saturation and value in [0, 1] to r, g, b in [0, 255]. The hue wraps around
+function Turbo.hsv(h, s, v)
+	h = Turbo.fract(h) * 6
+	s = Turbo.clamp(s, 0, 1)
+	v = Turbo.clamp(v, 0, 1)
+	local sector = math.floor(h)
+	local f = h - sector
+	local p, q, t = v * (1 - s), v * (1 - s * f), v * (1 - s * (1 - f))
+	local r, g, b
+	if sector == 0 then
+		r, g, b = v, t, p
+	elseif sector == 1 then
+		r, g, b = q, v, p
+	elseif sector == 2 then
+		r, g, b = p, v, t
+	elseif sector == 3 then
+		r, g, b = p, q, v
+	elseif sector == 4 then
+		r, g, b = t, p, v
+	else
+		r, g, b = v, p, q
+	end
+	return math.floor(r * 255 + 0.5), math.floor(g * 255 + 0.5), math.floor(b * 255 + 0.5)
+end
+
+-- Converts r, g, b in [0, 255] to a hue, saturation and value in [0, 1]
+function Turbo.rgb_to_hsv(r, g, b)
+	r, g, b = r / 255, g / 255, b / 255
+	local max, min = math.max(r, g, b), math.min(r, g, b)
+	local d = max - min
+	local h = 0
+	if d > 0 then
+		if max == r then
+			h = ((g - b) / d) % 6
+		elseif max == g then
+			h = (b - r) / d + 2
+		else
+			h = (r - g) / d + 4
+		end
+		h = h / 6
+	end
+	local s = 0
+	if max > 0 then
+		s = d / max
+	end
+	return h, s, max
+end
+
+-- Color at t in [0, 1] of a palette of colors like { { r = 255, g = 0, b = 0 }, ... }, spread
+-- evenly from its first to its last color
+function Turbo.palette(colors, t)
+	if #colors == 0 then
+		return 0, 0, 0
+	end
+	if #colors == 1 then
+		return colors[1].r, colors[1].g, colors[1].b
+	end
+	local position = Turbo.clamp(t, 0, 1) * (#colors - 1)
+	local index = math.min(math.floor(position), #colors - 2)
+	local from, to = colors[index + 1], colors[index + 2]
+	local f = position - index
+	return math.floor(Turbo.lerp(from.r, to.r, f) + 0.5),
+		math.floor(Turbo.lerp(from.g, to.g, f) + 0.5),
+		math.floor(Turbo.lerp(from.b, to.b, f) + 0.5)
+end
+
+-- Sets the color of a led of `Colors`, with the channels rounded and clamped to [0, 255]
+function Turbo.set(index, r, g, b)
+	local color = Colors[index]
+	if color == nil then
+		return
+	end
+	color.r = Turbo.clamp(math.floor(r + 0.5), 0, 255)
+	color.g = Turbo.clamp(math.floor(g + 0.5), 0, 255)
+	color.b = Turbo.clamp(math.floor(b + 0.5), 0, 255)
+end
+
+-- Easing functions, from t in [0, 1] to [0, 1]
+
+Turbo.ease = {}
+
+function Turbo.ease.linear(t)
+	return t
+end
+
+function Turbo.ease.in_quad(t)
+	return t * t
+end
+
+function Turbo.ease.out_quad(t)
+	return t * (2 - t)
+end
+
+function Turbo.ease.in_out_quad(t)
+	if t < 0.5 then
+		return 2 * t * t
+	end
+	return 1 - 2 * (1 - t) * (1 - t)
+end
+
+function Turbo.ease.in_cubic(t)
+	return t * t * t
+end
+
+function Turbo.ease.out_cubic(t)
+	return 1 - (1 - t) ^ 3
+end
+
+function Turbo.ease.in_out_cubic(t)
+	if t < 0.5 then
+		return 4 * t * t * t
+	end
+	return 1 - 4 * (1 - t) ^ 3
+end
+
+function Turbo.ease.in_out_sine(t)
+	return (1 - math.cos(math.pi * t)) / 2
+end
+
+-- Noise
+
+-- Pseudo random value in [0, 1) for an integer
+local function hash(n)
+	return Turbo.fract(math.sin(n * 12.9898) * 43758.5453)
+end
+
+-- Smooth 1D value noise in [0, 1]. Different seeds give unrelated noises
+function Turbo.noise(x, seed)
+	seed = seed or 0
+	local cell = math.floor(x)
+	local f = x - cell
+	local a, b = hash(cell + seed * 1013), hash(cell + 1 + seed * 1013)
+	return Turbo.lerp(a, b, f * f * (3 - 2 * f))
+end
+
+-- Frame timing
+
+-- Position in [0, 1) in a cycle repeating `rate` times per second
+function Turbo.phase(rate)
+	return Turbo.fract(Turbo.time * rate)
+end
+
+-- Moves `current` toward `target` by `rate` per second, without overshooting
+function Turbo.approach(current, target, rate)
+	local step = rate * Turbo.delta
+	if current < target then
+		return math.min(current + step, target)
+	end
+	return math.max(current - step, target)
+end
+
+return Turbo
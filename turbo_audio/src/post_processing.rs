@@ -0,0 +1,304 @@
+use crate::{resources::ledstrip::Pixel, simd};
+use serde::{Deserialize, Serialize};
+
+/// A stage of the post-processing chain, as written in the config of a ledstrip
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum PostProcessingStage {
+    /// Global brightness multiplied by the brightness of the ledstrip
+    Brightness,
+    /// Maps the linear colors of the effects to the perceived brightness of the leds
+    Gamma {
+        #[serde(
+            default = "default_gamma",
+            deserialize_with = "crate::config_parser::positive"
+        )]
+        gamma: f32,
+    },
+    /// Scales each channel (0 to 1) to correct the tint of the leds
+    WhiteBalance {
+        #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+        r: f32,
+        #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+        g: f32,
+        #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+        b: f32,
+    },
+    /// Dims the whole frame when it would draw more than `max_power` (0 to 1) of the power of a
+    /// fully white ledstrip
+    Limiter {
+        #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+        max_power: f32,
+    },
+    /// Carries the rounding error of each pixel over to the next frames so that dim colors keep
+    /// their shade instead of banding
+    Dithering,
+}
+
+// 65535 / 255, so that 255 maps to the highest 16-bit value
+const U16_PER_U8: f32 = 257.0;
+
+fn default_gamma() -> f32 {
+    2.2
+}
+
+/// Correction of the colors of a ledstrip for its batch of leds, so that ledstrips of different
+/// batches show the same color for the same rgb. Applied after the other stages, except the
+/// dithering, as it corrects what the leds output rather than the colors of the effects
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ColorCalibration {
+    /// Gain of each channel (0 to 1), to tone down a channel brighter than on the other strips
+    #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+    pub r: f32,
+    #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+    pub g: f32,
+    #[serde(deserialize_with = "crate::config_parser::unit_interval")]
+    pub b: f32,
+    /// Color temperature in kelvin of the white the leds show for a full white, like 8000 for
+    /// bluish leds. Not corrected if missing
+    #[serde(deserialize_with = "crate::config_parser::optional_color_temperature")]
+    pub white_point: Option<f32>,
+    /// Color temperature in kelvin the white point is corrected to
+    #[serde(deserialize_with = "crate::config_parser::color_temperature")]
+    pub target_white: f32,
+}
+
+impl Default for ColorCalibration {
+    fn default() -> Self {
+        Self {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+            white_point: None,
+            target_white: 6500.0,
+        }
+    }
+}
+
+impl ColorCalibration {
+    /// Gain of each channel, between 0 and 1
+    pub fn gains(&self) -> [f32; 3] {
+        let white = match self.white_point {
+            Some(white_point) => white_shift(white_point, self.target_white),
+            None => [1.0; 3],
+        };
+        let gains = [self.r, self.g, self.b];
+        [0, 1, 2].map(|channel| gains[channel].clamp(0.0, 1.0) * white[channel])
+    }
+}
+
+/// Gains of the channels turning the white of a color temperature in kelvin into the white of
+/// another one. The leds can't go above full, so the brightest channel is kept and the others
+/// are dimmed
+pub fn white_shift(from_kelvin: f32, to_kelvin: f32) -> [f32; 3] {
+    let (from, to) = (color_temperature(from_kelvin), color_temperature(to_kelvin));
+    let gains = [0, 1, 2].map(|channel| to[channel] / from[channel].max(0.01));
+    let max = gains.into_iter().fold(f32::EPSILON, f32::max);
+    gains.map(|gain| gain / max)
+}
+
+/// Rgb of a black body at a temperature in kelvin, with channels between 0 and 1 and the brightest
+/// at 1. An approximation of the Planckian locus good enough for lights, from 1000K to 40000K
+pub fn color_temperature(kelvin: f32) -> [f32; 3] {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.69873 * (t - 60.0).powf(-0.13320476)
+    };
+    let g = if t <= 66.0 {
+        99.4708 * t.ln() - 161.11957
+    } else {
+        288.12216 * (t - 60.0).powf(-0.07551485)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * (t - 10.0).ln() - 305.0448
+    };
+    [r, g, b].map(|channel| (channel / 255.0).clamp(0.0, 1.0))
+}
+
+/// Chain used by the ledstrips that don't configure one, which only applies the brightness
+pub fn default_stages() -> Vec<PostProcessingStage> {
+    vec![PostProcessingStage::Brightness]
+}
+
+/// Values the stages may need that aren't part of the frame
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessingContext {
+    /// Global brightness multiplied by the brightness of the ledstrip, between 0 and 1
+    pub brightness: f32,
+}
+
+/// A step applied to the frame of a ledstrip before it is sent. The channels of the pixels are
+/// between 0 and 255 but aren't rounded until the end of the chain, to 16 bits, so dim colors keep
+/// their precision until the output stage.
+pub trait PostProcessor: Send {
+    fn process(&mut self, pixels: &mut [[f32; 3]], context: &ProcessingContext);
+}
+
+struct Brightness;
+
+impl PostProcessor for Brightness {
+    fn process(&mut self, pixels: &mut [[f32; 3]], context: &ProcessingContext) {
+        if context.brightness < 1.0 {
+            simd::scale(pixels.as_flattened_mut(), context.brightness);
+        }
+    }
+}
+
+struct Gamma {
+    gamma: f32,
+}
+
+impl PostProcessor for Gamma {
+    fn process(&mut self, pixels: &mut [[f32; 3]], _context: &ProcessingContext) {
+        simd::gamma(pixels.as_flattened_mut(), self.gamma);
+    }
+}
+
+struct WhiteBalance {
+    scale: [f32; 3],
+}
+
+impl PostProcessor for WhiteBalance {
+    fn process(&mut self, pixels: &mut [[f32; 3]], _context: &ProcessingContext) {
+        simd::scale_rgb(pixels, self.scale);
+    }
+}
+
+struct Limiter {
+    max_power: f32,
+}
+
+impl PostProcessor for Limiter {
+    fn process(&mut self, pixels: &mut [[f32; 3]], _context: &ProcessingContext) {
+        // Leds draw roughly as much current as the sum of their channels
+        let max = self.max_power * pixels.len() as f32 * 3.0 * 255.0;
+        let total = simd::sum(pixels.as_flattened());
+        if total > max {
+            simd::scale(pixels.as_flattened_mut(), max / total);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Dithering {
+    // Rounding error of each channel from the previous frame
+    errors: Vec<[f32; 3]>,
+}
+
+impl PostProcessor for Dithering {
+    fn process(&mut self, pixels: &mut [[f32; 3]], _context: &ProcessingContext) {
+        self.errors.resize(pixels.len(), [0.0; 3]);
+        for (pixel, error) in pixels.iter_mut().zip(&mut self.errors) {
+            for (channel, error) in pixel.iter_mut().zip(error.iter_mut()) {
+                let value = *channel + *error;
+                *channel = value.round().clamp(0.0, 255.0);
+                *error = value - *channel;
+            }
+        }
+    }
+}
+
+/// Ordered stages turning the colors rendered for a ledstrip into the 16-bit frame given to the
+/// output stage.
+///
+/// Custom processors implement [`PostProcessor`] and are added with
+/// [`PostProcessingChain::push`].
+pub struct PostProcessingChain {
+    stages: Vec<Box<dyn PostProcessor>>,
+    pixels: Vec<[f32; 3]>,
+    output: Vec<u16>,
+}
+
+impl PostProcessingChain {
+    pub fn new(stages: &[PostProcessingStage], calibration: &ColorCalibration) -> Self {
+        let mut chain = Self {
+            stages: Vec::with_capacity(stages.len() + 1),
+            pixels: Vec::new(),
+            output: Vec::new(),
+        };
+        let gains = calibration.gains();
+        let mut calibrated = gains == [1.0; 3];
+        for stage in stages {
+            // Before the dithering, which carries the rounding error of the final values
+            if *stage == PostProcessingStage::Dithering && !calibrated {
+                chain.push(Box::new(WhiteBalance { scale: gains }));
+                calibrated = true;
+            }
+            chain.push(match *stage {
+                PostProcessingStage::Brightness => Box::new(Brightness),
+                PostProcessingStage::Gamma { gamma } => Box::new(Gamma { gamma }),
+                PostProcessingStage::WhiteBalance { r, g, b } => Box::new(WhiteBalance {
+                    scale: [r, g, b].map(|scale| scale.clamp(0.0, 1.0)),
+                }),
+                PostProcessingStage::Limiter { max_power } => Box::new(Limiter {
+                    max_power: max_power.clamp(0.0, 1.0),
+                }),
+                PostProcessingStage::Dithering => Box::<Dithering>::default(),
+            });
+        }
+        if !calibrated {
+            chain.push(Box::new(WhiteBalance { scale: gains }));
+        }
+        chain
+    }
+
+    /// Appends a processor to the end of the chain
+    pub fn push(&mut self, processor: Box<dyn PostProcessor>) {
+        self.stages.push(processor);
+    }
+
+    /// Runs the pixels through every stage and returns the rgb channels on 16 bits. The buffers
+    /// are kept from one frame to the next
+    pub fn process(&mut self, pixels: &[Pixel], context: &ProcessingContext) -> &[u16] {
+        self.pixels.clear();
+        self.pixels.extend_from_slice(pixels);
+        for stage in &mut self.stages {
+            stage.process(&mut self.pixels, context);
+        }
+        simd::to_u16(self.pixels.as_flattened(), &mut self.output);
+        &self.output
+    }
+}
+
+impl Default for PostProcessingChain {
+    fn default() -> Self {
+        Self::new(&default_stages(), &ColorCalibration::default())
+    }
+}
+
+/// Rounds a 16-bit frame to the 8 bits of the leds, into `output`
+pub fn quantize(frame: &[u16], output: &mut Vec<u8>) {
+    simd::to_u8(frame, output);
+}
+
+/// Output stage bringing the 16-bit frames of a ledstrip down to 8 bits while carrying the
+/// rounding error of each channel over to the next frames. A channel between two levels alternates
+/// between them and looks like the level in between, which smooths slow dark fades on leds
+/// refreshed fast enough for the flicker not to show, like WS2812 strips.
+#[derive(Debug, Default)]
+pub struct TemporalDithering {
+    // Rounding error of each channel from the previous frame, in 8-bit levels
+    errors: Vec<f32>,
+}
+
+impl TemporalDithering {
+    /// Brings the frame down to 8 bits into `output`
+    pub fn apply(&mut self, frame: &[u16], output: &mut Vec<u8>) {
+        self.errors.resize(frame.len(), 0.0);
+        output.clear();
+        output.extend(frame.iter().zip(&mut self.errors).map(|(channel, error)| {
+            let value = *channel as f32 / U16_PER_U8 + *error;
+            let level = value.round().clamp(0.0, 255.0);
+            *error = value - level;
+            level as u8
+        }));
+    }
+}
@@ -0,0 +1,111 @@
+use serde::Deserialize;
+use std::{
+    io::{BufRead, Write},
+    time::Duration,
+};
+use thiserror::Error;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum ReplError {
+    #[error("Couldn't reach the engine: {0}. Is the http server enabled in the settings?")]
+    Connect(Box<ureq::Transport>),
+
+    #[error("Http error {0}: {1}")]
+    Http(u16, String),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ReplArgs {
+    /// Effect whose environment the lines are evaluated in
+    pub effect_id: usize,
+
+    /// Address of the http server of the running instance, which may be on another machine
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    pub url: String,
+
+    /// Allow the console to change the globals of the effect
+    #[arg(long, requires = "token")]
+    pub write: bool,
+
+    /// `write_token` of the http settings of the instance, needed to write
+    #[arg(long)]
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Lua console in the environment of an effect of a running instance, through its http server
+pub fn run(args: &ReplArgs) -> Result<(), ReplError> {
+    let url = format!(
+        "{}/effects/{}/eval",
+        args.url.trim_end_matches('/'),
+        args.effect_id
+    );
+    console(args.effect_id, args.write, |code| {
+        let mut request = ureq::post(&url).timeout(REQUEST_TIMEOUT);
+        if let Some(token) = &args.token {
+            request = request.set("Authorization", &format!("Bearer {token}"));
+        }
+        let response = request.send_json(serde_json::json!({ "code": code, "write": args.write }));
+        match response {
+            Ok(response) => Ok(Ok(response.into_json()?)),
+            // The engine answers the errors of the lua with a bad request
+            Err(ureq::Error::Status(400, response)) => {
+                Ok(Err(response.into_json::<ErrorBody>()?.error))
+            }
+            Err(ureq::Error::Status(status, response)) => Err(ReplError::Http(
+                status,
+                response.into_string().unwrap_or_default(),
+            )),
+            Err(ureq::Error::Transport(e)) => Err(ReplError::Connect(e.into())),
+        }
+    })
+}
+
+/// Reads lines of lua from stdin and prints what `eval` answers for them, until ctrl-d. A line
+/// that doesn't make a complete chunk, like the start of a function, is continued on the next
+/// ones
+pub fn console<E: From<std::io::Error>>(
+    effect_id: usize,
+    write: bool,
+    mut eval: impl FnMut(&str) -> Result<Result<String, String>, E>,
+) -> Result<(), E> {
+    let mode = if write { "read-write" } else { "read-only" };
+    println!("Lua console for effect {effect_id} ({mode}). Ctrl-D to quit.");
+    let stdin = std::io::stdin();
+    let mut code = String::new();
+    loop {
+        if code.is_empty() {
+            print!("lua[{effect_id}]> ");
+        } else {
+            print!("lua[{effect_id}]>> ");
+        }
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        if code.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        code.push_str(&line);
+
+        match eval(code.trim_end())? {
+            Ok(result) if result.is_empty() => {}
+            Ok(result) => println!("{result}"),
+            // Lua reports the chunks cut short as an error at the end of the input
+            Err(error) if error.contains("<eof>") && !line.trim().is_empty() => continue,
+            Err(error) => eprintln!("error: {error}"),
+        }
+        code.clear();
+    }
+}
@@ -0,0 +1,402 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+use turbo_plugin::Color;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ColorError {
+    #[error("Unknown color name {0:?}")]
+    UnknownName(String),
+
+    #[error("Invalid hex color {0:?}, like #FF8800 or #F80")]
+    InvalidHex(String),
+
+    #[error("{0} of the color is {1}, it must be between {2} and {3}")]
+    OutOfRange(&'static str, f64, f64, f64),
+
+    #[error(
+        "Expected a color like \"#FF8800\", \"orange\", {{\"r\": 255, \"g\": 136, \"b\": 0}} or \
+         {{\"h\": 30, \"s\": 1, \"v\": 1}}, found {0}"
+    )]
+    Invalid(String),
+}
+
+/// Color of the config or of the settings of an effect, written as a hex string like `"#FF8800"`
+/// or `"#F80"`, a css name like `"orange"`, `{"r": 255, "g": 136, "b": 0}`, or
+/// `{"h": 30, "s": 1, "v": 1}` with the hue in degrees and the saturation and value between 0
+/// and 1. Serialized as a hex string
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ConfigColor(pub Color);
+
+impl FromStr for ConfigColor {
+    type Err = ColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(hex) = s.strip_prefix('#') {
+            return parse_hex(hex)
+                .map(Self)
+                .ok_or_else(|| ColorError::InvalidHex(s.to_owned()));
+        }
+        // Like `dark_orange` or `Dark Orange` for `darkorange`
+        let name: String = s
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '_' | '-'))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        NAMED_COLORS
+            .binary_search_by(|(known, _)| known.cmp(&name.as_str()))
+            .map(|index| Self(from_rgb(NAMED_COLORS[index].1)))
+            .map_err(|_| ColorError::UnknownName(s.to_owned()))
+    }
+}
+
+impl TryFrom<&serde_json::Value> for ConfigColor {
+    type Error = ColorError;
+
+    fn try_from(value: &serde_json::Value) -> Result<Self, Self::Error> {
+        if let Some(s) = value.as_str() {
+            return s.parse();
+        }
+        let field = |name| value.get(name).and_then(serde_json::Value::as_f64);
+        let is_exactly = |names: &[&str]| {
+            value.as_object().is_some_and(|map| {
+                map.len() == names.len() && names.iter().all(|name| map.contains_key(*name))
+            })
+        };
+        if is_exactly(&["r", "g", "b"]) {
+            if let (Some(r), Some(g), Some(b)) = (field("r"), field("g"), field("b")) {
+                let channel = |name, value| {
+                    in_range(name, value, 0.0, 255.0).map(|value| value.round() as u8)
+                };
+                return Ok(Self(Color {
+                    r: channel("r", r)?,
+                    g: channel("g", g)?,
+                    b: channel("b", b)?,
+                }));
+            }
+        }
+        if is_exactly(&["h", "s", "v"]) {
+            if let (Some(h), Some(s), Some(v)) = (field("h"), field("s"), field("v")) {
+                return Ok(Self(from_hsv(
+                    in_range("h", h, 0.0, 360.0)?,
+                    in_range("s", s, 0.0, 1.0)?,
+                    in_range("v", v, 0.0, 1.0)?,
+                )));
+            }
+        }
+        Err(ColorError::Invalid(value.to_string()))
+    }
+}
+
+impl fmt::Display for ConfigColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Color { r, g, b } = self.0;
+        write!(f, "#{r:02X}{g:02X}{b:02X}")
+    }
+}
+
+impl Serialize for ConfigColor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ConfigColor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        Self::try_from(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Rewrites the colors of json settings written as hex strings, names or hsv into the
+/// `{"r", "g", "b"}` objects the schema of the effect declares, which is how the effects read
+/// them. Returns whether anything was rewritten, or an error per invalid color
+pub fn normalize_colors(
+    schema: &serde_json::Value,
+    settings: &mut serde_json::Value,
+) -> Result<bool, Vec<String>> {
+    let mut errors = Vec::new();
+    let changed = normalize(schema, settings, &mut String::new(), &mut errors);
+    match errors.is_empty() {
+        true => Ok(changed),
+        false => Err(errors),
+    }
+}
+
+fn normalize(
+    schema: &serde_json::Value,
+    value: &mut serde_json::Value,
+    path: &mut String,
+    errors: &mut Vec<String>,
+) -> bool {
+    if is_color_schema(schema) {
+        // Already written the way the effect reads it, the schema checks the channels
+        let is_rgb = value.as_object().is_some_and(|map| {
+            ["r", "g", "b"]
+                .iter()
+                .all(|channel| map.contains_key(*channel))
+        });
+        if is_rgb {
+            return false;
+        }
+        return match ConfigColor::try_from(&*value) {
+            Ok(ConfigColor(Color { r, g, b })) => {
+                *value = serde_json::json!({ "r": r, "g": g, "b": b });
+                true
+            }
+            Err(e) => {
+                let field = if path.is_empty() { "/" } else { path };
+                errors.push(format!("{field}: {e}"));
+                false
+            }
+        };
+    }
+
+    let mut changed = false;
+    let length = path.len();
+    match value {
+        serde_json::Value::Object(map) => {
+            let Some(properties) = schema.get("properties") else {
+                return false;
+            };
+            for (key, value) in map {
+                if let Some(schema) = properties.get(key) {
+                    path.push('/');
+                    path.push_str(key);
+                    changed |= normalize(schema, value, path, errors);
+                    path.truncate(length);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            let Some(schema) = schema.get("items") else {
+                return false;
+            };
+            for (index, item) in items.iter_mut().enumerate() {
+                path.push_str(&format!("/{index}"));
+                changed |= normalize(schema, item, path, errors);
+                path.truncate(length);
+            }
+        }
+        _ => {}
+    }
+    changed
+}
+
+// Objects of the r, g and b channels
+fn is_color_schema(schema: &serde_json::Value) -> bool {
+    schema.get("type").and_then(serde_json::Value::as_str) == Some("object")
+        && schema.get("properties").is_some_and(|properties| {
+            ["r", "g", "b"]
+                .iter()
+                .all(|channel| properties.get(channel).is_some())
+        })
+}
+
+fn in_range(name: &'static str, value: f64, min: f64, max: f64) -> Result<f64, ColorError> {
+    match (min..=max).contains(&value) {
+        true => Ok(value),
+        false => Err(ColorError::OutOfRange(name, value, min, max)),
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        // Each digit doubled, like #F80 for #FF8800
+        3 => {
+            let digit = |shift: u32| ((rgb >> shift) & 0xF) as u8 * 0x11;
+            Some(Color {
+                r: digit(8),
+                g: digit(4),
+                b: digit(0),
+            })
+        }
+        6 => Some(from_rgb(rgb)),
+        _ => None,
+    }
+}
+
+fn from_rgb(rgb: u32) -> Color {
+    Color {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    }
+}
+
+// Hue in degrees, saturation and value between 0 and 1
+fn from_hsv(h: f64, s: f64, v: f64) -> Color {
+    let sector = (h / 60.0).rem_euclid(6.0);
+    let chroma = v * s;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let channel = |value: f64| ((value + v - chroma) * 255.0).round() as u8;
+    Color {
+        r: channel(r),
+        g: channel(g),
+        b: channel(b),
+    }
+}
+
+// Named colors of css, sorted by name
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
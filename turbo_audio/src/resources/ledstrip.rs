@@ -1,37 +1,451 @@
+use crate::{
+    audio::smoothing::SmoothingProfile, output_transform::OutputTransform,
+    overrides::DEFAULT_PRIORITY,
+};
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
-use turbo_plugin::Color;
+use thiserror::Error;
+use turbo_plugin::{effect_plugin::PixelRequirements, Color};
 
 pub type EffectInterval = (usize, usize);
+
+/// Color of a led once rendered, with channels between 0 and 255 that aren't rounded. The effects
+/// render 8-bit colors but the resampling, the blur and the post processing work on pixels, so
+/// their rounding errors don't add up into banding before the output stage.
+pub type Pixel = [f32; 3];
+
+pub fn to_pixel(color: &Color) -> Pixel {
+    [color.r as f32, color.g as f32, color.b as f32]
+}
+
+/// Rounds a pixel to the 8-bit color of the effects and previews
+pub fn to_color(pixel: &Pixel) -> Color {
+    let [r, g, b] = pixel.map(|channel| channel.round().clamp(0.0, 255.0) as u8);
+    Color { r, g, b }
+}
+
+/// Most pixels a ledstrip renders or leds it lights, far more than any real strip, so that a
+/// mistake in the settings fails to load instead of taking the whole memory
+pub const MAX_PIXELS: usize = 1 << 20;
+
+/// Why a ledstrip of the settings can't be built
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum LedStripError {
+    #[error("It has more than {MAX_PIXELS} pixels or leds")]
+    TooManyPixels,
+
+    #[error("Segment {segment}, rendering effect {effect_id}: {error}")]
+    Segment {
+        segment: usize,
+        effect_id: usize,
+        error: SegmentError,
+    },
+}
+
+/// Why a segment doesn't fit on its ledstrip
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SegmentError {
+    #[error("The segment doesn't have any leds")]
+    Empty,
+
+    #[error("The segment ends at led {end}, past the {size} leds of the ledstrip")]
+    OutOfBounds { end: usize, size: usize },
+
+    #[error("The skipped leds {0}-{1} aren't within the {2} leds of the segment")]
+    InvalidSkipped(usize, usize, usize),
+}
+
+/// What to do when an effect is assigned to a segment smaller than the minimum it declared
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UndersizedPolicy {
+    /// Render the effect at its native size and resample it to the segment
+    #[default]
+    Resample,
+    /// Render the effect on the segment anyway
+    Warn,
+}
+
+impl UndersizedPolicy {
+    /// Number of pixels the effect should be rendered on for a segment of `segment_size` pixels
+    pub fn render_size(self, requirements: PixelRequirements, segment_size: usize) -> usize {
+        let min = requirements.min as usize;
+        if self == UndersizedPolicy::Warn || segment_size >= min {
+            return segment_size;
+        }
+        (requirements.native as usize).max(min)
+    }
+}
+
+/// What a ledstrip shows in place of its effects when they can't render as they should
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FallbackScene {
+    /// Keeps the colors the leds had, black at startup
+    LastFrame,
+    Off,
+    /// Solid color, like `{"Color": [255, 140, 40]}`
+    Color([u8; 3]),
+}
+
+impl FallbackScene {
+    /// Shows the scene on the pixels
+    pub fn apply(self, pixels: &mut [Pixel]) {
+        match self {
+            FallbackScene::LastFrame => {}
+            FallbackScene::Off => pixels.fill(Pixel::default()),
+            FallbackScene::Color([r, g, b]) => pixels.fill([r as f32, g as f32, b as f32]),
+        }
+    }
+}
+
+/// Scenes of a ledstrip for when its effects can't render. The effects render anyway when a
+/// scene is missing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FallbackScenes {
+    /// Shown until the audio arrives
+    pub startup: Option<FallbackScene>,
+    /// Shown while the audio source is lost, and on the segments whose effect fails
+    pub failure: Option<FallbackScene>,
+}
+
+/// How the pixels of an effect are laid on its segment
+#[derive(Debug, Default, Clone)]
+pub struct SegmentLayout {
+    /// The first pixel of the effect is on the last led of the segment
+    pub reversed: bool,
+    /// The effect is rendered on half of the segment and mirrored, its first pixel being in the
+    /// center
+    pub mirrored: bool,
+    /// Inclusive ranges of leds, relative to the start of the segment, that stay off and that the
+    /// effect skips over, like dead leds or gaps between physical strips
+    pub skipped: Vec<EffectInterval>,
+}
+
+impl SegmentLayout {
+    // Indices in the segment of the leds the effect renders on, in order
+    fn live_leds(&self, segment_size: usize) -> Vec<usize> {
+        let mut leds: Vec<usize> = (0..segment_size)
+            .filter(|led| {
+                !self
+                    .skipped
+                    .iter()
+                    .any(|(start, end)| (*start..=*end).contains(led))
+            })
+            .collect();
+        if self.reversed {
+            leds.reverse();
+        }
+        leds
+    }
+
+    /// Number of pixels the effect renders on for a segment of `segment_size` leds
+    pub fn effect_size(&self, segment_size: usize) -> usize {
+        let live = self.live_leds(segment_size).len();
+        if self.mirrored {
+            live.div_ceil(2)
+        } else {
+            live
+        }
+    }
+
+    // For each pixel of the effect, the leds of the segment it lights
+    fn pixel_leds(&self, segment_size: usize) -> Vec<Vec<usize>> {
+        let leds = self.live_leds(segment_size);
+        if !self.mirrored {
+            return leds.into_iter().map(|led| vec![led]).collect();
+        }
+        let center = leds.len() / 2;
+        (0..leds.len().div_ceil(2))
+            .map(|pixel| {
+                let mut pixel_leds = vec![leds[center + pixel]];
+                if let Some(led) = (leds.len() - 1 - center)
+                    .checked_sub(pixel)
+                    .map(|index| leds[index])
+                    .filter(|led| !pixel_leds.contains(led))
+                {
+                    pixel_leds.push(led);
+                }
+                pixel_leds
+            })
+            .collect()
+    }
+
+    fn is_identity(&self) -> bool {
+        !self.reversed && !self.mirrored && self.skipped.is_empty()
+    }
+
+    /// The pixels of the effect, as they are on the `segment`, for the effects building on their
+    /// previous frame
+    pub fn gather<T: Copy>(&self, segment: &[T]) -> Vec<T> {
+        if self.is_identity() {
+            return segment.to_vec();
+        }
+        self.pixel_leds(segment.len())
+            .iter()
+            .map(|leds| segment[leds[0]])
+            .collect()
+    }
+
+    /// Lays the `pixels` of the effect on the `segment` and turns off the skipped leds
+    pub fn scatter<T: Copy + Default>(&self, pixels: &[T], segment: &mut [T]) {
+        if self.is_identity() {
+            segment.copy_from_slice(pixels);
+            return;
+        }
+        segment.fill(T::default());
+        for (leds, color) in self.pixel_leds(segment.len()).iter().zip(pixels) {
+            for led in leds {
+                segment[*led] = *color;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LedStripEffect {
+    pub effect_id: usize,
+    pub interval: EffectInterval,
+    pub smoothing: SmoothingProfile,
+    pub undersized: UndersizedPolicy,
+    pub blur: Option<GaussianBlur>,
+    pub layout: SegmentLayout,
+    /// Overrides of a higher priority replace the effect of the segment while they're set
+    pub priority: u8,
+}
+
 #[derive(Debug, Default)]
 pub struct LedStrip {
+    /// Pixels the effects render on, the leds of the strip unless it has a transform
     pub size: usize,
-    pub colors: Vec<Color>,
-    pub effects: Vec<(usize, EffectInterval)>,
+    pub colors: Vec<Pixel>,
+    pub effects: Vec<LedStripEffect>,
+    /// Turns the pixels into the leds of the strip when there are more or fewer of them
+    pub transform: Option<OutputTransform>,
+    pub scenes: FallbackScenes,
     used_led_count: usize,
 }
 
 impl LedStrip {
+    /// Leds of the strip, in the frames sent to its connection
+    pub fn led_count(&self) -> usize {
+        self.transform
+            .as_ref()
+            .map_or(self.size, OutputTransform::led_count)
+    }
+
     pub fn set_led_count(&mut self, size: usize) {
         self.size = size;
         let mut to_remove = HashSet::new();
-        for (effect_id, interval) in &self.effects {
-            if interval.1 >= size {
-                to_remove.insert(*effect_id);
+        for effect in &self.effects {
+            if effect.interval.1 >= size {
+                to_remove.insert(effect.effect_id);
             }
         }
         self.effects
-            .retain(|(effect_id, _interval)| !to_remove.contains(effect_id));
-        self.colors.resize(size, Color::default());
+            .retain(|effect| !to_remove.contains(&effect.effect_id));
+        self.colors.resize(size, Pixel::default());
+    }
+
+    /// Adds a segment of `size` leds after the last one. Fails if it doesn't fit on the strip
+    pub fn add_effect(
+        &mut self,
+        effect_id: usize,
+        size: usize,
+        smoothing: SmoothingProfile,
+        undersized: UndersizedPolicy,
+        blur_radius: f32,
+        layout: SegmentLayout,
+    ) -> Result<(), SegmentError> {
+        if size == 0 {
+            return Err(SegmentError::Empty);
+        }
+        // The size comes from the settings or the control api, so it can be anything
+        let Some(end) = self
+            .used_led_count
+            .checked_add(size - 1)
+            .filter(|end| *end < self.size)
+        else {
+            return Err(SegmentError::OutOfBounds {
+                end: self.used_led_count.saturating_add(size - 1),
+                size: self.size,
+            });
+        };
+        if let Some((start, end)) = layout
+            .skipped
+            .iter()
+            .find(|(start, end)| start > end || *end >= size)
+        {
+            return Err(SegmentError::InvalidSkipped(*start, *end, size));
+        }
+
+        let interval = (self.used_led_count, end);
+        self.effects.push(LedStripEffect {
+            effect_id,
+            interval,
+            smoothing,
+            undersized,
+            blur: GaussianBlur::new(blur_radius, size),
+            layout,
+            priority: DEFAULT_PRIORITY,
+        });
+        self.used_led_count = end + 1;
+        Ok(())
+    }
+
+    /// Sets the priority of the `segment`th effect interval of the strip, below which overrides
+    /// replace its effect
+    pub fn set_priority(&mut self, segment: usize, priority: u8) -> bool {
+        match self.effects.get_mut(segment) {
+            Some(effect) => {
+                effect.priority = priority;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces the effect rendered on the `segment`th effect interval of the strip.
+    pub fn set_effect(&mut self, segment: usize, effect_id: usize) -> bool {
+        match self.effects.get_mut(segment) {
+            Some(effect) => {
+                effect.effect_id = effect_id;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Fits `source` into `target` by averaging (or repeating) the pixels that land on each pixel
+pub fn resample(source: &[Pixel], target: &mut [Pixel]) {
+    if source.is_empty() {
+        target.fill(Pixel::default());
+        return;
+    }
+
+    let target_len = target.len();
+    for (index, pixel) in target.iter_mut().enumerate() {
+        let start = index * source.len() / target_len;
+        let end = ((index + 1) * source.len() / target_len).max(start + 1);
+        let pixels = &source[start..end];
+        *pixel = [0, 1, 2].map(|channel| {
+            pixels.iter().map(|pixel| pixel[channel]).sum::<f32>() / pixels.len() as f32
+        });
+    }
+}
+
+/// Spatial smoothing of a run of pixels, which softens effects lighting single pixels on dense
+/// strips
+#[derive(Debug, Clone)]
+pub struct GaussianBlur {
+    // Weights of the pixels from the center to `radius` pixels away
+    kernel: Vec<f32>,
+    // Copy of the pixels being blurred, kept from one frame to the next
+    scratch: Vec<Pixel>,
+}
+
+impl GaussianBlur {
+    /// Blur reaching `radius` pixels on each side of runs of up to `len` pixels, or None if it
+    /// wouldn't reach any neighbour
+    pub fn new(radius: f32, len: usize) -> Option<Self> {
+        if !radius.is_finite() || radius < 1.0 || len < 2 {
+            return None;
+        }
+        let sigma = radius / 2.0;
+        // The weights past the other end of the run would never be used
+        let kernel = (0..=(radius as usize).min(len - 1))
+            .map(|distance| (-((distance * distance) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        Some(Self {
+            kernel,
+            scratch: Vec::new(),
+        })
     }
 
-    pub fn add_effect(&mut self, effect_id: usize, size: usize) -> bool {
-        if self.used_led_count + size > self.size {
-            return false;
+    /// Blurs `pixels` in place. The pixels past the ends don't count, so the edges of the segment
+    /// keep their brightness and nothing bleeds from the neighbouring segments.
+    pub fn apply(&mut self, pixels: &mut [Pixel]) {
+        self.scratch.clear();
+        self.scratch.extend_from_slice(pixels);
+        let source = &self.scratch;
+        let radius = self.kernel.len() - 1;
+        for (index, pixel) in pixels.iter_mut().enumerate() {
+            let start = index.saturating_sub(radius);
+            let end = (index + radius + 1).min(source.len());
+            let mut sum = [0.0f32; 3];
+            let mut total_weight = 0.0;
+            for (neighbour, color) in source[start..end].iter().enumerate() {
+                let weight = self.kernel[(start + neighbour).abs_diff(index)];
+                for (sum, channel) in sum.iter_mut().zip(color) {
+                    *sum += channel * weight;
+                }
+                total_weight += weight;
+            }
+            *pixel = sum.map(|channel| channel / total_weight);
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GaussianBlur, LedStrip, SegmentError};
+
+    fn add(ledstrip: &mut LedStrip, size: usize) -> Result<(), SegmentError> {
+        ledstrip.add_effect(
+            1,
+            size,
+            Default::default(),
+            Default::default(),
+            0.0,
+            Default::default(),
+        )
+    }
+
+    #[test]
+    fn segments_fill_the_ledstrip() {
+        let mut ledstrip = LedStrip {
+            size: 10,
+            ..Default::default()
+        };
+
+        assert_eq!(add(&mut ledstrip, 4), Ok(()));
+        assert_eq!(add(&mut ledstrip, 6), Ok(()));
+        assert_eq!(
+            add(&mut ledstrip, 1),
+            Err(SegmentError::OutOfBounds { end: 10, size: 10 })
+        );
+        assert_eq!(ledstrip.effects[1].interval, (4, 9));
+    }
+
+    #[test]
+    fn refuses_the_segments_that_dont_fit() {
+        let mut ledstrip = LedStrip {
+            size: 10,
+            ..Default::default()
+        };
+        add(&mut ledstrip, 4).unwrap();
+
+        assert_eq!(add(&mut ledstrip, 0), Err(SegmentError::Empty));
+        assert_eq!(
+            add(&mut ledstrip, usize::MAX),
+            Err(SegmentError::OutOfBounds {
+                end: usize::MAX,
+                size: 10
+            })
+        );
+        assert_eq!(ledstrip.effects.len(), 1);
+        assert_eq!(add(&mut ledstrip, 6), Ok(()));
+    }
+
+    #[test]
+    fn blur_stops_at_the_end_of_the_run() {
+        let mut blur = GaussianBlur::new(1e9, 3).unwrap();
+        let mut pixels = [[0.0, 0.0, 0.0], [90.0, 90.0, 90.0], [0.0, 0.0, 0.0]];
+
+        blur.apply(&mut pixels);
 
-        let interval = (self.used_led_count, self.used_led_count + size - 1);
-        self.effects.push((effect_id, interval));
-        self.used_led_count += size;
-        true
+        assert_eq!(blur.kernel.len(), 3);
+        assert!(pixels.iter().all(|pixel| (pixel[0] - 30.0).abs() < 1e-3));
+        assert!(GaussianBlur::new(4.0, 1).is_none());
+        assert!(GaussianBlur::new(0.5, 10).is_none());
     }
 }
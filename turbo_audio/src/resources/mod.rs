@@ -1 +1,3 @@
+pub mod color;
 pub mod ledstrip;
+pub mod registry;
@@ -0,0 +1,98 @@
+use std::collections::{btree_map, BTreeMap};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("More than one {kind} has the id {id}")]
+    Collision { kind: &'static str, id: usize },
+}
+
+/// Resources of one kind (effects, ledstrips, connections...) indexed by their id.
+///
+/// Adding a resource under an id that is already taken is an error instead of replacing the
+/// resource, and iteration is always in increasing id order.
+pub struct Registry<T> {
+    // Name of the resources, for the errors
+    kind: &'static str,
+    resources: BTreeMap<usize, T>,
+}
+
+impl<T> Registry<T> {
+    pub fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            resources: BTreeMap::new(),
+        }
+    }
+
+    /// Fails if the id is already taken, so that callers can check before building the resource
+    pub fn check_vacant(&self, id: usize) -> Result<(), RegistryError> {
+        if self.resources.contains_key(&id) {
+            return Err(RegistryError::Collision {
+                kind: self.kind,
+                id,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, id: usize, resource: T) -> Result<(), RegistryError> {
+        match self.resources.entry(id) {
+            btree_map::Entry::Occupied(_) => Err(RegistryError::Collision {
+                kind: self.kind,
+                id,
+            }),
+            btree_map::Entry::Vacant(entry) => {
+                entry.insert(resource);
+                Ok(())
+            }
+        }
+    }
+
+    /// Id the next allocated resource gets, which is after every id in use
+    pub fn next_id(&self) -> usize {
+        self.resources.last_key_value().map_or(0, |(id, _)| id + 1)
+    }
+
+    /// Adds a resource under a new id and returns it
+    pub fn allocate(&mut self, resource: T) -> usize {
+        self.allocate_with(|_| resource)
+    }
+
+    /// Adds a resource built from its new id and returns the id
+    pub fn allocate_with(&mut self, build: impl FnOnce(usize) -> T) -> usize {
+        let id = self.next_id();
+        self.resources.insert(id, build(id));
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<T> {
+        self.resources.remove(&id)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&T> {
+        self.resources.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut T> {
+        self.resources.get_mut(&id)
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        self.resources.contains_key(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.resources.iter().map(|(id, resource)| (*id, resource))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.resources
+            .iter_mut()
+            .map(|(id, resource)| (*id, resource))
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.resources.values_mut()
+    }
+}
@@ -0,0 +1,166 @@
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr, time::Duration};
+
+// Days looked back for the entries missed while the engine wasn't checking, like across a
+// suspend. Older ones are skipped
+const MAX_CATCH_UP_DAYS: i64 = 7;
+
+/// Time of the day written like `07:00` or `23:30:15`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeOfDay(pub NaiveTime);
+
+impl FromStr for TimeOfDay {
+    type Err = chrono::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NaiveTime::parse_from_str(s, "%H:%M")
+            .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+            .map(Self)
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.second() == 0 {
+            return write!(f, "{}", self.0.format("%H:%M"));
+        }
+        write!(f, "{}", self.0.format("%H:%M:%S"))
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        time.parse().map_err(|e| {
+            serde::de::Error::custom(format!("Invalid time of the day {time:?}, like 07:30: {e}"))
+        })
+    }
+}
+
+/// Changes applied at a time of the day, for the ledstrips that double as room lighting, like a
+/// sunrise at 07:00 or dimming after 23:00
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntryConfig {
+    /// Local time of the day
+    pub at: TimeOfDay,
+    /// Days of the week it applies on, like `["Sat", "Sun"]`. Every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Profile switched to
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Global brightness reached once the fade is over
+    #[serde(
+        default,
+        deserialize_with = "crate::config_parser::optional_unit_interval"
+    )]
+    pub brightness: Option<f32>,
+    /// Minutes the brightness fades over, from its value at `at`
+    #[serde(default, deserialize_with = "crate::config_parser::non_negative")]
+    pub fade_minutes: f32,
+}
+
+impl ScheduleEntryConfig {
+    fn applies_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    pub fn fade(&self) -> Duration {
+        Duration::from_secs_f32(self.fade_minutes * 60.0)
+    }
+}
+
+/// Tells which entries of the schedule are due as the local time goes by
+#[derive(Debug)]
+pub struct Schedule {
+    entries: Vec<ScheduleEntryConfig>,
+    // Local time of the last check. None before the first one, which catches up on the day
+    // before, so that a restart ends up where the schedule would have left the engine
+    checked_at: Option<NaiveDateTime>,
+}
+
+impl Schedule {
+    pub fn new(entries: Vec<ScheduleEntryConfig>) -> Self {
+        Self {
+            entries,
+            checked_at: None,
+        }
+    }
+
+    pub fn entries(&self) -> &[ScheduleEntryConfig] {
+        &self.entries
+    }
+
+    /// Catches up on the entries due since `checked_at` rather than the day before, for an
+    /// engine that restored its state from then
+    pub fn resume_from(&mut self, checked_at: NaiveDateTime) {
+        self.checked_at = Some(checked_at);
+    }
+
+    /// Entries due since the last check, in order, with the time since they were due. When
+    /// catching up only the last profile and the last brightness are kept
+    pub fn due(&mut self, now: NaiveDateTime) -> Vec<(&ScheduleEntryConfig, Duration)> {
+        let catching_up = self.checked_at.is_none();
+        let since = self
+            .checked_at
+            .unwrap_or(now - chrono::Duration::days(1))
+            .max(now - chrono::Duration::days(MAX_CATCH_UP_DAYS));
+        self.checked_at = Some(now);
+        if since >= now {
+            return Vec::new();
+        }
+
+        let mut due: Vec<(NaiveDateTime, &ScheduleEntryConfig)> = Vec::new();
+        let mut day = since.date();
+        while day <= now.date() {
+            for entry in &self.entries {
+                let time = day.and_time(entry.at.0);
+                if entry.applies_on(day.weekday()) && since < time && time <= now {
+                    due.push((time, entry));
+                }
+            }
+            let Some(next) = day.succ_opt() else {
+                break;
+            };
+            day = next;
+        }
+        due.sort_by_key(|(time, _)| *time);
+
+        if catching_up {
+            let last_profile = due.iter().rposition(|(_, entry)| entry.profile.is_some());
+            let last_brightness = due
+                .iter()
+                .rposition(|(_, entry)| entry.brightness.is_some());
+            due = due
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| {
+                    Some(*index) == last_profile || Some(*index) == last_brightness
+                })
+                .map(|(_, due)| due)
+                .collect();
+        }
+        due.into_iter()
+            .map(|(time, entry)| (entry, (now - time).to_std().unwrap_or_default()))
+            .collect()
+    }
+}
+
+/// Local time of the day in seconds since midnight, for the effects following the clock
+pub fn seconds_since_midnight() -> f64 {
+    let time = Local::now().time();
+    time.num_seconds_from_midnight() as f64 + time.nanosecond() as f64 / 1e9
+}
+
+/// Local date and time the schedule is checked against
+pub fn local_now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
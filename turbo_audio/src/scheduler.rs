@@ -0,0 +1,260 @@
+use crate::{
+    audio::smoothing::SmoothingProfile,
+    plugins::effects::{Effect, EffectSettings, TickError},
+    resources::ledstrip::{self, EffectInterval, GaussianBlur, Pixel, SegmentLayout},
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use turbo_plugin::Color;
+
+// Weight of the newest measure in the moving average of the cost of an effect
+const COST_SMOOTHING: f32 = 0.2;
+
+/// Time an effect may spend rendering a frame. The effects that keep going over it are reported,
+/// and frozen with `freeze`, so that one slow script doesn't drag the frame rate of every ledstrip
+/// down
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct EffectBudgetConfig {
+    /// Time to render a frame of an effect, on all its segments
+    pub budget_ms: f32,
+    /// Frames in a row over the budget after which the effect is reported
+    pub max_slow_frames: u32,
+    /// Freezes the effects that keep going over budget. Unfreezing one gives it another try
+    pub freeze: bool,
+}
+
+impl Default for EffectBudgetConfig {
+    fn default() -> Self {
+        Self {
+            budget_ms: 8.0,
+            max_slow_frames: 60,
+            freeze: false,
+        }
+    }
+}
+
+/// Render times of an effect, measured by the scheduler
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EffectTiming {
+    /// Moving average of the time to render a frame, used to start the heaviest effects first
+    pub average: Duration,
+    pub max: Duration,
+    /// Frames rendered over the budget since the effect was added
+    pub slow_frames: u64,
+    frames: u64,
+    // Frames over the budget in a row
+    slow_streak: u32,
+}
+
+impl EffectTiming {
+    // Accounts for a frame that took `cost`, and returns whether the effect just went over the
+    // budget for too many frames in a row
+    fn record(&mut self, cost: Duration, budget: &EffectBudgetConfig) -> bool {
+        self.average = match self.frames {
+            0 => cost,
+            _ => self.average.mul_f32(1.0 - COST_SMOOTHING) + cost.mul_f32(COST_SMOOTHING),
+        };
+        self.frames += 1;
+        self.max = self.max.max(cost);
+        if cost.as_secs_f32() * 1000.0 <= budget.budget_ms {
+            self.slow_streak = 0;
+            return false;
+        }
+        self.slow_frames += 1;
+        self.slow_streak += 1;
+        if self.slow_streak != budget.max_slow_frames.max(1) {
+            return false;
+        }
+        // A frozen effect gets as many frames again once it's unfrozen
+        if budget.freeze {
+            self.slow_streak = 0;
+        }
+        true
+    }
+}
+
+/// Segment an effect renders on this frame
+#[derive(Debug)]
+pub struct RenderTarget {
+    pub led_strip_id: usize,
+    pub interval: EffectInterval,
+    pub smoothing: SmoothingProfile,
+    /// Pixels the effect is rendered on, before being resampled to the segment
+    pub render_size: usize,
+    pub blur: Option<GaussianBlur>,
+    pub layout: SegmentLayout,
+    /// Pixels of the segment, that the effect renders over
+    pub colors: Vec<Pixel>,
+    /// Set when the effect failed to render, in which case the colors are left as they were
+    pub error: Option<TickError>,
+}
+
+/// An effect and the segments it renders on. They are rendered one after the other since they
+/// share the state of the effect
+pub struct RenderJob<'a> {
+    pub effect_id: usize,
+    pub effect: &'a mut Effect,
+    pub settings: Option<Arc<EffectSettings>>,
+    pub targets: Vec<RenderTarget>,
+}
+
+impl RenderJob<'_> {
+    // Renders every target and returns how long it took
+    fn run(&mut self) -> Duration {
+        let start = Instant::now();
+        for target in &mut self.targets {
+            let mut pixels = target.layout.gather(&target.colors);
+            // The effect sees the previous frame at its own size, rounded to its 8-bit colors
+            let mut colors: Vec<Color> = if target.render_size == pixels.len() {
+                pixels.iter().map(ledstrip::to_color).collect()
+            } else {
+                let mut resampled = vec![Pixel::default(); target.render_size];
+                ledstrip::resample(&pixels, &mut resampled);
+                resampled.iter().map(ledstrip::to_color).collect()
+            };
+            let result = self
+                .effect
+                .tick(self.settings.as_deref(), &mut colors, target.smoothing);
+            if let Err(error) = result {
+                target.error = Some(error);
+                continue;
+            }
+            let rendered: Vec<Pixel> = colors.iter().map(ledstrip::to_pixel).collect();
+            if rendered.len() == pixels.len() {
+                pixels = rendered;
+            } else {
+                ledstrip::resample(&rendered, &mut pixels);
+            }
+            if let Some(blur) = &mut target.blur {
+                blur.apply(&mut pixels);
+            }
+            target.layout.scatter(&pixels, &mut target.colors);
+        }
+        start.elapsed()
+    }
+}
+
+/// Spreads the effects over a pool of threads every frame, so that the ledstrips and segments
+/// rendering different effects are rendered in parallel. The effects are started heaviest first,
+/// using their cost measured on the previous frames, so that the slowest thread (and so the frame
+/// time) stays low with many effects on a few cores.
+#[derive(Debug)]
+pub struct EffectScheduler {
+    pool: rayon::ThreadPool,
+    // effect id to its render times
+    timings: HashMap<usize, EffectTiming>,
+    budget: EffectBudgetConfig,
+    // Effects that went over the budget for too long during the last frame
+    over_budget: Vec<usize>,
+}
+
+fn thread_pool(threads: usize) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.max(1))
+        .thread_name(|index| format!("render-{index}"))
+        .build()
+        .expect("Couldn't start the effect render threads")
+}
+
+impl EffectScheduler {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            pool: thread_pool(threads),
+            timings: HashMap::new(),
+            budget: EffectBudgetConfig::default(),
+            over_budget: Vec::new(),
+        }
+    }
+
+    pub fn set_budget(&mut self, budget: EffectBudgetConfig) {
+        self.budget = budget;
+    }
+
+    pub fn budget(&self) -> &EffectBudgetConfig {
+        &self.budget
+    }
+
+    pub fn timing(&self, effect_id: usize) -> Option<&EffectTiming> {
+        self.timings.get(&effect_id)
+    }
+
+    /// Forgets the render times of a removed effect
+    pub fn remove(&mut self, effect_id: usize) {
+        self.timings.remove(&effect_id);
+    }
+
+    /// Effects that went over the budget for `max_slow_frames` frames in a row during the last
+    /// frame. Reported once until they get back under it, or every time with `freeze`
+    pub fn take_over_budget(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.over_budget)
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        if threads.max(1) != self.pool.current_num_threads() {
+            self.pool = thread_pool(threads);
+        }
+    }
+
+    /// Renders the jobs and returns their targets with the rendered colors, along with the id of
+    /// their effect
+    pub fn run(&mut self, jobs: Vec<RenderJob>) -> Vec<(usize, RenderTarget)> {
+        // The native effects of a library share its audio api state, so they stay on one thread
+        let (native, lua): (Vec<RenderJob>, Vec<RenderJob>) = jobs
+            .into_iter()
+            .partition(|job| matches!(job.effect, Effect::Native(_)));
+        let mut units: Vec<(Duration, Vec<RenderJob>)> = lua
+            .into_iter()
+            .map(|job| vec![job])
+            .chain((!native.is_empty()).then_some(native))
+            .map(|unit| {
+                let cost = unit.iter().map(|job| self.cost(job.effect_id)).sum();
+                (cost, unit)
+            })
+            .collect();
+        units.sort_by_key(|(cost, _)| Reverse(*cost));
+
+        // A unit per task, so that the idle threads steal the next heaviest one
+        let rendered: Vec<(usize, Duration, Vec<RenderTarget>)> = self.pool.install(|| {
+            units
+                .into_par_iter()
+                .with_max_len(1)
+                .flat_map_iter(|(_, jobs)| run_jobs(jobs))
+                .collect()
+        });
+
+        rendered
+            .into_iter()
+            .flat_map(|(effect_id, cost, targets)| {
+                let timing = self.timings.entry(effect_id).or_default();
+                if timing.record(cost, &self.budget) {
+                    self.over_budget.push(effect_id);
+                }
+                targets.into_iter().map(move |target| (effect_id, target))
+            })
+            .collect()
+    }
+
+    fn cost(&self, effect_id: usize) -> Duration {
+        self.timings
+            .get(&effect_id)
+            .map(|timing| timing.average)
+            .unwrap_or_default()
+    }
+}
+
+fn run_jobs(jobs: Vec<RenderJob>) -> Vec<(usize, Duration, Vec<RenderTarget>)> {
+    jobs.into_iter()
+        .map(|mut job| {
+            let cost = job.run();
+            (job.effect_id, cost, job.targets)
+        })
+        .collect()
+}
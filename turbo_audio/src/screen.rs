@@ -0,0 +1,296 @@
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use turbo_plugin::Color;
+
+/// Samples the colors along the edges of the screen, for the effects lighting the wall behind a
+/// monitor or a TV like an ambilight. Captures an X11 display, built with the `screen` feature
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ScreenCaptureConfig {
+    /// X display to capture, like `:0`. The one of the DISPLAY variable if missing
+    pub display: Option<String>,
+    /// Zones the colors of each edge are averaged over
+    pub zones: ScreenZones,
+    /// Pixels from the edges of the screen averaged into the zones
+    pub depth: u16,
+    /// Captures per second
+    pub fps: u32,
+}
+
+impl Default for ScreenCaptureConfig {
+    fn default() -> Self {
+        Self {
+            display: None,
+            zones: Default::default(),
+            depth: 64,
+            fps: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ScreenZones {
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+    pub left: usize,
+}
+
+impl Default for ScreenZones {
+    fn default() -> Self {
+        Self {
+            top: 16,
+            right: 9,
+            bottom: 16,
+            left: 9,
+        }
+    }
+}
+
+/// Average colors of the zones of each edge of the screen, from left to right along the top and
+/// bottom edges and from top to bottom along the sides. Empty while nothing is captured
+#[derive(Debug, Default, Clone)]
+pub struct ScreenColors {
+    pub top: Vec<Color>,
+    pub right: Vec<Color>,
+    pub bottom: Vec<Color>,
+    pub left: Vec<Color>,
+}
+
+impl ScreenColors {
+    pub fn edge(&self, name: &str) -> Option<&[Color]> {
+        match name {
+            "top" => Some(&self.top),
+            "right" => Some(&self.right),
+            "bottom" => Some(&self.bottom),
+            "left" => Some(&self.left),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.top.is_empty()
+            && self.right.is_empty()
+            && self.bottom.is_empty()
+            && self.left.is_empty()
+    }
+
+    /// Color of the zone at `t` in [0, 1) going clockwise around the screen from its top left
+    /// corner, like a ledstrip glued around it. `t` wraps around
+    pub fn around(&self, t: f32) -> Color {
+        let total = self.top.len() + self.right.len() + self.bottom.len() + self.left.len();
+        if total == 0 {
+            return Color::default();
+        }
+        let mut index = ((t.rem_euclid(1.0) * total as f32) as usize).min(total - 1);
+        for (edge, reversed) in [
+            (&self.top, false),
+            (&self.right, false),
+            (&self.bottom, true),
+            (&self.left, true),
+        ] {
+            if index < edge.len() {
+                if reversed {
+                    return edge[edge.len() - 1 - index];
+                }
+                return edge[index];
+            }
+            index -= edge.len();
+        }
+        Color::default()
+    }
+}
+
+/// Colors of the screen shared between the capture thread and the effects
+pub type SharedScreenColors = Arc<ArcSwap<ScreenColors>>;
+
+#[cfg(feature = "screen")]
+pub use capture::{ScreenCapture, ScreenCaptureError};
+
+#[cfg(feature = "screen")]
+mod capture {
+    use super::{ScreenCaptureConfig, ScreenColors, ScreenZones, SharedScreenColors};
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    };
+    use thiserror::Error;
+    use turbo_plugin::Color;
+    use x11rb::{
+        connection::Connection,
+        errors::{ConnectError, ConnectionError, ReplyError},
+        protocol::xproto::{ConnectionExt, ImageFormat, ImageOrder, Window},
+        rust_connection::RustConnection,
+    };
+
+    #[derive(Error, Debug)]
+    pub enum ScreenCaptureError {
+        #[error("Couldn't connect to the X server: {0}")]
+        Connect(#[from] ConnectError),
+
+        #[error("Lost the connection to the X server: {0}")]
+        Connection(#[from] ConnectionError),
+
+        #[error("The X server refused the capture: {0}")]
+        Reply(#[from] ReplyError),
+
+        #[error("Screens {0} bits deep aren't supported, only 24 and 32 bits")]
+        UnsupportedDepth(u8),
+    }
+
+    /// Captures the screen on its own thread, storing the colors of its edges for the effects.
+    /// They are cleared once the capture stops
+    pub struct ScreenCapture {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl ScreenCapture {
+        pub fn new(
+            config: &ScreenCaptureConfig,
+            colors: SharedScreenColors,
+        ) -> Result<Self, ScreenCaptureError> {
+            let grabber = EdgeGrabber::connect(config)?;
+            let period = Duration::from_secs(1) / config.fps.max(1);
+            let stop: Arc<AtomicBool> = Arc::default();
+            let thread = {
+                let stop = stop.clone();
+                thread::Builder::new()
+                    .name("screen-capture".to_owned())
+                    .spawn(move || {
+                        while !stop.load(Ordering::Relaxed) {
+                            let capture_start = Instant::now();
+                            match grabber.grab() {
+                                Ok(edges) => colors.store(Arc::new(edges)),
+                                Err(e) => {
+                                    tracing::error!("Stopped capturing the screen: {e}");
+                                    break;
+                                }
+                            }
+                            thread::sleep(period.saturating_sub(capture_start.elapsed()));
+                        }
+                        colors.store(Default::default());
+                    })
+                    .expect("Couldn't start the screen capture thread")
+            };
+            Ok(Self {
+                stop,
+                thread: Some(thread),
+            })
+        }
+    }
+
+    impl Drop for ScreenCapture {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                if thread.join().is_err() {
+                    tracing::error!("The screen capture thread panicked");
+                }
+            }
+        }
+    }
+
+    struct EdgeGrabber {
+        connection: RustConnection,
+        root: Window,
+        width: u16,
+        height: u16,
+        // Whether the pixels are stored as b, g, r, x rather than x, r, g, b
+        lsb_first: bool,
+        zones: ScreenZones,
+        depth: u16,
+    }
+
+    impl EdgeGrabber {
+        fn connect(config: &ScreenCaptureConfig) -> Result<Self, ScreenCaptureError> {
+            let (connection, screen_index) = x11rb::connect(config.display.as_deref())?;
+            let setup = connection.setup();
+            let screen = &setup.roots[screen_index];
+            // Both are stored on 32 bits per pixel
+            if !matches!(screen.root_depth, 24 | 32) {
+                return Err(ScreenCaptureError::UnsupportedDepth(screen.root_depth));
+            }
+            Ok(Self {
+                root: screen.root,
+                width: screen.width_in_pixels,
+                height: screen.height_in_pixels,
+                lsb_first: setup.image_byte_order == ImageOrder::LSB_FIRST,
+                zones: config.zones,
+                depth: config.depth.max(1),
+                connection,
+            })
+        }
+
+        fn grab(&self) -> Result<ScreenColors, ScreenCaptureError> {
+            let (width, height) = (self.width, self.height);
+            let depth_x = self.depth.min(width);
+            let depth_y = self.depth.min(height);
+            let bottom = (height - depth_y) as i16;
+            let right = (width - depth_x) as i16;
+            Ok(ScreenColors {
+                top: self.edge((0, 0), (width, depth_y), self.zones.top, true)?,
+                right: self.edge((right, 0), (depth_x, height), self.zones.right, false)?,
+                bottom: self.edge((0, bottom), (width, depth_y), self.zones.bottom, true)?,
+                left: self.edge((0, 0), (depth_x, height), self.zones.left, false)?,
+            })
+        }
+
+        // Averages a rectangle of the screen into zones side by side along it
+        fn edge(
+            &self,
+            (x, y): (i16, i16),
+            (width, height): (u16, u16),
+            zones: usize,
+            horizontal: bool,
+        ) -> Result<Vec<Color>, ScreenCaptureError> {
+            if zones == 0 {
+                return Ok(Vec::new());
+            }
+            let image = self
+                .connection
+                .get_image(ImageFormat::Z_PIXMAP, self.root, x, y, width, height, !0)?
+                .reply()?;
+            let (width, height) = (width as usize, height as usize);
+            // Sums of the red, green and blue of every zone, and their pixel count
+            let mut sums = vec![[0u64; 4]; zones];
+            for (index, pixel) in image.data.chunks_exact(4).enumerate() {
+                let (row, column) = (index / width, index % width);
+                let zone = if horizontal {
+                    column * zones / width
+                } else {
+                    row * zones / height
+                };
+                let (r, g, b) = if self.lsb_first {
+                    (pixel[2], pixel[1], pixel[0])
+                } else {
+                    (pixel[1], pixel[2], pixel[3])
+                };
+                let sum = &mut sums[zone.min(zones - 1)];
+                sum[0] += r as u64;
+                sum[1] += g as u64;
+                sum[2] += b as u64;
+                sum[3] += 1;
+            }
+            Ok(sums
+                .iter()
+                .map(|[r, g, b, count]| {
+                    let count = (*count).max(1);
+                    Color {
+                        r: (r / count) as u8,
+                        g: (g / count) as u8,
+                        b: (b / count) as u8,
+                    }
+                })
+                .collect())
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// Saves what was changed through the control api to a file, and restores it at the next start so
+/// that a power cut doesn't bring the installation back to the effects of the config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct SessionConfig {
+    pub path: PathBuf,
+    /// Seconds between two saves. The state is only written when it changed
+    #[serde(deserialize_with = "crate::config_parser::non_negative")]
+    pub save_interval_secs: f32,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("turbo_audio_session.json"),
+            save_interval_secs: 10.0,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("Couldn't access the session file: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("The session file is invalid: {0}")]
+    Invalid(#[from] serde_json::Error),
+}
+
+/// State of the engine that the control api changes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionState {
+    pub profile: Option<String>,
+    pub brightness: f32,
+    pub ledstrip_brightness: BTreeMap<usize, f32>,
+    /// Effect of every segment of the ledstrips
+    pub ledstrip_effects: BTreeMap<usize, Vec<usize>>,
+}
+
+impl Default for SessionState {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            brightness: 1.0,
+            ledstrip_brightness: BTreeMap::new(),
+            ledstrip_effects: BTreeMap::new(),
+        }
+    }
+}
+
+/// Content of the session file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedSession {
+    /// Digest of the settings file the state was saved with
+    pub settings_digest: String,
+    /// Local time of the save, from which the schedule catches up
+    pub saved_at: NaiveDateTime,
+    pub state: SessionState,
+}
+
+/// Session file of the engine, tied to the content of its settings file so that the changes made
+/// to the config aren't hidden by an older state
+#[derive(Debug)]
+pub struct Session {
+    config: SessionConfig,
+    settings_digest: String,
+    saved: Option<SessionState>,
+    last_save: Instant,
+}
+
+impl Session {
+    pub fn new(config: SessionConfig, settings: &[u8]) -> Self {
+        Self {
+            config,
+            settings_digest: Self::digest(settings),
+            saved: None,
+            last_save: Instant::now(),
+        }
+    }
+
+    /// Ties the next saves to settings changed without a restart
+    pub fn set_settings(&mut self, settings: &[u8]) {
+        self.settings_digest = Self::digest(settings);
+        self.saved = None;
+    }
+
+    /// The state saved with the same settings, None if there's no session file yet or the
+    /// settings changed since it was written
+    pub fn load(&mut self) -> Result<Option<SavedSession>, SessionError> {
+        let text = match fs::read_to_string(&self.config.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let saved: SavedSession = serde_json::from_str(&text)?;
+        if saved.settings_digest != self.settings_digest {
+            tracing::info!("The settings changed since the session was saved, starting from them");
+            return Ok(None);
+        }
+        self.saved = Some(saved.state.clone());
+        Ok(Some(saved))
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last_save.elapsed() >= Duration::from_secs_f32(self.config.save_interval_secs)
+    }
+
+    /// Writes the state if it changed since the last save. The file is replaced at once, so that
+    /// a power cut while saving leaves the previous state
+    pub fn save(&mut self, state: SessionState) {
+        self.last_save = Instant::now();
+        if self.saved.as_ref() == Some(&state) {
+            return;
+        }
+        let file = SavedSession {
+            settings_digest: self.settings_digest.clone(),
+            saved_at: crate::schedule::local_now(),
+            state,
+        };
+        let temporary_path = self.config.path.with_extension("tmp");
+        let result = serde_json::to_vec_pretty(&file)
+            .map_err(SessionError::from)
+            .and_then(|data| Ok(fs::write(&temporary_path, data)?))
+            .and_then(|_| Ok(fs::rename(&temporary_path, &self.config.path)?));
+        match result {
+            Ok(()) => self.saved = Some(file.state),
+            Err(e) => tracing::warn!("Couldn't save the session: {e}"),
+        }
+    }
+
+    fn digest(settings: &[u8]) -> String {
+        Sha256::digest(settings)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
@@ -0,0 +1,536 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Channels processed together by the vectorized kernels, the floats of an avx2 register
+const LANES: usize = 8;
+
+// 65535 / 255, so that 255 maps to the highest 16-bit value
+const U16_PER_U8: f32 = 257.0;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Switches the output stage between the vectorized kernels and the plain loops they replace,
+/// to compare them. Vectorized by default
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Runs the vectorized kernel compiled for avx2 when the cpu has it, or for the baseline of the
+// target, which has 128-bit vectors on x86_64 and aarch64. Runs the plain loop when disabled
+macro_rules! dispatch {
+    ($kernel:ident($($arg:expr),*)) => {{
+        if !is_enabled() {
+            return scalar::$kernel($($arg),*);
+        }
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // Safety: the cpu supports the features the kernel is compiled with
+            return unsafe { avx2::$kernel($($arg),*) };
+        }
+        kernels::$kernel($($arg),*)
+    }};
+}
+
+/// Multiplies every channel by `factor`
+pub fn scale(channels: &mut [f32], factor: f32) {
+    dispatch!(scale(channels, factor))
+}
+
+/// Multiplies the red, green and blue channels of the pixels by their own factor
+pub fn scale_rgb(pixels: &mut [[f32; 3]], factors: [f32; 3]) {
+    dispatch!(scale_rgb(pixels.as_flattened_mut(), factors))
+}
+
+pub fn sum(channels: &[f32]) -> f32 {
+    dispatch!(sum(channels))
+}
+
+/// Raises the channels, between 0 and 255, to `gamma` as fractions of 255
+pub fn gamma(channels: &mut [f32], gamma: f32) {
+    dispatch!(gamma(channels, gamma))
+}
+
+/// Rounds the channels, between 0 and 255, to 16 bits into `output`
+pub fn to_u16(channels: &[f32], output: &mut Vec<u16>) {
+    dispatch!(to_u16(channels, output))
+}
+
+/// Rounds a 16-bit frame to the 8 bits of the leds into `output`
+pub fn to_u8(frame: &[u16], output: &mut Vec<u8>) {
+    dispatch!(to_u8(frame, output))
+}
+
+/// Writes the rgb pixels into `output` with their channels in another order, `order` giving the
+/// rgb channel of each output channel
+pub fn reorder(rgb: &[u8], order: [usize; 3], output: &mut Vec<u8>) {
+    dispatch!(reorder(rgb, order, output))
+}
+
+// The iterator chains the output stage used before the kernels, kept as the reference
+mod scalar {
+    use super::U16_PER_U8;
+
+    pub fn scale(channels: &mut [f32], factor: f32) {
+        channels.iter_mut().for_each(|channel| *channel *= factor);
+    }
+
+    pub fn scale_rgb(channels: &mut [f32], factors: [f32; 3]) {
+        for pixel in channels.chunks_exact_mut(3) {
+            for (channel, factor) in pixel.iter_mut().zip(factors) {
+                *channel *= factor;
+            }
+        }
+    }
+
+    pub fn sum(channels: &[f32]) -> f32 {
+        channels.iter().sum()
+    }
+
+    pub fn gamma(channels: &mut [f32], gamma: f32) {
+        channels
+            .iter_mut()
+            .for_each(|channel| *channel = (*channel / 255.0).max(0.0).powf(gamma) * 255.0);
+    }
+
+    pub fn to_u16(channels: &[f32], output: &mut Vec<u16>) {
+        output.clear();
+        output.extend(
+            channels
+                .iter()
+                .map(|channel| (channel * U16_PER_U8).round().clamp(0.0, u16::MAX as f32) as u16),
+        );
+    }
+
+    pub fn to_u8(frame: &[u16], output: &mut Vec<u8>) {
+        output.clear();
+        output.extend(
+            frame
+                .iter()
+                .map(|channel| (*channel as f32 / U16_PER_U8).round() as u8),
+        );
+    }
+
+    pub fn reorder(rgb: &[u8], order: [usize; 3], output: &mut Vec<u8>) {
+        output.clear();
+        output.extend(
+            rgb.chunks_exact(3)
+                .flat_map(|pixel| order.map(|channel| pixel[channel])),
+        );
+    }
+}
+
+// Loops over chunks of LANES channels without branches, which the compiler turns into vector
+// instructions. Inlined into the avx2 wrappers so that they're compiled once more for avx2.
+// `reorder` is only vectorized by hand in the avx2 module
+mod kernels {
+    use super::{LANES, U16_PER_U8};
+    use std::f32::consts::{LN_2, LOG2_E, SQRT_2};
+
+    #[inline(always)]
+    pub fn scale(channels: &mut [f32], factor: f32) {
+        let mut chunks = channels.chunks_exact_mut(LANES);
+        for chunk in &mut chunks {
+            for channel in chunk {
+                *channel *= factor;
+            }
+        }
+        for channel in chunks.into_remainder() {
+            *channel *= factor;
+        }
+    }
+
+    #[inline(always)]
+    pub fn scale_rgb(channels: &mut [f32], factors: [f32; 3]) {
+        // LANES pixels, so that every chunk starts on a red channel
+        let pattern: [f32; LANES * 3] = std::array::from_fn(|index| factors[index % 3]);
+        let mut chunks = channels.chunks_exact_mut(LANES * 3);
+        for chunk in &mut chunks {
+            for (channel, factor) in chunk.iter_mut().zip(pattern) {
+                *channel *= factor;
+            }
+        }
+        for (channel, factor) in chunks.into_remainder().iter_mut().zip(pattern) {
+            *channel *= factor;
+        }
+    }
+
+    #[inline(always)]
+    pub fn sum(channels: &[f32]) -> f32 {
+        // One running sum per lane, added up at the end
+        let mut sums = [0.0; LANES];
+        let chunks = channels.chunks_exact(LANES);
+        let remainder: f32 = chunks.remainder().iter().sum();
+        for chunk in chunks {
+            for (sum, channel) in sums.iter_mut().zip(chunk) {
+                *sum += channel;
+            }
+        }
+        sums.iter().sum::<f32>() + remainder
+    }
+
+    // 2^23, which brings the subnormals to normal floats
+    const SUBNORMAL_SCALE: f32 = (1 << 23) as f32;
+
+    // log2 of a positive float, from its exponent and a series on its mantissa
+    #[inline(always)]
+    fn log2(x: f32) -> f32 {
+        // The exponent of the subnormals doesn't follow the scheme, so they're scaled first
+        let subnormal = x < f32::MIN_POSITIVE;
+        let x = if subnormal { x * SUBNORMAL_SCALE } else { x };
+        let bits = x.to_bits();
+        let mut exponent = ((bits >> 23) & 0xff) as i32 - if subnormal { 127 + 23 } else { 127 };
+        let mut mantissa = f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000);
+        // Brought between sqrt(0.5) and sqrt(2), where the series converges fast
+        let high = mantissa > SQRT_2;
+        mantissa = if high { mantissa * 0.5 } else { mantissa };
+        exponent += high as i32;
+        // ln(m) = 2 atanh((m - 1) / (m + 1))
+        let t = (mantissa - 1.0) / (mantissa + 1.0);
+        let t2 = t * t;
+        let ln =
+            2.0 * t * (1.0 + t2 * (1.0 / 3.0 + t2 * (1.0 / 5.0 + t2 * (1.0 / 7.0 + t2 / 9.0))));
+        exponent as f32 + ln * LOG2_E
+    }
+
+    // 1 / n! from n = 2
+    const EXP_SERIES: [f32; 8] = [
+        1.0 / 2.0,
+        1.0 / 6.0,
+        1.0 / 24.0,
+        1.0 / 120.0,
+        1.0 / 720.0,
+        1.0 / 5040.0,
+        1.0 / 40320.0,
+        1.0 / 362880.0,
+    ];
+
+    // 2^y, from the exponent of its integer part and a series on its fraction
+    #[inline(always)]
+    fn exp2(y: f32) -> f32 {
+        // NaN is brought to the lower bound too, unlike with clamp
+        let y = if y >= -126.0 { y.min(127.0) } else { -126.0 };
+        debug_assert!((-126.0..=127.0).contains(&y));
+        // Safety: y was brought between -126 and 127, in the range of i32. The checked cast isn't
+        // vectorized
+        let integer: i32 = unsafe { y.to_int_unchecked() };
+        let x = (y - integer as f32) * LN_2;
+        // Taylor series of e^x, enough for the precision of f32 as |x| < ln(2). Written out, a
+        // loop would keep the loops over the lanes from being vectorized
+        let [c2, c3, c4, c5, c6, c7, c8, c9] = EXP_SERIES;
+        let series = 1.0
+            + x * (1.0
+                + x * (c2
+                    + x * (c3 + x * (c4 + x * (c5 + x * (c6 + x * (c7 + x * (c8 + x * c9))))))));
+        series * f32::from_bits(((integer + 127) as u32) << 23)
+    }
+
+    #[inline(always)]
+    fn pow(x: f32, gamma: f32) -> f32 {
+        // 0 for zero, the negatives and NaN, like `max(0.0).powf(gamma)`
+        let positive = x > 0.0;
+        let value = exp2(gamma * log2(if positive { x } else { 1.0 }));
+        if positive {
+            value
+        } else {
+            0.0
+        }
+    }
+
+    #[inline(always)]
+    pub fn gamma(channels: &mut [f32], gamma: f32) {
+        let mut chunks = channels.chunks_exact_mut(LANES);
+        for chunk in &mut chunks {
+            for channel in chunk {
+                *channel = pow(*channel / 255.0, gamma) * 255.0;
+            }
+        }
+        for channel in chunks.into_remainder() {
+            *channel = pow(*channel / 255.0, gamma) * 255.0;
+        }
+    }
+
+    #[inline(always)]
+    fn round_u16(channel: f32) -> u16 {
+        // Clamped first, the channels round half up like `f32::round` for positive values. Unlike
+        // clamp, max and min turn NaN into a bound
+        let channel = (channel * U16_PER_U8).max(0.0).min(u16::MAX as f32) + 0.5;
+        debug_assert!((0.5..=u16::MAX as f32 + 0.5).contains(&channel));
+        // Safety: the channel was brought between 0.5 and 65535.5, in the range of i32. The
+        // checked cast isn't vectorized
+        unsafe { channel.to_int_unchecked::<i32>() as u16 }
+    }
+
+    #[inline(always)]
+    pub fn to_u16(channels: &[f32], output: &mut Vec<u16>) {
+        output.clear();
+        output.resize(channels.len(), 0);
+        let mut outputs = output.chunks_exact_mut(LANES);
+        let mut chunks = channels.chunks_exact(LANES);
+        for (output, chunk) in (&mut outputs).zip(&mut chunks) {
+            for (output, channel) in output.iter_mut().zip(chunk) {
+                *output = round_u16(*channel);
+            }
+        }
+        let remainder = outputs.into_remainder().iter_mut();
+        for (output, channel) in remainder.zip(chunks.remainder()) {
+            *output = round_u16(*channel);
+        }
+    }
+
+    // round(channel / 257) in integers, exact as 257 is odd so that no channel is halfway
+    #[inline(always)]
+    fn round_u8(channel: u16) -> u8 {
+        ((channel as u32 + 128) / 257) as u8
+    }
+
+    #[inline(always)]
+    pub fn to_u8(frame: &[u16], output: &mut Vec<u8>) {
+        output.clear();
+        output.resize(frame.len(), 0);
+        let mut outputs = output.chunks_exact_mut(LANES * 2);
+        let mut chunks = frame.chunks_exact(LANES * 2);
+        for (output, chunk) in (&mut outputs).zip(&mut chunks) {
+            for (output, channel) in output.iter_mut().zip(chunk) {
+                *output = round_u8(*channel);
+            }
+        }
+        let remainder = outputs.into_remainder().iter_mut();
+        for (output, channel) in remainder.zip(chunks.remainder()) {
+            *output = round_u8(*channel);
+        }
+    }
+
+    #[inline(always)]
+    pub fn reorder(rgb: &[u8], order: [usize; 3], output: &mut Vec<u8>) {
+        output.clear();
+        output.resize(rgb.len() / 3 * 3, 0);
+        let order = order.map(|channel| channel.min(2));
+        for (output, pixel) in output.chunks_exact_mut(3).zip(rgb.chunks_exact(3)) {
+            output.copy_from_slice(&order.map(|channel| pixel[channel]));
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    macro_rules! avx2_kernels {
+        ($(fn $name:ident($($arg:ident: $type:ty),*) $(-> $output:ty)?;)*) => {$(
+            #[target_feature(enable = "avx2")]
+            pub unsafe fn $name($($arg: $type),*) $(-> $output)? {
+                super::kernels::$name($($arg),*)
+            }
+        )*};
+    }
+
+    avx2_kernels! {
+        fn scale(channels: &mut [f32], factor: f32);
+        fn scale_rgb(channels: &mut [f32], factors: [f32; 3]);
+        fn sum(channels: &[f32]) -> f32;
+        fn gamma(channels: &mut [f32], gamma: f32);
+        fn to_u16(channels: &[f32], output: &mut Vec<u16>);
+        fn to_u8(frame: &[u16], output: &mut Vec<u8>);
+    }
+
+    // Byte shuffles aren't found by the compiler, so the pixels are shuffled 5 at a time, the 15
+    // bytes of a 128-bit register
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn reorder(rgb: &[u8], order: [usize; 3], output: &mut Vec<u8>) {
+        use std::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_shuffle_epi8, _mm_storeu_si128};
+
+        let order = order.map(|channel| channel.min(2));
+        // The last byte is zeroed, and overwritten by the next pixels
+        let mask: [u8; 16] = std::array::from_fn(|index| match index {
+            15 => 0x80,
+            _ => (index / 3 * 3 + order[index % 3]) as u8,
+        });
+        let mask = _mm_loadu_si128(mask.as_ptr() as *const __m128i);
+        let len = rgb.len() / 3 * 3;
+        output.clear();
+        output.resize(len, 0);
+        let mut offset = 0;
+        while offset + 16 <= len {
+            let pixels = _mm_loadu_si128(rgb.as_ptr().add(offset) as *const __m128i);
+            let shuffled = _mm_shuffle_epi8(pixels, mask);
+            _mm_storeu_si128(output.as_mut_ptr().add(offset) as *mut __m128i, shuffled);
+            offset += 15;
+        }
+        let remainder = output[offset..].chunks_exact_mut(3);
+        for (output, pixel) in remainder.zip(rgb[offset..len].chunks_exact(3)) {
+            output.copy_from_slice(&order.map(|channel| pixel[channel]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{kernels, scalar};
+
+    // Runs `$body` with `$kernels` naming the module of each vectorized version the cpu can run,
+    // and `$name` its name for the messages
+    macro_rules! for_each_kernel {
+        ($kernels:ident, $name:ident, $body:block) => {{
+            {
+                use kernels as $kernels;
+                let $name = "baseline";
+                $body
+            }
+            #[cfg(target_arch = "x86_64")]
+            if is_x86_feature_detected!("avx2") {
+                use super::avx2 as $kernels;
+                let $name = "avx2";
+                // Safety: the cpu supports avx2
+                #[allow(unused_unsafe)]
+                unsafe {
+                    $body
+                }
+            }
+        }};
+    }
+
+    // Channels around the edges of the kernels: zeros, subnormals, negatives, out of range and
+    // not finite values, then the whole range in small steps. Not a multiple of the lanes, so
+    // that the remainders are covered too
+    fn channels() -> Vec<f32> {
+        let edges = [
+            0.0,
+            -0.0,
+            f32::from_bits(1),
+            f32::MIN_POSITIVE / 2.0,
+            f32::MIN_POSITIVE,
+            f32::MIN_POSITIVE * 255.0,
+            1e-30,
+            -f32::from_bits(1),
+            -1.0,
+            -255.0,
+            0.5,
+            1.0,
+            127.5,
+            254.5,
+            255.0,
+            256.0,
+            1000.0,
+            1e30,
+            f32::MAX,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+        ];
+        edges
+            .into_iter()
+            .chain((0..=1021).map(|step| step as f32 * 0.25))
+            .collect()
+    }
+
+    fn assert_close(name: &str, input: f32, actual: f32, expected: f32, tolerance: f32) {
+        let same_infinity = actual.is_infinite() && actual == expected;
+        let error = (actual - expected).abs() / expected.abs().max(1.0);
+        assert!(
+            same_infinity || error <= tolerance,
+            "{name} gives {actual} for {input} instead of {expected}"
+        );
+    }
+
+    #[test]
+    fn gamma_matches_powf() {
+        // Of the output, 1 to 255. The leds are at most 16 bits, which is 1 / 257 of a level
+        let tolerance = 1e-4;
+        for gamma in [0.1, 0.45, 1.0, 2.2, 2.8, 10.0, 50.0] {
+            let inputs: Vec<f32> = channels()
+                .into_iter()
+                .filter(|channel| channel.is_finite() && *channel <= 255.0)
+                .collect();
+            let mut expected = inputs.clone();
+            scalar::gamma(&mut expected, gamma);
+            for_each_kernel!(vectorized, name, {
+                let mut actual = inputs.clone();
+                vectorized::gamma(&mut actual, gamma);
+                for ((input, actual), expected) in inputs.iter().zip(&actual).zip(&expected) {
+                    let name = format!("{name} gamma {gamma}");
+                    assert_close(&name, *input, *actual, *expected, tolerance);
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn gamma_stays_finite_out_of_range() {
+        for gamma in [0.1, 2.2, 50.0] {
+            for_each_kernel!(vectorized, name, {
+                let mut channels = channels();
+                vectorized::gamma(&mut channels, gamma);
+                for channel in channels {
+                    assert!(!channel.is_nan(), "{name} gamma {gamma} gives NaN");
+                    assert!(channel >= 0.0, "{name} gamma {gamma} gives {channel}");
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn to_u16_matches_the_reference() {
+        let inputs = channels();
+        let mut expected = Vec::new();
+        scalar::to_u16(&inputs, &mut expected);
+        for_each_kernel!(vectorized, name, {
+            let mut actual = Vec::new();
+            vectorized::to_u16(&inputs, &mut actual);
+            assert_eq!(actual, expected, "{name}");
+        });
+    }
+
+    #[test]
+    fn to_u8_matches_the_reference() {
+        let frame: Vec<u16> = (0..=u16::MAX).collect();
+        let mut expected = Vec::new();
+        scalar::to_u8(&frame, &mut expected);
+        for_each_kernel!(vectorized, name, {
+            let mut actual = Vec::new();
+            vectorized::to_u8(&frame, &mut actual);
+            assert_eq!(actual, expected, "{name}");
+        });
+    }
+
+    #[test]
+    fn scale_and_sum_match_the_reference() {
+        let mut inputs: Vec<f32> = channels()
+            .into_iter()
+            .filter(|channel| channel.is_finite() && channel.abs() <= 1000.0)
+            .collect();
+        // Whole pixels, as `scale_rgb` takes
+        inputs.truncate(inputs.len() / 3 * 3);
+        let mut expected = inputs.clone();
+        scalar::scale(&mut expected, 0.3);
+        let mut expected_rgb = inputs.clone();
+        scalar::scale_rgb(&mut expected_rgb, [0.2, 0.7, 1.0]);
+        let expected_sum = scalar::sum(&inputs);
+        for_each_kernel!(vectorized, name, {
+            let mut actual = inputs.clone();
+            vectorized::scale(&mut actual, 0.3);
+            assert_eq!(actual, expected, "{name}");
+            let mut actual = inputs.clone();
+            vectorized::scale_rgb(&mut actual, [0.2, 0.7, 1.0]);
+            assert_eq!(actual, expected_rgb, "{name}");
+            // The lanes are added in another order
+            let sum = vectorized::sum(&inputs);
+            assert_close(name, expected_sum, sum, expected_sum, 1e-5);
+        });
+    }
+
+    #[test]
+    fn reorder_matches_the_reference() {
+        // Not a multiple of the 5 pixels shuffled at once, and a partial pixel at the end
+        let rgb: Vec<u8> = (0..=255).cycle().take(3 * 101 + 2).collect();
+        for order in [[0, 1, 2], [1, 0, 2], [2, 1, 0], [0, 0, 0]] {
+            let mut expected = Vec::new();
+            scalar::reorder(&rgb, order, &mut expected);
+            for_each_kernel!(vectorized, name, {
+                let mut actual = Vec::new();
+                vectorized::reorder(&rgb, order, &mut actual);
+                assert_eq!(actual, expected, "{name} {order:?}");
+            });
+        }
+    }
+}
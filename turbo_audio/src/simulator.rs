@@ -0,0 +1,190 @@
+use crate::controller::Controller;
+use pixels::{Pixels, SurfaceTexture};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use turbo_plugin::Color;
+use winit::{
+    dpi::LogicalSize,
+    event::{Event, WindowEvent},
+    event_loop::{ControlFlow, EventLoopBuilder},
+    platform::run_return::EventLoopExtRunReturn,
+    window::WindowBuilder,
+};
+
+#[cfg(windows)]
+use winit::platform::windows::EventLoopBuilderExtWindows;
+#[cfg(all(unix, not(target_os = "macos")))]
+use winit::platform::x11::EventLoopBuilderExtX11;
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(16);
+// Size of the square each led is drawn in, shrunk for long strips so the window stays reasonable
+const MAX_LED_SIZE: usize = 24;
+const MIN_LED_SIZE: usize = 4;
+const MAX_ROW_WIDTH: usize = 1600;
+const BACKGROUND: [u8; 4] = [0x10, 0x10, 0x10, 0xff];
+
+// led strip colors, sorted by led strip id
+type SimulatorFrame = Vec<Vec<Color>>;
+
+/// Window rendering every ledstrip as a row of colored circles, to develop effects without any
+/// hardware connected.
+pub struct Simulator {
+    frame: Arc<Mutex<SimulatorFrame>>,
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+}
+
+impl Simulator {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let frame: Arc<Mutex<SimulatorFrame>> = Arc::default();
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        // The window can only be used from the thread that created it, so it is created by the
+        // simulator thread which then reports whether that worked
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let thread = thread::spawn({
+            let frame = frame.clone();
+            let should_quit = should_quit.clone();
+            move || Self::run(frame, should_quit, ready_tx)
+        });
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(Self {
+                frame,
+                thread: Some(thread),
+                should_quit,
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err("The simulator thread stopped before opening its window".into()),
+        }
+    }
+
+    /// Captures the ledstrip colors after a tick
+    pub fn update(&self, controller: &Controller) {
+        let mut ledstrips: Vec<_> = controller.led_strip_colors().collect();
+        ledstrips.sort_by_key(|(id, _)| *id);
+        *self.frame.lock().unwrap() = ledstrips.into_iter().map(|(_, colors)| colors).collect();
+    }
+
+    fn run(
+        frame: Arc<Mutex<SimulatorFrame>>,
+        should_quit: Arc<AtomicBool>,
+        ready_tx: mpsc::Sender<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
+    ) {
+        let mut event_loop = EventLoopBuilder::new().with_any_thread(true).build();
+        let window = match WindowBuilder::new()
+            .with_title("Turbo Audio simulator")
+            .with_inner_size(LogicalSize::new(800.0, 200.0))
+            .build(&event_loop)
+        {
+            Ok(window) => window,
+            Err(e) => {
+                let _ = ready_tx.send(Err(e.into()));
+                return;
+            }
+        };
+
+        let size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(size.width, size.height, &window);
+        let mut buffer_size: (usize, usize) = (1, 1);
+        let mut pixels = match Pixels::new(1, 1, surface_texture) {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                let _ = ready_tx.send(Err(e.into()));
+                return;
+            }
+        };
+        let _ = ready_tx.send(Ok(()));
+
+        event_loop.run_return(|event, _, control_flow| match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => {
+                tracing::info!("Simulator window closed, requesting to quit");
+                crate::SHOULD_QUIT.store(true, Ordering::Relaxed);
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                if let Err(e) = pixels.resize_surface(size.width, size.height) {
+                    tracing::error!("Couldn't resize the simulator surface: {e}");
+                }
+            }
+            Event::RedrawRequested(_) => {
+                let ledstrips = frame.lock().unwrap().clone();
+                let led_size = led_size(&ledstrips);
+                let new_buffer_size = (
+                    ledstrips.iter().map(Vec::len).max().unwrap_or(0).max(1) * led_size,
+                    ledstrips.len().max(1) * led_size,
+                );
+                if new_buffer_size != buffer_size {
+                    buffer_size = new_buffer_size;
+                    if let Err(e) = pixels.resize_buffer(buffer_size.0 as u32, buffer_size.1 as u32)
+                    {
+                        tracing::error!("Couldn't resize the simulator buffer: {e}");
+                    }
+                }
+
+                draw(pixels.frame_mut(), buffer_size.0, led_size, &ledstrips);
+                if let Err(e) = pixels.render() {
+                    tracing::error!("Couldn't render the simulator: {e}");
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            Event::MainEventsCleared => {
+                if should_quit.load(Ordering::Relaxed) {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+                window.request_redraw();
+                *control_flow = ControlFlow::WaitUntil(Instant::now() + REDRAW_INTERVAL);
+            }
+            _ => {}
+        });
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Simulator thread panicked");
+            }
+        }
+        tracing::info!("Simulator stopped.");
+    }
+}
+
+fn led_size(ledstrips: &SimulatorFrame) -> usize {
+    let longest = ledstrips.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    (MAX_ROW_WIDTH / longest).clamp(MIN_LED_SIZE, MAX_LED_SIZE)
+}
+
+/// Draws every ledstrip as a row of circles in a RGBA buffer `width` pixels wide
+fn draw(buffer: &mut [u8], width: usize, led_size: usize, ledstrips: &SimulatorFrame) {
+    let radius = led_size as f32 / 2.0;
+    for (index, pixel) in buffer.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (index % width, index / width);
+        let color = ledstrips
+            .get(y / led_size)
+            .and_then(|colors| colors.get(x / led_size))
+            .filter(|_| {
+                // Distance to the center of the led
+                let dx = (x % led_size) as f32 + 0.5 - radius;
+                let dy = (y % led_size) as f32 + 0.5 - radius;
+                dx * dx + dy * dy <= (radius - 1.0) * (radius - 1.0)
+            });
+        match color {
+            Some(color) => pixel.copy_from_slice(&[color.r, color.g, color.b, 0xff]),
+            None => pixel.copy_from_slice(&BACKGROUND),
+        }
+    }
+}
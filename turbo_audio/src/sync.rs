@@ -0,0 +1,474 @@
+use crate::audio::{audio_processing::FftResult, onset::OnsetDetector};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+// Band the beats are detected in, the one of the kicks
+const BEAT_BAND: (f32, f32) = (40.0, 150.0);
+// Seconds between two beats, from 240 down to 30 beats per minute
+const MIN_BEAT_INTERVAL: f64 = 0.25;
+const MAX_BEAT_INTERVAL: f64 = 2.0;
+// Part of the way the beat interval moves toward the interval of every new beat
+const BEAT_SMOOTHING: f64 = 0.2;
+// Onsets closer than this part of the beat interval to the last beat are off-beats
+const OFF_BEAT: f64 = 0.6;
+// Seconds without an onset before the beat is forgotten, like when the music stopped
+const BEAT_TIMEOUT: f64 = 10.0;
+// Share of the gap to the phase of the detected beats the beat count makes up per second. Lower
+// runs smoother through beats detected early or late, but takes longer to catch a new tempo
+const PHASE_LOCK_RATE: f64 = 4.0;
+// Beats in a bar. The music doesn't tell where its bars start, they're counted from the first
+// beat locked on
+const BEATS_PER_BAR: f64 = 4.0;
+
+// Part of the way the offset to the time of the leader moves toward every new measure, which
+// evens out the network latency
+const OFFSET_SMOOTHING: f64 = 0.1;
+// Measures this far from the offset jump to them, like when the leader restarted
+const MAX_OFFSET_STEP: f64 = 0.5;
+// Time without a message before a follower stops following its leader
+const LEADER_TIMEOUT: Duration = Duration::from_secs(3);
+// Largest message read, far above what the state of the leader takes
+const MAX_MESSAGE_SIZE: usize = 2048;
+
+/// Keeps several instances on a network coherent, like the strips of a room driven by different
+/// machines. The leader broadcasts its time base, its beat and its profile, which the followers
+/// adopt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyncConfig {
+    pub role: SyncRole,
+    /// Instances only follow a leader of the same group, so that several groups share a network
+    #[serde(default = "default_group")]
+    pub group: String,
+    /// Udp port the leader broadcasts to and the followers listen on
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Address the leader broadcasts to, the whole local network by default
+    #[serde(default = "default_address")]
+    pub address: IpAddr,
+    /// Milliseconds between two broadcasts of the leader
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u32,
+}
+
+fn default_group() -> String {
+    "default".to_owned()
+}
+
+fn default_port() -> u16 {
+    9940
+}
+
+fn default_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::BROADCAST)
+}
+
+fn default_interval_ms() -> u32 {
+    100
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncRole {
+    Leader,
+    Follower,
+}
+
+/// When the last beat fell and the time between two beats, in seconds of the shared time base
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BeatTiming {
+    pub last: f64,
+    pub interval: f64,
+}
+
+impl BeatTiming {
+    /// Position in [0, 1) between two beats at `time`, 0 on the beats
+    pub fn phase(&self, time: f64) -> f32 {
+        ((time - self.last) / self.interval).rem_euclid(1.0) as f32
+    }
+}
+
+/// Time base and beat shared by the effects, which are the ones of the leader when following one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncClock {
+    /// Seconds added to the local time to get the shared time
+    pub offset: f64,
+    pub beat: Option<BeatTiming>,
+    /// Seconds the beat is predicted ahead, to make up for the audio to light latency
+    pub lead: f64,
+    /// Beats counted continuously at the tempo since it's known, given by a [`PhaseLock`]. None
+    /// until the beat is known
+    pub beat_count: Option<BeatCount>,
+}
+
+impl SyncClock {
+    /// Seconds of the shared time base
+    pub fn time(&self) -> f64 {
+        local_time() + self.offset
+    }
+
+    /// Position in [0, 1) between two beats, predicted `lead` ahead so that the lights land on
+    /// the beat heard. None until the beat is known
+    pub fn beat_phase(&self) -> Option<f32> {
+        self.beat.map(|beat| beat.phase(self.time() + self.lead))
+    }
+
+    /// Beats counted at the tempo since it's known, predicted ahead like [`Self::beat_phase`]
+    pub fn beats(&self) -> Option<f64> {
+        let (count, beat) = self.beat_count.zip(self.beat)?;
+        let elapsed = (self.time() + self.lead - count.time).max(0.0);
+        Some(count.beats + elapsed / beat.interval)
+    }
+
+    /// Position in [0, 1) between two beats, running smoothly at the tempo rather than jumping
+    /// when a beat is detected a little early or late
+    pub fn tempo_phase(&self) -> Option<f32> {
+        self.beats().map(|beats| beats.rem_euclid(1.0) as f32)
+    }
+
+    /// Position in [0, 1) in a bar of 4 beats, for the animations spanning several beats
+    pub fn bar_phase(&self) -> Option<f32> {
+        self.beats()
+            .map(|beats| (beats / BEATS_PER_BAR).rem_euclid(1.0) as f32)
+    }
+
+    pub fn bpm(&self) -> Option<f32> {
+        self.beat.map(|beat| (60.0 / beat.interval) as f32)
+    }
+}
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+static CLOCK: Mutex<SyncClock> = Mutex::new(SyncClock {
+    offset: 0.0,
+    beat: None,
+    lead: 0.0,
+    beat_count: None,
+});
+
+/// Seconds since the engine started
+pub fn local_time() -> f64 {
+    EPOCH.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
+/// Shared time base and beat, updated every frame
+pub fn clock() -> SyncClock {
+    *CLOCK.lock().unwrap()
+}
+
+pub fn set_clock(clock: SyncClock) {
+    *CLOCK.lock().unwrap() = clock;
+}
+
+/// Follows the beat of the music from the onsets of the kicks, skipping the off-beats
+pub struct BeatTracker {
+    detector: OnsetDetector,
+    last_beat: Option<f64>,
+    interval: Option<f64>,
+}
+
+impl Default for BeatTracker {
+    fn default() -> Self {
+        Self {
+            detector: OnsetDetector::new(BEAT_BAND.0, BEAT_BAND.1),
+            last_beat: None,
+            interval: None,
+        }
+    }
+}
+
+impl BeatTracker {
+    /// Feeds the latest fft frame at `time` of the shared time base. Returns the beat once two
+    /// onsets gave its interval
+    pub fn tick(&mut self, fft_result: &FftResult, time: f64) -> Option<BeatTiming> {
+        if self.detector.tick(fft_result) {
+            self.add_onset(time);
+        }
+        let last = self.last_beat?;
+        if time - last > BEAT_TIMEOUT {
+            self.last_beat = None;
+            self.interval = None;
+            return None;
+        }
+        self.interval.map(|interval| BeatTiming { last, interval })
+    }
+
+    fn add_onset(&mut self, time: f64) {
+        let Some(last) = self.last_beat else {
+            self.last_beat = Some(time);
+            return;
+        };
+        let gap = time - last;
+        let Some(interval) = self.interval else {
+            if (MIN_BEAT_INTERVAL..=MAX_BEAT_INTERVAL).contains(&gap) {
+                self.interval = Some(gap);
+            }
+            self.last_beat = Some(time);
+            return;
+        };
+        if gap < interval * OFF_BEAT {
+            return;
+        }
+        // Beats missed in between, like on a break, leave a gap of several intervals
+        let beats = (gap / interval).round().max(1.0);
+        let measured = (gap / beats).clamp(MIN_BEAT_INTERVAL, MAX_BEAT_INTERVAL);
+        self.interval = Some(interval + (measured - interval) * BEAT_SMOOTHING);
+        self.last_beat = Some(time);
+    }
+}
+
+/// Beats counted at `time` of the shared time base, the beat predicted ahead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeatCount {
+    pub beats: f64,
+    pub time: f64,
+}
+
+/// Position of an external transport, like the one of JACK or an Ableton Link session, when it
+/// was last read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransportPosition {
+    /// Beats since the first beat of the first bar
+    pub beats: f64,
+    pub bpm: f64,
+    /// When the position was read, in seconds of [`local_time`]
+    pub read_at: f64,
+}
+
+impl TransportPosition {
+    /// Beats since the first beat of the first bar at `time` of the local time, at the tempo of
+    /// the transport
+    pub fn beats_at(&self, time: f64) -> f64 {
+        self.beats + (time - self.read_at) * self.bpm / 60.0
+    }
+}
+
+/// Position of the transport shared between the thread reading it and the controller. None while
+/// the transport is stopped or doesn't tell its beats
+pub type SharedTransport = Arc<Mutex<Option<TransportPosition>>>;
+
+/// Counts the beats at the tempo between the ones detected, easing toward their phase so that the
+/// count never jumps nor goes back
+#[derive(Default)]
+pub struct PhaseLock {
+    count: Option<BeatCount>,
+}
+
+impl PhaseLock {
+    /// Advances the count to `time`. Forgets it while the beat is unknown
+    pub fn update(&mut self, beat: Option<BeatTiming>, time: f64) -> Option<BeatCount> {
+        let Some(beat) = beat else {
+            self.count = None;
+            return None;
+        };
+        let target = beat.phase(time) as f64;
+        let beats = match self.count {
+            Some(count) => {
+                let elapsed = (time - count.time).max(0.0);
+                let predicted = count.beats + elapsed / beat.interval;
+                // Shortest way around the beat to the detected phase
+                let error = (target - predicted + 0.5).rem_euclid(1.0) - 0.5;
+                let correction = error * (elapsed * PHASE_LOCK_RATE).min(1.0);
+                predicted + correction.max(count.beats - predicted)
+            }
+            None => target,
+        };
+        self.count = Some(BeatCount { beats, time });
+        self.count
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SyncError {
+    #[error("Couldn't open the sync socket on port {port}: {source}")]
+    Bind { port: u16, source: io::Error },
+
+    #[error("Couldn't set up the sync socket: {0}")]
+    Socket(io::Error),
+}
+
+// Broadcast by the leader
+#[derive(Debug, Serialize, Deserialize)]
+struct SyncMessage {
+    group: String,
+    time: f64,
+    beat: Option<BeatTiming>,
+    profile: Option<String>,
+}
+
+// Leader followed, and what it last broadcast
+#[derive(Debug)]
+struct Leader {
+    address: SocketAddr,
+    seen_at: Instant,
+    beat: Option<BeatTiming>,
+    profile: Option<String>,
+}
+
+/// End of the sync protocol of an instance, polled every frame without blocking
+#[derive(Debug)]
+pub struct SyncPeer {
+    config: SyncConfig,
+    socket: UdpSocket,
+    // When the leader last broadcast its state
+    sent_at: Option<Instant>,
+    // Seconds added to the local time to get the time of the leader. Kept when losing it, so
+    // that the effects don't jump
+    offset: f64,
+    leader: Option<Leader>,
+}
+
+impl SyncPeer {
+    pub fn new(config: &SyncConfig) -> Result<Self, SyncError> {
+        let port = match config.role {
+            SyncRole::Leader => 0,
+            SyncRole::Follower => config.port,
+        };
+        let socket =
+            UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(|source| SyncError::Bind {
+                port: config.port,
+                source,
+            })?;
+        socket.set_nonblocking(true).map_err(SyncError::Socket)?;
+        if config.role == SyncRole::Leader {
+            socket.set_broadcast(true).map_err(SyncError::Socket)?;
+        }
+        tracing::info!(
+            "Syncing the group {} on port {} as a {:?}",
+            config.group,
+            config.port,
+            config.role
+        );
+        Ok(Self {
+            config: config.clone(),
+            socket,
+            sent_at: None,
+            offset: 0.0,
+            leader: None,
+        })
+    }
+
+    pub fn role(&self) -> SyncRole {
+        self.config.role
+    }
+
+    /// Seconds added to the local time to get the shared time
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    /// Beat of the leader followed, if it knows it
+    pub fn leader_beat(&self) -> Option<BeatTiming> {
+        self.leader.as_ref().and_then(|leader| leader.beat)
+    }
+
+    /// Broadcasts the state of the leader once the interval elapsed since the last broadcast
+    pub fn broadcast(&mut self, beat: Option<BeatTiming>, profile: Option<&str>) {
+        let interval = Duration::from_millis(self.config.interval_ms as u64);
+        if self
+            .sent_at
+            .is_some_and(|sent_at| sent_at.elapsed() < interval)
+        {
+            return;
+        }
+        self.sent_at = Some(Instant::now());
+        let message = SyncMessage {
+            group: self.config.group.clone(),
+            time: local_time(),
+            beat,
+            profile: profile.map(str::to_owned),
+        };
+        let Ok(message) = serde_json::to_vec(&message) else {
+            return;
+        };
+        if let Err(e) = self
+            .socket
+            .send_to(&message, (self.config.address, self.config.port))
+        {
+            tracing::debug!("Couldn't broadcast the sync state: {e}");
+        }
+    }
+
+    /// Reads the messages of the leader. Returns the profile it switched to since the last
+    /// call, if any
+    pub fn receive(&mut self) -> Option<String> {
+        let mut switched_to = None;
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        loop {
+            let (size, address) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    tracing::debug!("Couldn't read the sync socket: {e}");
+                    break;
+                }
+            };
+            if let Some(profile) = self.on_message(address, &buffer[..size]) {
+                switched_to = Some(profile);
+            }
+        }
+
+        if self
+            .leader
+            .as_ref()
+            .is_some_and(|leader| leader.seen_at.elapsed() > LEADER_TIMEOUT)
+        {
+            tracing::warn!("Lost the sync leader, keeping its time base");
+            self.leader = None;
+        }
+        switched_to
+    }
+
+    /// Decodes a message received from `address` and follows it if it's from the leader of the
+    /// group. Returns the profile the leader switched to. Public for the fuzz targets
+    pub fn on_message(&mut self, address: SocketAddr, data: &[u8]) -> Option<String> {
+        let Ok(message) = serde_json::from_slice::<SyncMessage>(data) else {
+            tracing::debug!("Ignoring an invalid sync message from {address}");
+            return None;
+        };
+        if message.group != self.config.group {
+            return None;
+        }
+        self.follow(address, message)
+    }
+
+    // Adjusts to a message. Returns the profile the leader switched to
+    fn follow(&mut self, address: SocketAddr, message: SyncMessage) -> Option<String> {
+        // A single leader is followed at a time, the others only take over once it's lost
+        if self
+            .leader
+            .as_ref()
+            .is_some_and(|leader| leader.address != address)
+        {
+            return None;
+        }
+        let measured = message.time - local_time();
+        if self.leader.is_none() {
+            tracing::info!("Following the sync leader at {address}");
+        }
+        // The latency of the network only delays the messages, so the offset is measured a bit
+        // short
+        if self.leader.is_none() || (measured - self.offset).abs() > MAX_OFFSET_STEP {
+            self.offset = measured;
+        } else {
+            self.offset += (measured - self.offset) * OFFSET_SMOOTHING;
+        }
+
+        let switched = self
+            .leader
+            .as_ref()
+            .is_none_or(|leader| leader.profile != message.profile);
+        self.leader = Some(Leader {
+            address,
+            seen_at: Instant::now(),
+            beat: message.beat,
+            profile: message.profile.clone(),
+        });
+        message.profile.filter(|_| switched)
+    }
+}
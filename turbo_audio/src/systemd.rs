@@ -0,0 +1,85 @@
+use std::{
+    cell::Cell,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    time::{Duration, Instant},
+};
+
+/// Tells systemd when the engine is ready or stopping, and pings its watchdog, for services of
+/// `Type=notify`. See sd_notify(3)
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    // Half of the watchdog timeout, so that a late tick doesn't get the service killed
+    watchdog_interval: Option<Duration>,
+    last_ping: Cell<Instant>,
+}
+
+impl Notifier {
+    /// None when turbo_audio isn't started by systemd, or by a service that doesn't expect
+    /// notifications
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("NOTIFY_SOCKET").ok()?;
+        // Names starting with @ are in the abstract namespace
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(&path),
+        };
+        let result = address.and_then(|address| Ok((UnixDatagram::unbound()?, address)));
+        let (socket, address) = match result {
+            Ok(notify) => notify,
+            Err(e) => {
+                tracing::error!("Couldn't open the systemd notification socket {path}: {e}");
+                return None;
+            }
+        };
+        // Only for the process systemd started, not its children
+        let watchdog_pid = std::env::var("WATCHDOG_PID").ok();
+        let watchdog_interval = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|_| watchdog_pid.is_none_or(|pid| pid == std::process::id().to_string()))
+            .map(|usec: u64| Duration::from_micros(usec) / 2);
+        if let Some(interval) = watchdog_interval {
+            tracing::info!("Pinging the systemd watchdog every {interval:?}");
+        }
+        Some(Self {
+            socket,
+            address,
+            watchdog_interval,
+            last_ping: Cell::new(Instant::now()),
+        })
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.address) {
+            tracing::warn!("Couldn't notify systemd: {e}");
+        }
+    }
+
+    pub fn ready(&self) {
+        self.notify("READY=1\nSTATUS=Running");
+    }
+
+    pub fn restarting(&self) {
+        self.notify("STATUS=Restarting to apply the config");
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1\nSTATUS=Stopping");
+    }
+
+    /// Pings the watchdog if it's due. Called every tick, so that systemd restarts the service
+    /// when the run loop hangs
+    pub fn tick(&self) {
+        let Some(interval) = self.watchdog_interval else {
+            return;
+        };
+        if self.last_ping.get().elapsed() >= interval {
+            self.notify("WATCHDOG=1");
+            self.last_ping.set(Instant::now());
+        }
+    }
+}
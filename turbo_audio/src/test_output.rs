@@ -0,0 +1,135 @@
+use crate::{
+    config_parser::TurboAudioConfig,
+    connections::{ConnectionError, ConnectionFactory},
+    test_pattern::{TestPattern, TestPatternRenderer},
+    ticks_per_second,
+};
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TestOutputError {
+    #[error("Connection {0} isn't in the config")]
+    UnknownConnection(usize),
+
+    #[error("No ledstrip is sent to connection {0}, give the led count with --leds")]
+    NoLeds(usize),
+
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TestOutputArgs {
+    /// Id of the connection in the config
+    connection: usize,
+
+    /// Pattern to show
+    #[arg(long, value_enum, default_value_t = TestPattern::Solid)]
+    pattern: TestPattern,
+
+    /// Leds in the frames. The leds of the ledstrips sent to the connection if missing
+    #[arg(long)]
+    leds: Option<usize>,
+
+    /// Seconds the pattern is shown. A single cycle of the pattern if missing
+    #[arg(long)]
+    secs: Option<f32>,
+}
+
+// Leds of the frames the engine sends to the connection
+fn connection_led_count(config: &TurboAudioConfig, connection_id: usize) -> usize {
+    config
+        .ledstrips
+        .iter()
+        .filter(|ledstrip| ledstrip.connection_id == connection_id)
+        .map(|ledstrip| ledstrip.offset.unwrap_or(0) + ledstrip.size)
+        .max()
+        .unwrap_or(0)
+}
+
+// Leds of every segment of the ledstrips sent to the connection, in the frames of the connection
+fn connection_segments(config: &TurboAudioConfig, connection_id: usize) -> Vec<Range<usize>> {
+    let mut segments = Vec::new();
+    for ledstrip in config
+        .ledstrips
+        .iter()
+        .filter(|ledstrip| ledstrip.connection_id == connection_id)
+    {
+        let mut start = ledstrip.offset.unwrap_or(0);
+        // The segments are in pixels, which don't line up with the leds once transformed
+        if ledstrip.effects.is_empty() || !ledstrip.output_stages().is_empty() {
+            segments.push(start..start + ledstrip.size);
+            continue;
+        }
+        for segment in &ledstrip.effects {
+            segments.push(start..start + segment.effect_size);
+            start += segment.effect_size;
+        }
+    }
+    segments
+}
+
+/// Shows a test pattern on a connection of the config, then turns its leds off, to check the
+/// wiring, the led count and the color order without running the engine
+pub fn run(config: &TurboAudioConfig, args: &TestOutputArgs) -> Result<(), TestOutputError> {
+    let device = config
+        .devices
+        .iter()
+        .find(|device| device.id == args.connection)
+        .ok_or(TestOutputError::UnknownConnection(args.connection))?;
+    let led_count = args
+        .leds
+        .unwrap_or_else(|| connection_led_count(config, args.connection));
+    if led_count == 0 {
+        return Err(TestOutputError::NoLeds(args.connection));
+    }
+
+    let mut connection = ConnectionFactory::default().create(&device.kind, &device.connection)?;
+    let mut encoder = device.encoding.encoder();
+    let mut renderer = TestPatternRenderer::new(
+        args.pattern,
+        led_count,
+        connection_segments(config, args.connection),
+    );
+    let tick = Duration::from_secs(1) / ticks_per_second();
+    let frame_count = match args.secs {
+        Some(secs) => ((secs * ticks_per_second() as f32).ceil() as u64).max(1),
+        None => renderer.cycle_ticks(),
+    };
+
+    println!("Showing {:?} on {led_count} leds", args.pattern);
+    let mut failed = 0;
+    let mut last_error = None;
+    for index in 0..=frame_count {
+        let frame_start = Instant::now();
+        // The last frame turns the leds off
+        let frame = if index < frame_count {
+            renderer.next_frame()
+        } else {
+            vec![0; led_count * 3]
+        };
+        // Sent every tick like the engine does, since some controllers time out otherwise
+        let result = encoder
+            .encode(&frame)
+            .map_err(ConnectionError::from)
+            .and_then(|packets| {
+                packets
+                    .into_iter()
+                    .try_for_each(|packet| connection.send_frame(packet))
+            });
+        if let Err(e) = result {
+            failed += 1;
+            last_error = Some(e);
+        }
+        std::thread::sleep(tick.saturating_sub(frame_start.elapsed()));
+    }
+
+    if let Some(e) = last_error {
+        println!("{failed}/{} frames failed: {e}", frame_count + 1);
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use crate::ticks_per_second;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use turbo_plugin::Color;
+
+/// Pattern shown on a connection instead of its ledstrips, to check the wiring, the led count and
+/// the color order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum TestPattern {
+    /// Red, green, blue and white on every led, a second each
+    Solid,
+    /// A single led lit from the first led to the last, green on the first led and white after
+    Chase,
+    /// Every segment in a dim color, with its first and last leds blinking as many times as its
+    /// number, counting from 1
+    Segments,
+}
+
+const RED: Color = Color { r: 255, g: 0, b: 0 };
+const GREEN: Color = Color { r: 0, g: 255, b: 0 };
+const BLUE: Color = Color { r: 0, g: 0, b: 255 };
+const WHITE: Color = Color {
+    r: 255,
+    g: 255,
+    b: 255,
+};
+// Alternated between the segments so that the boundaries show even when the markers are off
+const SEGMENT_COLORS: [Color; 2] = [Color { r: 32, g: 0, b: 0 }, Color { r: 0, g: 0, b: 32 }];
+
+const SOLID_COLORS: [Color; 4] = [RED, GREEN, BLUE, WHITE];
+// Ticks the markers stay on, then off, for every blink
+fn blink_ticks() -> u64 {
+    ticks_per_second() as u64 / 4
+}
+
+// Ticks between two series of blinks, so that they can be counted
+fn pause_ticks() -> u64 {
+    ticks_per_second() as u64
+}
+
+/// Renders the frames of a test pattern, one per tick
+#[derive(Debug)]
+pub struct TestPatternRenderer {
+    pattern: TestPattern,
+    led_count: usize,
+    // Leds of every segment in the frame
+    segments: Vec<Range<usize>>,
+    tick: u64,
+}
+
+impl TestPatternRenderer {
+    pub fn new(pattern: TestPattern, led_count: usize, segments: Vec<Range<usize>>) -> Self {
+        Self {
+            pattern,
+            led_count,
+            segments,
+            tick: 0,
+        }
+    }
+
+    /// Ticks after which the pattern starts over
+    pub fn cycle_ticks(&self) -> u64 {
+        let ticks = match self.pattern {
+            TestPattern::Solid => SOLID_COLORS.len() as u64 * ticks_per_second() as u64,
+            TestPattern::Chase => self.led_count as u64,
+            TestPattern::Segments => self.segments.len() as u64 * 2 * blink_ticks() + pause_ticks(),
+        };
+        ticks.max(1)
+    }
+
+    /// Rgb bytes of the next frame, 3 per led
+    pub fn next_frame(&mut self) -> Vec<u8> {
+        let mut colors = vec![Color::default(); self.led_count];
+        let tick = self.tick % self.cycle_ticks();
+        match self.pattern {
+            TestPattern::Solid => {
+                colors.fill(SOLID_COLORS[(tick / ticks_per_second() as u64) as usize]);
+            }
+            TestPattern::Chase => {
+                if let Some(color) = colors.get_mut(tick as usize) {
+                    *color = if tick == 0 { GREEN } else { WHITE };
+                }
+            }
+            TestPattern::Segments => {
+                for (index, segment) in self.segments.iter().enumerate() {
+                    let segment =
+                        segment.start.min(self.led_count)..segment.end.min(self.led_count);
+                    if segment.is_empty() {
+                        continue;
+                    }
+                    colors[segment.clone()].fill(SEGMENT_COLORS[index % SEGMENT_COLORS.len()]);
+                    let blinks = index as u64 + 1;
+                    if tick < blinks * 2 * blink_ticks() && (tick / blink_ticks()).is_multiple_of(2)
+                    {
+                        colors[segment.start] = WHITE;
+                        colors[segment.end - 1] = WHITE;
+                    }
+                }
+            }
+        }
+        self.tick += 1;
+        colors
+            .iter()
+            .flat_map(|color| [color.r, color.g, color.b])
+            .collect()
+    }
+}
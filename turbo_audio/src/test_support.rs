@@ -0,0 +1,361 @@
+//! Support for the end-to-end tests of the connections. [`MockReceiver`] listens on a local port
+//! like a tcp or udp led receiver and records the frames it gets, so that a test can point a
+//! device of its config at [`MockReceiver::address`] and assert on the exact bytes the engine
+//! sent. Only built for the tests and with the `test-support` feature.
+
+use crate::connections::{
+    encoder::{crc16, FRAMED_MAGIC},
+    tcp::AUTH_MAGIC,
+};
+use std::{
+    io::{self, ErrorKind, Read},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+// How often the receiver checks if it should stop while waiting for a sender or for bytes
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// Magic, length and sequence number of a frame of the framed encoding, before its data
+const FRAMED_HEADER_LEN: usize = 6;
+
+/// How the receiver splits the tcp stream into frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiverProtocol {
+    /// The rgb bytes as is, `frame_len` bytes per frame
+    Raw { frame_len: usize },
+    /// The frames of [`FrameEncoding::Framed`](crate::connections::encoder::FrameEncoding),
+    /// checked against their crc
+    Framed,
+}
+
+/// A frame the receiver got
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFrame {
+    /// Sequence number of the framed encoding
+    pub sequence: Option<u16>,
+    /// The rgb bytes
+    pub data: Vec<u8>,
+}
+
+/// Why the receiver didn't return the expected frames
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ReceiverError {
+    #[error("No frame was received within {0:?}")]
+    Timeout(Duration),
+
+    #[error("The stream doesn't start with the magic of a frame: {0:02x?}")]
+    InvalidMagic(Vec<u8>),
+
+    #[error("The crc of frame {0} doesn't match its data")]
+    InvalidCrc(u16),
+
+    #[error("The receiver stopped: {0}")]
+    Stopped(String),
+}
+
+/// Led receiver for the tests, accepting one sender at a time on a port of localhost. A sender
+/// reconnecting starts a new stream, with a new handshake. The udp receiver reads the datagrams
+/// as a single stream, so that the frames split over several datagrams are put back together.
+pub struct MockReceiver {
+    address: SocketAddr,
+    frames: mpsc::Receiver<Result<ReceivedFrame, ReceiverError>>,
+    // Token of the last handshake
+    token: Arc<Mutex<Option<String>>>,
+    should_quit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MockReceiver {
+    /// Listens for tcp senders on a free port of localhost
+    pub fn bind(protocol: ReceiverProtocol) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        Ok(Self::spawn(address, move |tx, token, should_quit| {
+            Self::accept_loop(&listener, protocol, tx, token, should_quit)
+        }))
+    }
+
+    /// Listens for datagrams on a free port of localhost
+    pub fn bind_udp(protocol: ReceiverProtocol) -> io::Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let address = socket.local_addr()?;
+        Ok(Self::spawn(address, move |tx, token, should_quit| {
+            let mut parser = StreamParser::new(protocol);
+            Self::receive_datagrams(&socket, &mut parser, tx, token, should_quit)
+        }))
+    }
+
+    // Runs the receiving loop on its own thread, reporting why it stopped as the last frame
+    fn spawn(
+        address: SocketAddr,
+        receive: impl FnOnce(
+                &mpsc::Sender<Result<ReceivedFrame, ReceiverError>>,
+                &Mutex<Option<String>>,
+                &AtomicBool,
+            ) -> io::Result<()>
+            + Send
+            + 'static,
+    ) -> Self {
+        let (tx, frames) = mpsc::channel();
+        let token = Arc::new(Mutex::new(None));
+        let should_quit = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let token = token.clone();
+            let should_quit = should_quit.clone();
+            move || {
+                if let Err(e) = receive(&tx, &token, &should_quit) {
+                    let _ = tx.send(Err(ReceiverError::Stopped(e.to_string())));
+                }
+            }
+        });
+        Self {
+            address,
+            frames,
+            token,
+            should_quit,
+            thread: Some(thread),
+        }
+    }
+
+    /// Address for the tcp or udp device of the config
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Token given by the last handshake, if the sender has one
+    pub fn token(&self) -> Option<String> {
+        self.token.lock().unwrap().clone()
+    }
+
+    /// Waits for the next frame
+    pub fn next_frame(&self, timeout: Duration) -> Result<ReceivedFrame, ReceiverError> {
+        match self.frames.recv_timeout(timeout) {
+            Ok(frame) => frame,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(ReceiverError::Timeout(timeout)),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(ReceiverError::Stopped(
+                "The receiver thread ended".to_owned(),
+            )),
+        }
+    }
+
+    /// Waits for the next `count` frames, for `timeout` in total
+    pub fn frames(
+        &self,
+        count: usize,
+        timeout: Duration,
+    ) -> Result<Vec<ReceivedFrame>, ReceiverError> {
+        let deadline = Instant::now() + timeout;
+        (0..count)
+            .map(|_| self.next_frame(deadline.saturating_duration_since(Instant::now())))
+            .collect()
+    }
+
+    /// Drops the frames received so far, like the ones sent before the effects settled
+    pub fn clear(&self) {
+        while self.frames.try_recv().is_ok() {}
+    }
+
+    fn accept_loop(
+        listener: &TcpListener,
+        protocol: ReceiverProtocol,
+        tx: &mpsc::Sender<Result<ReceivedFrame, ReceiverError>>,
+        token: &Mutex<Option<String>>,
+        should_quit: &AtomicBool,
+    ) -> io::Result<()> {
+        while !should_quit.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let mut parser = StreamParser::new(protocol);
+                    Self::receive(stream, &mut parser, tx, token, should_quit)?;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // Reads a sender until it disconnects or sends something invalid
+    fn receive(
+        mut stream: TcpStream,
+        parser: &mut StreamParser,
+        tx: &mpsc::Sender<Result<ReceivedFrame, ReceiverError>>,
+        token: &Mutex<Option<String>>,
+        should_quit: &AtomicBool,
+    ) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let mut buffer = [0; 4096];
+        while !should_quit.load(Ordering::Relaxed) {
+            let read = match stream.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(read) => read,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            if !Self::parse(parser, &buffer[..read], tx, token) {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_datagrams(
+        socket: &UdpSocket,
+        parser: &mut StreamParser,
+        tx: &mpsc::Sender<Result<ReceivedFrame, ReceiverError>>,
+        token: &Mutex<Option<String>>,
+        should_quit: &AtomicBool,
+    ) -> io::Result<()> {
+        let mut buffer = [0; 65536];
+        while !should_quit.load(Ordering::Relaxed) {
+            let read = match socket.recv(&mut buffer) {
+                Ok(read) => read,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            if !Self::parse(parser, &buffer[..read], tx, token) {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    // Sends the frames completed by `bytes`. False once the stream can't be followed anymore
+    fn parse(
+        parser: &mut StreamParser,
+        bytes: &[u8],
+        tx: &mpsc::Sender<Result<ReceivedFrame, ReceiverError>>,
+        token: &Mutex<Option<String>>,
+    ) -> bool {
+        parser.extend(bytes);
+        if let Some(handshake) = parser.take_handshake() {
+            *token.lock().unwrap() = Some(handshake);
+        }
+        loop {
+            match parser.next_frame() {
+                Ok(Some(frame)) => {
+                    let _ = tx.send(Ok(frame));
+                }
+                Ok(None) => return true,
+                Err(e) => {
+                    // The stream can't be followed past a broken frame
+                    let _ = tx.send(Err(e));
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for MockReceiver {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Bytes of a tcp stream not made into frames yet
+struct StreamParser {
+    protocol: ReceiverProtocol,
+    buffer: Vec<u8>,
+    // Whether the stream may still start with a handshake
+    at_start: bool,
+}
+
+impl StreamParser {
+    fn new(protocol: ReceiverProtocol) -> Self {
+        Self {
+            protocol,
+            buffer: Vec::new(),
+            at_start: true,
+        }
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // The token of the handshake starting the stream, once it is complete
+    fn take_handshake(&mut self) -> Option<String> {
+        if !self.at_start {
+            return None;
+        }
+        let prefix = self.buffer.len().min(AUTH_MAGIC.len());
+        if self.buffer[..prefix] != AUTH_MAGIC[..prefix] {
+            self.at_start = false;
+            return None;
+        }
+        let header_len = AUTH_MAGIC.len() + 2;
+        let length = u16::from_be_bytes(
+            self.buffer
+                .get(AUTH_MAGIC.len()..header_len)?
+                .try_into()
+                .ok()?,
+        );
+        let token = self.buffer.get(header_len..header_len + length as usize)?;
+        let token = String::from_utf8_lossy(token).into_owned();
+        self.buffer.drain(..header_len + length as usize);
+        self.at_start = false;
+        Some(token)
+    }
+
+    fn next_frame(&mut self) -> Result<Option<ReceivedFrame>, ReceiverError> {
+        if self.at_start {
+            // Waiting for the rest of the handshake
+            return Ok(None);
+        }
+        match self.protocol {
+            ReceiverProtocol::Raw { frame_len } => {
+                if frame_len == 0 || self.buffer.len() < frame_len {
+                    return Ok(None);
+                }
+                Ok(Some(ReceivedFrame {
+                    sequence: None,
+                    data: self.buffer.drain(..frame_len).collect(),
+                }))
+            }
+            ReceiverProtocol::Framed => {
+                let magic_len = self.buffer.len().min(FRAMED_MAGIC.len());
+                if self.buffer[..magic_len] != FRAMED_MAGIC[..magic_len] {
+                    let start = self.buffer.len().min(FRAMED_HEADER_LEN);
+                    return Err(ReceiverError::InvalidMagic(self.buffer[..start].to_vec()));
+                }
+                if self.buffer.len() < FRAMED_HEADER_LEN {
+                    return Ok(None);
+                }
+                let length = u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as usize;
+                let sequence = u16::from_be_bytes([self.buffer[4], self.buffer[5]]);
+                let frame_len = FRAMED_HEADER_LEN + length + 2;
+                if self.buffer.len() < frame_len {
+                    return Ok(None);
+                }
+                let frame: Vec<u8> = self.buffer.drain(..frame_len).collect();
+                let data = &frame[FRAMED_HEADER_LEN..FRAMED_HEADER_LEN + length];
+                let crc = u16::from_be_bytes([frame[frame_len - 2], frame[frame_len - 1]]);
+                if crc16(data) != crc {
+                    return Err(ReceiverError::InvalidCrc(sequence));
+                }
+                Ok(Some(ReceivedFrame {
+                    sequence: Some(sequence),
+                    data: data.to_vec(),
+                }))
+            }
+        }
+    }
+}
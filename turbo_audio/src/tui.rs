@@ -0,0 +1,244 @@
+use crate::{
+    audio::{audio_processing::FftResult, onset::OnsetDetector},
+    controller::Controller,
+    info::{ConnectionHealth, ConnectionInfo},
+};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Sparkline},
+    Frame, Terminal,
+};
+use std::{
+    io::Stdout,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const SPECTRUM_BANDS: usize = 64;
+const SPECTRUM_RANGE: (f32, f32) = (20.0, 16000.0);
+// Frequency band in which kicks carry most of their energy
+const BEAT_BAND: (f32, f32) = (40.0, 150.0);
+// How many frames the beat indicator stays lit after a beat
+const BEAT_FRAMES: u32 = 6;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Everything the dashboard shows, captured from the run loop once per tick
+#[derive(Default, Clone)]
+struct DashboardFrame {
+    spectrum: Vec<u64>,
+    beat: bool,
+    ledstrips: Vec<(usize, Vec<turbo_plugin::Color>)>,
+    connections: Vec<ConnectionInfo>,
+    fps: f32,
+}
+
+/// Terminal dashboard showing the live spectrum, beats, ledstrip previews, connection status and
+/// frame rate. Drawn from its own thread so that a slow terminal never delays a tick.
+pub struct Dashboard {
+    frame: Arc<Mutex<DashboardFrame>>,
+    beat_detector: OnsetDetector,
+    last_update: Option<Instant>,
+    fps: f32,
+    thread: Option<JoinHandle<()>>,
+    should_quit: Arc<AtomicBool>,
+}
+
+impl Dashboard {
+    pub fn new() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(std::io::stdout(), EnterAlternateScreen)?;
+        let terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))?;
+
+        let frame: Arc<Mutex<DashboardFrame>> = Arc::default();
+        let should_quit: Arc<AtomicBool> = Arc::default();
+        let thread = thread::spawn({
+            let frame = frame.clone();
+            let should_quit = should_quit.clone();
+            move || Self::draw_loop(terminal, frame, should_quit)
+        });
+
+        Ok(Self {
+            frame,
+            beat_detector: OnsetDetector::new(BEAT_BAND.0, BEAT_BAND.1),
+            last_update: None,
+            fps: 0.0,
+            thread: Some(thread),
+            should_quit,
+        })
+    }
+
+    /// Captures the state of the engine after a tick
+    pub fn update(&mut self, fft_result: &FftResult, controller: &Controller) {
+        let now = Instant::now();
+        if let Some(last_update) = self.last_update {
+            let elapsed = now.duration_since(last_update).as_secs_f32();
+            if elapsed > 0.0 {
+                self.fps += 0.1 * (1.0 / elapsed - self.fps);
+            }
+        }
+        self.last_update = Some(now);
+        self.beat_detector.tick(fft_result);
+
+        let mut ledstrips: Vec<_> = controller.led_strip_colors().collect();
+        ledstrips.sort_by_key(|(id, _)| *id);
+        let mut connections = controller.connection_info();
+        connections.sort_by_key(|connection| connection.id);
+
+        *self.frame.lock().unwrap() = DashboardFrame {
+            spectrum: spectrum(fft_result),
+            beat: self.beat_detector.frames_since_onset() < BEAT_FRAMES,
+            ledstrips,
+            connections,
+            fps: self.fps,
+        };
+    }
+
+    fn draw_loop(
+        mut terminal: Terminal<CrosstermBackend<Stdout>>,
+        frame: Arc<Mutex<DashboardFrame>>,
+        should_quit: Arc<AtomicBool>,
+    ) {
+        while !should_quit.load(Ordering::Relaxed) {
+            let dashboard_frame = frame.lock().unwrap().clone();
+            if let Err(e) = terminal.draw(|frame| draw(frame, &dashboard_frame)) {
+                tracing::error!("Couldn't draw the dashboard: {e}");
+                return;
+            }
+
+            // The terminal is in raw mode so ctrl-c doesn't raise a signal anymore
+            match event::poll(REDRAW_INTERVAL).and_then(|ready| ready.then(event::read).transpose())
+            {
+                Ok(Some(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    let ctrl_c = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                        tracing::info!("Quit requested from the dashboard");
+                        crate::SHOULD_QUIT.store(true, Ordering::Relaxed);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Couldn't read terminal events: {e}"),
+            }
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.should_quit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::error!("Dashboard thread panicked");
+            }
+        }
+        if let Err(e) = terminal::disable_raw_mode()
+            .and_then(|_| crossterm::execute!(std::io::stdout(), LeaveAlternateScreen))
+        {
+            tracing::error!("Couldn't restore the terminal: {e}");
+        }
+    }
+}
+
+/// Averages the fft into logarithmically spaced bands
+fn spectrum(fft_result: &FftResult) -> Vec<u64> {
+    fft_result
+        .log_bands(SPECTRUM_BANDS, SPECTRUM_RANGE)
+        .into_iter()
+        .map(|amplitude| (amplitude * 1000.0) as u64)
+        .collect()
+}
+
+fn draw(frame: &mut Frame, dashboard_frame: &DashboardFrame) {
+    let [header, spectrum, ledstrips, connections] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(10),
+        Constraint::Length(dashboard_frame.ledstrips.len() as u16 + 2),
+        Constraint::Min(3),
+    ])
+    .areas(frame.size());
+
+    let beat_style = if dashboard_frame.beat {
+        Style::default().fg(Color::Black).bg(Color::Yellow)
+    } else {
+        Style::default().fg(Color::DarkGray)
+    };
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::styled(" BEAT ", beat_style),
+            Span::raw(format!("  {:.1} fps  (q to quit)", dashboard_frame.fps)),
+        ])),
+        header,
+    );
+
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::default().title("Spectrum").borders(Borders::ALL))
+            .style(Style::default().fg(Color::Cyan))
+            .data(&dashboard_frame.spectrum),
+        spectrum,
+    );
+
+    let preview_width = ledstrips.width.saturating_sub(2 + 6) as usize;
+    let lines: Vec<Line> = dashboard_frame
+        .ledstrips
+        .iter()
+        .map(|(id, colors)| {
+            let mut spans = vec![Span::raw(format!("{id:>4}: "))];
+            // Downsample long strips so that they fit in a single line
+            let width = preview_width.min(colors.len());
+            spans.extend((0..width).map(|column| {
+                let color = colors[column * colors.len() / width];
+                Span::styled(
+                    "█",
+                    Style::default().fg(Color::Rgb(color.r, color.g, color.b)),
+                )
+            }));
+            Line::from(spans)
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().title("Ledstrips").borders(Borders::ALL)),
+        ledstrips,
+    );
+
+    let lines: Vec<Line> = dashboard_frame
+        .connections
+        .iter()
+        .map(|connection| {
+            let (health, color) = match connection.health {
+                ConnectionHealth::Connected => ("connected", Color::Green),
+                ConnectionHealth::Reconnecting => ("reconnecting", Color::Yellow),
+                ConnectionHealth::Dead => ("dead", Color::Red),
+            };
+            let latency = connection
+                .latency_ms
+                .map(|latency_ms| format!("  {latency_ms:.1}ms"))
+                .unwrap_or_default();
+            Line::from(vec![
+                Span::raw(format!("{:>4}: ", connection.id)),
+                Span::styled(health, Style::default().fg(color)),
+                Span::raw(format!(
+                    "{latency}  {:.1}kB/s  {} dropped/s  tripped {} time(s)",
+                    connection.bytes_per_second as f32 / 1000.0,
+                    connection.dropped_per_second,
+                    connection.trip_count
+                )),
+            ])
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().title("Connections").borders(Borders::ALL)),
+        connections,
+    );
+}
@@ -0,0 +1,102 @@
+use crate::ticks_per_second;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Limits past which a part of the engine is considered stalled and restarted, so that the rest
+/// of the pipeline keeps running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// Time without samples from a live audio device before its stream is restarted
+    pub audio_stall_ms: u64,
+    /// Average time to send a frame on a connection before it's restarted, since its frames are
+    /// dropped for newer ones
+    pub send_stall_ms: u64,
+    /// Frames per second under which the engine warns that it can't keep up. 90% of the target
+    /// fps if missing
+    pub min_fps: Option<u32>,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            audio_stall_ms: 2000,
+            send_stall_ms: 50,
+            min_fps: None,
+        }
+    }
+}
+
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+/// Watches the tick rate and the flow of the audio samples of the run loop
+#[derive(Debug)]
+pub struct Watchdog {
+    window_start: Instant,
+    ticks_in_window: u32,
+    // Whether the last window was under the minimum fps, to warn once until it recovers
+    slow: bool,
+    // Audio source name to when it last had samples, or was restarted
+    last_samples: HashMap<String, Instant>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            window_start: Instant::now(),
+            ticks_in_window: 0,
+            slow: false,
+            last_samples: HashMap::new(),
+        }
+    }
+}
+
+impl Watchdog {
+    /// Counts a tick of the run loop, and warns when the engine gets slower than `min_fps` or
+    /// catches up again
+    pub fn tick(&mut self, config: &WatchdogConfig) {
+        self.ticks_in_window += 1;
+        let elapsed = self.window_start.elapsed();
+        if elapsed < FPS_WINDOW {
+            return;
+        }
+        let fps = (self.ticks_in_window as f32 / elapsed.as_secs_f32()).round() as u32;
+        self.ticks_in_window = 0;
+        self.window_start = Instant::now();
+
+        let target = ticks_per_second();
+        let slow = fps < config.min_fps.unwrap_or(target * 9 / 10);
+        if slow && !self.slow {
+            tracing::warn!("The engine runs at {fps} fps instead of {target}");
+        } else if !slow && self.slow {
+            tracing::info!("The engine is back to {fps} fps");
+        }
+        self.slow = slow;
+    }
+
+    /// Accounts for whether the audio source got samples this tick, and returns true once when
+    /// it went without for longer than `audio_stall_ms`. The source then has as long again to
+    /// come back
+    pub fn audio_stalled(
+        &mut self,
+        name: &str,
+        has_samples: bool,
+        config: &WatchdogConfig,
+    ) -> bool {
+        let now = Instant::now();
+        let last_samples = self.last_samples.entry(name.to_owned()).or_insert(now);
+        if has_samples {
+            *last_samples = now;
+            return false;
+        }
+        if now.duration_since(*last_samples) < Duration::from_millis(config.audio_stall_ms) {
+            return false;
+        }
+        *last_samples = now;
+        true
+    }
+}
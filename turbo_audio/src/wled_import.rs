@@ -0,0 +1,515 @@
+use crate::{
+    config_parser::{
+        DeviceConfig, EffectConfig, EffectConfigType, EffectSettingConfig, LedstripConfig,
+        ProfileConfig, ProfileLedstripConfig, SettingsConfigType,
+    },
+    connections::encoder::FrameEncoding,
+    discovery::{self, DiscoveredLayout, DiscoveredSegment},
+    post_processing,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+// Port WLED receives DDP on
+const DDP_PORT: u16 = 4048;
+
+// WLED effect ids and the lua effects closest to them. The other effects become solid colors
+const EFFECTS: &[(u16, &str)] = &[
+    (0, "solid.lua"),
+    (2, "breathing.lua"),
+    (3, "color_wipe.lua"),
+    (9, "rainbow.lua"),
+    (13, "theater_chase.lua"),
+    (17, "twinkle.lua"),
+    (40, "scanner.lua"),
+];
+const SOLID_EFFECT: &str = "solid.lua";
+
+// Effect speed of WLED that the speed of 1 of the classic effects matches
+const DEFAULT_WLED_SPEED: u8 = 128;
+
+#[derive(Error, Debug)]
+pub enum ImportWledError {
+    #[error("Couldn't read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("{0} isn't valid json: {1}")]
+    Json(PathBuf, serde_json::Error),
+
+    #[error("{0} isn't a WLED export of presets, config or state")]
+    UnknownExport(PathBuf),
+
+    #[error("The exports don't tell how many leds there are, add the config (cfg.json) of WLED")]
+    NoLeds,
+
+    #[error("The exports don't name the controller, pass its address with --host")]
+    NoHost,
+
+    #[error("Couldn't write the config: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+/// Converts the exports of a WLED controller into the devices, ledstrips, effects and profiles of
+/// a config: its segments become the segments of the ledstrip, their effects and colors the
+/// closest lua effects, and its presets profiles
+#[derive(clap::Args, Debug, Clone)]
+pub struct ImportWledArgs {
+    /// Json exported from WLED, in any order: its presets (presets.json), its config (cfg.json)
+    /// or its state (the /json API)
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Hostname or ip the generated connection streams the colors to. Read from the exports if
+    /// missing
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Port the generated connection streams the colors to
+    #[arg(long, default_value_t = DDP_PORT)]
+    pub port: u16,
+
+    /// Id given to the generated device
+    #[arg(long, default_value_t = 1)]
+    pub device_id: usize,
+
+    /// Id given to the generated ledstrip
+    #[arg(long, default_value_t = 1)]
+    pub ledstrip_id: usize,
+
+    /// Id of the first generated effect and of its settings, the next ones follow it
+    #[arg(long, default_value_t = 1)]
+    pub first_effect_id: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct WledSegment {
+    #[serde(default)]
+    id: Option<usize>,
+    #[serde(default)]
+    start: usize,
+    #[serde(default)]
+    stop: usize,
+    #[serde(default = "on")]
+    on: bool,
+    #[serde(default = "full_brightness")]
+    bri: u8,
+    /// Primary, secondary and tertiary colors, as `[r, g, b(, w)]` or hex strings
+    #[serde(default)]
+    col: Vec<Value>,
+    #[serde(default)]
+    fx: u16,
+    #[serde(default = "default_speed")]
+    sx: u8,
+    #[serde(default)]
+    rev: bool,
+    #[serde(default)]
+    mi: bool,
+}
+
+impl Default for WledSegment {
+    fn default() -> Self {
+        Self {
+            id: None,
+            start: 0,
+            stop: 0,
+            on: true,
+            bri: full_brightness(),
+            col: Vec::new(),
+            fx: 0,
+            sx: DEFAULT_WLED_SPEED,
+            rev: false,
+            mi: false,
+        }
+    }
+}
+
+fn on() -> bool {
+    true
+}
+
+fn full_brightness() -> u8 {
+    255
+}
+
+fn default_speed() -> u8 {
+    DEFAULT_WLED_SPEED
+}
+
+#[derive(Debug, Deserialize)]
+struct WledState {
+    #[serde(default)]
+    seg: Vec<WledSegment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WledLeds {
+    count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct WledInfo {
+    leds: WledLeds,
+    #[serde(default)]
+    ip: Option<String>,
+}
+
+/// Export of the /json API
+#[derive(Debug, Deserialize)]
+struct WledFullState {
+    state: WledState,
+    #[serde(default)]
+    info: Option<WledInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WledHardwareLeds {
+    total: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct WledHardware {
+    led: WledHardwareLeds,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct WledIdentity {
+    #[serde(default)]
+    mdns: Option<String>,
+}
+
+/// Export of cfg.json
+#[derive(Debug, Deserialize)]
+struct WledConfig {
+    hw: WledHardware,
+    #[serde(default)]
+    id: WledIdentity,
+}
+
+#[derive(Debug, Deserialize)]
+struct WledPreset {
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default = "on")]
+    on: bool,
+    #[serde(default)]
+    bri: Option<u8>,
+    #[serde(default)]
+    seg: Vec<WledSegment>,
+}
+
+// Parts of the config filled by the import, serialized as is so that the floats keep their
+// precision
+#[derive(Serialize)]
+struct ImportedConfig {
+    devices: Vec<DeviceConfig>,
+    ledstrips: Vec<LedstripConfig>,
+    effect_settings: Vec<EffectSettingConfig>,
+    effects: Vec<EffectConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    profiles: Vec<ProfileConfig>,
+}
+
+// What the exports tell about the controller, each export filling in its part
+#[derive(Debug, Default)]
+struct WledExport {
+    led_count: Option<usize>,
+    // The mdns name of the config is kept over the ip of the state, whatever the file order
+    mdns: Option<String>,
+    ip: Option<String>,
+    segments: Option<Vec<WledSegment>>,
+    presets: BTreeMap<u32, WledPreset>,
+}
+
+impl WledExport {
+    fn read(&mut self, path: &Path) -> Result<(), ImportWledError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ImportWledError::Io(path.to_owned(), e))?;
+        let json: Value =
+            serde_json::from_str(&text).map_err(|e| ImportWledError::Json(path.to_owned(), e))?;
+        let parse_error = |e| ImportWledError::Json(path.to_owned(), e);
+        let Some(object) = json.as_object() else {
+            return Err(ImportWledError::UnknownExport(path.to_owned()));
+        };
+
+        if object.contains_key("state") {
+            let export = WledFullState::deserialize(&json).map_err(parse_error)?;
+            if let Some(info) = export.info {
+                self.led_count = self.led_count.or(Some(info.leds.count));
+                self.ip = info.ip;
+            }
+            self.segments = Some(export.state.seg);
+        } else if object.contains_key("seg") {
+            let state = WledState::deserialize(&json).map_err(parse_error)?;
+            self.segments = Some(state.seg);
+        } else if object.contains_key("hw") {
+            let config = WledConfig::deserialize(&json).map_err(parse_error)?;
+            // The config is the one to trust for the led count
+            self.led_count = Some(config.hw.led.total);
+            self.mdns = config.id.mdns.map(|mdns| format!("{mdns}.local"));
+        } else if !object.is_empty() && object.keys().all(|key| key.parse::<u32>().is_ok()) {
+            for (key, preset) in object {
+                // The empty preset 0 is always there
+                if preset.as_object().is_none_or(|preset| preset.is_empty()) {
+                    continue;
+                }
+                let preset = WledPreset::deserialize(preset).map_err(parse_error)?;
+                self.presets.insert(key.parse().unwrap(), preset);
+            }
+        } else {
+            return Err(ImportWledError::UnknownExport(path.to_owned()));
+        }
+        Ok(())
+    }
+}
+
+// Lua effects with their settings, one per distinct look of the segments
+#[derive(Debug, Default)]
+struct ImportedEffects {
+    first_id: usize,
+    effects: Vec<(&'static str, Value)>,
+}
+
+impl ImportedEffects {
+    fn effect_id(&mut self, segment: &WledSegment, index: usize) -> usize {
+        let effect = lua_effect(segment, index);
+        let position = match self.effects.iter().position(|known| *known == effect) {
+            Some(position) => position,
+            None => {
+                self.effects.push(effect);
+                self.effects.len() - 1
+            }
+        };
+        self.first_id + position
+    }
+}
+
+// Lua effect closest to the effect of the segment, drawing with its primary color
+fn lua_effect(segment: &WledSegment, index: usize) -> (&'static str, Value) {
+    if !segment.on {
+        return (SOLID_EFFECT, serde_json::json!({ "color": rgb([0, 0, 0]) }));
+    }
+    let file = match EFFECTS.iter().find(|(fx, _)| *fx == segment.fx) {
+        Some((_, file)) => *file,
+        None => {
+            tracing::warn!(
+                "WLED effect {} of segment {index} has no counterpart, it's a solid color",
+                segment.fx
+            );
+            SOLID_EFFECT
+        }
+    };
+    // Goes through the colors of the rainbow whatever the colors of the segment
+    if file == "rainbow.lua" {
+        return (file, serde_json::json!({}));
+    }
+
+    let mut settings = serde_json::Map::new();
+    if let Some(color) = segment.col.first().and_then(parse_color) {
+        // The classic effects have no brightness of their own
+        let dimmed = color.map(|channel| (channel as u32 * segment.bri as u32 / 255) as u8);
+        settings.insert("color".to_owned(), rgb(dimmed));
+    }
+    if file != SOLID_EFFECT && segment.sx != DEFAULT_WLED_SPEED {
+        let speed = segment.sx as f64 / DEFAULT_WLED_SPEED as f64;
+        settings.insert("speed".to_owned(), ((speed * 100.0).round() / 100.0).into());
+    }
+    (file, Value::Object(settings))
+}
+
+fn rgb([r, g, b]: [u8; 3]) -> Value {
+    serde_json::json!({ "r": r, "g": g, "b": b })
+}
+
+// Color of a segment, as `[r, g, b]` with an optional white or as a hex string like `FF8800`
+fn parse_color(color: &Value) -> Option<[u8; 3]> {
+    match color {
+        Value::Array(channels) => {
+            let channel =
+                |index: usize| -> Option<u8> { channels.get(index)?.as_u64()?.try_into().ok() };
+            Some([channel(0)?, channel(1)?, channel(2)?])
+        }
+        Value::String(hex) => {
+            let hex = hex.trim_start_matches('#');
+            let channel = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+            Some([channel(0)?, channel(2)?, channel(4)?])
+        }
+        _ => None,
+    }
+}
+
+/// Prints the devices, ledstrips, effects and profiles config converted from the WLED exports, to
+/// be merged into the settings file
+pub fn run(args: &ImportWledArgs) -> Result<(), ImportWledError> {
+    let mut export = WledExport::default();
+    for file in &args.files {
+        export.read(file)?;
+    }
+
+    // The layout of the current state, or of the first preset that has segments
+    let segments = export.segments.take().unwrap_or_else(|| {
+        export
+            .presets
+            .values()
+            .find(|preset| !preset.seg.is_empty())
+            .map(|preset| preset.seg.clone())
+            .unwrap_or_default()
+    });
+    let led_count = export
+        .led_count
+        .or(segments.iter().map(|segment| segment.stop).max())
+        .filter(|led_count| *led_count > 0)
+        .ok_or(ImportWledError::NoLeds)?;
+    let host = args
+        .host
+        .clone()
+        .or(export.mdns.take())
+        .or(export.ip.take())
+        .ok_or(ImportWledError::NoHost)?;
+
+    // Segments are numbered by their position when the export doesn't have their id
+    let mut segments: Vec<(usize, WledSegment)> = segments
+        .into_iter()
+        .enumerate()
+        .map(|(index, segment)| (segment.id.unwrap_or(index), segment))
+        .filter(|(_, segment)| segment.stop > segment.start && segment.start < led_count)
+        .collect();
+    segments.sort_by_key(|(_, segment)| segment.start);
+    if segments.is_empty() {
+        let segment = WledSegment {
+            stop: led_count,
+            ..Default::default()
+        };
+        segments.push((0, segment));
+    }
+    let layout = DiscoveredLayout {
+        led_count,
+        segments: segments
+            .iter()
+            .map(|(_, segment)| DiscoveredSegment {
+                start: segment.start,
+                len: segment.stop - segment.start,
+            })
+            .collect(),
+    };
+
+    let mut effects = ImportedEffects {
+        first_id: args.first_effect_id,
+        ..Default::default()
+    };
+    let mut segment_effects = discovery::ledstrip_effects(&layout, 0);
+    let mut base_effects = Vec::new();
+    for (index, (segment_config, (_, segment))) in
+        segment_effects.iter_mut().zip(&segments).enumerate()
+    {
+        segment_config.effect_id = effects.effect_id(segment, index);
+        segment_config.reversed = segment.rev;
+        segment_config.mirrored = segment.mi;
+        base_effects.push(segment_config.effect_id);
+    }
+
+    let mut profiles: Vec<ProfileConfig> = Vec::new();
+    for (id, preset) in &export.presets {
+        if preset.seg.is_empty() {
+            tracing::info!("Skipping preset {id}, which has no segments like the playlists");
+            continue;
+        }
+        let preset_segments: HashMap<usize, &WledSegment> = preset
+            .seg
+            .iter()
+            .enumerate()
+            .map(|(index, segment)| (segment.id.unwrap_or(index), segment))
+            .collect();
+        // The segments the preset leaves out keep the effect of the state
+        let profile_effects = segments
+            .iter()
+            .zip(&base_effects)
+            .enumerate()
+            .map(
+                |(index, ((segment_id, _), base_effect))| match preset_segments.get(segment_id) {
+                    Some(segment) => effects.effect_id(segment, index),
+                    None => *base_effect,
+                },
+            )
+            .collect();
+        let mut name = preset.n.clone().unwrap_or_else(|| format!("preset {id}"));
+        if profiles.iter().any(|profile| profile.name == name) {
+            name = format!("{name} ({id})");
+        }
+        profiles.push(ProfileConfig {
+            name,
+            brightness: match preset.on {
+                // Rounded, the 255 steps of WLED don't need more
+                true => preset
+                    .bri
+                    .map_or(1.0, |bri| (bri as f32 / 2.55).round() / 100.0),
+                false => 0.0,
+            },
+            ledstrips: vec![ProfileLedstripConfig {
+                ledstrip_id: args.ledstrip_id,
+                effects: profile_effects,
+            }],
+        });
+    }
+    tracing::info!(
+        "Imported {led_count} leds in {} segment(s), {} effect(s) and {} profile(s)",
+        segments.len(),
+        effects.effects.len(),
+        profiles.len()
+    );
+
+    let ids = (args.first_effect_id..).zip(effects.effects);
+    let (effect_settings, effect_configs): (Vec<_>, Vec<_>) = ids
+        .map(|(id, (file, settings))| {
+            let setting = EffectSettingConfig {
+                id,
+                setting: SettingsConfigType::Lua(settings),
+                seed: None,
+            };
+            let effect = EffectConfig {
+                effect_id: id,
+                settings_id: id,
+                effect: EffectConfigType::Lua(file.to_owned()),
+                bindings: HashMap::new(),
+                audio_source: None,
+            };
+            (setting, effect)
+        })
+        .unzip();
+    let config = ImportedConfig {
+        devices: vec![DeviceConfig {
+            kind: "Udp".to_owned(),
+            connection: serde_json::json!(format!("{host}:{}", args.port)),
+            id: args.device_id,
+            encoding: FrameEncoding::Ddp { destination_id: 1 },
+            keep_alive: None,
+            dithering: false,
+            delay_ms: 0,
+            compensate_latency: false,
+            color_lut: None,
+        }],
+        ledstrips: vec![LedstripConfig {
+            id: args.ledstrip_id,
+            connection_id: args.device_id,
+            fallback_connection_id: None,
+            offset: None,
+            size: led_count,
+            effects: segment_effects,
+            post_processing: post_processing::default_stages(),
+            calibration: Default::default(),
+            transform: Vec::new(),
+            group_size: 1,
+            startup_scene: None,
+            failure_scene: None,
+        }],
+        effect_settings,
+        effects: effect_configs,
+        profiles,
+    };
+    println!("{}", serde_json::to_string_pretty(&config)?);
+    Ok(())
+}
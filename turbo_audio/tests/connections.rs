@@ -0,0 +1,135 @@
+use std::time::Duration;
+use turbo_audio::{
+    connections::{
+        encoder::{EncodeError, FrameEncoding, LengthField},
+        Connection, ConnectionFactory,
+    },
+    test_support::{MockReceiver, ReceivedFrame, ReceiverProtocol},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn connect(kind: &str, parameters: serde_json::Value) -> Box<dyn Connection> {
+    ConnectionFactory::default()
+        .create(kind, &parameters)
+        .unwrap()
+}
+
+// Two frames of 2 leds, the second one different so that a repeated frame would show
+fn frames() -> [Vec<u8>; 2] {
+    [vec![255, 0, 0, 0, 128, 255], vec![1, 2, 3, 4, 5, 6]]
+}
+
+fn raw(data: &[u8]) -> ReceivedFrame {
+    ReceivedFrame {
+        sequence: None,
+        data: data.to_vec(),
+    }
+}
+
+#[test]
+fn tcp_sends_the_raw_frames() {
+    let receiver = MockReceiver::bind(ReceiverProtocol::Raw { frame_len: 6 }).unwrap();
+    let mut connection = connect("Tcp", serde_json::json!(receiver.address().to_string()));
+
+    for frame in frames() {
+        connection.send_frame(frame).unwrap();
+    }
+
+    let [first, second] = frames();
+    assert_eq!(
+        receiver.frames(2, TIMEOUT).unwrap(),
+        vec![raw(&first), raw(&second)]
+    );
+    assert_eq!(receiver.token(), None);
+}
+
+#[test]
+fn tcp_sends_the_token_before_the_frames() {
+    let receiver = MockReceiver::bind(ReceiverProtocol::Raw { frame_len: 6 }).unwrap();
+    let mut connection = connect(
+        "Tcp",
+        serde_json::json!({
+            "address": receiver.address().to_string(),
+            "token": "secret",
+        }),
+    );
+
+    let [first, _] = frames();
+    connection.send_frame(first.clone()).unwrap();
+
+    assert_eq!(receiver.next_frame(TIMEOUT).unwrap(), raw(&first));
+    assert_eq!(receiver.token().as_deref(), Some("secret"));
+}
+
+#[test]
+fn framed_frames_carry_their_sequence_and_crc() {
+    let receiver = MockReceiver::bind(ReceiverProtocol::Framed).unwrap();
+    let mut connection = connect("Tcp", serde_json::json!(receiver.address().to_string()));
+    let mut encoder = FrameEncoding::Framed.encoder();
+
+    for frame in frames() {
+        for packet in encoder.encode(&frame).unwrap() {
+            connection.send_frame(packet).unwrap();
+        }
+    }
+
+    let [first, second] = frames();
+    assert_eq!(
+        receiver.frames(2, TIMEOUT).unwrap(),
+        vec![
+            ReceivedFrame {
+                sequence: Some(1),
+                data: first,
+            },
+            ReceivedFrame {
+                sequence: Some(2),
+                data: second,
+            },
+        ]
+    );
+}
+
+#[test]
+fn framed_refuses_the_frames_longer_than_its_length_field() {
+    let mut encoder = FrameEncoding::Framed.encoder();
+
+    assert!(encoder.encode(&vec![0; u16::MAX as usize]).is_ok());
+    assert!(matches!(
+        encoder.encode(&vec![0; u16::MAX as usize + 1]),
+        Err(EncodeError::TooLong(65536, LengthField::U16Be))
+    ));
+}
+
+#[test]
+fn udp_sends_a_datagram_per_frame() {
+    let receiver = MockReceiver::bind_udp(ReceiverProtocol::Raw { frame_len: 6 }).unwrap();
+    let mut connection = connect("Udp", serde_json::json!(receiver.address().to_string()));
+
+    for frame in frames() {
+        connection.send_frame(frame).unwrap();
+    }
+
+    let [first, second] = frames();
+    assert_eq!(
+        receiver.frames(2, TIMEOUT).unwrap(),
+        vec![raw(&first), raw(&second)]
+    );
+}
+
+#[test]
+fn udp_splits_the_frames_longer_than_a_datagram() {
+    let receiver = MockReceiver::bind_udp(ReceiverProtocol::Raw { frame_len: 6 }).unwrap();
+    let mut connection = connect(
+        "Udp",
+        serde_json::json!({
+            "address": receiver.address().to_string(),
+            "max_datagram_size": 3,
+        }),
+    );
+
+    let [first, _] = frames();
+    connection.send_frame(first.clone()).unwrap();
+
+    assert_eq!(receiver.next_frame(TIMEOUT).unwrap(), raw(&first));
+}
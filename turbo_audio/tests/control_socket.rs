@@ -0,0 +1,65 @@
+#![cfg(unix)]
+
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+use turbo_audio::control::socket::ControlSocket;
+
+// A fresh directory of the temp dir, removed with what's left in it
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("turbo_audio-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn replaces_the_socket_left_behind() {
+    let dir = TempDir::new("stale-socket");
+    let path = dir.0.join("control.sock");
+    // Dropping the listener leaves the socket file, like an instance that crashed
+    drop(UnixListener::bind(&path).unwrap());
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let socket = ControlSocket::new(&path, sender);
+
+    assert!(socket.is_ok());
+}
+
+#[test]
+fn keeps_the_socket_of_a_running_instance() {
+    let dir = TempDir::new("live-socket");
+    let path = dir.0.join("control.sock");
+    let running = UnixListener::bind(&path).unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let socket = ControlSocket::new(&path, sender);
+
+    assert!(socket.is_err());
+    assert!(UnixStream::connect(&path).is_ok());
+    drop(running);
+}
+
+#[test]
+fn keeps_a_file_that_isnt_a_socket() {
+    let dir = TempDir::new("not-a-socket");
+    let path = dir.0.join("notes.txt");
+    std::fs::write(&path, "keep me").unwrap();
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let socket = ControlSocket::new(&path, sender);
+
+    assert!(socket.is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+}
@@ -0,0 +1,101 @@
+/*
+ * C interface of the native effects of turbo_audio, the same as the one of
+ * turbo_plugin::effect_plugin for the effects written in rust.
+ *
+ * An effect is a shared library exporting `_plugin_abi_version`, returning
+ * TURBO_EFFECT_PLUGIN_ABI_VERSION, and `_plugin_vtable`, returning a pointer to a static
+ * TurboEffectPluginVTable. Dropped in effects/bin as libname.so, it is registered as the `name`
+ * effect type. It's reloaded when the file changes.
+ *
+ * `load` is called once when the library is loaded, with the audio api the effects read the fft
+ * from, and `unload` before it's unloaded, which must call `free` of the audio api. Every effect
+ * using the library gets its own plugin from `plugin_create`, destroyed with `plugin_destroy`.
+ * `tick` is called once per frame, possibly from another thread than the previous one, with the
+ * colors of the previous frame to render over. `seed` is called before the first tick and
+ * whenever the seed of the settings changes, for the effects drawing at random to render the same
+ * frames for the same seed.
+ */
+
+#ifndef TURBO_EFFECT_H
+#define TURBO_EFFECT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define TURBO_EFFECT_PLUGIN_ABI_VERSION 6
+
+typedef struct {
+	uint8_t r;
+	uint8_t g;
+	uint8_t b;
+} TurboColor;
+
+/* Pixel counts an effect needs to render properly. A count of 0 means no requirement */
+typedef struct {
+	/* Fewest pixels the effect renders something recognizable on */
+	uint32_t min;
+	/* Pixel count the effect was designed for */
+	uint32_t native;
+} TurboPixelRequirements;
+
+/* Features describing the shape of the spectrum of a frame rather than its energy */
+typedef struct {
+	/* How much the spectrum grew since the previous frame, high on note onsets and hits */
+	float flux;
+	/* Center of mass of the spectrum in Hz, higher for brighter sounds */
+	float centroid;
+	/* Frequency in Hz below which 85% of the energy is */
+	float rolloff;
+	/* From 0 for a pure tone to 1 for white noise */
+	float flatness;
+} TurboSpectralFeatures;
+
+/* Energy of the 12 pitch classes of a frame, for the effects following the notes being played */
+typedef struct {
+	/* Energy of each pitch class from C to B, scaled so that the strongest one is 1 */
+	float pitch_classes[12];
+	/* Pitch class of the dominant note, from 0 for C to 11 for B, or -1 in silence */
+	int32_t dominant;
+} TurboChroma;
+
+/* Functions are called with `instance` as their first argument */
+typedef struct {
+	const void *instance;
+	/* Average amplitude between two frequencies, in Hz */
+	float (*get_average_amplitude)(const void *instance, float lower, float upper);
+	float (*get_frequency_amplitude)(const void *instance, float frequency);
+	float (*get_max_frequency)(const void *instance);
+	TurboSpectralFeatures (*get_spectral_features)(const void *instance);
+	TurboChroma (*get_chroma)(const void *instance);
+	/* Fills `samples` with the last `count` samples of the waveform, oldest first in [-1, 1],
+	 * and returns how many were written */
+	size_t (*get_samples)(const void *instance, float *samples, size_t count);
+	/* Root mean square of the last `count` samples */
+	float (*get_waveform_rms)(const void *instance, size_t count);
+	/* Average amplitude between two frequencies `frames_back` fft frames ago, the current one
+	 * being 0. 0 past the frames kept */
+	float (*get_history)(const void *instance, float lower, float upper, size_t frames_back);
+	/* Frames get_history can read back, the current one included */
+	size_t (*get_history_length)(const void *instance);
+	void (*free)(const void *instance);
+} TurboAudioApi;
+
+typedef struct {
+	/* Returns a new plugin, the state of one effect */
+	void *(*plugin_create)(void);
+	void (*plugin_destroy)(void *plugin);
+	/* Name of the effect, valid as long as the plugin */
+	const char *(*name)(const void *plugin);
+	TurboPixelRequirements (*pixel_requirements)(const void *plugin);
+	/* Renders a frame over the `len` colors of `leds` */
+	void (*tick)(const void *plugin, TurboColor *leds, unsigned long len);
+	/* Seeds the random generator of the plugin */
+	void (*seed)(const void *plugin, uint64_t seed);
+	void (*load)(TurboAudioApi audio_api);
+	void (*unload)(void);
+} TurboEffectPluginVTable;
+
+uint32_t _plugin_abi_version(void);
+const void *_plugin_vtable(void);
+
+#endif
@@ -3,6 +3,45 @@ use std::{
     sync::{Mutex, OnceLock},
 };
 
+/// Features describing the shape of the spectrum of a frame rather than its energy
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct SpectralFeatures {
+    /// How much the spectrum grew since the previous frame, high on note onsets and hits
+    pub flux: f32,
+    /// Center of mass of the spectrum in Hz, higher for brighter sounds
+    pub centroid: f32,
+    /// Frequency in Hz below which 85% of the energy is
+    pub rolloff: f32,
+    /// From 0 for a pure tone to 1 for white noise
+    pub flatness: f32,
+}
+
+/// Energy of the 12 pitch classes of a frame, for the effects following the notes being played
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct Chroma {
+    /// Energy of each pitch class from C to B, scaled so that the strongest one is 1
+    pub pitch_classes: [f32; 12],
+    /// Pitch class of the dominant note, from 0 for C to 11 for B, or -1 in silence
+    pub dominant: i32,
+}
+
+impl Default for Chroma {
+    fn default() -> Self {
+        Self {
+            pitch_classes: [0.0; 12],
+            dominant: -1,
+        }
+    }
+}
+
+impl Chroma {
+    pub fn dominant_note(&self) -> Option<usize> {
+        usize::try_from(self.dominant).ok()
+    }
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct AudioApi {
@@ -15,6 +54,17 @@ pub struct AudioApi {
     get_frequency_amplitude:
         extern "C" fn(*const std::ffi::c_void, std::ffi::c_float) -> std::ffi::c_float,
     get_max_frequency: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+    get_spectral_features: extern "C" fn(*const std::ffi::c_void) -> SpectralFeatures,
+    get_chroma: extern "C" fn(*const std::ffi::c_void) -> Chroma,
+    get_samples: extern "C" fn(*const std::ffi::c_void, *mut std::ffi::c_float, usize) -> usize,
+    get_waveform_rms: extern "C" fn(*const std::ffi::c_void, usize) -> std::ffi::c_float,
+    get_history: extern "C" fn(
+        *const std::ffi::c_void,
+        std::ffi::c_float,
+        std::ffi::c_float,
+        usize,
+    ) -> std::ffi::c_float,
+    get_history_length: extern "C" fn(*const std::ffi::c_void) -> usize,
     free: extern "C" fn(*const std::ffi::c_void),
 }
 
@@ -22,6 +72,8 @@ unsafe impl Send for AudioApi {}
 unsafe impl Sync for AudioApi {}
 
 impl AudioApi {
+    // One function pointer per field of the api
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         instance: *const std::ffi::c_void,
 
@@ -35,6 +87,17 @@ impl AudioApi {
             std::ffi::c_float,
         ) -> std::ffi::c_float,
         get_max_frequency: extern "C" fn(*const std::ffi::c_void) -> std::ffi::c_float,
+        get_spectral_features: extern "C" fn(*const std::ffi::c_void) -> SpectralFeatures,
+        get_chroma: extern "C" fn(*const std::ffi::c_void) -> Chroma,
+        get_samples: extern "C" fn(*const std::ffi::c_void, *mut std::ffi::c_float, usize) -> usize,
+        get_waveform_rms: extern "C" fn(*const std::ffi::c_void, usize) -> std::ffi::c_float,
+        get_history: extern "C" fn(
+            *const std::ffi::c_void,
+            std::ffi::c_float,
+            std::ffi::c_float,
+            usize,
+        ) -> std::ffi::c_float,
+        get_history_length: extern "C" fn(*const std::ffi::c_void) -> usize,
         free: extern "C" fn(*const std::ffi::c_void),
     ) -> Self {
         Self {
@@ -42,6 +105,12 @@ impl AudioApi {
             get_average_amplitude,
             get_frequency_amplitude,
             get_max_frequency,
+            get_spectral_features,
+            get_chroma,
+            get_samples,
+            get_waveform_rms,
+            get_history,
+            get_history_length,
             free,
         }
     }
@@ -86,6 +155,72 @@ pub fn get_max_frequency() -> std::ffi::c_float {
     (api.get_max_frequency)(api.instance)
 }
 
+pub fn get_spectral_features() -> SpectralFeatures {
+    let Some(api) = AUDIO_API_INSTANCE.get() else {
+        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
+        abort();
+    };
+    let api = api.lock().unwrap();
+
+    (api.get_spectral_features)(api.instance)
+}
+
+pub fn get_chroma() -> Chroma {
+    let Some(api) = AUDIO_API_INSTANCE.get() else {
+        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
+        abort();
+    };
+    let api = api.lock().unwrap();
+
+    (api.get_chroma)(api.instance)
+}
+
+/// Fills `samples` with the last samples of the waveform, oldest first in [-1, 1]. Returns how
+/// many were written, fewer than asked when the fft window is shorter, and none for a recording
+pub fn get_samples(samples: &mut [f32]) -> usize {
+    let Some(api) = AUDIO_API_INSTANCE.get() else {
+        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
+        abort();
+    };
+    let api = api.lock().unwrap();
+
+    (api.get_samples)(api.instance, samples.as_mut_ptr(), samples.len())
+}
+
+/// Root mean square of the last `count` samples of the waveform
+pub fn get_waveform_rms(count: usize) -> f32 {
+    let Some(api) = AUDIO_API_INSTANCE.get() else {
+        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
+        abort();
+    };
+    let api = api.lock().unwrap();
+
+    (api.get_waveform_rms)(api.instance, count)
+}
+
+/// Average amplitude between two frequencies `frames_back` fft frames ago, the current frame
+/// being 0. 0 past the [`get_history_length`] frames kept
+pub fn get_history(lower_freq: f32, upper_freq: f32, frames_back: usize) -> f32 {
+    let Some(api) = AUDIO_API_INSTANCE.get() else {
+        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
+        abort();
+    };
+    let api = api.lock().unwrap();
+
+    (api.get_history)(api.instance, lower_freq, upper_freq, frames_back)
+}
+
+/// Frames [`get_history`] can read back, the current one included
+pub fn get_history_length() -> usize {
+    let Some(api) = AUDIO_API_INSTANCE.get() else {
+        eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
+        abort();
+    };
+    let api = api.lock().unwrap();
+
+    (api.get_history_length)(api.instance)
+}
+
 pub fn free() {
     let Some(api) = AUDIO_API_INSTANCE.get() else {
         eprintln!("PLUGIN ERROR: Couldn't get the audio api pointer");
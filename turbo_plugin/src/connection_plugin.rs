@@ -0,0 +1,160 @@
+use std::ffi::{c_char, c_void};
+
+/// Version of the layout of [`ConnectionBackendVTable`], exported by the libraries as
+/// `_connection_plugin_abi_version`. turbo_audio refuses the libraries built for another version.
+pub const CONNECTION_PLUGIN_ABI_VERSION: u32 = 1;
+
+/// State of the link with the device, as far as the backend knows. Crosses the library boundary
+/// as its `u8` value, since an enum holding another value would be undefined behavior
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BackendHealth {
+    Connected = 0,
+    Connecting = 1,
+    Disconnected = 2,
+}
+
+impl TryFrom<u8> for BackendHealth {
+    /// The value that isn't a health
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Connected),
+            1 => Ok(Self::Connecting),
+            2 => Ok(Self::Disconnected),
+            _ => Err(value),
+        }
+    }
+}
+
+/// Transport carrying the encoded frames to a device, for protocols that don't ship with
+/// turbo_audio.
+///
+/// The backend is built into a shared library with [`make_connection_backend`] and used by the
+/// devices of type `Plugin`, whose `connection` gives the `library` to load and the `parameters`
+/// given to [`ConnectionBackend::connect`]. Every device gets its own backend.
+pub trait ConnectionBackend: Send + Sized {
+    /// Opens the link to the device. `parameters` is the json of the `parameters` of the device
+    /// config
+    fn connect(parameters: &str) -> Result<Self, String>;
+
+    /// Sends one packet of an encoded frame
+    fn send_frame(&mut self, packet: &[u8]) -> Result<(), String>;
+
+    fn health(&self) -> BackendHealth;
+
+    /// Starts over after turbo_audio gave up on the device because the sends kept failing
+    fn reconnect(&mut self) {}
+}
+
+#[macro_export]
+macro_rules! make_connection_backend {
+    ($backend:ty) => {
+        #[no_mangle]
+        extern "C" fn _connection_plugin_abi_version() -> u32 {
+            turbo_plugin::connection_plugin::CONNECTION_PLUGIN_ABI_VERSION
+        }
+
+        #[no_mangle]
+        extern "C" fn _connection_backend_vtable() -> *const std::ffi::c_void {
+            fn into_error(error: String) -> *mut std::ffi::c_char {
+                // An error can't contain a nul byte
+                let error = error.replace('\0', " ");
+                std::ffi::CString::new(error).unwrap().into_raw()
+            }
+
+            extern "C" fn connect(
+                parameters: *const u8,
+                len: usize,
+                error: *mut *mut std::ffi::c_char,
+            ) -> *mut std::ffi::c_void {
+                let parameters = unsafe { std::slice::from_raw_parts(parameters, len) };
+                let result = std::str::from_utf8(parameters)
+                    .map_err(|e| e.to_string())
+                    .and_then(
+                        <$backend as turbo_plugin::connection_plugin::ConnectionBackend>::connect,
+                    );
+                match result {
+                    Ok(backend) => Box::into_raw(Box::new(backend)) as *mut _,
+                    Err(e) => {
+                        unsafe { *error = into_error(e) };
+                        std::ptr::null_mut()
+                    }
+                }
+            }
+
+            extern "C" fn destroy(backend: *mut std::ffi::c_void) {
+                unsafe {
+                    drop(Box::from_raw(backend as *mut $backend));
+                }
+            }
+
+            extern "C" fn send_frame(
+                backend: *mut std::ffi::c_void,
+                packet: *const u8,
+                len: usize,
+            ) -> *mut std::ffi::c_char {
+                let backend = unsafe { &mut *(backend as *mut $backend) };
+                let packet = unsafe { std::slice::from_raw_parts(packet, len) };
+                match turbo_plugin::connection_plugin::ConnectionBackend::send_frame(
+                    backend, packet,
+                ) {
+                    Ok(()) => std::ptr::null_mut(),
+                    Err(e) => into_error(e),
+                }
+            }
+
+            extern "C" fn health(backend: *const std::ffi::c_void) -> u8 {
+                let backend = unsafe { &*(backend as *const $backend) };
+                turbo_plugin::connection_plugin::ConnectionBackend::health(backend) as u8
+            }
+
+            extern "C" fn reconnect(backend: *mut std::ffi::c_void) {
+                let backend = unsafe { &mut *(backend as *mut $backend) };
+                turbo_plugin::connection_plugin::ConnectionBackend::reconnect(backend);
+            }
+
+            extern "C" fn free_error(error: *mut std::ffi::c_char) {
+                unsafe {
+                    drop(std::ffi::CString::from_raw(error));
+                }
+            }
+
+            static VTABLE: turbo_plugin::connection_plugin::ConnectionBackendVTable =
+                turbo_plugin::connection_plugin::ConnectionBackendVTable {
+                    connect,
+                    destroy,
+                    send_frame,
+                    health,
+                    reconnect,
+                    free_error,
+                };
+
+            &VTABLE as *const turbo_plugin::connection_plugin::ConnectionBackendVTable as *const _
+        }
+    };
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct ConnectionBackendVTable {
+    /// Function that returns a pointer to a heap allocated backend connected with the json
+    /// parameters, or null after writing the error
+    pub connect: extern "C" fn(*const u8, usize, *mut *mut c_char) -> *mut c_void,
+
+    /// Function that closes and destroys a heap allocated backend
+    pub destroy: extern "C" fn(*mut c_void),
+
+    /// Function that sends a packet, returning null or the error
+    pub send_frame: extern "C" fn(*mut c_void, *const u8, usize) -> *mut c_char,
+
+    /// Function that returns the [`BackendHealth`] of the link as its `u8` value
+    pub health: extern "C" fn(*const c_void) -> u8,
+
+    /// Function that starts the link over
+    pub reconnect: extern "C" fn(*mut c_void),
+
+    /// Function that frees an error returned by the other functions
+    pub free_error: extern "C" fn(*mut c_char),
+}
@@ -1,13 +1,41 @@
 use crate::{audio_api, Color};
 use std::any::Any;
 
+/// Pixel counts an effect needs to render properly. A count of 0 means no requirement.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct PixelRequirements {
+    /// Fewest pixels the effect renders something recognizable on
+    pub min: u32,
+    /// Pixel count the effect was designed for
+    pub native: u32,
+}
+
+/// Version of the layout of [`NativeEffectPluginVTable`] and of the
+/// [`AudioApi`](crate::audio_api::AudioApi) it's loaded with, exported by the libraries as
+/// `_plugin_abi_version`. turbo_audio refuses the libraries built for another version, like C
+/// effects written against an older `include/turbo_effect.h`.
+pub const EFFECT_PLUGIN_ABI_VERSION: u32 = 6;
+
 pub trait NativeEffectPlugin: Any + Send + Sync {
     /// Get a name describing the `Plugin`.
     fn name(&self) -> *const std::ffi::c_char;
 
-    /// Tick fn
+    /// Pixel counts the effect needs. Segments smaller than that are rendered at the native size
+    /// and resampled.
+    fn pixel_requirements(&self) -> PixelRequirements {
+        PixelRequirements::default()
+    }
+
+    /// Renders a frame. `leds` holds the colors of the previous frame, so an effect can fade or
+    /// move them instead of drawing everything again. What isn't written is kept as is.
     fn tick(&self, leds: &mut [Color]);
 
+    /// Seeds the random generator of the effect, before the first tick and whenever the `seed`
+    /// of its settings changes, so that the same seed renders the same frames for the same
+    /// audio. Effects without randomness can ignore it.
+    fn seed(&self, _seed: u64) {}
+
     /// A callback called immediately after the plugin is loaded. Usually used
     /// for initialization.
     fn load();
@@ -20,6 +48,11 @@ pub trait NativeEffectPlugin: Any + Send + Sync {
 #[macro_export]
 macro_rules! make_native_effect_plugin {
     ($plugin:ty, $ctor:expr) => {
+        #[no_mangle]
+        extern "C" fn _plugin_abi_version() -> u32 {
+            turbo_plugin::effect_plugin::EFFECT_PLUGIN_ABI_VERSION
+        }
+
         #[no_mangle]
         extern "C" fn _plugin_vtable() -> *const std::ffi::c_void {
             extern "C" fn plugin_create() -> *mut std::ffi::c_void {
@@ -38,6 +71,13 @@ macro_rules! make_native_effect_plugin {
                 plugin.name()
             }
 
+            extern "C" fn pixel_requirements(
+                plugin: *const std::ffi::c_void,
+            ) -> turbo_plugin::effect_plugin::PixelRequirements {
+                let plugin = unsafe { &*(plugin as *const $plugin) };
+                plugin.pixel_requirements()
+            }
+
             extern "C" fn tick(
                 plugin: *const std::ffi::c_void,
                 colors: *mut Color,
@@ -48,6 +88,11 @@ macro_rules! make_native_effect_plugin {
                 plugin.tick(slice);
             }
 
+            extern "C" fn seed(plugin: *const std::ffi::c_void, seed: u64) {
+                let plugin = unsafe { &*(plugin as *const $plugin) };
+                plugin.seed(seed);
+            }
+
             extern "C" fn load(audio_api: turbo_plugin::audio_api::AudioApi) {
                 turbo_plugin::audio_api::on_load(audio_api);
                 <$plugin>::load();
@@ -63,7 +108,9 @@ macro_rules! make_native_effect_plugin {
                     plugin_create,
                     plugin_destroy,
                     name,
+                    pixel_requirements,
                     tick,
+                    seed,
                     load,
                     unload,
                 };
@@ -85,9 +132,15 @@ pub struct NativeEffectPluginVTable {
     /// Function that returns the name of the plugin
     pub name: extern "C" fn(*const std::ffi::c_void) -> *const std::ffi::c_char,
 
+    /// Function that returns the pixel counts the plugin needs
+    pub pixel_requirements: extern "C" fn(*const std::ffi::c_void) -> PixelRequirements,
+
     /// Function that ticks the plugin
     pub tick: extern "C" fn(*const std::ffi::c_void, *mut Color, std::ffi::c_ulong),
 
+    /// Function that seeds the random generator of the plugin
+    pub seed: extern "C" fn(*const std::ffi::c_void, u64),
+
     /// Function that gets called when the shared library gets loaded
     /// Useful for making initialization that is shared between plugin instances
     pub load: extern "C" fn(audio_api::AudioApi),
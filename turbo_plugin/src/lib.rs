@@ -1,4 +1,5 @@
 pub mod audio_api;
+pub mod connection_plugin;
 pub mod effect_plugin;
 pub mod general_plugin;
 
@@ -12,4 +13,3 @@ pub struct Color {
     pub g: u8,
     pub b: u8,
 }
-